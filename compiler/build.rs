@@ -0,0 +1,14 @@
+use std::env;
+use std::process::Command;
+
+fn main() {
+    // Cached objects are keyed with derived `Hash` implementations, which can change between Rust
+    // versions, so the runtime needs to know which version built it.
+    let rustc = env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let output = Command::new(rustc)
+        .arg("--version")
+        .output()
+        .expect("failed to run rustc");
+    let version = String::from_utf8(output.stdout).expect("rustc version isn't UTF-8");
+    println!("cargo:rustc-env=MAXIM_RUSTC_VERSION={}", version.trim());
+}
@@ -1,6 +1,7 @@
 #include <llvm-c/Core.h>
 #include <llvm-c/OrcBindings.h>
 #include <llvm-c/TargetMachine.h>
+#include <llvm/Config/llvm-config.h>
 #include <llvm/ExecutionEngine/ExecutionEngine.h>
 #include <llvm/IR/IRBuilder.h>
 #include <llvm/IR/LegacyPassManager.h>
@@ -17,6 +18,10 @@ LLVMTargetMachineRef LLVMAxiomSelectTarget() {
     return llvm::wrap(llvm::EngineBuilder().selectTarget());
 }
 
+const char *LLVMAxiomGetVersionString() {
+    return LLVM_VERSION_STRING;
+}
+
 // Builder utilities
 void LLVMAxiomSetFastMathFlags(LLVMBuilderRef builder, bool allowReassoc, bool noNans, bool noInfs, bool noSignedZeros,
                                bool allowReciprocal, bool allowContract, bool approxFunc) {
@@ -14,6 +14,13 @@ pub fn gen_load_control_statement(
     let field_type = controls::get_field_type(node.ctx.context, field);
     let result_ptr = node.ctx.allocb.build_alloca(&field_type, "control.field");
 
-    controls::build_field_get(node.ctx.module, node.ctx.b, field, ptrs, result_ptr);
+    controls::build_field_get(
+        node.ctx.module,
+        node.ctx.target,
+        node.ctx.b,
+        field,
+        ptrs,
+        result_ptr,
+    );
     result_ptr
 }
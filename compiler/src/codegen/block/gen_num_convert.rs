@@ -14,6 +14,7 @@ pub fn gen_num_convert_statement(
         node.ctx.allocb,
         node.ctx.b,
         node.ctx.module,
+        node.ctx.target,
         &base_num,
         target_form,
     )
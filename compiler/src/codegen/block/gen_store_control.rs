@@ -13,7 +13,14 @@ pub fn gen_store_control_statement(
     let ptrs = node.get_control_ptrs(control);
 
     let store_val = node.get_statement(value);
-    controls::build_field_set(node.ctx.module, node.ctx.b, field, ptrs, store_val);
+    controls::build_field_set(
+        node.ctx.module,
+        node.ctx.target,
+        node.ctx.b,
+        field,
+        ptrs,
+        store_val,
+    );
 
     // storing a control has no result, return an undefined value
     node.ctx
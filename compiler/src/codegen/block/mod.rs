@@ -13,9 +13,10 @@ mod gen_unary_op;
 
 use self::block_context::BlockContext;
 use crate::codegen::{
-    build_context_function, controls, functions, util, BuilderContext, LifecycleFunc, ObjectCache,
+    build_context_function, controls, data_analyzer, functions, globals, util, BuilderContext,
+    LifecycleFunc, ObjectCache,
 };
-use crate::mir::block::Statement;
+use crate::mir::block::{Function, Statement};
 use crate::mir::{Block, BlockRef};
 use inkwell::attribute::AttrKind;
 use inkwell::builder::Builder;
@@ -65,25 +66,44 @@ fn gen_statement(index: usize, statement: &Statement, node: &mut BlockContext) -
     }
 }
 
+pub fn get_lifecycle_func_name(
+    cache: &ObjectCache,
+    block: BlockRef,
+    lifecycle: LifecycleFunc,
+) -> String {
+    format!(
+        "maxim.block.{}.{}.{}",
+        block,
+        cache.block_code_key(block),
+        lifecycle
+    )
+}
+
 fn get_lifecycle_func(
     module: &Module,
     cache: &ObjectCache,
     block: BlockRef,
     lifecycle: LifecycleFunc,
 ) -> FunctionValue {
-    let func_name = format!("maxim.block.{}.{}", block, lifecycle);
-    let func = util::get_or_create_func(module, &func_name, true, &|| {
+    let func_name = get_lifecycle_func_name(cache, block, lifecycle);
+
+    // block functions use the C calling convention, as they can be built by either backend
+    let func = util::get_or_create_func(module, &func_name, false, &|| {
         let context = module.get_context();
         let layout = cache.block_layout(block).unwrap();
 
         (
             Linkage::ExternalLinkage,
-            context.void_type().fn_type(
-                &[
-                    &layout.pointer_struct.ptr_type(AddressSpace::Generic),
-                    &layout.constant_struct.ptr_type(AddressSpace::Generic),
-                ],
-                false,
+            globals::add_instance_param(
+                module,
+                cache.target(),
+                context.void_type().fn_type(
+                    &[
+                        &layout.pointer_struct.ptr_type(AddressSpace::Generic),
+                        &layout.constant_struct.ptr_type(AddressSpace::Generic),
+                    ],
+                    false,
+                ),
             ),
         )
     });
@@ -127,6 +147,7 @@ pub fn build_construct_func(module: &Module, cache: &ObjectCache, block: &Block)
                 let ptrs = block_ctx.get_control_ptrs(control_index);
                 controls::build_lifecycle_call(
                     module,
+                    cache.target(),
                     &mut block_ctx.ctx.b,
                     control.control_type,
                     LifecycleFunc::Construct,
@@ -137,6 +158,7 @@ pub fn build_construct_func(module: &Module, cache: &ObjectCache, block: &Block)
                     let ui_ptr = block_ctx.get_ui_ptr(control_index);
                     controls::build_ui_lifecycle_call(
                         module,
+                        cache.target(),
                         &mut block_ctx.ctx.b,
                         control.control_type,
                         LifecycleFunc::Construct,
@@ -146,13 +168,32 @@ pub fn build_construct_func(module: &Module, cache: &ObjectCache, block: &Block)
                 }
             }
 
-            for (func_index, function) in block_ctx.layout.functions.iter().enumerate() {
-                let layout_index = block_ctx.layout.function_index(func_index);
+            for (statement_index, statement) in block.statements.iter().enumerate() {
+                let (function, args) = match statement {
+                    Statement::CallFunc { function, args, .. } => (*function, args),
+                    _ => continue,
+                };
+                let layout_index = block_ctx.layout.statement_index(statement_index).unwrap();
                 let data_ptr = block_ctx.get_function_ptr(layout_index);
+
+                if function == Function::Delay {
+                    functions::DelayFunction::build_store_max_seconds(
+                        block_ctx.ctx.context,
+                        &mut block_ctx.ctx.b,
+                        data_ptr,
+                        data_analyzer::get_delay_max_seconds(
+                            cache.target().max_delay_seconds,
+                            block,
+                            args,
+                        ),
+                    );
+                }
+
                 functions::build_lifecycle_call(
                     module,
+                    cache.target(),
                     &mut block_ctx.ctx.b,
-                    *function,
+                    function,
                     functions::FunctionLifecycleFunc::Construct,
                     data_ptr,
                 );
@@ -172,6 +213,7 @@ pub fn build_update_func(module: &Module, cache: &ObjectCache, block: &Block) {
                 let ptrs = block_ctx.get_control_ptrs(control_index);
                 controls::build_lifecycle_call(
                     module,
+                    cache.target(),
                     &mut block_ctx.ctx.b,
                     control.control_type,
                     LifecycleFunc::Update,
@@ -182,6 +224,7 @@ pub fn build_update_func(module: &Module, cache: &ObjectCache, block: &Block) {
                     let ui_ptr = block_ctx.get_ui_ptr(control_index);
                     controls::build_ui_lifecycle_call(
                         module,
+                        cache.target(),
                         &mut block_ctx.ctx.b,
                         control.control_type,
                         LifecycleFunc::Update,
@@ -213,6 +256,7 @@ pub fn build_destruct_func(module: &Module, cache: &ObjectCache, block: &Block)
                     let ui_ptr = block_ctx.get_ui_ptr(control_index);
                     controls::build_ui_lifecycle_call(
                         module,
+                        cache.target(),
                         &mut block_ctx.ctx.b,
                         control.control_type,
                         LifecycleFunc::Destruct,
@@ -223,6 +267,7 @@ pub fn build_destruct_func(module: &Module, cache: &ObjectCache, block: &Block)
 
                 controls::build_lifecycle_call(
                     module,
+                    cache.target(),
                     &mut block_ctx.ctx.b,
                     control.control_type,
                     LifecycleFunc::Destruct,
@@ -235,6 +280,7 @@ pub fn build_destruct_func(module: &Module, cache: &ObjectCache, block: &Block)
                 let data_ptr = block_ctx.get_function_ptr(layout_index);
                 functions::build_lifecycle_call(
                     module,
+                    cache.target(),
                     &mut block_ctx.ctx.b,
                     *function,
                     functions::FunctionLifecycleFunc::Destruct,
@@ -261,5 +307,11 @@ pub fn build_lifecycle_call(
     const_ptr: PointerValue,
 ) {
     let func = get_lifecycle_func(module, cache, block, lifecycle);
-    builder.build_call(&func, &[&pointers_ptr, &const_ptr], "", true);
+    let instance = globals::get_instance_param(module, builder);
+    builder.build_call(
+        &func,
+        &globals::with_instance_arg(&[&pointers_ptr, &const_ptr], &instance),
+        "",
+        true,
+    );
 }
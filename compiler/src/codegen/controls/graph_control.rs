@@ -358,7 +358,7 @@ impl Control for GraphControl {
                     .ctx
                     .b
                     .build_load(
-                        &globals::get_sample_rate(control.ctx.module).as_pointer_value(),
+                        &globals::build_sample_rate_ptr(control.ctx.module, control.ctx.b),
                         "samplerate",
                     )
                     .into_vector_value(),
@@ -379,7 +379,7 @@ impl Control for GraphControl {
                     .ctx
                     .b
                     .build_load(
-                        &globals::get_bpm(control.ctx.module).as_pointer_value(),
+                        &globals::build_bpm_ptr(control.ctx.module, control.ctx.b),
                         "bpm",
                     )
                     .into_vector_value(),
@@ -19,7 +19,7 @@ use self::control_context::{ControlContext, ControlUiContext};
 use crate::ast::{ControlField, ControlType};
 use crate::codegen::data_analyzer::PointerSource;
 use crate::codegen::{
-    build_context_function, util, values, BuilderContext, LifecycleFunc, TargetProperties,
+    build_context_function, globals, util, values, BuilderContext, LifecycleFunc, TargetProperties,
 };
 use crate::mir::{ControlInitializer, VarType};
 use inkwell::attribute::AttrKind;
@@ -61,8 +61,10 @@ impl<'a> ControlFieldGenerator for PrivateGenerator<'a> {
         get_cb: &ControlFieldGeneratorCb,
         set_cb: &ControlFieldGeneratorCb,
     ) {
-        let (getter_func, getter_pass_by_val) = get_field_getter_func(self.module, field);
-        let (setter_func, setter_pass_by_val) = get_field_setter_func(self.module, field);
+        let (getter_func, getter_pass_by_val) =
+            get_field_getter_func(self.module, self.target, field);
+        let (setter_func, setter_pass_by_val) =
+            get_field_setter_func(self.module, self.target, field);
 
         build_field_getter_func(
             self.module,
@@ -81,8 +83,12 @@ impl<'a> ControlFieldGenerator for PrivateGenerator<'a> {
     }
 }
 
-pub fn get_group_type(context: &Context, control_type: ControlType) -> StructType {
-    values::remap_type(context, &VarType::of_control_value(control_type))
+pub fn get_group_type(
+    context: &Context,
+    target: &TargetProperties,
+    control_type: ControlType,
+) -> StructType {
+    values::remap_storage_type(context, target, &VarType::of_control_value(control_type))
 }
 
 pub fn get_field_type(context: &Context, field: ControlField) -> StructType {
@@ -137,25 +143,43 @@ map_controls! {
     Scope => ScopeControl
 }
 
+pub fn get_lifecycle_func_name(control_type: ControlType, lifecycle: LifecycleFunc) -> String {
+    format!("maxim.control.{}.{}", control_type, lifecycle)
+}
+
+pub fn get_ui_lifecycle_func_name(control_type: ControlType, lifecycle: LifecycleFunc) -> String {
+    format!("maxim.control.{}.ui_{}", control_type, lifecycle)
+}
+
+pub fn get_field_getter_func_name(field: ControlField) -> String {
+    format!("maxim.control.{}.getter", field)
+}
+
+pub fn get_field_setter_func_name(field: ControlField) -> String {
+    format!("maxim.control.{}.setter", field)
+}
+
 fn get_lifecycle_func(
     module: &Module,
+    target: &TargetProperties,
     control_type: ControlType,
     lifecycle: LifecycleFunc,
 ) -> FunctionValue {
-    let func_name = format!("maxim.control.{}.{}", control_type, lifecycle);
+    let func_name = get_lifecycle_func_name(control_type, lifecycle);
     let func = util::get_or_create_func(module, &func_name, true, &|| {
         let context = module.get_context();
+        let func_type = context.void_type().fn_type(
+            &[
+                &get_group_type(&context, target, control_type).ptr_type(AddressSpace::Generic),
+                &get_constant_ptr_type(&context, control_type).ptr_type(AddressSpace::Generic),
+                &get_data_type(&context, control_type).ptr_type(AddressSpace::Generic),
+                &get_shared_data_type(&context, control_type).ptr_type(AddressSpace::Generic),
+            ],
+            false,
+        );
         (
             Linkage::ExternalLinkage,
-            context.void_type().fn_type(
-                &[
-                    &get_group_type(&context, control_type).ptr_type(AddressSpace::Generic),
-                    &get_constant_ptr_type(&context, control_type).ptr_type(AddressSpace::Generic),
-                    &get_data_type(&context, control_type).ptr_type(AddressSpace::Generic),
-                    &get_shared_data_type(&context, control_type).ptr_type(AddressSpace::Generic),
-                ],
-                false,
-            ),
+            globals::add_instance_param(module, target, func_type),
         )
     });
     let context = module.get_context();
@@ -168,17 +192,18 @@ fn get_lifecycle_func(
 
 fn get_ui_lifecycle_func(
     module: &Module,
+    target: &TargetProperties,
     control_type: ControlType,
     lifecycle: LifecycleFunc,
 ) -> FunctionValue {
-    let func_name = format!("maxim.control.{}.ui_{}", control_type, lifecycle);
+    let func_name = get_ui_lifecycle_func_name(control_type, lifecycle);
     let func = util::get_or_create_func(module, &func_name, true, &|| {
         let context = module.get_context();
         (
             Linkage::ExternalLinkage,
             context.void_type().fn_type(
                 &[
-                    &get_group_type(&context, control_type).ptr_type(AddressSpace::Generic),
+                    &get_group_type(&context, target, control_type).ptr_type(AddressSpace::Generic),
                     &get_constant_ptr_type(&context, control_type).ptr_type(AddressSpace::Generic),
                     &get_data_type(&context, control_type).ptr_type(AddressSpace::Generic),
                     &get_shared_data_type(&context, control_type).ptr_type(AddressSpace::Generic),
@@ -197,47 +222,47 @@ fn get_ui_lifecycle_func(
     func
 }
 
-fn get_field_getter_func(module: &Module, field: ControlField) -> (FunctionValue, bool) {
+fn get_field_getter_func(
+    module: &Module,
+    target: &TargetProperties,
+    field: ControlField,
+) -> (FunctionValue, bool) {
     let var_type = VarType::of_control_field(field);
     let pass_by_val = values::pass_type_by_val(&var_type);
 
-    let func = util::get_or_create_func(
-        module,
-        &format!("maxim.control.{}.getter", field),
-        true,
-        &|| {
-            let control_type = ControlType::from(field);
-
-            let context = module.get_context();
-            let value_type = values::remap_type(&context, &var_type);
-            let group_type = get_group_type(&context, control_type).ptr_type(AddressSpace::Generic);
-            let const_type =
-                get_constant_ptr_type(&context, control_type).ptr_type(AddressSpace::Generic);
-            let data_type = get_data_type(&context, control_type).ptr_type(AddressSpace::Generic);
-            let shared_data_type =
-                get_shared_data_type(&context, control_type).ptr_type(AddressSpace::Generic);
-
-            let func_type = if pass_by_val {
-                value_type.fn_type(
-                    &[&group_type, &const_type, &data_type, &shared_data_type],
-                    false,
-                )
-            } else {
-                context.void_type().fn_type(
-                    &[
-                        &value_type.ptr_type(AddressSpace::Generic),
-                        &group_type,
-                        &const_type,
-                        &data_type,
-                        &shared_data_type,
-                    ],
-                    false,
-                )
-            };
-
-            (Linkage::ExternalLinkage, func_type)
-        },
-    );
+    let func = util::get_or_create_func(module, &get_field_getter_func_name(field), true, &|| {
+        let control_type = ControlType::from(field);
+
+        let context = module.get_context();
+        let value_type = values::remap_type(&context, &var_type);
+        let group_type =
+            get_group_type(&context, target, control_type).ptr_type(AddressSpace::Generic);
+        let const_type =
+            get_constant_ptr_type(&context, control_type).ptr_type(AddressSpace::Generic);
+        let data_type = get_data_type(&context, control_type).ptr_type(AddressSpace::Generic);
+        let shared_data_type =
+            get_shared_data_type(&context, control_type).ptr_type(AddressSpace::Generic);
+
+        let func_type = if pass_by_val {
+            value_type.fn_type(
+                &[&group_type, &const_type, &data_type, &shared_data_type],
+                false,
+            )
+        } else {
+            context.void_type().fn_type(
+                &[
+                    &value_type.ptr_type(AddressSpace::Generic),
+                    &group_type,
+                    &const_type,
+                    &data_type,
+                    &shared_data_type,
+                ],
+                false,
+            )
+        };
+
+        (Linkage::ExternalLinkage, func_type)
+    });
 
     let context = module.get_context();
 
@@ -256,53 +281,53 @@ fn get_field_getter_func(module: &Module, field: ControlField) -> (FunctionValue
     (func, pass_by_val)
 }
 
-fn get_field_setter_func(module: &Module, field: ControlField) -> (FunctionValue, bool) {
+fn get_field_setter_func(
+    module: &Module,
+    target: &TargetProperties,
+    field: ControlField,
+) -> (FunctionValue, bool) {
     let var_type = VarType::of_control_field(field);
     let pass_by_val = values::pass_type_by_val(&var_type);
 
-    let func = util::get_or_create_func(
-        module,
-        &format!("maxim.control.{}.setter", field),
-        true,
-        &|| {
-            let control_type = ControlType::from(field);
-
-            let context = module.get_context();
-            let value_type = values::remap_type(&context, &var_type);
-            let group_type = get_group_type(&context, control_type).ptr_type(AddressSpace::Generic);
-            let const_type =
-                get_constant_ptr_type(&context, control_type).ptr_type(AddressSpace::Generic);
-            let data_type = get_data_type(&context, control_type).ptr_type(AddressSpace::Generic);
-            let shared_data_type =
-                get_shared_data_type(&context, control_type).ptr_type(AddressSpace::Generic);
-
-            let func_type = if pass_by_val {
-                context.void_type().fn_type(
-                    &[
-                        &group_type,
-                        &const_type,
-                        &data_type,
-                        &shared_data_type,
-                        &value_type,
-                    ],
-                    false,
-                )
-            } else {
-                context.void_type().fn_type(
-                    &[
-                        &group_type,
-                        &const_type,
-                        &data_type,
-                        &shared_data_type,
-                        &value_type.ptr_type(AddressSpace::Generic),
-                    ],
-                    false,
-                )
-            };
-
-            (Linkage::ExternalLinkage, func_type)
-        },
-    );
+    let func = util::get_or_create_func(module, &get_field_setter_func_name(field), true, &|| {
+        let control_type = ControlType::from(field);
+
+        let context = module.get_context();
+        let value_type = values::remap_type(&context, &var_type);
+        let group_type =
+            get_group_type(&context, target, control_type).ptr_type(AddressSpace::Generic);
+        let const_type =
+            get_constant_ptr_type(&context, control_type).ptr_type(AddressSpace::Generic);
+        let data_type = get_data_type(&context, control_type).ptr_type(AddressSpace::Generic);
+        let shared_data_type =
+            get_shared_data_type(&context, control_type).ptr_type(AddressSpace::Generic);
+
+        let func_type = if pass_by_val {
+            context.void_type().fn_type(
+                &[
+                    &group_type,
+                    &const_type,
+                    &data_type,
+                    &shared_data_type,
+                    &value_type,
+                ],
+                false,
+            )
+        } else {
+            context.void_type().fn_type(
+                &[
+                    &group_type,
+                    &const_type,
+                    &data_type,
+                    &shared_data_type,
+                    &value_type.ptr_type(AddressSpace::Generic),
+                ],
+                false,
+            )
+        };
+
+        (Linkage::ExternalLinkage, func_type)
+    });
 
     let context = module.get_context();
     func.add_param_attribute(0, context.get_enum_attr(AttrKind::NoAlias, 1));
@@ -318,12 +343,13 @@ fn get_field_setter_func(module: &Module, field: ControlField) -> (FunctionValue
 
 pub fn build_field_get(
     module: &Module,
+    target: &TargetProperties,
     builder: &mut Builder,
     field: ControlField,
     ptrs: ControlPointers,
     out_val: PointerValue,
 ) {
-    let (func, pass_by_val) = get_field_getter_func(module, field);
+    let (func, pass_by_val) = get_field_getter_func(module, target, field);
 
     if pass_by_val {
         let get_val = builder
@@ -354,12 +380,13 @@ pub fn build_field_get(
 
 pub fn build_field_set(
     module: &Module,
+    target: &TargetProperties,
     builder: &mut Builder,
     field: ControlField,
     ptrs: ControlPointers,
     in_val: PointerValue,
 ) {
-    let (func, pass_by_val) = get_field_setter_func(module, field);
+    let (func, pass_by_val) = get_field_setter_func(module, target, field);
 
     let in_norm_val = if pass_by_val {
         builder.build_load(&in_val, "in")
@@ -383,15 +410,20 @@ pub fn build_field_set(
 
 pub fn build_lifecycle_call(
     module: &Module,
+    target: &TargetProperties,
     builder: &mut Builder,
     control_type: ControlType,
     lifecycle: LifecycleFunc,
     ptrs: ControlPointers,
 ) {
-    let func = get_lifecycle_func(module, control_type, lifecycle);
+    let func = get_lifecycle_func(module, target, control_type, lifecycle);
+    let instance = globals::get_instance_param(module, builder);
     builder.build_call(
         &func,
-        &[&ptrs.group, &ptrs.const_dat, &ptrs.data, &ptrs.shared],
+        &globals::with_instance_arg(
+            &[&ptrs.group, &ptrs.const_dat, &ptrs.data, &ptrs.shared],
+            &instance,
+        ),
         "",
         true,
     );
@@ -399,13 +431,14 @@ pub fn build_lifecycle_call(
 
 pub fn build_ui_lifecycle_call(
     module: &Module,
+    target: &TargetProperties,
     builder: &mut Builder,
     control_type: ControlType,
     lifecycle: LifecycleFunc,
     ptrs: ControlPointers,
     ui_ptr: PointerValue,
 ) {
-    let func = get_ui_lifecycle_func(module, control_type, lifecycle);
+    let func = get_ui_lifecycle_func(module, target, control_type, lifecycle);
     builder.build_call(
         &func,
         &[
@@ -427,7 +460,7 @@ fn build_lifecycle_func(
     lifecycle: LifecycleFunc,
     builder: &Fn(&mut ControlContext),
 ) {
-    let func = get_lifecycle_func(module, control, lifecycle);
+    let func = get_lifecycle_func(module, target, control, lifecycle);
     build_context_function(module, func, target, &|ctx: BuilderContext| {
         let val_ptr = ctx.func.get_nth_param(0).unwrap().into_pointer_value();
         let const_ptr = ctx.func.get_nth_param(1).unwrap().into_pointer_value();
@@ -453,7 +486,7 @@ fn build_ui_lifecycle_func(
     lifecycle: LifecycleFunc,
     builder: &Fn(&mut ControlUiContext),
 ) {
-    let func = get_ui_lifecycle_func(module, control, lifecycle);
+    let func = get_ui_lifecycle_func(module, target, control, lifecycle);
     build_context_function(module, func, target, &|ctx: BuilderContext| {
         let val_ptr = ctx.func.get_nth_param(0).unwrap().into_pointer_value();
         let const_ptr = ctx.func.get_nth_param(1).unwrap().into_pointer_value();
@@ -30,7 +30,7 @@ fn beats_from_frequency(
 ) -> VectorValue {
     builder.build_float_div(
         builder
-            .build_load(&globals::get_bpm(module).as_pointer_value(), "bpm")
+            .build_load(&globals::build_bpm_ptr(module, builder), "bpm")
             .into_vector_value(),
         builder.build_float_mul(util::get_vec_spread(context, 60.), val, ""),
         "",
@@ -47,14 +47,14 @@ fn beats_from_samples(
         builder.build_float_mul(
             val,
             builder
-                .build_load(&globals::get_bpm(module).as_pointer_value(), "bpm")
+                .build_load(&globals::build_bpm_ptr(module, builder), "bpm")
                 .into_vector_value(),
             "",
         ),
         builder.build_float_mul(
             builder
                 .build_load(
-                    &globals::get_sample_rate(module).as_pointer_value(),
+                    &globals::build_sample_rate_ptr(module, builder),
                     "samplerate",
                 )
                 .into_vector_value(),
@@ -75,7 +75,7 @@ fn beats_from_seconds(
         val,
         builder.build_float_mul(
             builder
-                .build_load(&globals::get_bpm(module).as_pointer_value(), "bpm")
+                .build_load(&globals::build_bpm_ptr(module, builder), "bpm")
                 .into_vector_value(),
             util::get_vec_spread(context, 60.),
             "",
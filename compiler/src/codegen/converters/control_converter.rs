@@ -138,7 +138,7 @@ fn control_from_samples(
                 util::get_vec_spread(context, 0.1),
                 builder
                     .build_load(
-                        &globals::get_sample_rate(module).as_pointer_value(),
+                        &globals::build_sample_rate_ptr(module, builder),
                         "samplerate",
                     )
                     .into_vector_value(),
@@ -22,7 +22,7 @@ fn frequency_from_beats(
 ) -> VectorValue {
     builder.build_float_div(
         builder
-            .build_load(&globals::get_bpm(module).as_pointer_value(), "bpm")
+            .build_load(&globals::build_bpm_ptr(module, builder), "bpm")
             .into_vector_value(),
         builder.build_float_mul(val, util::get_vec_spread(context, 60.), ""),
         "",
@@ -103,7 +103,7 @@ fn frequency_from_samples(
     builder.build_float_div(
         builder
             .build_load(
-                &globals::get_sample_rate(module).as_pointer_value(),
+                &globals::build_sample_rate_ptr(module, builder),
                 "samplerate",
             )
             .into_vector_value(),
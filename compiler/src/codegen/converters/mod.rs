@@ -11,7 +11,7 @@ mod seconds_converter;
 
 use crate::ast::FormType;
 use crate::codegen::values::NumValue;
-use crate::codegen::{build_context_function, util, BuilderContext, TargetProperties};
+use crate::codegen::{build_context_function, globals, util, BuilderContext, TargetProperties};
 use inkwell::basic_block::BasicBlock;
 use inkwell::builder::Builder;
 use inkwell::context::Context;
@@ -52,13 +52,21 @@ impl<'a> ConvertGenerator for PrivateGenerator<'a> {
     }
 }
 
-pub fn get_convert_func(module: &Module, target_form: FormType) -> FunctionValue {
-    let func_name = format!("maxim.converter.{}", target_form);
+pub fn get_convert_func_name(target_form: FormType) -> String {
+    format!("maxim.converter.{}", target_form)
+}
+
+pub fn get_convert_func(
+    module: &Module,
+    target: &TargetProperties,
+    target_form: FormType,
+) -> FunctionValue {
+    let func_name = get_convert_func_name(target_form);
     util::get_or_create_func(module, &func_name, true, &|| {
         let num_type = NumValue::get_type(&module.get_context());
         (
             Linkage::ExternalLinkage,
-            num_type.fn_type(&[&num_type], false),
+            globals::add_instance_param(module, target, num_type.fn_type(&[&num_type], false)),
         )
     })
 }
@@ -69,7 +77,7 @@ pub fn build_convert_func(
     target_form: FormType,
     build_func: &Fn(&mut ConvertGenerator),
 ) {
-    let func = get_convert_func(module, target_form);
+    let func = get_convert_func(module, target, target_form);
     build_context_function(module, func, target, &|ctx: BuilderContext| {
         let input_val = func.get_nth_param(0).unwrap().into_struct_value();
         let result_num = NumValue::new_undef(ctx.context, ctx.allocb);
@@ -171,13 +179,20 @@ pub fn build_funcs(module: &Module, target: &TargetProperties) {
 pub fn build_convert_direct(
     builder: &mut Builder,
     module: &Module,
+    target: &TargetProperties,
     source: &NumValue,
     target_form: FormType,
 ) -> StructValue {
-    let convert_func = get_convert_func(module, target_form);
+    let convert_func = get_convert_func(module, target, target_form);
     let loaded_val = builder.build_load(&source.val, "in").into_struct_value();
+    let instance = globals::get_instance_param(module, builder);
     builder
-        .build_call(&convert_func, &[&loaded_val], "num.converted", true)
+        .build_call(
+            &convert_func,
+            &globals::with_instance_arg(&[&loaded_val], &instance),
+            "num.converted",
+            true,
+        )
         .left()
         .unwrap()
         .into_struct_value()
@@ -187,12 +202,13 @@ pub fn build_convert(
     alloca_builder: &mut Builder,
     builder: &mut Builder,
     module: &Module,
+    target: &TargetProperties,
     source: &NumValue,
     target_form: FormType,
 ) -> NumValue {
     let context = module.get_context();
     let result_num = NumValue::new_undef(&context, alloca_builder);
-    let converted_num = build_convert_direct(builder, module, source, target_form);
+    let converted_num = build_convert_direct(builder, module, target, source, target_form);
     builder.build_store(&result_num.val, &converted_num);
     result_num
 }
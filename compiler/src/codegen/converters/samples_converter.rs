@@ -25,7 +25,7 @@ fn samples_from_beats(
             builder.build_float_mul(
                 builder
                     .build_load(
-                        &globals::get_sample_rate(module).as_pointer_value(),
+                        &globals::build_sample_rate_ptr(module, builder),
                         "samplerate",
                     )
                     .into_vector_value(),
@@ -35,7 +35,7 @@ fn samples_from_beats(
             "",
         ),
         builder
-            .build_load(&globals::get_bpm(module).as_pointer_value(), "bpm")
+            .build_load(&globals::build_bpm_ptr(module, builder), "bpm")
             .into_vector_value(),
         "",
     )
@@ -52,7 +52,7 @@ fn samples_from_control(
             val,
             builder
                 .build_load(
-                    &globals::get_sample_rate(module).as_pointer_value(),
+                    &globals::build_sample_rate_ptr(module, builder),
                     "samplerate",
                 )
                 .into_vector_value(),
@@ -76,7 +76,7 @@ fn samples_from_frequency(
     builder.build_float_div(
         builder
             .build_load(
-                &globals::get_sample_rate(module).as_pointer_value(),
+                &globals::build_sample_rate_ptr(module, builder),
                 "samplerate",
             )
             .into_vector_value(),
@@ -95,7 +95,7 @@ fn samples_from_seconds(
         val,
         builder
             .build_load(
-                &globals::get_sample_rate(module).as_pointer_value(),
+                &globals::build_sample_rate_ptr(module, builder),
                 "samplerate",
             )
             .into_vector_value(),
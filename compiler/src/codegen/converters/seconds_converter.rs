@@ -23,7 +23,7 @@ fn seconds_from_beats(
         val,
         builder.build_float_div(
             builder
-                .build_load(&globals::get_bpm(module).as_pointer_value(), "bpm")
+                .build_load(&globals::build_bpm_ptr(module, builder), "bpm")
                 .into_vector_value(),
             util::get_vec_spread(context, 60.),
             "",
@@ -68,7 +68,7 @@ fn seconds_from_samples(
         val,
         builder
             .build_load(
-                &globals::get_sample_rate(module).as_pointer_value(),
+                &globals::build_sample_rate_ptr(module, builder),
                 "samplerate",
             )
             .into_vector_value(),
@@ -0,0 +1,622 @@
+use super::bridge::build_bridge_call;
+use super::layout::Layout;
+use super::object_builder::{FuncBuilder, ObjectBuilder};
+use crate::ast::{FormType, OperatorType, UnaryOperation};
+use crate::codegen::data_analyzer::{self, BlockLayout};
+use crate::codegen::functions::FunctionLifecycleFunc;
+use crate::codegen::{
+    block, controls, converters, functions, globals, math, values, LifecycleFunc, ObjectCache,
+};
+use crate::mir::block::{Function, Global, Statement};
+use crate::mir::{Block, ConstantValue, VarType};
+use cranelift_codegen::ir::condcodes::FloatCC;
+use cranelift_codegen::ir::{types, InstBuilder, Value};
+use inkwell::types::BasicType;
+use inkwell::AddressSpace;
+
+/// The result of a statement, which is kept in memory with the layout LLVM code gives it, so it
+/// can be passed to the library.
+struct StatementValue {
+    ptr: Value,
+    var_type: VarType,
+}
+
+struct BlockContext<'a, 'b> {
+    f: &'a mut FuncBuilder<'b>,
+    cache: &'a ObjectCache,
+    layout: &'a Layout<'a>,
+    block_layout: &'a BlockLayout,
+    pointers_ptr: Value,
+    const_ptr: Value,
+    statements: Vec<StatementValue>,
+}
+
+impl<'a, 'b> BlockContext<'a, 'b> {
+    fn get_control_ptrs(&mut self, index: usize) -> [Value; 4] {
+        let layout_index = self.block_layout.control_index(index);
+        let control_struct = self
+            .layout
+            .field_struct(&self.block_layout.pointer_struct, layout_index);
+        let control_offset = self
+            .layout
+            .offset_of(&self.block_layout.pointer_struct, layout_index);
+        let base_ptr = self.f.offset(self.pointers_ptr, control_offset);
+        let const_offset = self
+            .layout
+            .offset_of(&self.block_layout.constant_struct, layout_index);
+
+        [
+            self.f
+                .load_ptr(base_ptr, self.layout.offset_of(&control_struct, 0)),
+            self.f.offset(self.const_ptr, const_offset),
+            self.f
+                .load_ptr(base_ptr, self.layout.offset_of(&control_struct, 1)),
+            self.f
+                .load_ptr(base_ptr, self.layout.offset_of(&control_struct, 2)),
+        ]
+    }
+
+    fn get_ui_ptr(&mut self, index: usize) -> Value {
+        let layout_index = self.block_layout.control_index(index);
+        let control_struct = self
+            .layout
+            .field_struct(&self.block_layout.pointer_struct, layout_index);
+        let control_offset = self
+            .layout
+            .offset_of(&self.block_layout.pointer_struct, layout_index);
+        self.f.load_ptr(
+            self.pointers_ptr,
+            control_offset + self.layout.offset_of(&control_struct, 3),
+        )
+    }
+
+    fn get_function_ptr(&mut self, layout_index: usize) -> Value {
+        let offset = self
+            .layout
+            .offset_of(&self.block_layout.pointer_struct, layout_index);
+        self.f.load_ptr(self.pointers_ptr, offset)
+    }
+
+    fn alloc_value(&mut self, var_type: &VarType) -> Value {
+        if *var_type == VarType::Void {
+            return self.f.null_ptr();
+        }
+
+        let value_type = self.layout.value_type(var_type);
+        let size = self.layout.size_of(&value_type);
+        let align = self.layout.align_of(&value_type);
+        self.f.alloc(size.max(1), align)
+    }
+
+    fn load_lanes(&mut self, num_ptr: Value) -> [Value; 2] {
+        let lane_size = self.layout.num().lane_size;
+        [
+            self.f.load(types::F64, num_ptr, 0),
+            self.f.load(types::F64, num_ptr, lane_size),
+        ]
+    }
+
+    fn store_lanes(&mut self, lanes: [Value; 2], num_ptr: Value) {
+        let lane_size = self.layout.num().lane_size;
+        self.f.store(lanes[0], num_ptr, 0);
+        self.f.store(lanes[1], num_ptr, lane_size);
+    }
+
+    fn load_form(&mut self, num_ptr: Value) -> Value {
+        let form_offset = self.layout.num().form_offset;
+        self.f.load(types::I8, num_ptr, form_offset)
+    }
+
+    fn store_form(&mut self, form: Value, num_ptr: Value) {
+        let form_offset = self.layout.num().form_offset;
+        self.f.store(form, num_ptr, form_offset);
+    }
+
+    fn const_num(&mut self, value: f64) -> Value {
+        let num_ptr = self.alloc_value(&VarType::Num);
+        self.store_constant(
+            &ConstantValue::new_num(value, value, FormType::None),
+            num_ptr,
+            0,
+        );
+        num_ptr
+    }
+
+    fn store_constant(&mut self, constant: &ConstantValue, ptr: Value, offset: u64) {
+        match constant {
+            ConstantValue::Num(num) => {
+                let num_layout = self.layout.num();
+                self.f.store_f64(num.left, ptr, offset);
+                self.f
+                    .store_f64(num.right, ptr, offset + num_layout.lane_size);
+                self.f
+                    .store_i8(num.form as u8, ptr, offset + num_layout.form_offset);
+            }
+            ConstantValue::Tuple(tuple) => {
+                let tuple_type = self.layout.value_type(&VarType::of_constant(constant));
+                for (item_index, item) in tuple.items.iter().enumerate() {
+                    let item_offset = self.layout.offset_of(&tuple_type, item_index);
+                    self.store_constant(item, ptr, offset + item_offset);
+                }
+            }
+        }
+    }
+
+    fn bool_to_float(&mut self, val: Value) -> Value {
+        let extended = self.f.b.ins().uextend(types::I32, val);
+        self.f.b.ins().fcvt_from_uint(types::F64, extended)
+    }
+}
+
+fn build_statement(ctx: &mut BlockContext, index: usize, statement: &Statement) -> StatementValue {
+    match statement {
+        Statement::Constant(constant) => {
+            let var_type = VarType::of_constant(constant);
+            let ptr = ctx.alloc_value(&var_type);
+            ctx.store_constant(constant, ptr, 0);
+            StatementValue { ptr, var_type }
+        }
+        Statement::Global(global) => {
+            let ptr = ctx.alloc_value(&VarType::Num);
+            let global_ptr = ctx.f.global_ptr(match global {
+                Global::SampleRate => globals::SAMPLERATE_GLOBAL_NAME,
+                Global::BPM => globals::BPM_GLOBAL_NAME,
+            });
+            let lanes = [
+                ctx.f.load(types::F64, global_ptr, 0),
+                ctx.f.load(types::F64, global_ptr, 8),
+            ];
+            ctx.store_lanes(lanes, ptr);
+            let form_offset = ctx.layout.num().form_offset;
+            ctx.f.store_i8(FormType::None as u8, ptr, form_offset);
+            StatementValue {
+                ptr,
+                var_type: VarType::Num,
+            }
+        }
+        Statement::NumConvert { target_form, input } => {
+            let ptr = ctx.alloc_value(&VarType::Num);
+            let input_ptr = ctx.statements[*input].ptr;
+            build_bridge_call(
+                ctx.f,
+                &converters::get_convert_func_name(*target_form),
+                &[ptr, input_ptr],
+            );
+            StatementValue {
+                ptr,
+                var_type: VarType::Num,
+            }
+        }
+        Statement::NumCast { target_form, input } => {
+            let ptr = ctx.alloc_value(&VarType::Num);
+            let input_ptr = ctx.statements[*input].ptr;
+            let num_layout = ctx.layout.num();
+            ctx.f
+                .copy(input_ptr, ptr, num_layout.size, num_layout.align);
+            ctx.f
+                .store_i8(*target_form as u8, ptr, num_layout.form_offset);
+            StatementValue {
+                ptr,
+                var_type: VarType::Num,
+            }
+        }
+        Statement::NumUnaryOp { op, input } => {
+            let ptr = ctx.alloc_value(&VarType::Num);
+            let input_ptr = ctx.statements[*input].ptr;
+            let [left, right] = ctx.load_lanes(input_ptr);
+            let mut apply = |x: Value| match op {
+                UnaryOperation::Positive => x,
+                UnaryOperation::Negative => ctx.f.b.ins().fneg(x),
+                UnaryOperation::Not => {
+                    let zero = ctx.f.b.ins().f64const(0.);
+                    let is_zero = ctx.f.b.ins().fcmp(FloatCC::Equal, x, zero);
+                    ctx.bool_to_float(is_zero)
+                }
+            };
+            let lanes = [apply(left), apply(right)];
+            ctx.store_lanes(lanes, ptr);
+            let form = ctx.load_form(input_ptr);
+            ctx.store_form(form, ptr);
+            StatementValue {
+                ptr,
+                var_type: VarType::Num,
+            }
+        }
+        Statement::NumMathOp { op, lhs, rhs } => {
+            let ptr = ctx.alloc_value(&VarType::Num);
+            let lhs_ptr = ctx.statements[*lhs].ptr;
+            let rhs_ptr = ctx.statements[*rhs].ptr;
+            build_math_op(ctx, *op, lhs_ptr, rhs_ptr, ptr);
+            let form = ctx.load_form(lhs_ptr);
+            ctx.store_form(form, ptr);
+            StatementValue {
+                ptr,
+                var_type: VarType::Num,
+            }
+        }
+        Statement::Extract { tuple, index } => {
+            let tuple_type = ctx.layout.value_type(&ctx.statements[*tuple].var_type);
+            let var_type = match &ctx.statements[*tuple].var_type {
+                VarType::Tuple(items) => items[*index].clone(),
+                _ => panic!("Attempted to extract element of non-tuple"),
+            };
+            let item_offset = ctx.layout.offset_of(&tuple_type, *index);
+            let tuple_ptr = ctx.statements[*tuple].ptr;
+            StatementValue {
+                ptr: ctx.f.offset(tuple_ptr, item_offset),
+                var_type,
+            }
+        }
+        Statement::Combine { indexes } => {
+            let var_type = VarType::Tuple(
+                indexes
+                    .iter()
+                    .map(|&index| ctx.statements[index].var_type.clone())
+                    .collect(),
+            );
+            let tuple_type = ctx.layout.value_type(&var_type);
+            let ptr = ctx.alloc_value(&var_type);
+            for (item_index, &index) in indexes.iter().enumerate() {
+                let item_type = ctx.layout.value_type(&ctx.statements[index].var_type);
+                let item_size = ctx.layout.size_of(&item_type);
+                let item_align = ctx.layout.align_of(&item_type);
+                let item_ptr = ctx.statements[index].ptr;
+                let dest_ptr = ctx
+                    .f
+                    .offset(ptr, ctx.layout.offset_of(&tuple_type, item_index));
+                ctx.f.copy(item_ptr, dest_ptr, item_size, item_align);
+            }
+            StatementValue { ptr, var_type }
+        }
+        Statement::CallFunc {
+            function,
+            args,
+            varargs,
+        } => build_call_func(ctx, index, *function, args, varargs),
+        Statement::StoreControl {
+            control,
+            field,
+            value,
+        } => {
+            let ptrs = ctx.get_control_ptrs(*control);
+            let value_ptr = ctx.statements[*value].ptr;
+            build_bridge_call(
+                ctx.f,
+                &controls::get_field_setter_func_name(*field),
+                &[ptrs[0], ptrs[1], ptrs[2], ptrs[3], value_ptr],
+            );
+            StatementValue {
+                ptr: ctx.alloc_value(&VarType::Void),
+                var_type: VarType::Void,
+            }
+        }
+        Statement::LoadControl { control, field } => {
+            let var_type = VarType::of_control_field(*field);
+            let ptr = ctx.alloc_value(&var_type);
+            let ptrs = ctx.get_control_ptrs(*control);
+            build_bridge_call(
+                ctx.f,
+                &controls::get_field_getter_func_name(*field),
+                &[ptr, ptrs[0], ptrs[1], ptrs[2], ptrs[3]],
+            );
+            StatementValue { ptr, var_type }
+        }
+    }
+}
+
+fn build_math_op(
+    ctx: &mut BlockContext,
+    op: OperatorType,
+    lhs_ptr: Value,
+    rhs_ptr: Value,
+    result_ptr: Value,
+) {
+    // these go through the library, which takes the vector at the start of each number
+    match op {
+        OperatorType::Modulo | OperatorType::Power => {
+            let func_name = if op == OperatorType::Modulo {
+                math::MOD_V2F64_FUNC_NAME
+            } else {
+                math::POW_V2F64_FUNC_NAME
+            };
+            build_bridge_call(ctx.f, func_name, &[result_ptr, lhs_ptr, rhs_ptr]);
+            return;
+        }
+        _ => {}
+    }
+
+    let lhs = ctx.load_lanes(lhs_ptr);
+    let rhs = ctx.load_lanes(rhs_ptr);
+    let mut apply = |a: Value, b: Value| match op {
+        OperatorType::Identity => a,
+        OperatorType::Add => ctx.f.b.ins().fadd(a, b),
+        OperatorType::Subtract => ctx.f.b.ins().fsub(a, b),
+        OperatorType::Multiply => ctx.f.b.ins().fmul(a, b),
+        OperatorType::Divide => ctx.f.b.ins().fdiv(a, b),
+        OperatorType::Modulo | OperatorType::Power => unreachable!(),
+        OperatorType::BitwiseAnd
+        | OperatorType::BitwiseOr
+        | OperatorType::BitwiseXor
+        | OperatorType::LogicalAnd
+        | OperatorType::LogicalOr => {
+            let mut a_int = ctx.f.b.ins().fcvt_to_sint_sat(types::I32, a);
+            let mut b_int = ctx.f.b.ins().fcvt_to_sint_sat(types::I32, b);
+            if op == OperatorType::LogicalAnd || op == OperatorType::LogicalOr {
+                a_int = ctx.f.b.ins().band_imm(a_int, 1);
+                b_int = ctx.f.b.ins().band_imm(b_int, 1);
+            }
+            let result_int = match op {
+                OperatorType::BitwiseAnd | OperatorType::LogicalAnd => {
+                    ctx.f.b.ins().band(a_int, b_int)
+                }
+                OperatorType::BitwiseOr | OperatorType::LogicalOr => {
+                    ctx.f.b.ins().bor(a_int, b_int)
+                }
+                _ => ctx.f.b.ins().bxor(a_int, b_int),
+            };
+            ctx.f.b.ins().fcvt_from_sint(types::F64, result_int)
+        }
+        OperatorType::LogicalEqual
+        | OperatorType::LogicalNotEqual
+        | OperatorType::LogicalGt
+        | OperatorType::LogicalLt
+        | OperatorType::LogicalGte
+        | OperatorType::LogicalLte => {
+            let condition = match op {
+                OperatorType::LogicalEqual => FloatCC::Equal,
+                OperatorType::LogicalNotEqual => FloatCC::OrderedNotEqual,
+                OperatorType::LogicalGt => FloatCC::GreaterThan,
+                OperatorType::LogicalLt => FloatCC::LessThan,
+                OperatorType::LogicalGte => FloatCC::GreaterThanOrEqual,
+                _ => FloatCC::LessThanOrEqual,
+            };
+            let compared = ctx.f.b.ins().fcmp(condition, a, b);
+            ctx.bool_to_float(compared)
+        }
+    };
+    let lanes = [apply(lhs[0], rhs[0]), apply(lhs[1], rhs[1])];
+    ctx.store_lanes(lanes, result_ptr);
+}
+
+/// Fills in the optional arguments of a call, the same way each function's `gen_real_args` does.
+fn build_real_args(ctx: &mut BlockContext, function: Function, args: &mut Vec<Value>) {
+    match function {
+        Function::Hold | Function::Accum => {
+            if args.len() < 3 {
+                args.push(ctx.const_num(0.));
+            }
+        }
+        Function::Delay => {
+            if args.len() < 3 {
+                let delay_constant = ctx.const_num(1.);
+                args.insert(1, delay_constant);
+            }
+        }
+        Function::SinOsc | Function::SawOsc | Function::TriOsc | Function::RmpOsc => {
+            if args.len() < 2 {
+                args.push(ctx.const_num(0.));
+            }
+        }
+        Function::SqrOsc => {
+            if args.len() < 2 {
+                args.push(ctx.const_num(0.));
+            }
+            if args.len() < 3 {
+                args.push(ctx.const_num(0.5));
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Builds the vararg struct passed to a function, matching the one built in
+/// `functions::build_call`. By-value varargs are copied into the array, others are pointed to.
+fn build_varargs(ctx: &mut BlockContext, vararg_type: &VarType, varargs: &[usize]) -> Value {
+    let is_by_value = values::pass_type_by_val(vararg_type);
+    let item_type = ctx.layout.value_type(vararg_type);
+    let item_ptr_type = item_type.ptr_type(AddressSpace::Generic);
+    let (item_size, item_align) = if is_by_value {
+        (
+            ctx.layout.size_of(&item_type),
+            ctx.layout.align_of(&item_type),
+        )
+    } else {
+        (
+            ctx.layout.size_of(&item_ptr_type),
+            ctx.layout.align_of(&item_ptr_type),
+        )
+    };
+
+    let array_ptr = ctx
+        .f
+        .alloc(item_size * varargs.len().max(1) as u64, item_align);
+    for (vararg_index, &vararg) in varargs.iter().enumerate() {
+        let vararg_ptr = ctx.statements[vararg].ptr;
+        let item_offset = item_size * vararg_index as u64;
+        if is_by_value {
+            let dest_ptr = ctx.f.offset(array_ptr, item_offset);
+            ctx.f.copy(vararg_ptr, dest_ptr, item_size, item_align);
+        } else {
+            ctx.f.store(vararg_ptr, array_ptr, item_offset);
+        }
+    }
+
+    // the array pointer is typed differently to the one in LLVM code, but is the same size
+    let context = ctx.layout.context;
+    let vararg_struct_type = context.struct_type(&[&context.i8_type(), &item_ptr_type], false);
+    let struct_ptr = ctx.f.alloc(
+        ctx.layout.size_of(&vararg_struct_type),
+        ctx.layout.align_of(&vararg_struct_type),
+    );
+    ctx.f.store_i8(varargs.len() as u8, struct_ptr, 0);
+    ctx.f.store(
+        array_ptr,
+        struct_ptr,
+        ctx.layout.offset_of(&vararg_struct_type, 1),
+    );
+    struct_ptr
+}
+
+fn build_call_func(
+    ctx: &mut BlockContext,
+    index: usize,
+    function: Function,
+    args: &[usize],
+    varargs: &[usize],
+) -> StatementValue {
+    let layout_index = ctx.block_layout.statement_index(index).unwrap();
+    let data_ptr = ctx.get_function_ptr(layout_index);
+    let return_type = function.return_type();
+    let ptr = ctx.alloc_value(&return_type);
+
+    // bridges take the return pointer first, but functions that return by pointer already take
+    // it after the data
+    let mut call_args = if values::pass_type_by_val(&return_type) {
+        vec![ptr, data_ptr]
+    } else {
+        vec![data_ptr, ptr]
+    };
+
+    let mut arg_ptrs: Vec<_> = args.iter().map(|&arg| ctx.statements[arg].ptr).collect();
+    build_real_args(ctx, function, &mut arg_ptrs);
+    call_args.extend(arg_ptrs);
+
+    if let Some(vararg_type) = function.var_arg() {
+        let vararg_struct = build_varargs(ctx, &vararg_type, varargs);
+        call_args.push(vararg_struct);
+    }
+
+    build_bridge_call(
+        ctx.f,
+        &functions::get_update_func_name(function),
+        &call_args,
+    );
+    StatementValue {
+        ptr,
+        var_type: return_type,
+    }
+}
+
+fn build_control_lifecycle_calls(ctx: &mut BlockContext, block: &Block, lifecycle: LifecycleFunc) {
+    let include_ui = ctx.cache.target().include_ui;
+    for (control_index, control) in block.controls.iter().enumerate() {
+        let ptrs = ctx.get_control_ptrs(control_index);
+        let ui_args = if include_ui {
+            let ui_ptr = ctx.get_ui_ptr(control_index);
+            Some([ptrs[0], ptrs[1], ptrs[2], ptrs[3], ui_ptr])
+        } else {
+            None
+        };
+        let ui_func_name = controls::get_ui_lifecycle_func_name(control.control_type, lifecycle);
+
+        // UI functions are constructed and updated after the control, but destructed before
+        if let (Some(ui_args), LifecycleFunc::Destruct) = (&ui_args, lifecycle) {
+            build_bridge_call(ctx.f, &ui_func_name, ui_args);
+        }
+        build_bridge_call(
+            ctx.f,
+            &controls::get_lifecycle_func_name(control.control_type, lifecycle),
+            &ptrs,
+        );
+        if let Some(ui_args) = &ui_args {
+            if lifecycle != LifecycleFunc::Destruct {
+                build_bridge_call(ctx.f, &ui_func_name, ui_args);
+            }
+        }
+    }
+}
+
+/// Stores the longest delay each delay call can reach in its data before it's constructed, the
+/// same way `block::build_construct_func` does.
+fn build_delay_max_seconds(ctx: &mut BlockContext, block: &Block) {
+    let delay_type = functions::get_data_type(ctx.cache.context(), Function::Delay);
+    let max_seconds_offset = ctx.layout.offset_of(
+        &delay_type,
+        functions::DelayFunction::MAX_SECONDS_FIELD as usize,
+    );
+    for (statement_index, statement) in block.statements.iter().enumerate() {
+        if let Statement::CallFunc {
+            function: Function::Delay,
+            args,
+            ..
+        } = statement
+        {
+            let layout_index = ctx.block_layout.statement_index(statement_index).unwrap();
+            let data_ptr = ctx.get_function_ptr(layout_index);
+            let max_seconds = data_analyzer::get_delay_max_seconds(
+                ctx.cache.target().max_delay_seconds,
+                block,
+                args,
+            );
+            ctx.f.store_f64(max_seconds, data_ptr, max_seconds_offset);
+        }
+    }
+}
+
+fn build_function_lifecycle_calls(ctx: &mut BlockContext, lifecycle: FunctionLifecycleFunc) {
+    for (func_index, &function) in ctx.block_layout.functions.iter().enumerate() {
+        let layout_index = ctx.block_layout.function_index(func_index);
+        let data_ptr = ctx.get_function_ptr(layout_index);
+        build_bridge_call(
+            ctx.f,
+            &functions::get_lifecycle_func_name(function, lifecycle),
+            &[data_ptr],
+        );
+    }
+}
+
+fn build_lifecycle_func(
+    builder: &mut ObjectBuilder,
+    cache: &ObjectCache,
+    layout: &Layout,
+    block: &Block,
+    lifecycle: LifecycleFunc,
+) {
+    let func_name = block::get_lifecycle_func_name(cache, block.id.id, lifecycle);
+    let block_layout = cache.block_layout(block.id.id).unwrap();
+    builder.define_func(&func_name, 2, &|f: &mut FuncBuilder, params: &[Value]| {
+        let mut ctx = BlockContext {
+            f,
+            cache,
+            layout,
+            block_layout,
+            pointers_ptr: params[0],
+            const_ptr: params[1],
+            statements: Vec::new(),
+        };
+
+        match lifecycle {
+            LifecycleFunc::Construct => {
+                build_control_lifecycle_calls(&mut ctx, block, lifecycle);
+                build_delay_max_seconds(&mut ctx, block);
+                build_function_lifecycle_calls(&mut ctx, FunctionLifecycleFunc::Construct);
+            }
+            LifecycleFunc::Update => {
+                build_control_lifecycle_calls(&mut ctx, block, lifecycle);
+                for (statement_index, statement) in block.statements.iter().enumerate() {
+                    let statement_value = build_statement(&mut ctx, statement_index, statement);
+                    ctx.statements.push(statement_value);
+                }
+            }
+            LifecycleFunc::Destruct => {
+                build_control_lifecycle_calls(&mut ctx, block, lifecycle);
+                build_function_lifecycle_calls(&mut ctx, FunctionLifecycleFunc::Destruct);
+            }
+        }
+    });
+}
+
+/// Builds an object with the lifecycle functions of a block, equivalent to the ones
+/// `block::build_funcs` builds.
+pub fn build_block_object(cache: &ObjectCache, block: &Block, name: &str) -> Vec<u8> {
+    let layout = Layout::new(cache.context(), cache.target());
+    let mut builder = ObjectBuilder::new(name);
+    for &lifecycle in &[
+        LifecycleFunc::Construct,
+        LifecycleFunc::Update,
+        LifecycleFunc::Destruct,
+    ] {
+        build_lifecycle_func(&mut builder, cache, &layout, block, lifecycle);
+    }
+    builder.finish()
+}
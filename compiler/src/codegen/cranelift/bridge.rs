@@ -0,0 +1,110 @@
+use super::object_builder::FuncBuilder;
+use crate::codegen::{
+    build_context_function, BuilderContext, ModuleFunctionIterator, TargetProperties,
+};
+use cranelift_codegen::ir::Value;
+use inkwell::module::{Linkage, Module};
+use inkwell::types::{BasicType, BasicTypeEnum, PointerType};
+use inkwell::values::{BasicValue, BasicValueEnum, FunctionValue};
+use inkwell::AddressSpace;
+
+/// The name of the function code built with Cranelift calls instead of a library function.
+pub fn get_bridge_func_name(func_name: &str) -> String {
+    format!("{}.bridge", func_name)
+}
+
+fn get_ptr_type(value_type: &BasicTypeEnum) -> PointerType {
+    match value_type {
+        BasicTypeEnum::IntType(val) => val.ptr_type(AddressSpace::Generic),
+        BasicTypeEnum::FloatType(val) => val.ptr_type(AddressSpace::Generic),
+        BasicTypeEnum::PointerType(val) => val.ptr_type(AddressSpace::Generic),
+        BasicTypeEnum::StructType(val) => val.ptr_type(AddressSpace::Generic),
+        BasicTypeEnum::ArrayType(val) => val.ptr_type(AddressSpace::Generic),
+        BasicTypeEnum::VectorType(val) => val.ptr_type(AddressSpace::Generic),
+    }
+}
+
+fn is_passed_by_ptr(value_type: &BasicTypeEnum) -> bool {
+    match value_type {
+        BasicTypeEnum::StructType(_)
+        | BasicTypeEnum::ArrayType(_)
+        | BasicTypeEnum::VectorType(_) => true,
+        _ => false,
+    }
+}
+
+fn build_bridge(module: &Module, target: &TargetProperties, func: FunctionValue) {
+    let context = module.get_context();
+    let func_type = func.get_type();
+    let param_types = func_type.get_param_types();
+    let return_type = func_type.get_return_type();
+
+    let mut bridge_param_types: Vec<BasicTypeEnum> = Vec::new();
+    if let Some(return_type) = &return_type {
+        bridge_param_types.push(get_ptr_type(return_type).into());
+    }
+    bridge_param_types.extend(param_types.iter().map(|param_type| {
+        if is_passed_by_ptr(param_type) {
+            get_ptr_type(param_type).into()
+        } else {
+            *param_type
+        }
+    }));
+    let bridge_param_refs: Vec<_> = bridge_param_types
+        .iter()
+        .map(|param_type| param_type as &BasicType)
+        .collect();
+
+    let bridge_name = get_bridge_func_name(func.get_name().to_str().unwrap());
+    let bridge_func = module.add_function(
+        &bridge_name,
+        &context.void_type().fn_type(&bridge_param_refs, false),
+        Some(&Linkage::ExternalLinkage),
+    );
+
+    build_context_function(module, bridge_func, target, &|ctx: BuilderContext| {
+        let mut bridge_params = ctx.func.params();
+        let return_ptr = return_type
+            .as_ref()
+            .map(|_| bridge_params.next().unwrap().into_pointer_value());
+
+        let args: Vec<BasicValueEnum> = bridge_params
+            .zip(param_types.iter())
+            .map(|(param, param_type)| {
+                if is_passed_by_ptr(param_type) {
+                    ctx.b.build_load(&param.into_pointer_value(), "arg")
+                } else {
+                    param
+                }
+            })
+            .collect();
+        let arg_refs: Vec<_> = args.iter().map(|arg| arg as &BasicValue).collect();
+
+        let return_val = ctx.b.build_call(&func, &arg_refs, "", true);
+        if let Some(return_ptr) = return_ptr {
+            ctx.b.build_store(&return_ptr, &return_val.left().unwrap());
+        }
+        ctx.b.build_return(None);
+    });
+}
+
+/// Builds a bridge for each function defined in the library. Library functions use the fast
+/// calling convention, and pass numbers as vectors and aggregates, none of which Cranelift can
+/// call. Bridges use the C calling convention, take vectors and aggregates by pointer instead,
+/// and write the return value of the function they call through a pointer passed before the
+/// other parameters.
+pub fn build_bridges(module: &Module, target: &TargetProperties) {
+    let library_funcs: Vec<_> = ModuleFunctionIterator::new(module)
+        .filter(|func| {
+            func.get_name().to_str().unwrap().starts_with("maxim.") && !func.is_declaration()
+        })
+        .collect();
+    for func in library_funcs {
+        build_bridge(module, target, func);
+    }
+}
+
+/// Calls the bridge of a library function.
+pub fn build_bridge_call(f: &mut FuncBuilder, func_name: &str, args: &[Value]) {
+    f.call(&get_bridge_func_name(func_name), args);
+}
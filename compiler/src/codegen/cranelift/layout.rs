@@ -0,0 +1,86 @@
+use crate::codegen::values::{self, NumValue};
+use crate::codegen::{StoragePrecision, TargetProperties};
+use crate::mir::VarType;
+use inkwell::context::Context;
+use inkwell::targets::TargetData;
+use inkwell::types::{AnyType, BasicType, BasicTypeEnum, StructType};
+
+/// Sizes and offsets of the types LLVM code is built with, so code built with Cranelift reads and
+/// writes memory exactly where LLVM code would.
+pub struct Layout<'a> {
+    pub context: &'a Context,
+    data: TargetData,
+}
+
+/// Where the parts of a number are, for either the type used in blocks or the one used for
+/// storage.
+#[derive(Debug, Clone, Copy)]
+pub struct NumLayout {
+    pub size: u64,
+    pub align: u64,
+    pub lane_size: u64,
+    pub form_offset: u64,
+}
+
+impl<'a> Layout<'a> {
+    pub fn new(context: &'a Context, target: &TargetProperties) -> Self {
+        Layout {
+            context,
+            data: target.machine.get_data(),
+        }
+    }
+
+    pub fn offset_of(&self, struct_type: &StructType, index: usize) -> u64 {
+        self.data
+            .offset_of_element(struct_type, index as u32)
+            .unwrap()
+    }
+
+    pub fn size_of<T: AnyType>(&self, value_type: &T) -> u64 {
+        self.data.get_abi_size(value_type)
+    }
+
+    /// The ABI alignment of a type, found from where it's placed after a byte in a struct.
+    pub fn align_of<T: AnyType + BasicType>(&self, value_type: &T) -> u64 {
+        let padded_type = self
+            .context
+            .struct_type(&[&self.context.i8_type(), value_type], false);
+        self.offset_of(&padded_type, 1)
+    }
+
+    pub fn field_type(&self, struct_type: &StructType, index: usize) -> BasicTypeEnum {
+        struct_type.get_field_types().remove(index)
+    }
+
+    pub fn field_struct(&self, struct_type: &StructType, index: usize) -> StructType {
+        self.field_type(struct_type, index).into_struct_type()
+    }
+
+    pub fn value_type(&self, var_type: &VarType) -> StructType {
+        values::remap_type(self.context, var_type)
+    }
+
+    pub fn num(&self) -> NumLayout {
+        self.num_layout(&NumValue::get_type(self.context), 8)
+    }
+
+    pub fn storage_num(&self, precision: StoragePrecision) -> NumLayout {
+        let lane_size = match precision {
+            StoragePrecision::Double => 8,
+            StoragePrecision::Single => 4,
+        };
+        self.num_layout(
+            &NumValue::get_storage_type(self.context, precision),
+            lane_size,
+        )
+    }
+
+    fn num_layout(&self, num_type: &StructType, lane_size: u64) -> NumLayout {
+        NumLayout {
+            size: self.size_of(num_type),
+            align: self.align_of(num_type),
+            lane_size,
+            form_offset: self.offset_of(num_type, 1),
+        }
+    }
+}
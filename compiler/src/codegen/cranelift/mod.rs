@@ -0,0 +1,9 @@
+mod block;
+mod bridge;
+mod layout;
+mod object_builder;
+mod surface;
+
+pub use self::block::build_block_object;
+pub use self::bridge::build_bridges;
+pub use self::surface::{build_surface_object, supports_surface};
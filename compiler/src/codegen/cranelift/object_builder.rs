@@ -0,0 +1,196 @@
+use cranelift_codegen::ir::{
+    types, AbiParam, FuncRef, Function, InstBuilder, MemFlags, StackSlotData, StackSlotKind, Type,
+    UserFuncName, Value,
+};
+use cranelift_codegen::settings::{self, Configurable};
+use cranelift_codegen::Context;
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext};
+use cranelift_module::{default_libcall_names, Linkage, Module};
+use cranelift_object::{ObjectBuilder as ModuleBuilder, ObjectModule};
+use std::collections::HashMap;
+
+/// Builds an object file for the host with Cranelift. Functions defined in it take pointers and
+/// use the C calling convention, so they can be called from code built with LLVM and the other
+/// way around.
+pub struct ObjectBuilder {
+    module: ObjectModule,
+    builder_context: FunctionBuilderContext,
+}
+
+impl ObjectBuilder {
+    pub fn new(name: &str) -> Self {
+        let mut flags = settings::builder();
+        flags.set("opt_level", "speed").unwrap();
+        let isa = cranelift_native::builder()
+            .unwrap()
+            .finish(settings::Flags::new(flags))
+            .unwrap();
+        let module_builder = ModuleBuilder::new(isa, name, default_libcall_names()).unwrap();
+
+        ObjectBuilder {
+            module: ObjectModule::new(module_builder),
+            builder_context: FunctionBuilderContext::new(),
+        }
+    }
+
+    /// Defines an exported function taking `param_count` pointers and returning nothing. The
+    /// callback is given the function's parameters, and the return is added after it.
+    pub fn define_func(
+        &mut self,
+        name: &str,
+        param_count: usize,
+        build: &Fn(&mut FuncBuilder, &[Value]),
+    ) {
+        let pointer_type = self.module.target_config().pointer_type();
+        let mut signature = self.module.make_signature();
+        signature
+            .params
+            .extend((0..param_count).map(|_| AbiParam::new(pointer_type)));
+        let func_id = self
+            .module
+            .declare_function(name, Linkage::Export, &signature)
+            .unwrap();
+
+        let mut func =
+            Function::with_name_signature(UserFuncName::user(0, func_id.as_u32()), signature);
+        {
+            let mut b = FunctionBuilder::new(&mut func, &mut self.builder_context);
+            let entry_block = b.create_block();
+            b.append_block_params_for_function_params(entry_block);
+            b.switch_to_block(entry_block);
+            let params = b.block_params(entry_block).to_vec();
+
+            let mut func_builder = FuncBuilder {
+                b,
+                module: &mut self.module,
+                imported_funcs: HashMap::new(),
+            };
+            build(&mut func_builder, &params);
+            func_builder.b.ins().return_(&[]);
+            func_builder.b.seal_all_blocks();
+            func_builder.b.finalize();
+        }
+
+        let mut context = Context::for_function(func);
+        self.module.define_function(func_id, &mut context).unwrap();
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.module.finish().emit().unwrap()
+    }
+}
+
+/// Builds the body of one function, with helpers for the memory accesses and calls generated
+/// code is mostly made of.
+pub struct FuncBuilder<'a> {
+    pub b: FunctionBuilder<'a>,
+    module: &'a mut ObjectModule,
+    imported_funcs: HashMap<String, FuncRef>,
+}
+
+impl<'a> FuncBuilder<'a> {
+    pub fn pointer_type(&self) -> Type {
+        self.module.target_config().pointer_type()
+    }
+
+    /// Reserves space on the stack, and returns a pointer to it.
+    pub fn alloc(&mut self, size: u64, align: u64) -> Value {
+        let slot = self.b.create_sized_stack_slot(StackSlotData::new(
+            StackSlotKind::ExplicitSlot,
+            size as u32,
+            align.max(1).trailing_zeros() as u8,
+        ));
+        let pointer_type = self.pointer_type();
+        self.b.ins().stack_addr(pointer_type, slot, 0)
+    }
+
+    pub fn offset(&mut self, ptr: Value, offset: u64) -> Value {
+        if offset == 0 {
+            ptr
+        } else {
+            self.b.ins().iadd_imm(ptr, offset as i64)
+        }
+    }
+
+    pub fn load(&mut self, value_type: Type, ptr: Value, offset: u64) -> Value {
+        self.b
+            .ins()
+            .load(value_type, MemFlags::trusted(), ptr, offset as i32)
+    }
+
+    pub fn load_ptr(&mut self, ptr: Value, offset: u64) -> Value {
+        let pointer_type = self.pointer_type();
+        self.load(pointer_type, ptr, offset)
+    }
+
+    pub fn store(&mut self, value: Value, ptr: Value, offset: u64) {
+        self.b
+            .ins()
+            .store(MemFlags::trusted(), value, ptr, offset as i32);
+    }
+
+    pub fn store_f64(&mut self, value: f64, ptr: Value, offset: u64) {
+        let value = self.b.ins().f64const(value);
+        self.store(value, ptr, offset);
+    }
+
+    pub fn store_i8(&mut self, value: u8, ptr: Value, offset: u64) {
+        let value = self.b.ins().iconst(types::I8, i64::from(value));
+        self.store(value, ptr, offset);
+    }
+
+    /// Copies `size` bytes between two values that don't overlap.
+    pub fn copy(&mut self, src: Value, dest: Value, size: u64, align: u64) {
+        let config = self.module.target_config();
+        let align = align.min(16) as u8;
+        self.b.emit_small_memory_copy(
+            config,
+            dest,
+            src,
+            size,
+            align,
+            align,
+            true,
+            MemFlags::trusted(),
+        );
+    }
+
+    pub fn null_ptr(&mut self) -> Value {
+        let pointer_type = self.pointer_type();
+        self.b.ins().iconst(pointer_type, 0)
+    }
+
+    /// Calls a function returning nothing, which is linked in by name. The signature is taken
+    /// from the types of the arguments.
+    pub fn call(&mut self, name: &str, args: &[Value]) {
+        let func_ref = match self.imported_funcs.get(name) {
+            Some(&func_ref) => func_ref,
+            None => {
+                let mut signature = self.module.make_signature();
+                signature.params.extend(
+                    args.iter()
+                        .map(|&arg| AbiParam::new(self.b.func.dfg.value_type(arg))),
+                );
+                let func_id = self
+                    .module
+                    .declare_function(name, Linkage::Import, &signature)
+                    .unwrap();
+                let func_ref = self.module.declare_func_in_func(func_id, self.b.func);
+                self.imported_funcs.insert(name.to_string(), func_ref);
+                func_ref
+            }
+        };
+        self.b.ins().call(func_ref, args);
+    }
+
+    /// Gets the address of a global, which is linked in by name.
+    pub fn global_ptr(&mut self, name: &str) -> Value {
+        let data_id = self
+            .module
+            .declare_data(name, Linkage::Import, true, false)
+            .unwrap();
+        let global_value = self.module.declare_data_in_func(data_id, self.b.func);
+        let pointer_type = self.pointer_type();
+        self.b.ins().symbol_value(pointer_type, global_value)
+    }
+}
@@ -0,0 +1,475 @@
+use super::bridge::build_bridge_call;
+use super::layout::{Layout, NumLayout};
+use super::object_builder::{FuncBuilder, ObjectBuilder};
+use crate::codegen::{
+    block, data_analyzer, intrinsics, surface, values, LifecycleFunc, ObjectCache, StoragePrecision,
+};
+use crate::mir::{Node, NodeData, OversampleFactor, Surface, SurfaceRef, UpdateRate};
+use cranelift_codegen::ir::condcodes::IntCC;
+use cranelift_codegen::ir::{types, InstBuilder, MemFlags, Value};
+use inkwell::types::StructType;
+
+struct SurfaceContext<'a, 'b> {
+    f: &'a mut FuncBuilder<'b>,
+    cache: &'a ObjectCache,
+    layout: &'a Layout<'a>,
+    surface: &'a Surface,
+    storage_num: NumLayout,
+}
+
+impl<'a, 'b> SurfaceContext<'a, 'b> {
+    /// Loads one lane of a stored number, converted to double precision.
+    fn load_lane(&mut self, num_ptr: Value, lane: u64) -> Value {
+        let offset = lane * self.storage_num.lane_size;
+        match self.cache.target().storage_precision {
+            StoragePrecision::Double => self.f.load(types::F64, num_ptr, offset),
+            StoragePrecision::Single => {
+                let stored = self.f.load(types::F32, num_ptr, offset);
+                self.f.b.ins().fpromote(types::F64, stored)
+            }
+        }
+    }
+
+    /// Stores one lane of a number, converted to the precision it's stored at.
+    fn store_lane(&mut self, value: Value, num_ptr: Value, lane: u64) {
+        let offset = lane * self.storage_num.lane_size;
+        let stored = match self.cache.target().storage_precision {
+            StoragePrecision::Double => value,
+            StoragePrecision::Single => self.f.b.ins().fdemote(types::F32, value),
+        };
+        self.f.store(stored, num_ptr, offset);
+    }
+
+    fn copy_num(&mut self, src: Value, dest: Value) {
+        let storage_num = self.storage_num;
+        self.f.copy(src, dest, storage_num.size, storage_num.align);
+    }
+}
+
+/// Whether a surface can be built with Cranelift. Oversampling and node profiling are only
+/// supported by the LLVM backend, so surfaces using them are built there instead.
+pub fn supports_surface(cache: &ObjectCache, surface: &Surface) -> bool {
+    !cache.target().profile_nodes
+        && surface
+            .nodes
+            .iter()
+            .all(|node| node.oversample_factor == OversampleFactor::None)
+}
+
+/// Builds a loop calling a lifecycle function on each voice of an extracted surface, skipping
+/// voices that aren't active.
+fn build_voice_loop(
+    ctx: &mut SurfaceContext,
+    surface: SurfaceRef,
+    lifecycle: LifecycleFunc,
+    voices_ptr: Value,
+    valid_bitmap: Option<Value>,
+) {
+    let voice_stride = ctx
+        .layout
+        .size_of(&ctx.cache.surface_layout(surface).unwrap().pointer_struct);
+    let func_name = surface::get_lifecycle_func_name(ctx.cache, surface, lifecycle);
+
+    let check_block = ctx.f.b.create_block();
+    let run_block = ctx.f.b.create_block();
+    let next_block = ctx.f.b.create_block();
+    let end_block = ctx.f.b.create_block();
+    ctx.f.b.append_block_param(check_block, types::I32);
+
+    let start_index = ctx.f.b.ins().iconst(types::I32, 0);
+    ctx.f.b.ins().jump(check_block, &[start_index]);
+    ctx.f.b.switch_to_block(check_block);
+
+    let current_index = ctx.f.b.block_params(check_block)[0];
+    let can_continue_loop = ctx.f.b.ins().icmp_imm(
+        IntCC::UnsignedLessThan,
+        current_index,
+        i64::from(values::ARRAY_CAPACITY),
+    );
+    let active_block = ctx.f.b.create_block();
+    ctx.f
+        .b
+        .ins()
+        .brif(can_continue_loop, active_block, &[], end_block, &[]);
+    ctx.f.b.switch_to_block(active_block);
+
+    if let Some(active_bitmap) = valid_bitmap {
+        // check if this iteration is active according to the bitmap
+        let shifted_bitmap = ctx.f.b.ins().ushr(active_bitmap, current_index);
+        let active_bit = ctx.f.b.ins().band_imm(shifted_bitmap, 1);
+        ctx.f
+            .b
+            .ins()
+            .brif(active_bit, run_block, &[], next_block, &[]);
+    } else {
+        ctx.f.b.ins().jump(run_block, &[]);
+    }
+
+    ctx.f.b.switch_to_block(run_block);
+    let pointer_type = ctx.f.pointer_type();
+    let index_extended = ctx.f.b.ins().uextend(pointer_type, current_index);
+    let voice_offset = ctx.f.b.ins().imul_imm(index_extended, voice_stride as i64);
+    let voice_ptr = ctx.f.b.ins().iadd(voices_ptr, voice_offset);
+    ctx.f.call(&func_name, &[voice_ptr]);
+    ctx.f.b.ins().jump(next_block, &[]);
+
+    ctx.f.b.switch_to_block(next_block);
+    let next_index = ctx.f.b.ins().iadd_imm(current_index, 1);
+    ctx.f.b.ins().jump(check_block, &[next_index]);
+
+    ctx.f.b.switch_to_block(end_block);
+}
+
+fn build_node_call(
+    ctx: &mut SurfaceContext,
+    node: &Node,
+    lifecycle: LifecycleFunc,
+    pointers_ptr: Value,
+    pointer_struct: &StructType,
+) {
+    match &node.data {
+        NodeData::Dummy => {}
+        NodeData::Custom { block, .. } => {
+            let const_ptr = ctx
+                .f
+                .offset(pointers_ptr, ctx.layout.offset_of(pointer_struct, 0));
+            let node_ptrs = ctx
+                .f
+                .offset(pointers_ptr, ctx.layout.offset_of(pointer_struct, 1));
+            let func_name = block::get_lifecycle_func_name(ctx.cache, *block, lifecycle);
+            ctx.f.call(&func_name, &[node_ptrs, const_ptr]);
+        }
+        NodeData::Group(surface_id) => {
+            let func_name = surface::get_lifecycle_func_name(ctx.cache, *surface_id, lifecycle);
+            ctx.f.call(&func_name, &[pointers_ptr]);
+        }
+        NodeData::ExtractGroup {
+            surface: surface_id,
+            source_sockets,
+            dest_sockets,
+        } => {
+            let voices_ptr = ctx
+                .f
+                .offset(pointers_ptr, ctx.layout.offset_of(pointer_struct, 0));
+            let sources_struct = ctx.layout.field_struct(pointer_struct, 1);
+            let sources_ptr = ctx
+                .f
+                .offset(pointers_ptr, ctx.layout.offset_of(pointer_struct, 1));
+            let dests_struct = ctx.layout.field_struct(pointer_struct, 2);
+            let dests_ptr = ctx
+                .f
+                .offset(pointers_ptr, ctx.layout.offset_of(pointer_struct, 2));
+            let bitmap_ptr_offset = ctx.layout.offset_of(pointer_struct, 3);
+
+            // if this is the update lifecycle function and there are source groups, generate a
+            // bitmap of which indices are valid
+            let valid_bitmap = if lifecycle == LifecycleFunc::Update && !source_sockets.is_empty() {
+                let active_bitmap = (0..source_sockets.len())
+                    .map(|socket_index| {
+                        let array_ptr = ctx.f.load_ptr(
+                            sources_ptr,
+                            ctx.layout.offset_of(&sources_struct, socket_index),
+                        );
+                        ctx.f.load(types::I32, array_ptr, 0)
+                    })
+                    .collect::<Vec<_>>()
+                    .into_iter()
+                    .fold(None, |acc: Option<Value>, bitmap| match acc {
+                        Some(acc) => Some(ctx.f.b.ins().band(acc, bitmap)),
+                        None => Some(bitmap),
+                    })
+                    .unwrap();
+                let bitmap_ptr = ctx.f.load_ptr(pointers_ptr, bitmap_ptr_offset);
+                ctx.f.store(active_bitmap, bitmap_ptr, 0);
+
+                Some(active_bitmap)
+            } else {
+                None
+            };
+
+            build_voice_loop(ctx, *surface_id, lifecycle, voices_ptr, valid_bitmap);
+
+            // set the bitmaps of all output arrays to be this input
+            if lifecycle == LifecycleFunc::Update {
+                let active_bitmap = match valid_bitmap {
+                    Some(active_bitmap) => active_bitmap,
+                    None => ctx.f.b.ins().iconst(types::I32, -1),
+                };
+
+                for dest_socket_index in 0..dest_sockets.len() {
+                    let array_ptr = ctx.f.load_ptr(
+                        dests_ptr,
+                        ctx.layout.offset_of(&dests_struct, dest_socket_index),
+                    );
+                    ctx.f.store(active_bitmap, array_ptr, 0);
+                }
+            }
+        }
+        NodeData::UnitDelay => {
+            // Unit delays are ordered after every other node, so copying here makes the value
+            // written this sample visible to readers of the delayed group on the next one.
+            if lifecycle == LifecycleFunc::Update {
+                let value_type = values::remap_storage_type(
+                    ctx.layout.context,
+                    ctx.cache.target(),
+                    &ctx.surface.groups[node.sockets[0].group_id].value_type,
+                );
+                let source_ptr = ctx
+                    .f
+                    .load_ptr(pointers_ptr, ctx.layout.offset_of(pointer_struct, 0));
+                let delayed_ptr = ctx
+                    .f
+                    .load_ptr(pointers_ptr, ctx.layout.offset_of(pointer_struct, 1));
+                let size = ctx.layout.size_of(&value_type);
+                let align = ctx.layout.align_of(&value_type);
+                ctx.f.copy(source_ptr, delayed_ptr, size, align);
+            }
+        }
+    }
+}
+
+/// Calls a node that only updates every `control_rate_divisor` samples, the same way
+/// `surface::build_control_rate_node_call` does.
+fn build_control_rate_node_call(
+    ctx: &mut SurfaceContext,
+    node: &Node,
+    lifecycle: LifecycleFunc,
+    pointers_ptr: Value,
+    pointer_struct: &StructType,
+) {
+    let data_struct = ctx.layout.field_struct(pointer_struct, 0);
+    let data_ptrs = ctx
+        .f
+        .offset(pointers_ptr, ctx.layout.offset_of(pointer_struct, 0));
+    if lifecycle != LifecycleFunc::Update {
+        build_node_call(ctx, node, lifecycle, data_ptrs, &data_struct);
+        return;
+    }
+    let interpolated_count = node.get_interpolated_sockets(&ctx.surface.groups).len();
+
+    let rate_struct = ctx.layout.field_struct(pointer_struct, 1);
+    let rate_ptrs = ctx
+        .f
+        .offset(pointers_ptr, ctx.layout.offset_of(pointer_struct, 1));
+    let values_struct = ctx
+        .layout
+        .field_type(&rate_struct, 1)
+        .into_pointer_type()
+        .element_type()
+        .into_struct_type();
+    let groups_struct = ctx.layout.field_struct(&rate_struct, 3);
+    let phase_ptr = ctx
+        .f
+        .load_ptr(rate_ptrs, ctx.layout.offset_of(&rate_struct, 0));
+    let targets_ptr = ctx
+        .f
+        .load_ptr(rate_ptrs, ctx.layout.offset_of(&rate_struct, 1));
+    let starts_ptr = ctx
+        .f
+        .load_ptr(rate_ptrs, ctx.layout.offset_of(&rate_struct, 2));
+    let groups_offset = ctx.layout.offset_of(&rate_struct, 3);
+
+    let phase = ctx.f.load(types::I32, phase_ptr, 0);
+    let run_block = ctx.f.b.create_block();
+    let interpolate_block = ctx.f.b.create_block();
+    ctx.f
+        .b
+        .ins()
+        .brif(phase, interpolate_block, &[], run_block, &[]);
+    ctx.f.b.switch_to_block(run_block);
+
+    // interpolation starts from wherever the groups are now, so there's no jump if the previous
+    // ramp was interrupted
+    let group_ptrs: Vec<_> = (0..interpolated_count)
+        .map(|socket_index| {
+            ctx.f.load_ptr(
+                rate_ptrs,
+                groups_offset + ctx.layout.offset_of(&groups_struct, socket_index),
+            )
+        })
+        .collect();
+    for (socket_index, &group_ptr) in group_ptrs.iter().enumerate() {
+        let start_ptr = ctx.f.offset(
+            starts_ptr,
+            ctx.layout.offset_of(&values_struct, socket_index),
+        );
+        ctx.copy_num(group_ptr, start_ptr);
+    }
+    build_node_call(ctx, node, lifecycle, data_ptrs, &data_struct);
+    ctx.f.b.ins().jump(interpolate_block, &[]);
+    ctx.f.b.switch_to_block(interpolate_block);
+
+    let divisor = ctx.cache.target().control_rate_divisor;
+    let next_phase = ctx.f.b.ins().iadd_imm(phase, 1);
+
+    if interpolated_count > 0 {
+        let next_phase_float = ctx.f.b.ins().fcvt_from_uint(types::F64, next_phase);
+        let divisor_float = ctx.f.b.ins().f64const(f64::from(divisor));
+        let progress = ctx.f.b.ins().fdiv(next_phase_float, divisor_float);
+
+        for socket_index in 0..interpolated_count {
+            // the groups are loaded again, as the loads above only happen on update samples
+            let group_ptr = ctx.f.load_ptr(
+                rate_ptrs,
+                groups_offset + ctx.layout.offset_of(&groups_struct, socket_index),
+            );
+            let value_offset = ctx.layout.offset_of(&values_struct, socket_index);
+            let target_ptr = ctx.f.offset(targets_ptr, value_offset);
+            let start_ptr = ctx.f.offset(starts_ptr, value_offset);
+
+            for lane in 0..2 {
+                let start = ctx.load_lane(start_ptr, lane);
+                let target = ctx.load_lane(target_ptr, lane);
+                let delta = ctx.f.b.ins().fsub(target, start);
+                let scaled_delta = ctx.f.b.ins().fmul(delta, progress);
+                let interpolated = ctx.f.b.ins().fadd(start, scaled_delta);
+                ctx.store_lane(interpolated, group_ptr, lane);
+            }
+            let form_offset = ctx.storage_num.form_offset;
+            let target_form = ctx.f.load(types::I8, target_ptr, form_offset);
+            ctx.f.store(target_form, group_ptr, form_offset);
+        }
+    }
+
+    let is_last_phase = ctx
+        .f
+        .b
+        .ins()
+        .icmp_imm(IntCC::Equal, next_phase, i64::from(divisor));
+    let zero_phase = ctx.f.b.ins().iconst(types::I32, 0);
+    let wrapped_phase = ctx.f.b.ins().select(is_last_phase, zero_phase, next_phase);
+    ctx.f.store(wrapped_phase, phase_ptr, 0);
+}
+
+/// Replaces non-finite values written by a node with zero, and records a fault if any are found,
+/// the same way `surface::build_sanitize_outputs` does.
+fn build_sanitize_outputs(
+    ctx: &mut SurfaceContext,
+    node_index: usize,
+    socket_count: usize,
+    pointers_ptr: Value,
+    pointer_struct: &StructType,
+) {
+    let sanitize_struct = ctx.layout.field_struct(pointer_struct, 1);
+    let sanitize_ptrs = ctx
+        .f
+        .offset(pointers_ptr, ctx.layout.offset_of(pointer_struct, 1));
+    let groups_struct = ctx.layout.field_struct(&sanitize_struct, 1);
+    let groups_offset = ctx.layout.offset_of(&sanitize_struct, 1);
+    let generation_ptr = ctx
+        .f
+        .load_ptr(sanitize_ptrs, ctx.layout.offset_of(&sanitize_struct, 0));
+
+    let exponent_mask = 0x7FF0_0000_0000_0000;
+    let mut any_faulted = ctx.f.b.ins().iconst(types::I8, 0);
+    for socket_index in 0..socket_count {
+        let group_ptr = ctx.f.load_ptr(
+            sanitize_ptrs,
+            groups_offset + ctx.layout.offset_of(&groups_struct, socket_index),
+        );
+        for lane in 0..2 {
+            let value = ctx.load_lane(group_ptr, lane);
+            let bits = ctx.f.b.ins().bitcast(types::I64, MemFlags::new(), value);
+            let exponent = ctx.f.b.ins().band_imm(bits, exponent_mask);
+            let non_finite = ctx
+                .f
+                .b
+                .ins()
+                .icmp_imm(IntCC::Equal, exponent, exponent_mask);
+            let zero = ctx.f.b.ins().f64const(0.);
+            let sanitized = ctx.f.b.ins().select(non_finite, zero, value);
+            ctx.store_lane(sanitized, group_ptr, lane);
+            any_faulted = ctx.f.b.ins().bor(any_faulted, non_finite);
+        }
+    }
+
+    let fault_block = ctx.f.b.create_block();
+    let end_block = ctx.f.b.create_block();
+    ctx.f
+        .b
+        .ins()
+        .brif(any_faulted, fault_block, &[], end_block, &[]);
+
+    ctx.f.b.switch_to_block(fault_block);
+    let surface_id = ctx.f.b.ins().iconst(types::I64, ctx.surface.id.id as i64);
+    let node_index = ctx.f.b.ins().iconst(types::I32, node_index as i64);
+    build_bridge_call(
+        ctx.f,
+        intrinsics::RECORD_FAULT_FUNC_NAME,
+        &[generation_ptr, surface_id, node_index],
+    );
+    ctx.f.b.ins().jump(end_block, &[]);
+
+    ctx.f.b.switch_to_block(end_block);
+}
+
+fn build_lifecycle_func(
+    builder: &mut ObjectBuilder,
+    cache: &ObjectCache,
+    layout: &Layout,
+    surface: &Surface,
+    lifecycle: LifecycleFunc,
+) {
+    let func_name = surface::get_lifecycle_func_name(cache, surface.id.id, lifecycle);
+    let surface_layout = cache.surface_layout(surface.id.id).unwrap();
+    builder.define_func(&func_name, 1, &|f: &mut FuncBuilder, params: &[Value]| {
+        let pointers_ptr = params[0];
+        let mut ctx = SurfaceContext {
+            f,
+            cache,
+            layout,
+            surface,
+            storage_num: layout.storage_num(cache.target().storage_precision),
+        };
+
+        for (node_index, node) in surface.nodes.iter().enumerate() {
+            let layout_ptr_index = surface_layout.node_ptr_index(node_index);
+            let node_struct = layout.field_struct(&surface_layout.pointer_struct, layout_ptr_index);
+            let node_ptrs = ctx.f.offset(
+                pointers_ptr,
+                layout.offset_of(&surface_layout.pointer_struct, layout_ptr_index),
+            );
+            let sanitized_sockets =
+                data_analyzer::get_sanitized_sockets(cache.target(), node, &surface.groups);
+            let (data_ptrs, data_struct) = if sanitized_sockets.is_empty() {
+                (node_ptrs, node_struct)
+            } else {
+                (
+                    ctx.f.offset(node_ptrs, layout.offset_of(&node_struct, 0)),
+                    layout.field_struct(&node_struct, 0),
+                )
+            };
+
+            if node.update_rate == UpdateRate::Audio {
+                build_node_call(&mut ctx, node, lifecycle, data_ptrs, &data_struct);
+            } else {
+                build_control_rate_node_call(&mut ctx, node, lifecycle, data_ptrs, &data_struct);
+            }
+
+            if lifecycle == LifecycleFunc::Update && !sanitized_sockets.is_empty() {
+                build_sanitize_outputs(
+                    &mut ctx,
+                    node_index,
+                    sanitized_sockets.len(),
+                    node_ptrs,
+                    &node_struct,
+                );
+            }
+        }
+    });
+}
+
+/// Builds an object with the lifecycle functions of a surface, equivalent to the ones
+/// `surface::build_funcs` builds. Check the surface can be built with `supports_surface` first.
+pub fn build_surface_object(cache: &ObjectCache, surface: &Surface, name: &str) -> Vec<u8> {
+    let layout = Layout::new(cache.context(), cache.target());
+    let mut builder = ObjectBuilder::new(name);
+    for &lifecycle in &[
+        LifecycleFunc::Construct,
+        LifecycleFunc::Update,
+        LifecycleFunc::Destruct,
+    ] {
+        build_lifecycle_func(&mut builder, cache, &layout, surface, lifecycle);
+    }
+    builder.finish()
+}
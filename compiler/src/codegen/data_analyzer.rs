@@ -1,12 +1,17 @@
-use crate::codegen::TargetProperties;
-use crate::codegen::{controls, functions, values, ObjectCache};
+use crate::codegen::{controls, functions, half_band, values, ObjectCache};
+use crate::codegen::{StoragePrecision, TargetProperties};
 use crate::mir::block::{Function, Statement};
-use crate::mir::{Block, Node, NodeData, Surface, ValueGroup, ValueGroupSource};
+use crate::mir::{
+    Block, BlockRef, ConstantValue, Node, NodeData, OversampleFactor, Surface, SurfaceRef,
+    UpdateRate, ValueGroup, ValueGroupSource, VarType,
+};
+use crate::util::stable_hasher::StableHasher;
 use inkwell::context::Context;
 use inkwell::types::{BasicType, BasicTypeEnum, StructType};
 use inkwell::values::{BasicValue, StructValue};
 use inkwell::AddressSpace;
 use std::collections::HashMap;
+use std::hash::Hasher;
 use std::{fmt, iter};
 
 #[derive(Debug, Clone, Copy)]
@@ -69,6 +74,25 @@ pub struct SurfaceLayout {
     pub node_layouts: Vec<NodeLayout>,
     node_scratch_offset: usize,
     node_initializer_offset: usize,
+    profile_ptr_index: Option<usize>,
+}
+
+/// The number of updates each node keeps timings for when the target profiles nodes. Must be a
+/// power of two.
+pub const PROFILE_WINDOW: u32 = 128;
+
+/// The timings of a node over the last `PROFILE_WINDOW` updates, in cycles. The struct contains
+/// the position the next timing will be written to, the number of timings written (up to the size
+/// of the window), and the timings themselves.
+pub fn get_profile_record_type(context: &Context) -> StructType {
+    context.struct_type(
+        &[
+            &context.i32_type(),
+            &context.i32_type(),
+            &context.i32_type().array_type(PROFILE_WINDOW),
+        ],
+        false,
+    )
 }
 
 /// Builds up the structure types used for initializing/retaining state of a node.
@@ -81,10 +105,38 @@ pub struct SurfaceLayout {
 ///  - `pointers` is a struct initialized to pointers to other structs. We also keep track of a data structure
 ///    recording where each entry should point, either to initialized or scratch with a GEP path, or to a
 ///    socket.
+///
+/// Oversampled nodes, nodes that don't run at audio rate and nodes with sanitized outputs have
+/// their layout wrapped with some extra state, see `build_oversampled_layout`,
+/// `build_control_rate_layout` and `build_sanitized_layout`.
 pub fn build_node_layout(
     cache: &ObjectCache,
     node: &Node,
     parent_groups: &[ValueGroup],
+) -> NodeLayout {
+    let layout = build_node_data_layout(cache, node, parent_groups);
+    let layout = match node.oversample_factor {
+        OversampleFactor::None => layout,
+        _ => build_oversampled_layout(cache.context(), cache.target(), node, parent_groups, layout),
+    };
+    let layout = match node.update_rate {
+        UpdateRate::Audio => layout,
+        UpdateRate::ControlHold | UpdateRate::ControlInterpolate => {
+            build_control_rate_layout(cache.context(), cache.target(), node, parent_groups, layout)
+        }
+    };
+    let sanitized_sockets = get_sanitized_sockets(cache.target(), node, parent_groups);
+    if sanitized_sockets.is_empty() {
+        layout
+    } else {
+        build_sanitized_layout(cache.context(), cache.target(), &sanitized_sockets, layout)
+    }
+}
+
+fn build_node_data_layout(
+    cache: &ObjectCache,
+    node: &Node,
+    parent_groups: &[ValueGroup],
 ) -> NodeLayout {
     let context = cache.context();
 
@@ -269,8 +321,12 @@ pub fn build_node_layout(
                 .iter()
                 .map(|socket| {
                     let socket_group = node.sockets[*socket].group_id;
-                    values::remap_type(context, &parent_groups[socket_group].value_type)
-                        .ptr_type(AddressSpace::Generic)
+                    values::remap_storage_type(
+                        context,
+                        cache.target(),
+                        &parent_groups[socket_group].value_type,
+                    )
+                    .ptr_type(AddressSpace::Generic)
                 })
                 .collect();
             let source_type_refs: Vec<_> = source_socket_types
@@ -282,8 +338,12 @@ pub fn build_node_layout(
                 .iter()
                 .map(|socket| {
                     let socket_group = node.sockets[*socket].group_id;
-                    values::remap_type(context, &parent_groups[socket_group].value_type)
-                        .ptr_type(AddressSpace::Generic)
+                    values::remap_storage_type(
+                        context,
+                        cache.target(),
+                        &parent_groups[socket_group].value_type,
+                    )
+                    .ptr_type(AddressSpace::Generic)
                 })
                 .collect();
             let dest_type_refs: Vec<_> = dest_socket_types
@@ -324,6 +384,361 @@ pub fn build_node_layout(
                 pointer_sources,
             }
         }
+        NodeData::UnitDelay => {
+            // A unit delay only needs pointers to the group it reads from and the group holding
+            // the delayed value. Both live in the surface, so there's no data of its own.
+            let socket_types: Vec<_> = node
+                .sockets
+                .iter()
+                .map(|socket| {
+                    values::remap_storage_type(
+                        context,
+                        cache.target(),
+                        &parent_groups[socket.group_id].value_type,
+                    )
+                    .ptr_type(AddressSpace::Generic)
+                })
+                .collect();
+            let socket_type_refs: Vec<_> = socket_types
+                .iter()
+                .map(|ptr_type| ptr_type as &BasicType)
+                .collect();
+
+            NodeLayout {
+                initialized_const: context.const_struct(&[], false),
+                scratch_struct: context.struct_type(&[], false).into(),
+                shared_struct: context.struct_type(&[], false).into(),
+                pointer_struct: context.struct_type(&socket_type_refs, false),
+                pointer_sources: (0..node.sockets.len())
+                    .map(|socket| PointerSource::Socket(socket, vec![]))
+                    .collect(),
+            }
+        }
+    }
+}
+
+/// Wraps the layout of a node that only runs every few samples. The node's own data goes first in
+/// both the scratch and pointer structs, so pointers into it stay valid. After it:
+///
+///  - `scratch` has the current phase, and the target and start value of each interpolated socket.
+///    Interpolated sockets are redirected to write into their target value.
+///  - `pointers` has pointers to each of the above, followed by pointers to the value groups of the
+///    interpolated sockets.
+fn build_control_rate_layout(
+    context: &Context,
+    target: &TargetProperties,
+    node: &Node,
+    parent_groups: &[ValueGroup],
+    layout: NodeLayout,
+) -> NodeLayout {
+    let interpolated_sockets = node.get_interpolated_sockets(parent_groups);
+    let num_type = values::remap_storage_type(context, target, &VarType::Num);
+    let num_ptr_type = num_type.ptr_type(AddressSpace::Generic);
+
+    let value_type_refs: Vec<_> = interpolated_sockets
+        .iter()
+        .map(|_| &num_type as &BasicType)
+        .collect();
+    let values_struct = context.struct_type(&value_type_refs, false);
+    let group_type_refs: Vec<_> = interpolated_sockets
+        .iter()
+        .map(|_| &num_ptr_type as &BasicType)
+        .collect();
+    let groups_struct = context.struct_type(&group_type_refs, false);
+
+    let scratch_struct = context.struct_type(
+        &[
+            &layout.scratch_struct,
+            &context.i32_type(),
+            &values_struct,
+            &values_struct,
+        ],
+        false,
+    );
+    let rate_pointer_struct = context.struct_type(
+        &[
+            &context.i32_type().ptr_type(AddressSpace::Generic),
+            &values_struct.ptr_type(AddressSpace::Generic),
+            &values_struct.ptr_type(AddressSpace::Generic),
+            &groups_struct,
+        ],
+        false,
+    );
+    let pointer_struct =
+        context.struct_type(&[&layout.pointer_struct, &rate_pointer_struct], false);
+
+    let data_pointer_sources = map_pointer_sources(
+        layout.pointer_sources,
+        PointerSource::Initialized,
+        |mut indices| {
+            indices.insert(0, 0);
+            PointerSource::Scratch(indices)
+        },
+        PointerSource::Shared,
+        |socket_index, mut sub_indices| match interpolated_sockets
+            .iter()
+            .position(|&socket| socket == socket_index)
+        {
+            Some(target_index) => {
+                sub_indices.insert(0, 2);
+                sub_indices.insert(1, target_index);
+                PointerSource::Scratch(sub_indices)
+            }
+            None => PointerSource::Socket(socket_index, sub_indices),
+        },
+    );
+    let rate_pointer_sources = vec![
+        PointerSource::Scratch(vec![1]),
+        PointerSource::Scratch(vec![2]),
+        PointerSource::Scratch(vec![3]),
+        PointerSource::Aggregate(
+            PointerSourceAggregateType::Struct,
+            interpolated_sockets
+                .iter()
+                .map(|&socket| PointerSource::Socket(socket, vec![]))
+                .collect(),
+        ),
+    ];
+
+    NodeLayout {
+        initialized_const: layout.initialized_const,
+        scratch_struct: scratch_struct.into(),
+        shared_struct: layout.shared_struct,
+        pointer_struct,
+        pointer_sources: vec![
+            PointerSource::Aggregate(PointerSourceAggregateType::Struct, data_pointer_sources),
+            PointerSource::Aggregate(PointerSourceAggregateType::Struct, rate_pointer_sources),
+        ],
+    }
+}
+
+/// Finds the number sockets a node writes to, which are checked for non-finite values after each
+/// update when the target sanitizes outputs. Only custom nodes are sanitized, since the outputs of
+/// groups are written by the nodes inside them.
+pub fn get_sanitized_sockets(
+    target: &TargetProperties,
+    node: &Node,
+    parent_groups: &[ValueGroup],
+) -> Vec<usize> {
+    match node.data {
+        NodeData::Custom { .. } if target.sanitize_outputs => {}
+        _ => return Vec::new(),
+    }
+
+    node.sockets
+        .iter()
+        .enumerate()
+        .filter(|(_, socket)| {
+            socket.value_written && parent_groups[socket.group_id].value_type == VarType::Num
+        })
+        .map(|(socket_index, _)| socket_index)
+        .collect()
+}
+
+/// Wraps the layout of a node whose outputs are sanitized. The node's own data goes first in both
+/// the scratch and pointer structs, so pointers into it stay valid. After it:
+///
+///  - `scratch` has the generation of the fault table the node last recorded a fault in.
+///  - `pointers` has a pointer to the above, followed by pointers to the value groups of the
+///    sanitized sockets.
+fn build_sanitized_layout(
+    context: &Context,
+    target: &TargetProperties,
+    sanitized_sockets: &[usize],
+    layout: NodeLayout,
+) -> NodeLayout {
+    let num_ptr_type =
+        values::remap_storage_type(context, target, &VarType::Num).ptr_type(AddressSpace::Generic);
+    let group_type_refs: Vec<_> = sanitized_sockets
+        .iter()
+        .map(|_| &num_ptr_type as &BasicType)
+        .collect();
+    let groups_struct = context.struct_type(&group_type_refs, false);
+
+    let scratch_struct = context.struct_type(&[&layout.scratch_struct, &context.i32_type()], false);
+    let sanitize_pointer_struct = context.struct_type(
+        &[
+            &context.i32_type().ptr_type(AddressSpace::Generic),
+            &groups_struct,
+        ],
+        false,
+    );
+    let pointer_struct =
+        context.struct_type(&[&layout.pointer_struct, &sanitize_pointer_struct], false);
+
+    let data_pointer_sources = map_pointer_sources(
+        layout.pointer_sources,
+        PointerSource::Initialized,
+        |mut indices| {
+            indices.insert(0, 0);
+            PointerSource::Scratch(indices)
+        },
+        PointerSource::Shared,
+        PointerSource::Socket,
+    );
+    let sanitize_pointer_sources = vec![
+        PointerSource::Scratch(vec![1]),
+        PointerSource::Aggregate(
+            PointerSourceAggregateType::Struct,
+            sanitized_sockets
+                .iter()
+                .map(|&socket| PointerSource::Socket(socket, vec![]))
+                .collect(),
+        ),
+    ];
+
+    NodeLayout {
+        initialized_const: layout.initialized_const,
+        scratch_struct: scratch_struct.into(),
+        shared_struct: layout.shared_struct,
+        pointer_struct,
+        pointer_sources: vec![
+            PointerSource::Aggregate(PointerSourceAggregateType::Struct, data_pointer_sources),
+            PointerSource::Aggregate(PointerSourceAggregateType::Struct, sanitize_pointer_sources),
+        ],
+    }
+}
+
+/// Wraps the layout of a node that runs several times per sample. The node's own data goes first in
+/// both the scratch and pointer structs, so pointers into it stay valid. After it:
+///
+///  - `scratch` has the value each input had on the last sample, the values passed to the node for
+///    each input, MIDI input and output, a buffer of each output's value over the oversampled
+///    steps, and the decimation filter state of each output. Converted sockets are redirected to
+///    the values passed to the node.
+///  - `pointers` has pointers to each of the above, followed by pointers to the value groups of the
+///    inputs, MIDI inputs and outputs.
+fn build_oversampled_layout(
+    context: &Context,
+    target: &TargetProperties,
+    node: &Node,
+    parent_groups: &[ValueGroup],
+    layout: NodeLayout,
+) -> NodeLayout {
+    let sockets = node.get_oversampled_sockets(parent_groups);
+    let num_type = values::remap_storage_type(context, target, &VarType::Num);
+    let midi_type = values::remap_type(context, &VarType::Midi);
+    let samples_type = context
+        .f64_type()
+        .vec_type(2)
+        .array_type(node.oversample_factor.multiplier());
+    let stage_type = half_band::get_stage_type(context);
+    let stage_type_refs: Vec<_> = (0..node.oversample_factor.stage_count())
+        .map(|_| &stage_type as &BasicType)
+        .collect();
+    let filter_type = context.struct_type(&stage_type_refs, false);
+
+    let build_repeated_struct = |count: usize, item_type: &BasicType| {
+        let type_refs: Vec<_> = (0..count).map(|_| item_type).collect();
+        context.struct_type(&type_refs, false)
+    };
+    let inputs_struct = build_repeated_struct(sockets.inputs.len(), &num_type);
+    let midi_inputs_struct = build_repeated_struct(sockets.midi_inputs.len(), &midi_type);
+    let outputs_struct = build_repeated_struct(sockets.outputs.len(), &num_type);
+    let samples_struct = build_repeated_struct(sockets.outputs.len(), &samples_type);
+    let filters_struct = build_repeated_struct(sockets.outputs.len(), &filter_type);
+    let input_groups_struct = build_repeated_struct(
+        sockets.inputs.len(),
+        &num_type.ptr_type(AddressSpace::Generic),
+    );
+    let midi_input_groups_struct = build_repeated_struct(
+        sockets.midi_inputs.len(),
+        &midi_type.ptr_type(AddressSpace::Generic),
+    );
+    let output_groups_struct = build_repeated_struct(
+        sockets.outputs.len(),
+        &num_type.ptr_type(AddressSpace::Generic),
+    );
+
+    let scratch_struct = context.struct_type(
+        &[
+            &layout.scratch_struct,
+            &inputs_struct,
+            &inputs_struct,
+            &midi_inputs_struct,
+            &outputs_struct,
+            &samples_struct,
+            &filters_struct,
+        ],
+        false,
+    );
+    let oversample_pointer_struct = context.struct_type(
+        &[
+            &inputs_struct.ptr_type(AddressSpace::Generic),
+            &inputs_struct.ptr_type(AddressSpace::Generic),
+            &midi_inputs_struct.ptr_type(AddressSpace::Generic),
+            &outputs_struct.ptr_type(AddressSpace::Generic),
+            &samples_struct.ptr_type(AddressSpace::Generic),
+            &filters_struct.ptr_type(AddressSpace::Generic),
+            &input_groups_struct,
+            &midi_input_groups_struct,
+            &output_groups_struct,
+        ],
+        false,
+    );
+    let pointer_struct =
+        context.struct_type(&[&layout.pointer_struct, &oversample_pointer_struct], false);
+
+    let redirected_sockets = [
+        (&sockets.inputs, 2),
+        (&sockets.midi_inputs, 3),
+        (&sockets.outputs, 4),
+    ];
+    let data_pointer_sources = map_pointer_sources(
+        layout.pointer_sources,
+        PointerSource::Initialized,
+        |mut indices| {
+            indices.insert(0, 0);
+            PointerSource::Scratch(indices)
+        },
+        PointerSource::Shared,
+        |socket_index, mut sub_indices| {
+            for &(socket_list, scratch_index) in &redirected_sockets {
+                if let Some(value_index) = socket_list
+                    .iter()
+                    .position(|&socket| socket == socket_index)
+                {
+                    sub_indices.insert(0, scratch_index);
+                    sub_indices.insert(1, value_index);
+                    return PointerSource::Scratch(sub_indices);
+                }
+            }
+            PointerSource::Socket(socket_index, sub_indices)
+        },
+    );
+    let build_group_sources = |socket_list: &[usize]| {
+        PointerSource::Aggregate(
+            PointerSourceAggregateType::Struct,
+            socket_list
+                .iter()
+                .map(|&socket| PointerSource::Socket(socket, vec![]))
+                .collect(),
+        )
+    };
+    let oversample_pointer_sources = vec![
+        PointerSource::Scratch(vec![1]),
+        PointerSource::Scratch(vec![2]),
+        PointerSource::Scratch(vec![3]),
+        PointerSource::Scratch(vec![4]),
+        PointerSource::Scratch(vec![5]),
+        PointerSource::Scratch(vec![6]),
+        build_group_sources(&sockets.inputs),
+        build_group_sources(&sockets.midi_inputs),
+        build_group_sources(&sockets.outputs),
+    ];
+
+    NodeLayout {
+        initialized_const: layout.initialized_const,
+        scratch_struct: scratch_struct.into(),
+        shared_struct: layout.shared_struct,
+        pointer_struct,
+        pointer_sources: vec![
+            PointerSource::Aggregate(PointerSourceAggregateType::Struct, data_pointer_sources),
+            PointerSource::Aggregate(
+                PointerSourceAggregateType::Struct,
+                oversample_pointer_sources,
+            ),
+        ],
     }
 }
 
@@ -425,7 +840,7 @@ pub fn build_block_layout(
                 context
                     .struct_type(
                         &[
-                            &controls::get_group_type(context, control.control_type)
+                            &controls::get_group_type(context, target, control.control_type)
                                 .ptr_type(AddressSpace::Generic),
                             &data_type.ptr_type(AddressSpace::Generic),
                             &shared_type.ptr_type(AddressSpace::Generic),
@@ -448,7 +863,7 @@ pub fn build_block_layout(
                 context
                     .struct_type(
                         &[
-                            &controls::get_group_type(context, control.control_type)
+                            &controls::get_group_type(context, target, control.control_type)
                                 .ptr_type(AddressSpace::Generic),
                             &data_type.ptr_type(AddressSpace::Generic),
                             &shared_type.ptr_type(AddressSpace::Generic),
@@ -509,7 +924,7 @@ pub fn build_surface_layout(cache: &ObjectCache, surface: &Surface) -> SurfaceLa
         .groups
         .iter()
         .map(|group| {
-            let value_type = values::remap_type(context, &group.value_type);
+            let value_type = values::remap_storage_type(context, cache.target(), &group.value_type);
             match group.source {
                 ValueGroupSource::None => {
                     let scratch_index = scratch_types.len();
@@ -522,7 +937,11 @@ pub fn build_surface_layout(cache: &ObjectCache, surface: &Surface) -> SurfaceLa
                 }
                 ValueGroupSource::Default(ref default_val) => {
                     let initialized_index = initialized_values.len();
-                    initialized_values.push(values::remap_constant(context, default_val));
+                    initialized_values.push(values::remap_storage_constant(
+                        context,
+                        cache.target(),
+                        default_val,
+                    ));
 
                     PointerSource::Initialized(vec![initialized_index])
                 }
@@ -574,6 +993,26 @@ pub fn build_surface_layout(cache: &ObjectCache, surface: &Surface) -> SurfaceLa
         pointer_sources.push(new_pointer_source);
     }
 
+    // each surface keeps the profile records of its own nodes, so extracted surfaces have a set
+    // for each voice
+    let profile_ptr_index = if cache.target().profile_nodes {
+        let profile_struct =
+            get_profile_record_type(context).array_type(surface.nodes.len() as u32);
+        let scratch_index = scratch_types.len();
+        scratch_types.push(profile_struct.into());
+
+        let ptr_index = pointer_types.len();
+        pointer_types
+            .push(context.struct_type(&[&profile_struct.ptr_type(AddressSpace::Generic)], false));
+        pointer_sources.push(PointerSource::Aggregate(
+            PointerSourceAggregateType::Struct,
+            vec![PointerSource::Scratch(vec![scratch_index])],
+        ));
+        Some(ptr_index)
+    } else {
+        None
+    };
+
     let initialized_val_refs: Vec<_> = initialized_values
         .iter()
         .map(|x| x as &BasicValue)
@@ -591,9 +1030,144 @@ pub fn build_surface_layout(cache: &ObjectCache, surface: &Surface) -> SurfaceLa
         pointer_sources,
         node_scratch_offset,
         node_initializer_offset,
+        profile_ptr_index,
+    }
+}
+
+/// Finds where the value group of an exported parameter is stored in the initialized data of a
+/// surface, looking through the surfaces of its group nodes too. Extracted surfaces share their
+/// initialized data between voices, so there's only ever one place.
+pub fn find_param_initialized_path(
+    cache: &ObjectCache,
+    surface: SurfaceRef,
+    param: usize,
+) -> Option<Vec<usize>> {
+    let surface_mir = cache.surface_mir(surface)?;
+    let layout = cache.surface_layout(surface)?;
+
+    // only groups with a default value are in the initialized data, in the order of the groups
+    let default_groups = surface_mir
+        .groups
+        .iter()
+        .filter(|group| match group.source {
+            ValueGroupSource::Default(_) => true,
+            _ => false,
+        });
+    for (initialized_index, group) in default_groups.enumerate() {
+        if group.param == Some(param) && group.value_type == VarType::Num {
+            return Some(vec![initialized_index]);
+        }
+    }
+
+    for (node_index, node) in surface_mir.nodes.iter().enumerate() {
+        let subsurface = match node.data {
+            NodeData::Group(subsurface) => subsurface,
+            NodeData::ExtractGroup { surface, .. } => surface,
+            _ => continue,
+        };
+        if let Some(mut path) = find_param_initialized_path(cache, subsurface, param) {
+            path.insert(0, layout.node_initialized_index(node_index));
+            return Some(path);
+        }
+    }
+
+    None
+}
+
+/// The number of bytes a surface claims from the arena when it's constructed at the given sample
+/// rate. Delay lines are the only thing that claims memory, with a buffer per channel that's big
+/// enough for their longest delay at the rate they run at. Surfaces that aren't in the maps don't
+/// claim anything.
+pub fn get_arena_size(
+    target: &TargetProperties,
+    sample_rate: f64,
+    surface: SurfaceRef,
+    surfaces: &HashMap<SurfaceRef, Surface>,
+    blocks: &HashMap<BlockRef, Block>,
+) -> u64 {
+    let surface_mir = match surfaces.get(&surface) {
+        Some(surface_mir) => surface_mir,
+        None => return 0,
+    };
+
+    surface_mir
+        .nodes
+        .iter()
+        .map(|node| {
+            let node_rate = sample_rate * f64::from(node.oversample_factor.multiplier());
+            match node.data {
+                NodeData::Custom { block, .. } => match blocks.get(&block) {
+                    Some(block) => block
+                        .statements
+                        .iter()
+                        .map(|statement| match statement {
+                            Statement::CallFunc {
+                                function: Function::Delay,
+                                args,
+                                ..
+                            } => get_delay_arena_size(
+                                target,
+                                get_delay_max_seconds(target.max_delay_seconds, block, args),
+                                node_rate,
+                            ),
+                            _ => 0,
+                        })
+                        .sum(),
+                    None => 0,
+                },
+                NodeData::Group(subsurface) => {
+                    get_arena_size(target, node_rate, subsurface, surfaces, blocks)
+                }
+                NodeData::ExtractGroup { surface, .. } => {
+                    u64::from(values::ARRAY_CAPACITY)
+                        * get_arena_size(target, node_rate, surface, surfaces, blocks)
+                }
+                NodeData::Dummy | NodeData::UnitDelay => 0,
+            }
+        })
+        .sum()
+}
+
+/// The longest delay in seconds a delay called with the given arguments can reach. The reserve is
+/// always the last argument, and if it's a constant the delay never needs more than it. Anything
+/// else can reach the longest delay the target allows.
+pub fn get_delay_max_seconds(max_delay_seconds: f64, block: &Block, args: &[usize]) -> f64 {
+    let reserve = args
+        .last()
+        .and_then(|&index| match block.statements.get(index) {
+            Some(Statement::Constant(ConstantValue::Num(num))) => Some(num.left.max(num.right)),
+            _ => None,
+        });
+    match reserve {
+        Some(reserve) => reserve.min(max_delay_seconds).max(0.),
+        None => max_delay_seconds,
     }
 }
 
+// matches the claims made by the delay function's construct, which rounds each buffer up to a
+// power of two samples and each claim up to 16 bytes
+fn get_delay_arena_size(target: &TargetProperties, max_seconds: f64, sample_rate: f64) -> u64 {
+    let sample_size = match target.storage_precision {
+        StoragePrecision::Double => 8,
+        StoragePrecision::Single => 4,
+    };
+    let capacity = u64::from(((max_seconds * sample_rate) as u32).next_power_of_two());
+    let channel_size = (capacity * sample_size + 15) & !15;
+    channel_size * 2
+}
+
+/// Builds a fingerprint of the layout of the given state types. State is copied byte-for-byte, so
+/// it can only be moved between runtimes whose fingerprints match.
+pub fn get_layout_fingerprint(target: &TargetProperties, state_types: &[StructType]) -> u64 {
+    let mut hasher = StableHasher::new();
+    let target_data = target.machine.get_data();
+    for state_type in state_types {
+        hasher.write(state_type.print_to_string().to_bytes());
+        hasher.write(&target_data.get_abi_size(state_type).to_le_bytes());
+    }
+    hasher.finish()
+}
+
 fn modify_pointer_source(
     source: PointerSource,
     initialized_modifier: &Fn(Vec<usize>) -> PointerSource,
@@ -701,7 +1275,17 @@ impl SurfaceLayout {
         self.node_scratch_offset + node
     }
 
+    pub fn node_initialized_index(&self, node: usize) -> usize {
+        self.node_initializer_offset + node
+    }
+
     pub fn node_ptr_index(&self, node: usize) -> usize {
         node
     }
+
+    /// The index of the struct holding a pointer to the profile records of each node, if the
+    /// target profiles nodes.
+    pub fn profile_ptr_index(&self) -> Option<usize> {
+        self.profile_ptr_index
+    }
 }
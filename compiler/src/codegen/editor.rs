@@ -65,6 +65,7 @@ pub fn build_convert_num_func(module: &Module, target: &TargetProperties, name:
             let result_num = converters::build_convert_direct(
                 &mut case_builder,
                 ctx.module,
+                ctx.target,
                 &input_num,
                 form_type,
             );
@@ -35,29 +35,33 @@ fn get_channel_update_data_type(context: &Context) -> StructType {
     )
 }
 
-fn get_channel_update_func(module: &Module) -> FunctionValue {
+fn get_channel_update_func(module: &Module, target: &TargetProperties) -> FunctionValue {
     util::get_or_create_func(module, "maxim.util.adsr.channelUpdate", true, &|| {
         let context = &module.get_context();
         let result_type = context.struct_type(&[&context.bool_type(), &context.f64_type()], false);
         (
             Linkage::PrivateLinkage,
-            result_type.fn_type(
-                &[
-                    &get_channel_update_data_type(context).ptr_type(AddressSpace::Generic),
-                    &context.bool_type(), // current trigger
-                    &context.f64_type(),  // attack
-                    &context.f64_type(),  // decay
-                    &context.f64_type(),  // sustain
-                    &context.f64_type(),  // release
-                ],
-                false,
+            globals::add_instance_param(
+                module,
+                target,
+                result_type.fn_type(
+                    &[
+                        &get_channel_update_data_type(context).ptr_type(AddressSpace::Generic),
+                        &context.bool_type(), // current trigger
+                        &context.f64_type(),  // attack
+                        &context.f64_type(),  // decay
+                        &context.f64_type(),  // sustain
+                        &context.f64_type(),  // release
+                    ],
+                    false,
+                ),
             ),
         )
     })
 }
 
 fn build_channel_update_func(module: &Module, target: &TargetProperties) {
-    let func = get_channel_update_func(module);
+    let func = get_channel_update_func(module, target);
     build_context_function(module, func, target, &|ctx: BuilderContext| {
         let pow_intrinsic = math::pow_v2f64(module);
 
@@ -208,7 +212,7 @@ fn build_channel_update_func(module: &Module, target: &TargetProperties) {
                 .build_extract_element(
                     &ctx.b
                         .build_load(
-                            &globals::get_sample_rate(ctx.module).as_pointer_value(),
+                            &globals::build_sample_rate_ptr(ctx.module, ctx.b),
                             "samplerate",
                         )
                         .into_vector_value(),
@@ -475,7 +479,8 @@ impl Function for AdsrFunction {
         result: PointerValue,
     ) {
         build_channel_update_func(func.ctx.module, func.ctx.target);
-        let channel_update_func = get_channel_update_func(func.ctx.module);
+        let channel_update_func = get_channel_update_func(func.ctx.module, func.ctx.target);
+        let instance = globals::get_instance_param(func.ctx.module, func.ctx.b);
 
         let left_update_data =
             unsafe { func.ctx.b.build_struct_gep(&func.data_ptr, 0, "data.left") };
@@ -537,14 +542,17 @@ impl Function for AdsrFunction {
             .b
             .build_call(
                 &channel_update_func,
-                &[
-                    &left_update_data,
-                    &left_trigger,
-                    &left_attack,
-                    &left_decay,
-                    &left_sustain,
-                    &left_release,
-                ],
+                &globals::with_instance_arg(
+                    &[
+                        &left_update_data,
+                        &left_trigger,
+                        &left_attack,
+                        &left_decay,
+                        &left_sustain,
+                        &left_release,
+                    ],
+                    &instance,
+                ),
                 "",
                 true,
             )
@@ -584,14 +592,17 @@ impl Function for AdsrFunction {
             .b
             .build_call(
                 &channel_update_func,
-                &[
-                    &right_update_data,
-                    &right_trigger,
-                    &right_attack,
-                    &right_decay,
-                    &right_sustain,
-                    &right_release,
-                ],
+                &globals::with_instance_arg(
+                    &[
+                        &right_update_data,
+                        &right_trigger,
+                        &right_attack,
+                        &right_decay,
+                        &right_sustain,
+                        &right_release,
+                    ],
+                    &instance,
+                ),
                 "",
                 true,
             )
@@ -106,7 +106,10 @@ pub fn build_internal_biquad_func(module: &Module, target: &TargetProperties) {
         );
 
         // z1 = out
-        ctx.b.build_store(&z1_vec_ptr, &output_vec);
+        ctx.b.build_store(
+            &z1_vec_ptr,
+            &util::build_denormal_offset(ctx.b, ctx.target, output_vec, "z1.offset"),
+        );
 
         ctx.b.build_return(Some(&output_vec));
     });
@@ -307,7 +310,7 @@ fn gen_biquad_call(
         .ctx
         .b
         .build_load(
-            &globals::get_sample_rate(func.ctx.module).as_pointer_value(),
+            &globals::build_sample_rate_ptr(func.ctx.module, func.ctx.b),
             "samplerate",
         )
         .into_vector_value();
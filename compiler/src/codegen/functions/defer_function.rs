@@ -89,7 +89,7 @@ impl Function for AmplitudeFunction {
                             func.ctx
                                 .b
                                 .build_load(
-                                    &globals::get_sample_rate(func.ctx.module).as_pointer_value(),
+                                    &globals::build_sample_rate_ptr(func.ctx.module, func.ctx.b),
                                     "samplerate",
                                 )
                                 .into_vector_value(),
@@ -266,7 +266,10 @@ impl Function for AccumFunction {
             .b
             .build_select(gate_bool, incremented_vec, base_vec, "newaccum")
             .into_vector_value();
-        func.ctx.b.build_store(&accum_ptr, &new_accum);
+        func.ctx.b.build_store(
+            &accum_ptr,
+            &util::build_denormal_offset(func.ctx.b, func.ctx.target, new_accum, "newaccum.offset"),
+        );
 
         let x_form = x_num.get_form(func.ctx.b);
         result_num.set_form(func.ctx.b, x_form);
@@ -5,14 +5,44 @@ use crate::codegen::{
 };
 use crate::mir::block;
 use inkwell::attribute::AttrKind;
+use inkwell::builder::Builder;
 use inkwell::context::Context;
 use inkwell::module::{Linkage, Module};
 use inkwell::types::StructType;
-use inkwell::values::{FunctionValue, PointerValue};
+use inkwell::values::{FunctionValue, PointerValue, VectorValue};
 use inkwell::{AddressSpace, IntPredicate};
 
 pub struct DelayFunction {}
 impl DelayFunction {
+    /// The fields of the data holding the left and right buffers, each paired with the field
+    /// holding the buffer's capacity in samples.
+    pub const BUFFER_FIELDS: [(u32, u32); 2] = [(4, 6), (5, 7)];
+
+    /// The field holding the longest delay in seconds the buffers have to fit, which the block
+    /// stores before constructing the function.
+    pub const MAX_SECONDS_FIELD: u32 = 8;
+
+    /// Stores the longest delay the call can reach in its data, so the buffers it claims when it's
+    /// constructed are no bigger than they need to be.
+    pub fn build_store_max_seconds(
+        context: &Context,
+        builder: &mut Builder,
+        data_ptr: PointerValue,
+        max_seconds: f64,
+    ) {
+        let max_seconds_ptr = unsafe {
+            builder.build_struct_gep(
+                &data_ptr,
+                DelayFunction::MAX_SECONDS_FIELD,
+                "maxseconds.ptr",
+            )
+        };
+        builder.build_store(
+            &max_seconds_ptr,
+            &context.f64_type().const_float(max_seconds),
+        );
+    }
+
     fn get_channel_update_func(module: &Module) -> FunctionValue {
         let func =
             util::get_or_create_func(module, "maxim.util.delay.channelUpdate", true, &|| {
@@ -23,6 +53,7 @@ impl DelayFunction {
                         &[
                             &context.i32_type().ptr_type(AddressSpace::Generic), // current position pointer
                             &context.i32_type().ptr_type(AddressSpace::Generic), // current size pointer
+                            &context.i32_type().ptr_type(AddressSpace::Generic), // capacity pointer
                             &context.i32_type(), // delay sample count
                             &context.i32_type(), // reserve sample count
                             &context
@@ -38,17 +69,18 @@ impl DelayFunction {
         let context = module.get_context();
         func.add_param_attribute(0, context.get_enum_attr(AttrKind::NoAlias, 1));
         func.add_param_attribute(1, context.get_enum_attr(AttrKind::NoAlias, 1));
-        func.add_param_attribute(4, context.get_enum_attr(AttrKind::NoAlias, 1));
+        func.add_param_attribute(2, context.get_enum_attr(AttrKind::NoAlias, 1));
+        func.add_param_attribute(5, context.get_enum_attr(AttrKind::NoAlias, 1));
         func
     }
 
     /// Builds a function that is equivalent to the following C++:
     /// ```cpp
-    /// float channelUpdate(uint64_t *currentPos, uint64_t *currentSize, uint64_t delaySamples, uint64_t reserveSamples, float **buffer, float input) {
+    /// float channelUpdate(uint32_t *currentPos, uint32_t *currentSize, uint32_t *capacity, uint32_t delaySamples, uint32_t reserveSamples, float **buffer, float input) {
     ///     float resultVal;
     ///
     ///     if (*currentSize) {
-    ///         uint64_t loadedCurrentPos = *currentPos;
+    ///         uint32_t loadedCurrentPos = *currentPos;
     ///         *currentPos = (loadedCurrentPos + 1) % *currentSize;
     ///
     ///         if (delaySamples != 0) {
@@ -63,15 +95,12 @@ impl DelayFunction {
     ///         resultVal = input;
     ///     }
     ///
-    ///     auto bufferSize = calculateNextPowerOfTwo(reserveSamples);
+    ///     auto bufferSize = min(calculateNextPowerOfTwo(reserveSamples), *capacity);
     ///     if (bufferSize != *currentSize) {
-    ///         *buffer = realloc(*buffer, bufferSize * sizeof(float));
-    ///         if (bufferSize == 0) {
-    ///             *buffer = nullptr;
-    ///         } else if (bufferSize > *currentSize) {
+    ///         if (bufferSize > *currentSize) {
     ///             memset(*buffer + *currentSize, 0, (bufferSize - *currentSize) * sizeof(float));
     ///         } else {
-    ///             *currentPos = *currentPos % bufferSize;
+    ///             *currentPos = *currentPos % max(bufferSize, 1);
     ///         }
     ///         *currentSize = bufferSize;
     ///     }
@@ -79,20 +108,23 @@ impl DelayFunction {
     ///     return resultVal;
     /// }
     /// ```
+    ///
+    /// The buffer is claimed when the function is constructed, and is never resized here. Reserves
+    /// longer than it can hold are clamped to its capacity.
     fn build_channel_update_func(module: &Module, target: &TargetProperties) {
         let func = DelayFunction::get_channel_update_func(module);
         build_context_function(module, func, target, &|ctx: BuilderContext| {
             let target_data = target.machine.get_data();
             let next_power_intrinsic = intrinsics::next_power_i32(ctx.module);
-            let realloc_intrinsic = intrinsics::realloc(ctx.module, &target_data);
             let memset_intrinsic = intrinsics::memset(ctx.module, &target_data);
 
             let current_pos_ptr = ctx.func.get_nth_param(0).unwrap().into_pointer_value();
             let current_size_ptr = ctx.func.get_nth_param(1).unwrap().into_pointer_value();
-            let delay_samples = ctx.func.get_nth_param(2).unwrap().into_int_value();
-            let reserve_samples = ctx.func.get_nth_param(3).unwrap().into_int_value();
-            let buffer_ptr_ptr = ctx.func.get_nth_param(4).unwrap().into_pointer_value();
-            let input_num = ctx.func.get_nth_param(5).unwrap().into_float_value();
+            let capacity_ptr = ctx.func.get_nth_param(2).unwrap().into_pointer_value();
+            let delay_samples = ctx.func.get_nth_param(3).unwrap().into_int_value();
+            let reserve_samples = ctx.func.get_nth_param(4).unwrap().into_int_value();
+            let buffer_ptr_ptr = ctx.func.get_nth_param(5).unwrap().into_pointer_value();
+            let input_num = ctx.func.get_nth_param(6).unwrap().into_float_value();
 
             let has_buffer_true_block = ctx.context.append_basic_block(&ctx.func, "hasbuffer.true");
             let has_samples_true_block =
@@ -108,11 +140,9 @@ impl DelayFunction {
             let has_buffer_continue_block = ctx
                 .context
                 .append_basic_block(&ctx.func, "hasbuffer.continue");
-            let needs_realloc_true_block = ctx
+            let needs_resize_true_block = ctx
                 .context
-                .append_basic_block(&ctx.func, "needsrealloc.true");
-            let size_zero_true_block = ctx.context.append_basic_block(&ctx.func, "sizezero.true");
-            let size_zero_false_block = ctx.context.append_basic_block(&ctx.func, "sizezero.false");
+                .append_basic_block(&ctx.func, "needsresize.true");
             let size_increase_true_block = ctx
                 .context
                 .append_basic_block(&ctx.func, "sizeincrease.true");
@@ -122,20 +152,22 @@ impl DelayFunction {
             let size_increase_continue_block = ctx
                 .context
                 .append_basic_block(&ctx.func, "sizeincrease.continue");
-            let size_zero_continue_block = ctx
-                .context
-                .append_basic_block(&ctx.func, "sizezero.continue");
-            let needs_realloc_continue_block = ctx
+            let needs_resize_continue_block = ctx
                 .context
-                .append_basic_block(&ctx.func, "needsrealloc.continue");
+                .append_basic_block(&ctx.func, "needsresize.continue");
 
             let result_ptr = ctx
                 .allocb
                 .build_alloca(&ctx.context.f64_type(), "resultval");
-            let buffer_ptr = ctx
-                .b
-                .build_load(&buffer_ptr_ptr, "bufferptr")
-                .into_pointer_value();
+            // samples are kept at the target's storage precision, and converted on read and write
+            let sample_type = target.storage_precision.float_type(ctx.context);
+            let buffer_ptr = ctx.b.build_pointer_cast(
+                ctx.b
+                    .build_load(&buffer_ptr_ptr, "bufferptr")
+                    .into_pointer_value(),
+                sample_type.ptr_type(AddressSpace::Generic),
+                "samplesptr",
+            );
 
             // if (*currentSize) {
             let current_size = ctx
@@ -156,7 +188,7 @@ impl DelayFunction {
 
             ctx.b.position_at_end(&has_buffer_true_block);
 
-            // uint64_t loadedCurrentPos = *currentPos;
+            // uint32_t loadedCurrentPos = *currentPos;
             let current_pos = ctx
                 .b
                 .build_load(&current_pos_ptr, "currentpos")
@@ -211,6 +243,9 @@ impl DelayFunction {
                     "result",
                 )
                 .into_float_value();
+            let result_val = ctx
+                .b
+                .build_float_cast(result_val, ctx.context.f64_type(), "");
             ctx.b.build_store(&result_ptr, &result_val);
             ctx.b
                 .build_unconditional_branch(&has_samples_continue_block);
@@ -232,7 +267,7 @@ impl DelayFunction {
                     ctx.b
                         .build_in_bounds_gep(&buffer_ptr, &[current_pos], "write.ptr")
                 },
-                &input_num,
+                &ctx.b.build_float_cast(input_num, sample_type, ""),
             );
             ctx.b.build_unconditional_branch(&has_buffer_continue_block);
 
@@ -244,90 +279,50 @@ impl DelayFunction {
 
             ctx.b.position_at_end(&has_buffer_continue_block);
 
-            // auto bufferSize = calculateNextPowerOfTwo(reserveSamples);
-            let new_buffer_size = ctx
+            // auto bufferSize = min(calculateNextPowerOfTwo(reserveSamples), *capacity);
+            let reserve_size = ctx
                 .b
                 .build_call(
                     &next_power_intrinsic,
                     &[&reserve_samples],
-                    "newbuffersize",
+                    "reservesize",
                     true,
                 )
                 .left()
                 .unwrap()
                 .into_int_value();
-
-            // if (bufferSize != *currentSize) {
-            let needs_realloc = ctx.b.build_int_compare(
-                IntPredicate::NE,
-                new_buffer_size,
-                current_size,
-                "needsrealloc",
-            );
-            ctx.b.build_conditional_branch(
-                &needs_realloc,
-                &needs_realloc_true_block,
-                &needs_realloc_continue_block,
-            );
-
-            ctx.b.position_at_end(&needs_realloc_true_block);
-
-            // *buffer = realloc(*buffer, bufferSize * sizeof(float));
-            let size_type = target_data.int_ptr_type_in_context(ctx.context);
-            let float_size = ctx
-                .context
-                .f64_type()
-                .size_of()
-                .const_cast(&size_type, false);
-            let realloc_size = ctx.b.build_int_mul(
-                ctx.b.build_int_cast(new_buffer_size, size_type, ""),
-                float_size,
-                "",
-            );
-            let realloc_ptr = ctx
+            let capacity = ctx.b.build_load(&capacity_ptr, "capacity").into_int_value();
+            let buffer_size = ctx
                 .b
-                .build_call(
-                    &realloc_intrinsic,
-                    &[
-                        &ctx.b.build_pointer_cast(
-                            buffer_ptr,
-                            ctx.context.i8_type().ptr_type(AddressSpace::Generic),
-                            "",
-                        ),
-                        &realloc_size,
-                    ],
-                    "",
-                    false,
+                .build_select(
+                    ctx.b.build_int_compare(
+                        IntPredicate::ULT,
+                        reserve_size,
+                        capacity,
+                        "fitscapacity",
+                    ),
+                    reserve_size,
+                    capacity,
+                    "buffersize",
                 )
-                .left()
-                .unwrap()
-                .into_pointer_value();
+                .into_int_value();
 
-            // if (bufferSize == 0) *buffer = nullptr;
-            let size_is_zero = ctx.b.build_int_compare(
-                IntPredicate::EQ,
-                realloc_size,
-                size_type.const_int(0, false),
-                "sizezero",
-            );
+            // if (bufferSize != *currentSize) {
+            let needs_resize =
+                ctx.b
+                    .build_int_compare(IntPredicate::NE, buffer_size, current_size, "needsresize");
             ctx.b.build_conditional_branch(
-                &size_is_zero,
-                &size_zero_true_block,
-                &size_zero_false_block,
+                &needs_resize,
+                &needs_resize_true_block,
+                &needs_resize_continue_block,
             );
 
-            let buffer_ptr_type = ctx.context.f64_type().ptr_type(AddressSpace::Generic);
+            ctx.b.position_at_end(&needs_resize_true_block);
 
-            ctx.b.position_at_end(&size_zero_true_block);
-            ctx.b
-                .build_store(&buffer_ptr_ptr, &buffer_ptr_type.const_null());
-            ctx.b.build_unconditional_branch(&size_zero_continue_block);
-
-            // else if (bufferSize > *currentSize) memset(*buffer + *currentSize, 0, (bufferSize - *currentSize) * sizeof(float));
-            ctx.b.position_at_end(&size_zero_false_block);
+            // if (bufferSize > *currentSize) memset(*buffer + *currentSize, 0, (bufferSize - *currentSize) * sizeof(float));
             let size_increase = ctx.b.build_int_compare(
                 IntPredicate::UGT,
-                new_buffer_size,
+                buffer_size,
                 current_size,
                 "sizeincrease",
             );
@@ -338,20 +333,33 @@ impl DelayFunction {
             );
 
             ctx.b.position_at_end(&size_increase_true_block);
+            let size_type = target_data.int_ptr_type_in_context(ctx.context);
+            let float_size = sample_type.size_of().const_cast(&size_type, false);
+            let samples_ptr = ctx.b.build_pointer_cast(
+                buffer_ptr,
+                ctx.context.i8_type().ptr_type(AddressSpace::Generic),
+                "samplesptr",
+            );
             let current_size_bytes = ctx.b.build_int_mul(
                 ctx.b.build_int_cast(current_size, size_type, ""),
                 float_size,
                 "currentsizebytes",
             );
+            let buffer_size_bytes = ctx.b.build_int_mul(
+                ctx.b.build_int_cast(buffer_size, size_type, ""),
+                float_size,
+                "buffersizebytes",
+            );
             ctx.b.build_call(
                 &memset_intrinsic,
                 &[
                     &unsafe {
                         ctx.b
-                            .build_in_bounds_gep(&realloc_ptr, &[current_size_bytes], "offsetptr")
+                            .build_in_bounds_gep(&samples_ptr, &[current_size_bytes], "offsetptr")
                     },
                     &ctx.context.i8_type().const_int(0, false),
-                    &ctx.b.build_int_sub(realloc_size, current_size_bytes, ""),
+                    &ctx.b
+                        .build_int_sub(buffer_size_bytes, current_size_bytes, ""),
                     &ctx.context.i32_type().const_int(0, false),
                     &ctx.context.bool_type().const_int(0, false),
                 ],
@@ -363,12 +371,27 @@ impl DelayFunction {
 
             // else {
             ctx.b.position_at_end(&size_decrease_true_block);
-            // *currentPos = *currentPos % *currentSize;
+            // *currentPos = *currentPos % max(bufferSize, 1);
+            let is_empty = ctx.b.build_int_compare(
+                IntPredicate::EQ,
+                buffer_size,
+                ctx.context.i32_type().const_int(0, false),
+                "isempty",
+            );
+            let pos_divisor = ctx
+                .b
+                .build_select(
+                    is_empty,
+                    ctx.context.i32_type().const_int(1, false),
+                    buffer_size,
+                    "posdivisor",
+                )
+                .into_int_value();
             ctx.b.build_store(
                 &current_pos_ptr,
                 &ctx.b.build_int_unsigned_rem(
                     ctx.b.build_load(&current_pos_ptr, "").into_int_value(),
-                    new_buffer_size,
+                    pos_divisor,
                     "",
                 ),
             );
@@ -377,24 +400,76 @@ impl DelayFunction {
                 .build_unconditional_branch(&size_increase_continue_block);
 
             ctx.b.position_at_end(&size_increase_continue_block);
-            ctx.b.build_store(
-                &buffer_ptr_ptr,
-                &ctx.b
-                    .build_pointer_cast(realloc_ptr, buffer_ptr_type, "newbufferptr"),
-            );
-            ctx.b.build_unconditional_branch(&size_zero_continue_block);
-
-            ctx.b.position_at_end(&size_zero_continue_block);
 
             // *currentSize = bufferSize;
-            ctx.b.build_store(&current_size_ptr, &new_buffer_size);
+            ctx.b.build_store(&current_size_ptr, &buffer_size);
             ctx.b
-                .build_unconditional_branch(&needs_realloc_continue_block);
+                .build_unconditional_branch(&needs_resize_continue_block);
 
-            ctx.b.position_at_end(&needs_realloc_continue_block);
+            ctx.b.position_at_end(&needs_resize_continue_block);
             ctx.b.build_return(Some(&ctx.b.build_load(&result_ptr, "")));
         });
     }
+
+    /// The number of samples a channel's buffer holds, enough for the longest delay the call can
+    /// reach at the current sample rate. Always a power of two, so positions can wrap around it.
+    fn build_capacity(func: &mut FunctionContext) -> VectorValue {
+        let sample_rate = func
+            .ctx
+            .b
+            .build_load(
+                &globals::build_construct_sample_rate_ptr(func.ctx.module, func.ctx.b),
+                "samplerate",
+            )
+            .into_vector_value();
+        let max_seconds_ptr = unsafe {
+            func.ctx.b.build_struct_gep(
+                &func.data_ptr,
+                DelayFunction::MAX_SECONDS_FIELD,
+                "maxseconds.ptr",
+            )
+        };
+        let max_seconds = func
+            .ctx
+            .b
+            .build_load(&max_seconds_ptr, "maxseconds")
+            .into_float_value();
+        let max_samples = func.ctx.b.build_float_to_unsigned_int(
+            func.ctx.b.build_float_mul(
+                sample_rate,
+                util::splat_vector(func.ctx.b, max_seconds, "maxseconds.vec"),
+                "maxsamples.float",
+            ),
+            func.ctx.context.i32_type().vec_type(2),
+            "maxsamples",
+        );
+
+        let next_power_intrinsic = intrinsics::next_power_i32(func.ctx.module);
+        let mut capacity = func.ctx.context.i32_type().vec_type(2).get_undef();
+        for channel in 0..2 {
+            let channel_index = func.ctx.context.i32_type().const_int(channel, false);
+            let channel_capacity = func
+                .ctx
+                .b
+                .build_call(
+                    &next_power_intrinsic,
+                    &[&func
+                        .ctx
+                        .b
+                        .build_extract_element(&max_samples, &channel_index, "")],
+                    "capacity",
+                    true,
+                )
+                .left()
+                .unwrap();
+            capacity = func
+                .ctx
+                .b
+                .build_insert_element(&capacity, &channel_capacity, &channel_index, "")
+                .into_vector_value();
+        }
+        capacity
+    }
 }
 
 impl Function for DelayFunction {
@@ -414,11 +489,113 @@ impl Function for DelayFunction {
                 &size_type,                                    // right buffer length
                 &channel_type.ptr_type(AddressSpace::Generic), // left buffer
                 &channel_type.ptr_type(AddressSpace::Generic), // right buffer
+                &size_type,                                    // left buffer capacity
+                &size_type,                                    // right buffer capacity
+                &context.f64_type(),                           // longest delay in seconds
             ],
             false,
         )
     }
 
+    fn gen_construct(func: &mut FunctionContext) {
+        // Buffers are claimed once, big enough for the longest delay the call can reach, so
+        // changing the reserve never allocates. If the arena can't fit a buffer the channel is
+        // left without one, and passes its input through.
+        let arena_claim_intrinsic = intrinsics::arena_claim(func.ctx.module, func.ctx.target);
+        let instance = globals::get_instance_param(func.ctx.module, func.ctx.b);
+        let capacity = DelayFunction::build_capacity(func);
+        let sample_size = func
+            .ctx
+            .target
+            .storage_precision
+            .float_type(func.ctx.context)
+            .size_of();
+        let size_type = func
+            .ctx
+            .target
+            .machine
+            .get_data()
+            .int_ptr_type_in_context(func.ctx.context);
+        let const_zero = func.ctx.context.i32_type().const_int(0, false);
+
+        for (channel, &(buffer_field, capacity_field)) in
+            DelayFunction::BUFFER_FIELDS.iter().enumerate()
+        {
+            let channel_capacity = func
+                .ctx
+                .b
+                .build_extract_element(
+                    &capacity,
+                    &func.ctx.context.i32_type().const_int(channel as u64, false),
+                    "capacity",
+                )
+                .into_int_value();
+            let claim_size = func.ctx.b.build_int_mul(
+                func.ctx
+                    .b
+                    .build_int_cast(channel_capacity, func.ctx.context.i64_type(), ""),
+                func.ctx
+                    .b
+                    .build_int_cast(sample_size, func.ctx.context.i64_type(), ""),
+                "claimsize",
+            );
+            let claimed_ptr = func
+                .ctx
+                .b
+                .build_call(
+                    &arena_claim_intrinsic,
+                    &globals::with_instance_arg(&[&claim_size], &instance),
+                    "claimed",
+                    true,
+                )
+                .left()
+                .unwrap()
+                .into_pointer_value();
+            let is_claimed = func.ctx.b.build_int_compare(
+                IntPredicate::NE,
+                func.ctx.b.build_ptr_to_int(claimed_ptr, size_type, ""),
+                size_type.const_int(0, false),
+                "isclaimed",
+            );
+
+            let buffer_ptr_ptr = unsafe {
+                func.ctx
+                    .b
+                    .build_struct_gep(&func.data_ptr, buffer_field, "buffer.ptr")
+            };
+            func.ctx.b.build_store(
+                &buffer_ptr_ptr,
+                &func.ctx.b.build_pointer_cast(
+                    claimed_ptr,
+                    func.ctx.context.f64_type().ptr_type(AddressSpace::Generic),
+                    "bufferptr",
+                ),
+            );
+            let capacity_ptr = unsafe {
+                func.ctx
+                    .b
+                    .build_struct_gep(&func.data_ptr, capacity_field, "capacity.ptr")
+            };
+            func.ctx.b.build_store(
+                &capacity_ptr,
+                &func
+                    .ctx
+                    .b
+                    .build_select(is_claimed, channel_capacity, const_zero, "capacity"),
+            );
+
+            // the position and length fields come first, in channel order
+            for &field_index in &[channel as u32, channel as u32 + 2] {
+                let field_ptr = unsafe {
+                    func.ctx
+                        .b
+                        .build_struct_gep(&func.data_ptr, field_index, "field.ptr")
+                };
+                func.ctx.b.build_store(&field_ptr, &const_zero);
+            }
+        }
+    }
+
     fn gen_real_args(ctx: &mut BuilderContext, mut args: Vec<PointerValue>) -> Vec<PointerValue> {
         if args.len() < 3 {
             let mut delay_constant = NumValue::new_undef(ctx.context, ctx.allocb);
@@ -470,6 +647,16 @@ impl Function for DelayFunction {
                 .b
                 .build_struct_gep(&func.data_ptr, 5, "rightbuffer.ptr")
         };
+        let left_capacity_ptr = unsafe {
+            func.ctx
+                .b
+                .build_struct_gep(&func.data_ptr, 6, "leftcapacity.ptr")
+        };
+        let right_capacity_ptr = unsafe {
+            func.ctx
+                .b
+                .build_struct_gep(&func.data_ptr, 7, "rightcapacity.ptr")
+        };
 
         let input_num = NumValue::new(args[0]);
         let delay_num = NumValue::new(args[1]);
@@ -480,13 +667,27 @@ impl Function for DelayFunction {
             .ctx
             .b
             .build_load(
-                &globals::get_sample_rate(func.ctx.module).as_pointer_value(),
+                &globals::build_sample_rate_ptr(func.ctx.module, func.ctx.b),
                 "samplerate",
             )
             .into_vector_value();
 
-        // determine reserve samples
-        let reserve_vec = reserve_num.get_vec(func.ctx.b);
+        // determine reserve samples, bounded by the longest delay the target allows
+        let reserve_vec = func
+            .ctx
+            .b
+            .build_call(
+                &min_intrinsic,
+                &[
+                    &reserve_num.get_vec(func.ctx.b),
+                    &util::get_vec_spread(func.ctx.context, func.ctx.target.max_delay_seconds),
+                ],
+                "reserve.bounded",
+                true,
+            )
+            .left()
+            .unwrap()
+            .into_vector_value();
         let reserve_samples_float = func
             .ctx
             .b
@@ -552,7 +753,12 @@ impl Function for DelayFunction {
         );
 
         // update the buffer
-        let input_vec = input_num.get_vec(func.ctx.b);
+        let input_vec = util::build_denormal_offset(
+            func.ctx.b,
+            func.ctx.target,
+            input_num.get_vec(func.ctx.b),
+            "input.offset",
+        );
         let left_element = func.ctx.context.i32_type().const_int(0, false);
         let left_result = func
             .ctx
@@ -562,6 +768,7 @@ impl Function for DelayFunction {
                 &[
                     &left_pos_ptr,
                     &left_buffer_length_ptr,
+                    &left_capacity_ptr,
                     &func
                         .ctx
                         .b
@@ -592,6 +799,7 @@ impl Function for DelayFunction {
                 &[
                     &right_pos_ptr,
                     &right_buffer_length_ptr,
+                    &right_capacity_ptr,
                     &func
                         .ctx
                         .b
@@ -639,31 +847,16 @@ impl Function for DelayFunction {
     }
 
     fn gen_destruct(func: &mut FunctionContext) {
-        let left_buffer_ptr = func
-            .ctx
-            .b
-            .build_load(
-                &unsafe {
-                    func.ctx
-                        .b
-                        .build_struct_gep(&func.data_ptr, 4, "leftbuffer.ptr")
-                },
-                "leftbuffer",
-            )
-            .into_pointer_value();
-        let right_buffer_ptr = func
-            .ctx
-            .b
-            .build_load(
-                &unsafe {
-                    func.ctx
-                        .b
-                        .build_struct_gep(&func.data_ptr, 5, "rightbuffer.ptr")
-                },
-                "rightbuffer",
-            )
-            .into_pointer_value();
-        func.ctx.b.build_free(&left_buffer_ptr);
-        func.ctx.b.build_free(&right_buffer_ptr);
+        // buffers belong to the arena, which is reset by the host once everything is destroyed, so
+        // all that's left is to forget about them
+        let const_zero = func.ctx.context.i32_type().const_int(0, false);
+        for field_index in &[2, 3, 6, 7] {
+            let field_ptr = unsafe {
+                func.ctx
+                    .b
+                    .build_struct_gep(&func.data_ptr, *field_index, "size.ptr")
+            };
+            func.ctx.b.build_store(&field_ptr, &const_zero);
+        }
     }
 }
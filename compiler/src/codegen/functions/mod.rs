@@ -13,7 +13,9 @@ mod vector_intrinsic_function;
 mod vector_shuffle_function;
 mod voices_function;
 
-use crate::codegen::{build_context_function, util, values, BuilderContext, TargetProperties};
+use crate::codegen::{
+    build_context_function, globals, util, values, BuilderContext, TargetProperties,
+};
 use crate::mir::{block, VarType};
 use inkwell::attribute::AttrKind;
 use inkwell::builder::Builder;
@@ -180,19 +182,44 @@ map_functions! {
     Adsr => AdsrFunction
 }
 
+/// Finds the fields of a function's data that point to buffers claimed from the arena, each paired
+/// with the field holding the buffer's capacity in samples.
+pub fn get_arena_buffer_fields(function_type: block::Function) -> &'static [(u32, u32)] {
+    match function_type {
+        block::Function::Delay => &DelayFunction::BUFFER_FIELDS,
+        _ => &[],
+    }
+}
+
+pub fn get_lifecycle_func_name(
+    function: block::Function,
+    lifecycle: FunctionLifecycleFunc,
+) -> String {
+    format!("maxim.function.{}.{}", function, lifecycle)
+}
+
+pub fn get_update_func_name(function: block::Function) -> String {
+    format!("maxim.function.{}.update", function)
+}
+
 fn get_lifecycle_func(
     module: &Module,
+    target: &TargetProperties,
     function: block::Function,
     lifecycle: FunctionLifecycleFunc,
 ) -> FunctionValue {
-    let func_name = format!("maxim.function.{}.{}", function, lifecycle);
+    let func_name = get_lifecycle_func_name(function, lifecycle);
     let func = util::get_or_create_func(module, &func_name, true, &|| {
         let context = module.get_context();
         (
             Linkage::ExternalLinkage,
-            context.void_type().fn_type(
-                &[&get_data_type(&context, function).ptr_type(AddressSpace::Generic)],
-                false,
+            globals::add_instance_param(
+                module,
+                target,
+                context.void_type().fn_type(
+                    &[&get_data_type(&context, function).ptr_type(AddressSpace::Generic)],
+                    false,
+                ),
             ),
         )
     });
@@ -200,8 +227,12 @@ fn get_lifecycle_func(
     func
 }
 
-fn get_update_func(module: &Module, function: block::Function) -> FunctionValue {
-    let func_name = format!("maxim.function.{}.update", function);
+fn get_update_func(
+    module: &Module,
+    target: &TargetProperties,
+    function: block::Function,
+) -> FunctionValue {
+    let func_name = get_update_func_name(function);
     let func = util::get_or_create_func(module, &func_name, true, &|| {
         let context = module.get_context();
         let mut arg_types: Vec<BasicTypeEnum> = vec![get_data_type(&context, function)
@@ -250,13 +281,14 @@ fn get_update_func(module: &Module, function: block::Function) -> FunctionValue
             .iter()
             .map(|arg_type| arg_type as &BasicType)
             .collect();
+        let func_type = if pass_return_by_val {
+            values::remap_type(&context, &mir_return_type).fn_type(&arg_refs, false)
+        } else {
+            context.void_type().fn_type(&arg_refs, false)
+        };
         (
             Linkage::ExternalLinkage,
-            if pass_return_by_val {
-                values::remap_type(&context, &mir_return_type).fn_type(&arg_refs, false)
-            } else {
-                context.void_type().fn_type(&arg_refs, false)
-            },
+            globals::add_instance_param(module, target, func_type),
         )
     });
     let context = module.get_context();
@@ -274,7 +306,7 @@ fn build_lifecycle_func(
     lifecycle: FunctionLifecycleFunc,
     builder: &Fn(&mut FunctionContext),
 ) {
-    let func = get_lifecycle_func(module, function, lifecycle);
+    let func = get_lifecycle_func(module, target, function, lifecycle);
     build_context_function(module, func, target, &|ctx: BuilderContext| {
         let data_ptr = ctx.func.get_nth_param(0).unwrap().into_pointer_value();
         let mut function_context = FunctionContext { ctx, data_ptr };
@@ -290,7 +322,7 @@ fn build_update_func(
     target: &TargetProperties,
     builder: &Fn(&mut FunctionContext, &[PointerValue], Option<VarArgs>, PointerValue),
 ) {
-    let func = get_update_func(module, function);
+    let func = get_update_func(module, target, function);
     build_context_function(module, func, target, &|ctx: BuilderContext| {
         let mut params_iter = ctx.func.params();
         let data_ptr = params_iter.next().unwrap().into_pointer_value();
@@ -309,10 +341,12 @@ fn build_update_func(
 
         let func_vararg = function.var_arg();
         let func_arg_types = function.arg_types();
+        let instance_count = if ctx.target.instanced { 1 } else { 0 };
         let arg_pointers: Vec<_> = params_iter
             .take(
                 ctx.func.count_params() as usize
                     - param_offset_count
+                    - instance_count
                     - if func_vararg.is_some() { 1 } else { 0 },
             )
             .zip(func_arg_types)
@@ -334,7 +368,10 @@ fn build_update_func(
 
         let vararg = if let Some(vararg_type) = func_vararg {
             Some(VarArgs::new(
-                ctx.func.get_last_param().unwrap().into_struct_value(),
+                ctx.func
+                    .get_nth_param(ctx.func.count_params() - 1 - instance_count as u32)
+                    .unwrap()
+                    .into_struct_value(),
                 values::pass_type_by_val(&vararg_type),
             ))
         } else {
@@ -358,13 +395,20 @@ fn build_update_func(
 
 pub fn build_lifecycle_call(
     module: &Module,
+    target: &TargetProperties,
     builder: &mut Builder,
     function: block::Function,
     lifecycle: FunctionLifecycleFunc,
     data_ptr: PointerValue,
 ) {
-    let func = get_lifecycle_func(module, function, lifecycle);
-    builder.build_call(&func, &[&data_ptr], "", true);
+    let func = get_lifecycle_func(module, target, function, lifecycle);
+    let instance = globals::get_instance_param(module, builder);
+    builder.build_call(
+        &func,
+        &globals::with_instance_arg(&[&data_ptr], &instance),
+        "",
+        true,
+    );
 }
 
 pub fn build_call(
@@ -375,7 +419,7 @@ pub fn build_call(
     varargs: &[PointerValue],
     out_val: PointerValue,
 ) {
-    let func = get_update_func(ctx.module, function);
+    let func = get_update_func(ctx.module, ctx.target, function);
     let mut pass_args: Vec<BasicValueEnum> = vec![data_ptr.into()];
     let pass_return_by_val = values::pass_type_by_val(&function.return_type());
     if !pass_return_by_val {
@@ -451,6 +495,10 @@ pub fn build_call(
         pass_args.push(va_struct.into());
     }
 
+    if let Some(instance) = globals::get_instance_param(ctx.module, ctx.b) {
+        pass_args.push(instance.into());
+    }
+
     let pass_arg_refs: Vec<_> = pass_args.iter().map(|arg| arg as &BasicValue).collect();
     let ret_val = ctx.b.build_call(&func, &pass_arg_refs, "", true);
     if pass_return_by_val {
@@ -57,7 +57,7 @@ fn gen_periodic_call(
         .ctx
         .b
         .build_load(
-            &globals::get_sample_rate(func.ctx.module).as_pointer_value(),
+            &globals::build_sample_rate_ptr(func.ctx.module, func.ctx.b),
             "samplerate",
         )
         .into_vector_value();
@@ -66,7 +66,7 @@ impl Function for SvFilterFunction {
                         func.ctx
                             .b
                             .build_load(
-                                &globals::get_sample_rate(func.ctx.module).as_pointer_value(),
+                                &globals::build_sample_rate_ptr(func.ctx.module, func.ctx.b),
                                 "samplerate",
                             )
                             .into_vector_value(),
@@ -125,17 +125,18 @@ impl Function for SvFilterFunction {
             .build_conditional_branch(&index_cond, &loop_body_block, &loop_end_block);
 
         func.ctx.b.position_at_end(&loop_body_block);
+        let new_low = func.ctx.b.build_float_add(
+            func.ctx.b.build_load(&low_ptr, "low").into_vector_value(),
+            func.ctx.b.build_float_mul(
+                f_val,
+                func.ctx.b.build_load(&band_ptr, "band").into_vector_value(),
+                "",
+            ),
+            "newlow",
+        );
         func.ctx.b.build_store(
             &low_ptr,
-            &func.ctx.b.build_float_add(
-                func.ctx.b.build_load(&low_ptr, "low").into_vector_value(),
-                func.ctx.b.build_float_mul(
-                    f_val,
-                    func.ctx.b.build_load(&band_ptr, "band").into_vector_value(),
-                    "",
-                ),
-                "newlow",
-            ),
+            &util::build_denormal_offset(func.ctx.b, func.ctx.target, new_low, "newlow.offset"),
         );
         func.ctx.b.build_store(
             &high_ptr,
@@ -153,17 +154,18 @@ impl Function for SvFilterFunction {
                 "newhigh",
             ),
         );
+        let new_band = func.ctx.b.build_float_add(
+            func.ctx.b.build_load(&band_ptr, "band").into_vector_value(),
+            func.ctx.b.build_float_mul(
+                f_val,
+                func.ctx.b.build_load(&high_ptr, "high").into_vector_value(),
+                "",
+            ),
+            "newband",
+        );
         func.ctx.b.build_store(
             &band_ptr,
-            &func.ctx.b.build_float_add(
-                func.ctx.b.build_load(&band_ptr, "band").into_vector_value(),
-                func.ctx.b.build_float_mul(
-                    f_val,
-                    func.ctx.b.build_load(&high_ptr, "high").into_vector_value(),
-                    "",
-                ),
-                "newband",
-            ),
+            &util::build_denormal_offset(func.ctx.b, func.ctx.target, new_band, "newband.offset"),
         );
 
         let next_index = func.ctx.b.build_int_add(
@@ -1,7 +1,7 @@
 use super::{Function, FunctionContext, VarArgs};
 use crate::ast::FormType;
-use crate::codegen::math;
 use crate::codegen::values::NumValue;
+use crate::codegen::{globals, math};
 use crate::mir::block;
 use inkwell::values::{BasicValue, FunctionValue, PointerValue};
 
@@ -10,14 +10,9 @@ fn gen_intrinsic_call(
     args: &[PointerValue],
     result: PointerValue,
     intrinsic: FunctionValue,
-    form: Option<FormType>,
 ) {
     let result_num = NumValue::new(result);
-    let result_form = match form {
-        Some(form) => func.ctx.context.i8_type().const_int(form as u64, false),
-        None => NumValue::new(args[0]).get_form(func.ctx.b),
-    };
-    result_num.set_form(func.ctx.b, result_form);
+    result_num.set_form(func.ctx.b, NumValue::new(args[0]).get_form(func.ctx.b));
 
     let vec_values: Vec<_> = args
         .iter()
@@ -34,6 +29,43 @@ fn gen_intrinsic_call(
     result_num.set_vec(func.ctx.b, result_vec);
 }
 
+pub struct NoiseFunction {}
+impl Function for NoiseFunction {
+    fn function_type() -> block::Function {
+        block::Function::Noise
+    }
+    fn gen_call(
+        func: &mut FunctionContext,
+        _args: &[PointerValue],
+        _varargs: Option<VarArgs>,
+        result: PointerValue,
+    ) {
+        // the seed belongs to the instance, so unlike the other intrinsics this is passed it
+        let result_num = NumValue::new(result);
+        result_num.set_form(
+            func.ctx.b,
+            func.ctx
+                .context
+                .i8_type()
+                .const_int(FormType::Oscillator as u64, false),
+        );
+        let instance = globals::get_instance_param(func.ctx.module, func.ctx.b);
+        let result_vec = func
+            .ctx
+            .b
+            .build_call(
+                &math::rand_v2f64(func.ctx.module, func.ctx.target),
+                &globals::with_instance_arg(&[], &instance),
+                "result",
+                true,
+            )
+            .left()
+            .unwrap()
+            .into_vector_value();
+        result_num.set_vec(func.ctx.b, result_vec);
+    }
+}
+
 macro_rules! define_vector_intrinsic (
     ($func_name:ident: $func_type:expr => $intrinsic_name:expr) => (
         pub struct $func_name {}
@@ -41,23 +73,12 @@ macro_rules! define_vector_intrinsic (
             fn function_type() -> block::Function { $func_type }
             fn gen_call(func: &mut FunctionContext, args: &[PointerValue], _varargs: Option<VarArgs>, result: PointerValue) {
                 let intrinsic = $intrinsic_name(func.ctx.module);
-                gen_intrinsic_call(func, args, result, intrinsic, None)
-            }
-        }
-    );
-    ($func_name:ident: $func_type:expr => $intrinsic_name:expr => $form_name:ident) => (
-        pub struct $func_name {}
-        impl Function for $func_name {
-            fn function_type() -> block::Function { $func_type }
-            fn gen_call(func: &mut FunctionContext, args: &[PointerValue], _varargs: Option<VarArgs>, result: PointerValue) {
-                let intrinsic = $intrinsic_name(func.ctx.module);
-                gen_intrinsic_call(func, args, result, intrinsic, Some(FormType::$form_name))
+                gen_intrinsic_call(func, args, result, intrinsic)
             }
         }
     )
 );
 
-define_vector_intrinsic!(NoiseFunction: block::Function::Noise => math::rand_v2f64 => Oscillator);
 define_vector_intrinsic!(SinFunction: block::Function::Sin => math::sin_v2f64);
 define_vector_intrinsic!(CosFunction: block::Function::Cos => math::cos_v2f64);
 define_vector_intrinsic!(TanFunction: block::Function::Tan => math::tan_v2f64);
@@ -1,13 +1,25 @@
-use crate::codegen::util;
+use crate::codegen::{util, TargetProperties};
 use crate::mir::block::FUNCTION_TABLE;
+use inkwell::builder::Builder;
 use inkwell::module::Module;
-use inkwell::types::{ArrayType, VectorType};
-use inkwell::values::GlobalValue;
+use inkwell::types::{ArrayType, BasicType, FunctionType, PointerType, StructType, VectorType};
+use inkwell::values::{BasicValue, GlobalValue, PointerValue, StructValue};
+use inkwell::AddressSpace;
 
 pub const SAMPLERATE_GLOBAL_NAME: &str = "maxim.samplerate";
+pub const CONSTRUCT_SAMPLERATE_GLOBAL_NAME: &str = "maxim.samplerate.construct";
 pub const BPM_GLOBAL_NAME: &str = "maxim.bpm";
 pub const RAND_SEED_GLOBAL_NAME: &str = "maxim.randseed";
 pub const PROFILE_TIME_GLOBAL_NAME: &str = "maxim.profiletimes";
+pub const ARENA_GLOBAL_NAME: &str = "maxim.arena";
+pub const ARENA_SIZE_GLOBAL_NAME: &str = "maxim.arena.size";
+pub const ARENA_USED_GLOBAL_NAME: &str = "maxim.arena.used";
+pub const FAULTS_GLOBAL_NAME: &str = "maxim.faults";
+pub const FAULT_COUNT_GLOBAL_NAME: &str = "maxim.faults.count";
+pub const FAULT_GENERATION_GLOBAL_NAME: &str = "maxim.faults.generation";
+
+/// The number of faults that can be recorded before further ones are dropped.
+pub const MAX_FAULTS: u32 = 64;
 
 pub fn get_sample_rate(module: &Module) -> GlobalValue {
     util::get_or_create_global(
@@ -17,6 +29,17 @@ pub fn get_sample_rate(module: &Module) -> GlobalValue {
     )
 }
 
+/// The sample rate nodes are constructed at, which delay lines size their buffers from. It's kept
+/// apart from the sample rate so new code can be constructed while other code is running, without
+/// either seeing the rate the other has scaled for its oversampled nodes.
+pub fn get_construct_sample_rate(module: &Module) -> GlobalValue {
+    util::get_or_create_global(
+        module,
+        CONSTRUCT_SAMPLERATE_GLOBAL_NAME,
+        &module.get_context().f64_type().vec_type(2),
+    )
+}
+
 pub fn get_bpm(module: &Module) -> GlobalValue {
     util::get_or_create_global(
         module,
@@ -48,14 +71,289 @@ pub fn get_profile_time(module: &Module) -> GlobalValue {
     )
 }
 
+/// A pointer to the memory that delay lines and other dynamic storage are claimed from. The
+/// memory is owned by whoever hosts the module, so nothing is allocated on the audio thread.
+pub fn get_arena(module: &Module) -> GlobalValue {
+    util::get_or_create_global(
+        module,
+        ARENA_GLOBAL_NAME,
+        &module
+            .get_context()
+            .i8_type()
+            .ptr_type(AddressSpace::Generic),
+    )
+}
+
+pub fn get_arena_size(module: &Module) -> GlobalValue {
+    util::get_or_create_global(
+        module,
+        ARENA_SIZE_GLOBAL_NAME,
+        &module.get_context().i64_type(),
+    )
+}
+
+pub fn get_arena_used(module: &Module) -> GlobalValue {
+    util::get_or_create_global(
+        module,
+        ARENA_USED_GLOBAL_NAME,
+        &module.get_context().i64_type(),
+    )
+}
+
+/// A fault is recorded as the surface and index of the node that output a non-finite value.
+pub fn get_fault_type(module: &Module) -> StructType {
+    let context = module.get_context();
+    context.struct_type(&[&context.i64_type(), &context.i32_type()], false)
+}
+
+fn get_faults_type(module: &Module) -> ArrayType {
+    get_fault_type(module).array_type(MAX_FAULTS)
+}
+
+/// The faults recorded by nodes with sanitized outputs, in the order they happened.
+pub fn get_faults(module: &Module) -> GlobalValue {
+    util::get_or_create_global(module, FAULTS_GLOBAL_NAME, &get_faults_type(module))
+}
+
+pub fn get_fault_count(module: &Module) -> GlobalValue {
+    util::get_or_create_global(
+        module,
+        FAULT_COUNT_GLOBAL_NAME,
+        &module.get_context().i32_type(),
+    )
+}
+
+/// Each node records a fault at most once per generation. The host bumps the generation when it
+/// clears the fault table, so nodes that are still faulting get recorded again.
+pub fn get_fault_generation(module: &Module) -> GlobalValue {
+    util::get_or_create_global(
+        module,
+        FAULT_GENERATION_GLOBAL_NAME,
+        &module.get_context().i32_type(),
+    )
+}
+
+/// Gives the fault table its initial, empty value.
+pub fn initialize_faults(module: &Module) {
+    let context = module.get_context();
+    get_faults(module).set_initializer(&get_faults_type(module).const_null());
+    get_fault_count(module).set_initializer(&context.i32_type().const_int(0, false));
+    get_fault_generation(module).set_initializer(&context.i32_type().const_int(1, false));
+}
+
 pub fn build_globals(module: &Module) {
     let context = module.get_context();
 
     get_sample_rate(module).set_initializer(&util::get_vec_spread(&context, 44100.));
+    get_construct_sample_rate(module).set_initializer(&util::get_vec_spread(&context, 44100.));
     get_bpm(module).set_initializer(&util::get_vec_spread(&context, 60.));
     get_rand_seed(module).set_initializer(&VectorType::const_vector(&[
         &context.i64_type().const_int(1, false),
         &context.i64_type().const_int(31337, false),
     ]));
     get_profile_time(module).set_initializer(&get_profile_time_type(module).const_null());
+    get_arena(module).set_initializer(
+        &context
+            .i8_type()
+            .ptr_type(AddressSpace::Generic)
+            .const_null(),
+    );
+    get_arena_size(module).set_initializer(&context.i64_type().const_int(0, false));
+    get_arena_used(module).set_initializer(&context.i64_type().const_int(0, false));
+    initialize_faults(module);
+}
+
+/// The globals that each instance of an instanced export keeps for itself instead, in the order
+/// they're laid out in `get_instance_globals_type`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum InstanceGlobal {
+    SampleRate,
+    Bpm,
+    RandSeed,
+    Arena,
+    ArenaSize,
+    ArenaUsed,
+    Faults,
+    FaultCount,
+    FaultGeneration,
+    ConstructSampleRate,
+}
+
+pub fn get_instance_globals_type(module: &Module) -> StructType {
+    let context = module.get_context();
+    context.struct_type(
+        &[
+            &context.f64_type().vec_type(2),
+            &context.f64_type().vec_type(2),
+            &context.i64_type().vec_type(2),
+            &context.i8_type().ptr_type(AddressSpace::Generic),
+            &context.i64_type(),
+            &context.i64_type(),
+            &get_faults_type(module),
+            &context.i32_type(),
+            &context.i32_type(),
+            &context.f64_type().vec_type(2),
+        ],
+        false,
+    )
+}
+
+/// The values an instance's globals start with. The arena pointer is left null, since it points
+/// into the instance and has to be set when it's created.
+pub fn get_instance_globals_const(
+    module: &Module,
+    sample_rate: f64,
+    bpm: f64,
+    arena_size: u64,
+) -> StructValue {
+    let context = module.get_context();
+    context.const_struct(
+        &[
+            &util::get_vec_spread(&context, sample_rate),
+            &util::get_vec_spread(&context, bpm),
+            &VectorType::const_vector(&[
+                &context.i64_type().const_int(1, false),
+                &context.i64_type().const_int(31337, false),
+            ]),
+            &context
+                .i8_type()
+                .ptr_type(AddressSpace::Generic)
+                .const_null(),
+            &context.i64_type().const_int(arena_size, false),
+            &context.i64_type().const_int(0, false),
+            &get_faults_type(module).const_null(),
+            &context.i32_type().const_int(0, false),
+            &context.i32_type().const_int(1, false),
+            &util::get_vec_spread(&context, sample_rate),
+        ],
+        false,
+    )
+}
+
+/// The type of the instance's globals that functions in instanced exports are given as their last
+/// parameter, see `add_instance_param`.
+pub fn get_instance_ptr_type(module: &Module) -> PointerType {
+    get_instance_globals_type(module).ptr_type(AddressSpace::Generic)
+}
+
+/// Adds the instance parameter to the end of a function type if the target is instanced. Functions
+/// that use the globals in this file, or call other functions that do, are created with this so
+/// that every call into an instance passes it down explicitly.
+pub fn add_instance_param(
+    module: &Module,
+    target: &TargetProperties,
+    func_type: FunctionType,
+) -> FunctionType {
+    if !target.instanced {
+        return func_type;
+    }
+
+    let mut param_types = func_type.get_param_types();
+    param_types.push(get_instance_ptr_type(module).into());
+    let param_refs: Vec<_> = param_types
+        .iter()
+        .map(|param_type| param_type as &BasicType)
+        .collect();
+    match func_type.get_return_type() {
+        Some(return_type) => return_type.fn_type(&param_refs, false),
+        None => module.get_context().void_type().fn_type(&param_refs, false),
+    }
+}
+
+/// The instance parameter of the function the builder is in, if it was created with one.
+pub fn get_instance_param(module: &Module, builder: &Builder) -> Option<PointerValue> {
+    let func = builder.get_insert_block()?.get_parent()?;
+    let param = func.get_last_param()?;
+    if param.get_type() == get_instance_ptr_type(module).into() {
+        Some(param.into_pointer_value())
+    } else {
+        None
+    }
+}
+
+/// Appends the current function's instance parameter to the arguments of a call to a function
+/// created with `add_instance_param`.
+pub fn with_instance_arg<'a>(
+    args: &[&'a BasicValue],
+    instance: &'a Option<PointerValue>,
+) -> Vec<&'a BasicValue> {
+    let mut args = args.to_vec();
+    if let Some(instance) = instance {
+        args.push(instance);
+    }
+    args
+}
+
+fn build_instance_global_ptr(
+    module: &Module,
+    builder: &Builder,
+    instance_global: InstanceGlobal,
+    get_global: &Fn(&Module) -> GlobalValue,
+) -> PointerValue {
+    match get_instance_param(module, builder) {
+        Some(instance_ptr) => unsafe {
+            builder.build_struct_gep(&instance_ptr, instance_global as u32, "")
+        },
+        None => get_global(module).as_pointer_value(),
+    }
+}
+
+pub fn build_sample_rate_ptr(module: &Module, builder: &Builder) -> PointerValue {
+    build_instance_global_ptr(
+        module,
+        builder,
+        InstanceGlobal::SampleRate,
+        &get_sample_rate,
+    )
+}
+
+pub fn build_construct_sample_rate_ptr(module: &Module, builder: &Builder) -> PointerValue {
+    build_instance_global_ptr(
+        module,
+        builder,
+        InstanceGlobal::ConstructSampleRate,
+        &get_construct_sample_rate,
+    )
+}
+
+pub fn build_bpm_ptr(module: &Module, builder: &Builder) -> PointerValue {
+    build_instance_global_ptr(module, builder, InstanceGlobal::Bpm, &get_bpm)
+}
+
+pub fn build_rand_seed_ptr(module: &Module, builder: &Builder) -> PointerValue {
+    build_instance_global_ptr(module, builder, InstanceGlobal::RandSeed, &get_rand_seed)
+}
+
+pub fn build_arena_ptr(module: &Module, builder: &Builder) -> PointerValue {
+    build_instance_global_ptr(module, builder, InstanceGlobal::Arena, &get_arena)
+}
+
+pub fn build_arena_size_ptr(module: &Module, builder: &Builder) -> PointerValue {
+    build_instance_global_ptr(module, builder, InstanceGlobal::ArenaSize, &get_arena_size)
+}
+
+pub fn build_arena_used_ptr(module: &Module, builder: &Builder) -> PointerValue {
+    build_instance_global_ptr(module, builder, InstanceGlobal::ArenaUsed, &get_arena_used)
+}
+
+pub fn build_faults_ptr(module: &Module, builder: &Builder) -> PointerValue {
+    build_instance_global_ptr(module, builder, InstanceGlobal::Faults, &get_faults)
+}
+
+pub fn build_fault_count_ptr(module: &Module, builder: &Builder) -> PointerValue {
+    build_instance_global_ptr(
+        module,
+        builder,
+        InstanceGlobal::FaultCount,
+        &get_fault_count,
+    )
+}
+
+pub fn build_fault_generation_ptr(module: &Module, builder: &Builder) -> PointerValue {
+    build_instance_global_ptr(
+        module,
+        builder,
+        InstanceGlobal::FaultGeneration,
+        &get_fault_generation,
+    )
 }
@@ -0,0 +1,98 @@
+use crate::codegen::util;
+use crate::util::half_band::{get_side_coefficients, SIDE_TAPS};
+use inkwell::builder::Builder;
+use inkwell::context::Context;
+use inkwell::types::StructType;
+use inkwell::values::{PointerValue, VectorValue};
+
+/// The state of one 2:1 decimation stage. The filter is split into its two polyphase branches:
+///
+///  - History of the odd input samples, which are convolved with the side taps.
+///  - History of the even input samples, of which only the one under the center tap is used.
+pub fn get_stage_type(context: &Context) -> StructType {
+    let sample_type = context.f64_type().vec_type(2);
+    context.struct_type(
+        &[
+            &sample_type.array_type((SIDE_TAPS * 2) as u32),
+            &sample_type.array_type(SIDE_TAPS as u32),
+        ],
+        false,
+    )
+}
+
+fn build_push_history(
+    context: &Context,
+    builder: &mut Builder,
+    history_ptr: PointerValue,
+    length: usize,
+    sample: VectorValue,
+) {
+    let const_zero = context.i32_type().const_int(0, false);
+    let get_sample_ptr = |builder: &mut Builder, index: usize| unsafe {
+        builder.build_in_bounds_gep(
+            &history_ptr,
+            &[
+                const_zero,
+                context.i32_type().const_int(index as u64, false),
+            ],
+            "history.ptr",
+        )
+    };
+
+    for index in (1..length).rev() {
+        let last_ptr = get_sample_ptr(builder, index - 1);
+        let next_ptr = get_sample_ptr(builder, index);
+        let last_sample = builder.build_load(&last_ptr, "history");
+        builder.build_store(&next_ptr, &last_sample);
+    }
+    let first_ptr = get_sample_ptr(builder, 0);
+    builder.build_store(&first_ptr, &sample);
+}
+
+/// Pushes a pair of samples through a decimation stage, returning one sample at half the rate.
+pub fn build_decimate(
+    context: &Context,
+    builder: &mut Builder,
+    state_ptr: PointerValue,
+    even_sample: VectorValue,
+    odd_sample: VectorValue,
+) -> VectorValue {
+    let odd_history_ptr = unsafe { builder.build_struct_gep(&state_ptr, 0, "oddhistory") };
+    let even_history_ptr = unsafe { builder.build_struct_gep(&state_ptr, 1, "evenhistory") };
+    build_push_history(context, builder, odd_history_ptr, SIDE_TAPS * 2, odd_sample);
+    build_push_history(context, builder, even_history_ptr, SIDE_TAPS, even_sample);
+
+    let const_zero = context.i32_type().const_int(0, false);
+    let load_history = |builder: &mut Builder, history_ptr: &PointerValue, index: usize| {
+        let sample_ptr = unsafe {
+            builder.build_in_bounds_gep(
+                history_ptr,
+                &[
+                    const_zero,
+                    context.i32_type().const_int(index as u64, false),
+                ],
+                "history.ptr",
+            )
+        };
+        builder
+            .build_load(&sample_ptr, "history")
+            .into_vector_value()
+    };
+    let center_sample = load_history(builder, &even_history_ptr, SIDE_TAPS - 1);
+    let mut result =
+        builder.build_float_mul(center_sample, util::get_vec_spread(context, 0.5), "center");
+
+    // the side taps are symmetric around the center, so pairs of samples can share a multiply
+    for (tap, coefficient) in get_side_coefficients().into_iter().enumerate() {
+        let near_sample = load_history(builder, &odd_history_ptr, SIDE_TAPS - 1 - tap);
+        let far_sample = load_history(builder, &odd_history_ptr, SIDE_TAPS + tap);
+        let tap_sum = builder.build_float_add(near_sample, far_sample, "tapsum");
+        result = builder.build_float_add(
+            result,
+            builder.build_float_mul(tap_sum, util::get_vec_spread(context, coefficient), ""),
+            "decimated",
+        );
+    }
+
+    result
+}
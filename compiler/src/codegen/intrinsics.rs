@@ -1,10 +1,12 @@
-use crate::codegen::{build_context_function, util, BuilderContext, TargetProperties};
+use crate::codegen::{build_context_function, globals, util, BuilderContext, TargetProperties};
 use inkwell::module::{Linkage, Module};
 use inkwell::targets::TargetData;
 use inkwell::types::{BasicType, VectorType};
 use inkwell::values::FunctionValue;
 use inkwell::{AddressSpace, IntPredicate};
 
+pub const RECORD_FAULT_FUNC_NAME: &str = "maxim.fault.record";
+
 pub fn memcpy(module: &Module) -> FunctionValue {
     util::get_or_create_func(module, "llvm.memcpy.p0i8.p0i8.i64", true, &|| {
         let context = module.get_context();
@@ -24,25 +26,6 @@ pub fn memcpy(module: &Module) -> FunctionValue {
     })
 }
 
-pub fn realloc(module: &Module, target: &TargetData) -> FunctionValue {
-    util::get_or_create_func(module, "realloc", false, &|| {
-        let ptr_type = module
-            .get_context()
-            .i8_type()
-            .ptr_type(AddressSpace::Generic);
-        (
-            Linkage::ExternalLinkage,
-            ptr_type.fn_type(
-                &[
-                    &ptr_type,
-                    &target.int_ptr_type_in_context(&module.get_context()),
-                ],
-                false,
-            ),
-        )
-    })
-}
-
 pub fn memset(module: &Module, target: &TargetData) -> FunctionValue {
     let target_ptr_type = target.int_ptr_type_in_context(&module.get_context());
     let intrinsic_name = format!("llvm.memset.p0i8.i{}", target_ptr_type.get_bit_width());
@@ -97,6 +80,75 @@ pub fn next_power_i32(module: &Module) -> FunctionValue {
     })
 }
 
+pub fn arena_claim(module: &Module, target: &TargetProperties) -> FunctionValue {
+    util::get_or_create_func(module, "maxim.arena.claim", true, &|| {
+        let context = module.get_context();
+        (
+            Linkage::PrivateLinkage,
+            globals::add_instance_param(
+                module,
+                target,
+                context
+                    .i8_type()
+                    .ptr_type(AddressSpace::Generic)
+                    .fn_type(&[&context.i64_type()], false),
+            ),
+        )
+    })
+}
+
+pub fn record_fault(module: &Module, target: &TargetProperties) -> FunctionValue {
+    util::get_or_create_func(module, RECORD_FAULT_FUNC_NAME, true, &|| {
+        let context = module.get_context();
+        (
+            Linkage::PrivateLinkage,
+            globals::add_instance_param(
+                module,
+                target,
+                context.void_type().fn_type(
+                    &[
+                        &context.i32_type().ptr_type(AddressSpace::Generic),
+                        &context.i64_type(),
+                        &context.i32_type(),
+                    ],
+                    false,
+                ),
+            ),
+        )
+    })
+}
+
+pub fn stmxcsr(module: &Module) -> FunctionValue {
+    util::get_or_create_func(module, "llvm.x86.sse.stmxcsr", true, &|| {
+        let context = module.get_context();
+        (
+            Linkage::ExternalLinkage,
+            context
+                .void_type()
+                .fn_type(&[&context.i8_type().ptr_type(AddressSpace::Generic)], false),
+        )
+    })
+}
+
+pub fn ldmxcsr(module: &Module) -> FunctionValue {
+    util::get_or_create_func(module, "llvm.x86.sse.ldmxcsr", true, &|| {
+        let context = module.get_context();
+        (
+            Linkage::ExternalLinkage,
+            context
+                .void_type()
+                .fn_type(&[&context.i8_type().ptr_type(AddressSpace::Generic)], false),
+        )
+    })
+}
+
+pub fn read_cycle_counter(module: &Module) -> FunctionValue {
+    util::get_or_create_func(module, "llvm.readcyclecounter", true, &|| {
+        let i64_type = module.get_context().i64_type();
+        (Linkage::ExternalLinkage, i64_type.fn_type(&[], false))
+    })
+}
+
 pub fn profile_timestamp_i64(module: &Module) -> FunctionValue {
     util::get_or_create_func(module, "profile_timestamp", false, &|| {
         let i64_type = module.get_context().i64_type();
@@ -107,6 +159,8 @@ pub fn profile_timestamp_i64(module: &Module) -> FunctionValue {
 pub fn build_intrinsics(module: &Module, target: &TargetProperties) {
     build_eucrem_v2i32(module, target);
     build_next_power_i32(module, target);
+    build_arena_claim(module, target);
+    build_record_fault(module, target);
 }
 
 // Integer modulo (euclidian remainder)
@@ -237,3 +291,151 @@ fn build_next_power_i32(module: &Module, target: &TargetProperties) {
         },
     );
 }
+
+// Claims a block of memory from the arena, returning null if there isn't enough left.
+// Memory is never given back, the arena is reset as a whole by the host once nothing uses it.
+fn build_arena_claim(module: &Module, target: &TargetProperties) {
+    build_context_function(
+        module,
+        arena_claim(module, target),
+        target,
+        &|ctx: BuilderContext| {
+            let fits_true_block = ctx.context.append_basic_block(&ctx.func, "fits.true");
+            let fits_false_block = ctx.context.append_basic_block(&ctx.func, "fits.false");
+
+            let byte_count = ctx.func.get_nth_param(0).unwrap().into_int_value();
+            let arena_used_ptr = globals::build_arena_used_ptr(ctx.module, ctx.b);
+
+            // keep every claimed block 16-byte aligned relative to the start of the arena
+            let aligned_count = ctx.b.build_and(
+                ctx.b
+                    .build_int_add(byte_count, ctx.context.i64_type().const_int(15, false), ""),
+                ctx.context.i64_type().const_int(!15, false),
+                "alignedcount",
+            );
+            let arena_used = ctx
+                .b
+                .build_load(&arena_used_ptr, "arenaused")
+                .into_int_value();
+            let arena_size = ctx
+                .b
+                .build_load(
+                    &globals::build_arena_size_ptr(ctx.module, ctx.b),
+                    "arenasize",
+                )
+                .into_int_value();
+            let new_used = ctx.b.build_int_add(arena_used, aligned_count, "newused");
+            let fits = ctx
+                .b
+                .build_int_compare(IntPredicate::ULE, new_used, arena_size, "fits");
+            ctx.b
+                .build_conditional_branch(&fits, &fits_true_block, &fits_false_block);
+
+            ctx.b.position_at_end(&fits_true_block);
+            ctx.b.build_store(&arena_used_ptr, &new_used);
+            let arena_ptr = ctx
+                .b
+                .build_load(&globals::build_arena_ptr(ctx.module, ctx.b), "arena")
+                .into_pointer_value();
+            let claimed_ptr = unsafe {
+                ctx.b
+                    .build_in_bounds_gep(&arena_ptr, &[arena_used], "claimed")
+            };
+            ctx.b.build_return(Some(&claimed_ptr));
+
+            ctx.b.position_at_end(&fits_false_block);
+            ctx.b.build_return(Some(
+                &ctx.context
+                    .i8_type()
+                    .ptr_type(AddressSpace::Generic)
+                    .const_null(),
+            ));
+        },
+    );
+}
+
+// Records that a node output a non-finite value, unless it's already been recorded in the current
+// generation of the fault table. Faults past the end of the table are dropped.
+fn build_record_fault(module: &Module, target: &TargetProperties) {
+    build_context_function(
+        module,
+        record_fault(module, target),
+        target,
+        &|ctx: BuilderContext| {
+            let record_true_block = ctx.context.append_basic_block(&ctx.func, "record.true");
+            let fits_true_block = ctx.context.append_basic_block(&ctx.func, "fits.true");
+            let end_block = ctx.context.append_basic_block(&ctx.func, "end");
+
+            let node_generation_ptr = ctx.func.get_nth_param(0).unwrap().into_pointer_value();
+            let surface_id = ctx.func.get_nth_param(1).unwrap().into_int_value();
+            let node_index = ctx.func.get_nth_param(2).unwrap().into_int_value();
+
+            let generation = ctx
+                .b
+                .build_load(
+                    &globals::build_fault_generation_ptr(ctx.module, ctx.b),
+                    "generation",
+                )
+                .into_int_value();
+            let node_generation = ctx
+                .b
+                .build_load(&node_generation_ptr, "nodegeneration")
+                .into_int_value();
+            let is_recorded = ctx.b.build_int_compare(
+                IntPredicate::EQ,
+                node_generation,
+                generation,
+                "isrecorded",
+            );
+            ctx.b
+                .build_conditional_branch(&is_recorded, &end_block, &record_true_block);
+
+            ctx.b.position_at_end(&record_true_block);
+            ctx.b.build_store(&node_generation_ptr, &generation);
+            let fault_count_ptr = globals::build_fault_count_ptr(ctx.module, ctx.b);
+            let fault_count = ctx
+                .b
+                .build_load(&fault_count_ptr, "faultcount")
+                .into_int_value();
+            let fits = ctx.b.build_int_compare(
+                IntPredicate::ULT,
+                fault_count,
+                ctx.context
+                    .i32_type()
+                    .const_int(u64::from(globals::MAX_FAULTS), false),
+                "fits",
+            );
+            ctx.b
+                .build_conditional_branch(&fits, &fits_true_block, &end_block);
+
+            ctx.b.position_at_end(&fits_true_block);
+            let fault_ptr = unsafe {
+                ctx.b.build_in_bounds_gep(
+                    &globals::build_faults_ptr(ctx.module, ctx.b),
+                    &[ctx.context.i32_type().const_int(0, false), fault_count],
+                    "fault",
+                )
+            };
+            ctx.b.build_store(
+                &unsafe { ctx.b.build_struct_gep(&fault_ptr, 0, "fault.surface") },
+                &surface_id,
+            );
+            ctx.b.build_store(
+                &unsafe { ctx.b.build_struct_gep(&fault_ptr, 1, "fault.node") },
+                &node_index,
+            );
+            ctx.b.build_store(
+                &fault_count_ptr,
+                &ctx.b.build_int_add(
+                    fault_count,
+                    ctx.context.i32_type().const_int(1, false),
+                    "newfaultcount",
+                ),
+            );
+            ctx.b.build_unconditional_branch(&end_block);
+
+            ctx.b.position_at_end(&end_block);
+            ctx.b.build_return(None);
+        },
+    );
+}
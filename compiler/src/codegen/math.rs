@@ -1,4 +1,6 @@
-use crate::codegen::{build_context_function, globals, util, BuilderContext, TargetProperties};
+use crate::codegen::{
+    build_context_function, globals, util, BuilderContext, MathAccuracy, TargetProperties,
+};
 use inkwell::context::Context;
 use inkwell::module::{Linkage, Module};
 use inkwell::types::{BasicType, VectorType};
@@ -7,6 +9,9 @@ use inkwell::values::{FunctionValue, VectorValue};
 use inkwell::FloatPredicate;
 use std::f64::consts;
 
+pub const MOD_V2F64_FUNC_NAME: &str = "maxim.mod.v2f64";
+pub const POW_V2F64_FUNC_NAME: &str = "maxim.pow.v2f64";
+
 // utils
 fn get_float_asm_func(module: &Module, asm: &str, constraints: &str) -> FunctionValue {
     let context = module.get_context();
@@ -30,8 +35,29 @@ fn get_i32_spread(context: &Context, val: u64) -> VectorValue {
     ])
 }
 
+fn factorial(n: u32) -> f64 {
+    (1..=n).map(f64::from).product()
+}
+
+// evaluates a polynomial in x, with coefficients ordered from the highest power down
+fn build_horner(ctx: &BuilderContext, x: VectorValue, coefficients: &[f64]) -> VectorValue {
+    let (&first, rest) = coefficients.split_first().unwrap();
+    rest.iter().fold(
+        util::get_vec_spread(ctx.context, first),
+        |r, &coefficient| {
+            ctx.b.build_float_add(
+                ctx.b.build_float_mul(r, x, ""),
+                util::get_vec_spread(ctx.context, coefficient),
+                "",
+            )
+        },
+    )
+}
+
 pub fn build_math_functions(module: &Module, target: &TargetProperties) {
     build_rand_v2f64(module, target);
+    build_min_v2f64(module, target);
+    build_max_v2f64(module, target);
     build_sin_v2f64(module, target);
     build_cos_v2f64(module, target);
     build_tan_v2f64(module, target);
@@ -59,59 +85,86 @@ pub fn build_math_functions(module: &Module, target: &TargetProperties) {
 }
 
 // rand
-pub fn rand_v2f64(module: &Module) -> FunctionValue {
+pub fn rand_v2f64(module: &Module, target: &TargetProperties) -> FunctionValue {
     util::get_or_create_func(module, "maxim.rand.v2f64", true, &|| {
         let v2f64_type = module.get_context().f64_type().vec_type(2);
-        (Linkage::PrivateLinkage, v2f64_type.fn_type(&[], false))
+        (
+            Linkage::PrivateLinkage,
+            globals::add_instance_param(module, target, v2f64_type.fn_type(&[], false)),
+        )
     })
 }
 
 fn build_rand_v2f64(module: &Module, target: &TargetProperties) {
     build_context_function(
         module,
-        rand_v2f64(module),
+        rand_v2f64(module, target),
         target,
         &|ctx: BuilderContext| {
-            let mul_pd_intrinsic =
-                util::get_or_create_func(module, "llvm.x86.sse41.pmuldq", true, &|| {
-                    (
-                        Linkage::ExternalLinkage,
-                        ctx.context.i64_type().vec_type(2).fn_type(
-                            &[
-                                &ctx.context.i32_type().vec_type(4),
-                                &ctx.context.i32_type().vec_type(4),
-                            ],
-                            false,
-                        ),
-                    )
-                });
-            let seed_ptr = globals::get_rand_seed(ctx.module).as_pointer_value();
+            let seed_ptr = globals::build_rand_seed_ptr(ctx.module, ctx.b);
 
             let current_seed = ctx.b.build_load(&seed_ptr, "seed").into_vector_value();
-            let new_seed = ctx
-                .b
-                .build_call(
-                    &mul_pd_intrinsic,
-                    &[
-                        &ctx.b.build_cast(
-                            InstructionOpcode::BitCast,
-                            &current_seed,
-                            &ctx.context.i32_type().vec_type(4),
-                            "",
-                        ),
-                        &VectorType::const_vector(&[
-                            &ctx.context.i32_type().const_int(16007, false),
-                            &ctx.context.i32_type().get_undef(),
-                            &ctx.context.i32_type().const_int(16007, false),
-                            &ctx.context.i32_type().get_undef(),
-                        ]),
-                    ],
-                    "",
-                    true,
-                )
-                .left()
-                .unwrap()
-                .into_vector_value();
+            let new_seed = if ctx.target.has_sse41() {
+                let mul_pd_intrinsic =
+                    util::get_or_create_func(module, "llvm.x86.sse41.pmuldq", true, &|| {
+                        (
+                            Linkage::ExternalLinkage,
+                            ctx.context.i64_type().vec_type(2).fn_type(
+                                &[
+                                    &ctx.context.i32_type().vec_type(4),
+                                    &ctx.context.i32_type().vec_type(4),
+                                ],
+                                false,
+                            ),
+                        )
+                    });
+                ctx.b
+                    .build_call(
+                        &mul_pd_intrinsic,
+                        &[
+                            &ctx.b.build_cast(
+                                InstructionOpcode::BitCast,
+                                &current_seed,
+                                &ctx.context.i32_type().vec_type(4),
+                                "",
+                            ),
+                            &VectorType::const_vector(&[
+                                &ctx.context.i32_type().const_int(16007, false),
+                                &ctx.context.i32_type().get_undef(),
+                                &ctx.context.i32_type().const_int(16007, false),
+                                &ctx.context.i32_type().get_undef(),
+                            ]),
+                        ],
+                        "",
+                        true,
+                    )
+                    .left()
+                    .unwrap()
+                    .into_vector_value()
+            } else {
+                // pmuldq multiplies the sign-extended low half of each lane, which other targets
+                // need spelled out
+                let seed_low = ctx
+                    .b
+                    .build_cast(
+                        InstructionOpcode::Trunc,
+                        &current_seed,
+                        &ctx.context.i32_type().vec_type(2),
+                        "seed.low",
+                    )
+                    .into_vector_value();
+                let seed_extended = ctx
+                    .b
+                    .build_cast(
+                        InstructionOpcode::SExt,
+                        &seed_low,
+                        &ctx.context.i64_type().vec_type(2),
+                        "seed.extended",
+                    )
+                    .into_vector_value();
+                ctx.b
+                    .build_int_mul(seed_extended, get_i64_spread(ctx.context, 16007), "")
+            };
             ctx.b.build_store(&seed_ptr, &new_seed);
 
             let shuffled = ctx.b.build_signed_int_to_float(
@@ -194,6 +247,58 @@ fn build_sin_v2f64(module: &Module, target: &TargetProperties) {
             )
             .into_vector_value();
 
+        if target.math_accuracy == MathAccuracy::Accurate {
+            // reflect into -pi/2..pi/2, where the Taylor series converges quickly
+            let reflected_x = ctx
+                .b
+                .build_select(
+                    ctx.b.build_float_compare(
+                        FloatPredicate::OGT,
+                        wrapped_x,
+                        util::get_vec_spread(ctx.context, consts::FRAC_PI_2),
+                        "",
+                    ),
+                    ctx.b.build_float_sub(
+                        util::get_vec_spread(ctx.context, consts::PI),
+                        wrapped_x,
+                        "",
+                    ),
+                    wrapped_x,
+                    "",
+                )
+                .into_vector_value();
+            let reflected_x = ctx
+                .b
+                .build_select(
+                    ctx.b.build_float_compare(
+                        FloatPredicate::OLT,
+                        reflected_x,
+                        util::get_vec_spread(ctx.context, -consts::FRAC_PI_2),
+                        "",
+                    ),
+                    ctx.b.build_float_sub(
+                        util::get_vec_spread(ctx.context, -consts::PI),
+                        reflected_x,
+                        "",
+                    ),
+                    reflected_x,
+                    "x.reflected",
+                )
+                .into_vector_value();
+
+            // sin(x) = x - x^3/3! + x^5/5! - ..., up to x^21
+            let coefficients: Vec<_> = (0..11)
+                .rev()
+                .map(|k| (-1_f64).powi(k as i32) / factorial(k * 2 + 1))
+                .collect();
+            let x2 = ctx.b.build_float_mul(reflected_x, reflected_x, "x2");
+            let result =
+                ctx.b
+                    .build_float_mul(reflected_x, build_horner(&ctx, x2, &coefficients), "res");
+            ctx.b.build_return(Some(&result));
+            return;
+        }
+
         let y_val = ctx.b.build_float_add(
             ctx.b.build_float_mul(
                 util::get_vec_spread(ctx.context, 1.273_239_544_723_765),
@@ -216,6 +321,13 @@ fn build_sin_v2f64(module: &Module, target: &TargetProperties) {
             "y",
         );
 
+        // the fast tier stops at the parabola, which is up to about 0.056 off, and the balanced
+        // tier refines it once more
+        if target.math_accuracy == MathAccuracy::Fast {
+            ctx.b.build_return(Some(&y_val));
+            return;
+        }
+
         let result = ctx.b.build_float_add(
             ctx.b.build_float_mul(
                 util::get_vec_spread(ctx.context, 0.224_999_904_632_568_36),
@@ -331,15 +443,22 @@ pub fn tan_slow_f64(module: &Module) -> FunctionValue {
 
 // min
 pub fn min_v2f64(module: &Module) -> FunctionValue {
-    util::get_or_create_func(module, "llvm.x86.sse2.min.pd", true, &|| {
+    util::get_or_create_func(module, "maxim.min.v2f64", true, &|| {
         let v2f64_type = module.get_context().f64_type().vec_type(2);
         (
-            Linkage::ExternalLinkage,
+            Linkage::PrivateLinkage,
             v2f64_type.fn_type(&[&v2f64_type, &v2f64_type], false),
         )
     })
 }
 
+fn build_min_v2f64(module: &Module, target: &TargetProperties) {
+    build_context_function(module, min_v2f64(module), target, &|ctx: BuilderContext| {
+        let res = build_sse_min_max(&ctx, "llvm.x86.sse2.min.pd", FloatPredicate::OLT);
+        ctx.b.build_return(Some(&res));
+    });
+}
+
 pub fn min_f64(module: &Module) -> FunctionValue {
     util::get_or_create_func(module, "llvm.minnum.f64", true, &|| {
         let f64_type = module.get_context().f64_type();
@@ -352,15 +471,22 @@ pub fn min_f64(module: &Module) -> FunctionValue {
 
 // max
 pub fn max_v2f64(module: &Module) -> FunctionValue {
-    util::get_or_create_func(module, "llvm.x86.sse2.max.pd", true, &|| {
+    util::get_or_create_func(module, "maxim.max.v2f64", true, &|| {
         let v2f64_type = module.get_context().f64_type().vec_type(2);
         (
-            Linkage::ExternalLinkage,
+            Linkage::PrivateLinkage,
             v2f64_type.fn_type(&[&v2f64_type, &v2f64_type], false),
         )
     })
 }
 
+fn build_max_v2f64(module: &Module, target: &TargetProperties) {
+    build_context_function(module, max_v2f64(module), target, &|ctx: BuilderContext| {
+        let res = build_sse_min_max(&ctx, "llvm.x86.sse2.max.pd", FloatPredicate::OGT);
+        ctx.b.build_return(Some(&res));
+    });
+}
+
 pub fn max_f64(module: &Module) -> FunctionValue {
     util::get_or_create_func(module, "llvm.maxnum.f64", true, &|| {
         let f64_type = module.get_context().f64_type();
@@ -371,9 +497,39 @@ pub fn max_f64(module: &Module) -> FunctionValue {
     })
 }
 
+// Calls minpd or maxpd with the function's two parameters. Other targets compare and select
+// instead, which keeps the same behaviour: if either lane is NaN, the second parameter is returned.
+fn build_sse_min_max(
+    ctx: &BuilderContext,
+    sse_name: &str,
+    predicate: FloatPredicate,
+) -> VectorValue {
+    let a = ctx.func.get_nth_param(0).unwrap().into_vector_value();
+    let b = ctx.func.get_nth_param(1).unwrap().into_vector_value();
+
+    if ctx.target.has_sse41() {
+        let sse_intrinsic = util::get_or_create_func(ctx.module, sse_name, true, &|| {
+            let v2f64_type = ctx.context.f64_type().vec_type(2);
+            (
+                Linkage::ExternalLinkage,
+                v2f64_type.fn_type(&[&v2f64_type, &v2f64_type], false),
+            )
+        });
+        ctx.b
+            .build_call(&sse_intrinsic, &[&a, &b], "", true)
+            .left()
+            .unwrap()
+            .into_vector_value()
+    } else {
+        let cond = ctx.b.build_float_compare(predicate, a, b, "");
+        ctx.b.build_select(cond, a, b, "").into_vector_value()
+    }
+}
+
 // sqrt
 pub fn sqrt_v2f64(module: &Module) -> FunctionValue {
-    util::get_or_create_func(module, "llvm.x86.sse2.sqrt.pd", true, &|| {
+    // this lowers to sqrtpd on x86, so there's no need for the SSE intrinsic
+    util::get_or_create_func(module, "llvm.sqrt.v2f64", true, &|| {
         let v2f64_type = module.get_context().f64_type().vec_type(2);
         (
             Linkage::ExternalLinkage,
@@ -394,6 +550,39 @@ fn sse_round_v2f64(module: &Module) -> FunctionValue {
     })
 }
 
+// Rounds the function's parameter with roundpd and the given rounding mode. Other targets use the
+// equivalent generic intrinsic, which LLVM lowers to their own rounding instruction.
+fn build_round_intrinsic(ctx: &BuilderContext, sse_mode: u64, generic_name: &str) -> VectorValue {
+    let x = ctx.func.get_nth_param(0).unwrap().into_vector_value();
+
+    if ctx.target.has_sse41() {
+        let sse_round_intrinsic = sse_round_v2f64(ctx.module);
+        ctx.b
+            .build_call(
+                &sse_round_intrinsic,
+                &[&x, &ctx.context.i32_type().const_int(sse_mode, false)],
+                "",
+                true,
+            )
+            .left()
+            .unwrap()
+            .into_vector_value()
+    } else {
+        let generic_intrinsic = util::get_or_create_func(ctx.module, generic_name, true, &|| {
+            let v2f64_type = ctx.context.f64_type().vec_type(2);
+            (
+                Linkage::ExternalLinkage,
+                v2f64_type.fn_type(&[&v2f64_type], false),
+            )
+        });
+        ctx.b
+            .build_call(&generic_intrinsic, &[&x], "", true)
+            .left()
+            .unwrap()
+            .into_vector_value()
+    }
+}
+
 // floor
 pub fn floor_v2f64(module: &Module) -> FunctionValue {
     util::get_or_create_func(module, "maxim.floor.v2f64", true, &|| {
@@ -411,21 +600,7 @@ fn build_floor_v2f64(module: &Module, target: &TargetProperties) {
         floor_v2f64(module),
         target,
         &|ctx: BuilderContext| {
-            let sse_round_intrinsic = sse_round_v2f64(ctx.module);
-            let res = ctx
-                .b
-                .build_call(
-                    &sse_round_intrinsic,
-                    &[
-                        &ctx.func.get_nth_param(0).unwrap().into_vector_value(),
-                        &ctx.context.i32_type().const_int(1, false), // 1 = floor
-                    ],
-                    "",
-                    true,
-                )
-                .left()
-                .unwrap()
-                .into_vector_value();
+            let res = build_round_intrinsic(&ctx, 1, "llvm.floor.v2f64"); // 1 = floor
             ctx.b.build_return(Some(&res));
         },
     );
@@ -448,21 +623,7 @@ fn build_ceil_v2f64(module: &Module, target: &TargetProperties) {
         ceil_v2f64(module),
         target,
         &|ctx: BuilderContext| {
-            let sse_round_intrinsic = sse_round_v2f64(ctx.module);
-            let res = ctx
-                .b
-                .build_call(
-                    &sse_round_intrinsic,
-                    &[
-                        &ctx.func.get_nth_param(0).unwrap().into_vector_value(),
-                        &ctx.context.i32_type().const_int(2, false), // 2 = ceil
-                    ],
-                    "",
-                    true,
-                )
-                .left()
-                .unwrap()
-                .into_vector_value();
+            let res = build_round_intrinsic(&ctx, 2, "llvm.ceil.v2f64"); // 2 = ceil
             ctx.b.build_return(Some(&res));
         },
     );
@@ -485,21 +646,7 @@ fn build_round_v2f64(module: &Module, target: &TargetProperties) {
         round_v2f64(module),
         target,
         &|ctx: BuilderContext| {
-            let sse_round_intrinsic = sse_round_v2f64(ctx.module);
-            let res = ctx
-                .b
-                .build_call(
-                    &sse_round_intrinsic,
-                    &[
-                        &ctx.func.get_nth_param(0).unwrap().into_vector_value(),
-                        &ctx.context.i32_type().const_int(0, false), // 0 = round
-                    ],
-                    "",
-                    true,
-                )
-                .left()
-                .unwrap()
-                .into_vector_value();
+            let res = build_round_intrinsic(&ctx, 0, "llvm.nearbyint.v2f64"); // 0 = round
             ctx.b.build_return(Some(&res));
         },
     );
@@ -531,7 +678,7 @@ pub fn copysign_v2f64(module: &Module) -> FunctionValue {
 // note: this must usage external linkage since blocks can generate code that uses this
 // (see gen_math_op.rs)
 pub fn mod_v2f64(module: &Module) -> FunctionValue {
-    util::get_or_create_func(module, "maxim.mod.v2f64", true, &|| {
+    util::get_or_create_func(module, MOD_V2F64_FUNC_NAME, true, &|| {
         let v2f64_type = module.get_context().f64_type().vec_type(2);
         (
             Linkage::ExternalLinkage,
@@ -610,7 +757,7 @@ fn build_fract_v2f64(module: &Module, target: &TargetProperties) {
 // note: this must usage external linkage since blocks can generate code that uses this
 // (see gen_math_op.rs)
 pub fn pow_v2f64(module: &Module) -> FunctionValue {
-    util::get_or_create_func(module, "maxim.pow.v2f64", true, &|| {
+    util::get_or_create_func(module, POW_V2F64_FUNC_NAME, true, &|| {
         let v2f64_type = module.get_context().f64_type().vec_type(2);
         (
             Linkage::ExternalLinkage,
@@ -764,25 +911,9 @@ fn build_exp2_v2f64(module: &Module, target: &TargetProperties) {
         exp2_v2f64(module),
         target,
         &|ctx: BuilderContext| {
-            let cvtpd_intrinsic =
-                util::get_or_create_func(module, "llvm.x86.sse2.cvtpd2dq", true, &|| {
-                    (
-                        Linkage::ExternalLinkage,
-                        ctx.context
-                            .i32_type()
-                            .vec_type(4)
-                            .fn_type(&[&ctx.context.f64_type().vec_type(2)], false),
-                    )
-                });
-
             let x_vec = ctx.func.get_nth_param(0).unwrap().into_vector_value();
 
-            let x_int_vec = ctx
-                .b
-                .build_call(&cvtpd_intrinsic, &[&x_vec], "", true)
-                .left()
-                .unwrap()
-                .into_vector_value();
+            let x_int_vec = build_round_to_v4i32(&ctx, x_vec);
             let x_int_two_vec = ctx.b.build_shuffle_vector(
                 &x_int_vec,
                 &ctx.context.i32_type().vec_type(4).get_undef(),
@@ -810,13 +941,30 @@ fn build_exp2_v2f64(module: &Module, target: &TargetProperties) {
                 )
             };
 
-            let r = util::get_vec_spread(ctx.context, 0.000_154_653_240_841_184_92); // const 0
-            let r = mad(r, 0.001_339_529_154_378_738); // const 1
-            let r = mad(r, 0.009_618_039_911_742_926); // const 2
-            let r = mad(r, 0.055_503_406_540_083_23); // const 3
-            let r = mad(r, 0.240_226_511_014_043_35); // const 4
-            let r = mad(r, 0.693_147_200_072_417); // const 5
-            let r = mad(r, 0.999_999_999_970_896_2); // const 6
+            let r = match target.math_accuracy {
+                MathAccuracy::Balanced => {
+                    let r = util::get_vec_spread(ctx.context, 0.000_154_653_240_841_184_92); // const 0
+                    let r = mad(r, 0.001_339_529_154_378_738); // const 1
+                    let r = mad(r, 0.009_618_039_911_742_926); // const 2
+                    let r = mad(r, 0.055_503_406_540_083_23); // const 3
+                    let r = mad(r, 0.240_226_511_014_043_35); // const 4
+                    let r = mad(r, 0.693_147_200_072_417); // const 5
+                    mad(r, 0.999_999_999_970_896_2) // const 6
+                }
+                accuracy => {
+                    // 2^x = sum of (x ln 2)^k / k!, with x in -0.5..0.5
+                    let degree = if accuracy == MathAccuracy::Fast {
+                        4
+                    } else {
+                        12
+                    };
+                    let coefficients: Vec<_> = (0..=degree)
+                        .rev()
+                        .map(|k| consts::LN_2.powi(k as i32) / factorial(k))
+                        .collect();
+                    build_horner(&ctx, x_frac, &coefficients)
+                }
+            };
 
             let k = ctx
                 .b
@@ -853,6 +1001,67 @@ fn build_exp2_v2f64(module: &Module, target: &TargetProperties) {
     )
 }
 
+// Rounds each lane to the nearest integer with cvtpd2dq, which puts them in the low two lanes of
+// the result and zeroes the high two. Other targets round and convert separately, leaving the
+// result the same for anything that fits in an i32.
+fn build_round_to_v4i32(ctx: &BuilderContext, x: VectorValue) -> VectorValue {
+    if ctx.target.has_sse41() {
+        let cvtpd_intrinsic =
+            util::get_or_create_func(ctx.module, "llvm.x86.sse2.cvtpd2dq", true, &|| {
+                (
+                    Linkage::ExternalLinkage,
+                    ctx.context
+                        .i32_type()
+                        .vec_type(4)
+                        .fn_type(&[&ctx.context.f64_type().vec_type(2)], false),
+                )
+            });
+        return ctx
+            .b
+            .build_call(&cvtpd_intrinsic, &[&x], "", true)
+            .left()
+            .unwrap()
+            .into_vector_value();
+    }
+
+    let rint_intrinsic = util::get_or_create_func(ctx.module, "llvm.rint.v2f64", true, &|| {
+        let v2f64_type = ctx.context.f64_type().vec_type(2);
+        (
+            Linkage::ExternalLinkage,
+            v2f64_type.fn_type(&[&v2f64_type], false),
+        )
+    });
+    let rounded = ctx
+        .b
+        .build_call(&rint_intrinsic, &[&x], "", true)
+        .left()
+        .unwrap()
+        .into_vector_value();
+    let converted = ctx
+        .b
+        .build_cast(
+            InstructionOpcode::FPToSI,
+            &rounded,
+            &ctx.context.i32_type().vec_type(2),
+            "",
+        )
+        .into_vector_value();
+    ctx.b.build_shuffle_vector(
+        &converted,
+        &VectorType::const_vector(&[
+            &ctx.context.i32_type().const_int(0, false),
+            &ctx.context.i32_type().const_int(0, false),
+        ]),
+        &VectorType::const_vector(&[
+            &ctx.context.i32_type().const_int(0, false),
+            &ctx.context.i32_type().const_int(1, false),
+            &ctx.context.i32_type().const_int(2, false),
+            &ctx.context.i32_type().const_int(3, false),
+        ]),
+        "",
+    )
+}
+
 // exp10
 pub fn exp10_v2f64(module: &Module) -> FunctionValue {
     util::get_or_create_func(module, "maxim.exp10.v2f64", true, &|| {
@@ -1019,11 +1228,28 @@ fn build_log2_v2f64(module: &Module, target: &TargetProperties) {
                 )
             };
 
-            let r = util::get_vec_spread(ctx.context, 0.410_981_538_279_884_26); // const 0
-            let r = mad(r, 0.402_155_483_170_645_3); // const 1
-            let r = mad(r, 0.577_550_146_270_368_7); // const 2
-            let r = mad(r, 0.961_787_806_001_666_5); // const 3
-            let r = mad(r, 2.885_390_127_834_398_3); // const 4
+            let r = match target.math_accuracy {
+                MathAccuracy::Balanced => {
+                    let r = util::get_vec_spread(ctx.context, 0.410_981_538_279_884_26); // const 0
+                    let r = mad(r, 0.402_155_483_170_645_3); // const 1
+                    let r = mad(r, 0.577_550_146_270_368_7); // const 2
+                    let r = mad(r, 0.961_787_806_001_666_5); // const 3
+                    mad(r, 2.885_390_127_834_398_3) // const 4
+                }
+                accuracy => {
+                    // log2(p) = 2/ln(2) * (y + y^3/3 + y^5/5 + ...), with y in 0..1/3
+                    let term_count: u32 = if accuracy == MathAccuracy::Fast {
+                        3
+                    } else {
+                        16
+                    };
+                    let coefficients: Vec<_> = (0..term_count)
+                        .rev()
+                        .map(|k| 2. / (f64::from(k * 2 + 1) * consts::LN_2))
+                        .collect();
+                    build_horner(&ctx, y2, &coefficients)
+                }
+            };
 
             let r = ctx.b.build_float_mul(r, y, "");
             let ilogb_float =
@@ -1073,6 +1299,81 @@ fn build_log10_v2f64(module: &Module, target: &TargetProperties) {
     )
 }
 
+// an internal helper that approximates atan(s) for s in -1..1, used by atan2k and atan
+fn build_atan_kernel(ctx: &BuilderContext, s: VectorValue) -> VectorValue {
+    match ctx.target.math_accuracy {
+        MathAccuracy::Fast => {
+            let t = ctx.b.build_float_mul(s, s, "t");
+            let r = build_horner(
+                ctx,
+                t,
+                &[0.020_835_1, -0.085_133, 0.180_141, -0.330_299_5, 0.999_866],
+            );
+            ctx.b.build_float_mul(s, r, "")
+        }
+        MathAccuracy::Balanced => {
+            let t = ctx.b.build_float_mul(s, s, "t");
+            let r = build_horner(
+                ctx,
+                t,
+                &[
+                    0.002_823_638_962_581_753_7,
+                    -0.015_956_902_876_496_315,
+                    0.042_504_988_610_744_476,
+                    -0.074_890_092_015_266_42,
+                    0.106_347_933_411_598_2,
+                    -0.142_027_363_181_114_2,
+                    0.199_926_957_488_06,
+                    -0.333_331_018_686_294_56,
+                ],
+            );
+            ctx.b.build_float_add(
+                s,
+                ctx.b
+                    .build_float_mul(s, ctx.b.build_float_mul(t, r, ""), ""),
+                "",
+            )
+        }
+        MathAccuracy::Accurate => {
+            let sqrt_intrinsic = sqrt_v2f64(ctx.module);
+
+            // atan(s) = 2 * atan(s / (1 + sqrt(1 + s^2))), applied twice to bring s into
+            // -tan(pi/16)..tan(pi/16) where the Taylor series converges quickly
+            let halve_angle = |s: VectorValue| {
+                let one = util::get_vec_spread(ctx.context, 1.);
+                let root = ctx
+                    .b
+                    .build_call(
+                        &sqrt_intrinsic,
+                        &[&ctx
+                            .b
+                            .build_float_add(one, ctx.b.build_float_mul(s, s, ""), "")],
+                        "",
+                        true,
+                    )
+                    .left()
+                    .unwrap()
+                    .into_vector_value();
+                ctx.b
+                    .build_float_div(s, ctx.b.build_float_add(one, root, ""), "")
+            };
+            let reduced_s = halve_angle(halve_angle(s));
+
+            // atan(s) = s - s^3/3 + s^5/5 - ..., up to s^21
+            let coefficients: Vec<_> = (0..11)
+                .rev()
+                .map(|k| (-1_f64).powi(k) / f64::from(k * 2 + 1))
+                .collect();
+            let t = ctx.b.build_float_mul(reduced_s, reduced_s, "t");
+            let r = ctx
+                .b
+                .build_float_mul(reduced_s, build_horner(ctx, t, &coefficients), "");
+            ctx.b
+                .build_float_mul(r, util::get_vec_spread(ctx.context, 4.), "")
+        }
+    }
+}
+
 // an internal function to support the various a* trig functions
 fn atan2k_v2f64(module: &Module) -> FunctionValue {
     util::get_or_create_func(module, "maxim.atan2k_v2f64", true, &|| {
@@ -1142,31 +1443,7 @@ fn build_atan2k_v2f64(module: &Module, target: &TargetProperties) {
             );
 
             let s = ctx.b.build_float_div(ry, rx, "s");
-            let t = ctx.b.build_float_mul(s, s, "t");
-
-            let mad = |r: VectorValue, exp: f64| {
-                ctx.b.build_float_add(
-                    ctx.b.build_float_mul(r, t, ""),
-                    util::get_vec_spread(ctx.context, exp),
-                    "",
-                )
-            };
-
-            let r = util::get_vec_spread(ctx.context, 0.002_823_638_962_581_753_7); // const 0
-            let r = mad(r, -0.015_956_902_876_496_315); // const 1
-            let r = mad(r, 0.042_504_988_610_744_476); // const 2
-            let r = mad(r, -0.074_890_092_015_266_42); // const 3
-            let r = mad(r, 0.106_347_933_411_598_2); // const 4
-            let r = mad(r, -0.142_027_363_181_114_2); // const 5
-            let r = mad(r, 0.199_926_957_488_06); // const 6
-            let r = mad(r, -0.333_331_018_686_294_56); // const 7
-
-            let t = ctx.b.build_float_add(
-                ctx.b
-                    .build_float_mul(r, ctx.b.build_float_mul(t, s, ""), ""),
-                s,
-                "",
-            );
+            let t = build_atan_kernel(&ctx, s);
             let t = ctx.b.build_float_add(
                 ctx.b
                     .build_float_mul(q, util::get_vec_spread(ctx.context, consts::FRAC_PI_2), ""),
@@ -1385,31 +1662,7 @@ fn build_atan_v2f64(module: &Module, target: &TargetProperties) {
                     "",
                 )
                 .into_vector_value();
-            let t = ctx.b.build_float_mul(s, s, "");
-
-            let mad = |r: VectorValue, exp: f64| {
-                ctx.b.build_float_add(
-                    ctx.b.build_float_mul(r, t, ""),
-                    util::get_vec_spread(ctx.context, exp),
-                    "",
-                )
-            };
-
-            let r = util::get_vec_spread(ctx.context, 0.002_823_638_962_581_753_7); // const 0
-            let r = mad(r, -0.015_956_902_876_496_315); // const 1
-            let r = mad(r, 0.042_504_988_610_744_476); // const 2
-            let r = mad(r, -0.074_890_092_015_266_42); // const 3
-            let r = mad(r, 0.106_347_933_411_598_2); // const 4
-            let r = mad(r, -0.142_027_363_181_114_2); // const 5
-            let r = mad(r, 0.199_926_957_488_06); // const 6
-            let r = mad(r, -0.333_331_018_686_294_56); // const 7
-
-            let t = ctx.b.build_float_add(
-                s,
-                ctx.b
-                    .build_float_mul(s, ctx.b.build_float_mul(t, r, ""), ""),
-                "",
-            );
+            let t = build_atan_kernel(&ctx, s);
             let t = ctx
                 .b
                 .build_select(
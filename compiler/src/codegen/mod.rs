@@ -1,28 +1,60 @@
+// Only the options are available without the `jit` feature, everything else needs LLVM.
+#[cfg(feature = "jit")]
 pub mod block;
+#[cfg(feature = "jit")]
 mod builder_context;
+#[cfg(feature = "jit")]
 pub mod controls;
+#[cfg(feature = "jit")]
 pub mod converters;
+#[cfg(feature = "jit")]
+pub mod cranelift;
+#[cfg(feature = "jit")]
 pub mod data_analyzer;
+#[cfg(feature = "jit")]
 pub mod editor;
+#[cfg(feature = "jit")]
 pub mod functions;
+#[cfg(feature = "jit")]
 pub mod globals;
+#[cfg(feature = "jit")]
+mod half_band;
+#[cfg(feature = "jit")]
 pub mod intrinsics;
+#[cfg(feature = "jit")]
 pub mod math;
+#[cfg(feature = "jit")]
 mod module_iterator;
+#[cfg(feature = "jit")]
 mod object_cache;
+#[cfg(feature = "jit")]
 mod optimizer;
+#[cfg(feature = "jit")]
 pub mod root;
+#[cfg(feature = "jit")]
 pub mod runtime_lib;
+#[cfg(feature = "jit")]
 pub mod surface;
 mod target_properties;
+#[cfg(feature = "jit")]
 pub mod util;
+#[cfg(feature = "jit")]
 pub mod values;
 
+#[cfg(feature = "jit")]
 pub use self::builder_context::{build_context_function, BuilderContext};
+#[cfg(feature = "jit")]
 pub use self::module_iterator::{ModuleFunctionIterator, ModuleGlobalIterator};
+#[cfg(feature = "jit")]
 pub use self::object_cache::ObjectCache;
+#[cfg(feature = "jit")]
 pub use self::optimizer::Optimizer;
-pub use self::target_properties::{OptimizationLevel, TargetProperties};
+#[cfg(feature = "jit")]
+pub use self::target_properties::TargetProperties;
+pub use self::target_properties::{
+    CodegenBackend, DenormalMode, MathAccuracy, OptimizationLevel, StoragePrecision,
+    DEFAULT_CONTROL_RATE_DIVISOR, DEFAULT_MAX_DELAY_SECONDS, DENORMAL_OFFSET,
+};
 
 use std::fmt;
 
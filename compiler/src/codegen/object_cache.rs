@@ -14,4 +14,16 @@ pub trait ObjectCache {
     fn block_mir(&self, id: BlockRef) -> Option<&Block>;
 
     fn block_layout(&self, id: BlockRef) -> Option<&data_analyzer::BlockLayout>;
+
+    /// A hash of everything that goes into a surface's code. It's part of the names of the
+    /// surface's functions, so a recompiled surface can be loaded while the old one is still
+    /// running, and so cached objects link against the code they were compiled with.
+    fn surface_code_key(&self, _id: SurfaceRef) -> u64 {
+        0
+    }
+
+    /// A hash of everything that goes into a block's code, see `surface_code_key`.
+    fn block_code_key(&self, _id: BlockRef) -> u64 {
+        0
+    }
 }
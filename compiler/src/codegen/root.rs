@@ -1,16 +1,23 @@
 use crate::codegen::data_analyzer::{PointerSource, PointerSourceAggregateType};
-use crate::codegen::values::remap_type;
+use crate::codegen::values::{
+    remap_storage_type, remap_type, MidiEventValue, MidiQueueValue, MidiValue,
+};
 use crate::codegen::{
-    build_context_function, surface, util, BuilderContext, LifecycleFunc, ObjectCache,
+    build_context_function, intrinsics, surface, util, BuilderContext, LifecycleFunc, ObjectCache,
 };
-use crate::mir::{Root, SurfaceRef};
+use crate::mir::{Root, SurfaceRef, VarType};
+use inkwell::builder::Builder;
 use inkwell::context::Context;
 use inkwell::module::{Linkage, Module};
-use inkwell::types::BasicType;
+use inkwell::types::{BasicType, StructType};
 use inkwell::values::{BasicValue, BasicValueEnum, GlobalValue, IntValue, PointerValue};
-use inkwell::AddressSpace;
+use inkwell::{AddressSpace, IntPredicate};
 use std::iter;
 
+/// The maximum number of frames that can be processed in a single call to a block update function.
+/// Larger blocks must be split up by the host.
+pub const MAX_BLOCK_FRAMES: u32 = 1024;
+
 fn get_gep_indices(context: &Context, path: impl IntoIterator<Item = u64>) -> Vec<IntValue> {
     iter::once(0)
         .chain(path)
@@ -74,6 +81,67 @@ pub fn remap_pointer_source(
     }
 }
 
+/// Stores the pointers a pointer source refers to into `dest`. This is the same as storing the
+/// value of `remap_pointer_source`, for when the data doesn't have a constant address.
+pub fn build_store_pointer_source(
+    builder: &Builder,
+    context: &Context,
+    dest: PointerValue,
+    src: &PointerSource,
+    initialized: PointerValue,
+    scratch: PointerValue,
+    sockets: PointerValue,
+) {
+    let (base_ptr, path): (PointerValue, Vec<u64>) = match src {
+        PointerSource::Initialized(path) => {
+            (initialized, path.iter().map(|itm| *itm as u64).collect())
+        }
+        PointerSource::Scratch(path) => (
+            scratch,
+            iter::once(0)
+                .chain(path.iter().map(|itm| *itm as u64))
+                .collect(),
+        ),
+        PointerSource::Shared(path) => (
+            scratch,
+            iter::once(1)
+                .chain(path.iter().map(|itm| *itm as u64))
+                .collect(),
+        ),
+        PointerSource::Socket(socket, path) => (
+            sockets,
+            iter::once(*socket as u64)
+                .chain(path.iter().map(|itm| *itm as u64))
+                .collect(),
+        ),
+        PointerSource::Aggregate(_, ref sub_sources) => {
+            for (index, sub_source) in sub_sources.iter().enumerate() {
+                let sub_dest = unsafe {
+                    builder.build_in_bounds_gep(
+                        &dest,
+                        &get_gep_indices(context, iter::once(index as u64)),
+                        "",
+                    )
+                };
+                build_store_pointer_source(
+                    builder,
+                    context,
+                    sub_dest,
+                    sub_source,
+                    initialized,
+                    scratch,
+                    sockets,
+                );
+            }
+            return;
+        }
+    };
+
+    let ptr =
+        unsafe { builder.build_in_bounds_gep(&base_ptr, &get_gep_indices(context, path), "") };
+    builder.build_store(&dest, &ptr);
+}
+
 pub fn build_initialized_global(
     module: &Module,
     cache: &ObjectCache,
@@ -87,22 +155,36 @@ pub fn build_initialized_global(
     global
 }
 
+/// The type of the scratch global, which holds the scratch and shared data of the root surface.
+pub fn get_scratch_type(context: &Context, cache: &ObjectCache, surface: SurfaceRef) -> StructType {
+    let layout = cache.surface_layout(surface).unwrap();
+    context.struct_type(&[&layout.scratch_struct, &layout.shared_struct], false)
+}
+
 pub fn build_scratch_global(
     module: &Module,
     cache: &ObjectCache,
     surface: SurfaceRef,
     name: &str,
 ) -> GlobalValue {
-    let layout = cache.surface_layout(surface).unwrap();
-    let virtual_scratch = module
-        .get_context()
-        .struct_type(&[&layout.scratch_struct, &layout.shared_struct], false);
+    let virtual_scratch = get_scratch_type(&module.get_context(), cache, surface);
     let global = util::get_or_create_global(module, name, &virtual_scratch);
     global.set_initializer(&virtual_scratch.const_null());
     //global.set_section("maxim.scratch");
     global
 }
 
+/// The type of the sockets global, which holds the value of each root socket.
+pub fn get_sockets_type(context: &Context, cache: &ObjectCache, root: &Root) -> StructType {
+    let socket_types: Vec<_> = root
+        .sockets
+        .iter()
+        .map(|vartype| remap_storage_type(context, cache.target(), vartype))
+        .collect();
+    let socket_type_refs: Vec<_> = socket_types.iter().map(|ty| ty as &BasicType).collect();
+    context.struct_type(&socket_type_refs, false)
+}
+
 pub struct SocketsGlobal {
     pub sockets: GlobalValue,
     pub socket_ptrs: GlobalValue,
@@ -110,24 +192,19 @@ pub struct SocketsGlobal {
 
 pub fn build_sockets_global(
     module: &Module,
+    cache: &ObjectCache,
     root: &Root,
     sockets_name: &str,
     pointers_name: &str,
 ) -> SocketsGlobal {
     let context = module.get_context();
-    let struct_types: Vec<_> = root
-        .sockets
-        .iter()
-        .map(|vartype| remap_type(&context, vartype))
-        .collect();
-    let sockets_type_refs: Vec<_> = struct_types.iter().map(|ty| ty as &BasicType).collect();
-    let sockets_struct_type = context.struct_type(&sockets_type_refs, false);
+    let sockets_struct_type = get_sockets_type(&context, cache, root);
     let sockets_global = util::get_or_create_global(module, sockets_name, &sockets_struct_type);
     sockets_global.set_initializer(&sockets_struct_type.const_null());
     //sockets_global.set_section("maxim.sockets");
 
     let void_ptr_ty = context.i8_type().ptr_type(AddressSpace::Generic);
-    let array_itms: Vec<_> = (0..struct_types.len())
+    let array_itms: Vec<_> = (0..root.sockets.len())
         .map(|index| unsafe {
             sockets_global
                 .as_pointer_value()
@@ -151,6 +228,90 @@ pub fn build_sockets_global(
     }
 }
 
+/// The type of the portal buffers global, which holds a block of values for each root socket.
+pub fn get_portal_buffers_type(context: &Context, cache: &ObjectCache, root: &Root) -> StructType {
+    let buffer_types: Vec<_> = root
+        .sockets
+        .iter()
+        .map(|vartype| {
+            remap_storage_type(context, cache.target(), vartype).array_type(MAX_BLOCK_FRAMES)
+        })
+        .collect();
+    let buffer_type_refs: Vec<_> = buffer_types.iter().map(|ty| ty as &BasicType).collect();
+    context.struct_type(&buffer_type_refs, false)
+}
+
+pub struct PortalBuffersGlobal {
+    pub buffers: GlobalValue,
+    pub buffer_ptrs: GlobalValue,
+}
+
+/// Builds a buffer of `MAX_BLOCK_FRAMES` values for each root socket, which the block update
+/// function reads and writes one frame at a time.
+pub fn build_portal_buffers_global(
+    module: &Module,
+    cache: &ObjectCache,
+    root: &Root,
+    buffers_name: &str,
+    pointers_name: &str,
+) -> PortalBuffersGlobal {
+    let context = module.get_context();
+    let buffers_struct_type = get_portal_buffers_type(&context, cache, root);
+    let buffers_global = util::get_or_create_global(module, buffers_name, &buffers_struct_type);
+    buffers_global.set_initializer(&buffers_struct_type.const_null());
+
+    let void_ptr_ty = context.i8_type().ptr_type(AddressSpace::Generic);
+    let array_itms: Vec<_> = (0..root.sockets.len())
+        .map(|index| unsafe {
+            buffers_global
+                .as_pointer_value()
+                .const_in_bounds_gep(&[
+                    context.i64_type().const_int(0, false),
+                    context.i32_type().const_int(index as u64, false),
+                ])
+                .const_cast(&void_ptr_ty)
+        })
+        .collect();
+    let pointers_arr = void_ptr_ty.const_array(&array_itms);
+    let pointers_global =
+        util::get_or_create_global(module, pointers_name, &pointers_arr.get_type());
+    pointers_global.set_constant(true);
+    pointers_global.set_initializer(&pointers_arr);
+
+    PortalBuffersGlobal {
+        buffers: buffers_global,
+        buffer_ptrs: pointers_global,
+    }
+}
+
+/// The indices of the root sockets that hold MIDI, which are the portals with a MIDI queue.
+pub fn get_midi_portals(root: &Root) -> Vec<usize> {
+    root.sockets
+        .iter()
+        .enumerate()
+        .filter(|(_, vartype)| **vartype == VarType::Midi)
+        .map(|(index, _)| index)
+        .collect()
+}
+
+/// The type of the MIDI queues global, which holds a queue for each MIDI portal in the order
+/// they're returned by `get_midi_portals`.
+pub fn get_midi_queues_type(context: &Context, root: &Root) -> StructType {
+    let queue_type = MidiQueueValue::get_type(context);
+    let queue_types: Vec<_> = get_midi_portals(root)
+        .iter()
+        .map(|_| &queue_type as &BasicType)
+        .collect();
+    context.struct_type(&queue_types, false)
+}
+
+pub fn build_midi_queues_global(module: &Module, root: &Root, name: &str) -> GlobalValue {
+    let queues_type = get_midi_queues_type(&module.get_context(), root);
+    let global = util::get_or_create_global(module, name, &queues_type);
+    global.set_initializer(&queues_type.const_null());
+    global
+}
+
 pub fn build_pointers_global(
     module: &Module,
     cache: &ObjectCache,
@@ -178,6 +339,57 @@ pub fn build_pointers_global(
     global
 }
 
+/// The flush-to-zero (bit 15) and denormals-are-zero (bit 6) flags in MXCSR.
+const MXCSR_FTZ_DAZ: u64 = 0x8040;
+
+/// Sets the flush-to-zero and denormals-are-zero flags, if the target uses them. Returns a pointer
+/// to the previous MXCSR value, which must be passed to `build_restore_mxcsr` before returning.
+pub fn build_enable_flush_to_zero(ctx: &BuilderContext) -> Option<PointerValue> {
+    if !ctx.target.uses_flush_to_zero() {
+        return None;
+    }
+
+    let i8_ptr_type = ctx.context.i8_type().ptr_type(AddressSpace::Generic);
+    let saved_ptr = ctx
+        .allocb
+        .build_alloca(&ctx.context.i32_type(), "mxcsr.saved.ptr");
+    let new_ptr = ctx
+        .allocb
+        .build_alloca(&ctx.context.i32_type(), "mxcsr.new.ptr");
+    ctx.b.build_call(
+        &intrinsics::stmxcsr(ctx.module),
+        &[&ctx.b.build_pointer_cast(saved_ptr, i8_ptr_type, "")],
+        "",
+        false,
+    );
+    let saved = ctx.b.build_load(&saved_ptr, "mxcsr.saved").into_int_value();
+    let flushing = ctx.b.build_or(
+        saved,
+        ctx.context.i32_type().const_int(MXCSR_FTZ_DAZ, false),
+        "mxcsr.new",
+    );
+    ctx.b.build_store(&new_ptr, &flushing);
+    ctx.b.build_call(
+        &intrinsics::ldmxcsr(ctx.module),
+        &[&ctx.b.build_pointer_cast(new_ptr, i8_ptr_type, "")],
+        "",
+        false,
+    );
+    Some(saved_ptr)
+}
+
+pub fn build_restore_mxcsr(ctx: &BuilderContext, saved_ptr: Option<PointerValue>) {
+    if let Some(saved_ptr) = saved_ptr {
+        let i8_ptr_type = ctx.context.i8_type().ptr_type(AddressSpace::Generic);
+        ctx.b.build_call(
+            &intrinsics::ldmxcsr(ctx.module),
+            &[&ctx.b.build_pointer_cast(saved_ptr, i8_ptr_type, "")],
+            "",
+            false,
+        );
+    }
+}
+
 pub fn build_lifecycle_func(
     module: &Module,
     cache: &ObjectCache,
@@ -193,7 +405,13 @@ pub fn build_lifecycle_func(
         )
     });
     build_context_function(module, func, cache.target(), &|ctx: BuilderContext| {
+        let saved_mxcsr = if lifecycle == LifecycleFunc::Update {
+            build_enable_flush_to_zero(&ctx)
+        } else {
+            None
+        };
         surface::build_lifecycle_call(module, cache, ctx.b, surface, lifecycle, pointers);
+        build_restore_mxcsr(&ctx, saved_mxcsr);
         ctx.b.build_return(None);
     });
 }
@@ -233,6 +451,241 @@ pub fn build_funcs(
     );
 }
 
+/// Builds a function that runs the update lifecycle for a single frame, delivering the events
+/// due in the MIDI queues onto their portals first. The queues are then moved along by a frame,
+/// so hosts that update a frame at a time can queue events like block hosts do.
+pub fn build_queued_update_func(
+    module: &Module,
+    cache: &ObjectCache,
+    surface: SurfaceRef,
+    name: &str,
+    pointers: PointerValue,
+    sockets: PointerValue,
+    midi_queues: PointerValue,
+    root: &Root,
+) {
+    let func = util::get_or_create_func(module, name, false, &|| {
+        (
+            Linkage::ExternalLinkage,
+            module.get_context().void_type().fn_type(&[], false),
+        )
+    });
+    build_context_function(module, func, cache.target(), &|ctx: BuilderContext| {
+        let saved_mxcsr = build_enable_flush_to_zero(&ctx);
+        let const_zero = ctx.context.i32_type().const_int(0, false);
+        let queues: Vec<_> = get_midi_portals(root)
+            .into_iter()
+            .enumerate()
+            .map(|(queue_index, portal_index)| {
+                let queue = MidiQueueValue::new(unsafe {
+                    ctx.b
+                        .build_struct_gep(&midi_queues, queue_index as u32, "midiqueue")
+                });
+                let portal_index = ctx.context.i32_type().const_int(portal_index as u64, false);
+                let socket_ptr = unsafe {
+                    ctx.b
+                        .build_in_bounds_gep(&sockets, &[const_zero, portal_index], "portal")
+                };
+                queue.deliver(ctx.b, module, const_zero, &MidiValue::new(socket_ptr));
+                queue
+            })
+            .collect();
+        surface::build_lifecycle_call(
+            module,
+            cache,
+            ctx.b,
+            surface,
+            LifecycleFunc::Update,
+            pointers,
+        );
+        let const_one = ctx.context.i32_type().const_int(1, false);
+        for queue in &queues {
+            queue.advance(ctx.b, module, const_one);
+        }
+        build_restore_mxcsr(&ctx, saved_mxcsr);
+        ctx.b.build_return(None);
+    });
+}
+
+/// Builds a function that runs the update lifecycle for a number of frames. Before each frame the
+/// value in each portal buffer is copied into its portal, and after the update the portal is
+/// copied back into the buffer, so inputs and outputs can share the same buffers. Only the events
+/// in use are copied for MIDI portals, which usually means just the count.
+///
+/// Events in the MIDI queues are pushed onto their portals on the frame they're due, after the
+/// buffer is copied in. They end up in the buffer along with the events the host wrote there, so
+/// like those they're still there after the block until the host clears them.
+///
+/// The buffers only hold `MAX_BLOCK_FRAMES` frames, so at most that many are run. The function
+/// returns the number of frames it ran, and hosts with larger blocks call it again for the rest.
+///
+/// The surface's update has to be defined in the same module for it to be inlined into the loop,
+/// so loads of controls that aren't written per-sample are hoisted out of it by the optimizer.
+pub fn build_block_update_func(
+    module: &Module,
+    cache: &ObjectCache,
+    surface: SurfaceRef,
+    name: &str,
+    pointers: PointerValue,
+    sockets: PointerValue,
+    buffers: PointerValue,
+    midi_queues: PointerValue,
+    root: &Root,
+) {
+    let func = util::get_or_create_func(module, name, false, &|| {
+        let context = module.get_context();
+        (
+            Linkage::ExternalLinkage,
+            context.i32_type().fn_type(&[&context.i32_type()], false),
+        )
+    });
+    build_context_function(module, func, cache.target(), &|mut ctx: BuilderContext| {
+        let frames = ctx.func.get_nth_param(0).unwrap().into_int_value();
+        let frame_count = build_block_update(
+            &mut ctx,
+            cache,
+            surface,
+            frames,
+            pointers,
+            sockets,
+            buffers,
+            midi_queues,
+            root,
+        );
+        ctx.b.build_return(Some(&frame_count));
+    });
+}
+
+/// Builds the loop of a block update function into the current function, returning the number
+/// of frames it runs.
+pub fn build_block_update(
+    ctx: &mut BuilderContext,
+    cache: &ObjectCache,
+    surface: SurfaceRef,
+    frames: IntValue,
+    pointers: PointerValue,
+    sockets: PointerValue,
+    buffers: PointerValue,
+    midi_queues: PointerValue,
+    root: &Root,
+) -> IntValue {
+    let module = ctx.module;
+    let saved_mxcsr = build_enable_flush_to_zero(ctx);
+    let max_frames = ctx
+        .context
+        .i32_type()
+        .const_int(u64::from(MAX_BLOCK_FRAMES), false);
+    let frame_count = ctx
+        .b
+        .build_select(
+            ctx.b
+                .build_int_compare(IntPredicate::UGT, frames, max_frames, ""),
+            max_frames,
+            frames,
+            "framecount",
+        )
+        .into_int_value();
+
+    // the queues are used after the loop as well as in it, so they're found before it
+    let midi_portals = get_midi_portals(root);
+    let queues: Vec<_> = (0..midi_portals.len())
+        .map(|queue_index| {
+            MidiQueueValue::new(unsafe {
+                ctx.b
+                    .build_struct_gep(&midi_queues, queue_index as u32, "midiqueue")
+            })
+        })
+        .collect();
+
+    let index_ptr = ctx
+        .allocb
+        .build_alloca(&ctx.context.i32_type(), "frameindex.ptr");
+    ctx.b
+        .build_store(&index_ptr, &ctx.context.i32_type().const_int(0, false));
+
+    let check_block = ctx.context.append_basic_block(&ctx.func, "frame.check");
+    let run_block = ctx.context.append_basic_block(&ctx.func, "frame.run");
+    let end_block = ctx.context.append_basic_block(&ctx.func, "frame.end");
+
+    ctx.b.build_unconditional_branch(&check_block);
+    ctx.b.position_at_end(&check_block);
+
+    let current_index = ctx.b.build_load(&index_ptr, "frameindex").into_int_value();
+    let can_continue_loop =
+        ctx.b
+            .build_int_compare(IntPredicate::ULT, current_index, frame_count, "cancontinue");
+    ctx.b
+        .build_conditional_branch(&can_continue_loop, &run_block, &end_block);
+    ctx.b.position_at_end(&run_block);
+
+    let const_zero = ctx.context.i32_type().const_int(0, false);
+    let portal_ptrs: Vec<_> = (0..root.sockets.len())
+        .map(|portal_index| {
+            let portal_index = ctx.context.i32_type().const_int(portal_index as u64, false);
+            let socket_ptr = unsafe {
+                ctx.b
+                    .build_in_bounds_gep(&sockets, &[const_zero, portal_index], "portal")
+            };
+            let buffer_ptr = unsafe {
+                ctx.b.build_in_bounds_gep(
+                    &buffers,
+                    &[const_zero, portal_index, current_index],
+                    "portalbuffer",
+                )
+            };
+            (socket_ptr, buffer_ptr)
+        })
+        .collect();
+
+    for (&(socket_ptr, buffer_ptr), vartype) in portal_ptrs.iter().zip(root.sockets.iter()) {
+        build_portal_copy(ctx.b, module, vartype, buffer_ptr, socket_ptr);
+    }
+    for (queue, &portal_index) in queues.iter().zip(midi_portals.iter()) {
+        let midi = MidiValue::new(portal_ptrs[portal_index].0);
+        queue.deliver(ctx.b, module, current_index, &midi);
+    }
+    surface::build_lifecycle_call(
+        module,
+        cache,
+        ctx.b,
+        surface,
+        LifecycleFunc::Update,
+        pointers,
+    );
+    for (&(socket_ptr, buffer_ptr), vartype) in portal_ptrs.iter().zip(root.sockets.iter()) {
+        build_portal_copy(ctx.b, module, vartype, socket_ptr, buffer_ptr);
+    }
+
+    let next_index = ctx.b.build_int_nuw_add(
+        current_index,
+        ctx.context.i32_type().const_int(1, false),
+        "nextindex",
+    );
+    ctx.b.build_store(&index_ptr, &next_index);
+    ctx.b.build_unconditional_branch(&check_block);
+
+    ctx.b.position_at_end(&end_block);
+    for queue in &queues {
+        queue.advance(ctx.b, module, frame_count);
+    }
+    build_restore_mxcsr(ctx, saved_mxcsr);
+    frame_count
+}
+
+fn build_portal_copy(
+    builder: &mut Builder,
+    module: &Module,
+    vartype: &VarType,
+    src: PointerValue,
+    dest: PointerValue,
+) {
+    if *vartype == VarType::Midi {
+        MidiValue::new(src).copy_events_to(builder, module, &MidiValue::new(dest));
+    } else {
+        util::copy_ptr(builder, module, src, dest);
+    }
+}
+
 pub fn build_socket_accessor_func(
     module: &Module,
     cache: &ObjectCache,
@@ -264,3 +717,154 @@ pub fn build_socket_accessor_func(
         ctx.b.build_return(Some(&socket_ptr));
     });
 }
+
+/// Builds a function that pushes an event onto the MIDI value of a portal. Like the portal itself,
+/// the events stay there until the host clears them, which it usually does after each update.
+pub fn build_midi_push_func(
+    module: &Module,
+    cache: &ObjectCache,
+    func_name: &str,
+    socket_ptrs: PointerValue,
+) {
+    let context = module.get_context();
+    let func = util::get_or_create_func(module, func_name, false, &|| {
+        (
+            Linkage::ExternalLinkage,
+            context.void_type().fn_type(
+                &[
+                    &context.i32_type(),
+                    &context.i8_type(),
+                    &context.i8_type(),
+                    &context.i8_type(),
+                    &context.i8_type(),
+                ],
+                false,
+            ),
+        )
+    });
+    build_context_function(module, func, cache.target(), &|mut ctx: BuilderContext| {
+        let socket_ptr_ptr = unsafe {
+            ctx.b.build_in_bounds_gep(
+                &socket_ptrs,
+                &[
+                    ctx.context.i32_type().const_int(0, false),
+                    ctx.func.get_nth_param(0).unwrap().into_int_value(),
+                ],
+                "",
+            )
+        };
+        let socket_ptr = ctx.b.build_load(&socket_ptr_ptr, "").into_pointer_value();
+        build_midi_push(&mut ctx, socket_ptr, 1);
+        ctx.b.build_return(None);
+    });
+}
+
+/// Builds a function that queues an event on the MIDI queue of a portal, to be delivered a number
+/// of frames into the next block update. Events for portals that don't hold MIDI are ignored.
+pub fn build_midi_queue_func(
+    module: &Module,
+    cache: &ObjectCache,
+    func_name: &str,
+    root: &Root,
+    midi_queues: PointerValue,
+) {
+    let context = module.get_context();
+    let func = util::get_or_create_func(module, func_name, false, &|| {
+        (
+            Linkage::ExternalLinkage,
+            context.void_type().fn_type(
+                &[
+                    &context.i32_type(),
+                    &context.i32_type(),
+                    &context.i8_type(),
+                    &context.i8_type(),
+                    &context.i8_type(),
+                    &context.i8_type(),
+                ],
+                false,
+            ),
+        )
+    });
+    build_context_function(module, func, cache.target(), &|mut ctx: BuilderContext| {
+        build_midi_queue(&mut ctx, root, midi_queues, 0);
+        ctx.b.build_return(None);
+    });
+}
+
+/// Builds the body of a function that queues an event on the MIDI queue of a portal. The portal,
+/// offset and the event's name, channel, note and param are the function's parameters, starting at
+/// `first_param`.
+pub fn build_midi_queue(
+    ctx: &mut BuilderContext,
+    root: &Root,
+    midi_queues: PointerValue,
+    first_param: u32,
+) {
+    let func = ctx.func;
+    let param = |index: u32| {
+        func.get_nth_param(first_param + index)
+            .unwrap()
+            .into_int_value()
+    };
+    let portal = param(0);
+    let offset = param(1);
+
+    let event = MidiEventValue::new(
+        ctx.allocb
+            .build_alloca(&MidiEventValue::get_type(ctx.context), "event"),
+    );
+    event.set_name(ctx.b, param(2));
+    event.set_channel(ctx.b, param(3));
+    event.set_note(ctx.b, param(4));
+    event.set_param(ctx.b, param(5));
+
+    let mut case_builder = ctx.context.create_builder();
+    let end_block = ctx.context.append_basic_block(&ctx.func, "queue.end");
+    let cases: Vec<_> = get_midi_portals(root)
+        .into_iter()
+        .enumerate()
+        .map(|(queue_index, portal_index)| {
+            let queue_block = ctx
+                .context
+                .append_basic_block(&ctx.func, &format!("queue.{}", portal_index));
+            case_builder.position_at_end(&queue_block);
+            let queue = MidiQueueValue::new(unsafe {
+                case_builder.build_struct_gep(&midi_queues, queue_index as u32, "midiqueue")
+            });
+            queue.push_event(&mut case_builder, ctx.module, offset, &event);
+            case_builder.build_unconditional_branch(&end_block);
+
+            let portal_const = ctx.context.i32_type().const_int(portal_index as u64, false);
+            (portal_const, queue_block)
+        })
+        .collect();
+    let switch_refs: Vec<_> = cases.iter().map(|&(ref a, ref b)| (a, b)).collect();
+    ctx.b.build_switch(&portal, &end_block, &switch_refs);
+    ctx.b.position_at_end(&end_block);
+}
+
+/// Pushes an event onto the MIDI value `socket_ptr` points to. The event's name, channel, note and
+/// param are the function's parameters, starting at `first_event_param`.
+pub fn build_midi_push(ctx: &mut BuilderContext, socket_ptr: PointerValue, first_event_param: u32) {
+    let midi = MidiValue::new(ctx.b.build_pointer_cast(
+        socket_ptr,
+        MidiValue::get_type(ctx.context).ptr_type(AddressSpace::Generic),
+        "midi",
+    ));
+
+    let event = MidiEventValue::new(
+        ctx.allocb
+            .build_alloca(&MidiEventValue::get_type(ctx.context), "event"),
+    );
+    let func = ctx.func;
+    let event_param = |index: u32| {
+        func.get_nth_param(first_event_param + index)
+            .unwrap()
+            .into_int_value()
+    };
+    event.set_name(ctx.b, event_param(0));
+    event.set_channel(ctx.b, event_param(1));
+    event.set_note(ctx.b, event_param(2));
+    event.set_param(ctx.b, event_param(3));
+    midi.push_event(ctx.b, ctx.module, &event);
+}
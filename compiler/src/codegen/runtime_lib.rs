@@ -8,4 +8,5 @@ pub fn codegen_lib(module: &Module, target: &TargetProperties) {
     intrinsics::build_intrinsics(module, target);
     math::build_math_functions(module, target);
     values::MidiValue::initialize(module, target);
+    values::MidiQueueValue::initialize(module, target);
 }
@@ -1,28 +1,49 @@
 use crate::codegen::{
-    block, build_context_function, util, values, BuilderContext, LifecycleFunc, ObjectCache,
+    block, build_context_function, data_analyzer, globals, half_band, intrinsics, util, values,
+    BuilderContext, LifecycleFunc, ObjectCache,
 };
-use crate::mir::{Node, NodeData, Surface, SurfaceRef};
+use crate::mir::{Node, NodeData, OversampleFactor, Surface, SurfaceRef, UpdateRate, ValueGroup};
 use inkwell::attribute::AttrKind;
 use inkwell::builder::Builder;
 use inkwell::module::{Linkage, Module};
-use inkwell::values::{FunctionValue, PointerValue};
+use inkwell::types::VectorType;
+use inkwell::values::{FunctionValue, InstructionOpcode, IntValue, PointerValue};
 use inkwell::{AddressSpace, IntPredicate};
 
+pub fn get_lifecycle_func_name(
+    cache: &ObjectCache,
+    surface: SurfaceRef,
+    lifecycle: LifecycleFunc,
+) -> String {
+    format!(
+        "maxim.surface.{}.{}.{}",
+        surface,
+        cache.surface_code_key(surface),
+        lifecycle
+    )
+}
+
 fn get_lifecycle_func(
     module: &Module,
     cache: &ObjectCache,
     surface: SurfaceRef,
     lifecycle: LifecycleFunc,
 ) -> FunctionValue {
-    let func_name = format!("maxim.surface.{}.{}", surface, lifecycle);
-    let func = util::get_or_create_func(module, &func_name, true, &|| {
+    let func_name = get_lifecycle_func_name(cache, surface, lifecycle);
+
+    // surface functions use the C calling convention, as they can be built by either backend
+    let func = util::get_or_create_func(module, &func_name, false, &|| {
         let context = module.get_context();
         let layout = cache.surface_layout(surface).unwrap();
         (
             Linkage::ExternalLinkage,
-            context.void_type().fn_type(
-                &[&layout.pointer_struct.ptr_type(AddressSpace::Generic)],
-                false,
+            globals::add_instance_param(
+                module,
+                cache.target(),
+                context.void_type().fn_type(
+                    &[&layout.pointer_struct.ptr_type(AddressSpace::Generic)],
+                    false,
+                ),
             ),
         )
     });
@@ -30,6 +51,87 @@ fn get_lifecycle_func(
     func
 }
 
+/// Builds a loop calling a lifecycle function on each voice of an extracted surface, skipping
+/// voices that aren't active.
+fn build_voice_loop(
+    ctx: &mut BuilderContext,
+    cache: &ObjectCache,
+    surface: SurfaceRef,
+    lifecycle: LifecycleFunc,
+    voice_pointers: PointerValue,
+    valid_bitmap: Option<IntValue>,
+) {
+    // build a for loop to iterate over each instance
+    let index_ptr = ctx
+        .allocb
+        .build_alloca(&ctx.context.i8_type(), "voiceindex.ptr");
+    ctx.b
+        .build_store(&index_ptr, &ctx.context.i8_type().const_int(0, false));
+
+    let check_block = ctx.context.append_basic_block(&ctx.func, "voice.check");
+    let check_active_block = ctx
+        .context
+        .append_basic_block(&ctx.func, "voice.checkactive");
+    let run_block = ctx.context.append_basic_block(&ctx.func, "voice.run");
+    let end_block = ctx.context.append_basic_block(&ctx.func, "voice.end");
+
+    ctx.b.build_unconditional_branch(&check_block);
+    ctx.b.position_at_end(&check_block);
+
+    let current_index = ctx.b.build_load(&index_ptr, "voiceindex").into_int_value();
+    let iter_limit = ctx
+        .context
+        .i8_type()
+        .const_int(u64::from(values::ARRAY_CAPACITY), false);
+    let can_continue_loop =
+        ctx.b
+            .build_int_compare(IntPredicate::ULT, current_index, iter_limit, "cancontinue");
+
+    ctx.b
+        .build_conditional_branch(&can_continue_loop, &check_active_block, &end_block);
+    ctx.b.position_at_end(&check_active_block);
+
+    // increment the stored value
+    let next_index = ctx.b.build_int_nuw_add(
+        current_index,
+        ctx.context.i8_type().const_int(1, false),
+        "nextindex",
+    );
+    ctx.b.build_store(&index_ptr, &next_index);
+
+    let index_32 = ctx
+        .b
+        .build_int_z_extend(current_index, ctx.context.i32_type(), "");
+    if let Some(active_bitmap) = valid_bitmap {
+        // check if this iteration is active according to the bitmap
+        let active_bit = util::get_bit(ctx.b, active_bitmap, index_32);
+        ctx.b
+            .build_conditional_branch(&active_bit, &run_block, &check_block);
+    } else {
+        ctx.b.build_unconditional_branch(&run_block);
+    }
+
+    ctx.b.position_at_end(&run_block);
+
+    let const_zero = ctx.context.i32_type().const_int(0, false);
+    let voice_pointers_ptr = unsafe {
+        ctx.b
+            .build_in_bounds_gep(&voice_pointers, &[const_zero, index_32], "pointersptr")
+    };
+
+    build_lifecycle_call(
+        ctx.module,
+        cache,
+        ctx.b,
+        surface,
+        lifecycle,
+        voice_pointers_ptr,
+    );
+
+    ctx.b.build_unconditional_branch(&check_block);
+    ctx.b.position_at_end(&end_block);
+}
+
 fn build_node_call(
     ctx: &mut BuilderContext,
     cache: &ObjectCache,
@@ -114,79 +216,15 @@ fn build_node_call(
                 None
             };
 
-            // build a for loop to iterate over each instance
-            let index_ptr = ctx
-                .allocb
-                .build_alloca(&ctx.context.i8_type(), "voiceindex.ptr");
-            ctx.b
-                .build_store(&index_ptr, &ctx.context.i8_type().const_int(0, false));
-
-            let check_block = ctx.context.append_basic_block(&ctx.func, "voice.check");
-            let check_active_block = ctx
-                .context
-                .append_basic_block(&ctx.func, "voice.checkactive");
-            let run_block = ctx.context.append_basic_block(&ctx.func, "voice.run");
-            let end_block = ctx.context.append_basic_block(&ctx.func, "voice.end");
-
-            ctx.b.build_unconditional_branch(&check_block);
-            ctx.b.position_at_end(&check_block);
-
-            let current_index = ctx.b.build_load(&index_ptr, "voiceindex").into_int_value();
-            let iter_limit = ctx
-                .context
-                .i8_type()
-                .const_int(u64::from(values::ARRAY_CAPACITY), false);
-            let can_continue_loop = ctx.b.build_int_compare(
-                IntPredicate::ULT,
-                current_index,
-                iter_limit,
-                "cancontinue",
-            );
-
-            ctx.b
-                .build_conditional_branch(&can_continue_loop, &check_active_block, &end_block);
-            ctx.b.position_at_end(&check_active_block);
-
-            // increment the stored value
-            let next_index = ctx.b.build_int_nuw_add(
-                current_index,
-                ctx.context.i8_type().const_int(1, false),
-                "nextindex",
-            );
-            ctx.b.build_store(&index_ptr, &next_index);
-
-            let index_32 = ctx
-                .b
-                .build_int_z_extend(current_index, ctx.context.i32_type(), "");
-            if let Some(active_bitmap) = valid_bitmap {
-                // check if this iteration is active according to the bitmap
-                let active_bit = util::get_bit(ctx.b, active_bitmap, index_32);
-                ctx.b
-                    .build_conditional_branch(&active_bit, &run_block, &check_block);
-            } else {
-                ctx.b.build_unconditional_branch(&run_block);
-            }
-
-            ctx.b.position_at_end(&run_block);
-
-            let const_zero = ctx.context.i32_type().const_int(0, false);
-            let voice_pointers_ptr = unsafe {
-                ctx.b
-                    .build_in_bounds_gep(&voice_pointers, &[const_zero, index_32], "pointersptr")
-            };
-
-            build_lifecycle_call(
-                ctx.module,
+            build_voice_loop(
+                ctx,
                 cache,
-                ctx.b,
                 *surface_id,
                 lifecycle,
-                voice_pointers_ptr,
+                voice_pointers,
+                valid_bitmap,
             );
 
-            ctx.b.build_unconditional_branch(&check_block);
-            ctx.b.position_at_end(&end_block);
-
             // set the bitmaps of all output arrays to be this input
             if lifecycle == LifecycleFunc::Update {
                 let active_bitmap = if let Some(active_bitmap) = valid_bitmap {
@@ -215,7 +253,608 @@ fn build_node_call(
                 }
             }
         }
+        NodeData::UnitDelay => {
+            // Unit delays are ordered after every other node, so copying here makes the value
+            // written this sample visible to readers of the delayed group on the next one.
+            if lifecycle == LifecycleFunc::Update {
+                let source_ptr = ctx
+                    .b
+                    .build_load(
+                        &unsafe { ctx.b.build_struct_gep(&pointers_ptr, 0, "source.ptr.ptr") },
+                        "source.ptr",
+                    )
+                    .into_pointer_value();
+                let delayed_ptr = ctx
+                    .b
+                    .build_load(
+                        &unsafe { ctx.b.build_struct_gep(&pointers_ptr, 1, "delayed.ptr.ptr") },
+                        "delayed.ptr",
+                    )
+                    .into_pointer_value();
+                util::copy_ptr(ctx.b, ctx.module, source_ptr, delayed_ptr);
+            }
+        }
+    }
+}
+
+/// Calls a node that only updates every `control_rate_divisor` samples. On the samples in between
+/// the node isn't run, so the groups it writes to hold their value, or if it has interpolated
+/// sockets, those groups ramp from their value at the last update towards the new value.
+fn build_control_rate_node_call(
+    ctx: &mut BuilderContext,
+    cache: &ObjectCache,
+    node: &Node,
+    parent_groups: &[ValueGroup],
+    lifecycle: LifecycleFunc,
+    pointers_ptr: PointerValue,
+) {
+    let data_pointers = unsafe { ctx.b.build_struct_gep(&pointers_ptr, 0, "data.ptrs") };
+    if lifecycle != LifecycleFunc::Update {
+        build_oversampled_node_call(ctx, cache, node, parent_groups, lifecycle, data_pointers);
+        return;
+    }
+    let interpolated_count = node.get_interpolated_sockets(parent_groups).len();
+
+    let rate_pointers = unsafe { ctx.b.build_struct_gep(&pointers_ptr, 1, "rate.ptrs") };
+    let phase_ptr = ctx
+        .b
+        .build_load(
+            &unsafe { ctx.b.build_struct_gep(&rate_pointers, 0, "phase.ptr.ptr") },
+            "phase.ptr",
+        )
+        .into_pointer_value();
+    let targets_ptr = ctx
+        .b
+        .build_load(
+            &unsafe { ctx.b.build_struct_gep(&rate_pointers, 1, "targets.ptr.ptr") },
+            "targets.ptr",
+        )
+        .into_pointer_value();
+    let starts_ptr = ctx
+        .b
+        .build_load(
+            &unsafe { ctx.b.build_struct_gep(&rate_pointers, 2, "starts.ptr.ptr") },
+            "starts.ptr",
+        )
+        .into_pointer_value();
+    let groups_ptr = unsafe { ctx.b.build_struct_gep(&rate_pointers, 3, "groups.ptr") };
+
+    let phase = ctx.b.build_load(&phase_ptr, "phase").into_int_value();
+    let run_block = ctx.context.append_basic_block(&ctx.func, "control.run");
+    let interpolate_block = ctx
+        .context
+        .append_basic_block(&ctx.func, "control.interpolate");
+
+    let is_update_sample = ctx.b.build_int_compare(
+        IntPredicate::EQ,
+        phase,
+        ctx.context.i32_type().const_int(0, false),
+        "isupdatesample",
+    );
+    ctx.b
+        .build_conditional_branch(&is_update_sample, &run_block, &interpolate_block);
+    ctx.b.position_at_end(&run_block);
+
+    // interpolation starts from wherever the groups are now, so there's no jump if the previous
+    // ramp was interrupted
+    let group_ptrs: Vec<_> = (0..interpolated_count)
+        .map(|socket_index| {
+            ctx.b
+                .build_load(
+                    &unsafe {
+                        ctx.b
+                            .build_struct_gep(&groups_ptr, socket_index as u32, "group.ptr.ptr")
+                    },
+                    "group.ptr",
+                )
+                .into_pointer_value()
+        })
+        .collect();
+    for (socket_index, &group_ptr) in group_ptrs.iter().enumerate() {
+        let start_ptr = unsafe {
+            ctx.b
+                .build_struct_gep(&starts_ptr, socket_index as u32, "start.ptr")
+        };
+        util::copy_ptr(ctx.b, ctx.module, group_ptr, start_ptr);
+    }
+    build_oversampled_node_call(ctx, cache, node, parent_groups, lifecycle, data_pointers);
+    ctx.b.build_unconditional_branch(&interpolate_block);
+    ctx.b.position_at_end(&interpolate_block);
+
+    let divisor = ctx
+        .context
+        .i32_type()
+        .const_int(u64::from(cache.target().control_rate_divisor), false);
+    let next_phase = ctx.b.build_int_nuw_add(
+        phase,
+        ctx.context.i32_type().const_int(1, false),
+        "nextphase",
+    );
+
+    if interpolated_count > 0 {
+        let progress = ctx.b.build_float_div(
+            ctx.b
+                .build_unsigned_int_to_float(next_phase, ctx.context.f64_type(), ""),
+            ctx.b
+                .build_unsigned_int_to_float(divisor, ctx.context.f64_type(), ""),
+            "progress",
+        );
+        let progress_vec = util::splat_vector(ctx.b, progress, "progress");
+
+        for (socket_index, &group_ptr) in group_ptrs.iter().enumerate() {
+            let target = values::NumValue::new(unsafe {
+                ctx.b
+                    .build_struct_gep(&targets_ptr, socket_index as u32, "target.ptr")
+            });
+            let start = values::NumValue::new(unsafe {
+                ctx.b
+                    .build_struct_gep(&starts_ptr, socket_index as u32, "start.ptr")
+            });
+            let group = values::NumValue::new(group_ptr);
+
+            let start_vec = start.get_vec(ctx.b);
+            let target_vec = target.get_vec(ctx.b);
+            let delta = ctx.b.build_float_sub(target_vec, start_vec, "delta");
+            let interpolated = ctx.b.build_float_add(
+                start_vec,
+                ctx.b.build_float_mul(delta, progress_vec, ""),
+                "interpolated",
+            );
+            group.set_vec(ctx.b, interpolated);
+            let target_form = target.get_form(ctx.b);
+            group.set_form(ctx.b, target_form);
+        }
+    }
+
+    let wrapped_phase = ctx.b.build_select(
+        ctx.b
+            .build_int_compare(IntPredicate::EQ, next_phase, divisor, ""),
+        ctx.context.i32_type().const_int(0, false),
+        next_phase,
+        "wrappedphase",
+    );
+    ctx.b.build_store(&phase_ptr, &wrapped_phase);
+}
+
+/// Calls a node that runs `oversample_factor` times per sample, or calls the node directly if it
+/// isn't oversampled. Inputs are linearly interpolated across the steps, and outputs are
+/// decimated back to the host rate through a cascade of half-band filters. The sample rate global
+/// is scaled while the node runs, so everything inside it sees the oversampled rate, and the
+/// construct sample rate is scaled the same way while it's constructed.
+fn build_oversampled_node_call(
+    ctx: &mut BuilderContext,
+    cache: &ObjectCache,
+    node: &Node,
+    parent_groups: &[ValueGroup],
+    lifecycle: LifecycleFunc,
+    pointers_ptr: PointerValue,
+) {
+    if node.oversample_factor == OversampleFactor::None {
+        build_node_call(ctx, cache, node, lifecycle, pointers_ptr);
+        return;
+    }
+
+    let data_pointers = unsafe { ctx.b.build_struct_gep(&pointers_ptr, 0, "data.ptrs") };
+    let multiplier = node.oversample_factor.multiplier();
+    let sample_rate_ptr = match lifecycle {
+        LifecycleFunc::Update => globals::build_sample_rate_ptr(ctx.module, ctx.b),
+        // delay lines size their buffers when they're constructed, so they need to see the rate
+        // they'll run at
+        LifecycleFunc::Construct => globals::build_construct_sample_rate_ptr(ctx.module, ctx.b),
+        LifecycleFunc::Destruct => {
+            build_node_call(ctx, cache, node, lifecycle, data_pointers);
+            return;
+        }
+    };
+    let host_sample_rate = ctx
+        .b
+        .build_load(&sample_rate_ptr, "hostsamplerate")
+        .into_vector_value();
+    let oversampled_rate = ctx.b.build_float_mul(
+        host_sample_rate,
+        util::get_vec_spread(ctx.context, f64::from(multiplier)),
+        "oversampledrate",
+    );
+
+    if lifecycle == LifecycleFunc::Construct {
+        ctx.b.build_store(&sample_rate_ptr, &oversampled_rate);
+        build_node_call(ctx, cache, node, lifecycle, data_pointers);
+        ctx.b.build_store(&sample_rate_ptr, &host_sample_rate);
+        return;
+    }
+
+    let sockets = node.get_oversampled_sockets(parent_groups);
+    let oversample_pointers =
+        unsafe { ctx.b.build_struct_gep(&pointers_ptr, 1, "oversample.ptrs") };
+    let load_pointer = |ctx: &mut BuilderContext, struct_ptr: &PointerValue, index: usize| {
+        ctx.b
+            .build_load(
+                &unsafe { ctx.b.build_struct_gep(struct_ptr, index as u32, "") },
+                "",
+            )
+            .into_pointer_value()
+    };
+    let last_inputs_ptr = load_pointer(ctx, &oversample_pointers, 0);
+    let inputs_ptr = load_pointer(ctx, &oversample_pointers, 1);
+    let midi_inputs_ptr = load_pointer(ctx, &oversample_pointers, 2);
+    let outputs_ptr = load_pointer(ctx, &oversample_pointers, 3);
+    let samples_ptr = load_pointer(ctx, &oversample_pointers, 4);
+    let filters_ptr = load_pointer(ctx, &oversample_pointers, 5);
+    let input_groups_ptr = unsafe { ctx.b.build_struct_gep(&oversample_pointers, 6, "") };
+    let midi_input_groups_ptr = unsafe { ctx.b.build_struct_gep(&oversample_pointers, 7, "") };
+    let output_groups_ptr = unsafe { ctx.b.build_struct_gep(&oversample_pointers, 8, "") };
+
+    let input_group_ptrs: Vec<_> = (0..sockets.inputs.len())
+        .map(|input_index| load_pointer(ctx, &input_groups_ptr, input_index))
+        .collect();
+    let output_group_ptrs: Vec<_> = (0..sockets.outputs.len())
+        .map(|output_index| load_pointer(ctx, &output_groups_ptr, output_index))
+        .collect();
+    let midi_input_ptrs: Vec<_> = (0..sockets.midi_inputs.len())
+        .map(|midi_index| {
+            let group_ptr = load_pointer(ctx, &midi_input_groups_ptr, midi_index);
+            let input_ptr = unsafe {
+                ctx.b
+                    .build_struct_gep(&midi_inputs_ptr, midi_index as u32, "midiinput.ptr")
+            };
+            util::copy_ptr(ctx.b, ctx.module, group_ptr, input_ptr);
+            input_ptr
+        })
+        .collect();
+
+    ctx.b.build_store(&sample_rate_ptr, &oversampled_rate);
+
+    let index_ptr = ctx
+        .allocb
+        .build_alloca(&ctx.context.i32_type(), "oversampleindex.ptr");
+    ctx.b
+        .build_store(&index_ptr, &ctx.context.i32_type().const_int(0, false));
+
+    let check_block = ctx
+        .context
+        .append_basic_block(&ctx.func, "oversample.check");
+    let run_block = ctx.context.append_basic_block(&ctx.func, "oversample.run");
+    let end_block = ctx.context.append_basic_block(&ctx.func, "oversample.end");
+
+    ctx.b.build_unconditional_branch(&check_block);
+    ctx.b.position_at_end(&check_block);
+
+    let current_index = ctx
+        .b
+        .build_load(&index_ptr, "oversampleindex")
+        .into_int_value();
+    let multiplier_const = ctx
+        .context
+        .i32_type()
+        .const_int(u64::from(multiplier), false);
+    let can_continue_loop = ctx.b.build_int_compare(
+        IntPredicate::ULT,
+        current_index,
+        multiplier_const,
+        "cancontinue",
+    );
+    ctx.b
+        .build_conditional_branch(&can_continue_loop, &run_block, &end_block);
+    ctx.b.position_at_end(&run_block);
+
+    let next_index = ctx.b.build_int_nuw_add(
+        current_index,
+        ctx.context.i32_type().const_int(1, false),
+        "nextindex",
+    );
+    if !sockets.inputs.is_empty() {
+        let progress = ctx.b.build_float_div(
+            ctx.b
+                .build_unsigned_int_to_float(next_index, ctx.context.f64_type(), ""),
+            ctx.context.f64_type().const_float(f64::from(multiplier)),
+            "progress",
+        );
+        let progress_vec = util::splat_vector(ctx.b, progress, "progress");
+
+        for (input_index, &group_ptr) in input_group_ptrs.iter().enumerate() {
+            let last_input = values::NumValue::new(unsafe {
+                ctx.b
+                    .build_struct_gep(&last_inputs_ptr, input_index as u32, "lastinput.ptr")
+            });
+            let input = values::NumValue::new(unsafe {
+                ctx.b
+                    .build_struct_gep(&inputs_ptr, input_index as u32, "input.ptr")
+            });
+            let group = values::NumValue::new(group_ptr);
+
+            let last_vec = last_input.get_vec(ctx.b);
+            let group_vec = group.get_vec(ctx.b);
+            let delta = ctx.b.build_float_sub(group_vec, last_vec, "delta");
+            let interpolated = ctx.b.build_float_add(
+                last_vec,
+                ctx.b.build_float_mul(delta, progress_vec, ""),
+                "interpolated",
+            );
+            input.set_vec(ctx.b, interpolated);
+            let group_form = group.get_form(ctx.b);
+            input.set_form(ctx.b, group_form);
+        }
+    }
+
+    build_node_call(ctx, cache, node, lifecycle, data_pointers);
+
+    // MIDI events only happen once per host sample
+    for &midi_input_ptr in &midi_input_ptrs {
+        values::MidiValue::new(midi_input_ptr)
+            .set_count(ctx.b, ctx.context.i8_type().const_int(0, false));
+    }
+
+    let const_zero = ctx.context.i32_type().const_int(0, false);
+    for output_index in 0..sockets.outputs.len() {
+        let output = values::NumValue::new(unsafe {
+            ctx.b
+                .build_struct_gep(&outputs_ptr, output_index as u32, "output.ptr")
+        });
+        let sample_ptr = unsafe {
+            ctx.b.build_in_bounds_gep(
+                &samples_ptr,
+                &[
+                    const_zero,
+                    ctx.context.i32_type().const_int(output_index as u64, false),
+                    current_index,
+                ],
+                "sample.ptr",
+            )
+        };
+        let output_vec = output.get_vec(ctx.b);
+        ctx.b.build_store(&sample_ptr, &output_vec);
+    }
+
+    ctx.b.build_store(&index_ptr, &next_index);
+    ctx.b.build_unconditional_branch(&check_block);
+    ctx.b.position_at_end(&end_block);
+
+    for (input_index, &group_ptr) in input_group_ptrs.iter().enumerate() {
+        let last_input_ptr = unsafe {
+            ctx.b
+                .build_struct_gep(&last_inputs_ptr, input_index as u32, "lastinput.ptr")
+        };
+        util::copy_ptr(ctx.b, ctx.module, group_ptr, last_input_ptr);
+    }
+
+    for (output_index, &group_ptr) in output_group_ptrs.iter().enumerate() {
+        let output_samples_ptr = unsafe {
+            ctx.b
+                .build_struct_gep(&samples_ptr, output_index as u32, "samples.ptr")
+        };
+        let mut samples: Vec<_> = (0..multiplier)
+            .map(|sample_index| {
+                let sample_ptr = unsafe {
+                    ctx.b.build_in_bounds_gep(
+                        &output_samples_ptr,
+                        &[
+                            const_zero,
+                            ctx.context
+                                .i32_type()
+                                .const_int(u64::from(sample_index), false),
+                        ],
+                        "sample.ptr",
+                    )
+                };
+                ctx.b.build_load(&sample_ptr, "sample").into_vector_value()
+            })
+            .collect();
+
+        let filter_ptr = unsafe {
+            ctx.b
+                .build_struct_gep(&filters_ptr, output_index as u32, "filter.ptr")
+        };
+        for stage_index in 0..node.oversample_factor.stage_count() {
+            let stage_ptr = unsafe {
+                ctx.b
+                    .build_struct_gep(&filter_ptr, stage_index, "stage.ptr")
+            };
+            samples = samples
+                .chunks(2)
+                .map(|pair| {
+                    half_band::build_decimate(ctx.context, ctx.b, stage_ptr, pair[0], pair[1])
+                })
+                .collect();
+        }
+
+        let output = values::NumValue::new(unsafe {
+            ctx.b
+                .build_struct_gep(&outputs_ptr, output_index as u32, "output.ptr")
+        });
+        let group = values::NumValue::new(group_ptr);
+        group.set_vec(ctx.b, samples[0]);
+        let output_form = output.get_form(ctx.b);
+        group.set_form(ctx.b, output_form);
+    }
+
+    ctx.b.build_store(&sample_rate_ptr, &host_sample_rate);
+}
+
+/// Replaces non-finite values written by a node with zero, so they can't spread to the rest of the
+/// graph. If any are found, the node is recorded in the fault table.
+///
+/// The check is done on the bits of each value, as the builder's fast-math flags allow the
+/// optimizer to assume floating point comparisons never see NaN or infinity.
+fn build_sanitize_outputs(
+    ctx: &mut BuilderContext,
+    surface: SurfaceRef,
+    node_index: usize,
+    socket_count: usize,
+    pointers_ptr: PointerValue,
+) {
+    let sanitize_pointers = unsafe { ctx.b.build_struct_gep(&pointers_ptr, 1, "sanitize.ptrs") };
+    let generation_ptr = ctx
+        .b
+        .build_load(
+            &unsafe { ctx.b.build_struct_gep(&sanitize_pointers, 0, "") },
+            "generation.ptr",
+        )
+        .into_pointer_value();
+    let groups_ptr = unsafe { ctx.b.build_struct_gep(&sanitize_pointers, 1, "groups.ptr") };
+
+    let i64_vec_type = ctx.context.i64_type().vec_type(2);
+    let exponent_mask = VectorType::const_vector(&[
+        &ctx.context
+            .i64_type()
+            .const_int(0x7FF0_0000_0000_0000, false),
+        &ctx.context
+            .i64_type()
+            .const_int(0x7FF0_0000_0000_0000, false),
+    ]);
+    let mut any_faulted = ctx.context.bool_type().const_int(0, false);
+    for socket_index in 0..socket_count {
+        let group = values::NumValue::new(
+            ctx.b
+                .build_load(
+                    &unsafe { ctx.b.build_struct_gep(&groups_ptr, socket_index as u32, "") },
+                    "group.ptr",
+                )
+                .into_pointer_value(),
+        );
+        let group_vec = group.get_vec(ctx.b);
+        let group_bits = ctx
+            .b
+            .build_cast(
+                InstructionOpcode::BitCast,
+                &group_vec,
+                &i64_vec_type,
+                "bits",
+            )
+            .into_vector_value();
+        let non_finite = ctx.b.build_int_compare(
+            IntPredicate::EQ,
+            ctx.b.build_and(group_bits, exponent_mask, "exponent"),
+            exponent_mask,
+            "nonfinite",
+        );
+        let sanitized_vec = ctx
+            .b
+            .build_select(
+                non_finite,
+                util::get_vec_spread(ctx.context, 0.),
+                group_vec,
+                "sanitized",
+            )
+            .into_vector_value();
+        group.set_vec(ctx.b, sanitized_vec);
+
+        let left_faulted = ctx
+            .b
+            .build_extract_element(&non_finite, &ctx.context.i32_type().const_int(0, false), "")
+            .into_int_value();
+        let right_faulted = ctx
+            .b
+            .build_extract_element(&non_finite, &ctx.context.i32_type().const_int(1, false), "")
+            .into_int_value();
+        any_faulted = ctx.b.build_or(
+            any_faulted,
+            ctx.b.build_or(left_faulted, right_faulted, ""),
+            "anyfaulted",
+        );
     }
+
+    let fault_block = ctx.context.append_basic_block(&ctx.func, "sanitize.fault");
+    let end_block = ctx.context.append_basic_block(&ctx.func, "sanitize.end");
+    ctx.b
+        .build_conditional_branch(&any_faulted, &fault_block, &end_block);
+
+    ctx.b.position_at_end(&fault_block);
+    let instance = globals::get_instance_param(ctx.module, ctx.b);
+    ctx.b.build_call(
+        &intrinsics::record_fault(ctx.module, ctx.target),
+        &globals::with_instance_arg(
+            &[
+                &generation_ptr,
+                &ctx.context.i64_type().const_int(surface, false),
+                &ctx.context.i32_type().const_int(node_index as u64, false),
+            ],
+            &instance,
+        ),
+        "",
+        true,
+    );
+    ctx.b.build_unconditional_branch(&end_block);
+
+    ctx.b.position_at_end(&end_block);
+}
+
+fn build_read_cycle_counter(ctx: &mut BuilderContext, name: &str) -> IntValue {
+    ctx.b
+        .build_call(&intrinsics::read_cycle_counter(ctx.module), &[], name, true)
+        .left()
+        .unwrap()
+        .into_int_value()
+}
+
+/// Writes the number of cycles since `start_cycles` into the next slot of a node's profile
+/// record, see `data_analyzer::get_profile_record_type`.
+fn build_profile_record(
+    ctx: &mut BuilderContext,
+    record_ptr: PointerValue,
+    start_cycles: IntValue,
+) {
+    let end_cycles = build_read_cycle_counter(ctx, "profile.end");
+    let elapsed_cycles = ctx.b.build_int_truncate(
+        ctx.b.build_int_sub(end_cycles, start_cycles, ""),
+        ctx.context.i32_type(),
+        "profile.elapsed",
+    );
+
+    let position_ptr = unsafe {
+        ctx.b
+            .build_struct_gep(&record_ptr, 0, "profile.position.ptr")
+    };
+    let count_ptr = unsafe { ctx.b.build_struct_gep(&record_ptr, 1, "profile.count.ptr") };
+    let timings_ptr = unsafe {
+        ctx.b
+            .build_struct_gep(&record_ptr, 2, "profile.timings.ptr")
+    };
+
+    let position = ctx
+        .b
+        .build_load(&position_ptr, "profile.position")
+        .into_int_value();
+    let timing_ptr = unsafe {
+        ctx.b.build_in_bounds_gep(
+            &timings_ptr,
+            &[ctx.context.i32_type().const_int(0, false), position],
+            "profile.timing.ptr",
+        )
+    };
+    ctx.b.build_store(&timing_ptr, &elapsed_cycles);
+
+    let next_position = ctx.b.build_and(
+        ctx.b
+            .build_int_add(position, ctx.context.i32_type().const_int(1, false), ""),
+        ctx.context
+            .i32_type()
+            .const_int(u64::from(data_analyzer::PROFILE_WINDOW - 1), false),
+        "profile.nextposition",
+    );
+    ctx.b.build_store(&position_ptr, &next_position);
+
+    let count = ctx
+        .b
+        .build_load(&count_ptr, "profile.count")
+        .into_int_value();
+    let window_size = ctx
+        .context
+        .i32_type()
+        .const_int(u64::from(data_analyzer::PROFILE_WINDOW), false);
+    let is_full = ctx
+        .b
+        .build_int_compare(IntPredicate::UGE, count, window_size, "profile.full");
+    let next_count = ctx
+        .b
+        .build_select(
+            is_full,
+            window_size,
+            ctx.b
+                .build_int_add(count, ctx.context.i32_type().const_int(1, false), ""),
+            "profile.nextcount",
+        )
+        .into_int_value();
+    ctx.b.build_store(&count_ptr, &next_count);
 }
 
 pub fn build_lifecycle_func(
@@ -228,6 +867,29 @@ pub fn build_lifecycle_func(
     build_context_function(module, func, cache.target(), &|mut ctx: BuilderContext| {
         let layout = cache.surface_layout(surface.id.id).unwrap();
         let pointers_ptr = ctx.func.get_nth_param(0).unwrap().into_pointer_value();
+        let profile_records_ptr = match layout.profile_ptr_index() {
+            Some(profile_ptr_index) if lifecycle == LifecycleFunc::Update => {
+                let profile_ptr_ptr = unsafe {
+                    ctx.b.build_in_bounds_gep(
+                        &pointers_ptr,
+                        &[
+                            ctx.context.i32_type().const_int(0, false),
+                            ctx.context
+                                .i32_type()
+                                .const_int(profile_ptr_index as u64, false),
+                            ctx.context.i32_type().const_int(0, false),
+                        ],
+                        "profile.records.ptr.ptr",
+                    )
+                };
+                Some(
+                    ctx.b
+                        .build_load(&profile_ptr_ptr, "profile.records.ptr")
+                        .into_pointer_value(),
+                )
+            }
+            _ => None,
+        };
 
         for (node_index, node) in surface.nodes.iter().enumerate() {
             let layout_ptr_index = layout.node_ptr_index(node_index);
@@ -235,8 +897,61 @@ pub fn build_lifecycle_func(
                 ctx.b
                     .build_struct_gep(&pointers_ptr, layout_ptr_index as u32, "")
             };
+            let sanitized_sockets =
+                data_analyzer::get_sanitized_sockets(cache.target(), node, &surface.groups);
+            let data_pointers_ptr = if sanitized_sockets.is_empty() {
+                node_pointers_ptr
+            } else {
+                unsafe { ctx.b.build_struct_gep(&node_pointers_ptr, 0, "data.ptrs") }
+            };
+            let profile_start_cycles =
+                profile_records_ptr.map(|_| build_read_cycle_counter(&mut ctx, "profile.start"));
 
-            build_node_call(&mut ctx, cache, node, lifecycle, node_pointers_ptr);
+            if node.update_rate == UpdateRate::Audio {
+                build_oversampled_node_call(
+                    &mut ctx,
+                    cache,
+                    node,
+                    &surface.groups,
+                    lifecycle,
+                    data_pointers_ptr,
+                );
+            } else {
+                build_control_rate_node_call(
+                    &mut ctx,
+                    cache,
+                    node,
+                    &surface.groups,
+                    lifecycle,
+                    data_pointers_ptr,
+                );
+            }
+
+            if lifecycle == LifecycleFunc::Update && !sanitized_sockets.is_empty() {
+                build_sanitize_outputs(
+                    &mut ctx,
+                    surface.id.id,
+                    node_index,
+                    sanitized_sockets.len(),
+                    node_pointers_ptr,
+                );
+            }
+
+            if let (Some(records_ptr), Some(start_cycles)) =
+                (profile_records_ptr, profile_start_cycles)
+            {
+                let record_ptr = unsafe {
+                    ctx.b.build_in_bounds_gep(
+                        &records_ptr,
+                        &[
+                            ctx.context.i32_type().const_int(0, false),
+                            ctx.context.i32_type().const_int(node_index as u64, false),
+                        ],
+                        "profile.record.ptr",
+                    )
+                };
+                build_profile_record(&mut ctx, record_ptr, start_cycles);
+            }
         }
 
         ctx.b.build_return(None);
@@ -258,5 +973,11 @@ pub fn build_lifecycle_call(
     pointer_ptr: PointerValue,
 ) {
     let func = get_lifecycle_func(module, cache, surface, lifecycle);
-    builder.build_call(&func, &[&pointer_ptr], "", true);
+    let instance = globals::get_instance_param(module, builder);
+    builder.build_call(
+        &func,
+        &globals::with_instance_arg(&[&pointer_ptr], &instance),
+        "",
+        true,
+    );
 }
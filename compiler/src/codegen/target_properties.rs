@@ -1,8 +1,15 @@
+#[cfg(feature = "jit")]
 use inkwell::context::Context;
+#[cfg(feature = "jit")]
 use inkwell::module::Module;
+#[cfg(feature = "jit")]
 use inkwell::targets::TargetMachine;
+#[cfg(feature = "jit")]
+use inkwell::types::FloatType;
+#[cfg(feature = "jit")]
+use std::hash::{Hash, Hasher};
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 #[repr(u8)]
 pub enum OptimizationLevel {
     Editor,
@@ -15,12 +22,14 @@ pub enum OptimizationLevel {
     AggressiveSize,
 }
 
+#[cfg(feature = "jit")]
 pub struct OptimizationSpecification {
     pub llvm_level: inkwell::OptimizationLevel,
     pub size_level: u32,
     pub inliner_threshold: u32,
 }
 
+#[cfg(feature = "jit")]
 impl OptimizationLevel {
     pub fn into_specification(self) -> OptimizationSpecification {
         match self {
@@ -63,13 +72,100 @@ impl OptimizationLevel {
     }
 }
 
+/// The precision numbers are stored at in value groups, sockets and delay lines. This only
+/// affects storage: values are extended to double precision when they're loaded and all
+/// arithmetic happens at double precision, so single precision halves the memory used by values
+/// and delay lines but doesn't make the math any faster. Accumulating state such as oscillator
+/// phases isn't affected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum StoragePrecision {
+    Double,
+    Single,
+}
+
+#[cfg(feature = "jit")]
+impl StoragePrecision {
+    pub fn float_type(self, context: &Context) -> FloatType {
+        match self {
+            StoragePrecision::Double => context.f64_type(),
+            StoragePrecision::Single => context.f32_type(),
+        }
+    }
+}
+
+/// How closely the generated math library approximates the functions it implements. Cheaper
+/// tiers use lower-degree polynomials, which produce smaller code at the cost of accuracy.
+///
+/// Errors are absolute for results up to one in magnitude and relative for larger ones, over the
+/// range of inputs each function is typically used with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum MathAccuracy {
+    /// Sines and cosines are a single parabola per half period, which is up to 0.06 off, and
+    /// tangents are up to 0.2 off near their poles. Everything else is within 1e-3.
+    Fast,
+    /// The sine parabola is refined once, to within 2e-3, and tangents are within 1e-2.
+    /// Everything else is within 1e-7.
+    Balanced,
+    /// Close to double precision: everything is within 1e-14.
+    Accurate,
+}
+
+/// How generated code keeps subnormal numbers out of feedback paths, where they'd otherwise slow
+/// down every operation as filters and delays decay towards silence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum DenormalMode {
+    /// Set the flush-to-zero and denormals-are-zero flags in MXCSR while the update runs, and
+    /// restore them afterwards. Falls back to `Offset` on targets that aren't x86.
+    FlushToZero,
+
+    /// Leave the floating point environment alone, and instead add a tiny offset to the state of
+    /// feedback paths so it never decays into the subnormal range.
+    Offset,
+}
+
+/// The code generator surfaces and blocks are compiled with in the runtime.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum CodegenBackend {
+    /// Compile everything with LLVM. Exports always use this.
+    Llvm,
+
+    /// Compile surfaces and blocks with Cranelift, which is much quicker to compile but produces
+    /// slower code. The library and root are still compiled with LLVM, and surfaces Cranelift
+    /// can't compile (such as ones with oversampled nodes) fall back to it.
+    Cranelift,
+}
+
+/// The offset added to feedback state in `DenormalMode::Offset`, around -360dB.
+pub const DENORMAL_OFFSET: f64 = 1e-18;
+
+/// The longest delay, in seconds, a delay line reserves memory for unless configured otherwise.
+pub const DEFAULT_MAX_DELAY_SECONDS: f64 = 10.;
+
+/// The number of samples between updates of control-rate nodes, unless configured otherwise.
+pub const DEFAULT_CONTROL_RATE_DIVISOR: u32 = 32;
+
+#[cfg(feature = "jit")]
 #[derive(Debug)]
 pub struct TargetProperties {
     pub include_ui: bool,
     pub optimization_level: OptimizationLevel,
+    pub control_rate_divisor: u32,
+    pub storage_precision: StoragePrecision,
+    pub math_accuracy: MathAccuracy,
+    pub max_delay_seconds: f64,
+    pub denormal_mode: DenormalMode,
+    pub sanitize_outputs: bool,
+    pub profile_nodes: bool,
+    pub instanced: bool,
+    pub backend: CodegenBackend,
     pub machine: TargetMachine,
 }
 
+#[cfg(feature = "jit")]
 impl TargetProperties {
     pub fn new(
         include_ui: bool,
@@ -79,6 +175,15 @@ impl TargetProperties {
         TargetProperties {
             include_ui,
             optimization_level,
+            control_rate_divisor: DEFAULT_CONTROL_RATE_DIVISOR,
+            storage_precision: StoragePrecision::Double,
+            math_accuracy: MathAccuracy::Balanced,
+            max_delay_seconds: DEFAULT_MAX_DELAY_SECONDS,
+            denormal_mode: DenormalMode::FlushToZero,
+            sanitize_outputs: false,
+            profile_nodes: false,
+            instanced: false,
+            backend: CodegenBackend::Llvm,
             machine,
         }
     }
@@ -89,4 +194,56 @@ impl TargetProperties {
         module.set_data_layout(&self.machine.get_data().get_data_layout());
         module
     }
+
+    /// Hashes everything that affects the generated code apart from the MIR itself, so compiled
+    /// objects are only reused with the same machine and options.
+    pub fn hash_codegen_options<H: Hasher>(&self, state: &mut H) {
+        self.machine.get_triple().to_bytes().hash(state);
+        self.machine.get_cpu().to_bytes().hash(state);
+        self.machine.get_feature_string().to_bytes().hash(state);
+        self.include_ui.hash(state);
+        self.optimization_level.hash(state);
+        self.control_rate_divisor.hash(state);
+        self.storage_precision.hash(state);
+        self.math_accuracy.hash(state);
+        self.max_delay_seconds.to_bits().hash(state);
+        self.denormal_mode.hash(state);
+        self.sanitize_outputs.hash(state);
+        self.profile_nodes.hash(state);
+        self.instanced.hash(state);
+        self.backend.hash(state);
+    }
+
+    /// Whether the update functions should set the flush-to-zero flags in MXCSR.
+    pub fn uses_flush_to_zero(&self) -> bool {
+        self.denormal_mode == DenormalMode::FlushToZero && self.is_x86() && self.has_feature("+sse")
+    }
+
+    /// Whether feedback paths should have denormal offsets injected into them.
+    pub fn uses_denormal_offsets(&self) -> bool {
+        !self.uses_flush_to_zero()
+    }
+
+    /// Whether the SSE4.1 intrinsics the math library was written against can be used. Other
+    /// targets, including x86 machines built with the generic feature level, get portable
+    /// equivalents.
+    pub fn has_sse41(&self) -> bool {
+        self.is_x86() && self.has_feature("+sse4.1")
+    }
+
+    fn has_feature(&self, feature: &str) -> bool {
+        self.machine
+            .get_feature_string()
+            .to_string_lossy()
+            .split(',')
+            .any(|enabled| enabled == feature)
+    }
+
+    fn is_x86(&self) -> bool {
+        let triple = self.machine.get_triple().to_string_lossy().into_owned();
+        match triple.split('-').next() {
+            Some("x86_64") | Some("i386") | Some("i486") | Some("i586") | Some("i686") => true,
+            _ => false,
+        }
+    }
 }
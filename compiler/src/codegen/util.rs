@@ -1,14 +1,16 @@
-use crate::codegen::intrinsics;
+use crate::codegen::{intrinsics, TargetProperties, DENORMAL_OFFSET};
 use inkwell::builder::Builder;
 use inkwell::context::Context;
 use inkwell::module::{Linkage, Module};
-use inkwell::types::{ArrayType, BasicType, BasicTypeEnum, FunctionType, VectorType};
+use inkwell::types::{AnyTypeEnum, ArrayType, BasicType, BasicTypeEnum, FunctionType, VectorType};
 use inkwell::values::{
-    ArrayValue, BasicValueEnum, FloatValue, FunctionValue, GlobalValue, IntValue, PointerValue,
-    VectorValue,
+    ArrayValue, BasicValueEnum, FloatValue, FunctionValue, GlobalValue, InstructionOpcode,
+    IntValue, PointerValue, VectorValue,
 };
 use inkwell::AddressSpace;
 use inkwell::IntPredicate;
+use std::ffi::CStr;
+use std::os::raw::c_char;
 
 pub fn get_size_of(t: &BasicTypeEnum) -> Option<IntValue> {
     match t {
@@ -132,10 +134,95 @@ pub fn splat_vector(builder: &Builder, val: FloatValue, name: &str) -> VectorVal
         .into_vector_value()
 }
 
+/// Converts a float vector to another float vector type with the same number of elements,
+/// extending or truncating each element.
+pub fn convert_float_vec(
+    builder: &Builder,
+    value: VectorValue,
+    dest_type: VectorType,
+    name: &str,
+) -> VectorValue {
+    let src_type = value.get_type();
+    if src_type == dest_type {
+        return value;
+    }
+
+    let context = src_type.get_context();
+    let opcode = if src_type.get_element_type() == context.f64_type().into() {
+        InstructionOpcode::FPTrunc
+    } else {
+        InstructionOpcode::FPExt
+    };
+    builder
+        .build_cast(opcode, &value, &dest_type, name)
+        .into_vector_value()
+}
+
+/// Adds the denormal offset to a value that's fed back into a node's state, if the target keeps
+/// denormals away that way. Otherwise the value is returned unchanged.
+pub fn build_denormal_offset(
+    builder: &Builder,
+    target: &TargetProperties,
+    value: VectorValue,
+    name: &str,
+) -> VectorValue {
+    if !target.uses_denormal_offsets() {
+        return value;
+    }
+
+    let context = value.get_type().get_context();
+    builder.build_float_add(value, get_vec_spread(&context, DENORMAL_OFFSET), name)
+}
+
+/// Copies between two pointers whose types only differ in the precision of the floats they
+/// contain, converting each float vector along the way.
+fn copy_converted_ptr(builder: &mut Builder, src: PointerValue, dest: PointerValue) {
+    let src_elem_type = src.get_type().element_type();
+    let dest_elem_type = dest.get_type().element_type();
+
+    if src_elem_type == dest_elem_type {
+        let value = builder.build_load(&src, "");
+        builder.build_store(&dest, &value);
+        return;
+    }
+
+    match (src_elem_type, dest_elem_type) {
+        (AnyTypeEnum::StructType(struct_type), AnyTypeEnum::StructType(_)) => {
+            for field_index in 0..struct_type.count_fields() {
+                let src_field = unsafe { builder.build_struct_gep(&src, field_index, "") };
+                let dest_field = unsafe { builder.build_struct_gep(&dest, field_index, "") };
+                copy_converted_ptr(builder, src_field, dest_field);
+            }
+        }
+        (AnyTypeEnum::ArrayType(array_type), AnyTypeEnum::ArrayType(_)) => {
+            let context = array_type.get_context();
+            for item_index in 0..array_type.len() {
+                let indices = [
+                    context.i32_type().const_int(0, false),
+                    context.i32_type().const_int(u64::from(item_index), false),
+                ];
+                let src_item = unsafe { builder.build_in_bounds_gep(&src, &indices, "") };
+                let dest_item = unsafe { builder.build_in_bounds_gep(&dest, &indices, "") };
+                copy_converted_ptr(builder, src_item, dest_item);
+            }
+        }
+        (AnyTypeEnum::VectorType(_), AnyTypeEnum::VectorType(dest_vec_type)) => {
+            let value = builder.build_load(&src, "").into_vector_value();
+            let converted = convert_float_vec(builder, value, dest_vec_type, "");
+            builder.build_store(&dest, &converted);
+        }
+        (src_type, dest_type) => panic!("Cannot convert {:?} to {:?}", src_type, dest_type),
+    }
+}
+
 pub fn copy_ptr(builder: &mut Builder, module: &Module, src: PointerValue, dest: PointerValue) {
     let src_elem_type = src.get_type().element_type();
     let dest_elem_type = dest.get_type().element_type();
-    assert_eq!(src_elem_type, dest_elem_type);
+    if src_elem_type != dest_elem_type {
+        // values stored at single precision are copied field-by-field to and from locals
+        copy_converted_ptr(builder, src, dest);
+        return;
+    }
 
     let param_size = get_size_of(&src_elem_type).unwrap();
     let context = module.get_context();
@@ -194,3 +281,14 @@ pub fn clear_bit(builder: &mut Builder, bitmap: IntValue, bit: IntValue) -> IntV
     );
     builder.build_and(bitmap, bitmask, "clearbit")
 }
+
+extern "C" {
+    fn LLVMAxiomGetVersionString() -> *const c_char;
+}
+
+/// The version of LLVM the compiler was built against, such as "6.0.1".
+pub fn get_llvm_version() -> &'static str {
+    unsafe { CStr::from_ptr(LLVMAxiomGetVersionString()) }
+        .to_str()
+        .unwrap()
+}
@@ -1,10 +1,9 @@
+use crate::mir::ARRAY_CAPACITY;
 use inkwell::builder::Builder;
 use inkwell::context::Context;
 use inkwell::types::StructType;
 use inkwell::values::{IntValue, PointerValue};
 
-pub const ARRAY_CAPACITY: u8 = 32;
-
 #[derive(Debug, Clone)]
 pub struct ArrayValue {
     pub val: PointerValue,
@@ -0,0 +1,387 @@
+use super::{MidiEventValue, MidiValue};
+use crate::codegen::{build_context_function, util, BuilderContext, TargetProperties};
+use inkwell::attribute::AttrKind;
+use inkwell::builder::Builder;
+use inkwell::context::Context;
+use inkwell::module::{Linkage, Module};
+use inkwell::types::StructType;
+use inkwell::values::{FunctionValue, IntValue, PointerValue};
+use inkwell::AddressSpace;
+use inkwell::IntPredicate;
+
+pub const MIDI_QUEUE_CAPACITY: u32 = 256;
+
+/// A queue of MIDI events waiting to be delivered at a sample offset. Entries are kept sorted by
+/// offset, so delivering them is a walk from the head of the queue. Offsets are relative to the
+/// start of the next block, and are moved along by `advance` at the end of each block.
+#[derive(Debug, Clone)]
+pub struct MidiQueueValue {
+    pub val: PointerValue,
+}
+
+impl MidiQueueValue {
+    pub fn get_type(context: &Context) -> StructType {
+        let entry_type = context.struct_type(
+            &[
+                &context.i32_type(),                // offset
+                &MidiEventValue::get_type(context), // event
+            ],
+            false,
+        );
+        context.struct_type(
+            &[
+                &context.i32_type(), // count
+                &context.i32_type(), // head
+                &entry_type.array_type(MIDI_QUEUE_CAPACITY),
+            ],
+            false,
+        )
+    }
+
+    pub fn new(val: PointerValue) -> Self {
+        MidiQueueValue { val }
+    }
+
+    fn get_count_ptr(&self, builder: &mut Builder) -> PointerValue {
+        unsafe { builder.build_struct_gep(&self.val, 0, "queue.count.ptr") }
+    }
+
+    fn get_head_ptr(&self, builder: &mut Builder) -> PointerValue {
+        unsafe { builder.build_struct_gep(&self.val, 1, "queue.head.ptr") }
+    }
+
+    fn get_entry_ptr(&self, builder: &mut Builder, index: IntValue) -> PointerValue {
+        let context = self.val.get_type().get_context();
+        unsafe {
+            builder.build_in_bounds_gep(
+                &self.val,
+                &[
+                    context.i64_type().const_int(0, false),
+                    context.i32_type().const_int(2, false),
+                    index,
+                ],
+                "queue.entry.ptr",
+            )
+        }
+    }
+
+    fn get_entry_offset_ptr(&self, builder: &mut Builder, index: IntValue) -> PointerValue {
+        let entry_ptr = self.get_entry_ptr(builder, index);
+        unsafe { builder.build_struct_gep(&entry_ptr, 0, "queue.entry.offset.ptr") }
+    }
+
+    fn get_entry_event(&self, builder: &mut Builder, index: IntValue) -> MidiEventValue {
+        let entry_ptr = self.get_entry_ptr(builder, index);
+        MidiEventValue::new(unsafe { builder.build_struct_gep(&entry_ptr, 1, "queue.entry.event") })
+    }
+
+    /// Queues an event to be delivered `offset` samples into the next block. Events are dropped if
+    /// the queue is full.
+    pub fn push_event(
+        &self,
+        builder: &mut Builder,
+        module: &Module,
+        offset: IntValue,
+        event: &MidiEventValue,
+    ) {
+        let push_func = MidiQueueValue::get_push_event_func(module);
+        builder.build_call(&push_func, &[&self.val, &offset, &event.val], "", true);
+    }
+
+    /// Pushes the events due at or before `frame` onto a MIDI value.
+    pub fn deliver(
+        &self,
+        builder: &mut Builder,
+        module: &Module,
+        frame: IntValue,
+        midi: &MidiValue,
+    ) {
+        let deliver_func = MidiQueueValue::get_deliver_func(module);
+        builder.build_call(&deliver_func, &[&self.val, &frame, &midi.val], "", true);
+    }
+
+    /// Removes the delivered events after a block of `frames` samples, and moves the offsets of
+    /// the rest so they're relative to the start of the next block.
+    pub fn advance(&self, builder: &mut Builder, module: &Module, frames: IntValue) {
+        let advance_func = MidiQueueValue::get_advance_func(module);
+        builder.build_call(&advance_func, &[&self.val, &frames], "", true);
+    }
+
+    fn get_push_event_func(module: &Module) -> FunctionValue {
+        let func = util::get_or_create_func(module, "maxim.midiqueue.pushEvent", true, &|| {
+            let context = module.get_context();
+            (
+                Linkage::ExternalLinkage,
+                context.void_type().fn_type(
+                    &[
+                        &MidiQueueValue::get_type(&context).ptr_type(AddressSpace::Generic),
+                        &context.i32_type(),
+                        &MidiEventValue::get_type(&context).ptr_type(AddressSpace::Generic),
+                    ],
+                    false,
+                ),
+            )
+        });
+        let context = module.get_context();
+        func.add_param_attribute(0, context.get_enum_attr(AttrKind::NoAlias, 1));
+        func.add_param_attribute(2, context.get_enum_attr(AttrKind::NoAlias, 1));
+        func
+    }
+
+    fn get_deliver_func(module: &Module) -> FunctionValue {
+        let func = util::get_or_create_func(module, "maxim.midiqueue.deliver", true, &|| {
+            let context = module.get_context();
+            (
+                Linkage::ExternalLinkage,
+                context.void_type().fn_type(
+                    &[
+                        &MidiQueueValue::get_type(&context).ptr_type(AddressSpace::Generic),
+                        &context.i32_type(),
+                        &MidiValue::get_type(&context).ptr_type(AddressSpace::Generic),
+                    ],
+                    false,
+                ),
+            )
+        });
+        let context = module.get_context();
+        func.add_param_attribute(0, context.get_enum_attr(AttrKind::NoAlias, 1));
+        func.add_param_attribute(2, context.get_enum_attr(AttrKind::NoAlias, 1));
+        func
+    }
+
+    fn get_advance_func(module: &Module) -> FunctionValue {
+        let func = util::get_or_create_func(module, "maxim.midiqueue.advance", true, &|| {
+            let context = module.get_context();
+            (
+                Linkage::ExternalLinkage,
+                context.void_type().fn_type(
+                    &[
+                        &MidiQueueValue::get_type(&context).ptr_type(AddressSpace::Generic),
+                        &context.i32_type(),
+                    ],
+                    false,
+                ),
+            )
+        });
+        let context = module.get_context();
+        func.add_param_attribute(0, context.get_enum_attr(AttrKind::NoAlias, 1));
+        func
+    }
+
+    pub fn initialize(module: &Module, target: &TargetProperties) {
+        MidiQueueValue::build_push_event_func(module, target);
+        MidiQueueValue::build_deliver_func(module, target);
+        MidiQueueValue::build_advance_func(module, target);
+    }
+
+    fn build_push_event_func(module: &Module, target: &TargetProperties) {
+        build_context_function(
+            module,
+            MidiQueueValue::get_push_event_func(module),
+            target,
+            &|ctx: BuilderContext| {
+                let can_push_block = ctx.func.append_basic_block("canpush");
+                let shift_check_block = ctx.func.append_basic_block("shift.check");
+                let shift_compare_block = ctx.func.append_basic_block("shift.compare");
+                let shift_run_block = ctx.func.append_basic_block("shift.run");
+                let insert_block = ctx.func.append_basic_block("insert");
+                let end_block = ctx.func.append_basic_block("end");
+
+                let queue =
+                    MidiQueueValue::new(ctx.func.get_nth_param(0).unwrap().into_pointer_value());
+                let offset = ctx.func.get_nth_param(1).unwrap().into_int_value();
+                let push_evt =
+                    MidiEventValue::new(ctx.func.get_nth_param(2).unwrap().into_pointer_value());
+                let const_one = ctx.context.i32_type().const_int(1, false);
+
+                let count_ptr = queue.get_count_ptr(ctx.b);
+                let current_count = ctx.b.build_load(&count_ptr, "count").into_int_value();
+                let can_push_cond = ctx.b.build_int_compare(
+                    IntPredicate::ULT,
+                    current_count,
+                    ctx.context
+                        .i32_type()
+                        .const_int(u64::from(MIDI_QUEUE_CAPACITY), false),
+                    "canpushcond",
+                );
+                ctx.b
+                    .build_conditional_branch(&can_push_cond, &can_push_block, &end_block);
+                ctx.b.position_at_end(&can_push_block);
+
+                // entries with a later offset are shifted back one at a time until the new event's
+                // place is found, so events at the same offset stay in the order they were pushed
+                let head_ptr = queue.get_head_ptr(ctx.b);
+                let head = ctx.b.build_load(&head_ptr, "head").into_int_value();
+                let index_ptr = ctx
+                    .allocb
+                    .build_alloca(&ctx.context.i32_type(), "index.ptr");
+                ctx.b.build_store(&index_ptr, &current_count);
+                ctx.b.build_unconditional_branch(&shift_check_block);
+
+                ctx.b.position_at_end(&shift_check_block);
+                let index = ctx.b.build_load(&index_ptr, "index").into_int_value();
+                let has_previous =
+                    ctx.b
+                        .build_int_compare(IntPredicate::UGT, index, head, "hasprevious");
+                ctx.b
+                    .build_conditional_branch(&has_previous, &shift_compare_block, &insert_block);
+
+                ctx.b.position_at_end(&shift_compare_block);
+                let previous_index = ctx.b.build_int_nuw_sub(index, const_one, "previousindex");
+                let previous_offset_ptr = queue.get_entry_offset_ptr(ctx.b, previous_index);
+                let previous_offset = ctx
+                    .b
+                    .build_load(&previous_offset_ptr, "previousoffset")
+                    .into_int_value();
+                let should_shift = ctx.b.build_int_compare(
+                    IntPredicate::UGT,
+                    previous_offset,
+                    offset,
+                    "shouldshift",
+                );
+                ctx.b
+                    .build_conditional_branch(&should_shift, &shift_run_block, &insert_block);
+
+                ctx.b.position_at_end(&shift_run_block);
+                let previous_entry_ptr = queue.get_entry_ptr(ctx.b, previous_index);
+                let entry_ptr = queue.get_entry_ptr(ctx.b, index);
+                let previous_entry = ctx.b.build_load(&previous_entry_ptr, "previousentry");
+                ctx.b.build_store(&entry_ptr, &previous_entry);
+                ctx.b.build_store(&index_ptr, &previous_index);
+                ctx.b.build_unconditional_branch(&shift_check_block);
+
+                ctx.b.position_at_end(&insert_block);
+                let insert_index = ctx.b.build_load(&index_ptr, "insertindex").into_int_value();
+                let insert_offset_ptr = queue.get_entry_offset_ptr(ctx.b, insert_index);
+                ctx.b.build_store(&insert_offset_ptr, &offset);
+                let insert_event = queue.get_entry_event(ctx.b, insert_index);
+                push_evt.copy_to(ctx.b, module, &insert_event);
+                let new_count = ctx
+                    .b
+                    .build_int_nuw_add(current_count, const_one, "newcount");
+                ctx.b.build_store(&count_ptr, &new_count);
+                ctx.b.build_unconditional_branch(&end_block);
+
+                ctx.b.position_at_end(&end_block);
+                ctx.b.build_return(None);
+            },
+        );
+    }
+
+    fn build_deliver_func(module: &Module, target: &TargetProperties) {
+        build_context_function(
+            module,
+            MidiQueueValue::get_deliver_func(module),
+            target,
+            &|ctx: BuilderContext| {
+                let check_block = ctx.func.append_basic_block("check");
+                let compare_block = ctx.func.append_basic_block("compare");
+                let run_block = ctx.func.append_basic_block("run");
+                let end_block = ctx.func.append_basic_block("end");
+
+                let queue =
+                    MidiQueueValue::new(ctx.func.get_nth_param(0).unwrap().into_pointer_value());
+                let frame = ctx.func.get_nth_param(1).unwrap().into_int_value();
+                let midi = MidiValue::new(ctx.func.get_nth_param(2).unwrap().into_pointer_value());
+
+                let count_ptr = queue.get_count_ptr(ctx.b);
+                let count = ctx.b.build_load(&count_ptr, "count").into_int_value();
+                let head_ptr = queue.get_head_ptr(ctx.b);
+                ctx.b.build_unconditional_branch(&check_block);
+
+                ctx.b.position_at_end(&check_block);
+                let head = ctx.b.build_load(&head_ptr, "head").into_int_value();
+                let has_entry = ctx
+                    .b
+                    .build_int_compare(IntPredicate::ULT, head, count, "hasentry");
+                ctx.b
+                    .build_conditional_branch(&has_entry, &compare_block, &end_block);
+
+                ctx.b.position_at_end(&compare_block);
+                let offset_ptr = queue.get_entry_offset_ptr(ctx.b, head);
+                let offset = ctx.b.build_load(&offset_ptr, "offset").into_int_value();
+                let is_due = ctx
+                    .b
+                    .build_int_compare(IntPredicate::ULE, offset, frame, "isdue");
+                ctx.b
+                    .build_conditional_branch(&is_due, &run_block, &end_block);
+
+                ctx.b.position_at_end(&run_block);
+                let event = queue.get_entry_event(ctx.b, head);
+                midi.push_event(ctx.b, module, &event);
+                let next_head = ctx.b.build_int_nuw_add(
+                    head,
+                    ctx.context.i32_type().const_int(1, false),
+                    "nexthead",
+                );
+                ctx.b.build_store(&head_ptr, &next_head);
+                ctx.b.build_unconditional_branch(&check_block);
+
+                ctx.b.position_at_end(&end_block);
+                ctx.b.build_return(None);
+            },
+        );
+    }
+
+    fn build_advance_func(module: &Module, target: &TargetProperties) {
+        build_context_function(
+            module,
+            MidiQueueValue::get_advance_func(module),
+            target,
+            &|ctx: BuilderContext| {
+                let check_block = ctx.func.append_basic_block("check");
+                let run_block = ctx.func.append_basic_block("run");
+                let end_block = ctx.func.append_basic_block("end");
+
+                let queue =
+                    MidiQueueValue::new(ctx.func.get_nth_param(0).unwrap().into_pointer_value());
+                let frames = ctx.func.get_nth_param(1).unwrap().into_int_value();
+                let const_zero = ctx.context.i32_type().const_int(0, false);
+                let const_one = ctx.context.i32_type().const_int(1, false);
+
+                let count_ptr = queue.get_count_ptr(ctx.b);
+                let count = ctx.b.build_load(&count_ptr, "count").into_int_value();
+                let head_ptr = queue.get_head_ptr(ctx.b);
+                let head = ctx.b.build_load(&head_ptr, "head").into_int_value();
+                let index_ptr = ctx
+                    .allocb
+                    .build_alloca(&ctx.context.i32_type(), "index.ptr");
+                ctx.b.build_store(&index_ptr, &head);
+                ctx.b.build_unconditional_branch(&check_block);
+
+                // the events that weren't delivered are all due after the block, so their offsets
+                // can't underflow
+                ctx.b.position_at_end(&check_block);
+                let index = ctx.b.build_load(&index_ptr, "index").into_int_value();
+                let has_entry =
+                    ctx.b
+                        .build_int_compare(IntPredicate::ULT, index, count, "hasentry");
+                ctx.b
+                    .build_conditional_branch(&has_entry, &run_block, &end_block);
+
+                ctx.b.position_at_end(&run_block);
+                let entry_ptr = queue.get_entry_ptr(ctx.b, index);
+                let entry = ctx.b.build_load(&entry_ptr, "entry");
+                let dest_index = ctx.b.build_int_nuw_sub(index, head, "destindex");
+                let dest_ptr = queue.get_entry_ptr(ctx.b, dest_index);
+                ctx.b.build_store(&dest_ptr, &entry);
+                let dest_offset_ptr = queue.get_entry_offset_ptr(ctx.b, dest_index);
+                let dest_offset = ctx
+                    .b
+                    .build_load(&dest_offset_ptr, "offset")
+                    .into_int_value();
+                let new_offset = ctx.b.build_int_nuw_sub(dest_offset, frames, "newoffset");
+                ctx.b.build_store(&dest_offset_ptr, &new_offset);
+                let next_index = ctx.b.build_int_nuw_add(index, const_one, "nextindex");
+                ctx.b.build_store(&index_ptr, &next_index);
+                ctx.b.build_unconditional_branch(&check_block);
+
+                ctx.b.position_at_end(&end_block);
+                let new_count = ctx.b.build_int_nuw_sub(count, head, "newcount");
+                ctx.b.build_store(&count_ptr, &new_count);
+                ctx.b.build_store(&head_ptr, &const_zero);
+                ctx.b.build_return(None);
+            },
+        );
+    }
+}
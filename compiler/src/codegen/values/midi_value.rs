@@ -1,5 +1,6 @@
 use super::MidiEventValue;
-use crate::codegen::{build_context_function, util, BuilderContext, TargetProperties};
+use crate::codegen::{build_context_function, intrinsics, util, BuilderContext, TargetProperties};
+use crate::mir::MIDI_EVENT_COUNT;
 use inkwell::attribute::AttrKind;
 use inkwell::builder::Builder;
 use inkwell::context::Context;
@@ -9,8 +10,6 @@ use inkwell::values::{FunctionValue, IntValue, PointerValue};
 use inkwell::AddressSpace;
 use inkwell::IntPredicate;
 
-pub const MIDI_EVENT_COUNT: u8 = 16;
-
 #[derive(Debug, Clone)]
 pub struct MidiValue {
     pub val: PointerValue,
@@ -41,6 +40,36 @@ impl MidiValue {
         util::copy_ptr(builder, module, self.val, other.val)
     }
 
+    /// Copies the count and the events in use to another value. Events past the count are left
+    /// as they were, so this is cheaper than `copy_to` when there are usually few events.
+    pub fn copy_events_to(&self, builder: &mut Builder, module: &Module, other: &MidiValue) {
+        let context = module.get_context();
+        let count = self.get_count(builder);
+        other.set_count(builder, count);
+
+        let event_size = MidiEventValue::get_type(&context).size_of().unwrap();
+        let events_size = builder.build_int_nuw_mul(
+            builder.build_int_z_extend(count, context.i64_type(), ""),
+            event_size,
+            "midi.events.size",
+        );
+        let i8_ptr_type = context.i8_type().ptr_type(AddressSpace::Generic);
+        let src_ptr = self.get_events_ptr(builder);
+        let dest_ptr = other.get_events_ptr(builder);
+        builder.build_call(
+            &intrinsics::memcpy(module),
+            &[
+                &builder.build_pointer_cast(dest_ptr, i8_ptr_type, ""),
+                &builder.build_pointer_cast(src_ptr, i8_ptr_type, ""),
+                &events_size,
+                &context.i32_type().const_int(0, false),
+                &context.bool_type().const_int(0, false),
+            ],
+            "",
+            false,
+        );
+    }
+
     pub fn get_count_ptr(&self, builder: &mut Builder) -> PointerValue {
         unsafe { builder.build_struct_gep(&self.val, 0, "midi.count.ptr") }
     }
@@ -1,15 +1,19 @@
 mod array_value;
 mod midi_event_value;
+mod midi_queue_value;
 mod midi_value;
 mod num_value;
 mod tuple_value;
 
-pub use self::array_value::{ArrayValue, ARRAY_CAPACITY};
+pub use self::array_value::ArrayValue;
 pub use self::midi_event_value::MidiEventValue;
+pub use self::midi_queue_value::{MidiQueueValue, MIDI_QUEUE_CAPACITY};
 pub use self::midi_value::MidiValue;
 pub use self::num_value::NumValue;
 pub use self::tuple_value::TupleValue;
+pub use crate::mir::{ARRAY_CAPACITY, MIDI_EVENT_COUNT};
 
+use crate::codegen::TargetProperties;
 use crate::mir::{ConstantValue, VarType};
 use inkwell::context::Context;
 use inkwell::types::{BasicType, StructType};
@@ -32,6 +36,30 @@ pub fn remap_type(context: &Context, mir_type: &VarType) -> StructType {
     }
 }
 
+/// Maps a type to the type it's stored as in value groups, sockets and portals, which may use a
+/// different precision to the values used while generating code.
+pub fn remap_storage_type(
+    context: &Context,
+    target: &TargetProperties,
+    mir_type: &VarType,
+) -> StructType {
+    match mir_type {
+        VarType::Num => NumValue::get_storage_type(context, target.storage_precision),
+        VarType::Tuple(inner_types) => {
+            let inner_structs: Vec<_> = inner_types
+                .iter()
+                .map(|t| remap_storage_type(context, target, t))
+                .collect();
+            let inner_types: Vec<_> = inner_structs.iter().map(|t| t as &BasicType).collect();
+            TupleValue::get_type(context, &inner_types)
+        }
+        VarType::Array(inner_type) => {
+            ArrayValue::get_type(context, remap_storage_type(context, target, &inner_type))
+        }
+        _ => remap_type(context, mir_type),
+    }
+}
+
 pub fn pass_type_by_val(mir_type: &VarType) -> bool {
     match mir_type {
         VarType::Num => true,
@@ -55,3 +83,29 @@ pub fn remap_constant(context: &Context, value: &ConstantValue) -> BasicValueEnu
         }
     }
 }
+
+pub fn remap_storage_constant(
+    context: &Context,
+    target: &TargetProperties,
+    value: &ConstantValue,
+) -> BasicValueEnum {
+    match value {
+        ConstantValue::Num(num) => NumValue::get_storage_const(
+            context,
+            target.storage_precision,
+            num.left,
+            num.right,
+            num.form as u8,
+        )
+        .into(),
+        ConstantValue::Tuple(tuple) => {
+            let values: Vec<_> = tuple
+                .items
+                .iter()
+                .map(|val| remap_storage_constant(context, target, val))
+                .collect();
+            let value_refs: Vec<_> = values.iter().map(|val| val as &BasicValue).collect();
+            TupleValue::get_const(context, &value_refs).into()
+        }
+    }
+}
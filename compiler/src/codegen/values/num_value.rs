@@ -1,8 +1,8 @@
-use crate::codegen::util;
+use crate::codegen::{util, StoragePrecision};
 use inkwell::builder::Builder;
 use inkwell::context::Context;
 use inkwell::module::Module;
-use inkwell::types::StructType;
+use inkwell::types::{StructType, VectorType};
 use inkwell::values::{IntValue, PointerValue, StructValue, VectorValue};
 use std::borrow::Borrow;
 
@@ -19,6 +19,35 @@ impl NumValue {
         )
     }
 
+    /// The type of a number stored in a value group, socket or portal. Values are converted to and
+    /// from double precision when they're read and written.
+    pub fn get_storage_type(context: &Context, precision: StoragePrecision) -> StructType {
+        context.struct_type(
+            &[
+                &precision.float_type(context).vec_type(2),
+                &context.i8_type(),
+            ],
+            false,
+        )
+    }
+
+    pub fn get_storage_const(
+        context: &Context,
+        precision: StoragePrecision,
+        left: f64,
+        right: f64,
+        form: u8,
+    ) -> StructValue {
+        let float_type = precision.float_type(context);
+        NumValue::get_storage_type(context, precision).const_named_struct(&[
+            &VectorType::const_vector(&[
+                &float_type.const_float(left),
+                &float_type.const_float(right),
+            ]),
+            &context.i8_type().const_int(u64::from(form), false),
+        ])
+    }
+
     pub fn new(val: PointerValue) -> Self {
         NumValue { val }
     }
@@ -66,12 +95,22 @@ impl NumValue {
 
     pub fn get_vec(&self, builder: &mut Builder) -> VectorValue {
         let vec = self.get_vec_ptr(builder);
-        builder.build_load(&vec, "num.vec").into_vector_value()
+        let stored_vec = builder.build_load(&vec, "num.vec").into_vector_value();
+        let context = self.val.get_type().get_context();
+        util::convert_float_vec(
+            builder,
+            stored_vec,
+            context.f64_type().vec_type(2),
+            "num.vec.converted",
+        )
     }
 
     pub fn set_vec(&self, builder: &mut Builder, value: VectorValue) {
         let vec = self.get_vec_ptr(builder);
-        builder.build_store(&vec, &value);
+        let stored_type = vec.get_type().element_type().into_vector_type();
+        let stored_value =
+            util::convert_float_vec(builder, value, stored_type, "num.vec.converted");
+        builder.build_store(&vec, &stored_value);
     }
 
     pub fn get_form_ptr(&self, builder: &mut Builder) -> PointerValue {
@@ -0,0 +1,325 @@
+use crate::frontend::exporter::{self, export_config};
+use crate::frontend::Transaction;
+use crate::{ast, codegen, util};
+use std::slice;
+
+/// Exports a transaction. Object output for wasm32 is linked into a WebAssembly module that can be
+/// loaded on its own, which needs `wasm-ld` on the path. Returns false if anything couldn't be
+/// built, linked or written.
+#[no_mangle]
+pub unsafe extern "C" fn maxim_export_transaction(
+    config: *const export_config::ExportConfig,
+    transaction: *mut Transaction,
+) -> bool {
+    let owned_transaction = Box::from_raw(transaction);
+    exporter::export(&*config, *owned_transaction).is_ok()
+}
+
+#[no_mangle]
+pub extern "C" fn maxim_create_audio_config(
+    sample_rate: f64,
+    bpm: f64,
+) -> *mut export_config::AudioConfig {
+    Box::into_raw(Box::new(export_config::AudioConfig { sample_rate, bpm }))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn maxim_destroy_audio_config(config: *mut export_config::AudioConfig) {
+    Box::from_raw(config);
+    // box will be dropped here
+}
+
+#[no_mangle]
+pub extern "C" fn maxim_create_target_config(
+    platform: export_config::TargetPlatform,
+    instruction_set: export_config::TargetInstructionSet,
+    feature_level: util::feature_level::FeatureLevel,
+) -> *mut export_config::TargetConfig {
+    Box::into_raw(Box::new(export_config::TargetConfig {
+        platform,
+        instruction_set,
+        feature_level,
+    }))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn maxim_destroy_target_config(config: *mut export_config::TargetConfig) {
+    Box::from_raw(config);
+    // box will be dropped here
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn maxim_create_code_config(
+    optimization_level: codegen::OptimizationLevel,
+    c_instrument_prefix: *const std::os::raw::c_char,
+    include_instrument: bool,
+    include_library: bool,
+) -> *mut export_config::CodeConfig {
+    let instrument_prefix = std::ffi::CStr::from_ptr(c_instrument_prefix)
+        .to_str()
+        .unwrap()
+        .to_string();
+    Box::into_raw(Box::new(export_config::CodeConfig {
+        optimization_level,
+        instrument_prefix,
+        include_instrument,
+        include_library,
+        control_rate_divisor: codegen::DEFAULT_CONTROL_RATE_DIVISOR,
+        storage_precision: codegen::StoragePrecision::Double,
+        math_accuracy: codegen::MathAccuracy::Balanced,
+        max_delay_seconds: codegen::DEFAULT_MAX_DELAY_SECONDS,
+        denormal_mode: codegen::DenormalMode::FlushToZero,
+        sanitize_outputs: false,
+        instanced: false,
+    }))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn maxim_code_config_set_control_rate_divisor(
+    config: *mut export_config::CodeConfig,
+    divisor: u32,
+) {
+    (*config).control_rate_divisor = divisor.max(1);
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn maxim_code_config_set_storage_precision(
+    config: *mut export_config::CodeConfig,
+    storage_precision: codegen::StoragePrecision,
+) {
+    (*config).storage_precision = storage_precision;
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn maxim_code_config_set_math_accuracy(
+    config: *mut export_config::CodeConfig,
+    math_accuracy: codegen::MathAccuracy,
+) {
+    (*config).math_accuracy = math_accuracy;
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn maxim_code_config_set_max_delay_seconds(
+    config: *mut export_config::CodeConfig,
+    max_delay_seconds: f64,
+) {
+    (*config).max_delay_seconds = max_delay_seconds.max(0.);
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn maxim_code_config_set_denormal_mode(
+    config: *mut export_config::CodeConfig,
+    denormal_mode: codegen::DenormalMode,
+) {
+    (*config).denormal_mode = denormal_mode;
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn maxim_code_config_set_sanitize_outputs(
+    config: *mut export_config::CodeConfig,
+    sanitize_outputs: bool,
+) {
+    (*config).sanitize_outputs = sanitize_outputs;
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn maxim_code_config_set_instanced(
+    config: *mut export_config::CodeConfig,
+    instanced: bool,
+) {
+    (*config).instanced = instanced;
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn maxim_destroy_code_config(config: *mut export_config::CodeConfig) {
+    Box::from_raw(config);
+    // box will be dropped here
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn maxim_create_object_output_config(
+    format: export_config::ObjectFormat,
+    c_location: *const std::os::raw::c_char,
+) -> *mut export_config::ObjectOutputConfig {
+    let location =
+        std::path::Path::new(std::ffi::CStr::from_ptr(c_location).to_str().unwrap()).to_path_buf();
+    Box::into_raw(Box::new(export_config::ObjectOutputConfig {
+        format,
+        location,
+    }))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn maxim_destroy_object_output_config(
+    config: *mut export_config::ObjectOutputConfig,
+) {
+    Box::from_raw(config);
+    // box will be dropped here
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn maxim_create_meta_output_config(
+    format: export_config::MetaFormat,
+    c_location: *const std::os::raw::c_char,
+    portal_names: *const *const std::os::raw::c_char,
+    portal_name_count: usize,
+) -> *mut export_config::MetaOutputConfig {
+    let location =
+        std::path::Path::new(std::ffi::CStr::from_ptr(c_location).to_str().unwrap()).to_path_buf();
+    let portal_names = (0..portal_name_count)
+        .map(|portal_index| {
+            let portal_name_ptr = *portal_names.add(portal_index);
+            std::ffi::CStr::from_ptr(portal_name_ptr)
+                .to_str()
+                .unwrap()
+                .to_string()
+        })
+        .collect();
+
+    Box::into_raw(Box::new(export_config::MetaOutputConfig {
+        format,
+        location,
+        portal_names,
+        params: Vec::new(),
+    }))
+}
+
+/// Adds an exported param to the meta output. Returns false without adding it if `unit` isn't a
+/// valid form.
+#[no_mangle]
+pub unsafe extern "C" fn maxim_meta_output_config_add_param(
+    config: *mut export_config::MetaOutputConfig,
+    c_name: *const std::os::raw::c_char,
+    min: f64,
+    max: f64,
+    default: f64,
+    unit: u8,
+) -> bool {
+    let unit = match ast::FormType::from_u8(unit) {
+        Some(unit) => unit,
+        None => return false,
+    };
+    let name = std::ffi::CStr::from_ptr(c_name)
+        .to_str()
+        .unwrap()
+        .to_string();
+    (*config).params.push(export_config::ExportParam {
+        name,
+        min,
+        max,
+        default,
+        unit,
+    });
+    true
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn maxim_destroy_meta_output_config(
+    config: *mut export_config::MetaOutputConfig,
+) {
+    Box::from_raw(config);
+    // box will be dropped here
+}
+
+#[no_mangle]
+pub extern "C" fn maxim_create_song_config(length: u32) -> *mut export_config::SongConfig {
+    Box::into_raw(Box::new(export_config::SongConfig {
+        events: Vec::new(),
+        length,
+        loop_range: None,
+    }))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn maxim_read_midi_file(
+    data: *const u8,
+    len: usize,
+    sample_rate: f64,
+    portal: usize,
+) -> *mut export_config::SongConfig {
+    match exporter::read_midi_file(slice::from_raw_parts(data, len), sample_rate, portal) {
+        Ok(song) => Box::into_raw(Box::new(song)),
+        Err(_) => std::ptr::null_mut(),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn maxim_song_config_add_event(
+    config: *mut export_config::SongConfig,
+    frame: u32,
+    portal: usize,
+    event: u8,
+    channel: u8,
+    note: u8,
+    param: u8,
+) {
+    (*config).events.push(export_config::SongEvent {
+        frame,
+        portal,
+        event,
+        channel,
+        note,
+        param,
+    });
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn maxim_song_config_set_loop(
+    config: *mut export_config::SongConfig,
+    loop_start: u32,
+    loop_end: u32,
+) {
+    (*config).loop_range = Some((loop_start, loop_end));
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn maxim_destroy_song_config(config: *mut export_config::SongConfig) {
+    Box::from_raw(config);
+    // box will be dropped here
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn maxim_create_export_config(
+    audio: *mut export_config::AudioConfig,
+    target: *mut export_config::TargetConfig,
+    code: *mut export_config::CodeConfig,
+    object_or_null: *mut export_config::ObjectOutputConfig,
+    meta_or_null: *mut export_config::MetaOutputConfig,
+) -> *mut export_config::ExportConfig {
+    let audio = *Box::from_raw(audio);
+    let target = *Box::from_raw(target);
+    let code = *Box::from_raw(code);
+    let object = if object_or_null == std::ptr::null_mut() {
+        None
+    } else {
+        Some(*Box::from_raw(object_or_null))
+    };
+    let meta = if meta_or_null == std::ptr::null_mut() {
+        None
+    } else {
+        Some(*Box::from_raw(meta_or_null))
+    };
+
+    Box::into_raw(Box::new(export_config::ExportConfig {
+        audio,
+        target,
+        code,
+        object,
+        meta,
+        song: None,
+    }))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn maxim_export_config_set_song(
+    config: *mut export_config::ExportConfig,
+    song: *mut export_config::SongConfig,
+) {
+    (*config).song = Some(*Box::from_raw(song));
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn maxim_destroy_export_config(config: *mut export_config::ExportConfig) {
+    Box::from_raw(config);
+    // box will be dropped here
+}
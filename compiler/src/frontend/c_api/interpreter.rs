@@ -0,0 +1,124 @@
+use crate::ast::FormType;
+use crate::codegen;
+use crate::frontend::Transaction;
+use crate::interpreter::{Interpreter, Num, Value};
+
+/// Creates an interpreter, which runs transactions without generating any code. It's available
+/// in builds without the `jit` feature, where it's the only way to run them.
+#[no_mangle]
+pub extern "C" fn maxim_create_interpreter(
+    include_ui: bool,
+    math_accuracy: codegen::MathAccuracy,
+) -> *mut Interpreter {
+    let mut interpreter = Interpreter::new();
+    interpreter.set_include_ui(include_ui);
+    interpreter.set_math_accuracy(math_accuracy);
+    Box::into_raw(Box::new(interpreter))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn maxim_destroy_interpreter(interpreter: *mut Interpreter) {
+    Box::from_raw(interpreter);
+    // box will be dropped here
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn maxim_interpreter_allocate_id(interpreter: *mut Interpreter) -> u64 {
+    use crate::mir::IdAllocator;
+    (*interpreter).alloc_id()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn maxim_interpreter_commit(
+    interpreter: *mut Interpreter,
+    transaction: *mut Transaction,
+) {
+    let owned_transaction = Box::from_raw(transaction);
+    (*interpreter).commit(*owned_transaction)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn maxim_interpreter_run_update(interpreter: *mut Interpreter) {
+    (*interpreter).run_update();
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn maxim_interpreter_set_bpm(interpreter: *mut Interpreter, bpm: f64) {
+    (*interpreter).set_bpm(bpm);
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn maxim_interpreter_get_bpm(interpreter: *const Interpreter) -> f64 {
+    (*interpreter).get_bpm()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn maxim_interpreter_set_sample_rate(
+    interpreter: *mut Interpreter,
+    sample_rate: f64,
+) {
+    (*interpreter).set_sample_rate(sample_rate);
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn maxim_interpreter_get_sample_rate(interpreter: *const Interpreter) -> f64 {
+    (*interpreter).get_sample_rate()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn maxim_interpreter_set_control_rate_divisor(
+    interpreter: *mut Interpreter,
+    divisor: u32,
+) {
+    (*interpreter).set_control_rate_divisor(divisor.max(1));
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn maxim_interpreter_set_max_delay_seconds(
+    interpreter: *mut Interpreter,
+    max_delay_seconds: f64,
+) {
+    (*interpreter).set_max_delay_seconds(max_delay_seconds.max(0.));
+}
+
+/// Reads the left and right channels of a num root socket. Returns false, leaving the outputs
+/// alone, if the socket doesn't hold a num.
+#[no_mangle]
+pub unsafe extern "C" fn maxim_interpreter_get_socket_num(
+    interpreter: *const Interpreter,
+    socket: usize,
+    left_out: *mut f64,
+    right_out: *mut f64,
+) -> bool {
+    match (*interpreter).get_socket(socket) {
+        Value::Num(num) => {
+            *left_out = num.left;
+            *right_out = num.right;
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Writes a num root socket. Returns false if the socket doesn't hold a num or the form isn't
+/// valid.
+#[no_mangle]
+pub unsafe extern "C" fn maxim_interpreter_set_socket_num(
+    interpreter: *mut Interpreter,
+    socket: usize,
+    left: f64,
+    right: f64,
+    form: u8,
+) -> bool {
+    let form = match FormType::from_u8(form) {
+        Some(form) => form,
+        None => return false,
+    };
+    match (*interpreter).get_socket(socket) {
+        Value::Num(_) => {
+            (*interpreter).set_socket(socket, Value::Num(Num::new(left, right, form)));
+            true
+        }
+        _ => false,
+    }
+}
@@ -1,174 +1,25 @@
-use super::{exporter, value_reader, Runtime, Transaction};
-use crate::frontend::exporter::export_config;
-use crate::util::feature_level::{get_target_feature_string, FEATURE_LEVEL};
-use crate::{ast, codegen, mir, parser, pass, util, CompileError};
-use inkwell::{orc, targets};
-use std::os::raw::c_void;
+#[cfg(feature = "jit")]
+mod export;
+mod interpreter;
+#[cfg(feature = "jit")]
+mod runtime;
+
+#[cfg(feature = "jit")]
+pub use self::export::*;
+pub use self::interpreter::*;
+#[cfg(feature = "jit")]
+pub use self::runtime::*;
+
+use crate::frontend::Transaction;
+use crate::{ast, mir, parser, pass, util, CompileError};
 use std::slice;
 
-#[no_mangle]
-pub extern "C" fn maxim_initialize() {
-    targets::Target::initialize_native(&targets::InitializationConfig::default()).unwrap();
-    orc::Orc::link_in_jit();
-}
-
 #[no_mangle]
 pub unsafe extern "C" fn maxim_destroy_string(string: *mut std::os::raw::c_char) {
     std::ffi::CString::from_raw(string);
     // string will be dropped here
 }
 
-#[no_mangle]
-pub unsafe extern "C" fn maxim_create_runtime(include_ui: bool) -> *mut Runtime {
-    // Create a fake target machine to get the current triple and CPU, then create one with our
-    // desired feature set.
-    // Todo: there's probably a better way to do this?
-    let temp_machine = targets::TargetMachine::select();
-    let current_triple = temp_machine.get_triple().to_str().unwrap();
-    let current_cpu = temp_machine.get_cpu().to_str().unwrap();
-    let target = targets::Target::from_triple(current_triple).unwrap();
-    let machine = target
-        .create_target_machine(
-            current_triple,
-            current_cpu,
-            &get_target_feature_string(*FEATURE_LEVEL),
-            codegen::OptimizationLevel::Editor
-                .into_specification()
-                .llvm_level,
-            targets::RelocMode::Default,
-            targets::CodeModel::Default,
-        )
-        .unwrap();
-
-    let target =
-        codegen::TargetProperties::new(include_ui, codegen::OptimizationLevel::Editor, machine);
-    Box::into_raw(Box::new(Runtime::new(target)))
-}
-
-#[no_mangle]
-pub unsafe extern "C" fn maxim_destroy_runtime(runtime: *mut Runtime) {
-    Box::from_raw(runtime);
-    // box will be dropped here
-}
-
-#[no_mangle]
-pub unsafe extern "C" fn maxim_allocate_id(runtime: *mut Runtime) -> u64 {
-    use crate::mir::IdAllocator;
-    (*runtime).alloc_id()
-}
-
-#[no_mangle]
-pub unsafe extern "C" fn maxim_export_transaction(
-    config: *const export_config::ExportConfig,
-    transaction: *mut Transaction,
-) -> bool {
-    let owned_transaction = Box::from_raw(transaction);
-    exporter::export(&*config, *owned_transaction).is_ok()
-}
-
-#[no_mangle]
-pub unsafe extern "C" fn maxim_run_update(runtime: *const Runtime) {
-    (*runtime).run_update();
-}
-
-#[no_mangle]
-pub unsafe extern "C" fn maxim_set_bpm(runtime: *mut Runtime, bpm: f64) {
-    (*runtime).set_bpm(bpm);
-}
-
-#[no_mangle]
-pub unsafe extern "C" fn maxim_get_bpm(runtime: *const Runtime) -> f64 {
-    (*runtime).get_bpm()
-}
-
-#[no_mangle]
-pub unsafe extern "C" fn maxim_set_sample_rate(runtime: *mut Runtime, sample_rate: f64) {
-    (*runtime).set_sample_rate(sample_rate);
-}
-
-#[no_mangle]
-pub unsafe extern "C" fn maxim_get_sample_rate(runtime: *const Runtime) -> f64 {
-    (*runtime).get_sample_rate()
-}
-
-#[no_mangle]
-pub unsafe extern "C" fn maxim_get_profile_times_ptr(runtime: *const Runtime) -> *mut u64 {
-    (*runtime).get_profile_times_ptr()
-}
-
-#[no_mangle]
-pub unsafe extern "C" fn maxim_commit(runtime: *mut Runtime, transaction: *mut Transaction) {
-    let owned_transaction = Box::from_raw(transaction);
-    (*runtime).commit(*owned_transaction)
-}
-
-#[no_mangle]
-pub unsafe extern "C" fn maxim_is_node_extracted(
-    runtime: *const Runtime,
-    surface: u64,
-    node: usize,
-) -> bool {
-    (*runtime).is_node_extracted(surface, node)
-}
-
-#[no_mangle]
-pub unsafe extern "C" fn maxim_convert_num(
-    runtime: *const Runtime,
-    result: *mut c_void,
-    target_form: i8,
-    num: *const c_void,
-) {
-    (*runtime).convert_num(result, target_form, num)
-}
-
-#[no_mangle]
-pub unsafe extern "C" fn maxim_get_portal_ptr(
-    runtime: *const Runtime,
-    portal: usize,
-) -> *mut c_void {
-    (*runtime).get_portal_ptr(portal)
-}
-
-#[no_mangle]
-pub unsafe extern "C" fn maxim_get_root_ptr(runtime: *const Runtime) -> *mut c_void {
-    (*runtime).get_root_ptr()
-}
-
-#[no_mangle]
-pub unsafe extern "C" fn maxim_get_node_ptr(
-    runtime: *const Runtime,
-    surface: u64,
-    surface_ptr: *mut c_void,
-    node: usize,
-) -> *mut c_void {
-    value_reader::get_node_ptr(&*runtime, surface, surface_ptr, node)
-}
-
-#[no_mangle]
-pub unsafe extern "C" fn maxim_get_extracted_bitmask_ptr(
-    runtime: *const Runtime,
-    surface: u64,
-    surface_ptr: *mut c_void,
-    node: usize,
-) -> *const u32 {
-    value_reader::get_node_active_bitmap_ptr(&*runtime, surface, surface_ptr, node)
-}
-
-#[no_mangle]
-pub extern "C" fn maxim_get_surface_ptr(node_ptr: *mut c_void) -> *mut c_void {
-    value_reader::get_surface_ptr(node_ptr)
-}
-
-#[no_mangle]
-pub unsafe extern "C" fn maxim_get_control_ptrs(
-    runtime: *const Runtime,
-    block: u64,
-    node_ptr: *mut c_void,
-    control: usize,
-) -> value_reader::ControlPointers {
-    value_reader::get_control_ptrs(&*runtime, block, node_ptr, control)
-}
-
 #[no_mangle]
 pub extern "C" fn maxim_create_transaction() -> *mut Transaction {
     Box::into_raw(Box::new(Transaction::new(None, Vec::new(), Vec::new())))
@@ -359,6 +210,23 @@ pub unsafe extern "C" fn maxim_build_value_group(
         .push(mir::ValueGroup::new(*owned_vartype, *owned_source));
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn maxim_build_param_value_group(
+    surface: *mut mir::Surface,
+    vartype: *mut mir::VarType,
+    source: *mut mir::ValueGroupSource,
+    param: usize,
+) {
+    let owned_vartype = Box::from_raw(vartype);
+    let owned_source = Box::from_raw(source);
+
+    (*surface).groups.push(mir::ValueGroup::new_param(
+        *owned_vartype,
+        *owned_source,
+        param,
+    ));
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn maxim_control_initializer_none() -> *mut mir::ControlInitializer {
     Box::into_raw(Box::new(mir::ControlInitializer::None))
@@ -433,6 +301,19 @@ pub unsafe extern "C" fn maxim_build_group_node(
     &mut (*surface).nodes[(*surface).nodes.len() - 1]
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn maxim_set_node_update_rate(node: *mut mir::Node, rate: mir::UpdateRate) {
+    (*node).update_rate = rate;
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn maxim_set_node_oversample_factor(
+    node: *mut mir::Node,
+    factor: mir::OversampleFactor,
+) {
+    (*node).oversample_factor = factor;
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn maxim_build_value_socket(
     node: *mut mir::Node,
@@ -552,156 +433,6 @@ pub unsafe extern "C" fn maxim_control_get_read(control: *const mir::block::Cont
     (*control).value_read
 }
 
-#[no_mangle]
-pub extern "C" fn maxim_create_audio_config(
-    sample_rate: f64,
-    bpm: f64,
-) -> *mut export_config::AudioConfig {
-    Box::into_raw(Box::new(export_config::AudioConfig { sample_rate, bpm }))
-}
-
-#[no_mangle]
-pub unsafe extern "C" fn maxim_destroy_audio_config(config: *mut export_config::AudioConfig) {
-    Box::from_raw(config);
-    // box will be dropped here
-}
-
-#[no_mangle]
-pub extern "C" fn maxim_create_target_config(
-    platform: export_config::TargetPlatform,
-    instruction_set: export_config::TargetInstructionSet,
-    feature_level: util::feature_level::FeatureLevel,
-) -> *mut export_config::TargetConfig {
-    Box::into_raw(Box::new(export_config::TargetConfig {
-        platform,
-        instruction_set,
-        feature_level,
-    }))
-}
-
-#[no_mangle]
-pub unsafe extern "C" fn maxim_destroy_target_config(config: *mut export_config::TargetConfig) {
-    Box::from_raw(config);
-    // box will be dropped here
-}
-
-#[no_mangle]
-pub unsafe extern "C" fn maxim_create_code_config(
-    optimization_level: codegen::OptimizationLevel,
-    c_instrument_prefix: *const std::os::raw::c_char,
-    include_instrument: bool,
-    include_library: bool,
-) -> *mut export_config::CodeConfig {
-    let instrument_prefix = std::ffi::CStr::from_ptr(c_instrument_prefix)
-        .to_str()
-        .unwrap()
-        .to_string();
-    Box::into_raw(Box::new(export_config::CodeConfig {
-        optimization_level,
-        instrument_prefix,
-        include_instrument,
-        include_library,
-    }))
-}
-
-#[no_mangle]
-pub unsafe extern "C" fn maxim_destroy_code_config(config: *mut export_config::CodeConfig) {
-    Box::from_raw(config);
-    // box will be dropped here
-}
-
-#[no_mangle]
-pub unsafe extern "C" fn maxim_create_object_output_config(
-    format: export_config::ObjectFormat,
-    c_location: *const std::os::raw::c_char,
-) -> *mut export_config::ObjectOutputConfig {
-    let location =
-        std::path::Path::new(std::ffi::CStr::from_ptr(c_location).to_str().unwrap()).to_path_buf();
-    Box::into_raw(Box::new(export_config::ObjectOutputConfig {
-        format,
-        location,
-    }))
-}
-
-#[no_mangle]
-pub unsafe extern "C" fn maxim_destroy_object_output_config(
-    config: *mut export_config::ObjectOutputConfig,
-) {
-    Box::from_raw(config);
-    // box will be dropped here
-}
-
-#[no_mangle]
-pub unsafe extern "C" fn maxim_create_meta_output_config(
-    format: export_config::MetaFormat,
-    c_location: *const std::os::raw::c_char,
-    portal_names: *const *const std::os::raw::c_char,
-    portal_name_count: usize,
-) -> *mut export_config::MetaOutputConfig {
-    let location =
-        std::path::Path::new(std::ffi::CStr::from_ptr(c_location).to_str().unwrap()).to_path_buf();
-    let portal_names = (0..portal_name_count)
-        .map(|portal_index| {
-            let portal_name_ptr = *portal_names.add(portal_index);
-            std::ffi::CStr::from_ptr(portal_name_ptr)
-                .to_str()
-                .unwrap()
-                .to_string()
-        })
-        .collect();
-
-    Box::into_raw(Box::new(export_config::MetaOutputConfig {
-        format,
-        location,
-        portal_names,
-    }))
-}
-
-#[no_mangle]
-pub unsafe extern "C" fn maxim_destroy_meta_output_config(
-    config: *mut export_config::MetaOutputConfig,
-) {
-    Box::from_raw(config);
-    // box will be dropped here
-}
-
-#[no_mangle]
-pub unsafe extern "C" fn maxim_create_export_config(
-    audio: *mut export_config::AudioConfig,
-    target: *mut export_config::TargetConfig,
-    code: *mut export_config::CodeConfig,
-    object_or_null: *mut export_config::ObjectOutputConfig,
-    meta_or_null: *mut export_config::MetaOutputConfig,
-) -> *mut export_config::ExportConfig {
-    let audio = *Box::from_raw(audio);
-    let target = *Box::from_raw(target);
-    let code = *Box::from_raw(code);
-    let object = if object_or_null == std::ptr::null_mut() {
-        None
-    } else {
-        Some(*Box::from_raw(object_or_null))
-    };
-    let meta = if meta_or_null == std::ptr::null_mut() {
-        None
-    } else {
-        Some(*Box::from_raw(meta_or_null))
-    };
-
-    Box::into_raw(Box::new(export_config::ExportConfig {
-        audio,
-        target,
-        code,
-        object,
-        meta,
-    }))
-}
-
-#[no_mangle]
-pub unsafe extern "C" fn maxim_destroy_export_config(config: *mut export_config::ExportConfig) {
-    Box::from_raw(config);
-    // box will be dropped here
-}
-
 #[no_mangle]
 pub unsafe extern "C" fn maxim_get_function_table_size() -> usize {
     mir::FUNCTION_TABLE.len()
@@ -0,0 +1,391 @@
+use crate::codegen;
+use crate::frontend::profile_report::{self, NodeProfile};
+use crate::frontend::{
+    math_accuracy, value_reader, AudioRuntime, DiskCache, Fault, Runtime, RuntimeSnapshot,
+    Transaction,
+};
+use crate::util::feature_level::{get_target_feature_string, FEATURE_LEVEL};
+use inkwell::{orc, targets};
+use std::os::raw::c_void;
+use std::slice;
+
+#[no_mangle]
+pub extern "C" fn maxim_initialize() {
+    let config = targets::InitializationConfig::default();
+    targets::Target::initialize_native(&config).unwrap();
+
+    // exports can target any of the supported instruction sets from any host
+    targets::Target::initialize_x86(&config);
+    targets::Target::initialize_arm(&config);
+    targets::Target::initialize_aarch64(&config);
+    #[cfg(feature = "webassembly")]
+    targets::Target::initialize_webassembly(&config);
+    orc::Orc::link_in_jit();
+}
+
+#[no_mangle]
+pub extern "C" fn maxim_supports_webassembly() -> bool {
+    cfg!(feature = "webassembly")
+}
+
+pub fn create_host_machine() -> targets::TargetMachine {
+    // Create a fake target machine to get the current triple and CPU, then create one with our
+    // desired feature set.
+    // Todo: there's probably a better way to do this?
+    let temp_machine = targets::TargetMachine::select();
+    let current_triple = temp_machine.get_triple().to_str().unwrap();
+    let current_cpu = temp_machine.get_cpu().to_str().unwrap();
+    let target = targets::Target::from_triple(current_triple).unwrap();
+    target
+        .create_target_machine(
+            current_triple,
+            current_cpu,
+            &get_target_feature_string(*FEATURE_LEVEL),
+            codegen::OptimizationLevel::Editor
+                .into_specification()
+                .llvm_level,
+            targets::RelocMode::Default,
+            targets::CodeModel::Default,
+        )
+        .unwrap()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn maxim_create_runtime(
+    include_ui: bool,
+    math_accuracy: codegen::MathAccuracy,
+    sanitize_outputs: bool,
+    profile_nodes: bool,
+    backend: codegen::CodegenBackend,
+) -> *mut Runtime {
+    let mut target = codegen::TargetProperties::new(
+        include_ui,
+        codegen::OptimizationLevel::Editor,
+        create_host_machine(),
+    );
+    target.math_accuracy = math_accuracy;
+    target.sanitize_outputs = sanitize_outputs;
+    target.profile_nodes = profile_nodes;
+    target.backend = backend;
+    Box::into_raw(Box::new(Runtime::new(target)))
+}
+
+/// Caches compiled code in a directory, limited to `max_size` bytes. Passing a null path stops
+/// caching. Returns false if the directory couldn't be opened.
+#[no_mangle]
+pub unsafe extern "C" fn maxim_set_disk_cache(
+    runtime: *mut Runtime,
+    c_path: *const std::os::raw::c_char,
+    max_size: u64,
+) -> bool {
+    if c_path.is_null() {
+        (*runtime).set_disk_cache(None);
+        return true;
+    }
+
+    let path = std::ffi::CStr::from_ptr(c_path).to_str().unwrap();
+    match DiskCache::open(path, max_size) {
+        Ok(disk_cache) => {
+            (*runtime).set_disk_cache(Some(disk_cache));
+            true
+        }
+        Err(_) => false,
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn maxim_measure_math_accuracy(
+    math_accuracy: codegen::MathAccuracy,
+) -> *mut std::os::raw::c_char {
+    let mut target = codegen::TargetProperties::new(
+        false,
+        codegen::OptimizationLevel::Editor,
+        create_host_machine(),
+    );
+    target.math_accuracy = math_accuracy;
+    let report: Vec<_> = math_accuracy::measure_math_accuracy(&target)
+        .iter()
+        .map(|result| result.to_string())
+        .collect();
+    std::ffi::CString::new(report.join("\n"))
+        .unwrap()
+        .into_raw()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn maxim_destroy_runtime(runtime: *mut Runtime) {
+    Box::from_raw(runtime);
+    // box will be dropped here
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn maxim_allocate_id(runtime: *mut Runtime) -> u64 {
+    use crate::mir::IdAllocator;
+    (*runtime).alloc_id()
+}
+
+/// The part of the runtime the audio thread uses, which is valid for as long as the runtime is.
+/// Its functions can be called while the runtime is preparing a commit on another thread.
+#[no_mangle]
+pub unsafe extern "C" fn maxim_get_audio_runtime(runtime: *const Runtime) -> *const AudioRuntime {
+    &**(*runtime).audio()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn maxim_run_update(audio: *const AudioRuntime) {
+    (*audio).run_update();
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn maxim_run_update_block(audio: *const AudioRuntime, frames: u32) -> u32 {
+    (*audio).run_update_block(frames)
+}
+
+#[no_mangle]
+pub extern "C" fn maxim_get_max_block_frames() -> u32 {
+    codegen::root::MAX_BLOCK_FRAMES
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn maxim_set_bpm(audio: *const AudioRuntime, bpm: f64) {
+    (*audio).set_bpm(bpm);
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn maxim_get_bpm(runtime: *const Runtime) -> f64 {
+    (*runtime).get_bpm()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn maxim_set_sample_rate(audio: *const AudioRuntime, sample_rate: f64) {
+    (*audio).set_sample_rate(sample_rate);
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn maxim_get_sample_rate(runtime: *const Runtime) -> f64 {
+    (*runtime).get_sample_rate()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn maxim_get_profile_times_ptr(runtime: *const Runtime) -> *mut u64 {
+    (*runtime).get_profile_times_ptr()
+}
+
+/// Copies up to `max_faults` faults into `faults`, returning how many were copied.
+#[no_mangle]
+pub unsafe extern "C" fn maxim_get_faults(
+    runtime: *const Runtime,
+    faults: *mut Fault,
+    max_faults: usize,
+) -> usize {
+    let runtime_faults = (*runtime).get_faults();
+    let copy_count = runtime_faults.len().min(max_faults);
+    std::ptr::copy_nonoverlapping(runtime_faults.as_ptr(), faults, copy_count);
+    copy_count
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn maxim_clear_faults(runtime: *mut Runtime) {
+    (*runtime).clear_faults()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn maxim_create_profile_report(
+    runtime: *const Runtime,
+) -> *mut Vec<NodeProfile> {
+    Box::into_raw(Box::new(profile_report::build_profile_report(&*runtime)))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn maxim_profile_report_len(report: *const Vec<NodeProfile>) -> usize {
+    (*report).len()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn maxim_profile_report_get(
+    report: *const Vec<NodeProfile>,
+    index: usize,
+) -> NodeProfile {
+    (*report)[index]
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn maxim_destroy_profile_report(report: *mut Vec<NodeProfile>) {
+    Box::from_raw(report);
+    // box will be dropped here
+}
+
+/// Serializes the state of every node in the runtime.
+#[no_mangle]
+pub unsafe extern "C" fn maxim_create_snapshot(runtime: *const Runtime) -> *mut Vec<u8> {
+    Box::into_raw(Box::new((*runtime).snapshot().to_bytes()))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn maxim_snapshot_len(snapshot: *const Vec<u8>) -> usize {
+    (*snapshot).len()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn maxim_snapshot_data(snapshot: *const Vec<u8>) -> *const u8 {
+    (*snapshot).as_ptr()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn maxim_destroy_snapshot(snapshot: *mut Vec<u8>) {
+    Box::from_raw(snapshot);
+    // box will be dropped here
+}
+
+/// Restores a serialized snapshot, returning false if it isn't a snapshot or its layout doesn't
+/// match the runtime's.
+#[no_mangle]
+pub unsafe extern "C" fn maxim_restore_snapshot(
+    runtime: *mut Runtime,
+    data: *const u8,
+    len: usize,
+) -> bool {
+    match RuntimeSnapshot::from_bytes(slice::from_raw_parts(data, len)) {
+        Some(snapshot) => (*runtime).restore(&snapshot).is_ok(),
+        None => false,
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn maxim_commit(runtime: *mut Runtime, transaction: *mut Transaction) {
+    let owned_transaction = Box::from_raw(transaction);
+    (*runtime).commit(*owned_transaction)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn maxim_prepare_commit(
+    runtime: *mut Runtime,
+    transaction: *mut Transaction,
+) -> bool {
+    let owned_transaction = Box::from_raw(transaction);
+    (*runtime).prepare(*owned_transaction)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn maxim_publish_commit(audio: *const AudioRuntime) -> bool {
+    (*audio).publish()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn maxim_reclaim_commit(runtime: *mut Runtime) -> bool {
+    (*runtime).reclaim()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn maxim_is_node_extracted(
+    runtime: *const Runtime,
+    surface: u64,
+    node: usize,
+) -> bool {
+    (*runtime).is_node_extracted(surface, node)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn maxim_is_socket_delayed(
+    runtime: *const Runtime,
+    surface: u64,
+    node: usize,
+    socket: usize,
+) -> bool {
+    (*runtime).is_socket_delayed(surface, node, socket)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn maxim_convert_num(
+    runtime: *const Runtime,
+    result: *mut c_void,
+    target_form: i8,
+    num: *const c_void,
+) {
+    (*runtime).convert_num(result, target_form, num)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn maxim_get_portal_ptr(
+    audio: *const AudioRuntime,
+    portal: usize,
+) -> *mut c_void {
+    (*audio).get_portal_ptr(portal)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn maxim_get_portal_buffer_ptr(
+    audio: *const AudioRuntime,
+    portal: usize,
+) -> *mut c_void {
+    (*audio).get_portal_buffer_ptr(portal)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn maxim_get_pending_portal_ptr(
+    runtime: *const Runtime,
+    portal: usize,
+) -> *mut c_void {
+    (*runtime).get_portal_ptr(portal)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn maxim_get_pending_portal_buffer_ptr(
+    runtime: *const Runtime,
+    portal: usize,
+) -> *mut c_void {
+    (*runtime).get_portal_buffer_ptr(portal)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn maxim_queue_midi(
+    audio: *const AudioRuntime,
+    portal: usize,
+    offset: u32,
+    event: u8,
+    channel: u8,
+    note: u8,
+    param: u8,
+) {
+    (*audio).queue_midi(portal, offset, event, channel, note, param)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn maxim_get_root_ptr(runtime: *const Runtime) -> *mut c_void {
+    (*runtime).get_root_ptr()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn maxim_get_node_ptr(
+    runtime: *const Runtime,
+    surface: u64,
+    surface_ptr: *mut c_void,
+    node: usize,
+) -> *mut c_void {
+    value_reader::get_node_ptr(&*runtime, surface, surface_ptr, node)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn maxim_get_extracted_bitmask_ptr(
+    runtime: *const Runtime,
+    surface: u64,
+    surface_ptr: *mut c_void,
+    node: usize,
+) -> *const u32 {
+    value_reader::get_node_active_bitmap_ptr(&*runtime, surface, surface_ptr, node)
+}
+
+#[no_mangle]
+pub extern "C" fn maxim_get_surface_ptr(node_ptr: *mut c_void) -> *mut c_void {
+    value_reader::get_surface_ptr(node_ptr)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn maxim_get_control_ptrs(
+    runtime: *const Runtime,
+    block: u64,
+    node_ptr: *mut c_void,
+    control: usize,
+) -> value_reader::ControlPointers {
+    value_reader::get_control_ptrs(&*runtime, block, node_ptr, control)
+}
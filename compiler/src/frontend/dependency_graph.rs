@@ -78,7 +78,7 @@ impl DependencyGraph {
         // build the new dep data
         for node in &surface.nodes {
             match node.data {
-                NodeData::Dummy => {}
+                NodeData::Dummy | NodeData::UnitDelay => {}
                 NodeData::Custom { block, .. } => depends_on_blocks.push(block),
                 NodeData::Group(surface) => depends_on_surfaces.push(surface),
                 NodeData::ExtractGroup { surface, .. } => depends_on_surfaces.push(surface),
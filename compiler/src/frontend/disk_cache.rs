@@ -0,0 +1,322 @@
+use crate::util::stable_hasher::StableHasher;
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fs;
+use std::hash::Hasher;
+use std::io;
+use std::path::PathBuf;
+
+const OBJECT_EXTENSION: &str = "o";
+const INDEX_FILE_NAME: &str = "index";
+
+/// Everything that goes into a compiled object, written out byte by byte. Objects are stored under
+/// a hash of their key, but the whole key is kept with them and compared when they're loaded, so
+/// a hash collision can't load the wrong code.
+#[derive(Debug, Clone)]
+pub struct CacheKey {
+    bytes: Vec<u8>,
+    hasher: StableHasher,
+}
+
+impl CacheKey {
+    pub fn new() -> Self {
+        CacheKey {
+            bytes: Vec::new(),
+            hasher: StableHasher::new(),
+        }
+    }
+
+    /// Writes the whole of another key into this one.
+    pub fn write_key(&mut self, key: &CacheKey) {
+        self.write_u64(key.bytes.len() as u64);
+        self.write(&key.bytes);
+    }
+}
+
+impl Default for CacheKey {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PartialEq for CacheKey {
+    fn eq(&self, other: &CacheKey) -> bool {
+        self.bytes == other.bytes
+    }
+}
+
+impl Eq for CacheKey {}
+
+impl Hasher for CacheKey {
+    /// A hash of the key, which names the object in the cache.
+    fn finish(&self) -> u64 {
+        self.hasher.finish()
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        self.bytes.extend_from_slice(bytes);
+        self.hasher.write(bytes);
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct CacheEntry {
+    size: u64,
+
+    /// When the object was last loaded or stored, counted in cache accesses.
+    last_used: u64,
+}
+
+/// A directory of compiled object files, keyed by everything that went into them. Each file holds
+/// the length of its key, the key and then the object. Once the files add up to more than the size
+/// limit, the least recently used ones are removed.
+///
+/// The cache is best-effort: objects that can't be read or written are treated as missing.
+#[derive(Debug)]
+pub struct DiskCache {
+    directory: PathBuf,
+    max_size: u64,
+    entries: HashMap<u64, CacheEntry>,
+    total_size: u64,
+    access_count: u64,
+    index_dirty: bool,
+}
+
+fn parse_key(name: &str) -> Option<u64> {
+    u64::from_str_radix(name, 16).ok()
+}
+
+/// Splits a cache file into the key it was stored with and its object.
+fn split_key(file: &[u8]) -> Option<(&[u8], &[u8])> {
+    if file.len() < 8 {
+        return None;
+    }
+    let (len_bytes, rest) = file.split_at(8);
+    let key_len = u64::from_le_bytes(len_bytes.try_into().unwrap());
+    if key_len > rest.len() as u64 {
+        return None;
+    }
+    Some(rest.split_at(key_len as usize))
+}
+
+impl DiskCache {
+    /// Opens the cache in a directory, creating the directory if it doesn't exist.
+    pub fn open(directory: impl Into<PathBuf>, max_size: u64) -> io::Result<Self> {
+        let directory = directory.into();
+        fs::create_dir_all(&directory)?;
+
+        // the index only records when objects were last used, the directory is the source of
+        // truth for which ones exist
+        let mut last_used = HashMap::new();
+        if let Ok(index) = fs::read_to_string(directory.join(INDEX_FILE_NAME)) {
+            for line in index.lines() {
+                let mut parts = line.split(' ');
+                if let (Some(key), Some(used)) = (parts.next(), parts.next()) {
+                    if let (Some(key), Ok(used)) = (parse_key(key), used.parse::<u64>()) {
+                        last_used.insert(key, used);
+                    }
+                }
+            }
+        }
+
+        let mut entries = HashMap::new();
+        for dir_entry in fs::read_dir(&directory)? {
+            let path = dir_entry?.path();
+            if path.extension().and_then(|extension| extension.to_str()) != Some(OBJECT_EXTENSION) {
+                continue;
+            }
+            let key = match path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(parse_key)
+            {
+                Some(key) => key,
+                None => continue,
+            };
+            if let Ok(metadata) = fs::metadata(&path) {
+                entries.insert(
+                    key,
+                    CacheEntry {
+                        size: metadata.len(),
+                        last_used: last_used.get(&key).cloned().unwrap_or(0),
+                    },
+                );
+            }
+        }
+
+        let mut cache = DiskCache {
+            directory,
+            max_size,
+            total_size: entries.values().map(|entry| entry.size).sum(),
+            access_count: entries
+                .values()
+                .map(|entry| entry.last_used)
+                .max()
+                .unwrap_or(0),
+            entries,
+            index_dirty: false,
+        };
+
+        // the limit might have shrunk since the cache was last used
+        cache.evict();
+        Ok(cache)
+    }
+
+    /// The combined size of every object in the cache, in bytes.
+    pub fn total_size(&self) -> u64 {
+        self.total_size
+    }
+
+    fn object_path(&self, key: u64) -> PathBuf {
+        self.directory
+            .join(format!("{:016x}.{}", key, OBJECT_EXTENSION))
+    }
+
+    /// Reads the object stored with a key, if there is one. An object stored with a different key
+    /// that has the same hash isn't loaded.
+    pub fn load(&mut self, key: &CacheKey) -> Option<Vec<u8>> {
+        let hash = key.finish();
+        if !self.entries.contains_key(&hash) {
+            return None;
+        }
+
+        let file = match fs::read(self.object_path(hash)) {
+            Ok(file) => file,
+            Err(_) => {
+                self.remove(hash);
+                return None;
+            }
+        };
+        let object = split_key(&file).and_then(|(stored_key, object)| {
+            if stored_key == key.bytes.as_slice() {
+                Some(object)
+            } else {
+                None
+            }
+        })?;
+
+        self.access_count += 1;
+        self.entries.get_mut(&hash).unwrap().last_used = self.access_count;
+        self.index_dirty = true;
+        Some(object.to_vec())
+    }
+
+    /// Stores an object with a key, replacing any object already stored with the same hash, then
+    /// evicts objects until the cache is within its size limit.
+    pub fn store(&mut self, key: &CacheKey, object: &[u8]) {
+        let hash = key.finish();
+        self.remove(hash);
+
+        let mut file = Vec::with_capacity(8 + key.bytes.len() + object.len());
+        file.extend_from_slice(&(key.bytes.len() as u64).to_le_bytes());
+        file.extend_from_slice(&key.bytes);
+        file.extend_from_slice(object);
+
+        // write to a temporary file first, so an interrupted write never leaves a partial object
+        let temp_path = self.directory.join(format!("{:016x}.tmp", hash));
+        let written = fs::write(&temp_path, &file)
+            .and_then(|_| fs::rename(&temp_path, self.object_path(hash)));
+        if written.is_err() {
+            let _ = fs::remove_file(&temp_path);
+            return;
+        }
+
+        self.access_count += 1;
+        self.entries.insert(
+            hash,
+            CacheEntry {
+                size: file.len() as u64,
+                last_used: self.access_count,
+            },
+        );
+        self.total_size += file.len() as u64;
+        self.index_dirty = true;
+        self.evict();
+        self.write_index();
+    }
+
+    fn remove(&mut self, key: u64) {
+        if let Some(entry) = self.entries.remove(&key) {
+            self.total_size -= entry.size;
+            self.index_dirty = true;
+            let _ = fs::remove_file(self.object_path(key));
+        }
+    }
+
+    fn evict(&mut self) {
+        while self.total_size > self.max_size {
+            let oldest_key = match self.entries.iter().min_by_key(|(_, entry)| entry.last_used) {
+                Some((&key, _)) => key,
+                None => break,
+            };
+            self.remove(oldest_key);
+        }
+    }
+
+    fn write_index(&mut self) {
+        if !self.index_dirty {
+            return;
+        }
+
+        let index: String = self
+            .entries
+            .iter()
+            .map(|(key, entry)| format!("{:016x} {}\n", key, entry.last_used))
+            .collect();
+        if fs::write(self.directory.join(INDEX_FILE_NAME), index).is_ok() {
+            self.index_dirty = false;
+        }
+    }
+}
+
+impl Drop for DiskCache {
+    fn drop(&mut self) {
+        self.write_index();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn open_empty_cache(name: &str) -> DiskCache {
+        let directory = env::temp_dir().join(format!("maxim-disk-cache-{}", name));
+        let _ = fs::remove_dir_all(&directory);
+        DiskCache::open(directory, 1 << 20).unwrap()
+    }
+
+    fn build_key(value: &str) -> CacheKey {
+        let mut key = CacheKey::new();
+        key.write(value.as_bytes());
+        key
+    }
+
+    #[test]
+    fn stored_objects_are_loaded_with_the_same_key() {
+        let mut cache = open_empty_cache("round-trip");
+        cache.store(&build_key("surface"), &[1, 2, 3]);
+
+        assert_eq!(cache.load(&build_key("surface")), Some(vec![1, 2, 3]));
+        assert_eq!(cache.load(&build_key("block")), None);
+    }
+
+    #[test]
+    fn objects_stored_with_a_colliding_hash_are_not_loaded() {
+        let mut cache = open_empty_cache("collision");
+        let stored_key = build_key("surface");
+        let requested_key = build_key("block");
+        cache.store(&stored_key, &[1, 2, 3]);
+
+        // move the object to where the requested key's object would be, as if their hashes were
+        // the same
+        fs::rename(
+            cache.object_path(stored_key.finish()),
+            cache.object_path(requested_key.finish()),
+        )
+        .unwrap();
+        let mut cache = DiskCache::open(cache.directory.clone(), cache.max_size).unwrap();
+
+        assert_eq!(cache.load(&requested_key), None);
+    }
+}
@@ -0,0 +1,1690 @@
+use super::build_instrument_module::PreparedTransaction;
+use super::build_meta_output::ModuleMetadata;
+use super::build_song::{get_song_entries, SONG_WAIT_QUEUE};
+use super::export_config::{AudioConfig, CodeConfig, SongConfig};
+use crate::ast::{
+    ControlField, ControlType, FormType, GraphField, OperatorType, RollField, UnaryOperation,
+};
+use crate::codegen::data_analyzer;
+use crate::codegen::root::{get_midi_portals, MAX_BLOCK_FRAMES};
+use crate::codegen::values::{ARRAY_CAPACITY, MIDI_EVENT_COUNT, MIDI_QUEUE_CAPACITY};
+use crate::codegen::{MathAccuracy, StoragePrecision};
+use crate::mir::block::{Function, Global, Statement};
+use crate::mir::{
+    Block, BlockRef, ConstantValue, ControlInitializer, Node, NodeData, OversampleFactor, Root,
+    Surface, SurfaceRef, UpdateRate, ValueGroupSource, VarType,
+};
+use crate::util::half_band::{get_side_coefficients, SIDE_TAPS};
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Write};
+
+/// Builds a self-contained C99 file that runs the instrument, for targets LLVM can't build objects
+/// for. The file exports the same functions as an object, and needs nothing apart from the C
+/// standard library to build.
+///
+/// The code follows the interpreter rather than the generated code, so it has the same
+/// differences:
+///
+///  - Denormals are left alone, and outputs aren't sanitized.
+///  - Default-initialized groups in extracted surfaces are kept per voice instead of shared.
+///
+/// Values are kept in double precision inside the instrument, `StoragePrecision` only applies to
+/// portals and delay buffers.
+pub fn build_c_source(
+    f: &mut dyn fmt::Write,
+    audio_config: &AudioConfig,
+    code_config: &CodeConfig,
+    song: Option<&SongConfig>,
+    module_meta: &ModuleMetadata,
+    transaction: &PreparedTransaction,
+    arena_size: u64,
+) -> fmt::Result {
+    let mut types = TypeRegistry::new();
+    let mut code = String::new();
+
+    let mut built_blocks = HashSet::new();
+    let mut param_surfaces = HashSet::new();
+    for surface_id in get_surface_order(&transaction.surfaces) {
+        let surface = &transaction.surfaces[&surface_id];
+        for node in &surface.nodes {
+            if let NodeData::Custom { block, .. } = node.data {
+                if built_blocks.insert(block) {
+                    build_block(
+                        &mut code,
+                        &mut types,
+                        code_config,
+                        &transaction.blocks[&block],
+                    )?;
+                }
+            }
+        }
+        build_surface(&mut code, &mut types, surface, &transaction.blocks)?;
+        if build_surface_params(&mut code, surface, &param_surfaces)? {
+            param_surfaces.insert(surface_id);
+        }
+    }
+    build_root(
+        &mut code,
+        &mut types,
+        module_meta,
+        &transaction.root,
+        param_surfaces.contains(&0),
+        song,
+    )?;
+
+    let sample_type = match code_config.storage_precision {
+        StoragePrecision::Double => "double",
+        StoragePrecision::Single => "float",
+    };
+    writeln!(
+        f,
+        "/* Generated by the Axiom exporter. Build as C99 and link the math library. */"
+    )?;
+    writeln!(f)?;
+    writeln!(f, "#include <math.h>")?;
+    writeln!(f, "#include <stddef.h>")?;
+    writeln!(f, "#include <stdint.h>")?;
+    writeln!(f, "#include <string.h>")?;
+    writeln!(f)?;
+    writeln!(f, "#define MAXIM_SAMPLE {}", sample_type)?;
+    writeln!(
+        f,
+        "#define MAXIM_SAMPLE_RATE {}",
+        c_float(audio_config.sample_rate)
+    )?;
+    writeln!(f, "#define MAXIM_BPM {}", c_float(audio_config.bpm))?;
+    writeln!(
+        f,
+        "#define MAXIM_CONTROL_RATE_DIVISOR {}u",
+        code_config.control_rate_divisor
+    )?;
+    writeln!(
+        f,
+        "#define MAXIM_MAX_DELAY_SECONDS {}",
+        c_float(code_config.max_delay_seconds)
+    )?;
+    writeln!(f, "#define MAXIM_ARENA_SIZE ((size_t) {}u)", arena_size)?;
+    writeln!(f, "#define MAXIM_ARRAY_CAPACITY {}", ARRAY_CAPACITY)?;
+    writeln!(f, "#define MAXIM_MIDI_EVENT_COUNT {}", MIDI_EVENT_COUNT)?;
+    writeln!(
+        f,
+        "#define MAXIM_MIDI_QUEUE_CAPACITY {}",
+        MIDI_QUEUE_CAPACITY
+    )?;
+    writeln!(f, "#define MAXIM_MAX_BLOCK_FRAMES {}u", MAX_BLOCK_FRAMES)?;
+    let math_accuracy = match code_config.math_accuracy {
+        MathAccuracy::Fast => "MAXIM_MATH_FAST",
+        MathAccuracy::Balanced => "MAXIM_MATH_BALANCED",
+        MathAccuracy::Accurate => "MAXIM_MATH_ACCURATE",
+    };
+    writeln!(f, "#define MAXIM_MATH_ACCURACY {}", math_accuracy)?;
+    writeln!(f, "#define MAXIM_HALF_BAND_SIDE_TAPS {}", SIDE_TAPS)?;
+    let coefficients: Vec<_> = get_side_coefficients().into_iter().map(c_float).collect();
+    writeln!(
+        f,
+        "#define MAXIM_HALF_BAND_COEFFICIENTS {{{}}}",
+        coefficients.join(", ")
+    )?;
+    writeln!(f)?;
+    f.write_str(include_str!("c_library.c"))?;
+    writeln!(f)?;
+    writeln!(f, "/* Instrument */")?;
+    writeln!(f)?;
+    f.write_str(&types.definitions)?;
+    f.write_str(&code)
+}
+
+/// Keeps track of the C types built for each `VarType`, so each is only defined once. Types the
+/// library already uses are defined there.
+struct TypeRegistry {
+    value_types: HashSet<VarType>,
+    portal_types: HashSet<VarType>,
+    definitions: String,
+}
+
+impl TypeRegistry {
+    fn new() -> Self {
+        let mut value_types = HashSet::new();
+        value_types.insert(VarType::Num);
+        value_types.insert(VarType::Midi);
+        value_types.insert(VarType::new_array(VarType::Num));
+        value_types.insert(VarType::new_array(VarType::Midi));
+        value_types.insert(VarType::Tuple(vec![VarType::Num; 2]));
+        value_types.insert(VarType::Tuple(vec![VarType::Num; 4]));
+
+        let mut portal_types = HashSet::new();
+        portal_types.insert(VarType::Num);
+        portal_types.insert(VarType::Midi);
+
+        TypeRegistry {
+            value_types,
+            portal_types,
+            definitions: String::new(),
+        }
+    }
+
+    /// The type of a value inside the instrument.
+    fn value_type(&mut self, var_type: &VarType) -> Result<String, fmt::Error> {
+        let name = format!("maxim_{}", mangle_type(var_type));
+        if self.value_types.contains(var_type) {
+            return Ok(name);
+        }
+
+        let definition = match var_type {
+            VarType::Tuple(items) => {
+                let mut definition = "typedef struct {\n".to_string();
+                for (index, item) in items.iter().enumerate() {
+                    writeln!(definition, "    {} i{};", self.value_type(item)?, index)?;
+                }
+                if items.is_empty() {
+                    writeln!(definition, "    uint8_t unused;")?;
+                }
+                writeln!(definition, "}} {};", name)?;
+                definition
+            }
+            VarType::Array(item) => format!(
+                "typedef struct {{\n    uint32_t bitmap;\n    {} items[MAXIM_ARRAY_CAPACITY];\n}} {};\n",
+                self.value_type(item)?,
+                name
+            ),
+            _ => unreachable!(),
+        };
+        writeln!(self.definitions, "{}", definition)?;
+        self.value_types.insert(var_type.clone());
+        Ok(name)
+    }
+
+    /// The type of a value in a portal or portal buffer, which is at the target's precision. Each
+    /// has functions to load it into the instrument's type and store it back.
+    fn portal_type(&mut self, var_type: &VarType) -> Result<String, fmt::Error> {
+        let mangled = mangle_type(var_type);
+        let name = format!("maxim_portal_{}", mangled);
+        if self.portal_types.contains(var_type) {
+            return Ok(name);
+        }
+
+        let value_name = self.value_type(var_type)?;
+        let mut definition = String::new();
+        let mut load_body = String::new();
+        let mut store_body = String::new();
+        match var_type {
+            VarType::Tuple(items) => {
+                writeln!(definition, "typedef struct {{")?;
+                for (index, item) in items.iter().enumerate() {
+                    let item_mangled = mangle_type(item);
+                    writeln!(definition, "    {} i{};", self.portal_type(item)?, index)?;
+                    writeln!(
+                        load_body,
+                        "    maxim_load_{}(&dest->i{1}, &src->i{1});",
+                        item_mangled, index
+                    )?;
+                    writeln!(
+                        store_body,
+                        "    maxim_store_{}(&dest->i{1}, &src->i{1});",
+                        item_mangled, index
+                    )?;
+                }
+                if items.is_empty() {
+                    writeln!(definition, "    uint8_t unused;")?;
+                }
+                writeln!(definition, "}} {};", name)?;
+            }
+            VarType::Array(item) => {
+                let item_mangled = mangle_type(item);
+                writeln!(definition, "typedef struct {{")?;
+                writeln!(definition, "    uint32_t bitmap;")?;
+                writeln!(
+                    definition,
+                    "    {} items[MAXIM_ARRAY_CAPACITY];",
+                    self.portal_type(item)?
+                )?;
+                writeln!(definition, "}} {};", name)?;
+                let array_body = |func: &str| {
+                    format!(
+                        "    int index;\n    dest->bitmap = src->bitmap;\n    for (index = 0; index < MAXIM_ARRAY_CAPACITY; index++) {{\n        maxim_{}_{}(&dest->items[index], &src->items[index]);\n    }}\n",
+                        func, item_mangled
+                    )
+                };
+                load_body = array_body("load");
+                store_body = array_body("store");
+            }
+            _ => unreachable!(),
+        }
+
+        writeln!(self.definitions, "{}", definition)?;
+        writeln!(
+            self.definitions,
+            "MAXIM_FUNC void maxim_load_{}({} *dest, const {} *src) {{\n{}}}\n",
+            mangled, value_name, name, load_body
+        )?;
+        writeln!(
+            self.definitions,
+            "MAXIM_FUNC void maxim_store_{}({} *dest, const {} *src) {{\n{}}}\n",
+            mangled, name, value_name, store_body
+        )?;
+        self.portal_types.insert(var_type.clone());
+        Ok(name)
+    }
+}
+
+/// Names a type by its structure, so the same type always has the same name.
+fn mangle_type(var_type: &VarType) -> String {
+    match var_type {
+        VarType::Num => "num".to_string(),
+        VarType::Midi => "midi".to_string(),
+        VarType::Tuple(items) => {
+            let mut name = format!("tuple{}", items.len());
+            for item in items {
+                name.push('_');
+                name.push_str(&mangle_type(item));
+            }
+            name
+        }
+        VarType::Array(item) => format!("array_{}", mangle_type(item)),
+        VarType::Void => "void".to_string(),
+    }
+}
+
+fn c_float(value: f64) -> String {
+    if value.is_nan() {
+        "NAN".to_string()
+    } else if value.is_infinite() {
+        if value > 0. {
+            "INFINITY".to_string()
+        } else {
+            "-INFINITY".to_string()
+        }
+    } else {
+        format!("{:?}", value)
+    }
+}
+
+fn c_form(form: FormType) -> &'static str {
+    match form {
+        FormType::None => "MAXIM_FORM_NONE",
+        FormType::Control => "MAXIM_FORM_CONTROL",
+        FormType::Oscillator => "MAXIM_FORM_OSCILLATOR",
+        FormType::Note => "MAXIM_FORM_NOTE",
+        FormType::Frequency => "MAXIM_FORM_FREQUENCY",
+        FormType::Beats => "MAXIM_FORM_BEATS",
+        FormType::Seconds => "MAXIM_FORM_SECONDS",
+        FormType::Samples => "MAXIM_FORM_SAMPLES",
+        FormType::Db => "MAXIM_FORM_DB",
+        FormType::Amplitude => "MAXIM_FORM_AMPLITUDE",
+        FormType::Q => "MAXIM_FORM_Q",
+    }
+}
+
+fn c_constant(value: &ConstantValue) -> String {
+    match value {
+        ConstantValue::Num(num) => format!(
+            "{{{{{}, {}}}, {}}}",
+            c_float(num.left),
+            c_float(num.right),
+            c_form(num.form)
+        ),
+        ConstantValue::Tuple(tuple) => {
+            let items: Vec<_> = tuple.items.iter().map(c_constant).collect();
+            format!("{{{}}}", items.join(", "))
+        }
+    }
+}
+
+fn c_operator(op: OperatorType) -> &'static str {
+    match op {
+        OperatorType::Identity => "maxim_op_identity",
+        OperatorType::Add => "maxim_op_add",
+        OperatorType::Subtract => "maxim_op_subtract",
+        OperatorType::Multiply => "maxim_op_multiply",
+        OperatorType::Divide => "maxim_op_divide",
+        OperatorType::Modulo => "maxim_op_modulo",
+        OperatorType::Power => "maxim_op_power",
+        OperatorType::BitwiseAnd => "maxim_op_bitwise_and",
+        OperatorType::BitwiseOr => "maxim_op_bitwise_or",
+        OperatorType::BitwiseXor => "maxim_op_bitwise_xor",
+        OperatorType::LogicalAnd => "maxim_op_logical_and",
+        OperatorType::LogicalOr => "maxim_op_logical_or",
+        OperatorType::LogicalEqual => "maxim_op_logical_equal",
+        OperatorType::LogicalNotEqual => "maxim_op_logical_not_equal",
+        OperatorType::LogicalGt => "maxim_op_logical_gt",
+        OperatorType::LogicalLt => "maxim_op_logical_lt",
+        OperatorType::LogicalGte => "maxim_op_logical_gte",
+        OperatorType::LogicalLte => "maxim_op_logical_lte",
+    }
+}
+
+/// The type of the data a function call keeps between samples, if it has any.
+fn function_state_type(function: Function) -> Option<&'static str> {
+    match function {
+        Function::Last => Some("maxim_last_state"),
+        Function::Amplitude => Some("maxim_amplitude_state"),
+        Function::Hold => Some("maxim_hold_state"),
+        Function::Accum => Some("maxim_accum_state"),
+        Function::Delay => Some("maxim_delay_state"),
+        Function::LowBqFilter
+        | Function::HighBqFilter
+        | Function::BandBqFilter
+        | Function::NotchBqFilter
+        | Function::AllBqFilter
+        | Function::PeakBqFilter => Some("maxim_biquad_state"),
+        Function::SvFilter => Some("maxim_sv_filter_state"),
+        Function::SinOsc
+        | Function::SqrOsc
+        | Function::SawOsc
+        | Function::TriOsc
+        | Function::RmpOsc => Some("maxim_periodic_state"),
+        Function::Note => Some("maxim_note_state"),
+        Function::Voices => Some("maxim_voices_state"),
+        Function::Adsr => Some("maxim_adsr_state"),
+        _ => None,
+    }
+}
+
+/// Fills in the optional arguments of a call, the same way each function's `gen_real_args` does.
+fn real_args(function: Function, mut args: Vec<String>) -> Vec<String> {
+    let zero = || "&maxim_const_zero".to_string();
+    match function {
+        Function::Hold | Function::Accum => {
+            if args.len() < 3 {
+                args.push(zero());
+            }
+        }
+        Function::Delay => {
+            if args.len() < 3 {
+                args.insert(1, "&maxim_const_one".to_string());
+            }
+        }
+        Function::SinOsc | Function::SawOsc | Function::TriOsc | Function::RmpOsc => {
+            if args.len() < 2 {
+                args.push(zero());
+            }
+        }
+        Function::SqrOsc => {
+            if args.len() < 2 {
+                args.push(zero());
+            }
+            if args.len() < 3 {
+                args.push("&maxim_const_half".to_string());
+            }
+        }
+        _ => {}
+    }
+    args
+}
+
+fn block_name(block: BlockRef) -> String {
+    format!("maxim_block_{}", block)
+}
+
+fn surface_name(surface: SurfaceRef) -> String {
+    format!("maxim_surface_{}", surface)
+}
+
+/// Orders the surfaces reachable from the root so every surface comes after the surfaces its nodes
+/// contain.
+fn get_surface_order(surfaces: &HashMap<SurfaceRef, Surface>) -> Vec<SurfaceRef> {
+    let mut order = Vec::new();
+    visit_surface(0, surfaces, &mut HashSet::new(), &mut order);
+    order
+}
+
+fn visit_surface(
+    surface_id: SurfaceRef,
+    surfaces: &HashMap<SurfaceRef, Surface>,
+    visited_surfaces: &mut HashSet<SurfaceRef>,
+    order: &mut Vec<SurfaceRef>,
+) {
+    if !visited_surfaces.insert(surface_id) {
+        return;
+    }
+
+    for node in &surfaces[&surface_id].nodes {
+        let subsurface_id = match node.data {
+            NodeData::Group(surface_id) => surface_id,
+            NodeData::ExtractGroup { surface, .. } => surface,
+            _ => continue,
+        };
+        visit_surface(subsurface_id, surfaces, visited_surfaces, order);
+    }
+    order.push(surface_id);
+}
+
+/// Builds the state struct of a block, and a function that runs one sample of it. The update
+/// function is given a pointer to the value of each control, which is the value of the node socket
+/// at the same index.
+fn build_block(
+    f: &mut String,
+    types: &mut TypeRegistry,
+    code_config: &CodeConfig,
+    block: &Block,
+) -> fmt::Result {
+    let name = block_name(block.id.id);
+
+    writeln!(f, "typedef struct {{")?;
+    let mut has_fields = false;
+    for (index, control) in block.controls.iter().enumerate() {
+        if control.control_type == ControlType::Graph {
+            writeln!(f, "    maxim_graph_control c{};", index)?;
+            has_fields = true;
+        }
+    }
+    for (index, statement) in block.statements.iter().enumerate() {
+        if let Statement::CallFunc { function, .. } = statement {
+            if let Some(state_type) = function_state_type(*function) {
+                writeln!(f, "    {} f{};", state_type, index)?;
+                has_fields = true;
+            }
+        }
+    }
+    if !has_fields {
+        writeln!(f, "    uint8_t unused;")?;
+    }
+    writeln!(f, "}} {};", name)?;
+    writeln!(f)?;
+
+    // the instance is zeroed before it's constructed, so only functions that claim buffers need
+    // constructing
+    writeln!(f, "static void {}_construct({} *self) {{", name, name)?;
+    writeln!(f, "    (void) self;")?;
+    for (index, statement) in block.statements.iter().enumerate() {
+        if let Statement::CallFunc {
+            function: Function::Delay,
+            args,
+            ..
+        } = statement
+        {
+            let max_seconds =
+                data_analyzer::get_delay_max_seconds(code_config.max_delay_seconds, block, args);
+            writeln!(
+                f,
+                "    maxim_delay_construct(&self->f{}, {});",
+                index,
+                c_float(max_seconds)
+            )?;
+        }
+    }
+    writeln!(f, "}}")?;
+    writeln!(f)?;
+
+    let mut params = vec![format!("{} *self", name)];
+    for (index, control) in block.controls.iter().enumerate() {
+        let value_type = types.value_type(&VarType::of_control_value(control.control_type))?;
+        params.push(format!("{} *c{}", value_type, index));
+    }
+    writeln!(f, "static void {}_update({}) {{", name, params.join(", "))?;
+    writeln!(f, "    (void) self;")?;
+    for index in 0..block.controls.len() {
+        writeln!(f, "    (void) c{};", index)?;
+    }
+
+    for (index, control) in block.controls.iter().enumerate() {
+        if control.control_type == ControlType::Graph {
+            writeln!(f, "    maxim_graph_update(&self->c{0}, c{0});", index)?;
+        }
+    }
+
+    for (index, statement) in block.statements.iter().enumerate() {
+        let value_type = VarType::of_statement(block, index);
+        let value = match statement {
+            Statement::Constant(value) => c_constant(value),
+            Statement::Global(Global::SampleRate) => {
+                "maxim_global_num(maxim_samplerate)".to_string()
+            }
+            Statement::Global(Global::BPM) => "maxim_global_num(maxim_bpm)".to_string(),
+            Statement::NumConvert { target_form, input } => {
+                format!("maxim_convert(&s{}, {})", input, c_form(*target_form))
+            }
+            Statement::NumCast { target_form, input } => {
+                format!("maxim_cast(&s{}, {})", input, c_form(*target_form))
+            }
+            Statement::NumUnaryOp { op, input } => {
+                let func = match op {
+                    UnaryOperation::Positive => "maxim_positive",
+                    UnaryOperation::Negative => "maxim_negative",
+                    UnaryOperation::Not => "maxim_not",
+                };
+                format!("{}(&s{})", func, input)
+            }
+            Statement::NumMathOp { op, lhs, rhs } => {
+                format!("{}(&s{}, &s{})", c_operator(*op), lhs, rhs)
+            }
+            Statement::Extract { tuple, index } => format!("s{}.i{}", tuple, index),
+            Statement::Combine { indexes } => {
+                let items: Vec<_> = indexes.iter().map(|item| format!("s{}", item)).collect();
+                format!("{{{}}}", items.join(", "))
+            }
+            Statement::CallFunc {
+                function,
+                args,
+                varargs,
+            } => {
+                let mut call_args = Vec::new();
+                if function_state_type(*function).is_some() {
+                    call_args.push(format!("&self->f{}", index));
+                }
+                call_args.extend(real_args(
+                    *function,
+                    args.iter().map(|arg| format!("&s{}", arg)).collect(),
+                ));
+                if function.data().var_arg.is_some() {
+                    let vararg_ptrs: Vec<_> = if varargs.is_empty() {
+                        vec!["&maxim_const_zero".to_string()]
+                    } else {
+                        varargs.iter().map(|arg| format!("&s{}", arg)).collect()
+                    };
+                    writeln!(
+                        f,
+                        "    const maxim_num *const s{}_varargs[] = {{{}}};",
+                        index,
+                        vararg_ptrs.join(", ")
+                    )?;
+                    call_args.push(vararg_ptrs.len().to_string());
+                    call_args.push(format!("s{}_varargs", index));
+                }
+                format!("maxim_fn_{}({})", function, call_args.join(", "))
+            }
+            Statement::StoreControl {
+                control,
+                field,
+                value,
+            } => {
+                match field {
+                    ControlField::Graph(GraphField::State) => writeln!(
+                        f,
+                        "    maxim_graph_set_state(&self->c{}, &s{});",
+                        control, value
+                    )?,
+                    ControlField::Graph(GraphField::Time) => writeln!(
+                        f,
+                        "    maxim_graph_set_time(&self->c{}, &s{});",
+                        control, value
+                    )?,
+                    ControlField::Graph(GraphField::Paused)
+                    | ControlField::Roll(RollField::Speed) => {}
+                    _ => writeln!(f, "    *c{} = s{};", control, value)?,
+                }
+                continue;
+            }
+            Statement::LoadControl { control, field } => match field {
+                ControlField::Graph(GraphField::State) => {
+                    format!("maxim_graph_get_state(&self->c{})", control)
+                }
+                ControlField::Graph(GraphField::Paused) => {
+                    format!("maxim_graph_get_paused(&self->c{})", control)
+                }
+                ControlField::Graph(GraphField::Time) => {
+                    format!("maxim_graph_get_time(&self->c{})", control)
+                }
+                // todo: the roll speed isn't implemented in the generated code either
+                ControlField::Roll(RollField::Speed) => "maxim_const_zero".to_string(),
+                _ => format!("*c{}", control),
+            },
+        };
+        writeln!(
+            f,
+            "    {} s{} = {};",
+            types.value_type(&value_type)?,
+            index,
+            value
+        )?;
+    }
+
+    writeln!(f, "}}")?;
+    writeln!(f)
+}
+
+/// Builds the state struct of a surface and its construct and update functions. The update
+/// function is given a pointer to the value of each of the parent node's sockets.
+fn build_surface(
+    f: &mut String,
+    types: &mut TypeRegistry,
+    surface: &Surface,
+    blocks: &HashMap<BlockRef, Block>,
+) -> fmt::Result {
+    let name = surface_name(surface.id.id);
+
+    // graph controls read their curves from constant data
+    let mut graph_data = Vec::new();
+    for (node_index, node) in surface.nodes.iter().enumerate() {
+        if let NodeData::Custom {
+            control_initializers,
+            ..
+        } = &node.data
+        {
+            for (control_index, initializer) in control_initializers.iter().enumerate() {
+                if let ControlInitializer::Graph(graph) = initializer {
+                    let data_name = format!("{}_graph_{}_{}", name, node_index, control_index);
+                    let float_array = |values: &[f64]| -> Vec<String> {
+                        values.iter().map(|&value| c_float(value)).collect()
+                    };
+                    let arrays = [
+                        ("double", "start_values", float_array(&graph.start_values)),
+                        ("double", "end_positions", float_array(&graph.end_positions)),
+                        ("double", "tension", float_array(&graph.tension)),
+                        (
+                            "uint8_t",
+                            "states",
+                            graph.states.iter().map(|state| state.to_string()).collect(),
+                        ),
+                    ];
+                    let mut fields = Vec::new();
+                    for (item_type, field, values) in &arrays {
+                        if values.is_empty() {
+                            fields.push("0".to_string());
+                        } else {
+                            writeln!(
+                                f,
+                                "static const {} {}_{}[] = {{{}}};",
+                                item_type,
+                                data_name,
+                                field,
+                                values.join(", ")
+                            )?;
+                            fields.push(format!("{}_{}", data_name, field));
+                        }
+                    }
+                    writeln!(
+                        f,
+                        "static const maxim_graph_data {} = {{{}, {}}};",
+                        data_name,
+                        graph.curve_count,
+                        fields.join(", ")
+                    )?;
+                    graph_data.push((node_index, control_index, data_name));
+                }
+            }
+        }
+    }
+    if !graph_data.is_empty() {
+        writeln!(f)?;
+    }
+
+    writeln!(f, "typedef struct {{")?;
+    let mut has_fields = false;
+    for (group_index, group) in surface.groups.iter().enumerate() {
+        if let ValueGroupSource::Socket(_) = group.source {
+            continue;
+        }
+        writeln!(
+            f,
+            "    {} g{};",
+            types.value_type(&group.value_type)?,
+            group_index
+        )?;
+        has_fields = true;
+    }
+    for (node_index, node) in surface.nodes.iter().enumerate() {
+        match &node.data {
+            NodeData::Custom { block, .. } => {
+                writeln!(f, "    {} n{};", block_name(*block), node_index)?
+            }
+            NodeData::Group(subsurface) => {
+                writeln!(f, "    {} n{};", surface_name(*subsurface), node_index)?
+            }
+            NodeData::ExtractGroup {
+                surface: subsurface,
+                ..
+            } => writeln!(
+                f,
+                "    {} n{}[MAXIM_ARRAY_CAPACITY];",
+                surface_name(*subsurface),
+                node_index
+            )?,
+            NodeData::Dummy | NodeData::UnitDelay => {}
+        }
+        if node.update_rate != UpdateRate::Audio {
+            let interpolated_count = node.get_interpolated_sockets(&surface.groups).len();
+            write!(f, "    struct {{\n        uint32_t phase;\n")?;
+            if interpolated_count > 0 {
+                writeln!(f, "        maxim_num starts[{}];", interpolated_count)?;
+                writeln!(f, "        maxim_num targets[{}];", interpolated_count)?;
+            }
+            writeln!(f, "    }} r{};", node_index)?;
+        }
+        if node.oversample_factor != OversampleFactor::None {
+            let sockets = node.get_oversampled_sockets(&surface.groups);
+            writeln!(f, "    struct {{")?;
+            if !sockets.inputs.is_empty() {
+                writeln!(
+                    f,
+                    "        maxim_num last_inputs[{}];",
+                    sockets.inputs.len()
+                )?;
+                writeln!(f, "        maxim_num inputs[{}];", sockets.inputs.len())?;
+            }
+            if !sockets.midi_inputs.is_empty() {
+                writeln!(
+                    f,
+                    "        maxim_midi midi_inputs[{}];",
+                    sockets.midi_inputs.len()
+                )?;
+            }
+            if !sockets.outputs.is_empty() {
+                writeln!(f, "        maxim_num outputs[{}];", sockets.outputs.len())?;
+                writeln!(
+                    f,
+                    "        maxim_decimation_stage stages[{}][{}];",
+                    sockets.outputs.len(),
+                    node.oversample_factor.stage_count()
+                )?;
+            }
+            if sockets.inputs.is_empty()
+                && sockets.midi_inputs.is_empty()
+                && sockets.outputs.is_empty()
+            {
+                writeln!(f, "        uint8_t unused;")?;
+            }
+            writeln!(f, "    }} o{};", node_index)?;
+        }
+        has_fields = true;
+    }
+    if !has_fields {
+        writeln!(f, "    uint8_t unused;")?;
+    }
+    writeln!(f, "}} {};", name)?;
+    writeln!(f)?;
+
+    // The instance is zeroed before it's constructed, so only defaults need to be filled in.
+    writeln!(f, "static void {}_construct({} *self) {{", name, name)?;
+    writeln!(f, "    (void) self;")?;
+    for (group_index, group) in surface.groups.iter().enumerate() {
+        if let ValueGroupSource::Default(value) = &group.source {
+            writeln!(
+                f,
+                "    {{\n        const {} value = {};\n        self->g{} = value;\n    }}",
+                types.value_type(&group.value_type)?,
+                c_constant(value),
+                group_index
+            )?;
+        }
+    }
+    for (node_index, control_index, data_name) in &graph_data {
+        writeln!(
+            f,
+            "    self->n{}.c{}.data = &{};",
+            node_index, control_index, data_name
+        )?;
+    }
+    for (node_index, node) in surface.nodes.iter().enumerate() {
+        // delay lines size their buffers when they're constructed, so they need to see the rate
+        // they'll run at
+        let multiplier = f64::from(node.oversample_factor.multiplier());
+        if node.oversample_factor != OversampleFactor::None {
+            writeln!(f, "    maxim_scale_samplerate({});", c_float(multiplier))?;
+        }
+        match &node.data {
+            NodeData::Custom { block, .. } => writeln!(
+                f,
+                "    {}_construct(&self->n{});",
+                block_name(*block),
+                node_index
+            )?,
+            NodeData::Group(subsurface) => writeln!(
+                f,
+                "    {}_construct(&self->n{});",
+                surface_name(*subsurface),
+                node_index
+            )?,
+            NodeData::ExtractGroup {
+                surface: subsurface,
+                ..
+            } => {
+                writeln!(f, "    {{\n        int voice;")?;
+                writeln!(
+                    f,
+                    "        for (voice = 0; voice < MAXIM_ARRAY_CAPACITY; voice++) {{"
+                )?;
+                writeln!(
+                    f,
+                    "            {}_construct(&self->n{}[voice]);",
+                    surface_name(*subsurface),
+                    node_index
+                )?;
+                writeln!(f, "        }}\n    }}")?;
+            }
+            _ => {}
+        }
+        if node.oversample_factor != OversampleFactor::None {
+            writeln!(
+                f,
+                "    maxim_scale_samplerate({});",
+                c_float(1. / multiplier)
+            )?;
+        }
+    }
+    writeln!(f, "}}")?;
+    writeln!(f)?;
+
+    writeln!(
+        f,
+        "static void {}_update({} *self, void *const *sockets) {{",
+        name, name
+    )?;
+    writeln!(f, "    (void) sockets;")?;
+    let mut group_ptrs = Vec::with_capacity(surface.groups.len());
+    for (group_index, group) in surface.groups.iter().enumerate() {
+        group_ptrs.push(match group.source {
+            ValueGroupSource::Socket(socket_index) => format!(
+                "(({} *) sockets[{}])",
+                types.value_type(&group.value_type)?,
+                socket_index
+            ),
+            _ => format!("(&self->g{})", group_index),
+        });
+    }
+    for (node_index, node) in surface.nodes.iter().enumerate() {
+        build_node_update(f, node, node_index, &group_ptrs, surface, blocks)?;
+    }
+    writeln!(f, "}}")?;
+    writeln!(f)
+}
+
+/// Builds functions that set and get the parameters in a surface, including those in its
+/// subsurfaces. Surfaces without any parameters don't get the functions, which is returned so
+/// surfaces containing them know whether to call them.
+fn build_surface_params(
+    f: &mut String,
+    surface: &Surface,
+    param_surfaces: &HashSet<SurfaceRef>,
+) -> Result<bool, fmt::Error> {
+    let params: Vec<_> = surface
+        .groups
+        .iter()
+        .enumerate()
+        .filter_map(|(group_index, group)| match (group.param, &group.source) {
+            (Some(param), ValueGroupSource::Default(_)) if group.value_type == VarType::Num => {
+                Some((param, group_index))
+            }
+            _ => None,
+        })
+        .collect();
+    let subsurfaces: Vec<_> = surface
+        .nodes
+        .iter()
+        .enumerate()
+        .filter_map(|(node_index, node)| match node.data {
+            NodeData::Group(subsurface) if param_surfaces.contains(&subsurface) => {
+                Some((node_index, subsurface, false))
+            }
+            NodeData::ExtractGroup {
+                surface: subsurface,
+                ..
+            } if param_surfaces.contains(&subsurface) => Some((node_index, subsurface, true)),
+            _ => None,
+        })
+        .collect();
+    if params.is_empty() && subsurfaces.is_empty() {
+        return Ok(false);
+    }
+
+    let name = surface_name(surface.id.id);
+    writeln!(
+        f,
+        "static void {}_set_param({} *self, uint32_t id, double value) {{",
+        name, name
+    )?;
+    for &(param, group_index) in &params {
+        writeln!(
+            f,
+            "    if (id == {}u) self->g{1}.v[0] = self->g{1}.v[1] = value;",
+            param, group_index
+        )?;
+    }
+    for &(node_index, subsurface, is_extracted) in &subsurfaces {
+        // groups in extracted surfaces are kept per voice, so every voice needs the value
+        if is_extracted {
+            writeln!(
+                f,
+                "    {{
+        int voice;"
+            )?;
+            writeln!(
+                f,
+                "        for (voice = 0; voice < MAXIM_ARRAY_CAPACITY; voice++) {{"
+            )?;
+            writeln!(
+                f,
+                "            {}_set_param(&self->n{}[voice], id, value);",
+                surface_name(subsurface),
+                node_index
+            )?;
+            writeln!(f, "        }}\n    }}")?;
+        } else {
+            writeln!(
+                f,
+                "    {}_set_param(&self->n{}, id, value);",
+                surface_name(subsurface),
+                node_index
+            )?;
+        }
+    }
+    writeln!(f, "}}")?;
+    writeln!(f)?;
+
+    writeln!(
+        f,
+        "static int {}_get_param(const {} *self, uint32_t id, double *value) {{",
+        name, name
+    )?;
+    for &(param, group_index) in &params {
+        writeln!(
+            f,
+            "    if (id == {}u) {{\n        *value = self->g{}.v[0];\n        return 1;\n    }}",
+            param, group_index
+        )?;
+    }
+    for &(node_index, subsurface, is_extracted) in &subsurfaces {
+        let index = if is_extracted { "[0]" } else { "" };
+        writeln!(
+            f,
+            "    if ({}_get_param(&self->n{}{}, id, value)) return 1;",
+            surface_name(subsurface),
+            node_index,
+            index
+        )?;
+    }
+    writeln!(f, "    return 0;")?;
+    writeln!(f, "}}")?;
+    writeln!(f)?;
+    Ok(true)
+}
+
+/// Builds the code that updates one node. Nodes that only run every few samples write interpolated
+/// sockets into their targets, and their groups ramp towards them on the samples in between.
+fn build_node_update(
+    f: &mut String,
+    node: &Node,
+    node_index: usize,
+    group_ptrs: &[String],
+    surface: &Surface,
+    blocks: &HashMap<BlockRef, Block>,
+) -> fmt::Result {
+    let mut socket_ptrs: Vec<_> = node
+        .sockets
+        .iter()
+        .map(|socket| group_ptrs[socket.group_id].clone())
+        .collect();
+    if node.update_rate == UpdateRate::Audio {
+        return build_oversampled_node_run(
+            f,
+            node,
+            node_index,
+            &socket_ptrs,
+            surface,
+            blocks,
+            "    ",
+        );
+    }
+
+    let rate = format!("self->r{}", node_index);
+    let interpolated_sockets = node.get_interpolated_sockets(&surface.groups);
+    writeln!(f, "    if ({}.phase == 0) {{", rate)?;
+    for (index, &socket_index) in interpolated_sockets.iter().enumerate() {
+        // interpolation starts from wherever the group is now, so there's no jump if the previous
+        // ramp was interrupted
+        writeln!(
+            f,
+            "        {}.starts[{}] = *{};",
+            rate, index, socket_ptrs[socket_index]
+        )?;
+        socket_ptrs[socket_index] = format!("(&{}.targets[{}])", rate, index);
+    }
+    build_oversampled_node_run(
+        f,
+        node,
+        node_index,
+        &socket_ptrs,
+        surface,
+        blocks,
+        "        ",
+    )?;
+    writeln!(f, "    }}")?;
+
+    writeln!(f, "    {{")?;
+    writeln!(f, "        uint32_t next_phase = {}.phase + 1;", rate)?;
+    if !interpolated_sockets.is_empty() {
+        writeln!(
+            f,
+            "        double progress = (double) next_phase / (double) MAXIM_CONTROL_RATE_DIVISOR;"
+        )?;
+        for (index, &socket_index) in interpolated_sockets.iter().enumerate() {
+            writeln!(
+                f,
+                "        *{} = maxim_interpolate(&{1}.starts[{2}], &{1}.targets[{2}], progress);",
+                group_ptrs[node.sockets[socket_index].group_id], rate, index
+            )?;
+        }
+    }
+    writeln!(
+        f,
+        "        {}.phase = next_phase == MAXIM_CONTROL_RATE_DIVISOR ? 0 : next_phase;",
+        rate
+    )?;
+    writeln!(f, "    }}")
+}
+
+/// Builds the code that runs a node `oversample_factor` times per sample, or runs it directly if it
+/// isn't oversampled. Like in the interpreter, inputs are linearly interpolated across the steps,
+/// MIDI inputs are only seen on the first step, and outputs are decimated back to the host rate
+/// through a cascade of half-band filters.
+fn build_oversampled_node_run(
+    f: &mut String,
+    node: &Node,
+    node_index: usize,
+    socket_ptrs: &[String],
+    surface: &Surface,
+    blocks: &HashMap<BlockRef, Block>,
+    indent: &str,
+) -> fmt::Result {
+    if node.oversample_factor == OversampleFactor::None {
+        return build_node_run(f, node, node_index, socket_ptrs, blocks, indent);
+    }
+
+    let state = format!("self->o{}", node_index);
+    let multiplier = node.oversample_factor.multiplier();
+    let sockets = node.get_oversampled_sockets(&surface.groups);
+    let mut step_ptrs = socket_ptrs.to_vec();
+    for (index, &socket_index) in sockets.inputs.iter().enumerate() {
+        step_ptrs[socket_index] = format!("(&{}.inputs[{}])", state, index);
+    }
+    for (index, &socket_index) in sockets.midi_inputs.iter().enumerate() {
+        step_ptrs[socket_index] = format!("(&{}.midi_inputs[{}])", state, index);
+    }
+    for (index, &socket_index) in sockets.outputs.iter().enumerate() {
+        step_ptrs[socket_index] = format!("(&{}.outputs[{}])", state, index);
+    }
+
+    writeln!(f, "{}{{", indent)?;
+    if !sockets.outputs.is_empty() {
+        writeln!(
+            f,
+            "{}    double samples[{}][{}][2];",
+            indent,
+            sockets.outputs.len(),
+            multiplier
+        )?;
+    }
+    writeln!(f, "{}    uint32_t step;", indent)?;
+    for (index, &socket_index) in sockets.midi_inputs.iter().enumerate() {
+        writeln!(
+            f,
+            "{}    {}.midi_inputs[{}] = *{};",
+            indent, state, index, socket_ptrs[socket_index]
+        )?;
+    }
+    writeln!(
+        f,
+        "{}    maxim_scale_samplerate({});",
+        indent,
+        c_float(f64::from(multiplier))
+    )?;
+    writeln!(
+        f,
+        "{}    for (step = 0; step < {}u; step++) {{",
+        indent, multiplier
+    )?;
+    if !sockets.inputs.is_empty() {
+        writeln!(
+            f,
+            "{}        double progress = (double) (step + 1) / {};",
+            indent,
+            c_float(f64::from(multiplier))
+        )?;
+    }
+    for (index, &socket_index) in sockets.inputs.iter().enumerate() {
+        writeln!(
+            f,
+            "{0}        {1}.inputs[{2}] = maxim_interpolate(&{1}.last_inputs[{2}], {3}, progress);",
+            indent, state, index, socket_ptrs[socket_index]
+        )?;
+    }
+    build_node_run(
+        f,
+        node,
+        node_index,
+        &step_ptrs,
+        blocks,
+        &format!("{}        ", indent),
+    )?;
+    // MIDI events only happen once per host sample
+    for index in 0..sockets.midi_inputs.len() {
+        writeln!(
+            f,
+            "{}        {}.midi_inputs[{}].count = 0;",
+            indent, state, index
+        )?;
+    }
+    for index in 0..sockets.outputs.len() {
+        writeln!(
+            f,
+            "{0}        samples[{2}][step][0] = {1}.outputs[{2}].v[0];",
+            indent, state, index
+        )?;
+        writeln!(
+            f,
+            "{0}        samples[{2}][step][1] = {1}.outputs[{2}].v[1];",
+            indent, state, index
+        )?;
+    }
+    writeln!(f, "{}    }}", indent)?;
+    writeln!(
+        f,
+        "{}    maxim_scale_samplerate({});",
+        indent,
+        c_float(1. / f64::from(multiplier))
+    )?;
+    for (index, &socket_index) in sockets.inputs.iter().enumerate() {
+        writeln!(
+            f,
+            "{}    {}.last_inputs[{}] = *{};",
+            indent, state, index, socket_ptrs[socket_index]
+        )?;
+    }
+    for (index, &socket_index) in sockets.outputs.iter().enumerate() {
+        writeln!(
+            f,
+            "{0}    *{3} = maxim_oversample_output({1}.stages[{2}], {4}u, samples[{2}], &{1}.outputs[{2}]);",
+            indent,
+            state,
+            index,
+            socket_ptrs[socket_index],
+            node.oversample_factor.stage_count()
+        )?;
+    }
+    writeln!(f, "{}}}", indent)
+}
+
+/// Builds the code that runs a node itself, working directly on the values its sockets point to.
+fn build_node_run(
+    f: &mut String,
+    node: &Node,
+    node_index: usize,
+    socket_ptrs: &[String],
+    blocks: &HashMap<BlockRef, Block>,
+    indent: &str,
+) -> fmt::Result {
+    match &node.data {
+        NodeData::Dummy => Ok(()),
+        NodeData::Custom { block, .. } => {
+            let control_count = blocks[block].controls.len();
+            let mut args = vec![format!("&self->n{}", node_index)];
+            args.extend(socket_ptrs[..control_count].iter().cloned());
+            writeln!(
+                f,
+                "{}{}_update({});",
+                indent,
+                block_name(*block),
+                args.join(", ")
+            )
+        }
+        NodeData::Group(subsurface) => {
+            writeln!(f, "{}{{", indent)?;
+            writeln!(
+                f,
+                "{}    void *const node_sockets[] = {{{}}};",
+                indent,
+                pointer_list(socket_ptrs)
+            )?;
+            writeln!(
+                f,
+                "{}    {}_update(&self->n{}, node_sockets);",
+                indent,
+                surface_name(*subsurface),
+                node_index
+            )?;
+            writeln!(f, "{}}}", indent)
+        }
+        NodeData::ExtractGroup {
+            surface: subsurface,
+            source_sockets,
+            dest_sockets,
+        } => {
+            // only the voices active in every source array are updated
+            let mut active_bitmap = "0xFFFFFFFFu".to_string();
+            for &socket in source_sockets {
+                write!(active_bitmap, " & {}->bitmap", socket_ptrs[socket])?;
+            }
+            let voice_ptrs: Vec<_> = socket_ptrs
+                .iter()
+                .enumerate()
+                .map(|(socket_index, ptr)| {
+                    if source_sockets.contains(&socket_index)
+                        || dest_sockets.contains(&socket_index)
+                    {
+                        format!("&{}->items[voice]", ptr)
+                    } else {
+                        ptr.clone()
+                    }
+                })
+                .collect();
+
+            writeln!(f, "{}{{", indent)?;
+            writeln!(f, "{}    uint32_t active = {};", indent, active_bitmap)?;
+            writeln!(f, "{}    int voice;", indent)?;
+            writeln!(
+                f,
+                "{}    for (voice = 0; voice < MAXIM_ARRAY_CAPACITY; voice++) {{",
+                indent
+            )?;
+            writeln!(f, "{}        if ((active >> voice) & 1) {{", indent)?;
+            writeln!(
+                f,
+                "{}            void *const node_sockets[] = {{{}}};",
+                indent,
+                pointer_list(&voice_ptrs)
+            )?;
+            writeln!(
+                f,
+                "{}            {}_update(&self->n{}[voice], node_sockets);",
+                indent,
+                surface_name(*subsurface),
+                node_index
+            )?;
+            writeln!(f, "{}        }}", indent)?;
+            writeln!(f, "{}    }}", indent)?;
+            for &socket in dest_sockets {
+                writeln!(f, "{}    {}->bitmap = active;", indent, socket_ptrs[socket])?;
+            }
+            writeln!(f, "{}}}", indent)
+        }
+        NodeData::UnitDelay => {
+            // Unit delays are ordered after every other node, so copying here makes the value
+            // written this sample visible to readers of the delayed group on the next one.
+            writeln!(f, "{}*{} = *{};", indent, socket_ptrs[1], socket_ptrs[0])
+        }
+    }
+}
+
+/// Builds the song's event table and the function that plays it. The function plays a number of
+/// frames of the song, and either queues the events that are due on their portal's MIDI queue or
+/// pushes them straight onto the portal.
+fn build_song(f: &mut String, root: &Root, song: &SongConfig) -> fmt::Result {
+    let song_entries = get_song_entries(root, song);
+    let midi_portals = get_midi_portals(root);
+
+    // entries are due a number of frames after the one before, see `SongEntry`
+    writeln!(f, "typedef struct {{")?;
+    writeln!(f, "    uint16_t delta;")?;
+    writeln!(f, "    uint8_t queue;")?;
+    writeln!(f, "    maxim_midi_event event;")?;
+    writeln!(f, "}} maxim_song_entry;")?;
+    writeln!(f)?;
+
+    // C arrays can't be empty, so an empty song still gets an entry which is never played
+    writeln!(
+        f,
+        "#define MAXIM_SONG_ENTRY_COUNT {}u",
+        song_entries.entries.len()
+    )?;
+    writeln!(f, "static const maxim_song_entry maxim_song_entries[] = {{")?;
+    for entry in &song_entries.entries {
+        let [event, channel, note, param] = entry.event;
+        writeln!(
+            f,
+            "    {{{}u, {}u, {{{}, {}, {}, {}}}}},",
+            entry.delta, entry.queue, event, channel, note, param
+        )?;
+    }
+    if song_entries.entries.is_empty() {
+        writeln!(f, "    {{0u, {}u, {{0, 0, 0, 0}}}},", SONG_WAIT_QUEUE)?;
+    }
+    writeln!(f, "}};")?;
+    writeln!(f, "static uint32_t maxim_song_position;")?;
+    writeln!(f, "static uint32_t maxim_song_cursor;")?;
+    writeln!(f, "static uint32_t maxim_song_base;")?;
+    writeln!(f)?;
+
+    writeln!(
+        f,
+        "static void maxim_song_play(uint32_t frames, int to_queues) {{"
+    )?;
+    writeln!(f, "    uint32_t played = 0;")?;
+    writeln!(f, "    for (;;) {{")?;
+    writeln!(
+        f,
+        "        uint32_t segment_end = maxim_song_position + (frames - played);"
+    )?;
+    if let Some((_, loop_end)) = song.loop_range {
+        writeln!(
+            f,
+            "        if (segment_end > {0}u) segment_end = {0}u;",
+            loop_end
+        )?;
+    }
+    writeln!(
+        f,
+        "        while (maxim_song_cursor < MAXIM_SONG_ENTRY_COUNT) {{"
+    )?;
+    writeln!(
+        f,
+        "            const maxim_song_entry *entry = &maxim_song_entries[maxim_song_cursor];"
+    )?;
+    writeln!(
+        f,
+        "            uint32_t frame = maxim_song_base + entry->delta;"
+    )?;
+    writeln!(f, "            uint32_t offset;")?;
+    writeln!(f, "            if (frame >= segment_end) break;")?;
+    writeln!(
+        f,
+        "            offset = played + (frame - maxim_song_position);"
+    )?;
+    writeln!(f, "            maxim_song_base = frame;")?;
+    writeln!(f, "            maxim_song_cursor++;")?;
+    writeln!(f, "            switch (entry->queue) {{")?;
+    // wait entries don't match any queue, so they fall through to the default
+    for (queue_index, portal_index) in midi_portals
+        .iter()
+        .enumerate()
+        .take(usize::from(SONG_WAIT_QUEUE))
+    {
+        writeln!(f, "            case {}:", queue_index)?;
+        writeln!(
+            f,
+            "                if (to_queues) maxim_midi_queue_push(&maxim_queue_{0}, offset, entry->event);",
+            portal_index
+        )?;
+        writeln!(
+            f,
+            "                else maxim_midi_push(&maxim_portal_{0}, entry->event);",
+            portal_index
+        )?;
+        writeln!(f, "                break;")?;
+    }
+    writeln!(
+        f,
+        "            default: (void) offset; (void) to_queues; break;"
+    )?;
+    writeln!(f, "            }}")?;
+    writeln!(f, "        }}")?;
+    writeln!(f, "        played += segment_end - maxim_song_position;")?;
+    writeln!(f, "        maxim_song_position = segment_end;")?;
+    match song.loop_range {
+        Some((loop_start, loop_end)) => {
+            writeln!(f, "        if (segment_end != {}u) break;", loop_end)?;
+            writeln!(f, "        maxim_song_position = {}u;", loop_start)?;
+            writeln!(
+                f,
+                "        maxim_song_cursor = {}u;",
+                song_entries.loop_cursor
+            )?;
+            writeln!(f, "        maxim_song_base = {}u;", song_entries.loop_base)?;
+            writeln!(f, "        if (played >= frames) break;")?;
+        }
+        None => writeln!(f, "        break;")?,
+    }
+    writeln!(f, "    }}")?;
+    writeln!(f, "}}")?;
+    writeln!(f)
+}
+
+/// Lists pointers for an initializer, with a null at the end so the list is never empty.
+fn pointer_list(ptrs: &[String]) -> String {
+    let mut items = ptrs.to_vec();
+    items.push("0".to_string());
+    items.join(", ")
+}
+
+/// Builds the root instance, the portals and the exported functions. Each sample the portals are
+/// loaded into the root sockets, the root surface is updated, and the sockets are stored back.
+fn build_root(
+    f: &mut String,
+    types: &mut TypeRegistry,
+    module_meta: &ModuleMetadata,
+    root: &Root,
+    has_params: bool,
+    song: Option<&SongConfig>,
+) -> fmt::Result {
+    let root_name = surface_name(0);
+    writeln!(f, "static {} maxim_instance;", root_name)?;
+    let mut portal_types = Vec::with_capacity(root.sockets.len());
+    for (index, socket_type) in root.sockets.iter().enumerate() {
+        let portal_type = types.portal_type(socket_type)?;
+        writeln!(
+            f,
+            "static {} maxim_socket_{};",
+            types.value_type(socket_type)?,
+            index
+        )?;
+        writeln!(f, "static {} maxim_portal_{};", portal_type, index)?;
+        writeln!(
+            f,
+            "static {} maxim_buffer_{}[MAXIM_MAX_BLOCK_FRAMES];",
+            portal_type, index
+        )?;
+        portal_types.push(mangle_type(socket_type));
+    }
+    let midi_portals = get_midi_portals(root);
+    for index in &midi_portals {
+        writeln!(f, "static maxim_midi_queue maxim_queue_{};", index)?;
+    }
+    let portal_ptrs: Vec<_> = (0..root.sockets.len())
+        .map(|index| format!("&maxim_portal_{}", index))
+        .collect();
+    let buffer_ptrs: Vec<_> = (0..root.sockets.len())
+        .map(|index| format!("maxim_buffer_{}", index))
+        .collect();
+    let socket_ptrs: Vec<_> = (0..root.sockets.len())
+        .map(|index| format!("&maxim_socket_{}", index))
+        .collect();
+    writeln!(
+        f,
+        "static void *const maxim_portal_ptrs[] = {{{}}};",
+        pointer_list(&portal_ptrs)
+    )?;
+    writeln!(
+        f,
+        "static void *const maxim_buffer_ptrs[] = {{{}}};",
+        pointer_list(&buffer_ptrs)
+    )?;
+    writeln!(f)?;
+
+    writeln!(f, "void {}(void) {{", module_meta.init_func_name)?;
+    writeln!(f, "    memset(&maxim_instance, 0, sizeof(maxim_instance));")?;
+    writeln!(f, "    maxim_arena_used = 0;")?;
+    writeln!(f, "    {}_construct(&maxim_instance);", root_name)?;
+    writeln!(f, "}}")?;
+    writeln!(f)?;
+
+    writeln!(f, "void {}(void) {{", module_meta.cleanup_func_name)?;
+    writeln!(
+        f,
+        "    /* delay buffers belong to the arena, which is reset by init, so there's nothing to free */"
+    )?;
+    writeln!(f, "}}")?;
+    writeln!(f)?;
+
+    if let Some(song) = song {
+        build_song(f, root, song)?;
+    }
+
+    // the block update runs this for each frame, since the song is played for the whole block
+    // before it starts
+    writeln!(f, "static void maxim_update(void) {{")?;
+    writeln!(
+        f,
+        "    void *const sockets[] = {{{}}};",
+        pointer_list(&socket_ptrs)
+    )?;
+    for (index, mangled) in portal_types.iter().enumerate() {
+        writeln!(
+            f,
+            "    maxim_load_{0}(&maxim_socket_{1}, &maxim_portal_{1});",
+            mangled, index
+        )?;
+    }
+    writeln!(f, "    {}_update(&maxim_instance, sockets);", root_name)?;
+    for (index, mangled) in portal_types.iter().enumerate() {
+        writeln!(
+            f,
+            "    maxim_store_{0}(&maxim_portal_{1}, &maxim_socket_{1});",
+            mangled, index
+        )?;
+    }
+    writeln!(f, "}}")?;
+    writeln!(f)?;
+
+    writeln!(f, "void {}(void) {{", module_meta.generate_func_name)?;
+    if song.is_some() {
+        writeln!(f, "    maxim_song_play(1, 0);")?;
+    }
+    writeln!(f, "    maxim_update();")?;
+    writeln!(f, "}}")?;
+    writeln!(f)?;
+
+    // Each portal is copied from its buffer before the update and back after it, so inputs and
+    // outputs can share the same buffers. Only the events in use are copied for MIDI portals. The
+    // buffers hold `MAX_BLOCK_FRAMES` frames, so the number of frames run is returned.
+    writeln!(
+        f,
+        "uint32_t {}(uint32_t frames) {{",
+        module_meta.generate_block_func_name
+    )?;
+    writeln!(f, "    uint32_t frame;")?;
+    writeln!(
+        f,
+        "    if (frames > MAXIM_MAX_BLOCK_FRAMES) frames = MAXIM_MAX_BLOCK_FRAMES;"
+    )?;
+    if song.is_some() {
+        writeln!(f, "    maxim_song_play(frames, 1);")?;
+    }
+    writeln!(f, "    for (frame = 0; frame < frames; frame++) {{")?;
+    for (index, vartype) in root.sockets.iter().enumerate() {
+        if *vartype == VarType::Midi {
+            writeln!(
+                f,
+                "        maxim_copy_midi_events(&maxim_portal_{0}, &maxim_buffer_{0}[frame]);",
+                index
+            )?;
+        } else {
+            writeln!(
+                f,
+                "        maxim_portal_{0} = maxim_buffer_{0}[frame];",
+                index
+            )?;
+        }
+    }
+    for index in &midi_portals {
+        writeln!(
+            f,
+            "        maxim_midi_queue_deliver(&maxim_queue_{0}, frame, &maxim_portal_{0});",
+            index
+        )?;
+    }
+    writeln!(f, "        maxim_update();")?;
+    for (index, vartype) in root.sockets.iter().enumerate() {
+        if *vartype == VarType::Midi {
+            writeln!(
+                f,
+                "        maxim_copy_midi_events(&maxim_buffer_{0}[frame], &maxim_portal_{0});",
+                index
+            )?;
+        } else {
+            writeln!(
+                f,
+                "        maxim_buffer_{0}[frame] = maxim_portal_{0};",
+                index
+            )?;
+        }
+    }
+    writeln!(f, "    }}")?;
+    for index in &midi_portals {
+        writeln!(
+            f,
+            "    maxim_midi_queue_advance(&maxim_queue_{}, frames);",
+            index
+        )?;
+    }
+    writeln!(f, "    return frames;")?;
+    writeln!(f, "}}")?;
+    writeln!(f)?;
+
+    writeln!(
+        f,
+        "void *{}(uint32_t id) {{\n    return maxim_portal_ptrs[id];\n}}\n",
+        module_meta.portal_func_name
+    )?;
+    writeln!(
+        f,
+        "void *{}(uint32_t id) {{\n    return maxim_buffer_ptrs[id];\n}}\n",
+        module_meta.portal_buffer_func_name
+    )?;
+
+    writeln!(
+        f,
+        "void {}(uint32_t id, uint8_t event, uint8_t channel, uint8_t note, uint8_t param) {{",
+        module_meta.midi_push_func_name
+    )?;
+    writeln!(f, "    maxim_midi_event midi_event;")?;
+    writeln!(f, "    midi_event.name = event;")?;
+    writeln!(f, "    midi_event.channel = channel;")?;
+    writeln!(f, "    midi_event.note = note;")?;
+    writeln!(f, "    midi_event.param = param;")?;
+    writeln!(
+        f,
+        "    maxim_midi_push((maxim_midi *) maxim_portal_ptrs[id], midi_event);"
+    )?;
+    writeln!(f, "}}")?;
+    writeln!(f)?;
+
+    writeln!(
+        f,
+        "void {}(uint32_t id, uint32_t offset, uint8_t event, uint8_t channel, uint8_t note, uint8_t param) {{",
+        module_meta.midi_queue_func_name
+    )?;
+    writeln!(f, "    maxim_midi_event midi_event;")?;
+    writeln!(f, "    midi_event.name = event;")?;
+    writeln!(f, "    midi_event.channel = channel;")?;
+    writeln!(f, "    midi_event.note = note;")?;
+    writeln!(f, "    midi_event.param = param;")?;
+    // instruments without MIDI portals don't use the event
+    writeln!(f, "    (void) offset;")?;
+    writeln!(f, "    (void) midi_event;")?;
+    writeln!(f, "    switch (id) {{")?;
+    for index in &midi_portals {
+        writeln!(
+            f,
+            "    case {0}: maxim_midi_queue_push(&maxim_queue_{0}, offset, midi_event); break;",
+            index
+        )?;
+    }
+    writeln!(f, "    default: break;")?;
+    writeln!(f, "    }}")?;
+    writeln!(f, "}}")?;
+    writeln!(f)?;
+
+    writeln!(
+        f,
+        "void {}(uint32_t id, double value) {{",
+        module_meta.set_param_func_name
+    )?;
+    if has_params {
+        writeln!(
+            f,
+            "    {}_set_param(&maxim_instance, id, value);",
+            root_name
+        )?;
+    } else {
+        writeln!(f, "    (void) id;\n    (void) value;")?;
+    }
+    writeln!(f, "}}")?;
+    writeln!(f)?;
+
+    writeln!(
+        f,
+        "double {}(uint32_t id) {{",
+        module_meta.get_param_func_name
+    )?;
+    writeln!(f, "    double value = 0.0;")?;
+    if has_params {
+        writeln!(
+            f,
+            "    {}_get_param(&maxim_instance, id, &value);",
+            root_name
+        )?;
+    } else {
+        writeln!(f, "    (void) id;")?;
+    }
+    writeln!(f, "    return value;")?;
+    writeln!(f, "}}")
+}
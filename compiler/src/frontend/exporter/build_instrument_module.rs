@@ -1,11 +1,38 @@
 use super::build_meta_output::ModuleMetadata;
-use crate::codegen::{block, data_analyzer, root, surface, ObjectCache, TargetProperties};
+use super::build_song::{
+    build_song_block_playback, build_song_frame_playback, build_song_state_global,
+    get_song_state_type,
+};
+use super::export_config::SongConfig;
+use crate::codegen::data_analyzer::{PointerSource, PointerSourceAggregateType};
+use crate::codegen::values::NumValue;
+use crate::codegen::{
+    block, build_context_function, data_analyzer, globals, intrinsics, root, surface, util,
+    BuilderContext, LifecycleFunc, ObjectCache, TargetProperties,
+};
 use crate::frontend::{mir_optimizer, Transaction};
 use crate::{mir, pass};
+use inkwell::builder::Builder;
 use inkwell::context::Context;
-use inkwell::module::Module;
-use std::collections::{HashMap, HashSet};
-use std::iter::FromIterator;
+use inkwell::module::{Linkage, Module};
+use inkwell::types::{BasicType, FunctionType, StructType};
+use inkwell::values::{BasicValue, IntValue, PointerValue};
+use inkwell::AddressSpace;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::iter::{self, FromIterator};
+
+// The fields of the instance struct of an instanced export
+const INSTANCE_GLOBALS_FIELD: u32 = 0;
+const INSTANCE_INITIALIZED_FIELD: u32 = 1;
+const INSTANCE_SCRATCH_FIELD: u32 = 2;
+const INSTANCE_SOCKETS_FIELD: u32 = 3;
+const INSTANCE_BUFFERS_FIELD: u32 = 4;
+const INSTANCE_POINTERS_FIELD: u32 = 5;
+const INSTANCE_SOCKET_PTRS_FIELD: u32 = 6;
+const INSTANCE_BUFFER_PTRS_FIELD: u32 = 7;
+const INSTANCE_MIDI_QUEUES_FIELD: u32 = 8;
+const INSTANCE_SONG_FIELD: u32 = 9;
+const INSTANCE_ARENA_FIELD: u32 = 10;
 
 struct ExportObjectCache<'context, 'target, 'mir> {
     context: &'context Context,
@@ -42,13 +69,24 @@ impl ObjectCache for ExportObjectCache<'_, '_, '_> {
     }
 }
 
-pub fn build_instrument_module(
-    context: &Context,
-    export_module: &Module,
-    target: &TargetProperties,
+/// The values each instance of an instanced export starts with.
+pub struct InstanceDefaults {
+    pub sample_rate: f64,
+    pub bpm: f64,
+    pub arena_size: u64,
+}
+
+/// The MIR of a transaction after the optimizer has prepared it for export.
+pub struct PreparedTransaction {
+    pub surfaces: HashMap<mir::SurfaceRef, mir::Surface>,
+    pub blocks: HashMap<mir::BlockRef, mir::Block>,
+    pub root: mir::Root,
+}
+
+pub fn prepare_transaction(
     transaction: Transaction,
-    module_meta: &ModuleMetadata,
-) {
+    target: &TargetProperties,
+) -> PreparedTransaction {
     let mut id_allocator = mir::IncrementalIdAllocator::new(0);
 
     // Reserve all of the currently-used IDs in the allocator, so we don't get duplicates when
@@ -74,13 +112,29 @@ pub fn build_instrument_module(
     pass::deduplicate_surfaces(&mut prepared_surfaces);
     pass::flatten_groups(&mut prepared_surfaces);
 
-    let block_layouts = build_block_layouts(&context, target, prepared_blocks.values());
+    PreparedTransaction {
+        surfaces: prepared_surfaces,
+        blocks: prepared_blocks,
+        root: transaction.root.unwrap(),
+    }
+}
+
+pub fn build_instrument_module(
+    context: &Context,
+    export_module: &Module,
+    target: &TargetProperties,
+    transaction: &PreparedTransaction,
+    module_meta: &ModuleMetadata,
+    instance_defaults: Option<&InstanceDefaults>,
+    song: Option<&SongConfig>,
+) {
+    let block_layouts = build_block_layouts(&context, target, transaction.blocks.values());
     let mut surface_layouts = HashMap::new();
     build_surface_layouts(
         &context,
         target,
-        &prepared_surfaces,
-        &prepared_blocks,
+        &transaction.surfaces,
+        &transaction.blocks,
         &mut surface_layouts,
         &block_layouts,
     );
@@ -88,31 +142,51 @@ pub fn build_instrument_module(
     let cache = ExportObjectCache {
         context: &context,
         target,
-        surface_mir: &prepared_surfaces,
-        block_mir: &prepared_blocks,
+        surface_mir: &transaction.surfaces,
+        block_mir: &transaction.blocks,
         surface_layout: &surface_layouts,
         block_layout: &block_layouts,
     };
 
-    for block in prepared_blocks.values() {
+    for block in transaction.blocks.values() {
         // If the block doesn't have a layout, it isn't reachable from the root so no need to build
         // it here.
         if block_layouts.get(&block.id.id).is_some() {
             block::build_funcs(&export_module, &cache, block);
         }
     }
-    for surface in prepared_surfaces.values() {
+    for surface in transaction.surfaces.values() {
         if surface_layouts.get(&surface.id.id).is_some() {
             surface::build_funcs(&export_module, &cache, surface);
         }
     }
 
-    build_root(
-        &export_module,
-        module_meta,
-        &cache,
-        &transaction.root.unwrap(),
-    );
+    let params: Vec<_> = transaction
+        .surfaces
+        .values()
+        .flat_map(|surface| surface.groups.iter().filter_map(|group| group.param))
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect();
+    match instance_defaults {
+        Some(defaults) => build_instanced_root(
+            &export_module,
+            module_meta,
+            &cache,
+            &transaction.root,
+            &params,
+            defaults,
+            song,
+        ),
+        None => build_root(
+            &export_module,
+            module_meta,
+            &cache,
+            &transaction.root,
+            &params,
+            song,
+        ),
+    }
 }
 
 fn build_root(
@@ -120,13 +194,17 @@ fn build_root(
     module_meta: &ModuleMetadata,
     cache: &dyn ObjectCache,
     root: &mir::Root,
+    params: &[usize],
+    song: Option<&SongConfig>,
 ) {
     let initialized_global =
         root::build_initialized_global(&module, cache, 0, "maxim.data.initialized");
-    initialized_global.set_constant(true);
+    // parameters are written into the initialized data, so it can only be constant without them
+    initialized_global.set_constant(params.is_empty());
     let scratch_global = root::build_scratch_global(&module, cache, 0, "maxim.data.scratch");
     let sockets_global = root::build_sockets_global(
         &module,
+        cache,
         root,
         "maxim.data.portals",
         "maxim.data.portals.ptr",
@@ -140,21 +218,740 @@ fn build_root(
         scratch_global.as_pointer_value(),
         sockets_global.sockets.as_pointer_value(),
     );
-    root::build_funcs(
+    let song_playback = song.map(|song| {
+        let state_global = build_song_state_global(&module, "maxim.data.song_state");
+        (song, state_global.as_pointer_value())
+    });
+    match song_playback {
+        Some((song, song_state_ptr)) => {
+            // the song is played onto the portals before each update, so the update can't be
+            // built by the root
+            for &(func_name, lifecycle) in &[
+                (&module_meta.init_func_name, LifecycleFunc::Construct),
+                (&module_meta.cleanup_func_name, LifecycleFunc::Destruct),
+            ] {
+                root::build_lifecycle_func(
+                    &module,
+                    cache,
+                    0,
+                    func_name,
+                    lifecycle,
+                    pointers_global.as_pointer_value(),
+                );
+            }
+
+            let generate_func =
+                util::get_or_create_func(module, &module_meta.generate_func_name, false, &|| {
+                    (
+                        Linkage::ExternalLinkage,
+                        module.get_context().void_type().fn_type(&[], false),
+                    )
+                });
+            build_context_function(
+                module,
+                generate_func,
+                cache.target(),
+                &|mut ctx: BuilderContext| {
+                    build_song_frame_playback(
+                        &mut ctx,
+                        root,
+                        song,
+                        song_state_ptr,
+                        sockets_global.sockets.as_pointer_value(),
+                    );
+                    let saved_mxcsr = root::build_enable_flush_to_zero(&ctx);
+                    surface::build_lifecycle_call(
+                        ctx.module,
+                        cache,
+                        ctx.b,
+                        0,
+                        LifecycleFunc::Update,
+                        pointers_global.as_pointer_value(),
+                    );
+                    root::build_restore_mxcsr(&ctx, saved_mxcsr);
+                    ctx.b.build_return(None);
+                },
+            );
+        }
+        None => root::build_funcs(
+            &module,
+            cache,
+            0,
+            &module_meta.init_func_name,
+            &module_meta.generate_func_name,
+            &module_meta.cleanup_func_name,
+            pointers_global.as_pointer_value(),
+        ),
+    }
+    root::build_socket_accessor_func(
         &module,
         cache,
-        0,
-        &module_meta.init_func_name,
-        &module_meta.generate_func_name,
-        &module_meta.cleanup_func_name,
-        pointers_global.as_pointer_value(),
+        &module_meta.portal_func_name,
+        sockets_global.socket_ptrs.as_pointer_value(),
     );
-    root::build_socket_accessor_func(
+    root::build_midi_push_func(
         &module,
         cache,
-        &module_meta.portal_func_name,
+        &module_meta.midi_push_func_name,
         sockets_global.socket_ptrs.as_pointer_value(),
     );
+
+    let buffers_global = root::build_portal_buffers_global(
+        &module,
+        cache,
+        root,
+        "maxim.data.buffers",
+        "maxim.data.buffers.ptr",
+    );
+    let midi_queues_global =
+        root::build_midi_queues_global(&module, root, "maxim.data.midi_queues");
+    match song_playback {
+        Some((song, song_state_ptr)) => {
+            let generate_block_func = util::get_or_create_func(
+                module,
+                &module_meta.generate_block_func_name,
+                false,
+                &|| {
+                    let context = module.get_context();
+                    (
+                        Linkage::ExternalLinkage,
+                        context.i32_type().fn_type(&[&context.i32_type()], false),
+                    )
+                },
+            );
+            build_context_function(
+                module,
+                generate_block_func,
+                cache.target(),
+                &|mut ctx: BuilderContext| {
+                    let frames = ctx.func.get_nth_param(0).unwrap().into_int_value();
+                    build_song_block_playback(
+                        &mut ctx,
+                        root,
+                        song,
+                        song_state_ptr,
+                        frames,
+                        midi_queues_global.as_pointer_value(),
+                    );
+                    let frame_count = root::build_block_update(
+                        &mut ctx,
+                        cache,
+                        0,
+                        frames,
+                        pointers_global.as_pointer_value(),
+                        sockets_global.sockets.as_pointer_value(),
+                        buffers_global.buffers.as_pointer_value(),
+                        midi_queues_global.as_pointer_value(),
+                        root,
+                    );
+                    ctx.b.build_return(Some(&frame_count));
+                },
+            );
+        }
+        None => root::build_block_update_func(
+            &module,
+            cache,
+            0,
+            &module_meta.generate_block_func_name,
+            pointers_global.as_pointer_value(),
+            sockets_global.sockets.as_pointer_value(),
+            buffers_global.buffers.as_pointer_value(),
+            midi_queues_global.as_pointer_value(),
+            root,
+        ),
+    }
+    root::build_midi_queue_func(
+        &module,
+        cache,
+        &module_meta.midi_queue_func_name,
+        root,
+        midi_queues_global.as_pointer_value(),
+    );
+    root::build_socket_accessor_func(
+        &module,
+        cache,
+        &module_meta.portal_buffer_func_name,
+        buffers_global.buffer_ptrs.as_pointer_value(),
+    );
+    build_param_funcs(
+        module,
+        module_meta,
+        cache,
+        params,
+        initialized_global.as_pointer_value(),
+    );
+}
+
+/// Builds the functions of an instanced export. Instead of globals, everything an instance changes
+/// is kept in memory the host provides, which is passed as the first parameter of each function.
+/// That includes the initialized data, since parameters are written into it.
+fn build_instanced_root(
+    module: &Module,
+    module_meta: &ModuleMetadata,
+    cache: &dyn ObjectCache,
+    root: &mir::Root,
+    params: &[usize],
+    defaults: &InstanceDefaults,
+    song: Option<&SongConfig>,
+) {
+    let context = module.get_context();
+    let target_data = cache.target().machine.get_data();
+    let size_type = target_data.int_ptr_type_in_context(&context);
+    let void_ptr_type = context.i8_type().ptr_type(AddressSpace::Generic);
+    let layout = cache.surface_layout(0).unwrap();
+    let socket_count = root.sockets.len() as u32;
+
+    let instance_type = context.struct_type(
+        &[
+            &globals::get_instance_globals_type(module),
+            &layout.initialized_const.get_type(),
+            &root::get_scratch_type(&context, cache, 0),
+            &root::get_sockets_type(&context, cache, root),
+            &root::get_portal_buffers_type(&context, cache, root),
+            &layout.pointer_struct,
+            &void_ptr_type.array_type(socket_count),
+            &void_ptr_type.array_type(socket_count),
+            &root::get_midi_queues_type(&context, root),
+            &get_song_state_type(&context),
+            // storing the arena as vectors keeps it 16-byte aligned
+            &context
+                .f64_type()
+                .vec_type(2)
+                .array_type(((defaults.arena_size + 15) / 16) as u32),
+        ],
+        false,
+    );
+    let instance_size = target_data.get_abi_size(&instance_type);
+
+    let state_size_func =
+        util::get_or_create_func(module, &module_meta.state_size_func_name, false, &|| {
+            (Linkage::ExternalLinkage, size_type.fn_type(&[], false))
+        });
+    build_context_function(
+        module,
+        state_size_func,
+        cache.target(),
+        &|ctx: BuilderContext| {
+            ctx.b
+                .build_return(Some(&size_type.const_int(instance_size, false)));
+        },
+    );
+
+    build_instance_func(
+        module,
+        cache,
+        &module_meta.create_func_name,
+        &instance_type,
+        &|| context.void_type().fn_type(&[&void_ptr_type], false),
+        &|ctx, instance_ptr| {
+            // the memory could contain anything, so start from zero like globals do
+            ctx.b.build_call(
+                &intrinsics::memset(ctx.module, &target_data),
+                &[
+                    &ctx.func.get_nth_param(0).unwrap(),
+                    &ctx.context.i8_type().const_int(0, false),
+                    &size_type.const_int(instance_size, false),
+                    &ctx.context.i32_type().const_int(16, false),
+                    &ctx.context.bool_type().const_int(0, false),
+                ],
+                "",
+                false,
+            );
+
+            let globals_ptr = get_instance_field(ctx, instance_ptr, INSTANCE_GLOBALS_FIELD);
+            ctx.b.build_store(
+                &globals_ptr,
+                &globals::get_instance_globals_const(
+                    ctx.module,
+                    defaults.sample_rate,
+                    defaults.bpm,
+                    defaults.arena_size,
+                ),
+            );
+            let initialized_ptr = get_instance_field(ctx, instance_ptr, INSTANCE_INITIALIZED_FIELD);
+            ctx.b
+                .build_store(&initialized_ptr, &layout.initialized_const);
+            let arena_ptr = get_instance_field(ctx, instance_ptr, INSTANCE_ARENA_FIELD);
+            ctx.b.build_store(
+                &globals::build_arena_ptr(ctx.module, ctx.b),
+                &ctx.b.build_pointer_cast(arena_ptr, void_ptr_type, "arena"),
+            );
+
+            let sockets_ptr = get_instance_field(ctx, instance_ptr, INSTANCE_SOCKETS_FIELD);
+            let buffers_ptr = get_instance_field(ctx, instance_ptr, INSTANCE_BUFFERS_FIELD);
+            let pointers_ptr = get_instance_field(ctx, instance_ptr, INSTANCE_POINTERS_FIELD);
+            root::build_store_pointer_source(
+                ctx.b,
+                ctx.context,
+                pointers_ptr,
+                &PointerSource::Aggregate(
+                    PointerSourceAggregateType::Struct,
+                    layout.pointer_sources.clone(),
+                ),
+                initialized_ptr,
+                get_instance_field(ctx, instance_ptr, INSTANCE_SCRATCH_FIELD),
+                sockets_ptr,
+            );
+
+            // the portal accessors can't index into the sockets and buffers with the ID they're
+            // given, so keep a pointer to each one
+            let socket_ptrs_ptr = get_instance_field(ctx, instance_ptr, INSTANCE_SOCKET_PTRS_FIELD);
+            let buffer_ptrs_ptr = get_instance_field(ctx, instance_ptr, INSTANCE_BUFFER_PTRS_FIELD);
+            let const_zero = ctx.context.i32_type().const_int(0, false);
+            for socket_index in 0..socket_count {
+                let socket_index = ctx
+                    .context
+                    .i32_type()
+                    .const_int(u64::from(socket_index), false);
+                for &(values_ptr, ptrs_ptr) in &[
+                    (sockets_ptr, socket_ptrs_ptr),
+                    (buffers_ptr, buffer_ptrs_ptr),
+                ] {
+                    let value_ptr = unsafe {
+                        ctx.b
+                            .build_in_bounds_gep(&values_ptr, &[const_zero, socket_index], "")
+                    };
+                    let ptr_ptr = unsafe {
+                        ctx.b
+                            .build_in_bounds_gep(&ptrs_ptr, &[const_zero, socket_index], "")
+                    };
+                    ctx.b.build_store(
+                        &ptr_ptr,
+                        &ctx.b.build_pointer_cast(value_ptr, void_ptr_type, ""),
+                    );
+                }
+            }
+
+            surface::build_lifecycle_call(
+                ctx.module,
+                cache,
+                ctx.b,
+                0,
+                LifecycleFunc::Construct,
+                pointers_ptr,
+            );
+            ctx.b.build_return(None);
+        },
+    );
+
+    build_instance_func(
+        module,
+        cache,
+        &module_meta.cleanup_func_name,
+        &instance_type,
+        &|| context.void_type().fn_type(&[&void_ptr_type], false),
+        &|ctx, instance_ptr| {
+            let pointers_ptr = get_instance_field(ctx, instance_ptr, INSTANCE_POINTERS_FIELD);
+            surface::build_lifecycle_call(
+                ctx.module,
+                cache,
+                ctx.b,
+                0,
+                LifecycleFunc::Destruct,
+                pointers_ptr,
+            );
+            ctx.b.build_return(None);
+        },
+    );
+
+    build_instance_func(
+        module,
+        cache,
+        &module_meta.generate_func_name,
+        &instance_type,
+        &|| context.void_type().fn_type(&[&void_ptr_type], false),
+        &|ctx, instance_ptr| {
+            if let Some(song) = song {
+                let song_state_ptr = get_instance_field(ctx, instance_ptr, INSTANCE_SONG_FIELD);
+                let sockets_ptr = get_instance_field(ctx, instance_ptr, INSTANCE_SOCKETS_FIELD);
+                build_song_frame_playback(ctx, root, song, song_state_ptr, sockets_ptr);
+            }
+
+            let pointers_ptr = get_instance_field(ctx, instance_ptr, INSTANCE_POINTERS_FIELD);
+            let saved_mxcsr = root::build_enable_flush_to_zero(ctx);
+            surface::build_lifecycle_call(
+                ctx.module,
+                cache,
+                ctx.b,
+                0,
+                LifecycleFunc::Update,
+                pointers_ptr,
+            );
+            root::build_restore_mxcsr(ctx, saved_mxcsr);
+            ctx.b.build_return(None);
+        },
+    );
+
+    build_instance_func(
+        module,
+        cache,
+        &module_meta.generate_block_func_name,
+        &instance_type,
+        &|| {
+            context
+                .i32_type()
+                .fn_type(&[&void_ptr_type, &context.i32_type()], false)
+        },
+        &|ctx, instance_ptr| {
+            let frames = ctx.func.get_nth_param(1).unwrap().into_int_value();
+            let pointers_ptr = get_instance_field(ctx, instance_ptr, INSTANCE_POINTERS_FIELD);
+            let sockets_ptr = get_instance_field(ctx, instance_ptr, INSTANCE_SOCKETS_FIELD);
+            let buffers_ptr = get_instance_field(ctx, instance_ptr, INSTANCE_BUFFERS_FIELD);
+            let midi_queues_ptr = get_instance_field(ctx, instance_ptr, INSTANCE_MIDI_QUEUES_FIELD);
+            if let Some(song) = song {
+                let song_state_ptr = get_instance_field(ctx, instance_ptr, INSTANCE_SONG_FIELD);
+                build_song_block_playback(ctx, root, song, song_state_ptr, frames, midi_queues_ptr);
+            }
+            let frame_count = root::build_block_update(
+                ctx,
+                cache,
+                0,
+                frames,
+                pointers_ptr,
+                sockets_ptr,
+                buffers_ptr,
+                midi_queues_ptr,
+                root,
+            );
+            ctx.b.build_return(Some(&frame_count));
+        },
+    );
+
+    for &(func_name, ptrs_field) in &[
+        (&module_meta.portal_func_name, INSTANCE_SOCKET_PTRS_FIELD),
+        (
+            &module_meta.portal_buffer_func_name,
+            INSTANCE_BUFFER_PTRS_FIELD,
+        ),
+    ] {
+        build_instance_func(
+            module,
+            cache,
+            func_name,
+            &instance_type,
+            &|| void_ptr_type.fn_type(&[&void_ptr_type, &context.i32_type()], false),
+            &|ctx, instance_ptr| {
+                let ptr = build_load_instance_ptr(ctx, instance_ptr, ptrs_field);
+                ctx.b.build_return(Some(&ptr));
+            },
+        );
+    }
+
+    build_instance_func(
+        module,
+        cache,
+        &module_meta.midi_push_func_name,
+        &instance_type,
+        &|| {
+            context.void_type().fn_type(
+                &[
+                    &void_ptr_type,
+                    &context.i32_type(),
+                    &context.i8_type(),
+                    &context.i8_type(),
+                    &context.i8_type(),
+                    &context.i8_type(),
+                ],
+                false,
+            )
+        },
+        &|ctx, instance_ptr| {
+            let socket_ptr = build_load_instance_ptr(ctx, instance_ptr, INSTANCE_SOCKET_PTRS_FIELD);
+            root::build_midi_push(ctx, socket_ptr, 2);
+            ctx.b.build_return(None);
+        },
+    );
+
+    build_instance_func(
+        module,
+        cache,
+        &module_meta.midi_queue_func_name,
+        &instance_type,
+        &|| {
+            context.void_type().fn_type(
+                &[
+                    &void_ptr_type,
+                    &context.i32_type(),
+                    &context.i32_type(),
+                    &context.i8_type(),
+                    &context.i8_type(),
+                    &context.i8_type(),
+                    &context.i8_type(),
+                ],
+                false,
+            )
+        },
+        &|ctx, instance_ptr| {
+            let midi_queues_ptr = get_instance_field(ctx, instance_ptr, INSTANCE_MIDI_QUEUES_FIELD);
+            root::build_midi_queue(ctx, root, midi_queues_ptr, 1);
+            ctx.b.build_return(None);
+        },
+    );
+
+    build_instance_func(
+        module,
+        cache,
+        &module_meta.set_param_func_name,
+        &instance_type,
+        &|| {
+            context.void_type().fn_type(
+                &[&void_ptr_type, &context.i32_type(), &context.f64_type()],
+                false,
+            )
+        },
+        &|ctx, instance_ptr| {
+            let initialized_ptr = get_instance_field(ctx, instance_ptr, INSTANCE_INITIALIZED_FIELD);
+            build_set_param(ctx, cache, params, initialized_ptr, 1);
+        },
+    );
+    build_instance_func(
+        module,
+        cache,
+        &module_meta.get_param_func_name,
+        &instance_type,
+        &|| {
+            context
+                .f64_type()
+                .fn_type(&[&void_ptr_type, &context.i32_type()], false)
+        },
+        &|ctx, instance_ptr| {
+            let initialized_ptr = get_instance_field(ctx, instance_ptr, INSTANCE_INITIALIZED_FIELD);
+            build_get_param(ctx, cache, params, initialized_ptr, 1);
+        },
+    );
+}
+
+fn build_param_funcs(
+    module: &Module,
+    module_meta: &ModuleMetadata,
+    cache: &dyn ObjectCache,
+    params: &[usize],
+    initialized_ptr: PointerValue,
+) {
+    let context = module.get_context();
+    let set_func =
+        util::get_or_create_func(module, &module_meta.set_param_func_name, false, &|| {
+            (
+                Linkage::ExternalLinkage,
+                context
+                    .void_type()
+                    .fn_type(&[&context.i32_type(), &context.f64_type()], false),
+            )
+        });
+    build_context_function(
+        module,
+        set_func,
+        cache.target(),
+        &|mut ctx: BuilderContext| {
+            build_set_param(&mut ctx, cache, params, initialized_ptr, 0);
+        },
+    );
+
+    let get_func =
+        util::get_or_create_func(module, &module_meta.get_param_func_name, false, &|| {
+            (
+                Linkage::ExternalLinkage,
+                context.f64_type().fn_type(&[&context.i32_type()], false),
+            )
+        });
+    build_context_function(
+        module,
+        get_func,
+        cache.target(),
+        &|mut ctx: BuilderContext| {
+            build_get_param(&mut ctx, cache, params, initialized_ptr, 0);
+        },
+    );
+}
+
+/// Builds the body of a function that sets a parameter to a value on both channels. The ID and
+/// value are the function's parameters starting at `first_param`. Unknown IDs are ignored.
+fn build_set_param(
+    ctx: &mut BuilderContext,
+    cache: &dyn ObjectCache,
+    params: &[usize],
+    initialized_ptr: PointerValue,
+    first_param: u32,
+) {
+    let param_id = ctx
+        .func
+        .get_nth_param(first_param)
+        .unwrap()
+        .into_int_value();
+    let value = ctx
+        .func
+        .get_nth_param(first_param + 1)
+        .unwrap()
+        .into_float_value();
+    build_param_switch(
+        ctx,
+        cache,
+        params,
+        initialized_ptr,
+        param_id,
+        &|builder, param_num| {
+            param_num.set_vec(builder, util::splat_vector(builder, value, "param.value"));
+            builder.build_return(None);
+        },
+    );
+    ctx.b.build_return(None);
+}
+
+/// Builds the body of a function that returns the left channel of a parameter. The ID is the
+/// function's parameter at `first_param`. Unknown IDs return zero.
+fn build_get_param(
+    ctx: &mut BuilderContext,
+    cache: &dyn ObjectCache,
+    params: &[usize],
+    initialized_ptr: PointerValue,
+    first_param: u32,
+) {
+    let context = ctx.context;
+    let param_id = ctx
+        .func
+        .get_nth_param(first_param)
+        .unwrap()
+        .into_int_value();
+    build_param_switch(
+        ctx,
+        cache,
+        params,
+        initialized_ptr,
+        param_id,
+        &|builder, param_num| {
+            let param_vec = param_num.get_vec(builder);
+            let value = builder.build_extract_element(
+                &param_vec,
+                &context.i32_type().const_int(0, false),
+                "param.value",
+            );
+            builder.build_return(Some(&value));
+        },
+    );
+    ctx.b
+        .build_return(Some(&context.f64_type().const_float(0.)));
+}
+
+/// Builds a switch over the parameters into the current function. Each case gets a builder in its
+/// own block and the storage of its parameter, and has to return. The function's builder is left in
+/// the block that unknown IDs go to.
+fn build_param_switch(
+    ctx: &mut BuilderContext,
+    cache: &dyn ObjectCache,
+    params: &[usize],
+    initialized_ptr: PointerValue,
+    param_id: IntValue,
+    build_case: &dyn Fn(&mut Builder, NumValue),
+) {
+    let mut case_builder = ctx.context.create_builder();
+    let unknown_block = ctx.context.append_basic_block(&ctx.func, "param.unknown");
+
+    let mut switch_cases = Vec::new();
+    for &param in params {
+        let path = match data_analyzer::find_param_initialized_path(cache, 0, param) {
+            Some(path) => path,
+            None => continue,
+        };
+
+        let param_block = ctx
+            .context
+            .append_basic_block(&ctx.func, &format!("param.{}", param));
+        case_builder.position_at_end(&param_block);
+        let indices: Vec<_> = iter::once(0)
+            .chain(path)
+            .map(|index| ctx.context.i32_type().const_int(index as u64, false))
+            .collect();
+        let storage_ptr =
+            unsafe { case_builder.build_in_bounds_gep(&initialized_ptr, &indices, "param.ptr") };
+        build_case(&mut case_builder, NumValue::new(storage_ptr));
+
+        let param_id_const = ctx.context.i32_type().const_int(param as u64, false);
+        switch_cases.push((param_id_const, param_block));
+    }
+    let switch_refs: Vec<_> = switch_cases.iter().map(|&(ref a, ref b)| (a, b)).collect();
+    ctx.b.build_switch(&param_id, &unknown_block, &switch_refs);
+    ctx.b.position_at_end(&unknown_block);
+}
+
+/// Builds a function that takes an instance as its first parameter. The body goes in an internal
+/// function that's also given the instance's globals as its last parameter, which it passes on to
+/// every function it calls that uses them, so calls into different instances never share anything.
+fn build_instance_func(
+    module: &Module,
+    cache: &dyn ObjectCache,
+    name: &str,
+    instance_type: &StructType,
+    get_type: &dyn Fn() -> FunctionType,
+    cb: &dyn Fn(&mut BuilderContext, PointerValue),
+) {
+    let func = util::get_or_create_func(module, name, false, &|| {
+        (Linkage::ExternalLinkage, get_type())
+    });
+    let body_func = util::get_or_create_func(module, &format!("{}.body", name), true, &|| {
+        (
+            Linkage::PrivateLinkage,
+            globals::add_instance_param(module, cache.target(), get_type()),
+        )
+    });
+    build_context_function(module, func, cache.target(), &|ctx: BuilderContext| {
+        let instance_ptr = ctx.b.build_pointer_cast(
+            ctx.func.get_nth_param(0).unwrap().into_pointer_value(),
+            instance_type.ptr_type(AddressSpace::Generic),
+            "instance",
+        );
+        let globals_ptr = get_instance_field(&ctx, instance_ptr, INSTANCE_GLOBALS_FIELD);
+        let args: Vec<_> = ctx
+            .func
+            .params()
+            .chain(iter::once(globals_ptr.into()))
+            .collect();
+        let arg_refs: Vec<_> = args.iter().map(|arg| arg as &BasicValue).collect();
+        let result = ctx.b.build_call(&body_func, &arg_refs, "", true).left();
+        ctx.b
+            .build_return(result.as_ref().map(|val| val as &BasicValue));
+    });
+    build_context_function(
+        module,
+        body_func,
+        cache.target(),
+        &|mut ctx: BuilderContext| {
+            let instance_ptr = ctx.b.build_pointer_cast(
+                ctx.func.get_nth_param(0).unwrap().into_pointer_value(),
+                instance_type.ptr_type(AddressSpace::Generic),
+                "instance",
+            );
+            cb(&mut ctx, instance_ptr);
+        },
+    );
+}
+
+fn get_instance_field(
+    ctx: &BuilderContext,
+    instance_ptr: PointerValue,
+    field: u32,
+) -> PointerValue {
+    unsafe { ctx.b.build_struct_gep(&instance_ptr, field, "") }
+}
+
+/// Loads the pointer at the index given by the function's second parameter from one of the
+/// instance's pointer arrays.
+fn build_load_instance_ptr(
+    ctx: &BuilderContext,
+    instance_ptr: PointerValue,
+    ptrs_field: u32,
+) -> PointerValue {
+    let ptrs_ptr = get_instance_field(ctx, instance_ptr, ptrs_field);
+    let ptr_ptr = unsafe {
+        ctx.b.build_in_bounds_gep(
+            &ptrs_ptr,
+            &[
+                ctx.context.i32_type().const_int(0, false),
+                ctx.func.get_nth_param(1).unwrap().into_int_value(),
+            ],
+            "",
+        )
+    };
+    ctx.b.build_load(&ptr_ptr, "").into_pointer_value()
 }
 
 fn prepare_surfaces(
@@ -163,7 +960,7 @@ fn prepare_surfaces(
     target: &TargetProperties,
 ) -> HashMap<mir::SurfaceRef, mir::Surface> {
     HashMap::from_iter(
-        mir_optimizer::prepare_surfaces(surfaces, allocator, target)
+        mir_optimizer::prepare_surfaces(surfaces, allocator, target.include_ui)
             .map(|mut surface| {
                 pass::sort_value_groups(&mut surface);
                 surface
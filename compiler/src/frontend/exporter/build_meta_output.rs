@@ -1,4 +1,7 @@
-use super::export_config::{AudioConfig, CodeConfig, MetaFormat, MetaOutputConfig};
+use super::export_config::{AudioConfig, CodeConfig, MetaFormat, MetaOutputConfig, SongConfig};
+use crate::codegen::root::MAX_BLOCK_FRAMES;
+use crate::codegen::values::{MIDI_EVENT_COUNT, MIDI_QUEUE_CAPACITY};
+use crate::codegen::StoragePrecision;
 use lazy_static::lazy_static;
 use regex::Regex;
 use std::borrow::Cow;
@@ -14,7 +17,15 @@ pub struct ModuleMetadata {
     pub init_func_name: String,
     pub cleanup_func_name: String,
     pub generate_func_name: String,
+    pub generate_block_func_name: String,
     pub portal_func_name: String,
+    pub portal_buffer_func_name: String,
+    pub midi_push_func_name: String,
+    pub midi_queue_func_name: String,
+    pub create_func_name: String,
+    pub state_size_func_name: String,
+    pub set_param_func_name: String,
+    pub get_param_func_name: String,
 }
 
 fn determine_c_file_name(output_path: &path::Path) -> Option<String> {
@@ -28,21 +39,92 @@ pub fn build_meta_output(
     audio_config: &AudioConfig,
     code_config: &CodeConfig,
     meta_config: &MetaOutputConfig,
+    song: Option<&SongConfig>,
     module_data: &ModuleMetadata,
 ) -> fmt::Result {
     let c_file_name = determine_c_file_name(&meta_config.location).unwrap();
     let def_prefix = code_config.instrument_prefix.to_uppercase();
     let portal_count = meta_config.portal_names.len().to_string();
+    let param_count = meta_config.params.len().to_string();
+    let param_values: Vec<_> = meta_config
+        .params
+        .iter()
+        .map(|param| {
+            // the debug format always includes a decimal point, so it reads as a float literal in
+            // every output format
+            [
+                format!("{:?}", param.min),
+                format!("{:?}", param.max),
+                format!("{:?}", param.default),
+                param.unit.to_string(),
+            ]
+        })
+        .collect();
     let samplerate_str = audio_config.sample_rate.to_string();
     let bpm_str = audio_config.bpm.to_string();
+    let max_block_frames_str = MAX_BLOCK_FRAMES.to_string();
+    let sample_type_str = match (meta_config.format, code_config.storage_precision) {
+        (MetaFormat::RustModule, StoragePrecision::Double) => "f64",
+        (MetaFormat::RustModule, StoragePrecision::Single) => "f32",
+        (MetaFormat::JavaScript, StoragePrecision::Double) => "Float64Array",
+        (MetaFormat::JavaScript, StoragePrecision::Single) => "Float32Array",
+        (_, StoragePrecision::Double) => "double",
+        (_, StoragePrecision::Single) => "float",
+    };
+    // Each frame of a number portal buffer holds a left and right sample followed by the form,
+    // padded to the alignment of the samples. MIDI frames are a count followed by the events.
+    let portal_frame_stride_str = match code_config.storage_precision {
+        StoragePrecision::Double => "32",
+        StoragePrecision::Single => "16",
+    };
+    let midi_frame_stride_str = (1 + 4 * u32::from(MIDI_EVENT_COUNT)).to_string();
+    let midi_event_count_str = MIDI_EVENT_COUNT.to_string();
+    let midi_queue_capacity_str = MIDI_QUEUE_CAPACITY.to_string();
+    let instanced_str = code_config.instanced.to_string();
+    // without a song, the song is empty and doesn't loop
+    let song_length_str = song.map_or(0, |song| song.length).to_string();
+    let song_loop_range = song.and_then(|song| song.loop_range);
+    let song_loops_str = match (meta_config.format, song_loop_range.is_some()) {
+        (MetaFormat::CHeader, true) => "1",
+        (MetaFormat::CHeader, false) => "0",
+        (_, true) => "true",
+        (_, false) => "false",
+    };
+    let (song_loop_start, song_loop_end) = song_loop_range.unwrap_or((0, 0));
+    let song_loop_start_str = song_loop_start.to_string();
+    let song_loop_end_str = song_loop_end.to_string();
     let template_str = match meta_config.format {
+        MetaFormat::CHeader if code_config.instanced => {
+            include_str!("header_instanced_template.h.tasty")
+        }
         MetaFormat::CHeader => include_str!("header_template.h.tasty"),
+        MetaFormat::RustModule if code_config.instanced => {
+            include_str!("rust_module_instanced_template.rs.tasty")
+        }
         MetaFormat::RustModule => include_str!("rust_module_template.rs.tasty"),
         MetaFormat::Json => include_str!("json_template.json.tasty"),
+        MetaFormat::JavaScript => include_str!("javascript_module_template.js.tasty"),
     };
     let mut context: HashMap<_, &str> = HashMap::new();
     context.insert(Cow::Borrowed("SAMPLERATE"), &samplerate_str);
     context.insert(Cow::Borrowed("BPM"), &bpm_str);
+    context.insert(Cow::Borrowed("MAX_BLOCK_FRAMES"), &max_block_frames_str);
+    context.insert(Cow::Borrowed("SAMPLE_TYPE"), sample_type_str);
+    context.insert(
+        Cow::Borrowed("PORTAL_FRAME_STRIDE"),
+        portal_frame_stride_str,
+    );
+    context.insert(Cow::Borrowed("MIDI_FRAME_STRIDE"), &midi_frame_stride_str);
+    context.insert(Cow::Borrowed("MIDI_EVENT_COUNT"), &midi_event_count_str);
+    context.insert(
+        Cow::Borrowed("MIDI_QUEUE_CAPACITY"),
+        &midi_queue_capacity_str,
+    );
+    context.insert(Cow::Borrowed("INSTANCED"), &instanced_str);
+    context.insert(Cow::Borrowed("SONG_LENGTH"), &song_length_str);
+    context.insert(Cow::Borrowed("SONG_LOOPS"), song_loops_str);
+    context.insert(Cow::Borrowed("SONG_LOOP_START"), &song_loop_start_str);
+    context.insert(Cow::Borrowed("SONG_LOOP_END"), &song_loop_end_str);
     context.insert(Cow::Borrowed("C_FILE_NAME"), &c_file_name);
     context.insert(Cow::Borrowed("FUNC_PREFIX"), &code_config.instrument_prefix);
     context.insert(Cow::Borrowed("DEF_PREFIX"), &def_prefix);
@@ -53,6 +135,33 @@ pub fn build_meta_output(
             portal_name,
         );
     }
+    context.insert(Cow::Borrowed("PARAM_COUNT"), &param_count);
+    for (param_index, param) in meta_config.params.iter().enumerate() {
+        let [min_str, max_str, default_str, unit_str] = &param_values[param_index];
+        context.insert(
+            Cow::Owned(format!("PARAM_NAME_{}", param_index)),
+            &param.name,
+        );
+        context.insert(Cow::Owned(format!("PARAM_MIN_{}", param_index)), min_str);
+        context.insert(Cow::Owned(format!("PARAM_MAX_{}", param_index)), max_str);
+        context.insert(
+            Cow::Owned(format!("PARAM_DEFAULT_{}", param_index)),
+            default_str,
+        );
+        context.insert(Cow::Owned(format!("PARAM_UNIT_{}", param_index)), unit_str);
+
+        // templates can't tell which iteration of a loop is the last, so lists that need separators
+        // (like JSON arrays) take them from here
+        let separator = if param_index + 1 < meta_config.params.len() {
+            ","
+        } else {
+            ""
+        };
+        context.insert(
+            Cow::Owned(format!("PARAM_SEPARATOR_{}", param_index)),
+            separator,
+        );
+    }
     context.insert(Cow::Borrowed("INIT_FUNC_NAME"), &module_data.init_func_name);
     context.insert(
         Cow::Borrowed("CLEANUP_FUNC_NAME"),
@@ -62,10 +171,42 @@ pub fn build_meta_output(
         Cow::Borrowed("GENERATE_FUNC_NAME"),
         &module_data.generate_func_name,
     );
+    context.insert(
+        Cow::Borrowed("GENERATE_BLOCK_FUNC_NAME"),
+        &module_data.generate_block_func_name,
+    );
     context.insert(
         Cow::Borrowed("PORTAL_FUNC_NAME"),
         &module_data.portal_func_name,
     );
+    context.insert(
+        Cow::Borrowed("PORTAL_BUFFER_FUNC_NAME"),
+        &module_data.portal_buffer_func_name,
+    );
+    context.insert(
+        Cow::Borrowed("MIDI_PUSH_FUNC_NAME"),
+        &module_data.midi_push_func_name,
+    );
+    context.insert(
+        Cow::Borrowed("MIDI_QUEUE_FUNC_NAME"),
+        &module_data.midi_queue_func_name,
+    );
+    context.insert(
+        Cow::Borrowed("CREATE_FUNC_NAME"),
+        &module_data.create_func_name,
+    );
+    context.insert(
+        Cow::Borrowed("STATE_SIZE_FUNC_NAME"),
+        &module_data.state_size_func_name,
+    );
+    context.insert(
+        Cow::Borrowed("SET_PARAM_FUNC_NAME"),
+        &module_data.set_param_func_name,
+    );
+    context.insert(
+        Cow::Borrowed("GET_PARAM_FUNC_NAME"),
+        &module_data.get_param_func_name,
+    );
 
     match process_template(f, template_str, &context) {
         Err(Error::Writer(err)) => Err(err),
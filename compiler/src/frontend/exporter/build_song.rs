@@ -0,0 +1,440 @@
+use super::export_config::SongConfig;
+use crate::codegen::root::{get_midi_portals, MAX_BLOCK_FRAMES};
+use crate::codegen::values::{MidiEventValue, MidiQueueValue, MidiValue};
+use crate::codegen::{util, BuilderContext};
+use crate::mir::Root;
+use inkwell::builder::Builder;
+use inkwell::context::Context;
+use inkwell::module::Module;
+use inkwell::types::StructType;
+use inkwell::values::{BasicValue, GlobalValue, IntValue, PointerValue};
+use inkwell::IntPredicate;
+
+/// The type of the playback state of a song, which is the frame it's up to, the index of the next
+/// entry in its table and the frame the entry before it was due on. Like the rest of an
+/// instrument's state, it starts from zero.
+pub fn get_song_state_type(context: &Context) -> StructType {
+    context.struct_type(
+        &[
+            &context.i32_type(), // position
+            &context.i32_type(), // cursor
+            &context.i32_type(), // base
+        ],
+        false,
+    )
+}
+
+pub fn build_song_state_global(module: &Module, name: &str) -> GlobalValue {
+    let state_type = get_song_state_type(&module.get_context());
+    let global = util::get_or_create_global(module, name, &state_type);
+    global.set_initializer(&state_type.const_null());
+    global
+}
+
+/// The queue index of entries that don't hold an event, which fill gaps between events that are
+/// too long for a single delta.
+pub const SONG_WAIT_QUEUE: u8 = u8::MAX;
+
+/// An entry in the table of a song, due `delta` frames after the entry before it, or after the
+/// start of the song for the first one.
+pub struct SongEntry {
+    pub delta: u16,
+    /// The index of the MIDI queue of the event's portal, in the order of `get_midi_portals`, or
+    /// `SONG_WAIT_QUEUE`.
+    pub queue: u8,
+    pub event: [u8; 4],
+}
+
+/// The table of a song that can be played, in the order the entries are due. Events for portals
+/// that don't hold MIDI are left out, as are events for MIDI portals past the 255th, since their
+/// queue index wouldn't fit.
+pub struct SongEntries {
+    pub entries: Vec<SongEntry>,
+    /// The index of the entry playback continues from after looping.
+    pub loop_cursor: usize,
+    /// The frame the entry before the loop cursor is due on, which its delta is from.
+    pub loop_base: u32,
+}
+
+pub fn get_song_entries(root: &Root, song: &SongConfig) -> SongEntries {
+    let midi_portals = get_midi_portals(root);
+    let mut events: Vec<_> = song
+        .events
+        .iter()
+        .filter_map(|event| {
+            let queue_index = midi_portals
+                .iter()
+                .position(|&portal| portal == event.portal)
+                .filter(|&queue_index| queue_index < usize::from(SONG_WAIT_QUEUE))?;
+            Some((queue_index as u8, event))
+        })
+        .collect();
+    events.sort_by_key(|&(_, event)| event.frame);
+
+    // entries are paired with the frame they're due on until the loop cursor has been found
+    let mut timed_entries = Vec::new();
+    let mut last_frame = 0;
+    for (queue_index, event) in events {
+        while event.frame - last_frame > u32::from(u16::MAX) {
+            last_frame += u32::from(u16::MAX);
+            let wait_entry = SongEntry {
+                delta: u16::MAX,
+                queue: SONG_WAIT_QUEUE,
+                event: [0; 4],
+            };
+            timed_entries.push((last_frame, wait_entry));
+        }
+        let entry = SongEntry {
+            delta: (event.frame - last_frame) as u16,
+            queue: queue_index,
+            event: [event.event, event.channel, event.note, event.param],
+        };
+        timed_entries.push((event.frame, entry));
+        last_frame = event.frame;
+    }
+
+    let loop_cursor = match song.loop_range {
+        Some((loop_start, _)) => timed_entries
+            .iter()
+            .position(|&(frame, _)| frame >= loop_start)
+            .unwrap_or_else(|| timed_entries.len()),
+        None => timed_entries.len(),
+    };
+    let loop_base = match loop_cursor {
+        0 => 0,
+        loop_cursor => timed_entries[loop_cursor - 1].0,
+    };
+    SongEntries {
+        entries: timed_entries.into_iter().map(|(_, entry)| entry).collect(),
+        loop_cursor,
+        loop_base,
+    }
+}
+
+struct SongTable {
+    entries: PointerValue,
+    count: u32,
+    loop_cursor: u32,
+    loop_base: u32,
+}
+
+fn build_song_table(module: &Module, root: &Root, song: &SongConfig) -> SongTable {
+    let context = module.get_context();
+    let song_entries = get_song_entries(root, song);
+    let entry_type = get_song_entry_type(&context);
+    let entries: Vec<_> = song_entries
+        .entries
+        .iter()
+        .map(|entry| {
+            let [event, channel, note, param] = entry.event;
+            entry_type.const_named_struct(&[
+                &context.i16_type().const_int(u64::from(entry.delta), false) as &dyn BasicValue,
+                &context.i8_type().const_int(u64::from(entry.queue), false),
+                &MidiEventValue::get_const(&context, event, channel, note, param),
+            ])
+        })
+        .collect();
+    let table_type = entry_type.array_type(entries.len() as u32);
+    let table_global = util::get_or_create_global(module, "maxim.song.events", &table_type);
+    table_global.set_constant(true);
+    table_global.set_initializer(&entry_type.const_array(&entries));
+
+    SongTable {
+        entries: table_global.as_pointer_value(),
+        count: entries.len() as u32,
+        loop_cursor: song_entries.loop_cursor as u32,
+        loop_base: song_entries.loop_base,
+    }
+}
+
+fn get_song_entry_type(context: &Context) -> StructType {
+    context.struct_type(
+        &[
+            &context.i16_type(),                // delta
+            &context.i8_type(),                 // queue index
+            &MidiEventValue::get_type(context), // event
+        ],
+        false,
+    )
+}
+
+/// Plays the song for a block, queueing each event that's due in it on the MIDI queue of its
+/// portal. Blocks are limited to `MAX_BLOCK_FRAMES` in the same way as the block update, which has
+/// to be built after this so the events are delivered in the same block. Events past the capacity
+/// of a queue are dropped.
+pub fn build_song_block_playback(
+    ctx: &mut BuilderContext,
+    root: &Root,
+    song: &SongConfig,
+    state: PointerValue,
+    frames: IntValue,
+    midi_queues: PointerValue,
+) {
+    let module = ctx.module;
+    let max_frames = ctx
+        .context
+        .i32_type()
+        .const_int(u64::from(MAX_BLOCK_FRAMES), false);
+    let frame_count = ctx
+        .b
+        .build_select(
+            ctx.b
+                .build_int_compare(IntPredicate::UGT, frames, max_frames, ""),
+            max_frames,
+            frames,
+            "song.framecount",
+        )
+        .into_int_value();
+    build_song_playback(
+        ctx,
+        root,
+        song,
+        state,
+        frame_count,
+        &|builder, queue_index, offset, event| {
+            let queue = MidiQueueValue::new(unsafe {
+                builder.build_struct_gep(&midi_queues, queue_index as u32, "midiqueue")
+            });
+            queue.push_event(builder, module, offset, event);
+        },
+    );
+}
+
+/// Plays the song for a single frame, pushing the events that are due onto their portals.
+pub fn build_song_frame_playback(
+    ctx: &mut BuilderContext,
+    root: &Root,
+    song: &SongConfig,
+    state: PointerValue,
+    sockets: PointerValue,
+) {
+    let module = ctx.module;
+    let context = ctx.context;
+    let midi_portals = get_midi_portals(root);
+    build_song_playback(
+        ctx,
+        root,
+        song,
+        state,
+        context.i32_type().const_int(1, false),
+        &|builder, queue_index, _, event| {
+            let portal_index = context
+                .i32_type()
+                .const_int(midi_portals[queue_index] as u64, false);
+            let midi = MidiValue::new(unsafe {
+                builder.build_in_bounds_gep(
+                    &sockets,
+                    &[context.i32_type().const_int(0, false), portal_index],
+                    "portal",
+                )
+            });
+            midi.push_event(builder, module, event);
+        },
+    );
+}
+
+/// Builds the playback of a song for `frames` frames into the current function. Each event that's
+/// due is given to `push_event` along with the index of its portal's MIDI queue and the frame in
+/// the block it's due on. If the song reaches the end of its loop partway through the block, the
+/// rest of the block is played from the start of the loop.
+fn build_song_playback(
+    ctx: &mut BuilderContext,
+    root: &Root,
+    song: &SongConfig,
+    state: PointerValue,
+    frames: IntValue,
+    push_event: &dyn Fn(&mut Builder, usize, IntValue, &MidiEventValue),
+) {
+    let table = build_song_table(ctx.module, root, song);
+    let i32_type = ctx.context.i32_type();
+    let const_zero = i32_type.const_int(0, false);
+    let const_one = i32_type.const_int(1, false);
+    let loop_range = song.loop_range.map(|(loop_start, loop_end)| {
+        (
+            i32_type.const_int(u64::from(loop_start), false),
+            i32_type.const_int(u64::from(loop_end), false),
+        )
+    });
+
+    let position_ptr = unsafe { ctx.b.build_struct_gep(&state, 0, "song.position.ptr") };
+    let cursor_ptr = unsafe { ctx.b.build_struct_gep(&state, 1, "song.cursor.ptr") };
+    let base_ptr = unsafe { ctx.b.build_struct_gep(&state, 2, "song.base.ptr") };
+    let played_ptr = ctx.allocb.build_alloca(&i32_type, "song.played.ptr");
+    ctx.b.build_store(&played_ptr, &const_zero);
+
+    let segment_block = ctx.context.append_basic_block(&ctx.func, "song.segment");
+    let check_block = ctx
+        .context
+        .append_basic_block(&ctx.func, "song.event.check");
+    let compare_block = ctx
+        .context
+        .append_basic_block(&ctx.func, "song.event.compare");
+    let play_block = ctx.context.append_basic_block(&ctx.func, "song.event.play");
+    let next_block = ctx.context.append_basic_block(&ctx.func, "song.event.next");
+    let segment_end_block = ctx
+        .context
+        .append_basic_block(&ctx.func, "song.segment.end");
+    let end_block = ctx.context.append_basic_block(&ctx.func, "song.end");
+
+    // The block is played in segments, which end at the end of the block or the end of the loop,
+    // whichever comes first.
+    ctx.b.build_unconditional_branch(&segment_block);
+    ctx.b.position_at_end(&segment_block);
+    let position = ctx
+        .b
+        .build_load(&position_ptr, "song.position")
+        .into_int_value();
+    let played = ctx
+        .b
+        .build_load(&played_ptr, "song.played")
+        .into_int_value();
+    let remaining = ctx.b.build_int_nuw_sub(frames, played, "song.remaining");
+    let mut segment_end = ctx
+        .b
+        .build_int_nuw_add(position, remaining, "song.segmentend");
+    if let Some((_, loop_end)) = loop_range {
+        segment_end = ctx
+            .b
+            .build_select(
+                ctx.b
+                    .build_int_compare(IntPredicate::UGT, segment_end, loop_end, ""),
+                loop_end,
+                segment_end,
+                "song.segmentend",
+            )
+            .into_int_value();
+    }
+    ctx.b.build_unconditional_branch(&check_block);
+
+    ctx.b.position_at_end(&check_block);
+    let cursor = ctx
+        .b
+        .build_load(&cursor_ptr, "song.cursor")
+        .into_int_value();
+    let has_event = ctx.b.build_int_compare(
+        IntPredicate::ULT,
+        cursor,
+        i32_type.const_int(u64::from(table.count), false),
+        "song.hasevent",
+    );
+    ctx.b
+        .build_conditional_branch(&has_event, &compare_block, &segment_end_block);
+
+    ctx.b.position_at_end(&compare_block);
+    let entry_ptr = unsafe {
+        ctx.b
+            .build_in_bounds_gep(&table.entries, &[const_zero, cursor], "song.entry.ptr")
+    };
+    let delta_ptr = unsafe {
+        ctx.b
+            .build_struct_gep(&entry_ptr, 0, "song.entry.delta.ptr")
+    };
+    let delta = ctx
+        .b
+        .build_load(&delta_ptr, "song.entry.delta")
+        .into_int_value();
+    let base = ctx.b.build_load(&base_ptr, "song.base").into_int_value();
+    let event_frame = ctx.b.build_int_nuw_add(
+        base,
+        ctx.b.build_int_z_extend(delta, i32_type, ""),
+        "song.entry.frame",
+    );
+    let is_due = ctx.b.build_int_compare(
+        IntPredicate::ULT,
+        event_frame,
+        segment_end,
+        "song.entry.isdue",
+    );
+    ctx.b
+        .build_conditional_branch(&is_due, &play_block, &segment_end_block);
+
+    ctx.b.position_at_end(&play_block);
+    let queue_index_ptr = unsafe {
+        ctx.b
+            .build_struct_gep(&entry_ptr, 1, "song.entry.queue.ptr")
+    };
+    let queue_index = ctx
+        .b
+        .build_load(&queue_index_ptr, "song.entry.queue")
+        .into_int_value();
+    let event =
+        MidiEventValue::new(unsafe { ctx.b.build_struct_gep(&entry_ptr, 2, "song.entry.event") });
+    let segment_offset = ctx
+        .b
+        .build_int_nuw_sub(event_frame, position, "song.entry.segmentoffset");
+    let offset = ctx
+        .b
+        .build_int_nuw_add(played, segment_offset, "song.entry.offset");
+
+    let mut case_builder = ctx.context.create_builder();
+    // wait entries don't match any queue, so they go straight to the next entry
+    let queue_count = get_midi_portals(root)
+        .len()
+        .min(usize::from(SONG_WAIT_QUEUE));
+    let cases: Vec<_> = (0..queue_count)
+        .map(|queue_index| {
+            let queue_block = ctx
+                .context
+                .append_basic_block(&ctx.func, &format!("song.queue.{}", queue_index));
+            case_builder.position_at_end(&queue_block);
+            push_event(&mut case_builder, queue_index, offset, &event);
+            case_builder.build_unconditional_branch(&next_block);
+
+            (
+                ctx.context.i8_type().const_int(queue_index as u64, false),
+                queue_block,
+            )
+        })
+        .collect();
+    let switch_refs: Vec<_> = cases.iter().map(|&(ref a, ref b)| (a, b)).collect();
+    ctx.b.build_switch(&queue_index, &next_block, &switch_refs);
+
+    ctx.b.position_at_end(&next_block);
+    ctx.b.build_store(&base_ptr, &event_frame);
+    let next_cursor = ctx
+        .b
+        .build_int_nuw_add(cursor, const_one, "song.nextcursor");
+    ctx.b.build_store(&cursor_ptr, &next_cursor);
+    ctx.b.build_unconditional_branch(&check_block);
+
+    ctx.b.position_at_end(&segment_end_block);
+    let segment_frames = ctx
+        .b
+        .build_int_nuw_sub(segment_end, position, "song.segmentframes");
+    let new_played = ctx
+        .b
+        .build_int_nuw_add(played, segment_frames, "song.played");
+    ctx.b.build_store(&played_ptr, &new_played);
+    ctx.b.build_store(&position_ptr, &segment_end);
+    match loop_range {
+        Some((loop_start, loop_end)) => {
+            let loop_block = ctx.context.append_basic_block(&ctx.func, "song.loop");
+            let is_loop_end =
+                ctx.b
+                    .build_int_compare(IntPredicate::EQ, segment_end, loop_end, "song.isloopend");
+            ctx.b
+                .build_conditional_branch(&is_loop_end, &loop_block, &end_block);
+
+            ctx.b.position_at_end(&loop_block);
+            ctx.b.build_store(&position_ptr, &loop_start);
+            ctx.b.build_store(
+                &cursor_ptr,
+                &i32_type.const_int(u64::from(table.loop_cursor), false),
+            );
+            ctx.b.build_store(
+                &base_ptr,
+                &i32_type.const_int(u64::from(table.loop_base), false),
+            );
+            let has_remaining =
+                ctx.b
+                    .build_int_compare(IntPredicate::ULT, new_played, frames, "song.hasremaining");
+            ctx.b
+                .build_conditional_branch(&has_remaining, &segment_block, &end_block);
+        }
+        None => {
+            ctx.b.build_unconditional_branch(&end_block);
+        }
+    }
+
+    ctx.b.position_at_end(&end_block);
+}
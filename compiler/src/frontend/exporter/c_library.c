@@ -0,0 +1,1344 @@
+/* The runtime library of an instrument exported as C source, a port of the functions, controls and
+ * converters the code generator builds. Values are kept in double precision, only portals and delay
+ * buffers use MAXIM_SAMPLE. */
+
+#if defined(__GNUC__) || defined(__clang__)
+#define MAXIM_FUNC static __attribute__((unused))
+#else
+#define MAXIM_FUNC static
+#endif
+
+#define MAXIM_FORM_NONE 0
+#define MAXIM_FORM_CONTROL 1
+#define MAXIM_FORM_OSCILLATOR 2
+#define MAXIM_FORM_NOTE 3
+#define MAXIM_FORM_FREQUENCY 4
+#define MAXIM_FORM_BEATS 5
+#define MAXIM_FORM_SECONDS 6
+#define MAXIM_FORM_SAMPLES 7
+#define MAXIM_FORM_DB 8
+#define MAXIM_FORM_AMPLITUDE 9
+#define MAXIM_FORM_Q 10
+
+#define MAXIM_PI 3.14159265358979323846
+
+typedef struct {
+    double v[2];
+    uint8_t form;
+} maxim_num;
+
+typedef struct {
+    MAXIM_SAMPLE v[2];
+    uint8_t form;
+} maxim_portal_num;
+
+typedef struct {
+    uint8_t name;
+    uint8_t channel;
+    uint8_t note;
+    uint8_t param;
+} maxim_midi_event;
+
+typedef struct {
+    uint8_t count;
+    maxim_midi_event events[MAXIM_MIDI_EVENT_COUNT];
+} maxim_midi;
+
+typedef maxim_midi maxim_portal_midi;
+
+typedef struct {
+    uint32_t offset;
+    maxim_midi_event event;
+} maxim_midi_queue_entry;
+
+/* Entries are kept sorted by offset, and delivered from the head. */
+typedef struct {
+    uint32_t count;
+    uint32_t head;
+    maxim_midi_queue_entry entries[MAXIM_MIDI_QUEUE_CAPACITY];
+} maxim_midi_queue;
+
+typedef struct {
+    uint32_t bitmap;
+    maxim_num items[MAXIM_ARRAY_CAPACITY];
+} maxim_array_num;
+
+typedef struct {
+    uint32_t bitmap;
+    maxim_midi items[MAXIM_ARRAY_CAPACITY];
+} maxim_array_midi;
+
+typedef struct {
+    maxim_num i0;
+    maxim_num i1;
+} maxim_tuple2_num_num;
+
+typedef struct {
+    maxim_num i0;
+    maxim_num i1;
+    maxim_num i2;
+    maxim_num i3;
+} maxim_tuple4_num_num_num_num;
+
+/* Oversampled nodes scale the sample rate while they run, so it's only constant between nodes. */
+static double maxim_samplerate[2] = {MAXIM_SAMPLE_RATE, MAXIM_SAMPLE_RATE};
+static const double maxim_bpm[2] = {MAXIM_BPM, MAXIM_BPM};
+static uint32_t maxim_rand_seed[2] = {1, 31337};
+
+static const maxim_num maxim_const_zero = {{0., 0.}, MAXIM_FORM_NONE};
+
+/* Delay lines claim their buffers from a statically allocated arena, so the instrument never
+ * allocates. Memory is never given back, the arena is reset as a whole by init. */
+static double maxim_arena[MAXIM_ARENA_SIZE / sizeof(double) + 1];
+static size_t maxim_arena_used = 0;
+
+MAXIM_FUNC void *maxim_arena_claim(size_t size) {
+    /* keep every claimed block 16-byte aligned relative to the start of the arena */
+    size_t aligned_size = (size + 15) & ~(size_t) 15;
+    void *claimed;
+    if (aligned_size > MAXIM_ARENA_SIZE - maxim_arena_used) {
+        return 0;
+    }
+    claimed = (unsigned char *) maxim_arena + maxim_arena_used;
+    maxim_arena_used += aligned_size;
+    return claimed;
+}
+
+/* Conversions that saturate like Rust's `as`, since out of range conversions are undefined in C. */
+MAXIM_FUNC int32_t maxim_to_i32(double x) {
+    if (x != x) return 0;
+    if (x <= -2147483648.) return INT32_MIN;
+    if (x >= 2147483647.) return INT32_MAX;
+    return (int32_t) x;
+}
+
+MAXIM_FUNC uint32_t maxim_to_u32(double x) {
+    if (!(x > 0.)) return 0;
+    if (x >= 4294967295.) return UINT32_MAX;
+    return (uint32_t) x;
+}
+
+MAXIM_FUNC uint32_t maxim_next_power_of_two(uint32_t x) {
+    uint32_t power = 1;
+    if (x == 0) return 0;
+    while (power < x && power != 0x80000000u) power <<= 1;
+    return power;
+}
+
+/* An ordered not-equal comparison, so NaN compares false. */
+MAXIM_FUNC int maxim_not_equal(double a, double b) {
+    return a < b || a > b;
+}
+
+MAXIM_FUNC maxim_num maxim_num_make(double left, double right, uint8_t form) {
+    maxim_num result;
+    result.v[0] = left;
+    result.v[1] = right;
+    result.form = form;
+    return result;
+}
+
+MAXIM_FUNC maxim_num maxim_global_num(const double *global) {
+    return maxim_num_make(global[0], global[1], MAXIM_FORM_NONE);
+}
+
+MAXIM_FUNC void maxim_midi_push(maxim_midi *midi, maxim_midi_event event) {
+    if (midi->count < MAXIM_MIDI_EVENT_COUNT) {
+        midi->events[midi->count] = event;
+        midi->count++;
+    }
+}
+
+/* Events at the same offset stay in the order they were pushed. */
+MAXIM_FUNC void maxim_midi_queue_push(maxim_midi_queue *queue, uint32_t offset, maxim_midi_event event) {
+    uint32_t index;
+    if (queue->count >= MAXIM_MIDI_QUEUE_CAPACITY) return;
+
+    index = queue->count;
+    while (index > queue->head && queue->entries[index - 1].offset > offset) {
+        queue->entries[index] = queue->entries[index - 1];
+        index--;
+    }
+    queue->entries[index].offset = offset;
+    queue->entries[index].event = event;
+    queue->count++;
+}
+
+MAXIM_FUNC void maxim_midi_queue_deliver(maxim_midi_queue *queue, uint32_t frame, maxim_midi *midi) {
+    while (queue->head < queue->count && queue->entries[queue->head].offset <= frame) {
+        maxim_midi_push(midi, queue->entries[queue->head].event);
+        queue->head++;
+    }
+}
+
+MAXIM_FUNC void maxim_midi_queue_advance(maxim_midi_queue *queue, uint32_t frames) {
+    uint32_t index;
+    for (index = queue->head; index < queue->count; index++) {
+        queue->entries[index - queue->head] = queue->entries[index];
+        queue->entries[index - queue->head].offset -= frames;
+    }
+    queue->count -= queue->head;
+    queue->head = 0;
+}
+
+/* Only the events in use are copied, so a portal without events costs a byte. */
+MAXIM_FUNC void maxim_copy_midi_events(maxim_portal_midi *dest, const maxim_portal_midi *src) {
+    dest->count = src->count;
+    memcpy(dest->events, src->events, src->count * sizeof(maxim_midi_event));
+}
+
+MAXIM_FUNC void maxim_load_num(maxim_num *dest, const maxim_portal_num *src) {
+    dest->v[0] = src->v[0];
+    dest->v[1] = src->v[1];
+    dest->form = src->form;
+}
+
+MAXIM_FUNC void maxim_store_num(maxim_portal_num *dest, const maxim_num *src) {
+    dest->v[0] = (MAXIM_SAMPLE) src->v[0];
+    dest->v[1] = (MAXIM_SAMPLE) src->v[1];
+    dest->form = src->form;
+}
+
+MAXIM_FUNC void maxim_load_midi(maxim_midi *dest, const maxim_portal_midi *src) {
+    *dest = *src;
+}
+
+MAXIM_FUNC void maxim_store_midi(maxim_portal_midi *dest, const maxim_midi *src) {
+    *dest = *src;
+}
+
+/* Math approximations
+ *
+ * These follow the interpreter's ports of the functions the code generator builds, step by step, so
+ * each tier of `MathAccuracy` rounds the same way here as in the generated code. MAXIM_MATH_ACCURACY
+ * picks the tier. */
+
+#define MAXIM_MATH_FAST 0
+#define MAXIM_MATH_BALANCED 1
+#define MAXIM_MATH_ACCURATE 2
+
+#define MAXIM_LOG2_E 1.4426950408889634
+#define MAXIM_LOG2_10 3.321928094887362
+
+/* evaluates a polynomial in x, with coefficients ordered from the highest power down */
+MAXIM_FUNC double maxim_horner(double x, const double *coefficients, int count) {
+    double result = coefficients[0];
+    int index;
+    for (index = 1; index < count; index++) {
+        result = result * x + coefficients[index];
+    }
+    return result;
+}
+
+MAXIM_FUNC double maxim_modulo(double x, double m) {
+    return x - floor(x / m) * m;
+}
+
+MAXIM_FUNC double maxim_sin(double x) {
+    /* keep in the range -pi..pi */
+    double ranged_x = maxim_modulo(x, MAXIM_PI * 2.);
+    double wrapped_x = ranged_x > MAXIM_PI ? ranged_x - MAXIM_PI * 2. : ranged_x;
+#if MAXIM_MATH_ACCURACY == MAXIM_MATH_ACCURATE
+    /* (-1)^k / (2k + 1)! for k from 10 down to 0 */
+    static const double coefficients[] = {
+        1.9572941063391263e-20, -8.22063524662433e-18, 2.8114572543455206e-15, -7.647163731819816e-13,
+        1.6059043836821613e-10, -2.505210838544172e-08, 2.7557319223985893e-06, -0.0001984126984126984,
+        0.008333333333333333,   -0.16666666666666666,  1.0};
+    double reflected_x = wrapped_x > MAXIM_PI / 2. ? MAXIM_PI - wrapped_x : wrapped_x;
+    reflected_x = reflected_x < -MAXIM_PI / 2. ? -MAXIM_PI - reflected_x : reflected_x;
+    return reflected_x * maxim_horner(reflected_x * reflected_x, coefficients, 11);
+#else
+    double y = 1.273239544723765 * wrapped_x + (-0.40528473456652137 * wrapped_x) * fabs(wrapped_x);
+#if MAXIM_MATH_ACCURACY == MAXIM_MATH_FAST
+    return y;
+#else
+    return 0.22499990463256836 * (y * fabs(y) - y) + y;
+#endif
+#endif
+}
+
+MAXIM_FUNC double maxim_cos(double x) {
+    return maxim_sin(x + MAXIM_PI / 2.);
+}
+
+MAXIM_FUNC double maxim_tan(double x) {
+    return maxim_sin(x) / maxim_cos(x);
+}
+
+MAXIM_FUNC double maxim_exp2(double x) {
+#if MAXIM_MATH_ACCURACY == MAXIM_MATH_BALANCED
+    static const double coefficients[] = {0.00015465324084118492, 0.001339529154378738, 0.009618039911742926,
+                                          0.05550340654008323,    0.24022651101404335,  0.693147200072417,
+                                          0.9999999999708962};
+#elif MAXIM_MATH_ACCURACY == MAXIM_MATH_FAST
+    /* ln(2)^k / k! for k from 4 down to 0 */
+    static const double coefficients[] = {0.009618129107628475, 0.055504108664821576, 0.2402265069591007,
+                                          0.6931471805599453, 1.0};
+#else
+    /* ln(2)^k / k! for k from 12 down to 0 */
+    static const double coefficients[] = {
+        2.567843599348819e-11,  4.445538271870809e-10, 7.05491162080112e-09, 1.0178086009239695e-07,
+        1.3215486790144303e-06, 1.5252733804059834e-05, 0.00015403530393381606, 0.0013333558146428441,
+        0.009618129107628475,   0.055504108664821576,  0.2402265069591007,   0.6931471805599453,
+        1.0};
+#endif
+    /* out of range values become the "integer indefinite" value, like cvtpd2dq gives */
+    double rounded = nearbyint(x);
+    int32_t x_int = rounded >= -2147483648. && rounded <= 2147483647. ? (int32_t) rounded : INT32_MIN;
+    double x_frac = x - (double) x_int;
+    double r = maxim_horner(x_frac, coefficients, (int) (sizeof(coefficients) / sizeof(coefficients[0])));
+
+    /* 2^x_int, built directly in the exponent bits */
+    uint64_t bits = (uint64_t) (((uint32_t) x_int + 1023u) << 20) << 32;
+    double scale;
+    memcpy(&scale, &bits, sizeof(scale));
+    return r * scale;
+}
+
+MAXIM_FUNC double maxim_exp(double x) {
+    return maxim_exp2(x * MAXIM_LOG2_E);
+}
+
+MAXIM_FUNC double maxim_exp10(double x) {
+    return maxim_exp2(x * MAXIM_LOG2_10);
+}
+
+MAXIM_FUNC double maxim_log2(double x) {
+#if MAXIM_MATH_ACCURACY == MAXIM_MATH_BALANCED
+    static const double coefficients[] = {0.41098153827988426, 0.4021554831706453, 0.5775501462703687,
+                                          0.9617878060016665, 2.8853901278343983};
+#elif MAXIM_MATH_ACCURACY == MAXIM_MATH_FAST
+    /* 2 / ((2k + 1) ln(2)) for k from 2 down to 0 */
+    static const double coefficients[] = {0.5770780163555853, 0.9617966939259757, 2.8853900817779268};
+#else
+    /* 2 / ((2k + 1) ln(2)) for k from 15 down to 0 */
+    static const double coefficients[] = {
+        0.09307709941219118, 0.09949620971648022, 0.1068662993251084,  0.11541560327111706,
+        0.12545174268599682, 0.1373995277037108,  0.15186263588304877, 0.16972882833987804,
+        0.19235933878519512, 0.2219530832136867,  0.2623081892525388,  0.3205988979753252,
+        0.41219858311113244, 0.5770780163555853,  0.9617966939259757,  2.8853900817779268};
+#endif
+    uint64_t bits;
+    int32_t ilogb;
+    double p;
+    double y;
+    memcpy(&bits, &x, sizeof(bits));
+    ilogb = (int32_t) ((int64_t) (bits >> 52) - 1023);
+    bits = (bits & (UINT64_MAX >> 12)) | ((uint64_t) 1023 << 52);
+    memcpy(&p, &bits, sizeof(p));
+    y = (p - 1.) / (p + 1.);
+    return maxim_horner(y * y, coefficients, (int) (sizeof(coefficients) / sizeof(coefficients[0]))) * y +
+           (double) ilogb;
+}
+
+MAXIM_FUNC double maxim_log(double x) {
+    return maxim_log2(x) / MAXIM_LOG2_E;
+}
+
+MAXIM_FUNC double maxim_log10(double x) {
+    return maxim_log2(x) / MAXIM_LOG2_10;
+}
+
+MAXIM_FUNC double maxim_pow(double x, double y) {
+    double sign;
+    if (x == 0.) {
+        return 0.;
+    }
+
+    /* the result is negative if x < 0 and if y is integer and odd */
+    sign = copysign(maxim_modulo(fabs(y), 2.), x) + 0.5;
+    return copysign(maxim_exp2(maxim_log2(x) * y), sign);
+}
+
+/* approximates atan(s) for s in -1..1 */
+MAXIM_FUNC double maxim_atan_kernel(double s) {
+#if MAXIM_MATH_ACCURACY == MAXIM_MATH_FAST
+    static const double coefficients[] = {0.0208351, -0.085133, 0.180141, -0.3302995, 0.999866};
+    return s * maxim_horner(s * s, coefficients, 5);
+#elif MAXIM_MATH_ACCURACY == MAXIM_MATH_BALANCED
+    static const double coefficients[] = {0.0028236389625817537, -0.015956902876496315, 0.042504988610744476,
+                                          -0.07489009201526642,  0.1063479334115982,    -0.1420273631811142,
+                                          0.19992695748806,      -0.33333101868629456};
+    double t = s * s;
+    return s + s * (t * maxim_horner(t, coefficients, 8));
+#else
+    /* (-1)^k / (2k + 1) for k from 10 down to 0 */
+    static const double coefficients[] = {
+        0.047619047619047616, -0.05263157894736842, 0.058823529411764705, -0.06666666666666667,
+        0.07692307692307693,  -0.09090909090909091, 0.1111111111111111,   -0.14285714285714285,
+        0.2,                  -0.3333333333333333,  1.0};
+    /* halve the angle twice, so the series converges quickly */
+    double reduced_s = s / (1. + sqrt(1. + s * s));
+    reduced_s = reduced_s / (1. + sqrt(1. + reduced_s * reduced_s));
+    return reduced_s * maxim_horner(reduced_s * reduced_s, coefficients, 11) * 4.;
+#endif
+}
+
+MAXIM_FUNC double maxim_atan2k(double y, double x) {
+    double q = x < 0. ? -2. : 0.;
+    double abs_x = fabs(x);
+    int cond = y > abs_x;
+    double rx = cond ? y : abs_x;
+    double ry = cond ? -abs_x : y;
+    q += cond ? 1. : 0.;
+    return q * (MAXIM_PI / 2.) + maxim_atan_kernel(ry / rx);
+}
+
+MAXIM_FUNC double maxim_asin(double x) {
+    double atan_val = maxim_atan2k(fabs(x), sqrt((1. + x) * (1. - x)));
+    return atan_val * (x < 0. ? -1. : 1.);
+}
+
+MAXIM_FUNC double maxim_acos(double x) {
+    double atan_val = maxim_atan2k(sqrt((1. + x) * (1. - x)), fabs(x));
+    return x < 0. ? -atan_val + MAXIM_PI : atan_val;
+}
+
+MAXIM_FUNC double maxim_atan(double x) {
+    double s = fabs(x);
+    int is_inverted = s > 1.;
+    double t = maxim_atan_kernel(is_inverted ? 1. / s : s);
+    return copysign(is_inverted ? MAXIM_PI / 2. - t : t, x);
+}
+
+MAXIM_FUNC double maxim_atan2(double y, double x) {
+    double r = maxim_atan2k(fabs(y), x) * (x < 0. ? -1. : 1.);
+    if (x == 0.) {
+        r = MAXIM_PI / 2.;
+    }
+    if (y == 0.) {
+        r = x < 0. ? MAXIM_PI : 0.;
+    }
+    return r * (y < 0. ? -1. : 1.);
+}
+
+MAXIM_FUNC double maxim_sinh(double x) {
+    return (maxim_exp(x) - maxim_exp(-x)) * 0.5;
+}
+
+MAXIM_FUNC double maxim_cosh(double x) {
+    return (maxim_exp(x) + maxim_exp(-x)) * 0.5;
+}
+
+MAXIM_FUNC double maxim_tanh(double x) {
+    return maxim_sinh(x) / maxim_cosh(x);
+}
+
+/* Converts a number to another form. Pairs without a conversion keep their value. */
+MAXIM_FUNC maxim_num maxim_convert(const maxim_num *num, uint8_t target) {
+    maxim_num result;
+    int channel;
+    for (channel = 0; channel < 2; channel++) {
+        double val = num->v[channel];
+        double sr = maxim_samplerate[channel];
+        double bpm = maxim_bpm[channel];
+        double out = val;
+
+#define MAXIM_CONVERT(to, from, expr) \
+    if (target == MAXIM_FORM_##to && num->form == MAXIM_FORM_##from) out = (expr); else
+        MAXIM_CONVERT(AMPLITUDE, DB, maxim_exp10(val / 20.))
+
+        MAXIM_CONVERT(BEATS, CONTROL, val * 8.)
+        MAXIM_CONVERT(BEATS, FREQUENCY, bpm / (60. * val))
+        MAXIM_CONVERT(BEATS, SAMPLES, val * bpm / (sr * 60.))
+        MAXIM_CONVERT(BEATS, SECONDS, val * (bpm * 60.))
+
+        MAXIM_CONVERT(CONTROL, BEATS, val / 8.)
+        MAXIM_CONVERT(CONTROL, DB, maxim_exp10(val / 20.) / 2.)
+        MAXIM_CONVERT(CONTROL, FREQUENCY, maxim_log(val + 1.) / log(20000.))
+        MAXIM_CONVERT(CONTROL, NOTE, val / 127.)
+        MAXIM_CONVERT(CONTROL, OSCILLATOR, val * 0.5 + 0.5)
+        MAXIM_CONVERT(CONTROL, Q, maxim_pow((val - 1. / 3.) / (12. - 1. / 3.), 1. / 3.))
+        MAXIM_CONVERT(CONTROL, SAMPLES, val * 1.1 / (val + 0.1 * sr))
+        MAXIM_CONVERT(CONTROL, SECONDS, val * 1.1 / (val + 0.5))
+
+        MAXIM_CONVERT(DB, AMPLITUDE, maxim_log10(val) * 20.)
+        MAXIM_CONVERT(DB, CONTROL, maxim_log10(val * 2.) * 20.)
+
+        MAXIM_CONVERT(FREQUENCY, BEATS, bpm / (val * 60.))
+        MAXIM_CONVERT(FREQUENCY, CONTROL, maxim_pow(20000., fmin(val, 8.)) - 1.)
+        MAXIM_CONVERT(FREQUENCY, NOTE, 440. * maxim_exp2((val - 69.) / 12.))
+        MAXIM_CONVERT(FREQUENCY, SAMPLES, sr / val)
+        MAXIM_CONVERT(FREQUENCY, SECONDS, 1. / val)
+
+        MAXIM_CONVERT(NOTE, CONTROL, val * 127.)
+        MAXIM_CONVERT(NOTE, FREQUENCY, 69. + 12. * maxim_log2(val / 440.))
+
+        MAXIM_CONVERT(OSCILLATOR, CONTROL, val * 2. - 1.)
+
+        MAXIM_CONVERT(Q, CONTROL, (12. - 1. / 3.) * maxim_pow(val, 3.) + 1. / 3.)
+
+        MAXIM_CONVERT(SAMPLES, BEATS, val * (sr * 60.) / bpm)
+        MAXIM_CONVERT(SAMPLES, CONTROL, val * sr / (11. - val * 10.))
+        MAXIM_CONVERT(SAMPLES, FREQUENCY, sr / val)
+        MAXIM_CONVERT(SAMPLES, SECONDS, val * sr)
+
+        MAXIM_CONVERT(SECONDS, BEATS, val / (bpm / 60.))
+        MAXIM_CONVERT(SECONDS, CONTROL, val / (2.2 - val * 2.))
+        MAXIM_CONVERT(SECONDS, FREQUENCY, 1. / val)
+        MAXIM_CONVERT(SECONDS, SAMPLES, val / sr)
+        {}
+#undef MAXIM_CONVERT
+
+        result.v[channel] = out;
+    }
+    result.form = target;
+    return result;
+}
+
+MAXIM_FUNC maxim_num maxim_cast(const maxim_num *num, uint8_t target) {
+    return maxim_num_make(num->v[0], num->v[1], target);
+}
+
+/* Ramps a value in a control-rate node's group towards the target it wrote. */
+MAXIM_FUNC maxim_num maxim_interpolate(const maxim_num *start, const maxim_num *target, double progress) {
+    return maxim_num_make(start->v[0] + (target->v[0] - start->v[0]) * progress,
+                          start->v[1] + (target->v[1] - start->v[1]) * progress, target->form);
+}
+
+/* Oversampling */
+
+static const double maxim_half_band_coefficients[MAXIM_HALF_BAND_SIDE_TAPS] = MAXIM_HALF_BAND_COEFFICIENTS;
+
+/* One 2:1 stage of the half-band decimation filter, split into its two polyphase branches. */
+typedef struct {
+    double odd_history[MAXIM_HALF_BAND_SIDE_TAPS * 2][2];
+    double even_history[MAXIM_HALF_BAND_SIDE_TAPS][2];
+} maxim_decimation_stage;
+
+MAXIM_FUNC void maxim_scale_samplerate(double scale) {
+    maxim_samplerate[0] *= scale;
+    maxim_samplerate[1] *= scale;
+}
+
+/* Takes a pair of samples and writes one at half the rate. The result can alias the inputs. */
+MAXIM_FUNC void maxim_decimate(maxim_decimation_stage *stage, const double *even, const double *odd,
+                               double *result) {
+    int channel;
+    memmove(stage->odd_history[1], stage->odd_history[0],
+            sizeof(stage->odd_history) - sizeof(stage->odd_history[0]));
+    memmove(stage->even_history[1], stage->even_history[0],
+            sizeof(stage->even_history) - sizeof(stage->even_history[0]));
+    for (channel = 0; channel < 2; channel++) {
+        stage->odd_history[0][channel] = odd[channel];
+        stage->even_history[0][channel] = even[channel];
+    }
+
+    for (channel = 0; channel < 2; channel++) {
+        double sum = stage->even_history[MAXIM_HALF_BAND_SIDE_TAPS - 1][channel] * 0.5;
+        int tap;
+
+        /* the side taps are symmetric around the center, so pairs of samples share a multiply */
+        for (tap = 0; tap < MAXIM_HALF_BAND_SIDE_TAPS; tap++) {
+            double tap_sum = stage->odd_history[MAXIM_HALF_BAND_SIDE_TAPS - 1 - tap][channel] +
+                             stage->odd_history[MAXIM_HALF_BAND_SIDE_TAPS + tap][channel];
+            sum += tap_sum * maxim_half_band_coefficients[tap];
+        }
+        result[channel] = sum;
+    }
+}
+
+/* Decimates the samples an output had over the steps of one host sample, through one stage per
+ * halving. `samples` is overwritten. */
+MAXIM_FUNC maxim_num maxim_oversample_output(maxim_decimation_stage *stages, uint32_t stage_count,
+                                             double (*samples)[2], const maxim_num *last_output) {
+    uint32_t stage;
+    for (stage = 0; stage < stage_count; stage++) {
+        uint32_t sample_count = 1u << (stage_count - stage - 1);
+        uint32_t sample;
+        for (sample = 0; sample < sample_count; sample++) {
+            maxim_decimate(&stages[stage], samples[sample * 2], samples[sample * 2 + 1], samples[sample]);
+        }
+    }
+    return maxim_num_make(samples[0][0], samples[0][1], last_output->form);
+}
+
+/* Operators */
+
+MAXIM_FUNC int maxim_as_bool(double x) {
+    return (maxim_to_i32(x) & 1) != 0;
+}
+
+MAXIM_FUNC maxim_num maxim_positive(const maxim_num *x) {
+    return *x;
+}
+
+MAXIM_FUNC maxim_num maxim_negative(const maxim_num *x) {
+    return maxim_num_make(-x->v[0], -x->v[1], x->form);
+}
+
+MAXIM_FUNC maxim_num maxim_not(const maxim_num *x) {
+    return maxim_num_make(x->v[0] == 0. ? 1. : 0., x->v[1] == 0. ? 1. : 0., x->form);
+}
+
+#define MAXIM_OPERATOR(name, expr)                                          \
+    MAXIM_FUNC maxim_num maxim_op_##name(const maxim_num *x, const maxim_num *y) { \
+        maxim_num result;                                                   \
+        int channel;                                                        \
+        for (channel = 0; channel < 2; channel++) {                         \
+            double a = x->v[channel];                                       \
+            double b = y->v[channel];                                       \
+            result.v[channel] = (expr);                                     \
+        }                                                                   \
+        result.form = x->form;                                              \
+        return result;                                                      \
+    }
+
+MAXIM_OPERATOR(identity, ((void) b, a))
+MAXIM_OPERATOR(add, a + b)
+MAXIM_OPERATOR(subtract, a - b)
+MAXIM_OPERATOR(multiply, a * b)
+MAXIM_OPERATOR(divide, a / b)
+MAXIM_OPERATOR(modulo, a - floor(a / b) * b)
+MAXIM_OPERATOR(power, maxim_pow(a, b))
+MAXIM_OPERATOR(bitwise_and, (double) (maxim_to_i32(a) & maxim_to_i32(b)))
+MAXIM_OPERATOR(bitwise_or, (double) (maxim_to_i32(a) | maxim_to_i32(b)))
+MAXIM_OPERATOR(bitwise_xor, (double) (maxim_to_i32(a) ^ maxim_to_i32(b)))
+MAXIM_OPERATOR(logical_and, maxim_as_bool(a) && maxim_as_bool(b) ? 1. : 0.)
+MAXIM_OPERATOR(logical_or, maxim_as_bool(a) || maxim_as_bool(b) ? 1. : 0.)
+MAXIM_OPERATOR(logical_equal, a == b ? 1. : 0.)
+MAXIM_OPERATOR(logical_not_equal, maxim_not_equal(a, b) ? 1. : 0.)
+MAXIM_OPERATOR(logical_gt, a > b ? 1. : 0.)
+MAXIM_OPERATOR(logical_lt, a < b ? 1. : 0.)
+MAXIM_OPERATOR(logical_gte, a >= b ? 1. : 0.)
+MAXIM_OPERATOR(logical_lte, a <= b ? 1. : 0.)
+
+#undef MAXIM_OPERATOR
+
+/* Math functions */
+
+MAXIM_FUNC double maxim_min(double a, double b) { return a < b ? a : b; }
+MAXIM_FUNC double maxim_max(double a, double b) { return a > b ? a : b; }
+MAXIM_FUNC double maxim_fract(double x) { return x - floor(x); }
+MAXIM_FUNC double maxim_to_rad(double x) { return x * (MAXIM_PI / 180.); }
+MAXIM_FUNC double maxim_to_deg(double x) { return x * (180. / MAXIM_PI); }
+
+#define MAXIM_UNARY(name, func)                                                   \
+    MAXIM_FUNC maxim_num maxim_fn_##name(const maxim_num *x) {                    \
+        return maxim_num_make(func(x->v[0]), func(x->v[1]), x->form);             \
+    }
+
+#define MAXIM_BINARY(name, func)                                                  \
+    MAXIM_FUNC maxim_num maxim_fn_##name(const maxim_num *x, const maxim_num *y) { \
+        return maxim_num_make(func(x->v[0], y->v[0]), func(x->v[1], y->v[1]), x->form); \
+    }
+
+MAXIM_UNARY(sin, maxim_sin)
+MAXIM_UNARY(cos, maxim_cos)
+MAXIM_UNARY(tan, maxim_tan)
+MAXIM_BINARY(min, maxim_min)
+MAXIM_BINARY(max, maxim_max)
+MAXIM_UNARY(sqrt, sqrt)
+MAXIM_UNARY(floor, floor)
+MAXIM_UNARY(ceil, ceil)
+/* nearbyint rounds half-way cases to even in the default rounding mode */
+MAXIM_UNARY(round, nearbyint)
+MAXIM_UNARY(abs, fabs)
+MAXIM_BINARY(copysign, copysign)
+MAXIM_UNARY(fract, maxim_fract)
+MAXIM_UNARY(exp, maxim_exp)
+MAXIM_UNARY(exp2, maxim_exp2)
+MAXIM_UNARY(exp10, maxim_exp10)
+MAXIM_UNARY(log, maxim_log)
+MAXIM_UNARY(log2, maxim_log2)
+MAXIM_UNARY(log10, maxim_log10)
+MAXIM_UNARY(asin, maxim_asin)
+MAXIM_UNARY(acos, maxim_acos)
+MAXIM_UNARY(atan, maxim_atan)
+MAXIM_BINARY(atan2, maxim_atan2)
+MAXIM_UNARY(sinh, maxim_sinh)
+MAXIM_UNARY(cosh, maxim_cosh)
+MAXIM_UNARY(tanh, maxim_tanh)
+MAXIM_BINARY(hypot, hypot)
+MAXIM_UNARY(toRad, maxim_to_rad)
+MAXIM_UNARY(toDeg, maxim_to_deg)
+
+#undef MAXIM_UNARY
+#undef MAXIM_BINARY
+
+MAXIM_FUNC maxim_num maxim_fn_clamp(const maxim_num *x, const maxim_num *min, const maxim_num *max) {
+    return maxim_num_make(maxim_max(maxim_min(x->v[0], max->v[0]), min->v[0]),
+                          maxim_max(maxim_min(x->v[1], max->v[1]), min->v[1]), x->form);
+}
+
+MAXIM_FUNC maxim_num maxim_fn_pan(const maxim_num *x, const maxim_num *pan) {
+    double pan_left = fmax(fmin(pan->v[0], 1.), -1.);
+    double pan_right = fmax(fmin(pan->v[1], 1.), -1.);
+    double base_left = (1. - pan_left) * maxim_sin(MAXIM_PI / 4. * (pan_left + 1.) + MAXIM_PI / 2.);
+    double base_right = (1. + pan_right) * maxim_sin(MAXIM_PI / 4. * (pan_right + 1.));
+    return maxim_num_make(x->v[0] * sqrt(base_left / 2.), x->v[1] * sqrt(base_right / 2.), x->form);
+}
+
+MAXIM_FUNC maxim_num maxim_fn_left(const maxim_num *x) {
+    return maxim_num_make(x->v[0], x->v[0], x->form);
+}
+
+MAXIM_FUNC maxim_num maxim_fn_right(const maxim_num *x) {
+    return maxim_num_make(x->v[1], x->v[1], x->form);
+}
+
+MAXIM_FUNC maxim_num maxim_fn_swap(const maxim_num *x) {
+    return maxim_num_make(x->v[1], x->v[0], x->form);
+}
+
+MAXIM_FUNC maxim_num maxim_fn_combine(const maxim_num *left, const maxim_num *right) {
+    return maxim_num_make(left->v[0], right->v[1], left->form);
+}
+
+MAXIM_FUNC maxim_num maxim_fn_mix(const maxim_num *a, const maxim_num *b, const maxim_num *mix) {
+    return maxim_num_make((b->v[0] - a->v[0]) * mix->v[0] + a->v[0],
+                          (b->v[1] - a->v[1]) * mix->v[1] + a->v[1], a->form);
+}
+
+MAXIM_FUNC maxim_num maxim_fn_sequence(const maxim_num *index, int32_t count, const maxim_num *const *values) {
+    int32_t left = (maxim_to_i32(index->v[0]) % count + count) % count;
+    int32_t right = (maxim_to_i32(index->v[1]) % count + count) % count;
+    return maxim_num_make(values[left]->v[0], values[right]->v[1], values[0]->form);
+}
+
+/* Stateful functions */
+
+typedef struct {
+    maxim_num last;
+} maxim_last_state;
+
+MAXIM_FUNC maxim_num maxim_fn_last(maxim_last_state *state, const maxim_num *x) {
+    maxim_num result = state->last;
+    state->last = *x;
+    return result;
+}
+
+typedef struct {
+    double estimate[2];
+} maxim_amplitude_state;
+
+MAXIM_FUNC maxim_num maxim_fn_amplitude(maxim_amplitude_state *state, const maxim_num *x) {
+    int channel;
+    for (channel = 0; channel < 2; channel++) {
+        double b0 = 1. - maxim_exp(-1. / (0.05 * maxim_samplerate[channel]));
+        state->estimate[channel] += b0 * (fabs(x->v[channel]) - state->estimate[channel]);
+    }
+    return maxim_num_make(state->estimate[0], state->estimate[1], MAXIM_FORM_AMPLITUDE);
+}
+
+typedef struct {
+    double val[2];
+    uint8_t gate[2];
+} maxim_hold_state;
+
+MAXIM_FUNC maxim_num maxim_fn_hold(maxim_hold_state *state, const maxim_num *x, const maxim_num *gate,
+                                   const maxim_num *else_val) {
+    maxim_num result;
+    int channel;
+    for (channel = 0; channel < 2; channel++) {
+        int is_gate = maxim_not_equal(gate->v[channel], 0.);
+        if (is_gate && !state->gate[channel]) {
+            state->val[channel] = x->v[channel];
+        }
+        state->gate[channel] = (uint8_t) is_gate;
+        result.v[channel] = is_gate ? state->val[channel] : else_val->v[channel];
+    }
+    result.form = x->form;
+    return result;
+}
+
+typedef struct {
+    double accum[2];
+} maxim_accum_state;
+
+MAXIM_FUNC maxim_num maxim_fn_accum(maxim_accum_state *state, const maxim_num *x, const maxim_num *gate,
+                                    const maxim_num *base) {
+    int channel;
+    for (channel = 0; channel < 2; channel++) {
+        state->accum[channel] = maxim_not_equal(gate->v[channel], 0.) ? state->accum[channel] + x->v[channel]
+                                                                      : base->v[channel];
+    }
+    return maxim_num_make(state->accum[0], state->accum[1], x->form);
+}
+
+typedef struct {
+    MAXIM_SAMPLE *buffer;
+    uint32_t pos;
+    uint32_t size;
+    uint32_t capacity;
+} maxim_delay_channel;
+
+typedef struct {
+    maxim_delay_channel channels[2];
+} maxim_delay_state;
+
+/* Buffers are claimed once, big enough for the longest delay the call can reach, so changing the
+ * reserve never allocates. If the arena can't fit a buffer the channel passes its input through. */
+MAXIM_FUNC void maxim_delay_construct(maxim_delay_state *state, double max_seconds) {
+    int channel;
+    for (channel = 0; channel < 2; channel++) {
+        maxim_delay_channel *delay_channel = &state->channels[channel];
+        uint32_t capacity = maxim_next_power_of_two(maxim_to_u32(max_seconds * maxim_samplerate[channel]));
+        delay_channel->buffer = (MAXIM_SAMPLE *) maxim_arena_claim(capacity * sizeof(MAXIM_SAMPLE));
+        delay_channel->capacity = delay_channel->buffer ? capacity : 0;
+        delay_channel->pos = 0;
+        delay_channel->size = 0;
+    }
+}
+
+/* The buffer is never resized here, reserves longer than it can hold are clamped to its capacity. */
+MAXIM_FUNC double maxim_delay_channel_update(maxim_delay_channel *channel, double input, uint32_t delay,
+                                             uint32_t reserve) {
+    double result = input;
+    uint32_t new_size;
+
+    if (channel->size) {
+        uint32_t write_pos = channel->pos;
+        channel->pos = (write_pos + 1) % channel->size;
+        if (delay != 0) {
+            result = channel->buffer[(write_pos + channel->size - delay) % channel->size];
+        }
+        channel->buffer[write_pos] = (MAXIM_SAMPLE) input;
+    }
+
+    new_size = maxim_next_power_of_two(reserve);
+    if (new_size > channel->capacity) {
+        new_size = channel->capacity;
+    }
+    if (new_size != channel->size) {
+        if (new_size > channel->size) {
+            memset(channel->buffer + channel->size, 0, (new_size - channel->size) * sizeof(MAXIM_SAMPLE));
+        } else {
+            channel->pos = channel->pos % (new_size > 1 ? new_size : 1);
+        }
+        channel->size = new_size;
+    }
+
+    return result;
+}
+
+MAXIM_FUNC maxim_num maxim_fn_delay(maxim_delay_state *state, const maxim_num *x, const maxim_num *delay,
+                                    const maxim_num *reserve) {
+    maxim_num result;
+    int channel;
+    for (channel = 0; channel < 2; channel++) {
+        double reserve_samples =
+            fmax(fmin(reserve->v[channel], MAXIM_MAX_DELAY_SECONDS) * maxim_samplerate[channel], 0.);
+        uint32_t delay_samples = maxim_to_u32(fmax(fmin(delay->v[channel], 1.), 0.) * reserve_samples);
+        result.v[channel] = maxim_delay_channel_update(&state->channels[channel], x->v[channel], delay_samples,
+                                                       maxim_to_u32(reserve_samples));
+    }
+    result.form = x->form;
+    return result;
+}
+
+#define MAXIM_BIQUAD_LOW 0
+#define MAXIM_BIQUAD_HIGH 1
+#define MAXIM_BIQUAD_BAND 2
+#define MAXIM_BIQUAD_NOTCH 3
+#define MAXIM_BIQUAD_ALL 4
+#define MAXIM_BIQUAD_PEAK 5
+
+typedef struct {
+    double a1[2], a2[2], b0[2], b1[2], b2[2];
+    double y1[2], y2[2], z1[2], z2[2];
+    double cached_freq[2], cached_q[2], cached_gain[2];
+} maxim_biquad_state;
+
+MAXIM_FUNC maxim_num maxim_biquad(maxim_biquad_state *state, int type, const maxim_num *input,
+                                  const maxim_num *freq, const maxim_num *q, const maxim_num *gain) {
+    maxim_num result;
+    int channel;
+    int changed = 0;
+
+    for (channel = 0; channel < 2; channel++) {
+        changed = changed || maxim_not_equal(freq->v[channel], state->cached_freq[channel]) ||
+                  maxim_not_equal(q->v[channel], state->cached_q[channel]) ||
+                  maxim_not_equal(gain->v[channel], state->cached_gain[channel]);
+    }
+    if (changed) {
+        for (channel = 0; channel < 2; channel++) {
+            double channel_q = fmax(q->v[channel], 0.5);
+            double f0 = fmax(freq->v[channel], 0.01);
+            double w0 = 2. * MAXIM_PI * f0 / maxim_samplerate[channel];
+            double alpha = maxim_sin(w0) / (2. * channel_q);
+            double cos_w0 = maxim_cos(w0);
+            double b0, b1, b2, a0, a1 = -2. * cos_w0, a2;
+
+            state->cached_freq[channel] = freq->v[channel];
+            state->cached_q[channel] = q->v[channel];
+            state->cached_gain[channel] = gain->v[channel];
+
+            switch (type) {
+            case MAXIM_BIQUAD_LOW:
+                b0 = (1. - cos_w0) / 2.;
+                b1 = 1. - cos_w0;
+                b2 = (1. - cos_w0) / 2.;
+                a0 = 1. + alpha;
+                a2 = 1. - alpha;
+                break;
+            case MAXIM_BIQUAD_HIGH:
+                b0 = (1. + cos_w0) / 2.;
+                b1 = -(1. + cos_w0);
+                b2 = (1. + cos_w0) / 2.;
+                a0 = 1. + alpha;
+                a2 = 1. - alpha;
+                break;
+            case MAXIM_BIQUAD_BAND:
+                b0 = alpha;
+                b1 = 0.;
+                b2 = -alpha;
+                a0 = 1. + alpha;
+                a2 = 1. - alpha;
+                break;
+            case MAXIM_BIQUAD_NOTCH:
+                b0 = 1.;
+                b1 = -2. * cos_w0;
+                b2 = 1.;
+                a0 = 1. + alpha;
+                a2 = 1. - alpha;
+                break;
+            case MAXIM_BIQUAD_ALL:
+                b0 = 1. - alpha;
+                b1 = -2. * cos_w0;
+                b2 = 1. + alpha;
+                a0 = 1. + alpha;
+                a2 = 1. - alpha;
+                break;
+            default: {
+                double g = fmax(gain->v[channel], 0.001);
+                b0 = 1. + alpha * g;
+                b1 = -2. * cos_w0;
+                b2 = 1. - alpha * g;
+                a0 = 1. + alpha / g;
+                a2 = 1. - alpha / g;
+                break;
+            }
+            }
+
+            state->b0[channel] = b0 / a0;
+            state->b1[channel] = b1 / a0;
+            state->b2[channel] = b2 / a0;
+            state->a1[channel] = a1 / a0;
+            state->a2[channel] = a2 / a0;
+        }
+    }
+
+    for (channel = 0; channel < 2; channel++) {
+        double x = input->v[channel];
+        double out = state->b0[channel] * x + state->b1[channel] * state->y1[channel] +
+                     state->b2[channel] * state->y2[channel] - state->a1[channel] * state->z1[channel] -
+                     state->a2[channel] * state->z2[channel];
+        state->y2[channel] = state->y1[channel];
+        state->y1[channel] = x;
+        state->z2[channel] = state->z1[channel];
+        state->z1[channel] = out;
+        result.v[channel] = out;
+    }
+    result.form = input->form;
+    return result;
+}
+
+MAXIM_FUNC maxim_num maxim_fn_lowBqFilter(maxim_biquad_state *state, const maxim_num *x, const maxim_num *freq,
+                                          const maxim_num *q) {
+    return maxim_biquad(state, MAXIM_BIQUAD_LOW, x, freq, q, &maxim_const_zero);
+}
+
+MAXIM_FUNC maxim_num maxim_fn_highBqFilter(maxim_biquad_state *state, const maxim_num *x, const maxim_num *freq,
+                                           const maxim_num *q) {
+    return maxim_biquad(state, MAXIM_BIQUAD_HIGH, x, freq, q, &maxim_const_zero);
+}
+
+MAXIM_FUNC maxim_num maxim_fn_bandBqFilter(maxim_biquad_state *state, const maxim_num *x, const maxim_num *freq,
+                                           const maxim_num *q) {
+    return maxim_biquad(state, MAXIM_BIQUAD_BAND, x, freq, q, &maxim_const_zero);
+}
+
+MAXIM_FUNC maxim_num maxim_fn_notchBqFilter(maxim_biquad_state *state, const maxim_num *x, const maxim_num *freq,
+                                            const maxim_num *q) {
+    return maxim_biquad(state, MAXIM_BIQUAD_NOTCH, x, freq, q, &maxim_const_zero);
+}
+
+MAXIM_FUNC maxim_num maxim_fn_allBqFilter(maxim_biquad_state *state, const maxim_num *x, const maxim_num *freq,
+                                          const maxim_num *q) {
+    return maxim_biquad(state, MAXIM_BIQUAD_ALL, x, freq, q, &maxim_const_zero);
+}
+
+MAXIM_FUNC maxim_num maxim_fn_peakBqFilter(maxim_biquad_state *state, const maxim_num *x, const maxim_num *freq,
+                                           const maxim_num *q, const maxim_num *gain) {
+    return maxim_biquad(state, MAXIM_BIQUAD_PEAK, x, freq, q, gain);
+}
+
+typedef struct {
+    double low[2];
+    double band[2];
+} maxim_sv_filter_state;
+
+MAXIM_FUNC maxim_tuple4_num_num_num_num maxim_fn_svFilter(maxim_sv_filter_state *state, const maxim_num *input,
+                                                          const maxim_num *freq, const maxim_num *q) {
+    maxim_tuple4_num_num_num_num result;
+    int channel, pass;
+    for (channel = 0; channel < 2; channel++) {
+        double f = maxim_sin(MAXIM_PI * freq->v[channel] / 2. / maxim_samplerate[channel]);
+        double high = 0.;
+        for (pass = 0; pass < 2; pass++) {
+            state->low[channel] += f * state->band[channel];
+            high = q->v[channel] * (input->v[channel] - state->band[channel]) - state->low[channel];
+            state->band[channel] += f * high;
+        }
+        result.i0.v[channel] = high;
+        result.i1.v[channel] = state->low[channel];
+        result.i2.v[channel] = state->band[channel];
+        result.i3.v[channel] = state->low[channel] + high;
+    }
+    result.i0.form = input->form;
+    result.i1.form = input->form;
+    result.i2.form = input->form;
+    result.i3.form = input->form;
+    return result;
+}
+
+MAXIM_FUNC double maxim_rand_value(uint32_t seed) {
+    double signed_seed = seed < 0x80000000u ? (double) seed : (double) seed - 4294967296.;
+    return signed_seed / 2147483648.;
+}
+
+MAXIM_FUNC maxim_num maxim_fn_noise(void) {
+    maxim_rand_seed[0] *= 16007u;
+    maxim_rand_seed[1] *= 16007u;
+    return maxim_num_make(maxim_rand_value(maxim_rand_seed[1]), maxim_rand_value(maxim_rand_seed[0]),
+                          MAXIM_FORM_OSCILLATOR);
+}
+
+typedef struct {
+    double phase[2];
+} maxim_periodic_state;
+
+#define MAXIM_OSC_SIN 0
+#define MAXIM_OSC_SQR 1
+#define MAXIM_OSC_SAW 2
+#define MAXIM_OSC_TRI 3
+#define MAXIM_OSC_RMP 4
+
+MAXIM_FUNC maxim_num maxim_periodic(maxim_periodic_state *state, int type, const maxim_num *freq,
+                                    const maxim_num *offset, const maxim_num *pulse_width) {
+    maxim_num result;
+    int channel;
+    for (channel = 0; channel < 2; channel++) {
+        double p = maxim_fract(offset->v[channel] + state->phase[channel]);
+        state->phase[channel] = maxim_fract(state->phase[channel] + freq->v[channel] / maxim_samplerate[channel]);
+        switch (type) {
+        case MAXIM_OSC_SIN: result.v[channel] = maxim_sin(p * MAXIM_PI * 2.); break;
+        case MAXIM_OSC_SQR: result.v[channel] = p < pulse_width->v[channel] ? 1. : -1.; break;
+        case MAXIM_OSC_SAW: result.v[channel] = 2. * p - 1.; break;
+        case MAXIM_OSC_TRI: result.v[channel] = 1. - fabs(4. * p - 2.); break;
+        default: result.v[channel] = 1. - 2. * p; break;
+        }
+    }
+    result.form = MAXIM_FORM_OSCILLATOR;
+    return result;
+}
+
+MAXIM_FUNC maxim_num maxim_fn_sinOsc(maxim_periodic_state *state, const maxim_num *freq, const maxim_num *offset) {
+    return maxim_periodic(state, MAXIM_OSC_SIN, freq, offset, &maxim_const_zero);
+}
+
+MAXIM_FUNC maxim_num maxim_fn_sqrOsc(maxim_periodic_state *state, const maxim_num *freq, const maxim_num *offset,
+                                     const maxim_num *pulse_width) {
+    return maxim_periodic(state, MAXIM_OSC_SQR, freq, offset, pulse_width);
+}
+
+MAXIM_FUNC maxim_num maxim_fn_sawOsc(maxim_periodic_state *state, const maxim_num *freq, const maxim_num *offset) {
+    return maxim_periodic(state, MAXIM_OSC_SAW, freq, offset, &maxim_const_zero);
+}
+
+MAXIM_FUNC maxim_num maxim_fn_triOsc(maxim_periodic_state *state, const maxim_num *freq, const maxim_num *offset) {
+    return maxim_periodic(state, MAXIM_OSC_TRI, freq, offset, &maxim_const_zero);
+}
+
+MAXIM_FUNC maxim_num maxim_fn_rmpOsc(maxim_periodic_state *state, const maxim_num *freq, const maxim_num *offset) {
+    return maxim_periodic(state, MAXIM_OSC_RMP, freq, offset, &maxim_const_zero);
+}
+
+MAXIM_FUNC maxim_num maxim_fn_mixdown(const maxim_array_num *array) {
+    double left = 0., right = 0.;
+    int index;
+    for (index = 0; index < MAXIM_ARRAY_CAPACITY; index++) {
+        if ((array->bitmap >> index) & 1) {
+            left += array->items[index].v[0];
+            right += array->items[index].v[1];
+        }
+    }
+    return maxim_num_make(left, right, array->items[0].form);
+}
+
+typedef struct {
+    double note;
+    double pitch;
+    double velocity;
+    double aftertouch;
+    uint8_t active_count;
+} maxim_note_state;
+
+MAXIM_FUNC maxim_tuple4_num_num_num_num maxim_fn_note(maxim_note_state *state, const maxim_midi *midi) {
+    maxim_tuple4_num_num_num_num result;
+    int index;
+    for (index = 0; index < midi->count; index++) {
+        const maxim_midi_event *event = &midi->events[index];
+        double param = (double) event->param;
+        switch (event->name) {
+        case 0:
+            state->note = (double) event->note;
+            state->velocity = param / 255.;
+            state->active_count++;
+            break;
+        case 1:
+            if (state->active_count > 0) state->active_count--;
+            break;
+        case 2:
+        case 3: state->aftertouch = param / 255.; break;
+        case 4: state->pitch = (param / 127.5 - 1.) * 6.; break;
+        default: break;
+        }
+    }
+
+    result.i0 = maxim_num_make(state->active_count > 0 ? 1. : 0., state->active_count > 0 ? 1. : 0.,
+                               MAXIM_FORM_NONE);
+    result.i1 = maxim_num_make(state->note + state->pitch, state->note + state->pitch, MAXIM_FORM_NOTE);
+    result.i2 = maxim_num_make(state->velocity, state->velocity, MAXIM_FORM_AMPLITUDE);
+    result.i3 = maxim_num_make(state->aftertouch, state->aftertouch, MAXIM_FORM_NONE);
+    return result;
+}
+
+typedef struct {
+    uint8_t notes[MAXIM_ARRAY_CAPACITY];
+} maxim_voices_state;
+
+MAXIM_FUNC maxim_array_midi maxim_fn_voices(maxim_voices_state *state, const maxim_midi *midi,
+                                            const maxim_array_num *last_active) {
+    maxim_array_midi result;
+    uint32_t active_bitmap = 0;
+    int index, event_index;
+
+    memset(&result, 0, sizeof(result));
+    for (index = 0; index < MAXIM_ARRAY_CAPACITY; index++) {
+        if (maxim_not_equal(last_active->items[index].v[0], 0.)) {
+            active_bitmap |= (uint32_t) 1 << index;
+        }
+    }
+
+    for (event_index = 0; event_index < midi->count; event_index++) {
+        maxim_midi_event event = midi->events[event_index];
+        if (event.name == 0) {
+            for (index = 0; index < MAXIM_ARRAY_CAPACITY; index++) {
+                if (!((active_bitmap >> index) & 1)) {
+                    state->notes[index] = event.note;
+                    active_bitmap |= (uint32_t) 1 << index;
+                    maxim_midi_push(&result.items[index], event);
+                    break;
+                }
+            }
+        } else {
+            for (index = 0; index < MAXIM_ARRAY_CAPACITY; index++) {
+                int matches = state->notes[index] == event.note || event.name == 3 || event.name == 4;
+                if (((active_bitmap >> index) & 1) && matches) {
+                    maxim_midi_push(&result.items[index], event);
+                }
+            }
+        }
+    }
+    result.bitmap = active_bitmap;
+    return result;
+}
+
+MAXIM_FUNC maxim_midi maxim_fn_channel(const maxim_midi *midi, const maxim_num *channel) {
+    maxim_midi result;
+    uint8_t channel_index = (uint8_t) fmax(fmin(channel->v[0], 16.), 0.);
+    int index;
+    result.count = 0;
+    for (index = 0; index < midi->count; index++) {
+        if (midi->events[index].channel == channel_index) {
+            maxim_midi_push(&result, midi->events[index]);
+        }
+    }
+    return result;
+}
+
+MAXIM_FUNC maxim_array_num maxim_fn_indexed(const maxim_num *count) {
+    maxim_array_num result;
+    uint32_t item_count = (uint32_t) fmax(fmin(count->v[0], 32.), 0.);
+    uint32_t index;
+    memset(&result, 0, sizeof(result));
+    result.bitmap = item_count == 0 ? 0 : 0xFFFFFFFFu >> (32 - item_count);
+    for (index = 0; index < item_count; index++) {
+        result.items[index] = maxim_num_make((double) index, (double) index, MAXIM_FORM_NONE);
+    }
+    return result;
+}
+
+#define MAXIM_ADSR_NOT_ACTIVE 0
+#define MAXIM_ADSR_ATTACK 1
+#define MAXIM_ADSR_DECAY 2
+#define MAXIM_ADSR_SUSTAIN 3
+#define MAXIM_ADSR_RELEASE 4
+
+typedef struct {
+    uint8_t last_trigger;
+    double pos;
+    double release_val;
+    double last_val;
+    uint8_t phase;
+} maxim_adsr_channel;
+
+typedef struct {
+    maxim_adsr_channel channels[2];
+} maxim_adsr_state;
+
+MAXIM_FUNC double maxim_adsr_channel_update(maxim_adsr_channel *channel, double trigger_val, double attack,
+                                            double decay, double sustain, double release) {
+    int trigger = maxim_not_equal(trigger_val, 0.);
+    double val = 0.;
+    if (trigger != channel->last_trigger) {
+        if (trigger) {
+            channel->phase = MAXIM_ADSR_ATTACK;
+        } else {
+            channel->release_val = channel->last_val;
+            channel->phase = release > 0. ? MAXIM_ADSR_RELEASE : MAXIM_ADSR_NOT_ACTIVE;
+        }
+        channel->pos = 0.;
+    }
+    channel->last_trigger = (uint8_t) trigger;
+
+    if (channel->phase != MAXIM_ADSR_NOT_ACTIVE) {
+        /* the envelope always advances at the left channel's sample rate */
+        channel->pos += 1. / maxim_samplerate[0];
+        if (channel->phase == MAXIM_ADSR_ATTACK && channel->pos >= attack) {
+            channel->pos = 0.;
+            channel->phase = decay > 0. ? MAXIM_ADSR_DECAY : MAXIM_ADSR_SUSTAIN;
+        } else if (channel->phase == MAXIM_ADSR_DECAY && channel->pos >= decay) {
+            channel->pos = 0.;
+            channel->phase = MAXIM_ADSR_SUSTAIN;
+        } else if (channel->phase == MAXIM_ADSR_RELEASE && channel->pos >= release) {
+            channel->pos = 0.;
+            channel->phase = MAXIM_ADSR_NOT_ACTIVE;
+        }
+    }
+
+    switch (channel->phase) {
+    case MAXIM_ADSR_ATTACK: val = channel->pos / attack; break;
+    case MAXIM_ADSR_DECAY: {
+        double f = 1. - maxim_pow(1. - channel->pos / decay, 2.);
+        val = (1. - f) + sustain * f;
+        break;
+    }
+    case MAXIM_ADSR_SUSTAIN: val = sustain; break;
+    case MAXIM_ADSR_RELEASE: {
+        double f = 1. - maxim_pow(1. - channel->pos / release, 2.);
+        val = channel->release_val * (1. - f);
+        break;
+    }
+    default: break;
+    }
+    channel->last_val = val;
+    return val;
+}
+
+MAXIM_FUNC maxim_tuple2_num_num maxim_fn_adsr(maxim_adsr_state *state, const maxim_num *trigger,
+                                              const maxim_num *attack, const maxim_num *decay,
+                                              const maxim_num *sustain, const maxim_num *release) {
+    maxim_tuple2_num_num result;
+    int channel;
+    for (channel = 0; channel < 2; channel++) {
+        result.i1.v[channel] =
+            maxim_adsr_channel_update(&state->channels[channel], trigger->v[channel], attack->v[channel],
+                                      decay->v[channel], sustain->v[channel], release->v[channel]);
+        result.i0.v[channel] = state->channels[channel].phase != MAXIM_ADSR_NOT_ACTIVE ? 1. : 0.;
+    }
+    result.i0.form = MAXIM_FORM_NONE;
+    result.i1.form = MAXIM_FORM_AMPLITUDE;
+    return result;
+}
+
+/* Controls */
+
+typedef struct {
+    uint8_t curve_count;
+    const double *start_values;
+    const double *end_positions;
+    const double *tension;
+    const uint8_t *states;
+} maxim_graph_data;
+
+typedef struct {
+    const maxim_graph_data *data;
+    uint32_t time;
+    uint8_t state;
+    uint8_t paused;
+} maxim_graph_control;
+
+/* Shapes the progress through a curve. */
+MAXIM_FUNC double maxim_tension_graph(double x, double tension) {
+    if (tension >= 0.) {
+        return maxim_pow(x, maxim_pow(20., tension));
+    } else {
+        return 1. - maxim_pow(1. - x, maxim_pow(20., -tension));
+    }
+}
+
+MAXIM_FUNC void maxim_graph_update(maxim_graph_control *control, maxim_num *value) {
+    const maxim_graph_data *data = control->data;
+    uint32_t last_curve_end = 0;
+    int curve;
+
+    if (!data) {
+        control->time = 0;
+        return;
+    }
+
+    for (curve = 0; curve < data->curve_count; curve++) {
+        uint32_t curve_end = maxim_to_u32(data->end_positions[curve] * maxim_samplerate[0] * 60. / maxim_bpm[0]);
+        int curve_active = control->time < curve_end;
+
+        /* we must pause at the start of a tagged curve, even if the curve isn't active */
+        int increment_sample =
+            control->time > last_curve_end || data->states[curve] != (uint8_t) (control->state + 1);
+
+        if (curve_active || !increment_sample) {
+            double curve_min = data->start_values[curve];
+            double curve_max = data->start_values[curve + 1];
+            uint32_t curve_length = curve_end - last_curve_end;
+            double output = curve_min;
+            if (curve_length != 0) {
+                double x = (double) (control->time - last_curve_end) / (double) curve_length;
+                output = curve_min + (curve_max - curve_min) * maxim_tension_graph(x, data->tension[curve]);
+            }
+            *value = maxim_num_make(output, output, MAXIM_FORM_NONE);
+
+            control->paused = (uint8_t) !increment_sample;
+            if (increment_sample) {
+                control->time++;
+            }
+            return;
+        }
+
+        last_curve_end = curve_end;
+    }
+
+    control->time = 0;
+}
+
+MAXIM_FUNC maxim_num maxim_graph_get_state(const maxim_graph_control *control) {
+    return maxim_num_make((double) control->state, (double) control->state, MAXIM_FORM_NONE);
+}
+
+MAXIM_FUNC maxim_num maxim_graph_get_paused(const maxim_graph_control *control) {
+    double paused = control->paused ? 1. : 0.;
+    return maxim_num_make(paused, paused, MAXIM_FORM_NONE);
+}
+
+MAXIM_FUNC maxim_num maxim_graph_get_time(const maxim_graph_control *control) {
+    return maxim_num_make((double) control->time, (double) control->time, MAXIM_FORM_SAMPLES);
+}
+
+MAXIM_FUNC void maxim_graph_set_state(maxim_graph_control *control, const maxim_num *value) {
+    control->state = (uint8_t) fmax(fmin(value->v[0], 254.), 0.);
+}
+
+MAXIM_FUNC void maxim_graph_set_time(maxim_graph_control *control, const maxim_num *value) {
+    control->time = maxim_to_u32(fmax(value->v[0], 0.));
+}
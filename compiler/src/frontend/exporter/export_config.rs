@@ -1,4 +1,5 @@
-use crate::codegen::OptimizationLevel;
+use crate::ast::FormType;
+use crate::codegen::{DenormalMode, MathAccuracy, OptimizationLevel, StoragePrecision};
 use crate::util::feature_level::FeatureLevel;
 use std::path::PathBuf;
 
@@ -22,6 +23,9 @@ pub enum TargetPlatform {
 pub enum TargetInstructionSet {
     I686,
     X64,
+    ARMv7,
+    AArch64,
+    Wasm32,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -37,6 +41,13 @@ pub struct CodeConfig {
     pub instrument_prefix: String,
     pub include_instrument: bool,
     pub include_library: bool,
+    pub control_rate_divisor: u32,
+    pub storage_precision: StoragePrecision,
+    pub math_accuracy: MathAccuracy,
+    pub max_delay_seconds: f64,
+    pub denormal_mode: DenormalMode,
+    pub sanitize_outputs: bool,
+    pub instanced: bool,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -46,6 +57,7 @@ pub enum ObjectFormat {
     Bitcode,
     IR,
     AssemblyListing,
+    CSource,
 }
 
 #[derive(Debug, Clone)]
@@ -60,6 +72,18 @@ pub enum MetaFormat {
     CHeader,
     RustModule,
     Json,
+    JavaScript,
+}
+
+/// An entry in the parameter table of the meta output. A parameter's ID is its index in the table,
+/// which is the ID its value group is tagged with.
+#[derive(Debug, Clone)]
+pub struct ExportParam {
+    pub name: String,
+    pub min: f64,
+    pub max: f64,
+    pub default: f64,
+    pub unit: FormType,
 }
 
 #[derive(Debug, Clone)]
@@ -67,6 +91,30 @@ pub struct MetaOutputConfig {
     pub format: MetaFormat,
     pub location: PathBuf,
     pub portal_names: Vec<String>,
+    pub params: Vec<ExportParam>,
+}
+
+/// A MIDI event in an embedded song, which is pushed onto a MIDI portal `frame` frames after the
+/// song starts.
+#[derive(Debug, Clone, Copy)]
+pub struct SongEvent {
+    pub frame: u32,
+    pub portal: usize,
+    pub event: u8,
+    pub channel: u8,
+    pub note: u8,
+    pub param: u8,
+}
+
+/// A sequence of MIDI events that's compiled into the exported module and played onto its MIDI
+/// portals as it generates. If a loop is set, playback jumps back to its start when it reaches its
+/// end, otherwise the song stops after the last event. Times are in frames at the export's sample
+/// rate.
+#[derive(Debug, Clone)]
+pub struct SongConfig {
+    pub events: Vec<SongEvent>,
+    pub length: u32,
+    pub loop_range: Option<(u32, u32)>,
 }
 
 #[derive(Debug, Clone)]
@@ -76,4 +124,5 @@ pub struct ExportConfig {
     pub code: CodeConfig,
     pub object: Option<ObjectOutputConfig>,
     pub meta: Option<MetaOutputConfig>,
+    pub song: Option<SongConfig>,
 }
@@ -0,0 +1,395 @@
+use super::export_config::{SongConfig, SongEvent};
+
+// The event types of MIDI values, which are numbered differently to MIDI status bytes
+const EVENT_NOTE_ON: u8 = 0;
+const EVENT_NOTE_OFF: u8 = 1;
+const EVENT_POLYPHONIC_AFTERTOUCH: u8 = 2;
+const EVENT_CHANNEL_AFTERTOUCH: u8 = 3;
+const EVENT_PITCH_WHEEL: u8 = 4;
+
+/// The tempo of a file that doesn't set one, in microseconds per quarter note (120 BPM).
+const DEFAULT_TEMPO: u32 = 500_000;
+
+struct Reader<'data> {
+    data: &'data [u8],
+    pos: usize,
+}
+
+impl<'data> Reader<'data> {
+    fn new(data: &'data [u8]) -> Self {
+        Reader { data, pos: 0 }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+
+    fn read_bytes(&mut self, count: usize) -> Result<&'data [u8], ()> {
+        let end = self.pos.checked_add(count).ok_or(())?;
+        let bytes = self.data.get(self.pos..end).ok_or(())?;
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn read_u8(&mut self) -> Result<u8, ()> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    fn read_u16(&mut self) -> Result<u16, ()> {
+        let bytes = self.read_bytes(2)?;
+        Ok(u16::from(bytes[0]) << 8 | u16::from(bytes[1]))
+    }
+
+    fn read_u32(&mut self) -> Result<u32, ()> {
+        let bytes = self.read_bytes(4)?;
+        Ok(bytes
+            .iter()
+            .fold(0, |value, &byte| value << 8 | u32::from(byte)))
+    }
+
+    fn read_var_len(&mut self) -> Result<u32, ()> {
+        let mut value = 0;
+        for _ in 0..4 {
+            let byte = self.read_u8()?;
+            value = value << 7 | u32::from(byte & 0x7F);
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(())
+    }
+
+    fn read_chunk(&mut self) -> Result<(&'data [u8], Reader<'data>), ()> {
+        let chunk_type = self.read_bytes(4)?;
+        let len = self.read_u32()? as usize;
+        Ok((chunk_type, Reader::new(self.read_bytes(len)?)))
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Timing {
+    TicksPerQuarter(u16),
+    SecondsPerTick(f64),
+}
+
+/// Reads a Standard MIDI File into a song. The events of every track and channel are played onto
+/// `portal`, and the song is as long as its longest track. Only format 0 and 1 files can be read,
+/// since the tracks of a format 2 file are separate songs.
+pub fn read_midi_file(data: &[u8], sample_rate: f64, portal: usize) -> Result<SongConfig, ()> {
+    let mut reader = Reader::new(data);
+    let (header_type, mut header) = reader.read_chunk()?;
+    if header_type != b"MThd" {
+        return Err(());
+    }
+    let format = header.read_u16()?;
+    let track_count = header.read_u16()?;
+    let division = header.read_u16()?;
+    if format > 1 {
+        return Err(());
+    }
+    let timing = if division & 0x8000 != 0 {
+        // the high byte is the negated SMPTE frame rate, and the low byte the ticks per frame
+        let frames_per_second = -f64::from((division >> 8) as u8 as i8);
+        let ticks_per_frame = f64::from(division & 0xFF);
+        Timing::SecondsPerTick(1. / (frames_per_second * ticks_per_frame))
+    } else {
+        Timing::TicksPerQuarter(division)
+    };
+    match timing {
+        Timing::TicksPerQuarter(0) => return Err(()),
+        Timing::SecondsPerTick(seconds) if !seconds.is_finite() || seconds <= 0. => return Err(()),
+        _ => {}
+    }
+
+    let mut track_events = Vec::new();
+    let mut tempo_changes = Vec::new();
+    let mut end_tick = 0;
+    let mut tracks_read = 0;
+    while tracks_read < track_count {
+        // chunks of unknown types have to be skipped
+        let (chunk_type, mut track) = reader.read_chunk()?;
+        if chunk_type != b"MTrk" {
+            continue;
+        }
+
+        let track_end_tick = read_track(&mut track, &mut track_events, &mut tempo_changes)?;
+        end_tick = end_tick.max(track_end_tick);
+        tracks_read += 1;
+    }
+
+    // both sorts are stable, so events on the same tick stay in the order they were in the file
+    track_events.sort_by_key(|&(tick, _)| tick);
+    tempo_changes.sort_by_key(|&(tick, _)| tick);
+
+    let tick_to_frame = |tick: u64| {
+        let seconds = match timing {
+            Timing::TicksPerQuarter(ticks_per_quarter) => {
+                let ticks_to_seconds = |ticks: u64, tempo: u32| {
+                    ticks as f64 * f64::from(tempo) / 1_000_000. / f64::from(ticks_per_quarter)
+                };
+
+                let mut seconds = 0.;
+                let mut last_tick = 0;
+                let mut tempo = DEFAULT_TEMPO;
+                for &(change_tick, change_tempo) in &tempo_changes {
+                    if change_tick >= tick {
+                        break;
+                    }
+                    seconds += ticks_to_seconds(change_tick - last_tick, tempo);
+                    last_tick = change_tick;
+                    tempo = change_tempo;
+                }
+                seconds + ticks_to_seconds(tick - last_tick, tempo)
+            }
+            Timing::SecondsPerTick(seconds_per_tick) => tick as f64 * seconds_per_tick,
+        };
+        (seconds * sample_rate).round() as u32
+    };
+
+    let events = track_events
+        .into_iter()
+        .map(|(tick, [event, channel, note, param])| SongEvent {
+            frame: tick_to_frame(tick),
+            portal,
+            event,
+            channel,
+            note,
+            param,
+        })
+        .collect();
+    Ok(SongConfig {
+        events,
+        length: tick_to_frame(end_tick),
+        loop_range: None,
+    })
+}
+
+/// Reads the events of a track, and returns the tick the track ends on.
+fn read_track(
+    track: &mut Reader,
+    events: &mut Vec<(u64, [u8; 4])>,
+    tempo_changes: &mut Vec<(u64, u32)>,
+) -> Result<u64, ()> {
+    let mut tick = 0;
+    let mut running_status = None;
+    while !track.is_empty() {
+        tick += u64::from(track.read_var_len()?);
+
+        // Channel messages can leave out their status byte if it's the same as the last one, in
+        // which case the byte that was read is their first data byte.
+        let status_byte = track.read_u8()?;
+        let (status, first_data) = if status_byte < 0x80 {
+            (running_status.ok_or(())?, Some(status_byte))
+        } else {
+            (status_byte, None)
+        };
+
+        match status {
+            0xFF => {
+                running_status = None;
+                let meta_type = track.read_u8()?;
+                let len = track.read_var_len()? as usize;
+                let meta_data = track.read_bytes(len)?;
+                match meta_type {
+                    // end of track
+                    0x2F => return Ok(tick),
+                    // set tempo
+                    0x51 if len == 3 => tempo_changes.push((
+                        tick,
+                        meta_data
+                            .iter()
+                            .fold(0, |tempo, &byte| tempo << 8 | u32::from(byte)),
+                    )),
+                    _ => {}
+                }
+            }
+            0xF0 | 0xF7 => {
+                running_status = None;
+                let len = track.read_var_len()? as usize;
+                track.read_bytes(len)?;
+            }
+            0x80..=0xEF => {
+                running_status = Some(status);
+                let data1 = match first_data {
+                    Some(data) => data,
+                    None => track.read_u8()?,
+                } & 0x7F;
+                let data2 = match status & 0xF0 {
+                    0xC0 | 0xD0 => 0,
+                    _ => track.read_u8()? & 0x7F,
+                };
+                if let Some(event) = convert_message(status, data1, data2) {
+                    events.push((tick, event));
+                }
+            }
+            _ => return Err(()),
+        }
+    }
+    Ok(tick)
+}
+
+/// Converts a channel message to an event, in the same way as MIDI from the editor's host. Messages
+/// that MIDI values can't hold, like control changes, are ignored.
+fn convert_message(status: u8, data1: u8, data2: u8) -> Option<[u8; 4]> {
+    let channel = status & 0x0F;
+    match status & 0xF0 {
+        0x80 => Some([EVENT_NOTE_OFF, channel, data1, 0]),
+        // a note on without a velocity is a note off, so it can share a running status with notes
+        0x90 if data2 == 0 => Some([EVENT_NOTE_OFF, channel, data1, 0]),
+        // velocities and pressures are 0-127, but params are 0-255
+        0x90 => Some([EVENT_NOTE_ON, channel, data1, data2 * 2]),
+        0xA0 => Some([EVENT_POLYPHONIC_AFTERTOUCH, channel, data1, data2 * 2]),
+        0xD0 => Some([EVENT_CHANNEL_AFTERTOUCH, channel, 0, data1 * 2]),
+        0xE0 => {
+            let pitch = u32::from(data2) << 7 | u32::from(data1);
+            Some([EVENT_PITCH_WHEEL, channel, 0, (pitch * 255 / 0x3FFF) as u8])
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: f64 = 48000.;
+
+    fn build_chunk(chunk_type: &[u8], body: &[u8]) -> Vec<u8> {
+        let mut chunk = chunk_type.to_vec();
+        chunk.extend_from_slice(&(body.len() as u32).to_be_bytes());
+        chunk.extend_from_slice(body);
+        chunk
+    }
+
+    fn build_file(division: u16, tracks: &[&[u8]]) -> Vec<u8> {
+        let mut header = vec![0, 1];
+        header.extend_from_slice(&(tracks.len() as u16).to_be_bytes());
+        header.extend_from_slice(&division.to_be_bytes());
+        let mut file = build_chunk(b"MThd", &header);
+        for track in tracks {
+            file.extend(build_chunk(b"MTrk", track));
+        }
+        file
+    }
+
+    fn get_events(song: &SongConfig) -> Vec<(u32, [u8; 4])> {
+        song.events
+            .iter()
+            .map(|event| {
+                assert_eq!(event.portal, 3);
+                (
+                    event.frame,
+                    [event.event, event.channel, event.note, event.param],
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn running_status_is_reused_for_data_bytes() {
+        // 96 ticks per quarter note at the default 120 BPM, so a tick is 250 frames
+        let track = [
+            0x00, 0x91, 60, 64, //
+            0x60, 62, 80, //
+            0x60, 60, 0, //
+            0x00, 62, 0, //
+            0x00, 0xFF, 0x2F, 0x00,
+        ];
+        let song = read_midi_file(&build_file(96, &[&track]), SAMPLE_RATE, 3).unwrap();
+        assert_eq!(
+            get_events(&song),
+            vec![
+                (0, [EVENT_NOTE_ON, 1, 60, 128]),
+                (24000, [EVENT_NOTE_ON, 1, 62, 160]),
+                (48000, [EVENT_NOTE_OFF, 1, 60, 0]),
+                (48000, [EVENT_NOTE_OFF, 1, 62, 0]),
+            ]
+        );
+        assert_eq!(song.length, 48000);
+        assert_eq!(song.loop_range, None);
+
+        // meta events cancel the running status, so data bytes can't follow them
+        let track = [
+            0x00, 0x91, 60, 64, //
+            0x00, 0xFF, 0x01, 0x00, //
+            0x60, 62, 80, //
+            0x00, 0xFF, 0x2F, 0x00,
+        ];
+        assert!(read_midi_file(&build_file(96, &[&track]), SAMPLE_RATE, 3).is_err());
+    }
+
+    #[test]
+    fn tempo_changes_apply_to_every_track() {
+        // the tempo halves to 60 BPM on the second beat, in a separate track to the notes
+        let tempo_track = [
+            0x60, 0xFF, 0x51, 0x03, 0x0F, 0x42, 0x40, //
+            0x00, 0xFF, 0x2F, 0x00,
+        ];
+        let note_track = [
+            0x00, 0x90, 60, 64, //
+            0x60, 62, 64, //
+            0x60, 64, 64, //
+            0x60, 0xFF, 0x2F, 0x00,
+        ];
+        let song = read_midi_file(
+            &build_file(96, &[&tempo_track, &note_track]),
+            SAMPLE_RATE,
+            3,
+        )
+        .unwrap();
+        assert_eq!(
+            get_events(&song),
+            vec![
+                (0, [EVENT_NOTE_ON, 0, 60, 128]),
+                (24000, [EVENT_NOTE_ON, 0, 62, 128]),
+                (72000, [EVENT_NOTE_ON, 0, 64, 128]),
+            ]
+        );
+        assert_eq!(song.length, 120_000);
+    }
+
+    #[test]
+    fn smpte_division_ignores_tempo() {
+        // 25 frames per second with 40 ticks per frame is a tick every millisecond
+        let track = [
+            0x00, 0xFF, 0x51, 0x03, 0x0F, 0x42, 0x40, //
+            0x00, 0x90, 60, 64, //
+            0x83, 0x60, 0x80, 60, 0, //
+            0x00, 0xFF, 0x2F, 0x00,
+        ];
+        let song = read_midi_file(&build_file(0xE728, &[&track]), SAMPLE_RATE, 3).unwrap();
+        assert_eq!(
+            get_events(&song),
+            vec![
+                (0, [EVENT_NOTE_ON, 0, 60, 128]),
+                (23040, [EVENT_NOTE_OFF, 0, 60, 0]),
+            ]
+        );
+        assert_eq!(song.length, 23040);
+
+        // a frame without any ticks has no timing
+        assert!(read_midi_file(&build_file(0xE700, &[&track]), SAMPLE_RATE, 3).is_err());
+    }
+
+    #[test]
+    fn truncated_chunks_are_rejected() {
+        let track = [
+            0x00, 0x90, 60, 64, //
+            0x60, 0x80, 60, 0, //
+            0x00, 0xFF, 0x2F, 0x00,
+        ];
+        let file = build_file(96, &[&track, &track]);
+        assert!(read_midi_file(&file, SAMPLE_RATE, 3).is_ok());
+        for len in 0..file.len() {
+            assert!(
+                read_midi_file(&file[..len], SAMPLE_RATE, 3).is_err(),
+                "file truncated to {} bytes",
+                len
+            );
+        }
+
+        // a track that ends partway through an event is rejected even if its chunk is whole
+        let track = [0x00, 0x90, 60];
+        assert!(read_midi_file(&build_file(96, &[&track]), SAMPLE_RATE, 3).is_err());
+    }
+}
@@ -1,33 +1,56 @@
+mod build_c_source;
 mod build_instrument_module;
 mod build_meta_output;
+mod build_song;
 pub mod export_config;
+mod midi_file;
 
-use self::build_instrument_module::build_instrument_module;
+use self::build_c_source::build_c_source;
+use self::build_instrument_module::{
+    build_instrument_module, prepare_transaction, InstanceDefaults,
+};
 use self::build_meta_output::{build_meta_output, ModuleMetadata};
 use self::export_config::{
     AudioConfig, CodeConfig, ExportConfig, MetaOutputConfig, ObjectFormat, ObjectOutputConfig,
-    TargetConfig, TargetInstructionSet, TargetPlatform,
+    SongConfig, TargetConfig, TargetInstructionSet, TargetPlatform,
 };
+pub use self::midi_file::read_midi_file;
 use super::Transaction;
 use crate::codegen::{
-    globals, runtime_lib, util, ModuleFunctionIterator, ModuleGlobalIterator, Optimizer,
-    TargetProperties,
+    data_analyzer, globals, runtime_lib, util, ModuleFunctionIterator, ModuleGlobalIterator,
+    Optimizer, TargetProperties,
 };
-use crate::util::feature_level::get_target_feature_string;
+use crate::mir::OversampleFactor;
+use crate::util::feature_level::{get_target_feature_string, FeatureFamily};
 use inkwell::context::Context;
 use inkwell::module::{Linkage, Module};
 use inkwell::targets::{CodeModel, FileType, RelocMode, Target};
 use inkwell::types::VectorType;
+use inkwell::AddressSpace;
+use std::path::{Path, PathBuf};
+use std::process::Command;
 use std::{fs, io};
 
+/// The linker wasm32 objects are linked into modules with, which has to be on the path.
+const WASM_LINKER: &str = "wasm-ld";
+
 fn export_meta(
     config: &MetaOutputConfig,
     audio_conf: &AudioConfig,
     code_conf: &CodeConfig,
+    song: Option<&SongConfig>,
     module_meta: &ModuleMetadata,
 ) -> io::Result<()> {
     let mut meta_output = String::new();
-    build_meta_output(&mut meta_output, audio_conf, code_conf, config, module_meta).unwrap();
+    build_meta_output(
+        &mut meta_output,
+        audio_conf,
+        code_conf,
+        config,
+        song,
+        module_meta,
+    )
+    .unwrap();
 
     fs::write(&config.location, &meta_output)
 }
@@ -36,22 +59,52 @@ fn get_target_cpu(instruction_set: TargetInstructionSet) -> &'static str {
     match instruction_set {
         TargetInstructionSet::I686 => "i686",
         TargetInstructionSet::X64 => "x86-64",
+        TargetInstructionSet::ARMv7
+        | TargetInstructionSet::AArch64
+        | TargetInstructionSet::Wasm32 => "generic",
+    }
+}
+
+fn get_feature_family(instruction_set: TargetInstructionSet) -> FeatureFamily {
+    match instruction_set {
+        TargetInstructionSet::I686 | TargetInstructionSet::X64 => FeatureFamily::X86,
+        TargetInstructionSet::ARMv7 => FeatureFamily::ARMv7,
+        TargetInstructionSet::AArch64 => FeatureFamily::AArch64,
+        TargetInstructionSet::Wasm32 => FeatureFamily::Wasm32,
     }
 }
 
-fn get_target_triple(target_conf: &TargetConfig) -> String {
+fn get_target_triple(target_conf: &TargetConfig) -> Option<String> {
+    // WebAssembly modules run the same on every platform. LLVM 6 still defaults wasm32 to ELF
+    // objects, so the environment has to ask for the WebAssembly object format.
+    if target_conf.instruction_set == TargetInstructionSet::Wasm32 {
+        return Some("wasm32-unknown-unknown-wasm".to_string());
+    }
+
     let cpu_specifier = match target_conf.instruction_set {
         TargetInstructionSet::I686 => "i686",
         TargetInstructionSet::X64 => "x86_64",
+        // Windows on 32-bit ARM only runs Thumb-2 code
+        TargetInstructionSet::ARMv7 if is_windows(target_conf.platform) => "thumbv7",
+        TargetInstructionSet::ARMv7 => "armv7",
+        TargetInstructionSet::AArch64 => "aarch64",
+        TargetInstructionSet::Wasm32 => unreachable!(),
     };
-    let platform_specifier = match target_conf.platform {
-        TargetPlatform::WindowsMsvc => "pc-windows-msvc",
-        TargetPlatform::WindowsGnu => "w64-windows-gnu",
-        TargetPlatform::Mac => "apple-darwin-macho",
-        TargetPlatform::Linux => "unknown-linux-gnu",
+    let platform_specifier = match (target_conf.platform, target_conf.instruction_set) {
+        (TargetPlatform::WindowsMsvc, _) => "pc-windows-msvc",
+        (TargetPlatform::WindowsGnu, _) => "w64-windows-gnu",
+        // there are no 32-bit ARM Macs
+        (TargetPlatform::Mac, TargetInstructionSet::ARMv7) => return None,
+        (TargetPlatform::Mac, _) => "apple-darwin-macho",
+        (TargetPlatform::Linux, TargetInstructionSet::ARMv7) => "unknown-linux-gnueabihf",
+        (TargetPlatform::Linux, _) => "unknown-linux-gnu",
     };
 
-    cpu_specifier.to_string() + "-" + platform_specifier
+    Some(cpu_specifier.to_string() + "-" + platform_specifier)
+}
+
+fn is_windows(platform: TargetPlatform) -> bool {
+    platform == TargetPlatform::WindowsMsvc || platform == TargetPlatform::WindowsGnu
 }
 
 fn export_object(
@@ -59,14 +112,29 @@ fn export_object(
     audio_conf: &AudioConfig,
     target_conf: &TargetConfig,
     code_conf: &CodeConfig,
+    song: Option<&SongConfig>,
     module_meta: &ModuleMetadata,
     transaction: Transaction,
 ) -> Result<(), ()> {
-    let target_triple = get_target_triple(target_conf);
+    // the WebAssembly backend is only there if the compiler was built with it
+    if target_conf.instruction_set == TargetInstructionSet::Wasm32 && !cfg!(feature = "webassembly")
+    {
+        return Err(());
+    }
+
+    // feature levels only make sense for the instruction sets they were made for
+    if !target_conf
+        .feature_level
+        .supports_family(get_feature_family(target_conf.instruction_set))
+    {
+        return Err(());
+    }
+
+    let target_triple = get_target_triple(target_conf).ok_or(())?;
     let target_cpu = get_target_cpu(target_conf.instruction_set);
     let target_features = get_target_feature_string(target_conf.feature_level);
 
-    let target = Target::from_triple(&target_triple).unwrap();
+    let target = Target::from_triple(&target_triple).map_err(|_| ())?;
 
     let machine = target
         .create_target_machine(
@@ -78,7 +146,47 @@ fn export_object(
             CodeModel::Default,
         )
         .unwrap();
-    let target_properties = TargetProperties::new(false, code_conf.optimization_level, machine);
+    let mut target_properties = TargetProperties::new(false, code_conf.optimization_level, machine);
+    target_properties.control_rate_divisor = code_conf.control_rate_divisor;
+    target_properties.storage_precision = code_conf.storage_precision;
+    target_properties.math_accuracy = code_conf.math_accuracy;
+    target_properties.max_delay_seconds = code_conf.max_delay_seconds;
+    target_properties.denormal_mode = code_conf.denormal_mode;
+    target_properties.sanitize_outputs = code_conf.sanitize_outputs;
+    target_properties.instanced = code_conf.instanced;
+
+    // Delay lines are the only thing claiming memory from the arena, so it's sized to fit the ones
+    // in the instrument at the sample rate it starts with. Instanced exports can change their rate
+    // later, in which case delays longer than that rate allows are clamped.
+    let prepared_transaction = prepare_transaction(transaction, &target_properties);
+    let arena_size = data_analyzer::get_arena_size(
+        &target_properties,
+        audio_conf.sample_rate,
+        0,
+        &prepared_transaction.surfaces,
+        &prepared_transaction.blocks,
+    );
+
+    // C source doesn't go through LLVM at all, and always includes the library and instrument since
+    // nothing else could provide them
+    if config.format == ObjectFormat::CSource {
+        if code_conf.instanced {
+            return Err(());
+        }
+
+        let mut source = String::new();
+        build_c_source(
+            &mut source,
+            audio_conf,
+            code_conf,
+            song,
+            module_meta,
+            &prepared_transaction,
+            arena_size,
+        )
+        .map_err(|_| {})?;
+        return fs::write(&config.location, &source).map_err(|_| {});
+    }
 
     let context = Context::create();
     let file_name = match config.location.file_name() {
@@ -87,11 +195,21 @@ fn export_object(
     };
     let output_module = target_properties.create_module(&context, file_name.to_str().unwrap());
 
-    if code_conf.include_library {
+    if code_conf.include_library && !code_conf.instanced {
         // build constant globals
+        // Oversampled nodes scale the sample rate while they run, so it can only be constant if
+        // there aren't any.
+        let has_oversampled_nodes = prepared_transaction.surfaces.values().any(|surface| {
+            surface
+                .nodes
+                .iter()
+                .any(|node| node.oversample_factor != OversampleFactor::None)
+        });
         let sample_rate_global = globals::get_sample_rate(&output_module);
-        sample_rate_global.set_constant(true);
+        sample_rate_global.set_constant(!has_oversampled_nodes);
         sample_rate_global.set_initializer(&util::get_vec_spread(&context, audio_conf.sample_rate));
+        globals::get_construct_sample_rate(&output_module)
+            .set_initializer(&util::get_vec_spread(&context, audio_conf.sample_rate));
 
         let bpm_global = globals::get_bpm(&output_module);
         bpm_global.set_constant(true);
@@ -101,16 +219,44 @@ fn export_object(
             &context.i64_type().const_int(31337, false),
         ]));
 
-        // build the library
+        // delay lines claim their buffers from a statically allocated arena, so the exported
+        // module never allocates
+        let arena_storage_type = context.i8_type().array_type(arena_size as u32);
+        let arena_storage_global =
+            util::get_or_create_global(&output_module, "maxim.arena.storage", &arena_storage_type);
+        arena_storage_global.set_initializer(&arena_storage_type.const_null());
+        globals::get_arena(&output_module).set_initializer(
+            &arena_storage_global
+                .as_pointer_value()
+                .const_cast(&context.i8_type().ptr_type(AddressSpace::Generic)),
+        );
+        globals::get_arena_size(&output_module)
+            .set_initializer(&context.i64_type().const_int(arena_size, false));
+        globals::get_arena_used(&output_module)
+            .set_initializer(&context.i64_type().const_int(0, false));
+        globals::initialize_faults(&output_module);
+    }
+    if code_conf.include_library {
         runtime_lib::codegen_lib(&output_module, &target_properties);
     }
     if code_conf.include_instrument {
+        let instance_defaults = InstanceDefaults {
+            sample_rate: audio_conf.sample_rate,
+            bpm: audio_conf.bpm,
+            arena_size,
+        };
         build_instrument_module(
             &context,
             &output_module,
             &target_properties,
-            transaction,
+            &prepared_transaction,
             module_meta,
+            if code_conf.instanced {
+                Some(&instance_defaults)
+            } else {
+                None
+            },
+            song,
         );
 
         hide_internal_symbols(&output_module);
@@ -128,7 +274,11 @@ fn export_object(
                 .write_to_memory_buffer(&output_module, FileType::Object)
                 .map_err(|_| {})?;
 
-            fs::write(&config.location, mem_buf.as_slice()).map_err(|_| {})
+            if target_conf.instruction_set == TargetInstructionSet::Wasm32 {
+                link_wasm_module(mem_buf.as_slice(), &config.location)
+            } else {
+                fs::write(&config.location, mem_buf.as_slice()).map_err(|_| {})
+            }
         }
         ObjectFormat::Bitcode => {
             if output_module.write_bitcode_to_path(&config.location) {
@@ -150,6 +300,28 @@ fn export_object(
 
             fs::write(&config.location, mem_buf.as_slice()).map_err(|_| {})
         }
+        ObjectFormat::CSource => unreachable!(),
+    }
+}
+
+/// Links a wasm32 object into a module that can be loaded on its own. It has no entry point, every
+/// exported function is exported from the module, and the C library functions the object calls
+/// are left as imports for the JavaScript module to provide.
+fn link_wasm_module(object: &[u8], location: &Path) -> Result<(), ()> {
+    let mut object_path = location.as_os_str().to_owned();
+    object_path.push(".o");
+    let object_path = PathBuf::from(object_path);
+    fs::write(&object_path, object).map_err(|_| {})?;
+
+    let status = Command::new(WASM_LINKER)
+        .args(&["--no-entry", "--export-dynamic", "--allow-undefined", "-o"])
+        .arg(location)
+        .arg(&object_path)
+        .status();
+    let _ = fs::remove_file(&object_path);
+    match status {
+        Ok(status) if status.success() => Ok(()),
+        _ => Err(()),
     }
 }
 
@@ -175,12 +347,38 @@ pub fn export(config: &ExportConfig, transaction: Transaction) -> Result<(), ()>
         init_func_name: config.code.instrument_prefix.clone() + "init",
         cleanup_func_name: config.code.instrument_prefix.clone() + "cleanup",
         generate_func_name: config.code.instrument_prefix.clone() + "generate",
+        generate_block_func_name: config.code.instrument_prefix.clone() + "generate_block",
         portal_func_name: config.code.instrument_prefix.clone() + "portal",
+        portal_buffer_func_name: config.code.instrument_prefix.clone() + "portal_buffer",
+        midi_push_func_name: config.code.instrument_prefix.clone() + "push_midi",
+        midi_queue_func_name: config.code.instrument_prefix.clone() + "queue_midi",
+        create_func_name: config.code.instrument_prefix.clone() + "create",
+        state_size_func_name: config.code.instrument_prefix.clone() + "state_size",
+        set_param_func_name: config.code.instrument_prefix.clone() + "set_param",
+        get_param_func_name: config.code.instrument_prefix.clone() + "get_param",
     };
 
+    // a loop has to move playback forward, or the song would never get past its end
+    if let Some(SongConfig {
+        loop_range: Some((loop_start, loop_end)),
+        ..
+    }) = &config.song
+    {
+        if loop_start >= loop_end {
+            return Err(());
+        }
+    }
+
     // Export the requested data
     if let Some(meta_config) = &config.meta {
-        export_meta(meta_config, &config.audio, &config.code, &module_meta).map_err(|_| {})?;
+        export_meta(
+            meta_config,
+            &config.audio,
+            &config.code,
+            config.song.as_ref(),
+            &module_meta,
+        )
+        .map_err(|_| {})?;
     }
     if let Some(object_config) = &config.object {
         export_object(
@@ -188,6 +386,7 @@ pub fn export(config: &ExportConfig, transaction: Transaction) -> Result<(), ()>
             &config.audio,
             &config.target,
             &config.code,
+            config.song.as_ref(),
             &module_meta,
             transaction,
         )?;
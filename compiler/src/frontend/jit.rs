@@ -1,3 +1,4 @@
+use inkwell::memory_buffer::MemoryBuffer;
 use inkwell::module::Module;
 use inkwell::orc::{Orc, OrcModuleKey};
 use inkwell::targets::TargetMachine;
@@ -21,6 +22,11 @@ impl Jit {
         self.orc.add_module(&module.clone())
     }
 
+    /// Loads object code that was already compiled for this machine.
+    pub fn deploy_object(&self, object: &MemoryBuffer) -> JitKey {
+        self.orc.add_object_file(object)
+    }
+
     pub fn remove(&self, key: JitKey) {
         self.orc.remove_module(key);
     }
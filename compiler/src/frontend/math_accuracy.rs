@@ -0,0 +1,407 @@
+use super::Jit;
+use crate::codegen::{
+    build_context_function, globals, math, util, BuilderContext, TargetProperties,
+};
+use inkwell::context::Context;
+use inkwell::module::{Linkage, Module};
+use inkwell::values::FunctionValue;
+use std::{f64, fmt, mem};
+
+const SAMPLE_COUNT: usize = 4096;
+
+#[derive(Debug, Clone, Copy)]
+enum Domain {
+    Linear(f64, f64),
+    Logarithmic(f64, f64),
+}
+
+impl Domain {
+    fn sample(self, index: usize, count: usize) -> f64 {
+        let position = index as f64 / (count - 1) as f64;
+        match self {
+            Domain::Linear(min, max) => min + (max - min) * position,
+            Domain::Logarithmic(min, max) => min * (max / min).powf(position),
+        }
+    }
+}
+
+struct MathTest {
+    name: &'static str,
+    get_func: fn(&Module) -> FunctionValue,
+    reference: fn(f64, f64) -> f64,
+    x_domain: Domain,
+    y_domain: Option<Domain>,
+}
+
+fn unary(
+    name: &'static str,
+    get_func: fn(&Module) -> FunctionValue,
+    reference: fn(f64, f64) -> f64,
+    x_domain: Domain,
+) -> MathTest {
+    MathTest {
+        name,
+        get_func,
+        reference,
+        x_domain,
+        y_domain: None,
+    }
+}
+
+fn binary(
+    name: &'static str,
+    get_func: fn(&Module) -> FunctionValue,
+    reference: fn(f64, f64) -> f64,
+    x_domain: Domain,
+    y_domain: Domain,
+) -> MathTest {
+    MathTest {
+        name,
+        get_func,
+        reference,
+        x_domain,
+        y_domain: Some(y_domain),
+    }
+}
+
+fn get_tests() -> Vec<MathTest> {
+    vec![
+        unary(
+            "sin",
+            math::sin_v2f64,
+            |x, _| x.sin(),
+            Domain::Linear(-10., 10.),
+        ),
+        unary(
+            "cos",
+            math::cos_v2f64,
+            |x, _| x.cos(),
+            Domain::Linear(-10., 10.),
+        ),
+        unary(
+            "tan",
+            math::tan_v2f64,
+            |x, _| x.tan(),
+            Domain::Linear(-1.4, 1.4),
+        ),
+        unary(
+            "exp",
+            math::exp_v2f64,
+            |x, _| x.exp(),
+            Domain::Linear(-20., 20.),
+        ),
+        unary(
+            "exp2",
+            math::exp2_v2f64,
+            |x, _| x.exp2(),
+            Domain::Linear(-30., 30.),
+        ),
+        unary(
+            "exp10",
+            math::exp10_v2f64,
+            |x, _| 10_f64.powf(x),
+            Domain::Linear(-8., 8.),
+        ),
+        unary(
+            "log",
+            math::log_v2f64,
+            |x, _| x.ln(),
+            Domain::Logarithmic(1e-6, 1e6),
+        ),
+        unary(
+            "log2",
+            math::log2_v2f64,
+            |x, _| x.log2(),
+            Domain::Logarithmic(1e-6, 1e6),
+        ),
+        unary(
+            "log10",
+            math::log10_v2f64,
+            |x, _| x.log10(),
+            Domain::Logarithmic(1e-6, 1e6),
+        ),
+        unary(
+            "asin",
+            math::asin_v2f64,
+            |x, _| x.asin(),
+            Domain::Linear(-1., 1.),
+        ),
+        unary(
+            "acos",
+            math::acos_v2f64,
+            |x, _| x.acos(),
+            Domain::Linear(-1., 1.),
+        ),
+        unary(
+            "atan",
+            math::atan_v2f64,
+            |x, _| x.atan(),
+            Domain::Linear(-20., 20.),
+        ),
+        unary(
+            "sinh",
+            math::sinh_v2f64,
+            |x, _| x.sinh(),
+            Domain::Linear(-10., 10.),
+        ),
+        unary(
+            "cosh",
+            math::cosh_v2f64,
+            |x, _| x.cosh(),
+            Domain::Linear(-10., 10.),
+        ),
+        unary(
+            "tanh",
+            math::tanh_v2f64,
+            |x, _| x.tanh(),
+            Domain::Linear(-10., 10.),
+        ),
+        binary(
+            "pow",
+            math::pow_v2f64,
+            |x, y| x.powf(y),
+            Domain::Logarithmic(1e-3, 1e3),
+            Domain::Linear(-4., 4.),
+        ),
+        binary(
+            "atan2",
+            math::atan2_v2f64,
+            |y, x| y.atan2(x),
+            Domain::Linear(-1., 1.),
+            Domain::Linear(-1., 1.),
+        ),
+        binary(
+            "hypot",
+            math::hypot_v2f64,
+            |x, y| x.hypot(y),
+            Domain::Linear(-100., 100.),
+            Domain::Linear(-100., 100.),
+        ),
+    ]
+}
+
+/// The worst error seen when comparing one generated math function against Rust's standard
+/// library. Errors are absolute where the expected result is at most one in magnitude, since
+/// relative errors are meaningless around zero crossings, and relative for larger results.
+#[derive(Debug, Clone)]
+pub struct AccuracyResult {
+    pub name: &'static str,
+    pub max_abs_error: f64,
+    pub max_rel_error: f64,
+    pub worst_input: (f64, f64),
+}
+
+impl AccuracyResult {
+    /// The worst error of either kind, which is what the accuracy tiers are documented in.
+    pub fn max_error(&self) -> f64 {
+        self.max_abs_error.max(self.max_rel_error)
+    }
+}
+
+impl fmt::Display for AccuracyResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(
+            f,
+            "{:<6} max abs error {:e}, max rel error {:e} (worst at {}, {})",
+            self.name,
+            self.max_abs_error,
+            self.max_rel_error,
+            self.worst_input.0,
+            self.worst_input.1
+        )
+    }
+}
+
+fn get_wrapper_name(test: &MathTest) -> String {
+    format!("maxim.accuracy.{}", test.name)
+}
+
+/// Builds a scalar wrapper around a vector math function, so it can be called from Rust.
+fn build_wrapper_func(module: &Module, target: &TargetProperties, test: &MathTest) {
+    let context = module.get_context();
+    let f64_type = context.f64_type();
+    let wrapper_func = util::get_or_create_func(module, &get_wrapper_name(test), false, &|| {
+        (
+            Linkage::ExternalLinkage,
+            f64_type.fn_type(&[&f64_type, &f64_type], false),
+        )
+    });
+    let math_func = (test.get_func)(module);
+    let has_y = test.y_domain.is_some();
+
+    build_context_function(module, wrapper_func, target, &|ctx: BuilderContext| {
+        let x_vec = util::splat_vector(
+            ctx.b,
+            ctx.func.get_nth_param(0).unwrap().into_float_value(),
+            "x",
+        );
+        let y_vec = util::splat_vector(
+            ctx.b,
+            ctx.func.get_nth_param(1).unwrap().into_float_value(),
+            "y",
+        );
+        let result = if has_y {
+            ctx.b
+                .build_call(&math_func, &[&x_vec, &y_vec], "result", true)
+        } else {
+            ctx.b.build_call(&math_func, &[&x_vec], "result", true)
+        }
+        .left()
+        .unwrap()
+        .into_vector_value();
+        let left_result =
+            ctx.b
+                .build_extract_element(&result, &ctx.context.i32_type().const_int(0, false), "");
+        ctx.b.build_return(Some(&left_result));
+    });
+}
+
+/// Measures the accuracy of every function in the generated math library, at the accuracy tier
+/// of the target. Each function is run over the range of inputs it's typically used with, and
+/// compared against the matching function from Rust's standard library.
+pub fn measure_math_accuracy(target: &TargetProperties) -> Vec<AccuracyResult> {
+    let context = Context::create();
+    let module = target.create_module(&context, "accuracy");
+    globals::build_globals(&module);
+    math::build_math_functions(&module, target);
+
+    let tests = get_tests();
+    for test in &tests {
+        build_wrapper_func(&module, target, test);
+    }
+
+    let jit = Jit::new();
+    let key = jit.deploy(&module);
+    let results = tests
+        .iter()
+        .map(|test| {
+            let func_address = jit.get_symbol_address(&get_wrapper_name(test)) as usize;
+            assert_ne!(func_address, 0);
+            let func: unsafe extern "C" fn(f64, f64) -> f64 =
+                unsafe { mem::transmute(func_address) };
+
+            let mut result = AccuracyResult {
+                name: test.name,
+                max_abs_error: 0.,
+                max_rel_error: 0.,
+                worst_input: (0., 0.),
+            };
+            let y_count = if test.y_domain.is_some() {
+                SAMPLE_COUNT / 64
+            } else {
+                1
+            };
+            let x_count = SAMPLE_COUNT / y_count;
+            for y_index in 0..y_count {
+                let y = match test.y_domain {
+                    Some(domain) => domain.sample(y_index, y_count),
+                    None => 0.,
+                };
+                for x_index in 0..x_count {
+                    let x = test.x_domain.sample(x_index, x_count);
+                    let expected = (test.reference)(x, y);
+                    let actual = unsafe { func(x, y) };
+
+                    let max_error = result.max_error();
+                    // NaNs would be skipped over by `max`
+                    let abs_error = match (actual - expected).abs() {
+                        error if error.is_nan() => f64::INFINITY,
+                        error => error,
+                    };
+                    if expected.abs() <= 1. {
+                        result.max_abs_error = result.max_abs_error.max(abs_error);
+                    } else {
+                        result.max_rel_error = result.max_rel_error.max(abs_error / expected.abs());
+                    }
+                    if result.max_error() > max_error {
+                        result.worst_input = (x, y);
+                    }
+                }
+            }
+            result
+        })
+        .collect();
+    jit.remove(key);
+
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::measure_math_accuracy;
+    use crate::codegen::{MathAccuracy, OptimizationLevel, TargetProperties};
+    use crate::frontend::c_api;
+
+    // The error allowed for each function at the fast, balanced and accurate tiers, which are the
+    // bounds documented on `MathAccuracy`.
+    const BOUNDS: &[(&str, [f64; 3])] = &[
+        ("sin", [6e-2, 2e-3, 1e-14]),
+        ("cos", [6e-2, 2e-3, 1e-14]),
+        ("tan", [2e-1, 1e-2, 1e-14]),
+        ("exp", [1e-3, 1e-7, 1e-14]),
+        ("exp2", [1e-3, 1e-7, 1e-14]),
+        ("exp10", [1e-3, 1e-7, 1e-14]),
+        ("log", [1e-3, 1e-7, 1e-14]),
+        ("log2", [1e-3, 1e-7, 1e-14]),
+        ("log10", [1e-3, 1e-7, 1e-14]),
+        ("asin", [1e-3, 1e-7, 1e-14]),
+        ("acos", [1e-3, 1e-7, 1e-14]),
+        ("atan", [1e-3, 1e-7, 1e-14]),
+        ("sinh", [1e-3, 1e-7, 1e-14]),
+        ("cosh", [1e-3, 1e-7, 1e-14]),
+        ("tanh", [1e-3, 1e-7, 1e-14]),
+        ("pow", [1e-3, 1e-7, 1e-14]),
+        ("atan2", [1e-3, 1e-7, 1e-14]),
+        ("hypot", [1e-14, 1e-14, 1e-14]),
+    ];
+
+    fn check_accuracy(math_accuracy: MathAccuracy) {
+        crate::maxim_initialize();
+        let mut target = TargetProperties::new(
+            false,
+            OptimizationLevel::Editor,
+            c_api::create_host_machine(),
+        );
+        target.math_accuracy = math_accuracy;
+        let tier = match math_accuracy {
+            MathAccuracy::Fast => 0,
+            MathAccuracy::Balanced => 1,
+            MathAccuracy::Accurate => 2,
+        };
+
+        let results = measure_math_accuracy(&target);
+        assert_eq!(results.len(), BOUNDS.len());
+        let failures: Vec<_> = results
+            .iter()
+            .filter(|result| {
+                let &(_, bounds) = BOUNDS
+                    .iter()
+                    .find(|(name, _)| *name == result.name)
+                    .unwrap();
+                !(result.max_error() <= bounds[tier])
+            })
+            .map(|result| result.to_string())
+            .collect();
+        assert!(
+            failures.is_empty(),
+            "{:?} math is out of bounds:\n{}",
+            math_accuracy,
+            failures.join("\n")
+        );
+    }
+
+    #[test]
+    fn fast_math_is_within_bounds() {
+        check_accuracy(MathAccuracy::Fast);
+    }
+
+    #[test]
+    fn balanced_math_is_within_bounds() {
+        check_accuracy(MathAccuracy::Balanced);
+    }
+
+    #[test]
+    fn accurate_math_is_within_bounds() {
+        check_accuracy(MathAccuracy::Accurate);
+    }
+}
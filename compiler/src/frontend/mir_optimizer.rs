@@ -1,4 +1,3 @@
-use crate::codegen::TargetProperties;
 use crate::{mir, pass};
 use std::iter;
 
@@ -6,10 +5,12 @@ use std::iter;
 ///   - Find extract regions and move them into extract groups
 ///   - Remove dead value groups (which can appear from the extractor grouping pass)
 ///   - Adjust order of nodes in the surface
+///
+/// Nodes that don't affect the output are only kept if `include_ui` is set.
 pub fn prepare_surfaces<'iter>(
     surfaces: impl IntoIterator<Item = mir::Surface> + 'iter,
     id_allocator: &'iter mut mir::IdAllocator,
-    target: &'iter TargetProperties,
+    include_ui: bool,
 ) -> impl Iterator<Item = mir::Surface> + 'iter {
     surfaces
         .into_iter()
@@ -18,7 +19,7 @@ pub fn prepare_surfaces<'iter>(
             new_surfaces.into_iter().chain(iter::once(surface))
         })
         .map(move |mut surface| {
-            pass::order_nodes(&mut surface, target);
+            pass::order_nodes(&mut surface, include_ui);
             pass::remove_dead_groups(&mut surface);
             surface
         })
@@ -1,14 +1,32 @@
 pub mod c_api;
 mod dependency_graph;
+mod disk_cache;
+#[cfg(feature = "jit")]
 pub mod exporter;
+#[cfg(feature = "jit")]
 mod jit;
-mod mir_optimizer;
+#[cfg(feature = "jit")]
+pub mod math_accuracy;
+pub mod mir_optimizer;
+#[cfg(feature = "jit")]
+pub mod profile_report;
+#[cfg(feature = "jit")]
 mod runtime;
+mod runtime_snapshot;
+#[cfg(feature = "jit")]
+mod state_migration;
+#[cfg(all(test, feature = "jit"))]
+mod tests;
+#[cfg(feature = "jit")]
 pub mod value_reader;
 
 pub use self::dependency_graph::DependencyGraph;
+pub use self::disk_cache::DiskCache;
+#[cfg(feature = "jit")]
 pub use self::jit::Jit;
-pub use self::runtime::Runtime;
+#[cfg(feature = "jit")]
+pub use self::runtime::{AudioRuntime, Fault, Runtime};
+pub use self::runtime_snapshot::RuntimeSnapshot;
 
 use crate::mir::{Block, BlockRef, Root, Surface, SurfaceRef};
 use std::collections::HashMap;
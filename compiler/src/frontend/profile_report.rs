@@ -0,0 +1,153 @@
+use super::Runtime;
+use crate::codegen::data_analyzer::PROFILE_WINDOW;
+use crate::codegen::values::ARRAY_CAPACITY;
+use crate::codegen::ObjectCache;
+use crate::mir::{NodeData, SurfaceRef};
+use std::os::raw::c_void;
+
+/// The number of buckets in a node's timing histogram. Bucket `n` counts the updates that took
+/// between `2^(n-1)` and `2^n` cycles, with the last bucket also counting anything longer.
+pub const HISTOGRAM_BINS: usize = 32;
+
+/// A node's timings, matching `data_analyzer::get_profile_record_type`.
+#[repr(C)]
+struct ProfileRecord {
+    position: u32,
+    count: u32,
+    timings: [u32; PROFILE_WINDOW as usize],
+}
+
+/// How long a node has taken to update over the last `PROFILE_WINDOW` samples, in cycles. Groups
+/// include the time taken by the nodes inside them.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct NodeProfile {
+    pub surface: SurfaceRef,
+    pub node: usize,
+
+    /// The voice of the extracted group the node is in, or -1 if it's not in one.
+    pub voice: i32,
+    pub sample_count: u32,
+    pub min_cycles: u32,
+    pub max_cycles: u32,
+    pub average_cycles: f64,
+    pub median_cycles: u32,
+    pub p90_cycles: u32,
+    pub p99_cycles: u32,
+    pub histogram: [u32; HISTOGRAM_BINS],
+}
+
+fn get_percentile(sorted_timings: &[u32], percentile: usize) -> u32 {
+    sorted_timings[(sorted_timings.len() - 1) * percentile / 100]
+}
+
+fn build_node_profile(
+    surface: SurfaceRef,
+    node: usize,
+    voice: Option<usize>,
+    record: &ProfileRecord,
+) -> NodeProfile {
+    let mut timings = record.timings[..record.count as usize].to_vec();
+    timings.sort();
+
+    let mut histogram = [0; HISTOGRAM_BINS];
+    for &timing in &timings {
+        let bin = (32 - timing.leading_zeros()) as usize;
+        histogram[bin.min(HISTOGRAM_BINS - 1)] += 1;
+    }
+
+    NodeProfile {
+        surface,
+        node,
+        voice: voice.map(|voice| voice as i32).unwrap_or(-1),
+        sample_count: record.count,
+        min_cycles: timings[0],
+        max_cycles: timings[timings.len() - 1],
+        average_cycles: timings.iter().map(|&timing| f64::from(timing)).sum::<f64>()
+            / timings.len() as f64,
+        median_cycles: get_percentile(&timings, 50),
+        p90_cycles: get_percentile(&timings, 90),
+        p99_cycles: get_percentile(&timings, 99),
+        histogram,
+    }
+}
+
+fn collect_surface_profiles(
+    runtime: &Runtime,
+    surface: SurfaceRef,
+    ptr: *const c_void,
+    voice: Option<usize>,
+    profiles: &mut Vec<NodeProfile>,
+) {
+    let surface_mir = runtime.surface_mir(surface).unwrap();
+    let layout = runtime.surface_layout(surface).unwrap();
+    let target_data = runtime.target().machine.get_data();
+    let get_field_ptr = |index: usize| {
+        let byte_offset = target_data
+            .offset_of_element(&layout.pointer_struct, index as u32)
+            .unwrap();
+        unsafe { ptr.offset(byte_offset as isize) }
+    };
+
+    let records = layout
+        .profile_ptr_index()
+        .map(|index| unsafe { *(get_field_ptr(index) as *const *const ProfileRecord) });
+
+    for (node_index, node) in surface_mir.nodes.iter().enumerate() {
+        if let Some(records) = records {
+            let record = unsafe { &*records.add(node_index) };
+            if record.count > 0 {
+                if let Some((external_surface, external_node)) =
+                    runtime.map_to_external_node(surface, node_index)
+                {
+                    profiles.push(build_node_profile(
+                        external_surface,
+                        external_node,
+                        voice,
+                        record,
+                    ));
+                }
+            }
+        }
+
+        // wrapped node layouts always keep the node's own pointers at the start, so the pointers
+        // of a group start at the node pointer
+        let node_ptr = get_field_ptr(layout.node_ptr_index(node_index));
+        match node.data {
+            NodeData::Group(subsurface) => {
+                collect_surface_profiles(runtime, subsurface, node_ptr, voice, profiles);
+            }
+            NodeData::ExtractGroup {
+                surface: subsurface,
+                ..
+            } => {
+                let voice_layout = runtime.surface_layout(subsurface).unwrap();
+                let voice_size = target_data.get_abi_size(&voice_layout.pointer_struct);
+                for voice_index in 0..usize::from(ARRAY_CAPACITY) {
+                    let voice_ptr =
+                        unsafe { node_ptr.offset((voice_size as usize * voice_index) as isize) };
+                    collect_surface_profiles(
+                        runtime,
+                        subsurface,
+                        voice_ptr,
+                        Some(voice_index),
+                        profiles,
+                    );
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Reads the timings of every node in the runtime, if it was created with node profiling
+/// enabled. Nodes are returned in the order they run, with voices of extracted groups following
+/// the group itself. Nodes that haven't run yet aren't included.
+pub fn build_profile_report(runtime: &Runtime) -> Vec<NodeProfile> {
+    let mut profiles = Vec::new();
+    let root_ptr = runtime.get_root_ptr();
+    if !root_ptr.is_null() {
+        collect_surface_profiles(runtime, 0, root_ptr, None, &mut profiles);
+    }
+    profiles
+}
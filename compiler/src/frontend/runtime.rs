@@ -1,52 +1,107 @@
 use super::dependency_graph::DependencyGraph;
+use super::disk_cache::{CacheKey, DiskCache};
 use super::jit::{Jit, JitKey};
 use super::mir_optimizer;
+use super::runtime_snapshot::RuntimeSnapshot;
+use super::state_migration::{self, StateSnapshot};
 use super::Transaction;
+use crate::codegen::values::MidiQueueValue;
 use crate::codegen::{
-    block, data_analyzer, editor, globals, root, runtime_lib, surface, ObjectCache, Optimizer,
-    TargetProperties,
+    block, cranelift, data_analyzer, editor, globals, root, runtime_lib, surface, util,
+    CodegenBackend, LifecycleFunc, ObjectCache, Optimizer, TargetProperties,
 };
 use crate::mir::{
-    Block, BlockRef, IdAllocator, IncrementalIdAllocator, InternalNodeRef, Root, Surface,
+    Block, BlockRef, IdAllocator, IncrementalIdAllocator, InternalNodeRef, NodeData, Root, Surface,
     SurfaceRef,
 };
 use inkwell::context::Context;
-use inkwell::module::Module;
-use std::collections::hash_map::Entry;
+use inkwell::memory_buffer::MemoryBuffer;
+use inkwell::module::{Linkage, Module};
+use inkwell::targets::FileType;
+use inkwell::types::StructType;
 use std::collections::{HashMap, HashSet, VecDeque};
-use std::iter::FromIterator;
+use std::hash::{Hash, Hasher};
+use std::iter::{self, FromIterator};
 use std::mem;
 use std::os::raw::c_void;
-use std::ptr;
+use std::sync::atomic::{AtomicPtr, AtomicU64, Ordering};
+use std::sync::Arc;
 use std::time::{Duration, Instant};
+use std::{hint, ptr, slice};
 
 struct RuntimeModule {
-    module: Module,
+    /// The module's IR, unless its object code was loaded from the disk cache.
+    module: Option<Module>,
+
+    /// Object code waiting to be loaded into the JIT instead of the IR.
+    object: Option<MemoryBuffer>,
     key: Option<JitKey>,
 }
 
 impl RuntimeModule {
-    pub fn new(module: Module, key: Option<JitKey>) -> Self {
-        RuntimeModule { module, key }
+    pub fn new(module: Option<Module>, object: Option<MemoryBuffer>, key: Option<JitKey>) -> Self {
+        RuntimeModule {
+            module,
+            object,
+            key,
+        }
     }
 }
 
+/// Bumped whenever a change to the compiler could change the code generated from the same MIR, so
+/// objects cached by older versions aren't loaded.
+const CODE_KEY_VERSION: u32 = 3;
+
 const INITIALIZED_GLOBAL_NAME: &str = "maxim.runtime.initialized";
 const SCRATCH_GLOBAL_NAME: &str = "maxim.runtime.scratch";
 const SOCKETS_GLOBAL_NAME: &str = "maxim.runtime.sockets";
 const PORTALS_GLOBAL_NAME: &str = "maxim.runtime.portals";
 const POINTERS_GLOBAL_NAME: &str = "maxim.runtime.pointers";
+const BUFFERS_GLOBAL_NAME: &str = "maxim.runtime.buffers";
+const PORTAL_BUFFERS_GLOBAL_NAME: &str = "maxim.runtime.portal_buffers";
+const MIDI_QUEUES_GLOBAL_NAME: &str = "maxim.runtime.midi_queues";
 
 const CONSTRUCT_FUNC_NAME: &str = "maxim.runtime.construct";
 const UPDATE_FUNC_NAME: &str = "maxim.runtime.update";
+const UPDATE_BLOCK_FUNC_NAME: &str = "maxim.runtime.update_block";
+const QUEUE_MIDI_FUNC_NAME: &str = "maxim.runtime.queue_midi";
 const DESTRUCT_FUNC_NAME: &str = "maxim.runtime.destruct";
 
 const CONVERT_NUM_FUNC_NAME: &str = "maxim.editor.convert_num";
 
+/// Root globals and functions are suffixed with the generation they were compiled in, so a new
+/// root can be loaded while the old one is still running.
+fn get_root_symbol(name: &str, generation: u64) -> String {
+    format!("{}.{}", name, generation)
+}
+
+/// A node that output a non-finite value, identified by its surface and index in the editor.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fault {
+    pub surface: SurfaceRef,
+    pub node: usize,
+}
+
+/// An entry in the fault table, matching `globals::get_fault_type`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct FaultEntry {
+    surface: SurfaceRef,
+    node: u32,
+}
+
 struct LibraryPointers {
     samplerate_ptr: *mut c_void,
+    construct_samplerate_ptr: *mut c_void,
     bpm_ptr: *mut c_void,
     profile_times_ptr: *mut c_void,
+    arena_ptr: *mut c_void,
+    arena_size_ptr: *mut c_void,
+    arena_used_ptr: *mut c_void,
+    faults_ptr: *mut c_void,
+    fault_count_ptr: *mut c_void,
+    fault_generation_ptr: *mut c_void,
     convert_num: unsafe extern "C" fn(*mut c_void, i8, *const c_void),
 }
 
@@ -56,6 +111,10 @@ impl LibraryPointers {
             jit.get_symbol_address(globals::SAMPLERATE_GLOBAL_NAME) as usize;
         assert_ne!(samplerate_ptr_address, 0);
 
+        let construct_samplerate_ptr_address =
+            jit.get_symbol_address(globals::CONSTRUCT_SAMPLERATE_GLOBAL_NAME) as usize;
+        assert_ne!(construct_samplerate_ptr_address, 0);
+
         let bpm_ptr_address = jit.get_symbol_address(globals::BPM_GLOBAL_NAME) as usize;
         assert_ne!(bpm_ptr_address, 0);
 
@@ -63,13 +122,39 @@ impl LibraryPointers {
             jit.get_symbol_address(globals::PROFILE_TIME_GLOBAL_NAME) as usize;
         assert_ne!(profile_times_address, 0);
 
+        let arena_address = jit.get_symbol_address(globals::ARENA_GLOBAL_NAME) as usize;
+        assert_ne!(arena_address, 0);
+
+        let arena_size_address = jit.get_symbol_address(globals::ARENA_SIZE_GLOBAL_NAME) as usize;
+        assert_ne!(arena_size_address, 0);
+
+        let arena_used_address = jit.get_symbol_address(globals::ARENA_USED_GLOBAL_NAME) as usize;
+        assert_ne!(arena_used_address, 0);
+
+        let faults_address = jit.get_symbol_address(globals::FAULTS_GLOBAL_NAME) as usize;
+        assert_ne!(faults_address, 0);
+
+        let fault_count_address = jit.get_symbol_address(globals::FAULT_COUNT_GLOBAL_NAME) as usize;
+        assert_ne!(fault_count_address, 0);
+
+        let fault_generation_address =
+            jit.get_symbol_address(globals::FAULT_GENERATION_GLOBAL_NAME) as usize;
+        assert_ne!(fault_generation_address, 0);
+
         let convert_num_address = jit.get_symbol_address(CONVERT_NUM_FUNC_NAME) as usize;
         assert_ne!(convert_num_address, 0);
 
         LibraryPointers {
             samplerate_ptr: samplerate_ptr_address as *mut c_void,
+            construct_samplerate_ptr: construct_samplerate_ptr_address as *mut c_void,
             bpm_ptr: bpm_ptr_address as *mut c_void,
             profile_times_ptr: profile_times_address as *mut c_void,
+            arena_ptr: arena_address as *mut c_void,
+            arena_size_ptr: arena_size_address as *mut c_void,
+            arena_used_ptr: arena_used_address as *mut c_void,
+            faults_ptr: faults_address as *mut c_void,
+            fault_count_ptr: fault_count_address as *mut c_void,
+            fault_generation_ptr: fault_generation_address as *mut c_void,
             convert_num: unsafe { mem::transmute(convert_num_address) },
         }
     }
@@ -82,28 +167,54 @@ struct RuntimePointers {
     sockets_ptr: *mut c_void,
     portals_ptr: *mut c_void,
     pointers_ptr: *mut c_void,
+    buffers_ptr: *mut c_void,
+    portal_buffers_ptr: *mut c_void,
+    midi_queues_ptr: *mut c_void,
+    portal_count: usize,
+    midi_portals: Vec<usize>,
+    midi_queue_size: usize,
     construct: unsafe extern "C" fn(),
     update: unsafe extern "C" fn(),
+    update_block: unsafe extern "C" fn(u32) -> u32,
+    queue_midi: unsafe extern "C" fn(u32, u32, u8, u8, u8, u8),
     destruct: unsafe extern "C" fn(),
 }
 
 impl RuntimePointers {
-    pub fn new(jit: &Jit) -> Self {
-        let construct_address = jit.get_symbol_address(CONSTRUCT_FUNC_NAME) as usize;
+    pub fn new(
+        jit: &Jit,
+        generation: u64,
+        portal_count: usize,
+        midi_portals: Vec<usize>,
+        midi_queue_size: usize,
+    ) -> Self {
+        let get_address =
+            |name: &str| jit.get_symbol_address(&get_root_symbol(name, generation)) as usize;
+
+        let construct_address = get_address(CONSTRUCT_FUNC_NAME);
         assert_ne!(construct_address, 0);
 
-        let update_address = jit.get_symbol_address(UPDATE_FUNC_NAME) as usize;
+        let update_address = get_address(UPDATE_FUNC_NAME);
         assert_ne!(update_address, 0);
 
-        let destruct_address = jit.get_symbol_address(DESTRUCT_FUNC_NAME) as usize;
+        let update_block_address = get_address(UPDATE_BLOCK_FUNC_NAME);
+        assert_ne!(update_block_address, 0);
+
+        let queue_midi_address = get_address(QUEUE_MIDI_FUNC_NAME);
+        assert_ne!(queue_midi_address, 0);
+
+        let destruct_address = get_address(DESTRUCT_FUNC_NAME);
         assert_ne!(destruct_address, 0);
 
         // pointers can be null when they're pointing to empty data
-        let initialized_ptr_address = jit.get_symbol_address(INITIALIZED_GLOBAL_NAME) as usize;
-        let scratch_ptr_address = jit.get_symbol_address(SCRATCH_GLOBAL_NAME) as usize;
-        let sockets_ptr_address = jit.get_symbol_address(SOCKETS_GLOBAL_NAME) as usize;
-        let portals_ptr_address = jit.get_symbol_address(PORTALS_GLOBAL_NAME) as usize;
-        let pointers_ptr_address = jit.get_symbol_address(POINTERS_GLOBAL_NAME) as usize;
+        let initialized_ptr_address = get_address(INITIALIZED_GLOBAL_NAME);
+        let scratch_ptr_address = get_address(SCRATCH_GLOBAL_NAME);
+        let sockets_ptr_address = get_address(SOCKETS_GLOBAL_NAME);
+        let portals_ptr_address = get_address(PORTALS_GLOBAL_NAME);
+        let pointers_ptr_address = get_address(POINTERS_GLOBAL_NAME);
+        let buffers_ptr_address = get_address(BUFFERS_GLOBAL_NAME);
+        let portal_buffers_ptr_address = get_address(PORTAL_BUFFERS_GLOBAL_NAME);
+        let midi_queues_ptr_address = get_address(MIDI_QUEUES_GLOBAL_NAME);
 
         RuntimePointers {
             initialized_ptr: initialized_ptr_address as *mut c_void,
@@ -111,11 +222,289 @@ impl RuntimePointers {
             sockets_ptr: sockets_ptr_address as *mut c_void,
             portals_ptr: portals_ptr_address as *mut c_void,
             pointers_ptr: pointers_ptr_address as *mut c_void,
+            buffers_ptr: buffers_ptr_address as *mut c_void,
+            portal_buffers_ptr: portal_buffers_ptr_address as *mut c_void,
+            midi_queues_ptr: midi_queues_ptr_address as *mut c_void,
+            portal_count,
+            midi_portals,
+            midi_queue_size,
             construct: unsafe { mem::transmute(construct_address) },
             update: unsafe { mem::transmute(update_address) },
+            update_block: unsafe { mem::transmute(update_block_address) },
+            queue_midi: unsafe { mem::transmute(queue_midi_address) },
             destruct: unsafe { mem::transmute(destruct_address) },
         }
     }
+
+    unsafe fn get_portal_ptr(&self, portal_index: usize) -> *mut c_void {
+        if portal_index < self.portal_count {
+            let portals_array = self.portals_ptr as *mut *mut c_void;
+            *portals_array.add(portal_index)
+        } else {
+            ptr::null_mut()
+        }
+    }
+
+    /// Copies the MIDI queues of the portals that hold MIDI in both the old and new code, so
+    /// events queued for the old code are still delivered after a commit is published.
+    unsafe fn take_midi_queues(&self, old: &RuntimePointers) {
+        for (queue_index, portal_index) in self.midi_portals.iter().enumerate() {
+            let old_queue_index = match old.midi_portals.iter().position(|old| old == portal_index)
+            {
+                Some(index) => index,
+                None => continue,
+            };
+            ptr::copy_nonoverlapping(
+                (old.midi_queues_ptr as *const u8).add(old_queue_index * old.midi_queue_size),
+                (self.midi_queues_ptr as *mut u8).add(queue_index * self.midi_queue_size),
+                self.midi_queue_size,
+            );
+        }
+    }
+
+    unsafe fn get_portal_buffer_ptr(&self, portal_index: usize) -> *mut c_void {
+        if portal_index < self.portal_count {
+            let buffers_array = self.portal_buffers_ptr as *mut *mut c_void;
+            *buffers_array.add(portal_index)
+        } else {
+            ptr::null_mut()
+        }
+    }
+}
+
+/// The MIR and layouts of the objects making up the running code. The runtime's own are replaced
+/// when a commit is prepared, so these are kept to find the running state if another commit is
+/// prepared before that one is published.
+struct PublishedObjects {
+    surface_mirs: HashMap<SurfaceRef, Surface>,
+    surface_layouts: HashMap<SurfaceRef, data_analyzer::SurfaceLayout>,
+    block_mirs: HashMap<BlockRef, Block>,
+    block_layouts: HashMap<BlockRef, data_analyzer::BlockLayout>,
+}
+
+struct PublishedCache<'a> {
+    runtime: &'a Runtime,
+    objects: &'a PublishedObjects,
+}
+
+impl ObjectCache for PublishedCache<'_> {
+    fn context(&self) -> &Context {
+        &self.runtime.context
+    }
+
+    fn target(&self) -> &TargetProperties {
+        &self.runtime.target
+    }
+
+    fn surface_mir(&self, id: SurfaceRef) -> Option<&Surface> {
+        self.objects.surface_mirs.get(&id)
+    }
+
+    fn surface_layout(&self, id: SurfaceRef) -> Option<&data_analyzer::SurfaceLayout> {
+        self.objects.surface_layouts.get(&id)
+    }
+
+    fn block_mir(&self, id: BlockRef) -> Option<&Block> {
+        self.objects.block_mirs.get(&id)
+    }
+
+    fn block_layout(&self, id: BlockRef) -> Option<&data_analyzer::BlockLayout> {
+        self.objects.block_layouts.get(&id)
+    }
+}
+
+struct PendingCommit {
+    pointers: *mut RuntimePointers,
+    published_objects: PublishedObjects,
+
+    /// The memory the pending code's delay lines were claimed from. Each commit gets its own, so
+    /// the new code can be constructed while the old code keeps using its buffers.
+    arena: Vec<u8>,
+}
+
+/// Set as the pending commit while the audio thread publishes it. It's never a valid pointer.
+fn publishing_marker() -> *mut RuntimePointers {
+    ptr::NonNull::dangling().as_ptr()
+}
+
+/// The part of the runtime the audio thread uses. Everything here can be called while a commit is
+/// being prepared on another thread, and none of it allocates, locks or frees memory, so it's safe
+/// to use from a real-time thread.
+///
+/// Only one thread should run updates at a time, and publishing has to happen on that thread,
+/// between updates.
+pub struct AudioRuntime {
+    running: AtomicPtr<RuntimePointers>,
+    pending: AtomicPtr<RuntimePointers>,
+    samplerate_ptr: *mut c_void,
+    bpm_ptr: *mut c_void,
+    sample_rate: AtomicU64,
+    bpm: AtomicU64,
+}
+
+// The pointers are into the JIT's globals and the code's data, which live as long as the runtime
+// that owns this. Which thread is allowed to touch them is documented on each method.
+unsafe impl Send for AudioRuntime {}
+unsafe impl Sync for AudioRuntime {}
+
+impl AudioRuntime {
+    fn new(library_pointers: &LibraryPointers) -> Self {
+        let audio = AudioRuntime {
+            running: AtomicPtr::new(ptr::null_mut()),
+            pending: AtomicPtr::new(ptr::null_mut()),
+            samplerate_ptr: library_pointers.samplerate_ptr,
+            bpm_ptr: library_pointers.bpm_ptr,
+            sample_rate: AtomicU64::new(0),
+            bpm: AtomicU64::new(0),
+        };
+        audio.set_bpm(60.);
+        audio.set_sample_rate(44100.);
+        audio
+    }
+
+    /// Swaps the running code for the prepared commit, if there is one. Returns false if no
+    /// commit was pending. Events still in the MIDI queues are moved over to the new code for the
+    /// portals that hold MIDI in both. The old code is destructed and freed when the next commit
+    /// is prepared or the runtime reclaims it.
+    pub fn publish(&self) -> bool {
+        let pending = self.pending.load(Ordering::Acquire);
+        if pending.is_null()
+            || self
+                .pending
+                .compare_exchange(
+                    pending,
+                    publishing_marker(),
+                    Ordering::AcqRel,
+                    Ordering::Acquire,
+                )
+                .is_err()
+        {
+            return false;
+        }
+
+        // while the marker is set the runtime can't free the pending code or reclaim the old code
+        if let Some(running) = self.running_pointers() {
+            unsafe {
+                (*pending).take_midi_queues(running);
+            }
+        }
+        self.running.store(pending, Ordering::Release);
+        self.pending.store(ptr::null_mut(), Ordering::Release);
+        true
+    }
+
+    /// Takes the prepared commit back if it hasn't been published, waiting for the audio thread
+    /// to finish if it's publishing it. Returns null if there wasn't one.
+    fn take_pending(&self) -> *mut RuntimePointers {
+        loop {
+            let pending = self.pending.load(Ordering::Acquire);
+            if pending == publishing_marker() {
+                hint::spin_loop();
+                continue;
+            }
+            if self
+                .pending
+                .compare_exchange(
+                    pending,
+                    ptr::null_mut(),
+                    Ordering::AcqRel,
+                    Ordering::Acquire,
+                )
+                .is_ok()
+            {
+                return pending;
+            }
+        }
+    }
+
+    fn running_pointers(&self) -> Option<&RuntimePointers> {
+        unsafe { self.running.load(Ordering::Acquire).as_ref() }
+    }
+
+    /// Runs an update for one frame. Events due in the MIDI queues are pushed onto their portals
+    /// first, so like the events the host pushes they stay there until it clears them.
+    pub unsafe fn run_update(&self) {
+        if let Some(pointers) = self.running_pointers() {
+            (pointers.update)();
+        }
+    }
+
+    /// Runs an update for each frame in the portal buffers, up to `frames`. Returns the number of
+    /// frames that were run, which is less than `frames` if it's more than the buffers hold.
+    pub unsafe fn run_update_block(&self, frames: u32) -> u32 {
+        match self.running_pointers() {
+            Some(pointers) => (pointers.update_block)(frames),
+            None => 0,
+        }
+    }
+
+    /// Queues a MIDI event to be delivered `offset` frames from the next update, counting a frame
+    /// for each `run_update` and the frames of each `run_update_block`. Events are dropped if the
+    /// portal's queue is full.
+    ///
+    /// This isn't thread-safe: the queues are read and moved along by the updates, so it has to
+    /// be called on the thread that runs them, between updates, like `publish`.
+    pub unsafe fn queue_midi(
+        &self,
+        portal_index: usize,
+        offset: u32,
+        event: u8,
+        channel: u8,
+        note: u8,
+        param: u8,
+    ) {
+        if let Some(pointers) = self.running_pointers() {
+            (pointers.queue_midi)(portal_index as u32, offset, event, channel, note, param);
+        }
+    }
+
+    /// The value of a portal in the running code. This changes when a commit is published, and
+    /// is null if the portal doesn't exist in the running code.
+    pub unsafe fn get_portal_ptr(&self, portal_index: usize) -> *mut c_void {
+        match self.running_pointers() {
+            Some(pointers) => pointers.get_portal_ptr(portal_index),
+            None => ptr::null_mut(),
+        }
+    }
+
+    /// The buffer of a portal in the running code, see `get_portal_ptr`.
+    pub unsafe fn get_portal_buffer_ptr(&self, portal_index: usize) -> *mut c_void {
+        match self.running_pointers() {
+            Some(pointers) => pointers.get_portal_buffer_ptr(portal_index),
+            None => ptr::null_mut(),
+        }
+    }
+
+    fn set_vector(ptr: *mut c_void, value: f64) {
+        let vec_ptr = ptr as *mut (f64, f64);
+        unsafe {
+            (*vec_ptr).0 = value;
+            (*vec_ptr).1 = value;
+        }
+    }
+
+    /// Must be called from the thread that runs updates, since the running code reads it.
+    pub fn set_bpm(&self, bpm: f64) {
+        self.bpm.store(bpm.to_bits(), Ordering::Relaxed);
+        AudioRuntime::set_vector(self.bpm_ptr, bpm);
+    }
+
+    pub fn get_bpm(&self) -> f64 {
+        f64::from_bits(self.bpm.load(Ordering::Relaxed))
+    }
+
+    /// Must be called from the thread that runs updates, since the running code reads it. Delay
+    /// lines keep the buffers they were constructed with until the next commit, so delays longer
+    /// than they can hold at a higher rate are clamped until then.
+    pub fn set_sample_rate(&self, sample_rate: f64) {
+        self.sample_rate
+            .store(sample_rate.to_bits(), Ordering::Relaxed);
+        AudioRuntime::set_vector(self.samplerate_ptr, sample_rate);
+    }
+
+    pub fn get_sample_rate(&self) -> f64 {
+        f64::from_bits(self.sample_rate.load(Ordering::Relaxed))
+    }
 }
 
 pub struct Runtime {
@@ -132,10 +521,27 @@ pub struct Runtime {
     block_modules: HashMap<BlockRef, RuntimeModule>,
     graph: DependencyGraph,
     jit: Jit,
+
+    /// The memory the running code's delay lines were claimed from.
+    arena: Vec<u8>,
+
+    /// The arena of code that isn't running any more, which the next commit claims from instead
+    /// of allocating a new one.
+    spare_arena: Vec<u8>,
     library_pointers: LibraryPointers,
-    runtime_pointers: Option<RuntimePointers>,
-    bpm: f64,
-    sample_rate: f64,
+    audio: Arc<AudioRuntime>,
+
+    /// The code the audio thread runs, or ran until it published the pending commit.
+    running: *mut RuntimePointers,
+    pending: Option<PendingCommit>,
+
+    /// Modules replaced by the pending commit, which the running code can still call into. They're
+    /// freed once the commit has been published and reclaimed.
+    replaced_keys: Vec<JitKey>,
+    generation: u64,
+    surface_code_keys: HashMap<SurfaceRef, CacheKey>,
+    block_code_keys: HashMap<BlockRef, CacheKey>,
+    disk_cache: Option<DiskCache>,
 }
 
 impl Runtime {
@@ -150,13 +556,17 @@ impl Runtime {
         optimizer.optimize_module(&library_module);
         jit.deploy(&library_module);
         let library_pointers = LibraryPointers::new(&jit);
+        let audio = Arc::new(AudioRuntime::new(&library_pointers));
 
         Runtime {
             id_allocator: IncrementalIdAllocator::new(1),
             context,
             target,
             optimizer,
-            root: (Root::new(Vec::new()), RuntimeModule::new(root_module, None)),
+            root: (
+                Root::new(Vec::new()),
+                RuntimeModule::new(Some(root_module), None, None),
+            ),
             surface_mirs: HashMap::new(),
             surface_layouts: HashMap::new(),
             surface_modules: HashMap::new(),
@@ -165,10 +575,33 @@ impl Runtime {
             block_modules: HashMap::new(),
             graph: DependencyGraph::new(),
             jit,
+            arena: Vec::new(),
+            spare_arena: Vec::new(),
             library_pointers,
-            runtime_pointers: None,
-            bpm: 60.,
-            sample_rate: 44100.,
+            audio,
+            running: ptr::null_mut(),
+            pending: None,
+            replaced_keys: Vec::new(),
+            generation: 0,
+            surface_code_keys: HashMap::new(),
+            block_code_keys: HashMap::new(),
+            disk_cache: None,
+        }
+    }
+
+    /// The part of the runtime the audio thread uses. It stays valid for as long as the runtime
+    /// does.
+    pub fn audio(&self) -> &Arc<AudioRuntime> {
+        &self.audio
+    }
+
+    /// Points the library at an arena, with the first `used` bytes already claimed. Delay lines
+    /// only claim memory when they're constructed, so the running code doesn't notice this.
+    fn set_arena(library_pointers: &LibraryPointers, arena: &mut [u8], used: usize) {
+        unsafe {
+            *(library_pointers.arena_ptr as *mut *mut u8) = arena.as_mut_ptr();
+            *(library_pointers.arena_size_ptr as *mut u64) = arena.len() as u64;
+            *(library_pointers.arena_used_ptr as *mut u64) = used as u64;
         }
     }
 
@@ -177,6 +610,9 @@ impl Runtime {
         globals::build_globals(&module);
         runtime_lib::codegen_lib(&module, target);
         editor::build_convert_num_func(&module, &target, CONVERT_NUM_FUNC_NAME);
+        if target.backend == CodegenBackend::Cranelift {
+            cranelift::build_bridges(&module, target);
+        }
         module
     }
 
@@ -223,18 +659,21 @@ impl Runtime {
         Vec::from_iter(required_surfaces.into_iter())
     }
 
-    fn deploy_module(jit: &Jit, module: &mut RuntimeModule) {
-        // if the module already has a key, remove it
+    fn deploy_module(jit: &Jit, module: &mut RuntimeModule, replaced_keys: &mut Vec<JitKey>) {
+        // if the module already has a key, the old code is replaced once the commit is published
         if let Some(key) = module.key {
-            jit.remove(key);
+            replaced_keys.push(key);
         }
-        let key = jit.deploy(&module.module);
+        let key = match module.object.take() {
+            Some(object) => jit.deploy_object(&object),
+            None => jit.deploy(module.module.as_ref().unwrap()),
+        };
         module.key = Some(key);
     }
 
-    fn remove_module(jit: &Jit, module: &mut RuntimeModule) {
+    fn remove_module(module: &mut RuntimeModule, replaced_keys: &mut Vec<JitKey>) {
         if let Some(key) = module.key {
-            jit.remove(key);
+            replaced_keys.push(key);
             module.key = None;
         }
     }
@@ -244,7 +683,8 @@ impl Runtime {
     }
 
     fn optimize_surfaces(&mut self, surfaces: impl IntoIterator<Item = Surface>) -> Vec<Surface> {
-        mir_optimizer::prepare_surfaces(surfaces, &mut self.id_allocator, &self.target).collect()
+        mir_optimizer::prepare_surfaces(surfaces, &mut self.id_allocator, self.target.include_ui)
+            .collect()
     }
 
     fn patch_in_blocks(&mut self, blocks: Vec<Block>) {
@@ -316,79 +756,252 @@ impl Runtime {
         (new_block_ids, sorted_surfaces)
     }
 
-    fn codegen_blocks(&mut self, block_ids: &[BlockRef]) {
+    /// Sets the cache compiled surfaces and blocks are stored in, so they don't have to be
+    /// compiled again in later commits or runs.
+    pub fn set_disk_cache(&mut self, disk_cache: Option<DiskCache>) {
+        self.disk_cache = disk_cache;
+    }
+
+    /// Starts a key with the versions of everything that builds the code. The rest of the key is
+    /// written with derived `Hash` implementations, which can change between Rust versions, so
+    /// the version of the Rust compiler this was built with is included too.
+    fn begin_code_key(&self) -> CacheKey {
+        let mut key = CacheKey::new();
+        CODE_KEY_VERSION.hash(&mut key);
+        env!("CARGO_PKG_VERSION").hash(&mut key);
+        env!("MAXIM_RUSTC_VERSION").hash(&mut key);
+        util::get_llvm_version().hash(&mut key);
+        self.target.hash_codegen_options(&mut key);
+        key
+    }
+
+    fn get_block_code_key(&self, block: BlockRef) -> CacheKey {
+        let mut key = self.begin_code_key();
+        self.block_mirs[&block].hash(&mut key);
+        key
+    }
+
+    /// Surfaces are laid out and linked against the surfaces and blocks they use, so their keys
+    /// include the keys of those too. Dependencies must already have keys.
+    fn get_surface_code_key(&self, surface: SurfaceRef) -> CacheKey {
+        let mut key = self.begin_code_key();
+        self.surface_mirs[&surface].hash(&mut key);
+
+        let deps = self.graph.get_surface_deps(surface).unwrap();
+        for dep_surface in &deps.depends_on_surfaces {
+            key.write_key(&self.surface_code_keys[dep_surface]);
+        }
+        for dep_block in &deps.depends_on_blocks {
+            key.write_key(&self.block_code_keys[dep_block]);
+        }
+        key
+    }
+
+    fn load_cached_object(&mut self, code_key: &CacheKey, name: &str) -> Option<MemoryBuffer> {
+        let object = self.disk_cache.as_mut()?.load(code_key)?;
+        Some(MemoryBuffer::create_from_memory_range_copy(&object, name))
+    }
+
+    /// Compiles an optimized module to object code and stores it in the disk cache, if there is
+    /// one. The object is returned so the JIT doesn't have to compile the module again.
+    fn cache_module(&mut self, code_key: &CacheKey, module: &Module) -> Option<MemoryBuffer> {
+        let disk_cache = self.disk_cache.as_mut()?;
+        let object = self
+            .target
+            .machine
+            .write_to_memory_buffer(module, FileType::Object)
+            .ok()?;
+        disk_cache.store(code_key, object.as_slice());
+        Some(object)
+    }
+
+    /// Stores object code built by Cranelift in the disk cache, if there is one, and wraps it for
+    /// the JIT.
+    fn cache_object(&mut self, code_key: &CacheKey, name: &str, object: &[u8]) -> MemoryBuffer {
+        if let Some(disk_cache) = self.disk_cache.as_mut() {
+            disk_cache.store(code_key, object);
+        }
+        MemoryBuffer::create_from_memory_range_copy(object, name)
+    }
+
+    /// Builds modules for the blocks whose code has changed, and returns which ones they were.
+    fn codegen_blocks(&mut self, block_ids: &[BlockRef]) -> Vec<BlockRef> {
+        let mut built_ids = Vec::new();
         for &block_id in block_ids {
-            let block = &self.block_mirs[&block_id];
+            let code_key = self.get_block_code_key(block_id);
+            let module_id = self
+                .block_modules
+                .get(&block_id)
+                .and_then(|module| module.key);
+
+            // if nothing that goes into the code has changed, the loaded code can keep being used
+            let old_code_key = self.block_code_keys.insert(block_id, code_key.clone());
+            if old_code_key.as_ref() == Some(&code_key) && module_id.is_some() {
+                continue;
+            }
+            built_ids.push(block_id);
 
-            let module_id = if let Entry::Occupied(old_module) = self.block_modules.entry(block_id)
-            {
-                old_module.get().key
-            } else {
-                None
+            let block = &self.block_mirs[&block_id];
+            let module_name = format!("block.{}.{}", block.id.id, block.id.debug_name);
+            let module = match self.load_cached_object(&code_key, &module_name) {
+                Some(object) => RuntimeModule::new(None, Some(object), module_id),
+                None if self.target.backend == CodegenBackend::Cranelift => {
+                    let block = &self.block_mirs[&block_id];
+                    let object = cranelift::build_block_object(self, block, &module_name);
+                    let object = self.cache_object(&code_key, &module_name, &object);
+                    RuntimeModule::new(None, Some(object), module_id)
+                }
+                None => {
+                    let block = &self.block_mirs[&block_id];
+                    let module = self.target.create_module(&self.context, &module_name);
+                    block::build_funcs(&module, self, block);
+                    self.optimizer.optimize_module(&module);
+                    let object = self.cache_module(&code_key, &module);
+                    RuntimeModule::new(Some(module), object, module_id)
+                }
             };
-
-            let module = RuntimeModule::new(
-                self.target.create_module(
-                    &self.context,
-                    &format!("block.{}.{}", block.id.id, block.id.debug_name),
-                ),
-                module_id,
-            );
-            block::build_funcs(&module.module, self, block);
-            self.optimizer.optimize_module(&module.module);
             self.block_modules.insert(block_id, module);
         }
+        built_ids
     }
 
-    fn codegen_surfaces(&mut self, surface_ids: &[SurfaceRef]) {
+    /// Builds modules for the surfaces whose code has changed, and returns which ones they were.
+    /// Surfaces must be given in dependency order, so their dependencies' keys are up to date.
+    fn codegen_surfaces(&mut self, surface_ids: &[SurfaceRef]) -> Vec<SurfaceRef> {
+        let mut built_ids = Vec::new();
         for &surface_id in surface_ids {
-            let surface = &self.surface_mirs[&surface_id];
-
-            let module_id =
-                if let Entry::Occupied(old_module) = self.surface_modules.entry(surface_id) {
-                    old_module.get().key
-                } else {
-                    None
-                };
+            let code_key = self.get_surface_code_key(surface_id);
+            let module_id = self
+                .surface_modules
+                .get(&surface_id)
+                .and_then(|module| module.key);
+
+            let old_code_key = self.surface_code_keys.insert(surface_id, code_key.clone());
+            if old_code_key.as_ref() == Some(&code_key) && module_id.is_some() {
+                continue;
+            }
+            built_ids.push(surface_id);
 
-            let module = RuntimeModule::new(
-                self.target.create_module(
-                    &self.context,
-                    &format!("surface.{}.{}", surface.id.id, surface.id.debug_name),
-                ),
-                module_id,
-            );
-            surface::build_funcs(&module.module, self, surface);
-            self.optimizer.optimize_module(&module.module);
+            let surface = &self.surface_mirs[&surface_id];
+            let module_name = format!("surface.{}.{}", surface.id.id, surface.id.debug_name);
+            let use_cranelift = self.target.backend == CodegenBackend::Cranelift
+                && cranelift::supports_surface(self, surface);
+            let module = match self.load_cached_object(&code_key, &module_name) {
+                Some(object) => RuntimeModule::new(None, Some(object), module_id),
+                None if use_cranelift => {
+                    let surface = &self.surface_mirs[&surface_id];
+                    let object = cranelift::build_surface_object(self, surface, &module_name);
+                    let object = self.cache_object(&code_key, &module_name, &object);
+                    RuntimeModule::new(None, Some(object), module_id)
+                }
+                None => {
+                    let surface = &self.surface_mirs[&surface_id];
+                    let module = self.target.create_module(&self.context, &module_name);
+                    surface::build_funcs(&module, self, surface);
+                    self.optimizer.optimize_module(&module);
+                    let object = self.cache_module(&code_key, &module);
+                    RuntimeModule::new(Some(module), object, module_id)
+                }
+            };
             self.surface_modules.insert(surface_id, module);
         }
+        built_ids
     }
 
     fn codegen_root(&self, root: &Root) -> Module {
         let module = self.target.create_module(&self.context, "root");
+        let symbol = |name: &str| get_root_symbol(name, self.generation);
         let initialized_global =
-            root::build_initialized_global(&module, self, 0, INITIALIZED_GLOBAL_NAME);
-        let scratch_global = root::build_scratch_global(&module, self, 0, SCRATCH_GLOBAL_NAME);
-        let sockets_global =
-            root::build_sockets_global(&module, root, SOCKETS_GLOBAL_NAME, PORTALS_GLOBAL_NAME);
+            root::build_initialized_global(&module, self, 0, &symbol(INITIALIZED_GLOBAL_NAME));
+        let scratch_global =
+            root::build_scratch_global(&module, self, 0, &symbol(SCRATCH_GLOBAL_NAME));
+        let sockets_global = root::build_sockets_global(
+            &module,
+            self,
+            root,
+            &symbol(SOCKETS_GLOBAL_NAME),
+            &symbol(PORTALS_GLOBAL_NAME),
+        );
         let pointers_global = root::build_pointers_global(
             &module,
             self,
             0,
-            POINTERS_GLOBAL_NAME,
+            &symbol(POINTERS_GLOBAL_NAME),
             initialized_global.as_pointer_value(),
             scratch_global.as_pointer_value(),
             sockets_global.sockets.as_pointer_value(),
         );
-        root::build_funcs(
+        let midi_queues_global =
+            root::build_midi_queues_global(&module, root, &symbol(MIDI_QUEUES_GLOBAL_NAME));
+
+        // The root surface's update is built into this module as well, so it can be inlined into
+        // the update loops and the loads of controls that don't change per-sample hoisted out of
+        // the block update. Cranelift builds are about compile time, so they keep calling it.
+        if self.target.backend != CodegenBackend::Cranelift {
+            surface::build_lifecycle_func(
+                &module,
+                self,
+                &self.surface_mirs[&0],
+                LifecycleFunc::Update,
+            );
+            let update_name = surface::get_lifecycle_func_name(self, 0, LifecycleFunc::Update);
+            module
+                .get_function(&update_name)
+                .unwrap()
+                .set_linkage(Linkage::PrivateLinkage);
+        }
+
+        root::build_lifecycle_func(
+            &module,
+            self,
+            0,
+            &symbol(CONSTRUCT_FUNC_NAME),
+            LifecycleFunc::Construct,
+            pointers_global.as_pointer_value(),
+        );
+        root::build_queued_update_func(
+            &module,
+            self,
+            0,
+            &symbol(UPDATE_FUNC_NAME),
+            pointers_global.as_pointer_value(),
+            sockets_global.sockets.as_pointer_value(),
+            midi_queues_global.as_pointer_value(),
+            root,
+        );
+        root::build_lifecycle_func(
             &module,
             self,
             0,
-            CONSTRUCT_FUNC_NAME,
-            UPDATE_FUNC_NAME,
-            DESTRUCT_FUNC_NAME,
+            &symbol(DESTRUCT_FUNC_NAME),
+            LifecycleFunc::Destruct,
             pointers_global.as_pointer_value(),
         );
+        let buffers_global = root::build_portal_buffers_global(
+            &module,
+            self,
+            root,
+            &symbol(BUFFERS_GLOBAL_NAME),
+            &symbol(PORTAL_BUFFERS_GLOBAL_NAME),
+        );
+        root::build_block_update_func(
+            &module,
+            self,
+            0,
+            &symbol(UPDATE_BLOCK_FUNC_NAME),
+            pointers_global.as_pointer_value(),
+            sockets_global.sockets.as_pointer_value(),
+            buffers_global.buffers.as_pointer_value(),
+            midi_queues_global.as_pointer_value(),
+            root,
+        );
+        root::build_midi_queue_func(
+            &module,
+            self,
+            &symbol(QUEUE_MIDI_FUNC_NAME),
+            root,
+            midi_queues_global.as_pointer_value(),
+        );
         self.optimizer.optimize_module(&module);
         module
     }
@@ -397,40 +1010,76 @@ impl Runtime {
         &mut self,
         new_block_ids: &[BlockRef],
         affected_surfaces: &[SurfaceRef],
-    ) {
-        self.codegen_blocks(new_block_ids);
-        self.codegen_surfaces(affected_surfaces);
+    ) -> (Vec<BlockRef>, Vec<SurfaceRef>) {
+        let built_blocks = self.codegen_blocks(new_block_ids);
+        let built_surfaces = self.codegen_surfaces(affected_surfaces);
 
-        self.root.1.module = self.codegen_root(&self.root.0);
+        self.root.1.module = Some(self.codegen_root(&self.root.0));
+        (built_blocks, built_surfaces)
     }
 
     fn deploy_transaction(&mut self, block_ids: &[BlockRef], affected_surfaces: &[SurfaceRef]) {
         for block in block_ids {
-            Runtime::deploy_module(&self.jit, self.block_modules.get_mut(block).unwrap());
+            Runtime::deploy_module(
+                &self.jit,
+                self.block_modules.get_mut(block).unwrap(),
+                &mut self.replaced_keys,
+            );
         }
         for surface in affected_surfaces {
-            Runtime::deploy_module(&self.jit, self.surface_modules.get_mut(surface).unwrap());
+            Runtime::deploy_module(
+                &self.jit,
+                self.surface_modules.get_mut(surface).unwrap(),
+                &mut self.replaced_keys,
+            );
         }
 
-        Runtime::deploy_module(&self.jit, &mut self.root.1);
-        self.runtime_pointers = Some(RuntimePointers::new(&self.jit));
+        Runtime::deploy_module(&self.jit, &mut self.root.1, &mut self.replaced_keys);
     }
 
-    pub fn commit(&mut self, transaction: Transaction) {
+    /// Compiles a transaction and loads it alongside the running code, then hands it to the audio
+    /// thread to publish. Returns false if there was nothing to compile.
+    ///
+    /// Everything that isn't real-time safe happens here, so this can run on a worker thread while
+    /// the audio thread keeps running updates, but not at the same time as anything else that
+    /// uses the runtime. If the last commit hasn't been published yet it's replaced by this one.
+    ///
+    /// The state of the running nodes is carried over while they keep running, so it's whatever
+    /// it was at some point during the copy. Anything that happens between this and the commit
+    /// being published is lost.
+    pub fn prepare(&mut self, transaction: Transaction) -> bool {
         // if the transaction is empty, early exit
         if transaction.surfaces.is_empty()
             && transaction.blocks.is_empty()
             && transaction.root.is_none()
         {
-            return;
+            return false;
         }
 
-        // run destructors on old data before beginning
-        if let Some(ref pointers) = self.runtime_pointers {
-            unsafe {
-                (pointers.destruct)();
+        // The running state has to be found with the layouts of the running code. If the last
+        // commit was never published, the running code is still the code from before it.
+        let unpublished = self.audio.take_pending();
+        let published_objects = match self.pending.take() {
+            Some(pending) if !unpublished.is_null() => {
+                unsafe {
+                    ((*pending.pointers).destruct)();
+                    Box::from_raw(pending.pointers);
+                }
+                self.spare_arena = pending.arena;
+                pending.published_objects
             }
-        }
+            pending => {
+                self.pending = pending;
+                self.reclaim();
+                PublishedObjects {
+                    surface_mirs: self.surface_mirs.clone(),
+                    surface_layouts: self.surface_layouts.clone(),
+                    block_mirs: self.block_mirs.clone(),
+                    block_layouts: self.block_layouts.clone(),
+                }
+            }
+        };
+        self.generation += 1;
 
         let patch_start = Instant::now();
         let (new_block_ids, affected_surfaces) = self.patch_transaction(transaction);
@@ -440,29 +1089,110 @@ impl Runtime {
         );
 
         let codegen_start = Instant::now();
-        self.codegen_transaction(&new_block_ids, &affected_surfaces);
+        let (built_blocks, built_surfaces) =
+            self.codegen_transaction(&new_block_ids, &affected_surfaces);
         println!(
             "Codegen took {}s",
             precise_duration_seconds(&codegen_start.elapsed())
         );
 
         let deploy_start = Instant::now();
-        self.deploy_transaction(&new_block_ids, &affected_surfaces);
+        self.deploy_transaction(&built_blocks, &built_surfaces);
         println!(
             "Deploy took {}s",
             precise_duration_seconds(&deploy_start.elapsed())
         );
 
-        // reset the BPM and sample rate
-        Runtime::set_vector(self.library_pointers.bpm_ptr, self.bpm);
-        Runtime::set_vector(self.library_pointers.samplerate_ptr, self.sample_rate);
+        // The new code has its own data and arena, so it can be constructed while the old code
+        // runs. Construction only uses the arena and construct sample rate globals, which the
+        // running code doesn't touch.
+        let sample_rate = self.audio.get_sample_rate();
+        let arena_size = data_analyzer::get_arena_size(
+            &self.target,
+            sample_rate,
+            0,
+            &self.surface_mirs,
+            &self.block_mirs,
+        );
+        let mut arena = mem::replace(&mut self.spare_arena, Vec::new());
+        arena.clear();
+        arena.resize(arena_size as usize, 0);
+        Runtime::set_arena(&self.library_pointers, &mut arena, 0);
+        AudioRuntime::set_vector(self.library_pointers.construct_samplerate_ptr, sample_rate);
+        let midi_queue_size = self
+            .target
+            .machine
+            .get_data()
+            .get_abi_size(&MidiQueueValue::get_type(&self.context));
+        let pointers = Box::into_raw(Box::new(RuntimePointers::new(
+            &self.jit,
+            self.generation,
+            self.root.0.sockets.len(),
+            root::get_midi_portals(&self.root.0),
+            midi_queue_size as usize,
+        )));
+        unsafe {
+            ((*pointers).construct)();
+        }
+
+        let published_cache = PublishedCache {
+            runtime: self,
+            objects: &published_objects,
+        };
+        let running_state_ptr = match unsafe { self.running.as_ref() } {
+            Some(running) => running.pointers_ptr,
+            None => ptr::null_mut(),
+        };
+        let state = StateSnapshot::capture(&published_cache, running_state_ptr);
+        state.restore(self, unsafe { (*pointers).pointers_ptr });
+
+        self.pending = Some(PendingCommit {
+            pointers,
+            published_objects,
+            arena,
+        });
+        self.audio.pending.store(pointers, Ordering::Release);
+        true
+    }
 
-        if let Some(ref pointers) = self.runtime_pointers {
-            // run the new constructor
+    /// Frees the code that stopped running when the audio thread published the last prepared
+    /// commit. Returns false if it hasn't been published yet. This happens when the next commit
+    /// is prepared, but can be done sooner to free memory and start reporting faults again.
+    pub fn reclaim(&mut self) -> bool {
+        let pending = match self.pending.take() {
+            Some(pending) => pending,
+            None => return true,
+        };
+        if !self.audio.pending.load(Ordering::Acquire).is_null() {
+            self.pending = Some(pending);
+            return false;
+        }
+
+        // the audio thread only runs the new code now
+        let old_pointers = mem::replace(&mut self.running, pending.pointers);
+        if !old_pointers.is_null() {
             unsafe {
-                (pointers.construct)();
+                ((*old_pointers).destruct)();
+                Box::from_raw(old_pointers);
             }
         }
+        for key in self.replaced_keys.drain(..) {
+            self.jit.remove(key);
+        }
+        self.spare_arena = mem::replace(&mut self.arena, pending.arena);
+
+        // node indices can change, so faults from before the commit wouldn't make sense after it
+        self.clear_faults();
+        true
+    }
+
+    /// Prepares, publishes and reclaims a transaction in one go, for hosts that don't run updates
+    /// on another thread.
+    pub fn commit(&mut self, transaction: Transaction) {
+        if self.prepare(transaction) {
+            self.audio.publish();
+            self.reclaim();
+        }
     }
 
     /// Remove any objects that aren't referenced by others (and aren't the root).
@@ -472,7 +1202,9 @@ impl Runtime {
         let surface_layouts = &mut self.surface_layouts;
         let block_mirs = &mut self.block_mirs;
         let block_layouts = &mut self.block_layouts;
-        let jit = &self.jit;
+        let surface_code_keys = &mut self.surface_code_keys;
+        let block_code_keys = &mut self.block_code_keys;
+        let replaced_keys = &mut self.replaced_keys;
 
         // we can now remove any objects that don't exist in the graph
         self.surface_modules.retain(|&key, module| {
@@ -481,7 +1213,8 @@ impl Runtime {
             } else {
                 surface_mirs.remove(&key);
                 surface_layouts.remove(&key);
-                Runtime::remove_module(jit, module);
+                surface_code_keys.remove(&key);
+                Runtime::remove_module(module, replaced_keys);
                 false
             }
         });
@@ -491,65 +1224,241 @@ impl Runtime {
             } else {
                 block_mirs.remove(&key);
                 block_layouts.remove(&key);
-                Runtime::remove_module(jit, module);
+                block_code_keys.remove(&key);
+                Runtime::remove_module(module, replaced_keys);
                 false
             }
         });
     }
 
-    pub unsafe fn run_update(&self) {
-        if let Some(ref pointers) = self.runtime_pointers {
-            (pointers.update)();
+    /// The code the runtime's layouts describe: the pending commit if there is one, otherwise the
+    /// running code.
+    fn current_pointers(&self) -> Option<&RuntimePointers> {
+        match &self.pending {
+            Some(pending) => unsafe { pending.pointers.as_ref() },
+            None => unsafe { self.running.as_ref() },
         }
     }
 
+    /// The data of the root surface. After a commit is prepared this points to the pending code,
+    /// so values written to it are in place once the commit is published.
     pub fn get_root_ptr(&self) -> *mut c_void {
-        if let Some(ref pointers) = self.runtime_pointers {
+        if let Some(pointers) = self.current_pointers() {
             pointers.pointers_ptr
         } else {
             ptr::null_mut()
         }
     }
 
-    pub unsafe fn get_portal_ptr(&self, portal_index: usize) -> *mut c_void {
-        if let Some(ref pointers) = self.runtime_pointers {
-            let portals_array = pointers.portals_ptr as *mut *mut c_void;
-            *portals_array.add(portal_index)
-        } else {
-            ptr::null_mut()
+    /// The value of a portal in the pending commit, or the running code if there isn't one. Hosts
+    /// that publish on the audio thread look portals up with this before publishing, so they can
+    /// switch to the new pointers at the same time as the code.
+    pub fn get_portal_ptr(&self, portal_index: usize) -> *mut c_void {
+        match self.current_pointers() {
+            Some(pointers) => unsafe { pointers.get_portal_ptr(portal_index) },
+            None => ptr::null_mut(),
         }
     }
 
-    fn set_vector(ptr: *mut c_void, value: f64) {
-        let vec_ptr = ptr as *mut (f64, f64);
-        unsafe {
-            (*vec_ptr).0 = value;
-            (*vec_ptr).1 = value;
+    /// The buffer of a portal in the pending commit, see `get_portal_ptr`.
+    pub fn get_portal_buffer_ptr(&self, portal_index: usize) -> *mut c_void {
+        match self.current_pointers() {
+            Some(pointers) => unsafe { pointers.get_portal_buffer_ptr(portal_index) },
+            None => ptr::null_mut(),
         }
     }
 
-    pub fn set_bpm(&mut self, bpm: f64) {
-        self.bpm = bpm;
-        Runtime::set_vector(self.library_pointers.bpm_ptr, bpm);
+    /// The types of the globals holding the runtime's state, in the order they're stored in a
+    /// snapshot. Empty if nothing has been committed.
+    fn get_state_types(&self) -> Vec<StructType> {
+        if self.current_pointers().is_none() {
+            return Vec::new();
+        }
+
+        vec![
+            root::get_scratch_type(&self.context, self, 0),
+            root::get_sockets_type(&self.context, self, &self.root.0),
+            root::get_portal_buffers_type(&self.context, self, &self.root.0),
+        ]
     }
 
-    pub fn get_bpm(&self) -> f64 {
-        self.bpm
+    /// A fingerprint of the layout of the runtime's state. Snapshots can only be restored into a
+    /// runtime with the same fingerprint as the one they were taken from.
+    pub fn layout_fingerprint(&self) -> u64 {
+        data_analyzer::get_layout_fingerprint(&self.target, &self.get_state_types())
+    }
+
+    fn get_state_ptrs(&self) -> Vec<*mut c_void> {
+        match self.current_pointers() {
+            Some(pointers) => vec![
+                pointers.scratch_ptr,
+                pointers.sockets_ptr,
+                pointers.buffers_ptr,
+            ],
+            None => Vec::new(),
+        }
+    }
+
+    /// Copies the state of every node out of the runtime. Must not happen during an update, or
+    /// while a commit is waiting to be published.
+    pub fn snapshot(&self) -> RuntimeSnapshot {
+        let target_data = self.target.machine.get_data();
+        let mut sections = self
+            .get_state_types()
+            .into_iter()
+            .zip(self.get_state_ptrs())
+            .map(|(state_type, state_ptr)| {
+                let size = target_data.get_abi_size(&state_type) as usize;
+                if size == 0 {
+                    Vec::new()
+                } else {
+                    unsafe { slice::from_raw_parts(state_ptr as *const u8, size) }.to_vec()
+                }
+            });
+        let mut next_section = || sections.next().unwrap_or_else(Vec::new);
+
+        let arena_used = unsafe { *(self.library_pointers.arena_used_ptr as *const u64) };
+        RuntimeSnapshot {
+            fingerprint: self.layout_fingerprint(),
+            arena_address: self.arena.as_ptr() as u64,
+            scratch: next_section(),
+            sockets: next_section(),
+            portal_buffers: next_section(),
+            arena: self.arena[..arena_used as usize].to_vec(),
+        }
+    }
+
+    /// Replaces the state of every node with the state in a snapshot. Fails without changing
+    /// anything if the snapshot was taken from a runtime with a different layout, or a commit is
+    /// waiting to be published. Must not happen while the audio thread is running updates.
+    pub fn restore(&mut self, snapshot: &RuntimeSnapshot) -> Result<(), ()> {
+        if !self.reclaim() {
+            return Err(());
+        }
+
+        let target_data = self.target.machine.get_data();
+        let state_types = self.get_state_types();
+        let sections = [
+            &snapshot.scratch,
+            &snapshot.sockets,
+            &snapshot.portal_buffers,
+        ];
+        let sizes_match = state_types
+            .iter()
+            .zip(sections.iter())
+            .all(|(state_type, section)| {
+                target_data.get_abi_size(state_type) == section.len() as u64
+            });
+        if snapshot.fingerprint != self.layout_fingerprint()
+            || !sizes_match
+            || snapshot.arena.len() > self.arena.len()
+        {
+            return Err(());
+        }
+
+        if let Some(pointers) = self.current_pointers() {
+            unsafe {
+                (pointers.destruct)();
+            }
+        }
+        self.clear_faults();
+
+        for (state_ptr, section) in self.get_state_ptrs().into_iter().zip(sections.iter()) {
+            // empty globals don't have an address
+            if section.is_empty() {
+                continue;
+            }
+            unsafe {
+                ptr::copy_nonoverlapping(section.as_ptr(), state_ptr as *mut u8, section.len());
+            }
+        }
+        self.arena[..snapshot.arena.len()].copy_from_slice(&snapshot.arena);
+        Runtime::set_arena(
+            &self.library_pointers,
+            &mut self.arena,
+            snapshot.arena.len(),
+        );
+
+        // delay buffers point into the arena the snapshot was taken from
+        state_migration::rebase_arena_buffers(
+            self,
+            snapshot.arena_address as usize,
+            self.arena.as_ptr() as usize,
+        );
+        Ok(())
     }
 
-    pub fn set_sample_rate(&mut self, sample_rate: f64) {
-        self.sample_rate = sample_rate;
-        Runtime::set_vector(self.library_pointers.samplerate_ptr, sample_rate);
+    pub fn get_bpm(&self) -> f64 {
+        self.audio.get_bpm()
     }
 
     pub fn get_sample_rate(&self) -> f64 {
-        self.sample_rate
+        self.audio.get_sample_rate()
     }
 
     pub fn get_profile_times_ptr(&self) -> *mut u64 {
         self.library_pointers.profile_times_ptr as *mut u64
     }
 
+    /// Returns the nodes that have output a non-finite value since the faults were last cleared,
+    /// in the order they first did. Only nodes in the editor are returned, faults in nodes the
+    /// editor doesn't know about are skipped.
+    pub fn get_faults(&self) -> Vec<Fault> {
+        let fault_count = unsafe { *(self.library_pointers.fault_count_ptr as *const u32) };
+        let entries = self.library_pointers.faults_ptr as *const FaultEntry;
+        (0..fault_count.min(globals::MAX_FAULTS))
+            .map(|index| unsafe { *entries.offset(index as isize) })
+            .filter_map(|entry| self.map_to_external_node(entry.surface, entry.node as usize))
+            .map(|(surface, node)| Fault { surface, node })
+            .collect()
+    }
+
+    /// Empties the fault table. Nodes that are still outputting non-finite values will be
+    /// recorded again.
+    pub fn clear_faults(&mut self) {
+        unsafe {
+            *(self.library_pointers.fault_count_ptr as *mut u32) = 0;
+            *(self.library_pointers.fault_generation_ptr as *mut u32) += 1;
+        }
+    }
+
+    /// Maps a node in the MIR back to the surface and node it came from in the editor, if there is
+    /// one.
+    pub fn map_to_external_node(
+        &self,
+        surface: SurfaceRef,
+        node: usize,
+    ) -> Option<(SurfaceRef, usize)> {
+        // if the surface was created by the optimizer, the node was moved into it from a surface
+        // in the editor
+        for (&parent_id, parent) in &self.surface_mirs {
+            for (parent_node_index, parent_node) in parent.nodes.iter().enumerate() {
+                let is_parent = match parent_node.data {
+                    NodeData::Group(subsurface) => subsurface == surface,
+                    NodeData::ExtractGroup {
+                        surface: subsurface,
+                        ..
+                    } => subsurface == surface,
+                    _ => false,
+                };
+                if !is_parent {
+                    continue;
+                }
+
+                let internal = InternalNodeRef::Surface(parent_node_index, node);
+                if let Some(external) = parent.source_map.find_external(internal) {
+                    return Some((parent_id, external));
+                }
+            }
+        }
+
+        let surface_mir = self.surface_mir(surface)?;
+        surface_mir
+            .source_map
+            .find_external(InternalNodeRef::Direct(node))
+            .map(|external| (surface, external))
+    }
+
     pub fn is_node_extracted(&self, surface: SurfaceRef, node: usize) -> bool {
         let surface_mir = self.surface_mir(surface).unwrap();
         let node_inner = surface_mir.source_map.map_to_internal(node);
@@ -561,6 +1470,24 @@ impl Runtime {
         }
     }
 
+    pub fn is_socket_delayed(&self, surface: SurfaceRef, node: usize, socket: usize) -> bool {
+        let surface_mir = self.surface_mir(surface).unwrap();
+
+        match surface_mir.source_map.map_to_internal(node) {
+            InternalNodeRef::Direct(node) => surface_mir.source_map.is_socket_delayed(node, socket),
+            InternalNodeRef::Surface(surface_node, node) => {
+                match surface_mir.nodes[surface_node].data {
+                    NodeData::Group(subsurface) => self.is_socket_delayed(subsurface, node, socket),
+                    NodeData::ExtractGroup {
+                        surface: subsurface,
+                        ..
+                    } => self.is_socket_delayed(subsurface, node, socket),
+                    _ => false,
+                }
+            }
+        }
+    }
+
     pub unsafe fn convert_num(&self, result: *mut c_void, target_form: i8, num: *const c_void) {
         (self.library_pointers.convert_num)(result, target_form, num)
     }
@@ -580,13 +1507,16 @@ impl Runtime {
     }
 
     pub fn print_modules(&self) {
-        for module in self.block_modules.values() {
-            module.module.print_to_stderr();
-        }
-        for module in self.surface_modules.values() {
-            module.module.print_to_stderr();
+        let modules = self
+            .block_modules
+            .values()
+            .chain(self.surface_modules.values())
+            .chain(iter::once(&self.root.1));
+        for module in modules {
+            if let Some(ref module) = module.module {
+                module.print_to_stderr();
+            }
         }
-        self.root.1.module.print_to_stderr();
     }
 }
 
@@ -614,6 +1544,20 @@ impl ObjectCache for Runtime {
     fn block_layout(&self, id: BlockRef) -> Option<&data_analyzer::BlockLayout> {
         self.block_layouts.get(&id)
     }
+
+    fn surface_code_key(&self, id: SurfaceRef) -> u64 {
+        self.surface_code_keys
+            .get(&id)
+            .map(|key| key.finish())
+            .unwrap_or(0)
+    }
+
+    fn block_code_key(&self, id: BlockRef) -> u64 {
+        self.block_code_keys
+            .get(&id)
+            .map(|key| key.finish())
+            .unwrap_or(0)
+    }
 }
 
 impl IdAllocator for Runtime {
@@ -624,9 +1568,17 @@ impl IdAllocator for Runtime {
 
 impl Drop for Runtime {
     fn drop(&mut self) {
-        if let Some(ref pointers) = self.runtime_pointers {
-            unsafe {
-                (pointers.destruct)();
+        // the audio thread must have stopped by now, so whatever it was running can be freed
+        self.audio.running.store(ptr::null_mut(), Ordering::Release);
+        self.audio.pending.store(ptr::null_mut(), Ordering::Release);
+
+        let pending_pointers = self.pending.take().map(|pending| pending.pointers);
+        for &pointers in pending_pointers.iter().chain(iter::once(&self.running)) {
+            if !pointers.is_null() {
+                let pointers = unsafe { Box::from_raw(pointers) };
+                unsafe {
+                    (pointers.destruct)();
+                }
             }
         }
     }
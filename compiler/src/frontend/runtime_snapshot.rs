@@ -0,0 +1,137 @@
+use std::convert::TryInto;
+
+/// Identifies serialized snapshots, followed by the format version.
+const SNAPSHOT_MAGIC: &[u8; 4] = b"MXSS";
+const SNAPSHOT_VERSION: u32 = 1;
+
+/// The DSP state of a runtime at one point in time: the contents of the scratch, sockets and
+/// portal buffer globals, and the part of the arena that's in use. Can be restored into any
+/// runtime with the same layout fingerprint.
+#[derive(Debug, Clone)]
+pub struct RuntimeSnapshot {
+    pub fingerprint: u64,
+
+    /// The address of the arena the snapshot was taken from, so buffer pointers into it can be
+    /// moved to the arena it's restored into.
+    pub arena_address: u64,
+    pub scratch: Vec<u8>,
+    pub sockets: Vec<u8>,
+    pub portal_buffers: Vec<u8>,
+    pub arena: Vec<u8>,
+}
+
+fn write_section(bytes: &mut Vec<u8>, section: &[u8]) {
+    bytes.extend_from_slice(&(section.len() as u64).to_le_bytes());
+    bytes.extend_from_slice(section);
+}
+
+fn read_u64(bytes: &mut &[u8]) -> Option<u64> {
+    if bytes.len() < 8 {
+        return None;
+    }
+    let (value_bytes, rest) = bytes.split_at(8);
+    *bytes = rest;
+    Some(u64::from_le_bytes(value_bytes.try_into().unwrap()))
+}
+
+fn read_section(bytes: &mut &[u8]) -> Option<Vec<u8>> {
+    let len = read_u64(bytes)? as usize;
+    if bytes.len() < len {
+        return None;
+    }
+    let (section, rest) = bytes.split_at(len);
+    *bytes = rest;
+    Some(section.to_vec())
+}
+
+impl RuntimeSnapshot {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(SNAPSHOT_MAGIC);
+        bytes.extend_from_slice(&SNAPSHOT_VERSION.to_le_bytes());
+        bytes.extend_from_slice(&self.fingerprint.to_le_bytes());
+        bytes.extend_from_slice(&self.arena_address.to_le_bytes());
+        write_section(&mut bytes, &self.scratch);
+        write_section(&mut bytes, &self.sockets);
+        write_section(&mut bytes, &self.portal_buffers);
+        write_section(&mut bytes, &self.arena);
+        bytes
+    }
+
+    /// Reads a snapshot written by `to_bytes`, or returns nothing if the data isn't a snapshot.
+    pub fn from_bytes(mut bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < 8
+            || &bytes[..4] != SNAPSHOT_MAGIC
+            || bytes[4..8] != SNAPSHOT_VERSION.to_le_bytes()
+        {
+            return None;
+        }
+        bytes = &bytes[8..];
+
+        let snapshot = RuntimeSnapshot {
+            fingerprint: read_u64(&mut bytes)?,
+            arena_address: read_u64(&mut bytes)?,
+            scratch: read_section(&mut bytes)?,
+            sockets: read_section(&mut bytes)?,
+            portal_buffers: read_section(&mut bytes)?,
+            arena: read_section(&mut bytes)?,
+        };
+        if bytes.is_empty() {
+            Some(snapshot)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build_snapshot() -> RuntimeSnapshot {
+        RuntimeSnapshot {
+            fingerprint: 0x0123_4567_89ab_cdef,
+            arena_address: 0x7f00_0000_1000,
+            scratch: vec![1, 2, 3],
+            sockets: Vec::new(),
+            portal_buffers: vec![4; 17],
+            arena: vec![5, 6],
+        }
+    }
+
+    #[test]
+    fn snapshot_round_trips_through_bytes() {
+        let snapshot = build_snapshot();
+        let read = RuntimeSnapshot::from_bytes(&snapshot.to_bytes()).unwrap();
+
+        assert_eq!(read.fingerprint, snapshot.fingerprint);
+        assert_eq!(read.arena_address, snapshot.arena_address);
+        assert_eq!(read.scratch, snapshot.scratch);
+        assert_eq!(read.sockets, snapshot.sockets);
+        assert_eq!(read.portal_buffers, snapshot.portal_buffers);
+        assert_eq!(read.arena, snapshot.arena);
+    }
+
+    #[test]
+    fn unrecognized_data_is_not_a_snapshot() {
+        let bytes = build_snapshot().to_bytes();
+
+        assert!(RuntimeSnapshot::from_bytes(&[]).is_none());
+        assert!(RuntimeSnapshot::from_bytes(&bytes[..8]).is_none());
+        assert!(RuntimeSnapshot::from_bytes(&bytes[..bytes.len() - 1]).is_none());
+        let mut padded = bytes.clone();
+        padded.push(0);
+        assert!(RuntimeSnapshot::from_bytes(&padded).is_none());
+        let mut wrong_magic = bytes.clone();
+        wrong_magic[0] ^= 0xff;
+        assert!(RuntimeSnapshot::from_bytes(&wrong_magic).is_none());
+        let mut wrong_version = bytes.clone();
+        wrong_version[4] ^= 0xff;
+        assert!(RuntimeSnapshot::from_bytes(&wrong_version).is_none());
+
+        // a section claiming to be longer than the data that's left
+        let mut overlong_section = bytes;
+        overlong_section[24] = 0xff;
+        assert!(RuntimeSnapshot::from_bytes(&overlong_section).is_none());
+    }
+}
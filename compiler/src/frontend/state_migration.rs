@@ -0,0 +1,383 @@
+use super::Runtime;
+use crate::codegen::data_analyzer::SurfaceLayout;
+use crate::codegen::values::ARRAY_CAPACITY;
+use crate::codegen::{functions, ObjectCache};
+use crate::mir::block::Function;
+use crate::mir::{BlockRef, NodeData, SurfaceRef};
+use std::collections::HashMap;
+use std::os::raw::c_void;
+use std::{ptr, slice};
+
+/// Identifies a node across recompiles. Nodes are matched by what they contain and their order
+/// among the nodes in the surface containing the same thing, so adding or removing other nodes
+/// doesn't disturb them. Extracted groups get a new surface each time their parent is prepared, so
+/// they're matched by order alone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum NodeKey {
+    Custom(BlockRef),
+    Group(SurfaceRef),
+    ExtractGroup,
+}
+
+struct FunctionState {
+    function: Function,
+    data: Vec<u8>,
+
+    /// The contents of each buffer the function claimed from the arena, in the order of
+    /// `functions::get_arena_buffer_fields`.
+    buffers: Vec<Vec<u8>>,
+}
+
+enum NodeState {
+    Custom(Vec<FunctionState>),
+    Group(SurfaceState),
+    ExtractGroup(Vec<SurfaceState>),
+}
+
+#[derive(Default)]
+struct SurfaceState {
+    nodes: HashMap<NodeKey, Vec<NodeState>>,
+}
+
+/// The state of every function call in a runtime, captured before a commit so it can be carried
+/// over to the recompiled nodes afterwards.
+pub struct StateSnapshot {
+    root: Option<SurfaceState>,
+}
+
+fn get_node_ptr(
+    cache: &ObjectCache,
+    layout: &SurfaceLayout,
+    ptr: *mut c_void,
+    node: usize,
+) -> *mut c_void {
+    // wrapped node layouts always keep the node's own pointers at the start
+    let byte_offset = cache
+        .target()
+        .machine
+        .get_data()
+        .offset_of_element(&layout.pointer_struct, layout.node_ptr_index(node) as u32)
+        .unwrap();
+    unsafe { ptr.offset(byte_offset as isize) }
+}
+
+fn get_voice_ptrs(
+    cache: &ObjectCache,
+    surface: SurfaceRef,
+    node_ptr: *mut c_void,
+) -> impl Iterator<Item = *mut c_void> {
+    let voice_layout = cache.surface_layout(surface).unwrap();
+    let voice_size = cache
+        .target()
+        .machine
+        .get_data()
+        .get_abi_size(&voice_layout.pointer_struct);
+    (0..usize::from(ARRAY_CAPACITY))
+        .map(move |voice| unsafe { node_ptr.offset((voice_size as usize * voice) as isize) })
+}
+
+/// Finds the data of each function call in a custom node, in the order of `BlockLayout::functions`.
+fn get_function_data_ptrs(
+    cache: &ObjectCache,
+    block: BlockRef,
+    node_ptr: *mut c_void,
+) -> Vec<*mut u8> {
+    let block_layout = cache.block_layout(block).unwrap();
+    let target_data = cache.target().machine.get_data();
+
+    // must match the pointer struct built for custom nodes in `data_analyzer::build_node_layout`
+    let node_pointer_struct = cache.context().struct_type(
+        &[&block_layout.constant_struct, &block_layout.pointer_struct],
+        false,
+    );
+    let block_offset = target_data
+        .offset_of_element(&node_pointer_struct, 1)
+        .unwrap();
+
+    (0..block_layout.functions.len())
+        .map(|function| {
+            let function_offset = target_data
+                .offset_of_element(
+                    &block_layout.pointer_struct,
+                    block_layout.function_index(function) as u32,
+                )
+                .unwrap();
+            let data_ptr_ptr = unsafe { node_ptr.add((block_offset + function_offset) as usize) }
+                as *const *mut u8;
+            unsafe { *data_ptr_ptr }
+        })
+        .collect()
+}
+
+fn get_field_ptr(
+    cache: &ObjectCache,
+    function: Function,
+    data_ptr: *mut u8,
+    field: u32,
+) -> *mut u8 {
+    let data_type = functions::get_data_type(cache.context(), function);
+    let byte_offset = cache
+        .target()
+        .machine
+        .get_data()
+        .offset_of_element(&data_type, field)
+        .unwrap();
+    unsafe { data_ptr.add(byte_offset as usize) }
+}
+
+fn get_sample_size(cache: &ObjectCache) -> usize {
+    let sample_type = cache.target().storage_precision.float_type(cache.context());
+    cache.target().machine.get_data().get_abi_size(&sample_type) as usize
+}
+
+fn capture_function(cache: &ObjectCache, function: Function, data_ptr: *mut u8) -> FunctionState {
+    let data_type = functions::get_data_type(cache.context(), function);
+    let data_size = cache.target().machine.get_data().get_abi_size(&data_type) as usize;
+    let data = unsafe { slice::from_raw_parts(data_ptr, data_size) }.to_vec();
+
+    let sample_size = get_sample_size(cache);
+    let buffers = functions::get_arena_buffer_fields(function)
+        .iter()
+        .map(|&(buffer_field, capacity_field)| unsafe {
+            let buffer_ptr =
+                *(get_field_ptr(cache, function, data_ptr, buffer_field) as *const *const u8);
+            let capacity =
+                *(get_field_ptr(cache, function, data_ptr, capacity_field) as *const u32);
+            if buffer_ptr.is_null() {
+                Vec::new()
+            } else {
+                slice::from_raw_parts(buffer_ptr, capacity as usize * sample_size).to_vec()
+            }
+        })
+        .collect();
+
+    FunctionState {
+        function,
+        data,
+        buffers,
+    }
+}
+
+fn restore_function(cache: &ObjectCache, state: &FunctionState, data_ptr: *mut u8) {
+    let buffer_fields = functions::get_arena_buffer_fields(state.function);
+    let sample_size = get_sample_size(cache);
+
+    // The new function claimed its buffers when it was constructed. If they're smaller than the
+    // old ones (e.g. the sample rate went up) it keeps its newly constructed state.
+    let new_buffers: Vec<_> = buffer_fields
+        .iter()
+        .map(|&(buffer_field, capacity_field)| unsafe {
+            let buffer_ptr =
+                *(get_field_ptr(cache, state.function, data_ptr, buffer_field) as *const *mut u8);
+            let capacity =
+                *(get_field_ptr(cache, state.function, data_ptr, capacity_field) as *const u32);
+            (buffer_ptr, capacity)
+        })
+        .collect();
+    let buffers_fit = state
+        .buffers
+        .iter()
+        .zip(new_buffers.iter())
+        .all(|(buffer, &(_, capacity))| buffer.len() <= capacity as usize * sample_size);
+    if !buffers_fit {
+        return;
+    }
+
+    unsafe {
+        ptr::copy_nonoverlapping(state.data.as_ptr(), data_ptr, state.data.len());
+        for ((&(buffer_field, capacity_field), buffer), &(buffer_ptr, capacity)) in buffer_fields
+            .iter()
+            .zip(state.buffers.iter())
+            .zip(new_buffers.iter())
+        {
+            ptr::copy_nonoverlapping(buffer.as_ptr(), buffer_ptr, buffer.len());
+            *(get_field_ptr(cache, state.function, data_ptr, buffer_field) as *mut *mut u8) =
+                buffer_ptr;
+            *(get_field_ptr(cache, state.function, data_ptr, capacity_field) as *mut u32) =
+                capacity;
+        }
+    }
+}
+
+fn capture_surface(cache: &ObjectCache, surface: SurfaceRef, ptr: *mut c_void) -> SurfaceState {
+    let surface_mir = cache.surface_mir(surface).unwrap();
+    let layout = cache.surface_layout(surface).unwrap();
+    let mut state = SurfaceState::default();
+
+    for (node_index, node) in surface_mir.nodes.iter().enumerate() {
+        let node_ptr = get_node_ptr(cache, layout, ptr, node_index);
+        let (key, node_state) = match node.data {
+            NodeData::Custom { block, .. } => {
+                let block_layout = cache.block_layout(block).unwrap();
+                let function_states = block_layout
+                    .functions
+                    .iter()
+                    .zip(get_function_data_ptrs(cache, block, node_ptr))
+                    .map(|(&function, data_ptr)| capture_function(cache, function, data_ptr))
+                    .collect();
+                (NodeKey::Custom(block), NodeState::Custom(function_states))
+            }
+            NodeData::Group(subsurface) => (
+                NodeKey::Group(subsurface),
+                NodeState::Group(capture_surface(cache, subsurface, node_ptr)),
+            ),
+            NodeData::ExtractGroup {
+                surface: subsurface,
+                ..
+            } => {
+                let voice_states = get_voice_ptrs(cache, subsurface, node_ptr)
+                    .map(|voice_ptr| capture_surface(cache, subsurface, voice_ptr))
+                    .collect();
+                (NodeKey::ExtractGroup, NodeState::ExtractGroup(voice_states))
+            }
+            NodeData::Dummy | NodeData::UnitDelay => continue,
+        };
+        state
+            .nodes
+            .entry(key)
+            .or_insert_with(Vec::new)
+            .push(node_state);
+    }
+
+    state
+}
+
+fn restore_surface(runtime: &Runtime, state: &SurfaceState, surface: SurfaceRef, ptr: *mut c_void) {
+    let surface_mir = runtime.surface_mir(surface).unwrap();
+    let layout = runtime.surface_layout(surface).unwrap();
+    let mut key_counts = HashMap::new();
+
+    for (node_index, node) in surface_mir.nodes.iter().enumerate() {
+        let key = match node.data {
+            NodeData::Custom { block, .. } => NodeKey::Custom(block),
+            NodeData::Group(subsurface) => NodeKey::Group(subsurface),
+            NodeData::ExtractGroup { .. } => NodeKey::ExtractGroup,
+            NodeData::Dummy | NodeData::UnitDelay => continue,
+        };
+        let key_count = key_counts.entry(key).or_insert(0);
+        let old_state = state
+            .nodes
+            .get(&key)
+            .and_then(|node_states| node_states.get(*key_count));
+        *key_count += 1;
+
+        let node_ptr = get_node_ptr(runtime, layout, ptr, node_index);
+        match (&node.data, old_state) {
+            (NodeData::Custom { block, .. }, Some(NodeState::Custom(function_states))) => {
+                // function calls keep their state as long as they're still in the same place,
+                // calling the same function
+                let block_layout = runtime.block_layout(*block).unwrap();
+                for ((&function, data_ptr), function_state) in block_layout
+                    .functions
+                    .iter()
+                    .zip(get_function_data_ptrs(runtime, *block, node_ptr))
+                    .zip(function_states.iter())
+                {
+                    if function == function_state.function {
+                        restore_function(runtime, function_state, data_ptr);
+                    }
+                }
+            }
+            (NodeData::Group(subsurface), Some(NodeState::Group(surface_state))) => {
+                restore_surface(runtime, surface_state, *subsurface, node_ptr);
+            }
+            (
+                NodeData::ExtractGroup {
+                    surface: subsurface,
+                    ..
+                },
+                Some(NodeState::ExtractGroup(voice_states)),
+            ) => {
+                for (voice_ptr, voice_state) in
+                    get_voice_ptrs(runtime, *subsurface, node_ptr).zip(voice_states.iter())
+                {
+                    restore_surface(runtime, voice_state, *subsurface, voice_ptr);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn visit_surface_functions(
+    cache: &ObjectCache,
+    surface: SurfaceRef,
+    ptr: *mut c_void,
+    visitor: &mut FnMut(Function, *mut u8),
+) {
+    let surface_mir = cache.surface_mir(surface).unwrap();
+    let layout = cache.surface_layout(surface).unwrap();
+
+    for (node_index, node) in surface_mir.nodes.iter().enumerate() {
+        let node_ptr = get_node_ptr(cache, layout, ptr, node_index);
+        match node.data {
+            NodeData::Custom { block, .. } => {
+                let block_layout = cache.block_layout(block).unwrap();
+                for (&function, data_ptr) in block_layout
+                    .functions
+                    .iter()
+                    .zip(get_function_data_ptrs(cache, block, node_ptr))
+                {
+                    visitor(function, data_ptr);
+                }
+            }
+            NodeData::Group(subsurface) => {
+                visit_surface_functions(cache, subsurface, node_ptr, visitor);
+            }
+            NodeData::ExtractGroup {
+                surface: subsurface,
+                ..
+            } => {
+                for voice_ptr in get_voice_ptrs(cache, subsurface, node_ptr) {
+                    visit_surface_functions(cache, subsurface, voice_ptr, visitor);
+                }
+            }
+            NodeData::Dummy | NodeData::UnitDelay => {}
+        }
+    }
+}
+
+/// Points the arena buffers of every function in the cache at the same offset in a different
+/// arena, after the contents of the arena at `old_arena` have been copied to `new_arena`.
+pub fn rebase_arena_buffers(runtime: &Runtime, old_arena: usize, new_arena: usize) {
+    let root_ptr = runtime.get_root_ptr();
+    if root_ptr.is_null() {
+        return;
+    }
+
+    visit_surface_functions(runtime, 0, root_ptr, &mut |function, data_ptr| {
+        for &(buffer_field, _) in functions::get_arena_buffer_fields(function) {
+            let buffer_ptr_ptr =
+                get_field_ptr(runtime, function, data_ptr, buffer_field) as *mut usize;
+            unsafe {
+                if *buffer_ptr_ptr != 0 {
+                    *buffer_ptr_ptr = *buffer_ptr_ptr - old_arena + new_arena;
+                }
+            }
+        }
+    });
+}
+
+impl StateSnapshot {
+    /// Captures the state of the nodes in the surface pointed to by `root_ptr`, which must have
+    /// been compiled with `cache`. Must happen before the nodes are destroyed, since destructors
+    /// can let go of buffers.
+    pub fn capture(cache: &ObjectCache, root_ptr: *mut c_void) -> Self {
+        StateSnapshot {
+            root: if root_ptr.is_null() {
+                None
+            } else {
+                Some(capture_surface(cache, 0, root_ptr))
+            },
+        }
+    }
+
+    /// Carries the captured state over to the nodes in the surface pointed to by `root_ptr`, which
+    /// must have been compiled with the runtime's current layouts and just constructed. Data that
+    /// isn't carried over keeps its newly constructed value.
+    pub fn restore(&self, runtime: &Runtime, root_ptr: *mut c_void) {
+        if let (Some(root), false) = (&self.root, root_ptr.is_null()) {
+            restore_surface(runtime, root, 0, root_ptr);
+        }
+    }
+}
@@ -0,0 +1,285 @@
+use super::{c_api, Runtime, RuntimeSnapshot, Transaction};
+use crate::codegen::values::ARRAY_CAPACITY;
+use crate::codegen::{OptimizationLevel, TargetProperties};
+use crate::mir::{Root, ValueGroup, ValueGroupSource, VarType};
+use crate::test_support::{
+    assert_close, build_delay_block, build_delay_groups, build_node, build_root, build_surface,
+    compile_block, SAMPLE_RATE,
+};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+fn build_runtime(transaction: Transaction) -> Runtime {
+    crate::maxim_initialize();
+    let target = TargetProperties::new(
+        false,
+        OptimizationLevel::Editor,
+        c_api::create_host_machine(),
+    );
+    let mut runtime = Runtime::new(target);
+    runtime.audio().set_sample_rate(SAMPLE_RATE);
+    runtime.commit(transaction);
+    runtime
+}
+
+// runs one sample and returns the left channel of the only root socket
+fn run_sample(runtime: &Runtime) -> f64 {
+    unsafe {
+        runtime.audio().run_update();
+        (*(runtime.audio().get_portal_ptr(0) as *const [f64; 2]))[0]
+    }
+}
+
+#[test]
+fn inserted_node_keeps_delay_and_filter_state() {
+    let delay_block = build_delay_block();
+    let delay_node = build_node(&delay_block, &[("in", 1), ("out", 0)]);
+    let transaction = Transaction::new(
+        build_root(),
+        vec![build_surface(
+            build_delay_groups(),
+            vec![delay_node.clone()],
+        )],
+        vec![delay_block],
+    );
+    let reference = build_runtime(transaction.clone());
+    let mut runtime = build_runtime(transaction);
+    for _ in 0..1000 {
+        run_sample(&reference);
+        run_sample(&runtime);
+    }
+
+    // a node writing the same value as the default is inserted before the delay, so it moves to a
+    // new index but should carry on as if nothing happened
+    let source_block = compile_block(2, "out:num = 1");
+    let source_node = build_node(&source_block, &[("out", 1)]);
+    runtime.commit(Transaction::new(
+        None,
+        vec![build_surface(
+            build_delay_groups(),
+            vec![source_node, delay_node],
+        )],
+        vec![source_block],
+    ));
+
+    for sample in 0..1000 {
+        assert_close(
+            run_sample(&runtime),
+            run_sample(&reference),
+            &format!("sample {}", sample),
+        );
+    }
+}
+
+#[test]
+fn changed_function_is_not_restored() {
+    let counter_block = compile_block(1, "out:num = accum(1, 1)");
+    let counter_node = build_node(&counter_block, &[("out", 0)]);
+    let groups = vec![ValueGroup::new(VarType::Num, ValueGroupSource::Socket(0))];
+    let mut runtime = build_runtime(Transaction::new(
+        build_root(),
+        vec![build_surface(groups.clone(), vec![counter_node])],
+        vec![counter_block],
+    ));
+    let mut count = 0.;
+    for _ in 0..100 {
+        count = run_sample(&runtime);
+    }
+    assert_close(count, 100., "count before the commit");
+
+    // the block's first function is now a `last` call, which would output the count if the
+    // counter's data was copied into it
+    let last_block = compile_block(1, "out:num = last(5)");
+    let last_node = build_node(&last_block, &[("out", 0)]);
+    runtime.commit(Transaction::new(
+        None,
+        vec![build_surface(groups, vec![last_node])],
+        vec![last_block],
+    ));
+
+    assert_close(run_sample(&runtime), 0., "first sample after the commit");
+    assert_close(run_sample(&runtime), 5., "second sample after the commit");
+}
+
+#[test]
+fn extracted_voices_keep_their_state() {
+    // a counter in every voice, mixed down into the root socket
+    let voice_block = compile_block(1, "out:num = accum(1, 1)");
+    let voice_node = build_node(&voice_block, &[("out", 1)]);
+    let groups = vec![
+        ValueGroup::new(VarType::Num, ValueGroupSource::Socket(0)),
+        ValueGroup::new(VarType::new_array(VarType::Num), ValueGroupSource::None),
+    ];
+    let mix_block = compile_block(2, "out:num = mixdown(in:num[])");
+    let mix_node = build_node(&mix_block, &[("in", 1), ("out", 0)]);
+    let mut runtime = build_runtime(Transaction::new(
+        build_root(),
+        vec![build_surface(
+            groups.clone(),
+            vec![voice_node.clone(), mix_node],
+        )],
+        vec![voice_block, mix_block],
+    ));
+    let voice_count = f64::from(ARRAY_CAPACITY);
+    let mut mix = 0.;
+    for _ in 0..100 {
+        mix = run_sample(&runtime);
+    }
+    assert_close(mix, voice_count * 100., "mix before the commit");
+
+    // committing the surface extracts the voices into a new surface, which is matched by order
+    let mix_block = compile_block(2, "out:num = mixdown(in:num[]) * 2");
+    let mix_node = build_node(&mix_block, &[("in", 1), ("out", 0)]);
+    runtime.commit(Transaction::new(
+        None,
+        vec![build_surface(groups, vec![voice_node, mix_node])],
+        vec![mix_block],
+    ));
+
+    assert_close(
+        run_sample(&runtime),
+        voice_count * 101. * 2.,
+        "mix after the commit",
+    );
+}
+
+fn build_delay_runtime() -> Runtime {
+    let delay_block = build_delay_block();
+    let delay_node = build_node(&delay_block, &[("in", 1), ("out", 0)]);
+    build_runtime(Transaction::new(
+        build_root(),
+        vec![build_surface(build_delay_groups(), vec![delay_node])],
+        vec![delay_block],
+    ))
+}
+
+#[test]
+fn snapshot_with_a_different_fingerprint_is_rejected() {
+    let runtime = build_delay_runtime();
+    for _ in 0..1000 {
+        run_sample(&runtime);
+    }
+    let snapshot = runtime.snapshot();
+
+    let counter_block = compile_block(1, "out:num = accum(1, 1)");
+    let counter_node = build_node(&counter_block, &[("out", 0)]);
+    let mut counter_runtime = build_runtime(Transaction::new(
+        build_root(),
+        vec![build_surface(
+            vec![ValueGroup::new(VarType::Num, ValueGroupSource::Socket(0))],
+            vec![counter_node],
+        )],
+        vec![counter_block],
+    ));
+    assert_ne!(snapshot.fingerprint, counter_runtime.layout_fingerprint());
+    for _ in 0..10 {
+        run_sample(&counter_runtime);
+    }
+    assert!(counter_runtime.restore(&snapshot).is_err());
+
+    // a snapshot with the right sections but the wrong fingerprint is rejected too
+    let mut delay_runtime = build_delay_runtime();
+    let mut wrong_fingerprint = snapshot;
+    wrong_fingerprint.fingerprint ^= 1;
+    assert!(delay_runtime.restore(&wrong_fingerprint).is_err());
+
+    // neither runtime was changed
+    assert_close(
+        run_sample(&counter_runtime),
+        11.,
+        "counter after the restore",
+    );
+    assert_close(
+        run_sample(&delay_runtime),
+        run_sample(&build_delay_runtime()),
+        "delay after the restore",
+    );
+}
+
+#[test]
+fn restored_delay_buffers_point_into_the_new_arena() {
+    let runtime = build_delay_runtime();
+    for _ in 0..1000 {
+        run_sample(&runtime);
+    }
+    let snapshot = RuntimeSnapshot::from_bytes(&runtime.snapshot().to_bytes()).unwrap();
+
+    // the original keeps running, so a restored delay that still read from its arena would
+    // see samples from further on than the snapshot
+    let expected: Vec<_> = (0..1000).map(|_| run_sample(&runtime)).collect();
+
+    let mut restored = build_delay_runtime();
+    restored.restore(&snapshot).unwrap();
+    assert_ne!(restored.snapshot().arena_address, snapshot.arena_address);
+    for (sample, &expected_value) in expected.iter().enumerate() {
+        assert_close(
+            run_sample(&restored),
+            expected_value,
+            &format!("sample {}", sample),
+        );
+    }
+}
+
+#[test]
+fn publishing_while_the_audio_thread_runs_updates() {
+    let build_transaction = |value: u32, root: Option<Root>| {
+        let block = compile_block(1, &format!("out:num = {}", value));
+        let node = build_node(&block, &[("out", 0)]);
+        Transaction::new(
+            root,
+            vec![build_surface(
+                vec![ValueGroup::new(VarType::Num, ValueGroupSource::Socket(0))],
+                vec![node],
+            )],
+            vec![block],
+        )
+    };
+    let mut runtime = build_runtime(build_transaction(1, build_root()));
+
+    // the audio thread publishes whatever is pending between its updates, and checks it only ever
+    // sees the output of code that was committed
+    let audio = Arc::clone(runtime.audio());
+    let stop = Arc::new(AtomicBool::new(false));
+    let audio_stop = Arc::clone(&stop);
+    let audio_thread = thread::spawn(move || {
+        let mut publishes = 0;
+        let mut last_value = 0.;
+        while !audio_stop.load(Ordering::Acquire) {
+            if audio.publish() {
+                publishes += 1;
+            }
+            unsafe {
+                audio.run_update();
+                last_value = (*(audio.get_portal_ptr(0) as *const [f64; 2]))[0];
+            }
+            assert!(
+                (1. ..=4.).contains(&last_value),
+                "unexpected output {}",
+                last_value
+            );
+        }
+        (publishes, last_value)
+    });
+
+    // some commits are replaced before they're published, some are left for the next prepare to
+    // reclaim and some are reclaimed as soon as they're published
+    let mut value = 1;
+    for commit in 0..30 {
+        value = commit % 4 + 1;
+        assert!(runtime.prepare(build_transaction(value, None)));
+        if commit % 3 == 0 {
+            while !runtime.reclaim() {
+                thread::yield_now();
+            }
+        }
+    }
+    while !runtime.reclaim() {
+        thread::yield_now();
+    }
+    stop.store(true, Ordering::Release);
+    let (publishes, last_value) = audio_thread.join().unwrap();
+
+    assert!(publishes > 0, "nothing was published");
+    assert_close(last_value, f64::from(value), "output after the last commit");
+}
@@ -1,7 +1,7 @@
 use crate::codegen::data_analyzer::SurfaceLayout;
 use crate::codegen::ObjectCache;
 use crate::codegen::TargetProperties;
-use crate::mir::{BlockRef, InternalNodeRef, NodeData, SurfaceRef};
+use crate::mir::{BlockRef, InternalNodeRef, NodeData, OversampleFactor, SurfaceRef, UpdateRate};
 use std::os::raw::c_void;
 use std::ptr::{null, null_mut};
 
@@ -82,11 +82,21 @@ pub fn get_node_active_bitmap_ptr(
             let extract_node_ptr =
                 get_internal_node_ptr(cache.target(), surface_layout, ptr, surface_node);
             let extract_node_layout = &surface_layout.node_layouts[surface_node];
+
+            // control-rate and oversampled nodes wrap their pointers in another struct, with the
+            // node's own pointers at the start
+            let extract_node = &surface_mir.nodes[surface_node];
+            let wrapper_count = (extract_node.update_rate != UpdateRate::Audio) as usize
+                + (extract_node.oversample_factor != OversampleFactor::None) as usize;
+            let extract_pointer_struct = (0..wrapper_count)
+                .fold(extract_node_layout.pointer_struct, |pointer_struct, _| {
+                    pointer_struct.get_field_types()[0].into_struct_type()
+                });
             let bitmap_ptr_offset = cache
                 .target()
                 .machine
                 .get_data()
-                .offset_of_element(&extract_node_layout.pointer_struct, 3)
+                .offset_of_element(&extract_pointer_struct, 3)
                 .unwrap();
             let bitmap_ptr_ptr =
                 unsafe { extract_node_ptr.offset(bitmap_ptr_offset as isize) } as *const *const u32;
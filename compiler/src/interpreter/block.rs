@@ -0,0 +1,191 @@
+use super::controls::ControlState;
+use super::converters;
+use super::functions::{self, not_equal, FunctionState};
+use super::math;
+use super::value::{Num, Value};
+use super::Globals;
+use crate::ast::{FormType, OperatorType, UnaryOperation};
+use crate::codegen::MathAccuracy;
+use crate::mir::block::{Function, Global, Statement};
+use crate::mir::{Block, ControlInitializer};
+
+/// The state of one instance of a block: the data of each control, and of each function call.
+#[derive(Debug, Clone)]
+pub struct BlockInstance {
+    controls: Vec<ControlState>,
+    functions: Vec<FunctionState>,
+}
+
+impl BlockInstance {
+    pub fn new(block: &Block, control_initializers: &[ControlInitializer]) -> Self {
+        BlockInstance {
+            controls: block
+                .controls
+                .iter()
+                .enumerate()
+                .map(|(index, control)| {
+                    ControlState::new(
+                        control.control_type,
+                        control_initializers
+                            .get(index)
+                            .unwrap_or(&ControlInitializer::None),
+                    )
+                })
+                .collect(),
+            functions: block
+                .statements
+                .iter()
+                .map(|statement| match statement {
+                    Statement::CallFunc { function, .. } => FunctionState::new(*function),
+                    _ => FunctionState::Stateless,
+                })
+                .collect(),
+        }
+    }
+
+    /// Takes the state of each function call out of the instance, in the order of the calls in
+    /// the block.
+    pub fn into_function_states(self, block: &Block) -> Vec<(Function, FunctionState)> {
+        block
+            .statements
+            .iter()
+            .zip(self.functions)
+            .filter_map(|(statement, state)| match statement {
+                Statement::CallFunc { function, .. } => Some((*function, state)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Carries over the state of function calls from a previous instance. Calls keep their state
+    /// as long as they're still in the same place, calling the same function.
+    pub fn restore_function_states(
+        &mut self,
+        block: &Block,
+        states: Vec<(Function, FunctionState)>,
+    ) {
+        let calls = block
+            .statements
+            .iter()
+            .zip(self.functions.iter_mut())
+            .filter_map(|(statement, state)| match statement {
+                Statement::CallFunc { function, .. } => Some((*function, state)),
+                _ => None,
+            });
+        for ((function, state), (old_function, old_state)) in calls.zip(states) {
+            if function == old_function {
+                *state = old_state;
+            }
+        }
+    }
+
+    /// Runs one sample of the block. `controls` holds the value of each of the block's controls,
+    /// which is the value of the node socket at the same index.
+    pub fn update(&mut self, block: &Block, controls: &mut [Value], globals: &mut Globals) {
+        for (control, value) in self.controls.iter_mut().zip(controls.iter_mut()) {
+            control.update(value, globals);
+        }
+
+        let mut results: Vec<Value> = Vec::with_capacity(block.statements.len());
+        for (index, statement) in block.statements.iter().enumerate() {
+            let result = match statement {
+                Statement::Constant(value) => Value::from_constant(value),
+                Statement::Global(global) => Value::Num(Num::from_vec(
+                    match global {
+                        Global::SampleRate => globals.sample_rate,
+                        Global::BPM => globals.bpm,
+                    },
+                    FormType::None,
+                )),
+                Statement::NumConvert { target_form, input } => Value::Num(converters::convert(
+                    results[*input].as_num(),
+                    *target_form,
+                    globals,
+                )),
+                Statement::NumCast { target_form, input } => {
+                    Value::Num(results[*input].as_num().with_form(*target_form))
+                }
+                Statement::NumUnaryOp { op, input } => {
+                    Value::Num(unary_op(*op, results[*input].as_num()))
+                }
+                Statement::NumMathOp { op, lhs, rhs } => Value::Num(math_op(
+                    *op,
+                    results[*lhs].as_num(),
+                    results[*rhs].as_num(),
+                    globals.math_accuracy,
+                )),
+                Statement::Extract { tuple, index } => results[*tuple].as_tuple()[*index].clone(),
+                Statement::Combine { indexes } => Value::Tuple(
+                    indexes
+                        .iter()
+                        .map(|&index| results[index].clone())
+                        .collect(),
+                ),
+                Statement::CallFunc {
+                    function,
+                    args,
+                    varargs,
+                } => {
+                    let arg_values: Vec<_> = args.iter().map(|&arg| &results[arg]).collect();
+                    let vararg_values: Vec<_> = varargs.iter().map(|&arg| &results[arg]).collect();
+                    functions::call(
+                        *function,
+                        &mut self.functions[index],
+                        &arg_values,
+                        &vararg_values,
+                        globals,
+                    )
+                }
+                Statement::StoreControl {
+                    control,
+                    field,
+                    value,
+                } => {
+                    self.controls[*control].set_field(
+                        *field,
+                        &mut controls[*control],
+                        &results[*value],
+                    );
+                    Value::Void
+                }
+                Statement::LoadControl { control, field } => {
+                    self.controls[*control].get_field(*field, &controls[*control])
+                }
+            };
+            results.push(result);
+        }
+    }
+}
+
+fn unary_op(op: UnaryOperation, num: Num) -> Num {
+    match op {
+        UnaryOperation::Positive => num,
+        UnaryOperation::Negative => num.map(|x| -x),
+        UnaryOperation::Not => num.map(|x| if x == 0. { 1. } else { 0. }),
+    }
+}
+
+fn math_op(op: OperatorType, lhs: Num, rhs: Num, accuracy: MathAccuracy) -> Num {
+    let bool_float = |val: bool| if val { 1. } else { 0. };
+    let as_bool = |x: f64| (x as i32) & 1 != 0;
+    lhs.zip(rhs, |a, b| match op {
+        OperatorType::Identity => a,
+        OperatorType::Add => a + b,
+        OperatorType::Subtract => a - b,
+        OperatorType::Multiply => a * b,
+        OperatorType::Divide => a / b,
+        OperatorType::Modulo => math::modulo(a, b),
+        OperatorType::Power => math::pow(a, b, accuracy),
+        OperatorType::BitwiseAnd => f64::from(a as i32 & b as i32),
+        OperatorType::BitwiseOr => f64::from(a as i32 | b as i32),
+        OperatorType::BitwiseXor => f64::from(a as i32 ^ b as i32),
+        OperatorType::LogicalAnd => bool_float(as_bool(a) && as_bool(b)),
+        OperatorType::LogicalOr => bool_float(as_bool(a) || as_bool(b)),
+        OperatorType::LogicalEqual => bool_float(a == b),
+        OperatorType::LogicalNotEqual => bool_float(not_equal(a, b)),
+        OperatorType::LogicalGt => bool_float(a > b),
+        OperatorType::LogicalLt => bool_float(a < b),
+        OperatorType::LogicalGte => bool_float(a >= b),
+        OperatorType::LogicalLte => bool_float(a <= b),
+    })
+}
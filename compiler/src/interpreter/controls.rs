@@ -0,0 +1,134 @@
+use super::math;
+use super::value::{Num, Value};
+use super::Globals;
+use crate::ast::{ControlField, ControlType, FormType, GraphField, RollField};
+use crate::codegen::MathAccuracy;
+use crate::mir::{ControlInitializer, GraphControlInitializer};
+
+/// The persistent data of a control, next to the value it shares with its socket.
+#[derive(Debug, Clone)]
+pub enum ControlState {
+    Stateless,
+    Graph(GraphState),
+}
+
+#[derive(Debug, Clone)]
+pub struct GraphState {
+    initializer: Option<GraphControlInitializer>,
+    time: u32,
+    state: u8,
+    paused: bool,
+}
+
+impl ControlState {
+    pub fn new(control_type: ControlType, initializer: &ControlInitializer) -> Self {
+        match control_type {
+            ControlType::Graph => ControlState::Graph(GraphState {
+                initializer: initializer.as_graph_control().cloned(),
+                time: 0,
+                state: 0,
+                paused: false,
+            }),
+            _ => ControlState::Stateless,
+        }
+    }
+
+    /// Runs the per-sample update of the control, before any statements in its block.
+    pub fn update(&mut self, value: &mut Value, globals: &Globals) {
+        if let ControlState::Graph(graph) = self {
+            graph.update(value, globals);
+        }
+    }
+
+    pub fn get_field(&self, field: ControlField, value: &Value) -> Value {
+        match (self, field) {
+            (ControlState::Graph(graph), ControlField::Graph(GraphField::State)) => {
+                Value::Num(Num::splat(f64::from(graph.state), FormType::None))
+            }
+            (ControlState::Graph(graph), ControlField::Graph(GraphField::Paused)) => Value::Num(
+                Num::splat(if graph.paused { 1. } else { 0. }, FormType::None),
+            ),
+            (ControlState::Graph(graph), ControlField::Graph(GraphField::Time)) => {
+                Value::Num(Num::splat(f64::from(graph.time), FormType::Samples))
+            }
+            // todo: the roll speed isn't implemented in the generated code either
+            (_, ControlField::Roll(RollField::Speed)) => Value::Num(Num::zero()),
+            _ => value.clone(),
+        }
+    }
+
+    pub fn set_field(&mut self, field: ControlField, value: &mut Value, new_value: &Value) {
+        match (self, field) {
+            (ControlState::Graph(graph), ControlField::Graph(GraphField::State)) => {
+                let left = new_value.as_num().left;
+                graph.state = left.min(f64::from(u8::max_value()) - 1.).max(0.) as u8;
+            }
+            (ControlState::Graph(_), ControlField::Graph(GraphField::Paused)) => {}
+            (ControlState::Graph(graph), ControlField::Graph(GraphField::Time)) => {
+                graph.time = new_value.as_num().left.max(0.) as u32;
+            }
+            (_, ControlField::Roll(RollField::Speed)) => {}
+            _ => *value = new_value.clone(),
+        }
+    }
+}
+
+/// Shapes the progress through a curve, equivalent to `tensionGraph` in the generated code.
+fn tension_graph(x: f64, tension: f64, accuracy: MathAccuracy) -> f64 {
+    let q = 20.;
+    if tension >= 0. {
+        math::pow(x, math::pow(q, tension, accuracy), accuracy)
+    } else {
+        1. - math::pow(1. - x, math::pow(q, -tension, accuracy), accuracy)
+    }
+}
+
+impl GraphState {
+    fn update(&mut self, value: &mut Value, globals: &Globals) {
+        let initializer = match &self.initializer {
+            Some(initializer) => initializer,
+            None => {
+                self.time = 0;
+                return;
+            }
+        };
+
+        let mut last_curve_end = 0;
+        for curve in 0..initializer.curve_count as usize {
+            let curve_end = (initializer.end_positions[curve] * globals.sample_rate[0] * 60.
+                / globals.bpm[0]) as u32;
+            let curve_active = self.time < curve_end;
+
+            // we must pause at the start of a tagged curve, even if the curve isn't active
+            let increment_sample = self.time > last_curve_end
+                || initializer.states[curve] != self.state.wrapping_add(1);
+            let is_paused = !increment_sample;
+
+            if curve_active || is_paused {
+                let curve_min = initializer.start_values[curve];
+                let curve_max = initializer.start_values[curve + 1];
+                let curve_length = curve_end.wrapping_sub(last_curve_end);
+                let output = if curve_length != 0 {
+                    let x =
+                        f64::from(self.time.wrapping_sub(last_curve_end)) / f64::from(curve_length);
+                    curve_min
+                        + (curve_max - curve_min)
+                            * tension_graph(x, initializer.tension[curve], globals.math_accuracy)
+                } else {
+                    curve_min
+                };
+                *value = Value::Num(Num::splat(output, FormType::None));
+
+                self.paused = is_paused;
+                if increment_sample {
+                    self.time += 1;
+                }
+                return;
+            }
+
+            last_curve_end = curve_end;
+        }
+
+        self.time = 0;
+    }
+}
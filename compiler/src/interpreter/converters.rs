@@ -0,0 +1,75 @@
+use super::math;
+use super::value::Num;
+use super::Globals;
+use crate::ast::FormType;
+use std::f64::consts;
+
+/// Converts a number to another form, with the same conversion table as the generated
+/// `maxim.converter.*` functions. Pairs without a conversion keep their value.
+pub fn convert(num: Num, target_form: FormType, globals: &Globals) -> Num {
+    let sample_rate = globals.sample_rate;
+    let bpm = globals.bpm;
+    let accuracy = globals.math_accuracy;
+    let mut result = [0.; 2];
+    for channel in 0..2 {
+        let val = num.vec()[channel];
+        let sr = sample_rate[channel];
+        let bpm = bpm[channel];
+
+        result[channel] = match (target_form, num.form) {
+            (FormType::Amplitude, FormType::Db) => math::exp10(val / 20., accuracy),
+
+            (FormType::Beats, FormType::Control) => val * 8.,
+            (FormType::Beats, FormType::Frequency) => bpm / (60. * val),
+            (FormType::Beats, FormType::Samples) => val * bpm / (sr * 60.),
+            (FormType::Beats, FormType::Seconds) => val * (bpm * 60.),
+
+            (FormType::Control, FormType::Beats) => val / 8.,
+            (FormType::Control, FormType::Db) => math::exp10(val / 20., accuracy) / 2.,
+            (FormType::Control, FormType::Frequency) => {
+                math::log(val + 1., accuracy) / 20000_f64.log(consts::E)
+            }
+            (FormType::Control, FormType::Note) => val / 127.,
+            (FormType::Control, FormType::Oscillator) => val * 0.5 + 0.5,
+            (FormType::Control, FormType::Q) => {
+                math::pow((val - 1. / 3.) / (12. - 1. / 3.), 1. / 3., accuracy)
+            }
+            (FormType::Control, FormType::Samples) => val * 1.1 / (val + 0.1 * sr),
+            (FormType::Control, FormType::Seconds) => val * 1.1 / (val + 0.5),
+
+            (FormType::Db, FormType::Amplitude) => math::log10(val, accuracy) * 20.,
+            (FormType::Db, FormType::Control) => math::log10(val * 2., accuracy) * 20.,
+
+            (FormType::Frequency, FormType::Beats) => bpm / (val * 60.),
+            (FormType::Frequency, FormType::Control) => {
+                math::pow(20000., math::min(val, 8.), accuracy) - 1.
+            }
+            (FormType::Frequency, FormType::Note) => 440. * math::exp2((val - 69.) / 12., accuracy),
+            (FormType::Frequency, FormType::Samples) => sr / val,
+            (FormType::Frequency, FormType::Seconds) => 1. / val,
+
+            (FormType::Note, FormType::Control) => val * 127.,
+            (FormType::Note, FormType::Frequency) => 69. + 12. * math::log2(val / 440., accuracy),
+
+            (FormType::Oscillator, FormType::Control) => val * 2. - 1.,
+
+            (FormType::Q, FormType::Control) => {
+                (12. - 1. / 3.) * math::pow(val, 3., accuracy) + 1. / 3.
+            }
+
+            (FormType::Samples, FormType::Beats) => val * (sr * 60.) / bpm,
+            (FormType::Samples, FormType::Control) => val * sr / (11. - val * 10.),
+            (FormType::Samples, FormType::Frequency) => sr / val,
+            (FormType::Samples, FormType::Seconds) => val * sr,
+
+            (FormType::Seconds, FormType::Beats) => val / (bpm / 60.),
+            (FormType::Seconds, FormType::Control) => val / (2.2 - val * 2.),
+            (FormType::Seconds, FormType::Frequency) => 1. / val,
+            (FormType::Seconds, FormType::Samples) => val / sr,
+
+            _ => val,
+        };
+    }
+
+    Num::from_vec(result, target_form)
+}
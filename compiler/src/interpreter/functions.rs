@@ -0,0 +1,794 @@
+use super::math;
+use super::value::{Array, Midi, MidiEvent, Num, Value};
+use super::Globals;
+use crate::ast::FormType;
+use crate::codegen::MathAccuracy;
+use crate::mir::block::Function;
+use crate::mir::{VarType, ARRAY_CAPACITY};
+use std::f64::consts;
+
+/// The persistent data of one function call in a block, matching the data type the generated
+/// function is given. Everything starts out zeroed, like after the generated constructor.
+#[derive(Debug, Clone)]
+pub enum FunctionState {
+    Stateless,
+    Last(Num),
+    Amplitude([f64; 2]),
+    Hold { val: [f64; 2], gate: [bool; 2] },
+    Accum([f64; 2]),
+    Delay([DelayChannel; 2]),
+    Biquad(BiquadState),
+    SvFilter { low: [f64; 2], band: [f64; 2] },
+    Periodic([f64; 2]),
+    Note(NoteState),
+    Voices([u8; ARRAY_CAPACITY as usize]),
+    Adsr([AdsrChannel; 2]),
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DelayChannel {
+    buffer: Vec<f64>,
+    pos: u32,
+    size: u32,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct BiquadState {
+    a1: [f64; 2],
+    a2: [f64; 2],
+    b0: [f64; 2],
+    b1: [f64; 2],
+    b2: [f64; 2],
+    y1: [f64; 2],
+    y2: [f64; 2],
+    z1: [f64; 2],
+    z2: [f64; 2],
+    cached_freq: [f64; 2],
+    cached_q: [f64; 2],
+    cached_gain: [f64; 2],
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct NoteState {
+    note: f64,
+    pitch: f64,
+    velocity: f64,
+    aftertouch: f64,
+    active_count: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AdsrPhase {
+    NotActive,
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct AdsrChannel {
+    last_trigger: bool,
+    pos: f64,
+    release_val: f64,
+    last_val: f64,
+    phase: AdsrPhase,
+}
+
+impl Default for AdsrChannel {
+    fn default() -> Self {
+        AdsrChannel {
+            last_trigger: false,
+            pos: 0.,
+            release_val: 0.,
+            last_val: 0.,
+            phase: AdsrPhase::NotActive,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BiquadType {
+    Low,
+    High,
+    Band,
+    Notch,
+    All,
+    Peak,
+}
+
+impl FunctionState {
+    pub fn new(function: Function) -> Self {
+        match function {
+            Function::Last => FunctionState::Last(Num::zero()),
+            Function::Amplitude => FunctionState::Amplitude([0.; 2]),
+            Function::Hold => FunctionState::Hold {
+                val: [0.; 2],
+                gate: [false; 2],
+            },
+            Function::Accum => FunctionState::Accum([0.; 2]),
+            Function::Delay => FunctionState::Delay(Default::default()),
+            Function::LowBqFilter
+            | Function::HighBqFilter
+            | Function::BandBqFilter
+            | Function::NotchBqFilter
+            | Function::AllBqFilter
+            | Function::PeakBqFilter => FunctionState::Biquad(BiquadState::default()),
+            Function::SvFilter => FunctionState::SvFilter {
+                low: [0.; 2],
+                band: [0.; 2],
+            },
+            Function::SinOsc
+            | Function::SqrOsc
+            | Function::SawOsc
+            | Function::TriOsc
+            | Function::RmpOsc => FunctionState::Periodic([0.; 2]),
+            Function::Note => FunctionState::Note(NoteState::default()),
+            Function::Voices => FunctionState::Voices([0; ARRAY_CAPACITY as usize]),
+            Function::Adsr => FunctionState::Adsr(Default::default()),
+            _ => FunctionState::Stateless,
+        }
+    }
+}
+
+/// Fills in the optional arguments of a call, the same way each function's `gen_real_args` does.
+fn real_args(function: Function, args: &[&Value]) -> Vec<Value> {
+    let mut args: Vec<_> = args.iter().map(|&arg| arg.clone()).collect();
+    let zero = || Value::Num(Num::zero());
+    match function {
+        Function::Hold | Function::Accum => {
+            if args.len() < 3 {
+                args.push(zero());
+            }
+        }
+        Function::Delay => {
+            if args.len() < 3 {
+                args.insert(1, Value::Num(Num::splat(1., FormType::None)));
+            }
+        }
+        Function::SinOsc | Function::SawOsc | Function::TriOsc | Function::RmpOsc => {
+            if args.len() < 2 {
+                args.push(zero());
+            }
+        }
+        Function::SqrOsc => {
+            if args.len() < 2 {
+                args.push(zero());
+            }
+            if args.len() < 3 {
+                args.push(Value::Num(Num::splat(0.5, FormType::None)));
+            }
+        }
+        _ => {}
+    }
+    args
+}
+
+fn unary(args: &[Value], f: impl Fn(f64) -> f64) -> Value {
+    Value::Num(args[0].as_num().map(f))
+}
+
+fn binary(args: &[Value], f: impl Fn(f64, f64) -> f64) -> Value {
+    Value::Num(args[0].as_num().zip(args[1].as_num(), f))
+}
+
+/// An ordered not-equal comparison, so NaN compares false like `FloatPredicate::ONE`.
+pub fn not_equal(a: f64, b: f64) -> bool {
+    a != b && !a.is_nan() && !b.is_nan()
+}
+
+/// Evaluates one call of a function on its argument values.
+pub fn call(
+    function: Function,
+    state: &mut FunctionState,
+    args: &[&Value],
+    varargs: &[&Value],
+    globals: &mut Globals,
+) -> Value {
+    let args = real_args(function, args);
+    let sample_rate = globals.sample_rate;
+    let accuracy = globals.math_accuracy;
+
+    match function {
+        Function::Sin => unary(&args, |x| math::sin(x, accuracy)),
+        Function::Cos => unary(&args, |x| math::cos(x, accuracy)),
+        Function::Tan => unary(&args, |x| math::tan(x, accuracy)),
+        Function::Min => binary(&args, math::min),
+        Function::Max => binary(&args, math::max),
+        Function::Sqrt => unary(&args, f64::sqrt),
+        Function::Floor => unary(&args, f64::floor),
+        Function::Ceil => unary(&args, f64::ceil),
+        Function::Round => unary(&args, math::round),
+        Function::Abs => unary(&args, f64::abs),
+        Function::CopySign => binary(&args, f64::copysign),
+        Function::Fract => unary(&args, math::fract),
+        Function::Exp => unary(&args, |x| math::exp(x, accuracy)),
+        Function::Exp2 => unary(&args, |x| math::exp2(x, accuracy)),
+        Function::Exp10 => unary(&args, |x| math::exp10(x, accuracy)),
+        Function::Log => unary(&args, |x| math::log(x, accuracy)),
+        Function::Log2 => unary(&args, |x| math::log2(x, accuracy)),
+        Function::Log10 => unary(&args, |x| math::log10(x, accuracy)),
+        Function::Asin => unary(&args, |x| math::asin(x, accuracy)),
+        Function::Acos => unary(&args, |x| math::acos(x, accuracy)),
+        Function::Atan => unary(&args, |x| math::atan(x, accuracy)),
+        Function::Atan2 => binary(&args, |y, x| math::atan2(y, x, accuracy)),
+        Function::Sinh => unary(&args, |x| math::sinh(x, accuracy)),
+        Function::Cosh => unary(&args, |x| math::cosh(x, accuracy)),
+        Function::Tanh => unary(&args, |x| math::tanh(x, accuracy)),
+        Function::Hypot => binary(&args, math::hypot),
+        Function::ToRad => unary(&args, |x| x * (consts::PI / 180.)),
+        Function::ToDeg => unary(&args, |x| x * (180. / consts::PI)),
+        Function::Clamp => {
+            let x = args[0].as_num();
+            let min = args[1].as_num();
+            let max = args[2].as_num();
+            Value::Num(x.zip(max, math::min).zip(min, math::max))
+        }
+        Function::Pan => {
+            let x = args[0].as_num();
+            let pan = args[1].as_num().map(|p| p.min(1.).max(-1.));
+            let base_sin = [
+                math::sin(
+                    consts::FRAC_PI_4 * (pan.left + 1.) + consts::FRAC_PI_2,
+                    accuracy,
+                ),
+                math::sin(consts::FRAC_PI_4 * (pan.right + 1.), accuracy),
+            ];
+            let base = [
+                (1. - pan.left) * base_sin[0],
+                (1. + pan.right) * base_sin[1],
+            ];
+            Value::Num(Num::new(
+                x.left * (base[0] / 2.).sqrt(),
+                x.right * (base[1] / 2.).sqrt(),
+                x.form,
+            ))
+        }
+        Function::Left => {
+            let x = args[0].as_num();
+            Value::Num(Num::splat(x.left, x.form))
+        }
+        Function::Right => {
+            let x = args[0].as_num();
+            Value::Num(Num::splat(x.right, x.form))
+        }
+        Function::Swap => {
+            let x = args[0].as_num();
+            Value::Num(Num::new(x.right, x.left, x.form))
+        }
+        Function::Combine => {
+            let left = args[0].as_num();
+            let right = args[1].as_num();
+            Value::Num(Num::new(left.left, right.right, left.form))
+        }
+        Function::Mix => {
+            let a = args[0].as_num();
+            let b = args[1].as_num();
+            let mix = args[2].as_num();
+            Value::Num(Num::new(
+                (b.left - a.left) * mix.left + a.left,
+                (b.right - a.right) * mix.right + a.right,
+                a.form,
+            ))
+        }
+        Function::Sequence => {
+            let index = args[0].as_num();
+            let count = varargs.len() as i32;
+            let pick = |x: f64| (((x as i32) % count + count) % count) as usize;
+            let left = varargs[pick(index.left)].as_num();
+            let right = varargs[pick(index.right)].as_num();
+            Value::Num(Num::new(left.left, right.right, varargs[0].as_num().form))
+        }
+        Function::Last => {
+            let last = match state {
+                FunctionState::Last(last) => last,
+                _ => unreachable!(),
+            };
+            let result = *last;
+            *last = args[0].as_num();
+            Value::Num(result)
+        }
+        Function::Amplitude => {
+            let estimate = match state {
+                FunctionState::Amplitude(estimate) => estimate,
+                _ => unreachable!(),
+            };
+            let input = args[0].as_num().vec();
+            for channel in 0..2 {
+                let b0 = 1. - math::exp(-1. / (0.05 * sample_rate[channel]), accuracy);
+                estimate[channel] += b0 * (input[channel].abs() - estimate[channel]);
+            }
+            Value::Num(Num::from_vec(*estimate, FormType::Amplitude))
+        }
+        Function::Hold => {
+            let (val, last_gate) = match state {
+                FunctionState::Hold { val, gate } => (val, gate),
+                _ => unreachable!(),
+            };
+            let x = args[0].as_num();
+            let gate = args[1].as_num().vec();
+            let else_val = args[2].as_num().vec();
+            let mut result = [0.; 2];
+            for channel in 0..2 {
+                let gate = not_equal(gate[channel], 0.);
+                if gate && !last_gate[channel] {
+                    val[channel] = x.vec()[channel];
+                }
+                last_gate[channel] = gate;
+                result[channel] = if gate {
+                    val[channel]
+                } else {
+                    else_val[channel]
+                };
+            }
+            Value::Num(Num::from_vec(result, x.form))
+        }
+        Function::Accum => {
+            let accum = match state {
+                FunctionState::Accum(accum) => accum,
+                _ => unreachable!(),
+            };
+            let x = args[0].as_num();
+            let gate = args[1].as_num().vec();
+            let base = args[2].as_num().vec();
+            for channel in 0..2 {
+                accum[channel] = if not_equal(gate[channel], 0.) {
+                    accum[channel] + x.vec()[channel]
+                } else {
+                    base[channel]
+                };
+            }
+            Value::Num(Num::from_vec(*accum, x.form))
+        }
+        Function::Delay => {
+            let channels = match state {
+                FunctionState::Delay(channels) => channels,
+                _ => unreachable!(),
+            };
+            let x = args[0].as_num();
+            let delay = args[1].as_num().vec();
+            let reserve = args[2].as_num().vec();
+            let mut result = [0.; 2];
+            for channel in 0..2 {
+                let reserve_samples = (reserve[channel].min(globals.max_delay_seconds)
+                    * sample_rate[channel])
+                    .max(0.);
+                let delay_samples = (delay[channel].min(1.).max(0.) * reserve_samples) as u32;
+                let capacity =
+                    next_power_of_two((globals.max_delay_seconds * sample_rate[channel]) as u32);
+                result[channel] = channels[channel].update(
+                    x.vec()[channel],
+                    delay_samples,
+                    next_power_of_two(reserve_samples as u32),
+                    capacity,
+                );
+            }
+            Value::Num(Num::from_vec(result, x.form))
+        }
+        Function::LowBqFilter => biquad(state, &args, sample_rate, accuracy, BiquadType::Low),
+        Function::HighBqFilter => biquad(state, &args, sample_rate, accuracy, BiquadType::High),
+        Function::BandBqFilter => biquad(state, &args, sample_rate, accuracy, BiquadType::Band),
+        Function::NotchBqFilter => biquad(state, &args, sample_rate, accuracy, BiquadType::Notch),
+        Function::AllBqFilter => biquad(state, &args, sample_rate, accuracy, BiquadType::All),
+        Function::PeakBqFilter => biquad(state, &args, sample_rate, accuracy, BiquadType::Peak),
+        Function::SvFilter => {
+            let (low, band) = match state {
+                FunctionState::SvFilter { low, band } => (low, band),
+                _ => unreachable!(),
+            };
+            let input = args[0].as_num();
+            let freq = args[1].as_num().vec();
+            let q = args[2].as_num().vec();
+            let mut high = [0.; 2];
+            for channel in 0..2 {
+                let f = math::sin(
+                    consts::PI * freq[channel] / 2. / sample_rate[channel],
+                    accuracy,
+                );
+                for _ in 0..2 {
+                    low[channel] += f * band[channel];
+                    high[channel] =
+                        q[channel] * (input.vec()[channel] - band[channel]) - low[channel];
+                    band[channel] += f * high[channel];
+                }
+            }
+            let notch = [low[0] + high[0], low[1] + high[1]];
+            Value::Tuple(vec![
+                Value::Num(Num::from_vec(high, input.form)),
+                Value::Num(Num::from_vec(*low, input.form)),
+                Value::Num(Num::from_vec(*band, input.form)),
+                Value::Num(Num::from_vec(notch, input.form)),
+            ])
+        }
+        Function::Noise => {
+            for lane in globals.rand_seed.iter_mut() {
+                *lane = i64::from(*lane as i32) * 16007;
+            }
+            let scale = 2_f64.powi(31);
+            Value::Num(Num::new(
+                f64::from(globals.rand_seed[1] as i32) / scale,
+                f64::from(globals.rand_seed[0] as i32) / scale,
+                FormType::Oscillator,
+            ))
+        }
+        Function::SinOsc => periodic(state, &args, sample_rate, |p, _| {
+            math::sin(p * consts::PI * 2., accuracy)
+        }),
+        Function::SqrOsc => periodic(state, &args, sample_rate, |p, pulse_width| {
+            if p < pulse_width {
+                1.
+            } else {
+                -1.
+            }
+        }),
+        Function::SawOsc => periodic(state, &args, sample_rate, |p, _| 2. * p - 1.),
+        Function::TriOsc => periodic(state, &args, sample_rate, |p, _| 1. - (4. * p - 2.).abs()),
+        Function::RmpOsc => periodic(state, &args, sample_rate, |p, _| 1. - 2. * p),
+        Function::Mixdown => {
+            let array = args[0].as_array();
+            let mut result = [0.; 2];
+            for (index, item) in array.items.iter().enumerate() {
+                if array.is_active(index) {
+                    let item = item.as_num();
+                    result[0] += item.left;
+                    result[1] += item.right;
+                }
+            }
+            Value::Num(Num::from_vec(result, array.items[0].as_num().form))
+        }
+        Function::Note => {
+            let note = match state {
+                FunctionState::Note(note) => note,
+                _ => unreachable!(),
+            };
+            for event in &args[0].as_midi().events {
+                let param = f64::from(event.param);
+                match event.name {
+                    0 => {
+                        note.note = f64::from(event.note);
+                        note.velocity = param / 255.;
+                        note.active_count = note.active_count.wrapping_add(1);
+                    }
+                    1 => {
+                        if note.active_count > 0 {
+                            note.active_count -= 1;
+                        }
+                    }
+                    2 | 3 => note.aftertouch = param / 255.,
+                    4 => note.pitch = (param / 127.5 - 1.) * 6.,
+                    _ => {}
+                }
+            }
+            let active = if note.active_count > 0 { 1. } else { 0. };
+            Value::Tuple(vec![
+                Value::Num(Num::splat(active, FormType::None)),
+                Value::Num(Num::splat(note.note + note.pitch, FormType::Note)),
+                Value::Num(Num::splat(note.velocity, FormType::Amplitude)),
+                Value::Num(Num::splat(note.aftertouch, FormType::None)),
+            ])
+        }
+        Function::Voices => {
+            let notes = match state {
+                FunctionState::Voices(notes) => notes,
+                _ => unreachable!(),
+            };
+            let last_active = args[1].as_array();
+            let mut active_bitmap = 0u32;
+            for (index, item) in last_active.items.iter().enumerate() {
+                if not_equal(item.as_num().left, 0.) {
+                    active_bitmap |= 1 << index;
+                }
+            }
+
+            let mut result = Array::new(&VarType::Midi);
+            for event in &args[0].as_midi().events {
+                if event.name == 0 {
+                    let free_voice = (0..ARRAY_CAPACITY as usize)
+                        .find(|&index| (active_bitmap >> index) & 1 == 0);
+                    if let Some(index) = free_voice {
+                        notes[index] = event.note;
+                        active_bitmap |= 1 << index;
+                        push_midi(&mut result.items[index], *event);
+                    }
+                } else {
+                    for index in 0..ARRAY_CAPACITY as usize {
+                        let matches =
+                            notes[index] == event.note || event.name == 3 || event.name == 4;
+                        if (active_bitmap >> index) & 1 != 0 && matches {
+                            push_midi(&mut result.items[index], *event);
+                        }
+                    }
+                }
+            }
+            result.bitmap = active_bitmap;
+            Value::Array(result)
+        }
+        Function::Channel => {
+            let channel = args[1].as_num().left.min(16.).max(0.) as u8;
+            let mut result = Midi::default();
+            for event in &args[0].as_midi().events {
+                if event.channel == channel {
+                    result.push_event(*event);
+                }
+            }
+            Value::Midi(result)
+        }
+        Function::Indexed => {
+            let count = args[0].as_num().left.min(32.).max(0.) as u8;
+            let mut result = Array::new(&VarType::Num);
+            result.bitmap = (u64::from(!0u32) >> (32 - u32::from(count))) as u32;
+            for index in 0..count as usize {
+                result.items[index] = Value::Num(Num::splat(index as f64, FormType::None));
+            }
+            Value::Array(result)
+        }
+        Function::Adsr => {
+            let channels = match state {
+                FunctionState::Adsr(channels) => channels,
+                _ => unreachable!(),
+            };
+            let params: Vec<_> = args.iter().map(|arg| arg.as_num().vec()).collect();
+            let mut active = [0.; 2];
+            let mut value = [0.; 2];
+            for channel in 0..2 {
+                let (is_active, val) = channels[channel].update(
+                    params[0][channel],
+                    params[1][channel],
+                    params[2][channel],
+                    params[3][channel],
+                    params[4][channel],
+                    sample_rate[0],
+                    accuracy,
+                );
+                active[channel] = if is_active { 1. } else { 0. };
+                value[channel] = val;
+            }
+            Value::Tuple(vec![
+                Value::Num(Num::from_vec(active, FormType::None)),
+                Value::Num(Num::from_vec(value, FormType::Amplitude)),
+            ])
+        }
+    }
+}
+
+fn push_midi(value: &mut Value, event: MidiEvent) {
+    if let Value::Midi(midi) = value {
+        midi.push_event(event);
+    }
+}
+
+fn periodic(
+    state: &mut FunctionState,
+    args: &[Value],
+    sample_rate: [f64; 2],
+    next_value: impl Fn(f64, f64) -> f64,
+) -> Value {
+    let phase = match state {
+        FunctionState::Periodic(phase) => phase,
+        _ => unreachable!(),
+    };
+    let freq = args[0].as_num().vec();
+    let offset = args[1].as_num().vec();
+    let pulse_width = args.get(2).map(|arg| arg.as_num().vec()).unwrap_or([0.; 2]);
+    let mut result = [0.; 2];
+    for channel in 0..2 {
+        let input_phase = math::fract(offset[channel] + phase[channel]);
+        phase[channel] = math::fract(phase[channel] + freq[channel] / sample_rate[channel]);
+        result[channel] = next_value(input_phase, pulse_width[channel]);
+    }
+    Value::Num(Num::from_vec(result, FormType::Oscillator))
+}
+
+fn biquad(
+    state: &mut FunctionState,
+    args: &[Value],
+    sample_rate: [f64; 2],
+    accuracy: MathAccuracy,
+    filter_type: BiquadType,
+) -> Value {
+    let data = match state {
+        FunctionState::Biquad(data) => data,
+        _ => unreachable!(),
+    };
+    let input = args[0].as_num();
+    let freq = args[1].as_num().vec();
+    let q = args[2].as_num().vec();
+    let gain = if filter_type == BiquadType::Peak {
+        args[3].as_num().vec()
+    } else {
+        [0.; 2]
+    };
+
+    let changed = (0..2).any(|channel| {
+        not_equal(freq[channel], data.cached_freq[channel])
+            || not_equal(q[channel], data.cached_q[channel])
+            || not_equal(gain[channel], data.cached_gain[channel])
+    });
+    if changed {
+        data.cached_freq = freq;
+        data.cached_q = q;
+        data.cached_gain = gain;
+
+        for channel in 0..2 {
+            let q = q[channel].max(0.5);
+            let f0 = freq[channel].max(0.01);
+            let w0 = 2. * consts::PI * f0 / sample_rate[channel];
+            let alpha = math::sin(w0, accuracy) / (2. * q);
+            let cos = math::cos(w0, accuracy);
+
+            let (b0, b1, b2, a0, a1, a2) = match filter_type {
+                BiquadType::Low => (
+                    (1. - cos) / 2.,
+                    1. - cos,
+                    (1. - cos) / 2.,
+                    1. + alpha,
+                    -2. * cos,
+                    1. - alpha,
+                ),
+                BiquadType::High => (
+                    (1. + cos) / 2.,
+                    -(1. + cos),
+                    (1. + cos) / 2.,
+                    1. + alpha,
+                    -2. * cos,
+                    1. - alpha,
+                ),
+                BiquadType::Band => (alpha, 0., -alpha, 1. + alpha, -2. * cos, 1. - alpha),
+                BiquadType::Notch => (1., -2. * cos, 1., 1. + alpha, -2. * cos, 1. - alpha),
+                BiquadType::All => (
+                    1. - alpha,
+                    -2. * cos,
+                    1. + alpha,
+                    1. + alpha,
+                    -2. * cos,
+                    1. - alpha,
+                ),
+                BiquadType::Peak => {
+                    let g = gain[channel].max(0.001);
+                    (
+                        1. + alpha * g,
+                        -2. * cos,
+                        1. - alpha * g,
+                        1. + alpha / g,
+                        -2. * cos,
+                        1. - alpha / g,
+                    )
+                }
+            };
+            data.b0[channel] = b0 / a0;
+            data.b1[channel] = b1 / a0;
+            data.b2[channel] = b2 / a0;
+            data.a1[channel] = a1 / a0;
+            data.a2[channel] = a2 / a0;
+        }
+    }
+
+    let mut result = [0.; 2];
+    for channel in 0..2 {
+        let x = input.vec()[channel];
+        let out = data.b0[channel] * x
+            + data.b1[channel] * data.y1[channel]
+            + data.b2[channel] * data.y2[channel]
+            - data.a1[channel] * data.z1[channel]
+            - data.a2[channel] * data.z2[channel];
+        data.y2[channel] = data.y1[channel];
+        data.y1[channel] = x;
+        data.z2[channel] = data.z1[channel];
+        data.z1[channel] = out;
+        result[channel] = out;
+    }
+    Value::Num(Num::from_vec(result, input.form))
+}
+
+/// Rounds up to a power of two like `intrinsics::next_power_i32`, which leaves 0 as it is.
+fn next_power_of_two(x: u32) -> u32 {
+    if x == 0 {
+        0
+    } else {
+        x.next_power_of_two()
+    }
+}
+
+impl DelayChannel {
+    fn update(&mut self, x: f64, delay: u32, reserve_size: u32, capacity: u32) -> f64 {
+        let size = self.size;
+        let result = if size > 0 {
+            let write_pos = self.pos;
+            self.pos = (self.pos + 1) % size;
+            let result = if delay != 0 {
+                self.buffer[(write_pos.wrapping_add(size).wrapping_sub(delay) % size) as usize]
+            } else {
+                x
+            };
+            self.buffer[write_pos as usize] = x;
+            result
+        } else {
+            x
+        };
+
+        // The generated code claims a buffer big enough for the longest delay when the function is
+        // constructed, and clamps the size to it. The buffer here grows as it's needed instead,
+        // but keeps its contents the same way.
+        let new_size = reserve_size.min(capacity);
+        if new_size > size {
+            if new_size as usize > self.buffer.len() {
+                self.buffer.resize(new_size as usize, 0.);
+            }
+            for sample in &mut self.buffer[size as usize..new_size as usize] {
+                *sample = 0.;
+            }
+        } else if new_size < size {
+            self.pos %= new_size.max(1);
+        }
+        self.size = new_size;
+        result
+    }
+}
+
+impl AdsrChannel {
+    fn update(
+        &mut self,
+        trigger: f64,
+        attack: f64,
+        decay: f64,
+        sustain: f64,
+        release: f64,
+        sample_rate: f64,
+        accuracy: MathAccuracy,
+    ) -> (bool, f64) {
+        let trigger = not_equal(trigger, 0.);
+        if trigger != self.last_trigger {
+            if trigger {
+                self.phase = AdsrPhase::Attack;
+            } else {
+                self.release_val = self.last_val;
+                self.phase = if release > 0. {
+                    AdsrPhase::Release
+                } else {
+                    AdsrPhase::NotActive
+                };
+            }
+            self.pos = 0.;
+        }
+        self.last_trigger = trigger;
+
+        if self.phase != AdsrPhase::NotActive {
+            self.pos += 1. / sample_rate;
+            match self.phase {
+                AdsrPhase::Attack if self.pos >= attack => {
+                    self.pos = 0.;
+                    self.phase = if decay > 0. {
+                        AdsrPhase::Decay
+                    } else {
+                        AdsrPhase::Sustain
+                    };
+                }
+                AdsrPhase::Decay if self.pos >= decay => {
+                    self.pos = 0.;
+                    self.phase = AdsrPhase::Sustain;
+                }
+                AdsrPhase::Release if self.pos >= release => {
+                    self.pos = 0.;
+                    self.phase = AdsrPhase::NotActive;
+                }
+                _ => {}
+            }
+        }
+
+        let val = match self.phase {
+            AdsrPhase::Attack => self.pos / attack,
+            AdsrPhase::Decay => {
+                let f = 1. - math::pow(1. - self.pos / decay, 2., accuracy);
+                (1. - f) + sustain * f
+            }
+            AdsrPhase::Sustain => sustain,
+            AdsrPhase::Release => {
+                let f = 1. - math::pow(1. - self.pos / release, 2., accuracy);
+                self.release_val * (1. - f)
+            }
+            AdsrPhase::NotActive => 0.,
+        };
+        self.last_val = val;
+        (self.phase != AdsrPhase::NotActive, val)
+    }
+}
@@ -0,0 +1,306 @@
+use crate::codegen::MathAccuracy;
+use std::f64::consts;
+
+// These mirror the functions built in `codegen::math` step by step, so the interpreter rounds the
+// same way the generated code does. Anything that changes there needs changing here too.
+
+fn factorial(n: u32) -> f64 {
+    (1..=n).map(f64::from).product()
+}
+
+// evaluates a polynomial in x, with coefficients ordered from the highest power down
+fn horner(x: f64, coefficients: &[f64]) -> f64 {
+    let (&first, rest) = coefficients.split_first().unwrap();
+    rest.iter()
+        .fold(first, |r, &coefficient| r * x + coefficient)
+}
+
+/// Rounds to the nearest integer, with ties going to the even one, like `roundpd` does.
+pub fn round(x: f64) -> f64 {
+    let rounded = x.round();
+    if (rounded - x).abs() == 0.5 {
+        2. * (x / 2.).round()
+    } else {
+        rounded
+    }
+}
+
+pub fn min(a: f64, b: f64) -> f64 {
+    if a < b {
+        a
+    } else {
+        b
+    }
+}
+
+pub fn max(a: f64, b: f64) -> f64 {
+    if a > b {
+        a
+    } else {
+        b
+    }
+}
+
+pub fn modulo(x: f64, m: f64) -> f64 {
+    x - (x / m).floor() * m
+}
+
+pub fn fract(x: f64) -> f64 {
+    x - x.floor()
+}
+
+pub fn sin(x: f64, accuracy: MathAccuracy) -> f64 {
+    // keep in the range -pi..pi
+    let ranged_x = modulo(x, consts::PI * 2.);
+    let wrapped_x = if ranged_x > consts::PI {
+        ranged_x - consts::PI * 2.
+    } else {
+        ranged_x
+    };
+
+    if accuracy == MathAccuracy::Accurate {
+        let reflected_x = if wrapped_x > consts::FRAC_PI_2 {
+            consts::PI - wrapped_x
+        } else {
+            wrapped_x
+        };
+        let reflected_x = if reflected_x < -consts::FRAC_PI_2 {
+            -consts::PI - reflected_x
+        } else {
+            reflected_x
+        };
+
+        let coefficients: Vec<_> = (0..11)
+            .rev()
+            .map(|k| (-1_f64).powi(k as i32) / factorial(k * 2 + 1))
+            .collect();
+        return reflected_x * horner(reflected_x * reflected_x, &coefficients);
+    }
+
+    let y = 1.273_239_544_723_765 * wrapped_x
+        + (-0.405_284_734_566_521_37 * wrapped_x) * wrapped_x.abs();
+    if accuracy == MathAccuracy::Fast {
+        return y;
+    }
+
+    0.224_999_904_632_568_36 * (y * y.abs() - y) + y
+}
+
+pub fn cos(x: f64, accuracy: MathAccuracy) -> f64 {
+    sin(x + consts::PI / 2., accuracy)
+}
+
+pub fn tan(x: f64, accuracy: MathAccuracy) -> f64 {
+    sin(x, accuracy) / cos(x, accuracy)
+}
+
+pub fn pow(x: f64, y: f64, accuracy: MathAccuracy) -> f64 {
+    if x == 0. {
+        return 0.;
+    }
+
+    let r = exp2(log2(x, accuracy) * y, accuracy);
+
+    // the result is negative if x < 0 and if y is integer and odd
+    let sign = modulo(y.abs(), 2.).copysign(x) + 0.5;
+    r.copysign(sign)
+}
+
+pub fn exp(x: f64, accuracy: MathAccuracy) -> f64 {
+    exp2(x * consts::LOG2_E, accuracy)
+}
+
+pub fn exp2(x: f64, accuracy: MathAccuracy) -> f64 {
+    // cvtpd2dq gives the "integer indefinite" value for anything out of range
+    let rounded = round(x);
+    let x_int = if rounded >= f64::from(i32::min_value()) && rounded <= f64::from(i32::max_value())
+    {
+        rounded as i32
+    } else {
+        i32::min_value()
+    };
+    let x_frac = x - f64::from(x_int);
+
+    let r = match accuracy {
+        MathAccuracy::Balanced => horner(
+            x_frac,
+            &[
+                0.000_154_653_240_841_184_92,
+                0.001_339_529_154_378_738,
+                0.009_618_039_911_742_926,
+                0.055_503_406_540_083_23,
+                0.240_226_511_014_043_35,
+                0.693_147_200_072_417,
+                0.999_999_999_970_896_2,
+            ],
+        ),
+        accuracy => {
+            let degree = if accuracy == MathAccuracy::Fast {
+                4
+            } else {
+                12
+            };
+            let coefficients: Vec<_> = (0..=degree)
+                .rev()
+                .map(|k| consts::LN_2.powi(k as i32) / factorial(k))
+                .collect();
+            horner(x_frac, &coefficients)
+        }
+    };
+
+    // 2^x_int, built directly in the exponent bits
+    let k = x_int.wrapping_add(1023).wrapping_shl(20);
+    r * f64::from_bits(u64::from(k as u32) << 32)
+}
+
+pub fn exp10(x: f64, accuracy: MathAccuracy) -> f64 {
+    exp2(x * 10_f64.log2(), accuracy)
+}
+
+pub fn log(x: f64, accuracy: MathAccuracy) -> f64 {
+    log2(x, accuracy) / consts::LOG2_E
+}
+
+pub fn log2(x: f64, accuracy: MathAccuracy) -> f64 {
+    let bits = x.to_bits();
+    let ilogb = (bits >> 52).wrapping_sub(1023) as i32;
+    let p = f64::from_bits(bits & (u64::max_value() >> 12) | 1023 << 52);
+    let y = (p - 1.) / (p + 1.);
+    let y2 = y * y;
+
+    let r = match accuracy {
+        MathAccuracy::Balanced => horner(
+            y2,
+            &[
+                0.410_981_538_279_884_26,
+                0.402_155_483_170_645_3,
+                0.577_550_146_270_368_7,
+                0.961_787_806_001_666_5,
+                2.885_390_127_834_398_3,
+            ],
+        ),
+        accuracy => {
+            let term_count: u32 = if accuracy == MathAccuracy::Fast {
+                3
+            } else {
+                16
+            };
+            let coefficients: Vec<_> = (0..term_count)
+                .rev()
+                .map(|k| 2. / (f64::from(k * 2 + 1) * consts::LN_2))
+                .collect();
+            horner(y2, &coefficients)
+        }
+    };
+
+    r * y + f64::from(ilogb)
+}
+
+pub fn log10(x: f64, accuracy: MathAccuracy) -> f64 {
+    log2(x, accuracy) / 10_f64.log2()
+}
+
+// approximates atan(s) for s in -1..1
+fn atan_kernel(s: f64, accuracy: MathAccuracy) -> f64 {
+    match accuracy {
+        MathAccuracy::Fast => {
+            s * horner(
+                s * s,
+                &[0.020_835_1, -0.085_133, 0.180_141, -0.330_299_5, 0.999_866],
+            )
+        }
+        MathAccuracy::Balanced => {
+            let t = s * s;
+            let r = horner(
+                t,
+                &[
+                    0.002_823_638_962_581_753_7,
+                    -0.015_956_902_876_496_315,
+                    0.042_504_988_610_744_476,
+                    -0.074_890_092_015_266_42,
+                    0.106_347_933_411_598_2,
+                    -0.142_027_363_181_114_2,
+                    0.199_926_957_488_06,
+                    -0.333_331_018_686_294_56,
+                ],
+            );
+            s + s * (t * r)
+        }
+        MathAccuracy::Accurate => {
+            let halve_angle = |s: f64| s / (1. + (1. + s * s).sqrt());
+            let reduced_s = halve_angle(halve_angle(s));
+
+            let coefficients: Vec<_> = (0..11)
+                .rev()
+                .map(|k| (-1_f64).powi(k) / f64::from(k * 2 + 1))
+                .collect();
+            reduced_s * horner(reduced_s * reduced_s, &coefficients) * 4.
+        }
+    }
+}
+
+fn atan2k(y: f64, x: f64, accuracy: MathAccuracy) -> f64 {
+    let q = if x < 0. { -2. } else { 0. };
+    let x = x.abs();
+    let cond = y > x;
+    let rx = if cond { y } else { x };
+    let ry = if cond { -x } else { y };
+    let q = q + if cond { 1. } else { 0. };
+
+    q * consts::FRAC_PI_2 + atan_kernel(ry / rx, accuracy)
+}
+
+pub fn asin(x: f64, accuracy: MathAccuracy) -> f64 {
+    let atan_val = atan2k(x.abs(), ((1. + x) * (1. - x)).sqrt(), accuracy);
+    atan_val * if x < 0. { -1. } else { 1. }
+}
+
+pub fn acos(x: f64, accuracy: MathAccuracy) -> f64 {
+    let atan_val = atan2k(((1. + x) * (1. - x)).sqrt(), x.abs(), accuracy);
+    let is_negative = x < 0.;
+    atan_val * if is_negative { -1. } else { 1. } + if is_negative { consts::PI } else { 0. }
+}
+
+pub fn atan(x: f64, accuracy: MathAccuracy) -> f64 {
+    let s = x.abs();
+    let is_inverted = s > 1.;
+    let s = if is_inverted { 1. / s } else { s };
+    let t = atan_kernel(s, accuracy);
+    let t = if is_inverted {
+        consts::FRAC_PI_2 - t
+    } else {
+        t
+    };
+    t.copysign(x)
+}
+
+pub fn atan2(y: f64, x: f64, accuracy: MathAccuracy) -> f64 {
+    let r = atan2k(y.abs(), x, accuracy) * if x < 0. { -1. } else { 1. };
+    let r = if x == 0. { consts::FRAC_PI_2 } else { r };
+    let r = if y == 0. {
+        if x < 0. {
+            consts::PI
+        } else {
+            0.
+        }
+    } else {
+        r
+    };
+    r * if y < 0. { -1. } else { 1. }
+}
+
+pub fn sinh(x: f64, accuracy: MathAccuracy) -> f64 {
+    (exp(x, accuracy) - exp(-x, accuracy)) * 0.5
+}
+
+pub fn cosh(x: f64, accuracy: MathAccuracy) -> f64 {
+    (exp(x, accuracy) + exp(-x, accuracy)) * 0.5
+}
+
+pub fn tanh(x: f64, accuracy: MathAccuracy) -> f64 {
+    sinh(x, accuracy) / cosh(x, accuracy)
+}
+
+pub fn hypot(x: f64, y: f64) -> f64 {
+    (x * x + y * y).sqrt()
+}
@@ -0,0 +1,196 @@
+mod block;
+mod controls;
+mod converters;
+mod functions;
+mod math;
+mod oversample;
+mod surface;
+#[cfg(test)]
+mod tests;
+mod value;
+
+pub use self::value::{Array, Midi, MidiEvent, Num, Value};
+
+use self::surface::SurfaceInstance;
+use crate::codegen::{MathAccuracy, DEFAULT_CONTROL_RATE_DIVISOR, DEFAULT_MAX_DELAY_SECONDS};
+use crate::frontend::{mir_optimizer, Transaction};
+use crate::mir::{Block, BlockRef, IdAllocator, IncrementalIdAllocator, Root, Surface, SurfaceRef};
+use std::collections::HashMap;
+
+/// Global state shared by everything running in the interpreter, the counterpart of the globals
+/// in the runtime library and the options in `TargetProperties` that change behaviour.
+#[derive(Debug, Clone)]
+pub struct Globals {
+    pub sample_rate: [f64; 2],
+    pub bpm: [f64; 2],
+    pub rand_seed: [i64; 2],
+    pub control_rate_divisor: u32,
+    pub max_delay_seconds: f64,
+    pub math_accuracy: MathAccuracy,
+}
+
+/// The MIR the interpreter is running.
+pub struct Program {
+    surfaces: HashMap<SurfaceRef, Surface>,
+    blocks: HashMap<BlockRef, Block>,
+}
+
+impl Program {
+    fn surface(&self, surface: SurfaceRef) -> &Surface {
+        self.surfaces
+            .get(&surface)
+            .unwrap_or_else(|| panic!("Surface {} hasn't been committed", surface))
+    }
+
+    fn block(&self, block: BlockRef) -> &Block {
+        self.blocks
+            .get(&block)
+            .unwrap_or_else(|| panic!("Block {} hasn't been committed", block))
+    }
+}
+
+/// Runs MIR sample by sample without any code generation, with the same semantics as the code
+/// `Runtime` generates. This makes it a reference to compare JIT output against, and a runtime for
+/// hosts where LLVM isn't available.
+///
+/// Transactions are committed like they are to a `Runtime`: the MIR goes through the same
+/// preparation passes, and function state is carried over to the nodes that are still there
+/// afterwards. Math functions follow the approximations picked by `MathAccuracy` step by step, so
+/// results match the generated code closely, if not always to the last bit. A few things aren't
+/// modelled:
+///
+///  - Denormals are left alone, as with `DenormalMode::FlushToZero`, and outputs aren't sanitized
+///    or profiled.
+///  - Default-initialized groups in extracted surfaces are kept per voice instead of shared.
+pub struct Interpreter {
+    program: Program,
+    root: Root,
+    root_sockets: Vec<Value>,
+    root_instance: Option<SurfaceInstance>,
+    globals: Globals,
+    id_allocator: IncrementalIdAllocator,
+    include_ui: bool,
+}
+
+impl Interpreter {
+    pub fn new() -> Self {
+        Interpreter {
+            program: Program {
+                surfaces: HashMap::new(),
+                blocks: HashMap::new(),
+            },
+            root: Root::new(Vec::new()),
+            root_sockets: Vec::new(),
+            root_instance: None,
+            globals: Globals {
+                sample_rate: [44100.; 2],
+                bpm: [60.; 2],
+                rand_seed: [1, 31337],
+                control_rate_divisor: DEFAULT_CONTROL_RATE_DIVISOR,
+                max_delay_seconds: DEFAULT_MAX_DELAY_SECONDS,
+                math_accuracy: MathAccuracy::Balanced,
+            },
+            id_allocator: IncrementalIdAllocator::new(1),
+            include_ui: false,
+        }
+    }
+
+    pub fn commit(&mut self, transaction: Transaction) {
+        // the state has to be taken while the program still has the MIR it was built from
+        let old_state = self
+            .root_instance
+            .take()
+            .map(|root_instance| root_instance.into_state(&self.program));
+
+        if let Some(root) = transaction.root {
+            self.root = root;
+        }
+        for &id in transaction.surfaces.keys().chain(transaction.blocks.keys()) {
+            self.id_allocator.reserve(id);
+        }
+        let mut blocks = transaction.blocks;
+        mir_optimizer::prepare_blocks(blocks.values_mut());
+        let surfaces: Vec<_> = mir_optimizer::prepare_surfaces(
+            transaction.surfaces.into_iter().map(|(_, surface)| surface),
+            &mut self.id_allocator,
+            self.include_ui,
+        )
+        .collect();
+        self.program.blocks.extend(blocks);
+        self.program
+            .surfaces
+            .extend(surfaces.into_iter().map(|surface| (surface.id.id, surface)));
+
+        self.root_sockets = self.root.sockets.iter().map(Value::default_of).collect();
+        self.root_instance = if self.program.surfaces.contains_key(&0) {
+            let mut root_instance = SurfaceInstance::new(0, &self.program);
+            if let Some(old_state) = old_state {
+                root_instance.restore(old_state, &self.program);
+            }
+            Some(root_instance)
+        } else {
+            None
+        };
+    }
+
+    /// Runs one sample of the root surface.
+    pub fn run_update(&mut self) {
+        if let Some(root_instance) = &mut self.root_instance {
+            root_instance.update(&mut self.root_sockets, &self.program, &mut self.globals);
+        }
+    }
+
+    pub fn get_socket(&self, socket: usize) -> &Value {
+        &self.root_sockets[socket]
+    }
+
+    pub fn set_socket(&mut self, socket: usize, value: Value) {
+        self.root_sockets[socket] = value;
+    }
+
+    pub fn set_bpm(&mut self, bpm: f64) {
+        self.globals.bpm = [bpm; 2];
+    }
+
+    pub fn get_bpm(&self) -> f64 {
+        self.globals.bpm[0]
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f64) {
+        self.globals.sample_rate = [sample_rate; 2];
+    }
+
+    pub fn get_sample_rate(&self) -> f64 {
+        self.globals.sample_rate[0]
+    }
+
+    pub fn set_control_rate_divisor(&mut self, control_rate_divisor: u32) {
+        self.globals.control_rate_divisor = control_rate_divisor;
+    }
+
+    pub fn set_max_delay_seconds(&mut self, max_delay_seconds: f64) {
+        self.globals.max_delay_seconds = max_delay_seconds;
+    }
+
+    pub fn set_math_accuracy(&mut self, math_accuracy: MathAccuracy) {
+        self.globals.math_accuracy = math_accuracy;
+    }
+
+    /// Sets whether nodes that don't affect the output are kept, like
+    /// `TargetProperties::include_ui`. Only affects surfaces committed afterwards.
+    pub fn set_include_ui(&mut self, include_ui: bool) {
+        self.include_ui = include_ui;
+    }
+}
+
+impl IdAllocator for Interpreter {
+    fn alloc_id(&mut self) -> u64 {
+        self.id_allocator.alloc_id()
+    }
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Interpreter::new()
+    }
+}
@@ -0,0 +1,171 @@
+use super::value::{Midi, Num, Value};
+use crate::mir::{Node, OversampledSockets, ValueGroup};
+use crate::util::half_band::{get_side_coefficients, SIDE_TAPS};
+
+/// The state of a node that runs several times per sample, the counterpart of the scratch data
+/// `build_oversampled_layout` adds. Converted sockets are redirected to `inputs`, `midi_inputs`
+/// and `outputs` while the node runs.
+#[derive(Debug, Clone)]
+pub struct OversampleState {
+    sockets: OversampledSockets,
+    multiplier: u32,
+    last_inputs: Vec<Num>,
+    inputs: Vec<Num>,
+    midi_inputs: Vec<Value>,
+    outputs: Vec<Num>,
+    filters: Vec<Vec<DecimationStage>>,
+}
+
+/// One 2:1 stage of the half-band decimation filter, split into its two polyphase branches like
+/// the stages in the generated code.
+#[derive(Debug, Clone)]
+struct DecimationStage {
+    odd_history: [[f64; 2]; SIDE_TAPS * 2],
+    even_history: [[f64; 2]; SIDE_TAPS],
+}
+
+impl OversampleState {
+    pub fn new(node: &Node, parent_groups: &[ValueGroup]) -> Self {
+        let sockets = node.get_oversampled_sockets(parent_groups);
+        let stage_count = node.oversample_factor.stage_count();
+        OversampleState {
+            multiplier: node.oversample_factor.multiplier(),
+            last_inputs: vec![Num::zero(); sockets.inputs.len()],
+            inputs: vec![Num::zero(); sockets.inputs.len()],
+            midi_inputs: vec![Value::Midi(Midi::default()); sockets.midi_inputs.len()],
+            outputs: vec![Num::zero(); sockets.outputs.len()],
+            filters: (0..sockets.outputs.len())
+                .map(|_| {
+                    (0..stage_count)
+                        .map(|_| DecimationStage {
+                            odd_history: [[0.; 2]; SIDE_TAPS * 2],
+                            even_history: [[0.; 2]; SIDE_TAPS],
+                        })
+                        .collect()
+                })
+                .collect(),
+            sockets,
+        }
+    }
+
+    /// Runs the steps of one host sample. `sockets` holds the values of the node's sockets at the
+    /// host rate, and `run_step` runs the node once on them at the oversampled rate.
+    pub fn update(&mut self, sockets: &mut [Value], mut run_step: impl FnMut(&mut [Value])) {
+        for (midi_input, &socket_index) in
+            self.midi_inputs.iter_mut().zip(&self.sockets.midi_inputs)
+        {
+            *midi_input = sockets[socket_index].clone();
+        }
+        let host_inputs: Vec<_> = self
+            .sockets
+            .inputs
+            .iter()
+            .map(|&socket_index| sockets[socket_index].clone())
+            .collect();
+        let host_midi_inputs: Vec<_> = self
+            .sockets
+            .midi_inputs
+            .iter()
+            .map(|&socket_index| sockets[socket_index].clone())
+            .collect();
+
+        let mut samples = vec![Vec::with_capacity(self.multiplier as usize); self.outputs.len()];
+        for step in 0..self.multiplier {
+            let progress = f64::from(step + 1) / f64::from(self.multiplier);
+            for ((input, last_input), host_input) in self
+                .inputs
+                .iter_mut()
+                .zip(&self.last_inputs)
+                .zip(&host_inputs)
+            {
+                let group = host_input.as_num();
+                *input = Num::new(
+                    last_input.left + (group.left - last_input.left) * progress,
+                    last_input.right + (group.right - last_input.right) * progress,
+                    group.form,
+                );
+            }
+
+            for (input, &socket_index) in self.inputs.iter().zip(&self.sockets.inputs) {
+                sockets[socket_index] = Value::Num(*input);
+            }
+            for (midi_input, &socket_index) in
+                self.midi_inputs.iter().zip(&self.sockets.midi_inputs)
+            {
+                sockets[socket_index] = midi_input.clone();
+            }
+            for (output, &socket_index) in self.outputs.iter().zip(&self.sockets.outputs) {
+                sockets[socket_index] = Value::Num(*output);
+            }
+            run_step(sockets);
+
+            // MIDI events only happen once per host sample
+            for midi_input in &mut self.midi_inputs {
+                *midi_input = Value::Midi(Midi::default());
+            }
+            for ((output, output_samples), &socket_index) in self
+                .outputs
+                .iter_mut()
+                .zip(&mut samples)
+                .zip(&self.sockets.outputs)
+            {
+                *output = sockets[socket_index].as_num();
+                output_samples.push(output.vec());
+            }
+        }
+
+        for ((last_input, host_input), &socket_index) in self
+            .last_inputs
+            .iter_mut()
+            .zip(host_inputs)
+            .zip(&self.sockets.inputs)
+        {
+            *last_input = host_input.as_num();
+            sockets[socket_index] = host_input;
+        }
+        for (host_midi_input, &socket_index) in
+            host_midi_inputs.into_iter().zip(&self.sockets.midi_inputs)
+        {
+            sockets[socket_index] = host_midi_input;
+        }
+
+        let coefficients = get_side_coefficients();
+        for (((output, mut output_samples), filter), &socket_index) in self
+            .outputs
+            .iter()
+            .zip(samples)
+            .zip(&mut self.filters)
+            .zip(&self.sockets.outputs)
+        {
+            for stage in filter.iter_mut() {
+                output_samples = output_samples
+                    .chunks(2)
+                    .map(|pair| stage.decimate(&coefficients, pair[0], pair[1]))
+                    .collect();
+            }
+            sockets[socket_index] = Value::Num(Num::from_vec(output_samples[0], output.form));
+        }
+    }
+}
+
+impl DecimationStage {
+    fn decimate(&mut self, coefficients: &[f64], even: [f64; 2], odd: [f64; 2]) -> [f64; 2] {
+        self.odd_history.rotate_right(1);
+        self.odd_history[0] = odd;
+        self.even_history.rotate_right(1);
+        self.even_history[0] = even;
+
+        let mut result = [0.; 2];
+        for (channel, result) in result.iter_mut().enumerate() {
+            *result = self.even_history[SIDE_TAPS - 1][channel] * 0.5;
+
+            // the side taps are symmetric around the center, so pairs of samples share a multiply
+            for (tap, &coefficient) in coefficients.iter().enumerate() {
+                let tap_sum = self.odd_history[SIDE_TAPS - 1 - tap][channel]
+                    + self.odd_history[SIDE_TAPS + tap][channel];
+                *result += tap_sum * coefficient;
+            }
+        }
+        result
+    }
+}
@@ -0,0 +1,426 @@
+use super::block::BlockInstance;
+use super::functions::FunctionState;
+use super::oversample::OversampleState;
+use super::value::{Num, Value};
+use super::{Globals, Program};
+use crate::mir::block::Function;
+use crate::mir::{
+    BlockRef, Node, NodeData, OversampleFactor, SurfaceRef, UpdateRate, ValueGroup,
+    ValueGroupSource, ARRAY_CAPACITY,
+};
+use std::collections::HashMap;
+
+/// The state of one instance of a surface: the value of each of its groups, and the state of each
+/// of its nodes.
+#[derive(Debug, Clone)]
+pub struct SurfaceInstance {
+    surface: SurfaceRef,
+    groups: Vec<Value>,
+    nodes: Vec<NodeInstance>,
+}
+
+#[derive(Debug, Clone)]
+struct NodeInstance {
+    kind: NodeKind,
+    control_rate: Option<ControlRateState>,
+    oversample: Option<OversampleState>,
+}
+
+#[derive(Debug, Clone)]
+enum NodeKind {
+    Dummy,
+    Custom(BlockInstance),
+    Group(SurfaceInstance),
+    ExtractGroup(Vec<SurfaceInstance>),
+    UnitDelay,
+}
+
+/// Identifies a node across commits, in the same way as the runtime's state migration. Nodes are
+/// matched by what they contain and their order among the nodes in the surface containing the
+/// same thing. Extracted groups get a new surface each time their parent is prepared, so they're
+/// matched by order alone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum NodeKey {
+    Custom(BlockRef),
+    Group(SurfaceRef),
+    ExtractGroup,
+}
+
+enum NodeState {
+    Custom(Vec<(Function, FunctionState)>),
+    Group(SurfaceState),
+    ExtractGroup(Vec<SurfaceState>),
+}
+
+/// The state of every function call in a surface instance, taken out of it before a commit so it
+/// can be carried over to the instance built afterwards. Like in the runtime, the values of groups
+/// and controls aren't carried over.
+#[derive(Default)]
+pub struct SurfaceState {
+    nodes: HashMap<NodeKey, Vec<NodeState>>,
+}
+
+fn get_node_key(node: &Node) -> Option<NodeKey> {
+    match node.data {
+        NodeData::Custom { block, .. } => Some(NodeKey::Custom(block)),
+        NodeData::Group(surface) => Some(NodeKey::Group(surface)),
+        NodeData::ExtractGroup { .. } => Some(NodeKey::ExtractGroup),
+        NodeData::Dummy | NodeData::UnitDelay => None,
+    }
+}
+
+/// The state of a node that only runs every `control_rate_divisor` samples. Interpolated sockets
+/// are written into `targets` when the node runs, and their groups ramp from `starts` towards
+/// them on the samples in between.
+#[derive(Debug, Clone)]
+struct ControlRateState {
+    phase: u32,
+    interpolated_sockets: Vec<usize>,
+    starts: Vec<Num>,
+    targets: Vec<Num>,
+}
+
+impl SurfaceInstance {
+    pub fn new(surface: SurfaceRef, program: &Program) -> Self {
+        let mir = program.surface(surface);
+        SurfaceInstance {
+            surface,
+            groups: mir
+                .groups
+                .iter()
+                .map(|group| match &group.source {
+                    ValueGroupSource::Default(value) => Value::from_constant(value),
+                    _ => Value::default_of(&group.value_type),
+                })
+                .collect(),
+            nodes: mir
+                .nodes
+                .iter()
+                .map(|node| NodeInstance::new(node, &mir.groups, program))
+                .collect(),
+        }
+    }
+
+    /// Takes the state of the instance's function calls out of it. `program` must still have the
+    /// MIR the instance was built from.
+    pub fn into_state(self, program: &Program) -> SurfaceState {
+        let mir = program.surface(self.surface);
+        let mut state = SurfaceState::default();
+        for (node, instance) in mir.nodes.iter().zip(self.nodes) {
+            let key = match get_node_key(node) {
+                Some(key) => key,
+                None => continue,
+            };
+            let node_state = match (instance.kind, &node.data) {
+                (NodeKind::Custom(block_instance), NodeData::Custom { block, .. }) => {
+                    NodeState::Custom(block_instance.into_function_states(program.block(*block)))
+                }
+                (NodeKind::Group(surface_instance), _) => {
+                    NodeState::Group(surface_instance.into_state(program))
+                }
+                (NodeKind::ExtractGroup(voices), _) => NodeState::ExtractGroup(
+                    voices
+                        .into_iter()
+                        .map(|voice| voice.into_state(program))
+                        .collect(),
+                ),
+                _ => unreachable!(),
+            };
+            state
+                .nodes
+                .entry(key)
+                .or_insert_with(Vec::new)
+                .push(node_state);
+        }
+        state
+    }
+
+    /// Carries the state taken from a previous instance over to this one, which must have just
+    /// been built. Anything that isn't carried over keeps its initial value.
+    pub fn restore(&mut self, mut state: SurfaceState, program: &Program) {
+        let mir = program.surface(self.surface);
+        let mut old_states: HashMap<_, _> = state
+            .nodes
+            .drain()
+            .map(|(key, node_states)| (key, node_states.into_iter()))
+            .collect();
+
+        for (node, instance) in mir.nodes.iter().zip(self.nodes.iter_mut()) {
+            let old_state = match get_node_key(node) {
+                Some(key) => old_states.get_mut(&key).and_then(Iterator::next),
+                None => continue,
+            };
+            match (&mut instance.kind, &node.data, old_state) {
+                (
+                    NodeKind::Custom(block_instance),
+                    NodeData::Custom { block, .. },
+                    Some(NodeState::Custom(function_states)),
+                ) => {
+                    block_instance.restore_function_states(program.block(*block), function_states);
+                }
+                (NodeKind::Group(surface_instance), _, Some(NodeState::Group(surface_state))) => {
+                    surface_instance.restore(surface_state, program);
+                }
+                (
+                    NodeKind::ExtractGroup(voices),
+                    _,
+                    Some(NodeState::ExtractGroup(voice_states)),
+                ) => {
+                    for (voice, voice_state) in voices.iter_mut().zip(voice_states) {
+                        voice.restore(voice_state, program);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Runs one sample of the surface. `sockets` holds the values of the parent node's sockets,
+    /// which groups sourced from a socket read and write.
+    pub fn update(&mut self, sockets: &mut [Value], program: &Program, globals: &mut Globals) {
+        let mir = program.surface(self.surface);
+        for (group, value) in mir.groups.iter().zip(self.groups.iter_mut()) {
+            if let ValueGroupSource::Socket(socket_index) = group.source {
+                *value = sockets[socket_index].clone();
+            }
+        }
+
+        for (node, instance) in mir.nodes.iter().zip(self.nodes.iter_mut()) {
+            instance.update(node, &mut self.groups, program, globals);
+        }
+
+        for (group, value) in mir.groups.iter().zip(self.groups.iter()) {
+            if let ValueGroupSource::Socket(socket_index) = group.source {
+                sockets[socket_index] = value.clone();
+            }
+        }
+    }
+}
+
+impl NodeInstance {
+    fn new(node: &Node, parent_groups: &[ValueGroup], program: &Program) -> Self {
+        let kind = match &node.data {
+            NodeData::Dummy => NodeKind::Dummy,
+            NodeData::Custom {
+                block,
+                control_initializers,
+            } => NodeKind::Custom(BlockInstance::new(
+                program.block(*block),
+                control_initializers,
+            )),
+            NodeData::Group(surface) => NodeKind::Group(SurfaceInstance::new(*surface, program)),
+            NodeData::ExtractGroup { surface, .. } => NodeKind::ExtractGroup(
+                (0..ARRAY_CAPACITY)
+                    .map(|_| SurfaceInstance::new(*surface, program))
+                    .collect(),
+            ),
+            NodeData::UnitDelay => NodeKind::UnitDelay,
+        };
+        let control_rate = if node.update_rate == UpdateRate::Audio {
+            None
+        } else {
+            let interpolated_sockets = node.get_interpolated_sockets(parent_groups);
+            Some(ControlRateState {
+                phase: 0,
+                starts: vec![Num::zero(); interpolated_sockets.len()],
+                targets: vec![Num::zero(); interpolated_sockets.len()],
+                interpolated_sockets,
+            })
+        };
+        let oversample = if node.oversample_factor == OversampleFactor::None {
+            None
+        } else {
+            Some(OversampleState::new(node, parent_groups))
+        };
+        NodeInstance {
+            kind,
+            control_rate,
+            oversample,
+        }
+    }
+
+    fn update(
+        &mut self,
+        node: &Node,
+        groups: &mut [Value],
+        program: &Program,
+        globals: &mut Globals,
+    ) {
+        let rate = match &mut self.control_rate {
+            Some(rate) => rate,
+            None => {
+                NodeInstance::run(
+                    &mut self.kind,
+                    &mut self.oversample,
+                    node,
+                    groups,
+                    program,
+                    globals,
+                );
+                return;
+            }
+        };
+
+        if rate.phase == 0 {
+            // interpolation starts from wherever the groups are now, so there's no jump if the
+            // previous ramp was interrupted
+            for (start, &socket_index) in rate.starts.iter_mut().zip(&rate.interpolated_sockets) {
+                *start = groups[node.sockets[socket_index].group_id].as_num();
+            }
+
+            // the node writes interpolated sockets into their targets instead of their groups
+            let mut held_values = Vec::with_capacity(rate.interpolated_sockets.len());
+            for (target, &socket_index) in rate.targets.iter().zip(&rate.interpolated_sockets) {
+                let group = &mut groups[node.sockets[socket_index].group_id];
+                held_values.push(std::mem::replace(group, Value::Num(*target)));
+            }
+            NodeInstance::run(
+                &mut self.kind,
+                &mut self.oversample,
+                node,
+                groups,
+                program,
+                globals,
+            );
+            for ((target, &socket_index), held_value) in rate
+                .targets
+                .iter_mut()
+                .zip(&rate.interpolated_sockets)
+                .zip(held_values)
+            {
+                let group = &mut groups[node.sockets[socket_index].group_id];
+                *target = std::mem::replace(group, held_value).as_num();
+            }
+        }
+
+        let divisor = globals.control_rate_divisor;
+        let next_phase = rate.phase + 1;
+        if !rate.interpolated_sockets.is_empty() {
+            let progress = f64::from(next_phase) / f64::from(divisor);
+            for ((start, target), &socket_index) in rate
+                .starts
+                .iter()
+                .zip(&rate.targets)
+                .zip(&rate.interpolated_sockets)
+            {
+                groups[node.sockets[socket_index].group_id] = Value::Num(Num::new(
+                    start.left + (target.left - start.left) * progress,
+                    start.right + (target.right - start.right) * progress,
+                    target.form,
+                ));
+            }
+        }
+        rate.phase = if next_phase == divisor { 0 } else { next_phase };
+    }
+
+    /// Runs the node on the values of the groups its sockets are connected to. Oversampled nodes
+    /// are run once per step, with the sample rate scaled while they run.
+    fn run(
+        kind: &mut NodeKind,
+        oversample: &mut Option<OversampleState>,
+        node: &Node,
+        groups: &mut [Value],
+        program: &Program,
+        globals: &mut Globals,
+    ) {
+        let mut sockets: Vec<_> = node
+            .sockets
+            .iter()
+            .map(|socket| groups[socket.group_id].clone())
+            .collect();
+
+        match oversample {
+            Some(oversample) => {
+                let host_sample_rate = globals.sample_rate;
+                let multiplier = f64::from(node.oversample_factor.multiplier());
+                globals.sample_rate = [
+                    host_sample_rate[0] * multiplier,
+                    host_sample_rate[1] * multiplier,
+                ];
+                oversample.update(&mut sockets, |sockets| {
+                    NodeInstance::run_step(kind, node, sockets, program, globals)
+                });
+                globals.sample_rate = host_sample_rate;
+            }
+            None => NodeInstance::run_step(kind, node, &mut sockets, program, globals),
+        }
+
+        for (socket, value) in node.sockets.iter().zip(sockets) {
+            groups[socket.group_id] = value;
+        }
+    }
+
+    /// Runs one step of the node itself, which works directly on the values of its sockets.
+    fn run_step(
+        kind: &mut NodeKind,
+        node: &Node,
+        sockets: &mut [Value],
+        program: &Program,
+        globals: &mut Globals,
+    ) {
+        match (kind, &node.data) {
+            (NodeKind::Dummy, _) => {}
+            (NodeKind::Custom(instance), NodeData::Custom { block, .. }) => {
+                instance.update(program.block(*block), sockets, globals);
+            }
+            (NodeKind::Group(instance), _) => instance.update(sockets, program, globals),
+            (
+                NodeKind::ExtractGroup(voices),
+                NodeData::ExtractGroup {
+                    source_sockets,
+                    dest_sockets,
+                    ..
+                },
+            ) => {
+                // only the voices active in every source array are updated
+                let active_bitmap = if source_sockets.is_empty() {
+                    !0
+                } else {
+                    source_sockets
+                        .iter()
+                        .fold(!0, |acc, &socket| acc & sockets[socket].as_array().bitmap)
+                };
+
+                for (voice_index, voice) in voices.iter_mut().enumerate() {
+                    if (active_bitmap >> voice_index) & 1 == 0 {
+                        continue;
+                    }
+
+                    let is_extracted = |socket_index: usize| {
+                        source_sockets.contains(&socket_index)
+                            || dest_sockets.contains(&socket_index)
+                    };
+                    let mut voice_sockets: Vec<_> = sockets
+                        .iter()
+                        .enumerate()
+                        .map(|(socket_index, value)| {
+                            if is_extracted(socket_index) {
+                                value.as_array().items[voice_index].clone()
+                            } else {
+                                value.clone()
+                            }
+                        })
+                        .collect();
+                    voice.update(&mut voice_sockets, program, globals);
+                    for (socket_index, value) in voice_sockets.into_iter().enumerate() {
+                        if is_extracted(socket_index) {
+                            sockets[socket_index].as_array_mut().items[voice_index] = value;
+                        } else {
+                            sockets[socket_index] = value;
+                        }
+                    }
+                }
+
+                for &socket in dest_sockets {
+                    sockets[socket].as_array_mut().bitmap = active_bitmap;
+                }
+            }
+            (NodeKind::UnitDelay, _) => {
+                // Unit delays are ordered after every other node, so copying here makes the value
+                // written this sample visible to readers of the delayed group on the next one.
+                sockets[1] = sockets[0].clone();
+            }
+            _ => unreachable!(),
+        }
+    }
+}
@@ -0,0 +1,265 @@
+use super::Interpreter;
+#[cfg(feature = "jit")]
+use crate::codegen::{OptimizationLevel, TargetProperties};
+use crate::frontend::Transaction;
+#[cfg(feature = "jit")]
+use crate::frontend::{c_api, Runtime};
+#[cfg(feature = "jit")]
+use crate::mir::{OversampleFactor, UpdateRate};
+use crate::mir::{ValueGroup, ValueGroupSource, VarType, ARRAY_CAPACITY};
+use crate::test_support::{
+    assert_close, build_delay_block, build_delay_groups, build_node, build_root, build_surface,
+    compile_block, SAMPLE_RATE,
+};
+
+#[cfg(feature = "jit")]
+const BPM: f64 = 120.;
+
+fn build_interpreter(transaction: Transaction) -> Interpreter {
+    let mut interpreter = Interpreter::new();
+    interpreter.set_sample_rate(SAMPLE_RATE);
+    interpreter.commit(transaction);
+    interpreter
+}
+
+// runs one sample and returns the left channel of the only root socket
+fn run_sample(interpreter: &mut Interpreter) -> f64 {
+    interpreter.run_update();
+    interpreter.get_socket(0).as_num().left
+}
+
+// runs a block that writes to the only root socket, and returns the left channel of each sample
+fn run_block(code: &str, sample_rate: f64, sample_count: usize) -> Vec<f64> {
+    let block = compile_block(1, code);
+    let surface = build_surface(
+        vec![ValueGroup::new(VarType::Num, ValueGroupSource::Socket(0))],
+        vec![build_node(&block, &[("out", 0)])],
+    );
+
+    let mut interpreter = Interpreter::new();
+    interpreter.set_sample_rate(sample_rate);
+    interpreter.commit(Transaction::new(build_root(), vec![surface], vec![block]));
+    (0..sample_count)
+        .map(|_| run_sample(&mut interpreter))
+        .collect()
+}
+
+#[test]
+fn interpreter_accumulates_each_sample() {
+    assert_eq!(
+        run_block("out:num = accum(1, 1)", SAMPLE_RATE, 8),
+        vec![1., 2., 3., 4., 5., 6., 7., 8.]
+    );
+}
+
+#[test]
+fn interpreter_delays_by_whole_samples() {
+    // Half of a 16 sample reserve at 1kHz. The buffer is sized after the first sample, which
+    // passes its input through, and the next 8 read the silence it starts with.
+    assert_eq!(
+        run_block("out:num = delay(accum(1, 1), 0.5, 0.016)", 1000., 16),
+        vec![1., 0., 0., 0., 0., 0., 0., 0., 0., 2., 3., 4., 5., 6., 7., 8.]
+    );
+}
+
+#[test]
+fn interpreter_inserted_node_keeps_delay_and_filter_state() {
+    let delay_block = build_delay_block();
+    let delay_node = build_node(&delay_block, &[("in", 1), ("out", 0)]);
+    let transaction = Transaction::new(
+        build_root(),
+        vec![build_surface(
+            build_delay_groups(),
+            vec![delay_node.clone()],
+        )],
+        vec![delay_block],
+    );
+    let mut reference = build_interpreter(transaction.clone());
+    let mut interpreter = build_interpreter(transaction);
+    for _ in 0..1000 {
+        run_sample(&mut reference);
+        run_sample(&mut interpreter);
+    }
+
+    // a node writing the same value as the default is inserted before the delay, so it moves to a
+    // new index but should carry on as if nothing happened
+    let source_block = compile_block(2, "out:num = 1");
+    let source_node = build_node(&source_block, &[("out", 1)]);
+    interpreter.commit(Transaction::new(
+        None,
+        vec![build_surface(
+            build_delay_groups(),
+            vec![source_node, delay_node],
+        )],
+        vec![source_block],
+    ));
+
+    for sample in 0..1000 {
+        assert_close(
+            run_sample(&mut interpreter),
+            run_sample(&mut reference),
+            &format!("sample {}", sample),
+        );
+    }
+}
+
+#[test]
+fn interpreter_changed_block_keeps_matching_function_state() {
+    let groups = vec![ValueGroup::new(VarType::Num, ValueGroupSource::Socket(0))];
+    let counter_block = compile_block(1, "out:num = accum(1, 1)");
+    let counter_node = build_node(&counter_block, &[("out", 0)]);
+    let mut interpreter = build_interpreter(Transaction::new(
+        build_root(),
+        vec![build_surface(groups.clone(), vec![counter_node])],
+        vec![counter_block],
+    ));
+    for _ in 0..100 {
+        run_sample(&mut interpreter);
+    }
+
+    // the counter is still the block's first function, so it keeps counting
+    let doubled_block = compile_block(1, "out:num = accum(1, 1) * 2");
+    let doubled_node = build_node(&doubled_block, &[("out", 0)]);
+    interpreter.commit(Transaction::new(
+        None,
+        vec![build_surface(groups.clone(), vec![doubled_node])],
+        vec![doubled_block],
+    ));
+    assert_close(run_sample(&mut interpreter), 202., "count after the edit");
+
+    // the block's first function is now a `last` call, which would output the count if the
+    // counter's state was carried into it
+    let last_block = compile_block(1, "out:num = last(5)");
+    let last_node = build_node(&last_block, &[("out", 0)]);
+    interpreter.commit(Transaction::new(
+        None,
+        vec![build_surface(groups, vec![last_node])],
+        vec![last_block],
+    ));
+    assert_close(
+        run_sample(&mut interpreter),
+        0.,
+        "first sample after the commit",
+    );
+    assert_close(
+        run_sample(&mut interpreter),
+        5.,
+        "second sample after the commit",
+    );
+}
+
+#[test]
+fn interpreter_extracted_voices_keep_their_state() {
+    // a counter in every voice, mixed down into the root socket
+    let voice_block = compile_block(1, "out:num = accum(1, 1)");
+    let voice_node = build_node(&voice_block, &[("out", 1)]);
+    let groups = vec![
+        ValueGroup::new(VarType::Num, ValueGroupSource::Socket(0)),
+        ValueGroup::new(VarType::new_array(VarType::Num), ValueGroupSource::None),
+    ];
+    let mix_block = compile_block(2, "out:num = mixdown(in:num[])");
+    let mix_node = build_node(&mix_block, &[("in", 1), ("out", 0)]);
+    let mut interpreter = build_interpreter(Transaction::new(
+        build_root(),
+        vec![build_surface(
+            groups.clone(),
+            vec![voice_node.clone(), mix_node],
+        )],
+        vec![voice_block, mix_block],
+    ));
+    let voice_count = f64::from(ARRAY_CAPACITY);
+    let mut mix = 0.;
+    for _ in 0..100 {
+        mix = run_sample(&mut interpreter);
+    }
+    assert_close(mix, voice_count * 100., "mix before the commit");
+
+    // committing the surface extracts the voices into a new surface, which is matched by order
+    let mix_block = compile_block(2, "out:num = mixdown(in:num[]) * 2");
+    let mix_node = build_node(&mix_block, &[("in", 1), ("out", 0)]);
+    interpreter.commit(Transaction::new(
+        None,
+        vec![build_surface(groups, vec![voice_node, mix_node])],
+        vec![mix_block],
+    ));
+
+    assert_close(
+        run_sample(&mut interpreter),
+        voice_count * 101. * 2.,
+        "mix after the commit",
+    );
+}
+
+// A control-rate oscillator feeding an oversampled filter and waveshaper, which is written to
+// the only root socket.
+#[cfg(feature = "jit")]
+fn build_transaction() -> Transaction {
+    let osc_block = compile_block(
+        1,
+        "out:num = sinOsc(220 Hz) * exp(0.3) + sin(sawOsc(3 Hz)) * 0.2",
+    );
+    let shaper_block = compile_block(
+        2,
+        "out:num = tanh(lowBqFilter(in:num, 1200 Hz, 0.8) * 3) + abs(in:num) ^ 1.5 * 0.1",
+    );
+
+    let mut osc_node = build_node(&osc_block, &[("out", 1)]);
+    osc_node.update_rate = UpdateRate::ControlInterpolate;
+    let mut shaper_node = build_node(&shaper_block, &[("in", 1), ("out", 0)]);
+    shaper_node.oversample_factor = OversampleFactor::X4;
+
+    let surface = build_surface(
+        vec![
+            ValueGroup::new(VarType::Num, ValueGroupSource::Socket(0)),
+            ValueGroup::new(VarType::Num, ValueGroupSource::None),
+        ],
+        vec![osc_node, shaper_node],
+    );
+    Transaction::new(build_root(), vec![surface], vec![osc_block, shaper_block])
+}
+
+#[cfg(feature = "jit")]
+#[test]
+fn interpreter_matches_runtime() {
+    crate::maxim_initialize();
+    let target = TargetProperties::new(
+        false,
+        OptimizationLevel::Editor,
+        c_api::create_host_machine(),
+    );
+    let math_accuracy = target.math_accuracy;
+    let transaction = build_transaction();
+
+    let mut runtime = Runtime::new(target);
+    runtime.audio().set_sample_rate(SAMPLE_RATE);
+    runtime.audio().set_bpm(BPM);
+    runtime.commit(transaction.clone());
+
+    let mut interpreter = Interpreter::new();
+    interpreter.set_sample_rate(SAMPLE_RATE);
+    interpreter.set_bpm(BPM);
+    interpreter.set_math_accuracy(math_accuracy);
+    interpreter.commit(transaction);
+
+    for sample in 0..4096 {
+        let runtime_output = unsafe {
+            runtime.audio().run_update();
+            *(runtime.audio().get_portal_ptr(0) as *const [f64; 2])
+        };
+        interpreter.run_update();
+        let interpreter_output = interpreter.get_socket(0).as_num();
+
+        for &(runtime_value, interpreter_value) in &[
+            (runtime_output[0], interpreter_output.left),
+            (runtime_output[1], interpreter_output.right),
+        ] {
+            assert!(
+                (runtime_value - interpreter_value).abs() < 1e-6,
+                "sample {}: runtime gave {}, interpreter gave {}",
+                sample,
+                runtime_value,
+                interpreter_value
+            );
+        }
+    }
+}
@@ -0,0 +1,161 @@
+use crate::ast::FormType;
+use crate::mir::{ConstantValue, VarType, ARRAY_CAPACITY, MIDI_EVENT_COUNT};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Num {
+    pub left: f64,
+    pub right: f64,
+    pub form: FormType,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MidiEvent {
+    pub name: u8,
+    pub channel: u8,
+    pub note: u8,
+    pub param: u8,
+}
+
+/// A MIDI value holds up to `MIDI_EVENT_COUNT` events, pushing more than that drops them.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Midi {
+    pub events: Vec<MidiEvent>,
+}
+
+/// An array holds `ARRAY_CAPACITY` items, with a bitmap of which of them are active.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Array {
+    pub bitmap: u32,
+    pub items: Vec<Value>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Num(Num),
+    Midi(Midi),
+    Tuple(Vec<Value>),
+    Array(Array),
+    Void,
+}
+
+impl Num {
+    pub fn new(left: f64, right: f64, form: FormType) -> Self {
+        Num { left, right, form }
+    }
+
+    pub fn splat(value: f64, form: FormType) -> Self {
+        Num::new(value, value, form)
+    }
+
+    pub fn zero() -> Self {
+        Num::splat(0., FormType::None)
+    }
+
+    pub fn vec(self) -> [f64; 2] {
+        [self.left, self.right]
+    }
+
+    pub fn from_vec(vec: [f64; 2], form: FormType) -> Self {
+        Num::new(vec[0], vec[1], form)
+    }
+
+    pub fn map(self, f: impl Fn(f64) -> f64) -> Self {
+        Num::new(f(self.left), f(self.right), self.form)
+    }
+
+    pub fn zip(self, other: Num, f: impl Fn(f64, f64) -> f64) -> Self {
+        Num::new(
+            f(self.left, other.left),
+            f(self.right, other.right),
+            self.form,
+        )
+    }
+
+    pub fn with_form(self, form: FormType) -> Self {
+        Num::new(self.left, self.right, form)
+    }
+}
+
+impl Midi {
+    pub fn push_event(&mut self, event: MidiEvent) {
+        if self.events.len() < MIDI_EVENT_COUNT as usize {
+            self.events.push(event);
+        }
+    }
+}
+
+impl Array {
+    pub fn new(item_type: &VarType) -> Self {
+        Array {
+            bitmap: 0,
+            items: (0..ARRAY_CAPACITY)
+                .map(|_| Value::default_of(item_type))
+                .collect(),
+        }
+    }
+
+    pub fn is_active(&self, index: usize) -> bool {
+        (self.bitmap >> index) & 1 != 0
+    }
+
+    pub fn set_active(&mut self, index: usize) {
+        self.bitmap |= 1 << index;
+    }
+}
+
+impl Value {
+    /// The value of a zero-initialized group of a type.
+    pub fn default_of(value_type: &VarType) -> Self {
+        match value_type {
+            VarType::Num => Value::Num(Num::zero()),
+            VarType::Midi => Value::Midi(Midi::default()),
+            VarType::Tuple(items) => Value::Tuple(items.iter().map(Value::default_of).collect()),
+            VarType::Array(item_type) => Value::Array(Array::new(item_type)),
+            VarType::Void => Value::Void,
+        }
+    }
+
+    pub fn from_constant(constant: &ConstantValue) -> Self {
+        match constant {
+            ConstantValue::Num(num) => Value::Num(Num::new(num.left, num.right, num.form)),
+            ConstantValue::Tuple(tuple) => {
+                Value::Tuple(tuple.items.iter().map(Value::from_constant).collect())
+            }
+        }
+    }
+
+    pub fn as_num(&self) -> Num {
+        match self {
+            Value::Num(num) => *num,
+            _ => panic!("Expected a num value"),
+        }
+    }
+
+    pub fn as_midi(&self) -> &Midi {
+        match self {
+            Value::Midi(midi) => midi,
+            _ => panic!("Expected a MIDI value"),
+        }
+    }
+
+    pub fn as_tuple(&self) -> &[Value] {
+        match self {
+            Value::Tuple(items) => items,
+            _ => panic!("Expected a tuple value"),
+        }
+    }
+
+    pub fn as_array(&self) -> &Array {
+        match self {
+            Value::Array(array) => array,
+            _ => panic!("Expected an array value"),
+        }
+    }
+
+    pub fn as_array_mut(&mut self) -> &mut Array {
+        match self {
+            Value::Array(array) => array,
+            _ => panic!("Expected an array value"),
+        }
+    }
+}
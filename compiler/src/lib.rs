@@ -3,11 +3,15 @@ mod compile_error;
 pub mod ast;
 pub mod codegen;
 pub mod frontend;
+pub mod interpreter;
 pub mod mir;
 pub mod parser;
 pub mod pass;
 pub mod util;
 
+#[cfg(test)]
+mod test_support;
+
 pub use crate::compile_error::{CompileError, CompileResult};
 
 // C api
@@ -1,7 +1,7 @@
 use crate::ast::ControlType;
 use std::fmt;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Hash)]
 pub struct Control {
     pub name: String,
     pub control_type: ControlType,
@@ -13,7 +13,7 @@ pub use self::statement::{Global, Statement};
 pub type BlockRef = PoolRef;
 pub type BlockId = PoolId<Block>;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Hash)]
 pub struct Block {
     pub id: BlockId,
     pub controls: Vec<Control>,
@@ -13,11 +13,11 @@ mod var_type;
 pub use self::block::{Block, BlockId, BlockRef, FUNCTION_TABLE};
 pub use self::constant_value::{ConstantNum, ConstantTuple, ConstantValue};
 pub use self::control_initializer::{ControlInitializer, GraphControlInitializer};
-pub use self::node::{Node, NodeData};
+pub use self::node::{Node, NodeData, OversampleFactor, OversampledSockets, UpdateRate};
 pub use self::pool_id::{IdAllocator, IncrementalIdAllocator};
 pub use self::root::Root;
 pub use self::source_map::{InternalNodeRef, SourceMap};
 pub use self::surface::{Surface, SurfaceId, SurfaceRef};
 pub use self::value_group::{ValueGroup, ValueGroupSource};
 pub use self::value_socket::ValueSocket;
-pub use self::var_type::VarType;
+pub use self::var_type::{VarType, ARRAY_CAPACITY, MIDI_EVENT_COUNT};
@@ -1,4 +1,4 @@
-use crate::mir::{BlockRef, ControlInitializer, SurfaceRef, ValueSocket};
+use crate::mir::{BlockRef, ControlInitializer, SurfaceRef, ValueGroup, ValueSocket, VarType};
 use std::fmt;
 
 #[derive(Debug, PartialEq, Clone, Hash)]
@@ -14,17 +14,122 @@ pub enum NodeData {
         source_sockets: Vec<usize>,
         dest_sockets: Vec<usize>,
     },
+    UnitDelay,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+#[repr(u8)]
+pub enum UpdateRate {
+    Audio,
+    ControlHold,
+    ControlInterpolate,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+#[repr(u8)]
+pub enum OversampleFactor {
+    None,
+    X2,
+    X4,
+    X8,
+}
+
+impl OversampleFactor {
+    pub fn multiplier(self) -> u32 {
+        1 << self.stage_count()
+    }
+
+    /// The number of 2x stages needed to reach this factor.
+    pub fn stage_count(self) -> u32 {
+        match self {
+            OversampleFactor::None => 0,
+            OversampleFactor::X2 => 1,
+            OversampleFactor::X4 => 2,
+            OversampleFactor::X8 => 3,
+        }
+    }
+}
+
+/// The sockets of an oversampled node that are converted between rates.
+#[derive(Debug, Clone)]
+pub struct OversampledSockets {
+    pub inputs: Vec<usize>,
+    pub midi_inputs: Vec<usize>,
+    pub outputs: Vec<usize>,
 }
 
 #[derive(Debug, PartialEq, Clone, Hash)]
 pub struct Node {
     pub sockets: Vec<ValueSocket>,
     pub data: NodeData,
+    pub update_rate: UpdateRate,
+    pub oversample_factor: OversampleFactor,
 }
 
 impl Node {
     pub fn new(sockets: Vec<ValueSocket>, data: NodeData) -> Node {
-        Node { sockets, data }
+        Node {
+            sockets,
+            data,
+            update_rate: UpdateRate::Audio,
+            oversample_factor: OversampleFactor::None,
+        }
+    }
+
+    /// Finds the sockets of the node that are interpolated between control-rate updates. These
+    /// are number sockets that are only written to by the node.
+    pub fn get_interpolated_sockets(&self, parent_groups: &[ValueGroup]) -> Vec<usize> {
+        if self.update_rate != UpdateRate::ControlInterpolate {
+            return Vec::new();
+        }
+
+        self.sockets
+            .iter()
+            .enumerate()
+            .filter(|(_, socket)| {
+                socket.value_written
+                    && !socket.value_read
+                    && parent_groups[socket.group_id].value_type == VarType::Num
+            })
+            .map(|(socket_index, _)| socket_index)
+            .collect()
+    }
+
+    /// Finds the sockets of the node that need to be converted between rates when it's
+    /// oversampled. Number sockets that are only read are interpolated up, ones that are only
+    /// written are decimated down. MIDI sockets that are only read get their events on the first
+    /// oversampled step only.
+    pub fn get_oversampled_sockets(&self, parent_groups: &[ValueGroup]) -> OversampledSockets {
+        let mut sockets = OversampledSockets {
+            inputs: Vec::new(),
+            midi_inputs: Vec::new(),
+            outputs: Vec::new(),
+        };
+        if self.oversample_factor == OversampleFactor::None {
+            return sockets;
+        }
+
+        for (socket_index, socket) in self.sockets.iter().enumerate() {
+            let value_type = &parent_groups[socket.group_id].value_type;
+            match (socket.value_read, socket.value_written, value_type) {
+                (true, false, VarType::Num) => sockets.inputs.push(socket_index),
+                (true, false, VarType::Midi) => sockets.midi_inputs.push(socket_index),
+                (false, true, VarType::Num) => sockets.outputs.push(socket_index),
+                _ => {}
+            }
+        }
+
+        sockets
+    }
+}
+
+impl fmt::Display for UpdateRate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UpdateRate::Audio => write!(f, "audio"),
+            UpdateRate::ControlHold => write!(f, "control hold"),
+            UpdateRate::ControlInterpolate => write!(f, "control interpolate"),
+        }
     }
 }
 
@@ -60,6 +165,10 @@ impl fmt::Display for Node {
                 write!(f, "extract @{}", surface)?;
                 Some((source_sockets, dest_sockets))
             }
+            NodeData::UnitDelay => {
+                write!(f, "delay")?;
+                None
+            }
         };
 
         write!(f, " (")?;
@@ -80,6 +189,15 @@ impl fmt::Display for Node {
             }
         }
 
-        write!(f, ")")
+        write!(f, ")")?;
+
+        if self.update_rate != UpdateRate::Audio {
+            write!(f, " [{}]", self.update_rate)?;
+        }
+        if self.oversample_factor != OversampleFactor::None {
+            write!(f, " [{}x oversampled]", self.oversample_factor.multiplier())?;
+        }
+
+        Ok(())
     }
 }
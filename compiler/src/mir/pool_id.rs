@@ -1,5 +1,5 @@
-use std::fmt;
 use std::marker::PhantomData;
+use std::{fmt, hash};
 
 pub type PoolRef = u64;
 
@@ -42,6 +42,12 @@ impl<T> PartialEq for PoolId<T> {
 
 impl<T> Eq for PoolId<T> {}
 
+impl<T> hash::Hash for PoolId<T> {
+    fn hash<H: hash::Hasher>(&self, state: &mut H) {
+        self.id.hash(state);
+    }
+}
+
 pub trait IdAllocator {
     fn alloc_id(&mut self) -> u64;
 }
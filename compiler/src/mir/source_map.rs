@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum InternalNodeRef {
@@ -32,6 +32,7 @@ impl InternalNodeRef {
 pub struct SourceMap {
     map: HashMap<ExternalNodeRef, InternalNodeRef>,
     deleted_index: usize,
+    delayed_sockets: HashSet<(usize, usize)>,
 }
 
 impl SourceMap {
@@ -39,6 +40,7 @@ impl SourceMap {
         SourceMap {
             map: HashMap::new(),
             deleted_index: 0,
+            delayed_sockets: HashSet::new(),
         }
     }
 
@@ -69,10 +71,35 @@ impl SourceMap {
             .collect()
     }
 
+    /// Finds the external node that maps to exactly `internal`, if there is one. Unlike
+    /// `map_to_external`, this can find nodes that were moved into a surface.
+    pub fn find_external(&self, internal: InternalNodeRef) -> Option<usize> {
+        let mapped = self
+            .map
+            .iter()
+            .filter_map(|(&external, &internal_ref)| match external {
+                ExternalNodeRef::Direct(external) if internal_ref == internal => Some(external),
+                _ => None,
+            })
+            .next();
+        match (mapped, internal) {
+            (Some(external), _) => Some(external),
+            (None, InternalNodeRef::Direct(node)) => self.map_to_external(node).into_iter().next(),
+            (None, InternalNodeRef::Surface(_, _)) => None,
+        }
+    }
+
     pub fn move_to(&mut self, movements: impl IntoIterator<Item = (usize, usize)>) {
         let mut new_map = self.map.clone();
+        let mut new_delayed_sockets = HashSet::new();
 
         for (before_internal, after_internal) in movements {
+            for &(delayed_node, delayed_socket) in &self.delayed_sockets {
+                if delayed_node == before_internal {
+                    new_delayed_sockets.insert((after_internal, delayed_socket));
+                }
+            }
+
             let externals = self.map_to_external(before_internal);
             for external in externals.into_iter() {
                 let old_internal = self.map_to_internal(external);
@@ -86,6 +113,17 @@ impl SourceMap {
         }
 
         self.map = new_map;
+        self.delayed_sockets = new_delayed_sockets;
+    }
+
+    /// Marks a socket on an internal node as reading its value group one sample late, because the
+    /// connection closes a feedback loop.
+    pub fn set_socket_delayed(&mut self, internal: usize, socket: usize) {
+        self.delayed_sockets.insert((internal, socket));
+    }
+
+    pub fn is_socket_delayed(&self, internal: usize, socket: usize) -> bool {
+        self.delayed_sockets.contains(&(internal, socket))
     }
 
     pub fn move_into(
@@ -1,7 +1,7 @@
 use crate::mir::pool_id::{PoolId, PoolRef};
 use crate::mir::SourceMap;
 use crate::mir::{Node, ValueGroup};
-use std::fmt;
+use std::{fmt, hash};
 
 pub type SurfaceRef = PoolRef;
 pub type SurfaceId = PoolId<Surface>;
@@ -25,6 +25,15 @@ impl Surface {
     }
 }
 
+// The source map only maps nodes back to the editor, so it isn't included.
+impl hash::Hash for Surface {
+    fn hash<H: hash::Hasher>(&self, state: &mut H) {
+        self.id.hash(state);
+        self.groups.hash(state);
+        self.nodes.hash(state);
+    }
+}
+
 impl fmt::Display for Surface {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         writeln!(f, "surface @{:?} {{", self.id)?;
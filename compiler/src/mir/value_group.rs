@@ -12,11 +12,26 @@ pub enum ValueGroupSource {
 pub struct ValueGroup {
     pub value_type: VarType,
     pub source: ValueGroupSource,
+    /// The ID of the exported parameter the group holds, if any. Only groups with a default value
+    /// can be parameters, since the parameter is written into the default's storage.
+    pub param: Option<usize>,
 }
 
 impl ValueGroup {
     pub fn new(value_type: VarType, source: ValueGroupSource) -> Self {
-        ValueGroup { value_type, source }
+        ValueGroup {
+            value_type,
+            source,
+            param: None,
+        }
+    }
+
+    pub fn new_param(value_type: VarType, source: ValueGroupSource, param: usize) -> Self {
+        ValueGroup {
+            value_type,
+            source,
+            param: Some(param),
+        }
     }
 }
 
@@ -26,6 +41,10 @@ impl fmt::Display for ValueGroup {
             ValueGroupSource::None => write!(f, "none"),
             ValueGroupSource::Socket(socket) => write!(f, "${}", socket),
             ValueGroupSource::Default(value) => write!(f, "{}", value),
+        }?;
+        if let Some(param) = self.param {
+            write!(f, " [param {}]", param)?;
         }
+        Ok(())
     }
 }
@@ -7,6 +7,12 @@ use crate::mir::Block;
 use crate::mir::ConstantValue;
 use std::fmt;
 
+/// The number of items every array has room for.
+pub const ARRAY_CAPACITY: u8 = 32;
+
+/// The most events a MIDI value can hold in a single sample.
+pub const MIDI_EVENT_COUNT: u8 = 16;
+
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Hash)]
 pub enum VarType {
     Num,
@@ -130,7 +130,13 @@ fn find_flatten_candidates<'surface>(
 
             if visited_surfaces.insert(subsurface_id) {
                 // The surface hasn't been visited yet, insert it into the candidates list.
-                flatten_candidates.insert(subsurface_id);
+                // Groups that don't run at audio rate or are oversampled need to stay as a node so
+                // they can be updated separately.
+                if node.update_rate == mir::UpdateRate::Audio
+                    && node.oversample_factor == mir::OversampleFactor::None
+                {
+                    flatten_candidates.insert(subsurface_id);
+                }
             } else {
                 // We're revisiting the surface, so remove it from the candidates in case it's there.
                 flatten_candidates.remove(&subsurface_id);
@@ -1,75 +1,267 @@
-use crate::codegen::TargetProperties;
 use crate::mir;
-use std::collections::VecDeque;
+use std::collections::HashMap;
 
 type NodeRef = usize;
 type ValueSocketRef = (NodeRef, usize);
 
-pub fn order_nodes(surface: &mut mir::Surface, target: &TargetProperties) {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum VisitState {
+    Unvisited,
+    Visiting,
+    Visited,
+}
+
+pub fn order_nodes(surface: &mut mir::Surface, include_ui: bool) {
     let outputs = get_output_nodes(&surface);
     let associated_sockets = get_associated_sockets(&surface);
-    let ordered_nodes = get_ordered_nodes(
+    let (ordered_nodes, delayed_sockets) = get_ordered_nodes(
         &surface.nodes,
         &outputs,
         &associated_sockets,
         &mut surface.source_map,
-        target,
+        include_ui,
     );
     surface.nodes = ordered_nodes;
+    insert_unit_delays(surface, &delayed_sockets);
 }
 
-fn get_ordered_nodes(
-    nodes: &[mir::Node],
-    outputs: &[NodeRef],
-    value_groups: &[Vec<ValueSocketRef>],
-    source_map: &mut mir::SourceMap,
-    target: &TargetProperties,
-) -> Vec<mir::Node> {
-    let mut inverse_order = Vec::new();
-    let mut is_node_visited = vec![false; nodes.len()];
-    let mut node_queue = VecDeque::new();
-    let mut source_map_moves = Vec::new();
-
-    for &output_node in outputs {
-        is_node_visited[output_node] = true;
-        node_queue.push_back(output_node);
+struct NodeOrderer<'a> {
+    nodes: &'a [mir::Node],
+    value_groups: &'a [Vec<ValueSocketRef>],
+    visit_states: Vec<VisitState>,
+    order: Vec<NodeRef>,
+    path: Vec<ValueSocketRef>,
+    deferred_nodes: Vec<NodeRef>,
+    delayed_sockets: Vec<ValueSocketRef>,
+    unordered_sockets: Vec<ValueSocketRef>,
+    shared_sockets: Vec<ValueSocketRef>,
+    needs_reorder: bool,
+}
+
+impl<'a> NodeOrderer<'a> {
+    pub fn new(
+        nodes: &'a [mir::Node],
+        value_groups: &'a [Vec<ValueSocketRef>],
+        delayed_sockets: Vec<ValueSocketRef>,
+        unordered_sockets: Vec<ValueSocketRef>,
+    ) -> Self {
+        NodeOrderer {
+            nodes,
+            value_groups,
+            visit_states: vec![VisitState::Unvisited; nodes.len()],
+            order: Vec::new(),
+            path: Vec::new(),
+            deferred_nodes: Vec::new(),
+            delayed_sockets,
+            unordered_sockets,
+            shared_sockets: Vec::new(),
+            needs_reorder: false,
+        }
+    }
+
+    pub fn is_visited(&self, node: NodeRef) -> bool {
+        self.visit_states[node] != VisitState::Unvisited
     }
 
-    while let Some(next_node) = node_queue.pop_front() {
-        source_map_moves.push((next_node, nodes.len() - inverse_order.len() - 1));
-        inverse_order.push(nodes[next_node].clone());
+    /// Orders a node and everything it depends on, along with the writers of any sockets on the
+    /// way that were cut from the walk.
+    pub fn visit_from(&mut self, node: NodeRef) {
+        self.visit(node);
+        while let Some(deferred_node) = self.deferred_nodes.pop() {
+            self.visit(deferred_node);
+        }
+    }
+
+    /// Depth-first walk from a node to the nodes it depends on, pushing each node onto the order
+    /// once everything it depends on has been pushed.
+    pub fn visit(&mut self, node: NodeRef) {
+        if self.is_visited(node) {
+            return;
+        }
+        self.visit_states[node] = VisitState::Visiting;
+
+        let nodes = self.nodes;
+        let value_groups = self.value_groups;
+        for (socket_index, socket) in nodes[node].sockets.iter().enumerate() {
+            // Sockets that were cut to break a loop don't depend on the group's other writers, but
+            // the writers still need to run. They're visited once this walk is done, so nothing
+            // on it is waiting on them.
+            let socket_ref = (node, socket_index);
+            let is_cut = self.delayed_sockets.contains(&socket_ref)
+                || self.unordered_sockets.contains(&socket_ref);
 
-        for socket in &nodes[next_node].sockets {
             // walk to other nodes through sockets _if the socket writes to the group_.
             for &(connected_node_ref, connected_socket_ref) in &value_groups[socket.group_id] {
                 let connected_socket = &nodes[connected_node_ref].sockets[connected_socket_ref];
-                if !connected_socket.value_written || is_node_visited[connected_node_ref] {
+                if connected_node_ref == node || !connected_socket.value_written {
                     continue;
                 }
 
-                is_node_visited[connected_node_ref] = true;
-                node_queue.push_back(connected_node_ref);
+                match self.visit_states[connected_node_ref] {
+                    VisitState::Unvisited if is_cut => self.deferred_nodes.push(connected_node_ref),
+                    VisitState::Unvisited => {
+                        self.path.push(socket_ref);
+                        self.visit(connected_node_ref);
+                        self.path.pop();
+                    }
+                    // A socket that only writes doesn't see the other writer's value, so there's
+                    // no loop to break.
+                    VisitState::Visiting if socket.value_read && !is_cut => {
+                        self.break_loop(socket_ref, connected_node_ref);
+                    }
+                    VisitState::Visiting | VisitState::Visited => {}
+                }
             }
         }
+
+        self.visit_states[node] = VisitState::Visited;
+        self.order.push(node);
     }
 
-    // For UX, add nodes that don't affect the output (i.e aren't connected in the flow) so the user
-    // can still play with them. We can't really determine the order of these, since there's no
-    // output.
-    // We don't need these when there's no UI displayed, since they don't actually affect anything.
-    if target.include_ui && inverse_order.len() < nodes.len() {
-        for (node_index, &was_visited) in is_node_visited.iter().enumerate() {
-            if !was_visited {
-                source_map_moves.push((node_index, nodes.len() - inverse_order.len() - 1));
-                inverse_order.push(nodes[node_index].clone());
+    /// Breaks the feedback loop closed by `socket` reading from `writer`, which is still waiting on
+    /// the socket's node. A socket that only reads can read the previous sample instead. One that
+    /// also writes has to stay on its group, so the loop is cut at another socket on the walk from
+    /// the writer and the surface needs to be ordered again.
+    fn break_loop(&mut self, socket: ValueSocketRef, writer: NodeRef) {
+        let nodes = self.nodes;
+        let socket_data = |(node, socket): ValueSocketRef| &nodes[node].sockets[socket];
+        if !socket_data(socket).value_written {
+            if !self.delayed_sockets.contains(&socket) {
+                self.delayed_sockets.push(socket);
             }
+            return;
+        }
+
+        let loop_start = self
+            .path
+            .iter()
+            .position(|&(path_node, _)| path_node == writer)
+            .unwrap();
+        let loop_sockets = &self.path[loop_start..];
+
+        // A socket on the loop that only writes doesn't take a value from the node it leads to,
+        // it just makes sure its write lands last. Dropping that keeps every value on time.
+        if let Some(&write_only) = loop_sockets
+            .iter()
+            .rev()
+            .find(|&&path_socket| !socket_data(path_socket).value_read)
+        {
+            self.unordered_sockets.push(write_only);
+            self.needs_reorder = true;
+        } else if let Some(&read_only) = loop_sockets
+            .iter()
+            .rev()
+            .find(|&&path_socket| !socket_data(path_socket).value_written)
+        {
+            self.delayed_sockets.push(read_only);
+            self.needs_reorder = true;
+        } else if !self.shared_sockets.contains(&socket) {
+            // Every socket around the loop both reads and writes, so the nodes share the groups
+            // rather than feeding each other. The socket keeps reading what the writer left on
+            // the last sample, which is marked the same as a delay so the editor can show it.
+            self.shared_sockets.push(socket);
         }
     }
+}
 
-    source_map.move_to(source_map_moves);
+fn get_ordered_nodes(
+    nodes: &[mir::Node],
+    outputs: &[NodeRef],
+    value_groups: &[Vec<ValueSocketRef>],
+    source_map: &mut mir::SourceMap,
+    include_ui: bool,
+) -> (Vec<mir::Node>, Vec<ValueSocketRef>) {
+    // Cutting a loop somewhere other than where it was found means the nodes already ordered may
+    // be in the wrong place, so order again with the cut sockets until no more are needed. Each
+    // pass cuts at least one more socket, so this always finishes.
+    let mut delayed_sockets = Vec::new();
+    let mut unordered_sockets = Vec::new();
+    let orderer = loop {
+        let mut orderer = NodeOrderer::new(nodes, value_groups, delayed_sockets, unordered_sockets);
 
-    inverse_order.reverse();
-    inverse_order
+        for &output_node in outputs {
+            orderer.visit_from(output_node);
+        }
+
+        // For UX, add nodes that don't affect the output (i.e aren't connected in the flow) so the
+        // user can still play with them. These are ordered the same way, but since there's no
+        // output we start from each node that hasn't been visited yet.
+        // We don't need these when there's no UI displayed, since they don't actually affect
+        // anything.
+        if include_ui {
+            for node_index in 0..nodes.len() {
+                orderer.visit_from(node_index);
+            }
+        }
+
+        if !orderer.needs_reorder {
+            break orderer;
+        }
+        delayed_sockets = orderer.delayed_sockets;
+        unordered_sockets = orderer.unordered_sockets;
+    };
+
+    let source_map_moves: Vec<_> = orderer
+        .order
+        .iter()
+        .enumerate()
+        .map(|(new_index, &old_index)| (old_index, new_index))
+        .collect();
+    source_map.move_to(source_map_moves.iter().cloned());
+
+    let new_indices: HashMap<_, _> = source_map_moves.into_iter().collect();
+    for &(node, socket) in &orderer.shared_sockets {
+        source_map.set_socket_delayed(new_indices[&node], socket);
+    }
+
+    // Only sockets on nodes that made it into the order need a delay.
+    let delayed_sockets = orderer
+        .delayed_sockets
+        .iter()
+        .filter_map(|&(node, socket)| new_indices.get(&node).map(|&node| (node, socket)))
+        .collect();
+    let ordered_nodes = orderer
+        .order
+        .iter()
+        .map(|&node| nodes[node].clone())
+        .collect();
+
+    (ordered_nodes, delayed_sockets)
+}
+
+/// Breaks feedback loops by moving each delayed socket onto a new value group that holds the
+/// previous sample of the group it was connected to. The value is copied over by a unit delay node
+/// added to the end of the surface, so it runs after every other node has read or written.
+fn insert_unit_delays(surface: &mut mir::Surface, delayed_sockets: &[ValueSocketRef]) {
+    let mut delayed_groups = HashMap::new();
+
+    for &(node_index, socket_index) in delayed_sockets {
+        let source_group = surface.nodes[node_index].sockets[socket_index].group_id;
+        let delayed_group = if let Some(&delayed_group) = delayed_groups.get(&source_group) {
+            delayed_group
+        } else {
+            let delayed_group = surface.groups.len();
+            let value_type = surface.groups[source_group].value_type.clone();
+            surface.groups.push(mir::ValueGroup::new(
+                value_type,
+                mir::ValueGroupSource::None,
+            ));
+            surface.nodes.push(mir::Node::new(
+                vec![
+                    mir::ValueSocket::new(source_group, false, true, false),
+                    mir::ValueSocket::new(delayed_group, true, false, false),
+                ],
+                mir::NodeData::UnitDelay,
+            ));
+            delayed_groups.insert(source_group, delayed_group);
+            delayed_group
+        };
+
+        surface.nodes[node_index].sockets[socket_index].group_id = delayed_group;
+        surface
+            .source_map
+            .set_socket_delayed(node_index, socket_index);
+    }
 }
 
 /// Finds sockets that are connected to each value group
@@ -108,3 +300,125 @@ fn get_output_nodes(surface: &mir::Surface) -> Vec<NodeRef> {
 
     result
 }
+
+#[cfg(test)]
+mod tests {
+    use super::order_nodes;
+    use crate::mir::{
+        Node, NodeData, Surface, SurfaceId, ValueGroup, ValueGroupSource, ValueSocket, VarType,
+    };
+
+    fn build_surface(group_count: usize, nodes: Vec<Vec<ValueSocket>>) -> Surface {
+        let mut groups = vec![ValueGroup::new(VarType::Num, ValueGroupSource::Socket(0))];
+        groups.extend(
+            (1..group_count).map(|_| ValueGroup::new(VarType::Num, ValueGroupSource::None)),
+        );
+        Surface::new(
+            SurfaceId::new_with_id("root".to_string(), 0),
+            groups,
+            nodes
+                .into_iter()
+                .map(|sockets| Node::new(sockets, NodeData::Dummy))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn read_only_loop_is_delayed() {
+        // a writes the output and group 2, and reads group 1 which b writes from group 2
+        let mut surface = build_surface(
+            3,
+            vec![
+                vec![
+                    ValueSocket::new(0, true, false, false),
+                    ValueSocket::new(1, false, true, false),
+                    ValueSocket::new(2, true, false, false),
+                ],
+                vec![
+                    ValueSocket::new(1, true, false, false),
+                    ValueSocket::new(2, false, true, false),
+                ],
+            ],
+        );
+        order_nodes(&mut surface, false);
+
+        // b runs first and reads the previous sample of group 2 through a new group
+        assert_eq!(surface.nodes.len(), 3);
+        assert_eq!(surface.groups.len(), 4);
+        assert_eq!(surface.nodes[0].sockets[1].group_id, 3);
+        assert_eq!(surface.nodes[1].sockets[1].group_id, 1);
+        assert!(surface.source_map.is_socket_delayed(0, 1));
+        assert!(!surface.source_map.is_socket_delayed(1, 1));
+        assert_eq!(surface.nodes[2].data, NodeData::UnitDelay);
+        assert_eq!(surface.nodes[2].sockets[0].group_id, 2);
+        assert_eq!(surface.nodes[2].sockets[1].group_id, 3);
+    }
+
+    #[test]
+    fn read_write_loop_is_cut_at_a_read_only_socket() {
+        // the loop closes on b's socket, which both reads and writes group 2
+        let mut surface = build_surface(
+            3,
+            vec![
+                vec![
+                    ValueSocket::new(0, true, false, false),
+                    ValueSocket::new(1, false, true, false),
+                    ValueSocket::new(2, true, false, false),
+                ],
+                vec![
+                    ValueSocket::new(1, true, false, false),
+                    ValueSocket::new(2, true, true, false),
+                ],
+            ],
+        );
+        order_nodes(&mut surface, false);
+
+        // a reads the previous sample of group 1, so b can run after a and see its write
+        assert_eq!(surface.nodes.len(), 3);
+        assert_eq!(surface.nodes[0].sockets.len(), 3);
+        assert_eq!(surface.nodes[0].sockets[1].group_id, 3);
+        assert_eq!(surface.nodes[1].sockets[1].group_id, 2);
+        assert!(surface.source_map.is_socket_delayed(0, 1));
+        assert!(!surface.source_map.is_socket_delayed(1, 1));
+        assert_eq!(surface.nodes[2].data, NodeData::UnitDelay);
+        assert_eq!(surface.nodes[2].sockets[0].group_id, 1);
+    }
+
+    #[test]
+    fn shared_read_write_loop_is_marked_delayed() {
+        // a and b both read and write group 1, so there's nothing to delay
+        let mut surface = build_surface(
+            2,
+            vec![
+                vec![
+                    ValueSocket::new(0, true, false, false),
+                    ValueSocket::new(1, true, true, false),
+                ],
+                vec![ValueSocket::new(1, true, true, false)],
+            ],
+        );
+        order_nodes(&mut surface, false);
+
+        assert_eq!(surface.nodes.len(), 2);
+        assert_eq!(surface.groups.len(), 2);
+        assert_eq!(surface.nodes[0].sockets.len(), 1);
+        assert!(surface.source_map.is_socket_delayed(0, 0));
+        assert!(!surface.source_map.is_socket_delayed(1, 1));
+    }
+
+    #[test]
+    fn unconnected_nodes_are_only_kept_for_ui() {
+        let sockets = vec![
+            vec![ValueSocket::new(0, true, false, false)],
+            vec![ValueSocket::new(1, true, false, false)],
+        ];
+
+        let mut surface = build_surface(2, sockets.clone());
+        order_nodes(&mut surface, false);
+        assert_eq!(surface.nodes.len(), 1);
+
+        let mut surface = build_surface(2, sockets);
+        order_nodes(&mut surface, true);
+        assert_eq!(surface.nodes.len(), 2);
+    }
+}
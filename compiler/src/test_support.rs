@@ -0,0 +1,81 @@
+use crate::ast::{ControlType, FormType};
+use crate::mir::{
+    Block, BlockId, ConstantValue, ControlInitializer, Node, NodeData, Root, Surface, SurfaceId,
+    ValueGroup, ValueGroupSource, ValueSocket, VarType,
+};
+use crate::{parser, pass};
+
+pub const SAMPLE_RATE: f64 = 44100.;
+
+pub fn compile_block(id: u64, code: &str) -> Block {
+    let mut stream = parser::get_token_stream(code);
+    let ast = parser::Parser::parse(&mut stream).unwrap();
+    pass::lower_ast(BlockId::new_with_id(format!("block{}", id), id), &ast).unwrap()
+}
+
+// creates a node for the block, with each control connected to the group of the same name
+pub fn build_node(block: &Block, groups: &[(&str, usize)]) -> Node {
+    let sockets = block
+        .controls
+        .iter()
+        .map(|control| {
+            let &(_, group_id) = groups
+                .iter()
+                .find(|(name, _)| *name == control.name)
+                .unwrap();
+            ValueSocket::new(
+                group_id,
+                control.value_written,
+                control.value_read,
+                control.control_type == ControlType::AudioExtract,
+            )
+        })
+        .collect();
+    Node::new(
+        sockets,
+        NodeData::Custom {
+            block: block.id.id,
+            control_initializers: block
+                .controls
+                .iter()
+                .map(|_| ControlInitializer::None)
+                .collect(),
+        },
+    )
+}
+
+pub fn build_surface(groups: Vec<ValueGroup>, nodes: Vec<Node>) -> Surface {
+    Surface::new(SurfaceId::new_with_id("root".to_string(), 0), groups, nodes)
+}
+
+pub fn build_root() -> Option<Root> {
+    Some(Root::new(vec![VarType::Num]))
+}
+
+pub fn assert_close(actual: f64, expected: f64, message: &str) {
+    assert!(
+        (actual - expected).abs() <= 1e-9 * expected.abs().max(1.),
+        "{}: got {}, expected {}",
+        message,
+        actual,
+        expected
+    );
+}
+
+// A counter feeding a delay and a filter, which reads its input from a group that defaults to 1.
+pub fn build_delay_block() -> Block {
+    compile_block(
+        1,
+        "out:num = lowBqFilter(delay(accum(in:num, 1), 0.01), 1200 Hz, 0.8)",
+    )
+}
+
+pub fn build_delay_groups() -> Vec<ValueGroup> {
+    vec![
+        ValueGroup::new(VarType::Num, ValueGroupSource::Socket(0)),
+        ValueGroup::new(
+            VarType::Num,
+            ValueGroupSource::Default(ConstantValue::new_num(1., 1., FormType::None)),
+        ),
+    ]
+}
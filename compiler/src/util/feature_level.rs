@@ -7,8 +7,52 @@ pub enum FeatureLevel {
     SSE42,
     AVX,
     AVX2,
+
+    VFP3,
+    NEON,
+
+    ARMV8,
+
+    /// Only what every CPU the target triple describes supports, for hosts that don't have any of
+    /// the other levels.
+    Generic,
 }
 
+/// The instruction sets a feature level belongs to. Levels are only comparable with other levels
+/// of the same family. `FeatureLevel::Generic` doesn't belong to any, and can be used with all of
+/// them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FeatureFamily {
+    X86,
+    ARMv7,
+    AArch64,
+
+    /// WebAssembly doesn't have any levels of its own, so only the generic level can target it.
+    Wasm32,
+}
+
+impl FeatureLevel {
+    pub fn family(self) -> Option<FeatureFamily> {
+        match self {
+            FeatureLevel::SSE41 | FeatureLevel::SSE42 | FeatureLevel::AVX | FeatureLevel::AVX2 => {
+                Some(FeatureFamily::X86)
+            }
+            FeatureLevel::VFP3 | FeatureLevel::NEON => Some(FeatureFamily::ARMv7),
+            FeatureLevel::ARMV8 => Some(FeatureFamily::AArch64),
+            FeatureLevel::Generic => None,
+        }
+    }
+
+    /// Whether the level can be used when targeting an instruction set of the family.
+    pub fn supports_family(self, family: FeatureFamily) -> bool {
+        match self.family() {
+            Some(level_family) => level_family == family,
+            None => true,
+        }
+    }
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
 fn get_feature_level() -> FeatureLevel {
     if is_x86_feature_detected!("avx2") {
         FeatureLevel::AVX2
@@ -19,27 +63,70 @@ fn get_feature_level() -> FeatureLevel {
     } else if is_x86_feature_detected!("sse4.1") {
         FeatureLevel::SSE41
     } else {
-        panic!("Axiom requires a CPU that supports at least SSE4.1")
+        FeatureLevel::Generic
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+fn get_feature_level() -> FeatureLevel {
+    // NEON is part of the AArch64 baseline, so there's nothing to detect
+    FeatureLevel::ARMV8
+}
+
+#[cfg(target_arch = "arm")]
+fn get_feature_level() -> FeatureLevel {
+    // there's no stable runtime detection for ARM, so go with what the compiler was built for
+    if cfg!(target_feature = "neon") {
+        FeatureLevel::NEON
+    } else if cfg!(target_feature = "vfp3") {
+        FeatureLevel::VFP3
+    } else {
+        FeatureLevel::Generic
     }
 }
 
+#[cfg(not(any(
+    target_arch = "x86",
+    target_arch = "x86_64",
+    target_arch = "arm",
+    target_arch = "aarch64"
+)))]
+fn get_feature_level() -> FeatureLevel {
+    FeatureLevel::Generic
+}
+
 lazy_static! {
     pub static ref FEATURE_LEVEL: FeatureLevel = get_feature_level();
 }
 
 pub fn get_target_feature_string(feature_level: FeatureLevel) -> String {
-    // we need SSE4.1 at a minimum, so dynamically enable SSE4.2, AVX, and AVX2 if we can
-    let mut base_features = "+x87,+mmx,+sse,+sse2,+sse3,+ssse3,+sse4.1".to_string();
+    match feature_level.family() {
+        Some(FeatureFamily::X86) => {
+            // we need SSE4.1 at a minimum, so dynamically enable SSE4.2, AVX, and AVX2 if we can
+            let mut base_features = "+x87,+mmx,+sse,+sse2,+sse3,+ssse3,+sse4.1".to_string();
 
-    if feature_level >= FeatureLevel::SSE42 {
-        base_features.push_str(",+sse4.2");
-    }
-    if feature_level >= FeatureLevel::AVX {
-        base_features.push_str(",+avx");
-    }
-    if feature_level >= FeatureLevel::AVX2 {
-        base_features.push_str(",+avx2");
-    }
+            if feature_level >= FeatureLevel::SSE42 {
+                base_features.push_str(",+sse4.2");
+            }
+            if feature_level >= FeatureLevel::AVX {
+                base_features.push_str(",+avx");
+            }
+            if feature_level >= FeatureLevel::AVX2 {
+                base_features.push_str(",+avx2");
+            }
 
-    base_features
+            base_features
+        }
+        Some(FeatureFamily::ARMv7) => {
+            // ARMv7 NEON only has single precision lanes, so it mostly helps single precision
+            // exports. Double precision math always runs on the VFP unit.
+            if feature_level >= FeatureLevel::NEON {
+                "+vfp3,+neon".to_string()
+            } else {
+                "+vfp3,-neon".to_string()
+            }
+        }
+        Some(FeatureFamily::AArch64) => "+fp-armv8,+neon".to_string(),
+        Some(FeatureFamily::Wasm32) | None => String::new(),
+    }
 }
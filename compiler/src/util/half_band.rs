@@ -0,0 +1,30 @@
+use std::f64::consts::PI;
+
+/// The number of non-zero taps on each side of the center tap. The full filter is
+/// `SIDE_TAPS * 4 - 1` taps long, since every other tap of a half-band filter is zero.
+pub const SIDE_TAPS: usize = 4;
+
+/// Coefficients for the non-zero side taps, starting at the tap next to the center. The center
+/// tap is always 0.5.
+///
+/// These come from a Blackman-windowed sinc, normalized so the filter has unity gain at DC.
+pub fn get_side_coefficients() -> Vec<f64> {
+    let window_radius = (SIDE_TAPS * 2) as f64;
+    let raw_coefficients: Vec<_> = (0..SIDE_TAPS)
+        .map(|tap| {
+            let offset = (tap * 2 + 1) as f64;
+            let sinc = (PI * offset / 2.).sin() / (PI * offset);
+            let window = 0.42
+                + 0.5 * (PI * offset / window_radius).cos()
+                + 0.08 * (2. * PI * offset / window_radius).cos();
+            sinc * window
+        })
+        .collect();
+
+    // each side should sum to 0.25, so with the center tap the whole filter sums to 1
+    let side_sum: f64 = raw_coefficients.iter().sum();
+    raw_coefficients
+        .into_iter()
+        .map(|coefficient| coefficient * 0.25 / side_sum)
+        .collect()
+}
@@ -1,2 +1,4 @@
 pub mod constant_propagate;
 pub mod feature_level;
+pub mod half_band;
+pub mod stable_hasher;
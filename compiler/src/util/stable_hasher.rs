@@ -0,0 +1,37 @@
+use std::hash::Hasher;
+
+/// An FNV-1a hasher. Unlike the standard library's hasher its output only depends on the bytes
+/// written to it, so it's the same between runs. Derived `Hash` implementations can write
+/// different bytes with different Rust versions though, so stored hashes of them also need the
+/// version.
+#[derive(Debug, Clone, Copy)]
+pub struct StableHasher {
+    state: u64,
+}
+
+impl StableHasher {
+    pub fn new() -> Self {
+        StableHasher {
+            state: 0xcbf2_9ce4_8422_2325,
+        }
+    }
+}
+
+impl Default for StableHasher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Hasher for StableHasher {
+    fn finish(&self) -> u64 {
+        self.state
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.state ^= u64::from(byte);
+            self.state = self.state.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+}
@@ -3,10 +3,9 @@
 #include <QtCore/QFileInfo>
 #include <QtCore/QStandardPaths>
 #include <QtWidgets/QMessageBox>
+#include <algorithm>
 #include <ctime>
 #include <iostream>
-#include <pmmintrin.h>
-#include <xmmintrin.h>
 
 #include "../AxiomEditor.h"
 #include "../model/ModelRoot.h"
@@ -19,21 +18,66 @@
 
 using namespace AxiomBackend;
 
-GenerateContext::GenerateContext(uint64_t count, AudioBackend *backend)
-    : backend(backend), _maxGenerateCount(count),
-      runtimeLock(backend->_editor->window()->project()->mainRoot().lockRuntime()) {
-    beforeFpuState = _mm_getcsr();
-    _MM_SET_FLUSH_ZERO_MODE(_MM_FLUSH_ZERO_ON);
-    _MM_SET_DENORMALS_ZERO_MODE(_MM_DENORMALS_ZERO_ON);
+// Denormals are flushed to zero while generating, since they're very slow to work with and
+// inaudible anyway. Each architecture keeps the flag in its own control register.
+#if defined(__aarch64__)
+static const uint64_t FLUSH_TO_ZERO_FLAGS = 1 << 24;
+
+static uint64_t getFpuState() {
+    uint64_t state;
+    __asm__ __volatile__("mrs %0, fpcr" : "=r"(state));
+    return state;
+}
+
+static void setFpuState(uint64_t state) {
+    __asm__ __volatile__("msr fpcr, %0" : : "r"(state));
+}
+#elif defined(__arm__)
+static const uint64_t FLUSH_TO_ZERO_FLAGS = 1 << 24;
+
+static uint64_t getFpuState() {
+    uint32_t state;
+    __asm__ __volatile__("vmrs %0, fpscr" : "=r"(state));
+    return state;
+}
+
+static void setFpuState(uint64_t state) {
+    __asm__ __volatile__("vmsr fpscr, %0" : : "r"((uint32_t) state));
+}
+#elif defined(__SSE__) || defined(_M_X64) || defined(_M_IX86)
+#include <pmmintrin.h>
+#include <xmmintrin.h>
+
+static const uint64_t FLUSH_TO_ZERO_FLAGS = _MM_FLUSH_ZERO_ON | _MM_DENORMALS_ZERO_ON;
+
+static uint64_t getFpuState() {
+    return _mm_getcsr();
+}
+
+static void setFpuState(uint64_t state) {
+    _mm_setcsr((unsigned int) state);
+}
+#else
+static const uint64_t FLUSH_TO_ZERO_FLAGS = 0;
+
+static uint64_t getFpuState() {
+    return 0;
+}
+
+static void setFpuState(uint64_t) {}
+#endif
+
+GenerateContext::GenerateContext(AudioBackend *backend) : backend(backend) {
+    beforeFpuState = getFpuState();
+    setFpuState(beforeFpuState | FLUSH_TO_ZERO_FLAGS);
 }
 
 GenerateContext::~GenerateContext() {
-    _mm_setcsr(beforeFpuState);
+    setFpuState(beforeFpuState);
 }
 
 void GenerateContext::generate() {
-    backend->generatedSamples++;
-    backend->_editor->window()->runtime()->runUpdate();
+    backend->_editor->window()->runtime()->audio().runUpdate();
 }
 
 NumValue *AudioBackend::getAudioPortal(size_t portalId) const {
@@ -127,15 +171,25 @@ void AudioBackend::deserialize(QByteArray *data,
 }
 
 void AudioBackend::setBpm(float bpm) {
-    _editor->window()->runtime()->setBpm(bpm);
+    _editor->window()->runtime()->audio().setBpm(bpm);
 }
 
 void AudioBackend::setSampleRate(float sampleRate) {
-    _editor->window()->runtime()->setSampleRate(sampleRate);
+    _editor->window()->runtime()->audio().setSampleRate(sampleRate);
 }
 
 void AudioBackend::queueMidiEvent(uint64_t deltaFrames, size_t portalId, AxiomBackend::MidiEvent event) {
-    queuedEvents.push_back({deltaFrames, portalId, event});
+    if (portalId >= portalKeys.size()) return;
+    auto offset = (uint32_t) std::min(deltaFrames, (uint64_t) UINT32_MAX);
+    _editor->window()->runtime()->audio().queueMidi(portalKeys[portalId], offset, event);
+}
+
+void AudioBackend::queuePreviewEvent(size_t portalId, AxiomBackend::MidiEvent event) {
+    auto written = previewEventsWritten.load(std::memory_order_relaxed);
+    if (written - previewEventsRead.load(std::memory_order_acquire) >= PREVIEW_EVENT_CAPACITY) return;
+
+    previewEvents[written % PREVIEW_EVENT_CAPACITY] = {portalId, event};
+    previewEventsWritten.store(written + 1, std::memory_order_release);
 }
 
 void AudioBackend::clearMidi(size_t portalId) {
@@ -147,28 +201,28 @@ void AudioBackend::clearNotes(size_t portalId) {
 }
 
 GenerateContext AudioBackend::beginGenerate() {
-    // decrement all deltaFrames from last time
-    for (auto &event : queuedEvents) {
-        if (event.deltaFrames > generatedSamples) {
-            event.deltaFrames -= generatedSamples;
-        } else {
-            event.deltaFrames = 0;
-        }
-
-        if (event.deltaFrames == 0) {
-            getMidiPortal(event.portalId)->pushEvent(event.event);
-
-            // note: deque doesn't invalidate iterators when removing items from start or end, so this is safe to do.
-            queuedEvents.pop_front();
+    // If the UI thread has the runtime locked it's still preparing a commit, so keep running the old code. Publishing
+    // and swapping the portal pointers are both done here so they always match the code that's running.
+    {
+        auto runtimeLock = _editor->window()->project()->mainRoot().tryLockRuntime();
+        if (runtimeLock.owns_lock() && hasPendingPortals) {
+            _editor->window()->runtime()->audio().publish();
+            std::swap(portalValues, pendingPortalValues);
+            std::swap(portalKeys, pendingPortalKeys);
+            hasPendingPortals = false;
         }
     }
-    generatedSamples = 0;
 
-    if (queuedEvents.empty()) {
-        return GenerateContext(UINT64_MAX, this);
-    } else {
-        return GenerateContext(queuedEvents[0].deltaFrames, this);
+    // preview events go through the runtime's queue like any other, which only this thread can touch
+    auto read = previewEventsRead.load(std::memory_order_relaxed);
+    auto written = previewEventsWritten.load(std::memory_order_acquire);
+    for (; read != written; read++) {
+        const auto &previewEvent = previewEvents[read % PREVIEW_EVENT_CAPACITY];
+        queueMidiEvent(0, previewEvent.portalId, previewEvent.event);
     }
+    previewEventsRead.store(read, std::memory_order_release);
+
+    return GenerateContext(this);
 }
 
 void AudioBackend::previewEvent(AxiomBackend::MidiEvent event) {}
@@ -183,12 +237,16 @@ void AudioBackend::internalUpdateConfiguration() {
     auto newPortals = std::move(_editor->window()->project()->getAudioConfiguration().portals);
     std::sort(newPortals.begin(), newPortals.end());
 
-    // update the value pointers
-    portalValues.clear();
-    portalValues.reserve(newPortals.size());
+    // update the value pointers, which the audio thread switches to when it publishes the commit
+    pendingPortalValues.clear();
+    pendingPortalValues.reserve(newPortals.size());
+    pendingPortalKeys.clear();
+    pendingPortalKeys.reserve(newPortals.size());
     for (const auto &newPortal : newPortals) {
-        portalValues.push_back(_editor->window()->runtime()->getPortalPtr(newPortal._key));
+        pendingPortalValues.push_back(_editor->window()->runtime()->getPendingPortalPtr(newPortal._key));
+        pendingPortalKeys.push_back(newPortal._key);
     }
+    hasPendingPortals = true;
 
     // no point continuing if the portals are the same
     if (hasCurrent && newPortals == currentPortals) {
@@ -1,7 +1,8 @@
 #pragma once
 
 #include <QtCore/QByteArray>
-#include <deque>
+#include <array>
+#include <atomic>
 #include <functional>
 #include <mutex>
 #include <optional>
@@ -15,34 +16,26 @@ namespace AxiomBackend {
     class AudioBackend;
 
     // An RAII handler for generating samples into a buffer.
-    // While this object is alive, the runtime is guaranteed to be locked, and the FPU state will be correct.
-    // You shouldn't call `generate` more times than `maxGenerateCount`. Once the count has expired, delete this object
-    // and request a new one with `beginGenerate` on the audio backend.
+    // While this object is alive, the runtime won't publish new code, and the FPU state will be correct.
+    // Delete this object once you've generated the current buffer, and request a new one with `beginGenerate` on the
+    // audio backend for the next.
     // ONLY call `generate` from the thread you requested the context from!
     class GenerateContext {
         friend class AudioBackend;
 
     private:
-        GenerateContext(uint64_t count, AudioBackend *backend);
+        explicit GenerateContext(AudioBackend *backend);
 
     public:
         ~GenerateContext();
 
-        // The max number of samples (i.e `generate` calls) until you should request a new context. This is used, for
-        // example, for the internal queueing of MIDI events.
-        // Note: the return value of this function will _always_ be greater than 0. This value will not change after
-        // the class has been created.
-        uint64_t maxGenerateCount() const { return _maxGenerateCount; }
-
         // Simulates the internal graph once. Inputs will be read as per their state before this call, and outputs will
-        // be written to.
+        // be written to. Queued MIDI events that are due are pushed onto their portals first.
         void generate();
 
     private:
         AudioBackend *backend;
-        uint64_t _maxGenerateCount;
-        std::lock_guard<std::mutex> runtimeLock;
-        unsigned int beforeFpuState;
+        uint64_t beforeFpuState;
     };
 
     class AudioBackend {
@@ -74,22 +67,29 @@ namespace AxiomBackend {
             QByteArray *data,
             std::optional<std::function<void(QDataStream &, uint32_t)>> deserializeCustomCallback = std::nullopt);
 
-        // Queues a MIDI event to be input in a certain number of samples time. Should be called from the audio thread.
-        // You should call clearMidi after the first generated sample (at least) to clear the MIDI portals that had
-        // data queued.
+        // Queues a MIDI event to be input in a certain number of samples time, on the runtime's queue for the portal.
+        // ONLY call this from the audio thread, outside of `generate`. Events can be delivered on any sample, so call
+        // clearMidi after every generated sample to clear the MIDI portals that had data queued.
         void queueMidiEvent(uint64_t deltaFrames, size_t portalId, MidiEvent event);
+
+        // Queues a MIDI event to be input on the next sample. Unlike `queueMidiEvent` this can be called from the UI
+        // thread, for example in `previewEvent`. The events are handed to the audio thread in `beginGenerate`, and are
+        // dropped if it's fallen too far behind.
+        void queuePreviewEvent(size_t portalId, MidiEvent event);
         void clearMidi(size_t portalId);
 
         // Clears all pressed MIDI keys. Should be called from the audio thread.
         void clearNotes(size_t portalId);
 
-        // Signals that you're about to start a batch of `generate` calls. May block if the runtime is being rebuilt,
-        // but as long as the returned value exists, generation calls are safe.
+        // Signals that you're about to start a batch of `generate` calls. This never blocks: if the runtime has finished
+        // rebuilding it switches to the new code here, otherwise the old code keeps running until a later call.
+        // Portal pointers returned before this call may change, so fetch them again afterwards.
         GenerateContext beginGenerate();
 
         // To be implemented by the audio backend, called from the UI thread when the IO configuration changes.
         // Note that this is not always called when the runtime is rebuilt, only if the rebuild results in a change in
-        // configuration. The runtime will be locked while in this method.
+        // configuration. The runtime will be locked while in this method, but the audio thread can still be generating
+        // with the old configuration until the next `beginGenerate`.
         virtual void handleConfigurationChange(const AudioConfiguration &configuration) = 0;
 
         // To be implemented by the audio backend, called from the UI thread when a new project is created to setup
@@ -124,8 +124,9 @@ namespace AxiomBackend {
         size_t internalRemapPortal(uint64_t id);
 
     private:
-        struct QueuedEvent {
-            uint64_t deltaFrames;
+        static constexpr size_t PREVIEW_EVENT_CAPACITY = 64;
+
+        struct PreviewEvent {
             size_t portalId;
             MidiEvent event;
         };
@@ -134,10 +135,20 @@ namespace AxiomBackend {
         std::vector<ConfigurationPortal> currentPortals;
 
         AxiomEditor *_editor;
-        std::vector<void *> portalValues;
 
-        // todo: use a circular buffer instead of a deque here
-        std::deque<QueuedEvent> queuedEvents;
-        size_t generatedSamples = 0;
+        // Only touched by the audio thread. The pending values are for the prepared commit, and are written by the
+        // UI thread with the runtime locked, then swapped in when the audio thread publishes the commit.
+        // The runtime's index for each portal is kept alongside its value, for queueing MIDI events.
+        std::vector<void *> portalValues;
+        std::vector<void *> pendingPortalValues;
+        std::vector<size_t> portalKeys;
+        std::vector<size_t> pendingPortalKeys;
+        bool hasPendingPortals = false;
+
+        // A single-producer single-consumer ring of events from the UI thread. The UI thread writes an event then
+        // bumps `previewEventsWritten`, the audio thread queues the events up to it then bumps `previewEventsRead`.
+        std::array<PreviewEvent, PREVIEW_EVENT_CAPACITY> previewEvents;
+        std::atomic<size_t> previewEventsWritten{0};
+        std::atomic<size_t> previewEventsRead{0};
     };
 }
@@ -62,7 +62,7 @@ public:
 
     void previewEvent(AxiomBackend::MidiEvent event) override {
         if (midiInputPortal == -1) return;
-        queueMidiEvent(0, (size_t) midiInputPortal, event);
+        queuePreviewEvent((size_t) midiInputPortal, event);
     }
 
 #ifdef PORTMIDI
@@ -186,35 +186,25 @@ public:
         backend->processIncomingMidiEvents();
 #endif
 
-        uint64_t processPos = 0;
-
         auto outputNums = (float *) outputBuffer;
 
-        auto sampleFrames64 = (uint64_t) framesPerBuffer;
-        while (processPos < sampleFrames64) {
-            auto context = backend->beginGenerate();
-            auto endProcessPos = processPos + context.maxGenerateCount();
-            if (endProcessPos > sampleFrames64) endProcessPos = sampleFrames64;
-
-            for (auto i = processPos; i < endProcessPos; i++) {
-                context.generate();
+        auto context = backend->beginGenerate();
+        for (unsigned long i = 0; i < framesPerBuffer; i++) {
+            context.generate();
 
-                if (backend->audioOutputPortal != -1) {
-                    auto outputNum = *backend->getAudioPortal(backend->audioOutputPortal);
-                    *outputNums++ = (float) outputNum.left;
-                    *outputNums++ = (float) outputNum.right;
-                }
+            if (backend->audioOutputPortal != -1) {
+                auto outputNum = *backend->getAudioPortal(backend->audioOutputPortal);
+                *outputNums++ = (float) outputNum.left;
+                *outputNums++ = (float) outputNum.right;
+            }
 
 #ifdef PORTMIDI
-                backend->processOutgoingMidiEvents();
+            backend->processOutgoingMidiEvents();
 #endif
 
-                if (backend->midiInputPortal != -1 && i == processPos) {
-                    backend->clearMidi((size_t) backend->midiInputPortal);
-                }
+            if (backend->midiInputPortal != -1) {
+                backend->clearMidi((size_t) backend->midiInputPortal);
             }
-
-            processPos = endProcessPos;
         }
 
         return 0;
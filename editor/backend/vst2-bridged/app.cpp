@@ -290,46 +290,38 @@ private:
 
     void handleGenerate(VstAudioGenerateMessage message) {
         auto sampleFrames64 = (uint64_t) message.sampleCount;
-        uint64_t processPos = 0;
-        while (processPos < sampleFrames64) {
-            auto context = backend->beginGenerate();
-            auto endProcessPos = processPos + context.maxGenerateCount();
-            if (endProcessPos > sampleFrames64) endProcessPos = sampleFrames64;
-
-            for (auto i = processPos; i < endProcessPos; i++) {
-                for (size_t inputIndex = 0; inputIndex < backend->audioInputs.size(); inputIndex++) {
-                    const auto &input = backend->audioInputs[inputIndex];
-                    if (input) {
-                        auto inputSource = getInputBufferPtr(inputIndex);
-                        auto &inputNum = *backend->getAudioPortal(input->portalIndex);
-                        inputNum.left = inputSource[i * 2];
-                        inputNum.right = inputSource[i * 2 + 1];
-                        inputNum.form = AxiomBackend::NumForm::OSCILLATOR;
-                    }
+        auto context = backend->beginGenerate();
+        for (uint64_t i = 0; i < sampleFrames64; i++) {
+            for (size_t inputIndex = 0; inputIndex < backend->audioInputs.size(); inputIndex++) {
+                const auto &input = backend->audioInputs[inputIndex];
+                if (input) {
+                    auto inputSource = getInputBufferPtr(inputIndex);
+                    auto &inputNum = *backend->getAudioPortal(input->portalIndex);
+                    inputNum.left = inputSource[i * 2];
+                    inputNum.right = inputSource[i * 2 + 1];
+                    inputNum.form = AxiomBackend::NumForm::OSCILLATOR;
                 }
+            }
 
-                context.generate();
-
-                for (size_t outputIndex = 0; outputIndex < backend->audioOutputs.size(); outputIndex++) {
-                    const auto &output = backend->audioOutputs[outputIndex];
-                    auto outputDest = getOutputBufferPtr(outputIndex);
+            context.generate();
 
-                    if (output) {
-                        auto outputNum = *backend->getAudioPortal(output->portalIndex);
-                        outputDest[i * 2] = (float) outputNum.left;
-                        outputDest[i * 2 + 1] = (float) outputNum.right;
-                    } else {
-                        outputDest[i * 2] = 0;
-                        outputDest[i * 2 + 1] = 0;
-                    }
-                }
+            for (size_t outputIndex = 0; outputIndex < backend->audioOutputs.size(); outputIndex++) {
+                const auto &output = backend->audioOutputs[outputIndex];
+                auto outputDest = getOutputBufferPtr(outputIndex);
 
-                if (backend->midiInputPortal != -1 && i == processPos) {
-                    backend->clearMidi((size_t) backend->midiInputPortal);
+                if (output) {
+                    auto outputNum = *backend->getAudioPortal(output->portalIndex);
+                    outputDest[i * 2] = (float) outputNum.left;
+                    outputDest[i * 2 + 1] = (float) outputNum.right;
+                } else {
+                    outputDest[i * 2] = 0;
+                    outputDest[i * 2 + 1] = 0;
                 }
             }
 
-            processPos = endProcessPos;
+            if (backend->midiInputPortal != -1) {
+                backend->clearMidi((size_t) backend->midiInputPortal);
+            }
         }
 
         AppAudioMessage msg(AppAudioMessageType::GENERATE_DONE);
@@ -59,47 +59,38 @@ void AxiomVstPlugin::processReplacing(float **inputs, float **outputs, VstInt32
         _backend.setBpm((float) timeInfo->tempo);
     }
 
-    auto sampleFrames64 = (uint64_t) sampleFrames;
-    uint64_t processPos = 0;
-    while (processPos < sampleFrames64) {
-        auto context = _backend.beginGenerate();
-        auto endProcessPos = processPos + context.maxGenerateCount();
-        if (endProcessPos > sampleFrames64) endProcessPos = sampleFrames64;
-
-        for (auto i = processPos; i < endProcessPos; i++) {
-            for (size_t inputIndex = 0; inputIndex < expectedInputCount; inputIndex++) {
-                const auto &input = _backend.audioInputs[inputIndex];
-                if (input) {
-                    auto &inputNum = *_backend.getAudioPortal(input->portalIndex);
-                    inputNum.left = inputs[inputIndex * 2][i];
-                    inputNum.right = inputs[inputIndex * 2 + 1][i];
-                    inputNum.form = AxiomBackend::NumForm::OSCILLATOR;
-                }
+    auto context = _backend.beginGenerate();
+    for (VstInt32 i = 0; i < sampleFrames; i++) {
+        for (size_t inputIndex = 0; inputIndex < expectedInputCount; inputIndex++) {
+            const auto &input = _backend.audioInputs[inputIndex];
+            if (input) {
+                auto &inputNum = *_backend.getAudioPortal(input->portalIndex);
+                inputNum.left = inputs[inputIndex * 2][i];
+                inputNum.right = inputs[inputIndex * 2 + 1][i];
+                inputNum.form = AxiomBackend::NumForm::OSCILLATOR;
             }
+        }
 
-            context.generate();
-
-            for (size_t outputIndex = 0; outputIndex < expectedOutputCount; outputIndex++) {
-                const auto &output = _backend.audioOutputs[outputIndex];
-                auto leftIndex = outputIndex * 2;
-                auto rightIndex = leftIndex + 1;
-
-                if (output) {
-                    auto outputNum = *_backend.getAudioPortal(output->portalIndex);
-                    outputs[leftIndex][i] = (float) outputNum.left;
-                    outputs[rightIndex][i] = (float) outputNum.right;
-                } else {
-                    outputs[leftIndex][i] = 0;
-                    outputs[rightIndex][i] = 0;
-                }
-            }
+        context.generate();
 
-            if (_backend.midiInputPortal != -1 && i == processPos) {
-                _backend.clearMidi((size_t) _backend.midiInputPortal);
+        for (size_t outputIndex = 0; outputIndex < expectedOutputCount; outputIndex++) {
+            const auto &output = _backend.audioOutputs[outputIndex];
+            auto leftIndex = outputIndex * 2;
+            auto rightIndex = leftIndex + 1;
+
+            if (output) {
+                auto outputNum = *_backend.getAudioPortal(output->portalIndex);
+                outputs[leftIndex][i] = (float) outputNum.left;
+                outputs[rightIndex][i] = (float) outputNum.right;
+            } else {
+                outputs[leftIndex][i] = 0;
+                outputs[rightIndex][i] = 0;
             }
         }
 
-        processPos = endProcessPos;
+        if (_backend.midiInputPortal != -1) {
+            _backend.clearMidi((size_t) _backend.midiInputPortal);
+        }
     }
 
     expectedInputCount = _backend.audioInputs.size();
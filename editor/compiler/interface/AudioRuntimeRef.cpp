@@ -0,0 +1,40 @@
+#include "AudioRuntimeRef.h"
+
+#include "Frontend.h"
+
+using namespace MaximCompiler;
+
+AudioRuntimeRef::AudioRuntimeRef(void *handle) : handle(handle) {}
+
+void AudioRuntimeRef::runUpdate() {
+    MaximFrontend::maxim_run_update(get());
+}
+
+uint32_t AudioRuntimeRef::runUpdateBlock(uint32_t frames) {
+    return MaximFrontend::maxim_run_update_block(get(), frames);
+}
+
+void AudioRuntimeRef::setBpm(double bpm) {
+    MaximFrontend::maxim_set_bpm(get(), bpm);
+}
+
+void AudioRuntimeRef::setSampleRate(double sampleRate) {
+    MaximFrontend::maxim_set_sample_rate(get(), sampleRate);
+}
+
+bool AudioRuntimeRef::publish() {
+    return MaximFrontend::maxim_publish_commit(get());
+}
+
+void *AudioRuntimeRef::getPortalPtr(size_t portal) {
+    return MaximFrontend::maxim_get_portal_ptr(get(), portal);
+}
+
+void *AudioRuntimeRef::getPortalBufferPtr(size_t portal) {
+    return MaximFrontend::maxim_get_portal_buffer_ptr(get(), portal);
+}
+
+void AudioRuntimeRef::queueMidi(size_t portal, uint32_t offset, const AxiomModel::MidiEventValue &event) {
+    MaximFrontend::maxim_queue_midi(get(), portal, offset, (uint8_t) event.event, event.channel, event.note,
+                                    event.param);
+}
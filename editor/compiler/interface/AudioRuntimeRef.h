@@ -0,0 +1,40 @@
+#pragma once
+
+#include <cstddef>
+#include <cstdint>
+
+#include "editor/model/Value.h"
+
+namespace MaximCompiler {
+
+    // The part of a runtime the audio thread uses. None of these functions allocate or lock, and they can be called
+    // while the runtime is preparing a commit on another thread.
+    class AudioRuntimeRef {
+    public:
+        explicit AudioRuntimeRef(void *handle);
+
+        void *get() const { return handle; }
+
+        void runUpdate();
+
+        // Returns the number of frames that were run, which is at most the number the portal buffers hold.
+        uint32_t runUpdateBlock(uint32_t frames);
+
+        void setBpm(double bpm);
+
+        void setSampleRate(double sampleRate);
+
+        bool publish();
+
+        void *getPortalPtr(size_t portal);
+
+        void *getPortalBufferPtr(size_t portal);
+
+        // Queues an event to be pushed onto a MIDI portal `offset` frames from the next update. This isn't thread-safe:
+        // like `publish`, it must only be called from the thread that runs updates, between updates.
+        void queueMidi(size_t portal, uint32_t offset, const AxiomModel::MidiEventValue &event);
+
+    private:
+        void *handle;
+    };
+}
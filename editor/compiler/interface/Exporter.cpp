@@ -19,6 +19,35 @@ CodeConfig::CodeConfig(MaximFrontend::OptimizationLevel optimizationLevel, const
                                                           includeInstrument, includeLibrary),
                   &MaximFrontend::maxim_destroy_code_config) {}
 
+void CodeConfig::setControlRateDivisor(uint32_t divisor) {
+    MaximFrontend::maxim_code_config_set_control_rate_divisor(get(), divisor);
+}
+
+
+void CodeConfig::setStoragePrecision(MaximFrontend::StoragePrecision precision) {
+    MaximFrontend::maxim_code_config_set_storage_precision(get(), precision);
+}
+
+void CodeConfig::setMathAccuracy(MaximFrontend::MathAccuracy mathAccuracy) {
+    MaximFrontend::maxim_code_config_set_math_accuracy(get(), mathAccuracy);
+}
+
+void CodeConfig::setMaxDelaySeconds(double maxDelaySeconds) {
+    MaximFrontend::maxim_code_config_set_max_delay_seconds(get(), maxDelaySeconds);
+}
+
+void CodeConfig::setDenormalMode(MaximFrontend::DenormalMode denormalMode) {
+    MaximFrontend::maxim_code_config_set_denormal_mode(get(), denormalMode);
+}
+
+void CodeConfig::setSanitizeOutputs(bool sanitizeOutputs) {
+    MaximFrontend::maxim_code_config_set_sanitize_outputs(get(), sanitizeOutputs);
+}
+
+void CodeConfig::setInstanced(bool instanced) {
+    MaximFrontend::maxim_code_config_set_instanced(get(), instanced);
+}
+
 ObjectOutputConfig::ObjectOutputConfig(MaximFrontend::ObjectFormat format, const QString &location)
     : OwnedObject(MaximFrontend::maxim_create_object_output_config(format, location.toUtf8().constData()),
                   &MaximFrontend::maxim_destroy_object_output_config) {}
@@ -44,6 +73,33 @@ MetaOutputConfig::MetaOutputConfig(MaximFrontend::MetaFormat format, const QStri
     : OwnedObject(createMetaOutputConfig(format, location, portalNames, portalNameCount),
                   &MaximFrontend::maxim_destroy_meta_output_config) {}
 
+bool MetaOutputConfig::addParam(const QString &name, double min, double max, double defaultValue, uint8_t unit) {
+    return MaximFrontend::maxim_meta_output_config_add_param(get(), name.toUtf8().constData(), min, max, defaultValue,
+                                                             unit);
+}
+
+SongConfig::SongConfig(uint32_t length) : SongConfig(MaximFrontend::maxim_create_song_config(length)) {}
+
+SongConfig::SongConfig(void *handle) : OwnedObject(handle, &MaximFrontend::maxim_destroy_song_config) {}
+
+std::optional<SongConfig> SongConfig::fromMidiFile(const QByteArray &data, double sampleRate, size_t portal) {
+    auto handle = MaximFrontend::maxim_read_midi_file((const uint8_t *) data.constData(), (size_t) data.size(),
+                                                      sampleRate, portal);
+    if (!handle) {
+        return std::nullopt;
+    }
+    return SongConfig(handle);
+}
+
+void SongConfig::addEvent(uint32_t frame, size_t portal, const AxiomModel::MidiEventValue &event) {
+    MaximFrontend::maxim_song_config_add_event(get(), frame, portal, (uint8_t) event.event, event.channel, event.note,
+                                               event.param);
+}
+
+void SongConfig::setLoop(uint32_t loopStart, uint32_t loopEnd) {
+    MaximFrontend::maxim_song_config_set_loop(get(), loopStart, loopEnd);
+}
+
 template<class T>
 static void *releaseOrNull(std::optional<T> val) {
     if (val) {
@@ -62,6 +118,10 @@ ExportConfig::ExportConfig(MaximCompiler::AudioConfig audio, MaximCompiler::Targ
                                                             releaseOrNull(std::move(metaOutput))),
                   &MaximFrontend::maxim_destroy_export_config) {}
 
+void ExportConfig::setSong(MaximCompiler::SongConfig song) {
+    MaximFrontend::maxim_export_config_set_song(get(), song.release());
+}
+
 bool Exporter::exportTransaction(const ExportConfig &config, MaximCompiler::Transaction transaction) {
     return MaximFrontend::maxim_export_transaction(config.get(), transaction.release());
 }
@@ -1,11 +1,13 @@
 #pragma once
 
+#include <QtCore/QByteArray>
 #include <QtCore/QString>
 #include <optional>
 
 #include "Frontend.h"
 #include "OwnedObject.h"
 #include "Transaction.h"
+#include "editor/model/Value.h"
 
 namespace MaximCompiler {
 
@@ -24,6 +26,21 @@ namespace MaximCompiler {
     public:
         CodeConfig(MaximFrontend::OptimizationLevel optimizationLevel, const QString &instrumentPrefix,
                    bool includeInstrument, bool includeLibrary);
+
+        void setControlRateDivisor(uint32_t divisor);
+
+
+        void setStoragePrecision(MaximFrontend::StoragePrecision precision);
+
+        void setMathAccuracy(MaximFrontend::MathAccuracy mathAccuracy);
+
+        void setMaxDelaySeconds(double maxDelaySeconds);
+
+        void setDenormalMode(MaximFrontend::DenormalMode denormalMode);
+
+        void setSanitizeOutputs(bool sanitizeOutputs);
+
+        void setInstanced(bool instanced);
     };
 
     class ObjectOutputConfig : public OwnedObject {
@@ -35,12 +52,30 @@ namespace MaximCompiler {
     public:
         MetaOutputConfig(MaximFrontend::MetaFormat format, const QString &location, QString *portalNames,
                          size_t portalNameCount);
+
+        bool addParam(const QString &name, double min, double max, double defaultValue, uint8_t unit);
+    };
+
+    class SongConfig : public OwnedObject {
+    public:
+        explicit SongConfig(uint32_t length);
+
+        static std::optional<SongConfig> fromMidiFile(const QByteArray &data, double sampleRate, size_t portal);
+
+        void addEvent(uint32_t frame, size_t portal, const AxiomModel::MidiEventValue &event);
+
+        void setLoop(uint32_t loopStart, uint32_t loopEnd);
+
+    private:
+        explicit SongConfig(void *handle);
     };
 
     class ExportConfig : public OwnedObject {
     public:
         ExportConfig(AudioConfig audio, TargetConfig target, CodeConfig code,
                      std::optional<ObjectOutputConfig> objectOutput, std::optional<MetaOutputConfig> metaOutput);
+
+        void setSong(SongConfig song);
     };
 
     class Exporter {
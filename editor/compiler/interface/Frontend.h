@@ -9,6 +9,9 @@ namespace MaximFrontend {
     using MaximRuntime = void;
     using MaximRuntimeRef = MaximRuntime;
 
+    using MaximAudioRuntime = void;
+    using MaximAudioRuntimeRef = MaximAudioRuntime;
+
     using MaximTransaction = void;
     using MaximTransactionRef = MaximTransaction;
 
@@ -34,8 +37,12 @@ namespace MaximFrontend {
     using MaximCodeConfig = void;
     using MaximObjectOutputConfig = void;
     using MaximMetaOutputConfig = void;
+    using MaximSongConfig = void;
     using MaximExportConfig = void;
     using MaximExportConfigRef = MaximExportConfig;
+    using MaximProfileReport = void;
+    using MaximSnapshot = void;
+    using MaximInterpreter = void;
 
     struct SourcePos {
         ptrdiff_t line;
@@ -55,11 +62,30 @@ namespace MaximFrontend {
         void *ui;
     };
 
-    enum class FeatureLevel : uint8_t { SSE41, SSE42, AVX, AVX2 };
+    struct Fault {
+        uint64_t surface;
+        size_t node;
+    };
+
+    struct NodeProfile {
+        uint64_t surface;
+        size_t node;
+        int32_t voice;
+        uint32_t sampleCount;
+        uint32_t minCycles;
+        uint32_t maxCycles;
+        double averageCycles;
+        uint32_t medianCycles;
+        uint32_t p90Cycles;
+        uint32_t p99Cycles;
+        uint32_t histogram[32];
+    };
+
+    enum class FeatureLevel : uint8_t { SSE41, SSE42, AVX, AVX2, VFP3, NEON, ARMV8, GENERIC };
 
     enum class TargetPlatform : uint8_t { WINDOWS_MSVC, WINDOWS_GNU, MAC, LINUX };
 
-    enum class TargetInstructionSet : uint8_t { I686, X64 };
+    enum class TargetInstructionSet : uint8_t { I686, X64, ARMV7, AARCH64, WASM32 };
 
     enum class OptimizationLevel : uint8_t {
         EDITOR,
@@ -72,28 +98,64 @@ namespace MaximFrontend {
         AGGRESSIVE_SIZE,
     };
 
-    enum class ObjectFormat : uint8_t { OBJECT, BITCODE, IR, ASSEMBLY_LISTING };
+    enum class ObjectFormat : uint8_t { OBJECT, BITCODE, IR, ASSEMBLY_LISTING, C_SOURCE };
+
+    enum class MetaFormat : uint8_t { C_HEADER, RUST_MODULE, JSON, JAVASCRIPT };
 
-    enum class MetaFormat : uint8_t { C_HEADER, RUST_MODULE, JSON };
+    enum class UpdateRate : uint8_t { AUDIO, CONTROL_HOLD, CONTROL_INTERPOLATE };
+
+    enum class OversampleFactor : uint8_t { NONE, X2, X4, X8 };
+
+    enum class StoragePrecision : uint8_t { DOUBLE, SINGLE };
+
+    enum class MathAccuracy : uint8_t { FAST, BALANCED, ACCURATE };
+
+    enum class DenormalMode : uint8_t { FLUSH_TO_ZERO, OFFSET };
+
+    enum class CodegenBackend : uint8_t { LLVM, CRANELIFT };
 
     extern "C" {
     void maxim_initialize();
+    bool maxim_supports_webassembly();
 
-    MaximRuntime *maxim_create_runtime(bool includeUi);
+    MaximRuntime *maxim_create_runtime(bool includeUi, MathAccuracy mathAccuracy, bool sanitizeOutputs,
+                                       bool profileNodes, CodegenBackend backend);
+    const char *maxim_measure_math_accuracy(MathAccuracy mathAccuracy);
     void maxim_destroy_runtime(MaximRuntime *);
     uint64_t maxim_allocate_id(MaximRuntimeRef *runtime);
+    bool maxim_set_disk_cache(MaximRuntimeRef *runtime, const char *path, uint64_t maxSize);
     bool maxim_export_transaction(MaximExportConfigRef *config, MaximTransaction *transaction);
 
-    void maxim_run_update(MaximRuntimeRef *runtime);
-    void maxim_set_bpm(MaximRuntimeRef *runtime, double bpm);
+    MaximAudioRuntimeRef *maxim_get_audio_runtime(MaximRuntimeRef *runtime);
+    void maxim_run_update(MaximAudioRuntimeRef *audio);
+    uint32_t maxim_run_update_block(MaximAudioRuntimeRef *audio, uint32_t frames);
+    uint32_t maxim_get_max_block_frames();
+    void maxim_set_bpm(MaximAudioRuntimeRef *audio, double bpm);
     double maxim_get_bpm(MaximRuntimeRef *runtime);
-    void maxim_set_sample_rate(MaximRuntimeRef *runtime, double sample_rate);
+    void maxim_set_sample_rate(MaximAudioRuntimeRef *audio, double sample_rate);
     double maxim_get_sample_rate(MaximRuntimeRef *runtime);
     uint64_t *maxim_get_profile_times_ptr(MaximRuntimeRef *runtime);
+    size_t maxim_get_faults(MaximRuntimeRef *runtime, Fault *faults, size_t maxFaults);
+    void maxim_clear_faults(MaximRuntimeRef *runtime);
+    MaximProfileReport *maxim_create_profile_report(MaximRuntimeRef *runtime);
+    size_t maxim_profile_report_len(MaximProfileReport *report);
+    NodeProfile maxim_profile_report_get(MaximProfileReport *report, size_t index);
+    void maxim_destroy_profile_report(MaximProfileReport *report);
+    MaximSnapshot *maxim_create_snapshot(MaximRuntimeRef *runtime);
+    size_t maxim_snapshot_len(MaximSnapshot *snapshot);
+    const uint8_t *maxim_snapshot_data(MaximSnapshot *snapshot);
+    void maxim_destroy_snapshot(MaximSnapshot *snapshot);
+    bool maxim_restore_snapshot(MaximRuntimeRef *runtime, const uint8_t *data, size_t len);
     bool maxim_is_node_extracted(MaximRuntimeRef *runtime, uint64_t surface, size_t node);
+    bool maxim_is_socket_delayed(MaximRuntimeRef *runtime, uint64_t surface, size_t node, size_t socket);
     void maxim_convert_num(MaximRuntimeRef *runtime, void *result, uint8_t targetForm, const void *input);
 
-    void *maxim_get_portal_ptr(MaximRuntimeRef *runtime, size_t portal);
+    void *maxim_get_portal_ptr(MaximAudioRuntimeRef *audio, size_t portal);
+    void *maxim_get_portal_buffer_ptr(MaximAudioRuntimeRef *audio, size_t portal);
+    void *maxim_get_pending_portal_ptr(MaximRuntimeRef *runtime, size_t portal);
+    void *maxim_get_pending_portal_buffer_ptr(MaximRuntimeRef *runtime, size_t portal);
+    void maxim_queue_midi(MaximAudioRuntimeRef *audio, size_t portal, uint32_t offset, uint8_t event, uint8_t channel,
+                          uint8_t note, uint8_t param);
     void *maxim_get_root_ptr(MaximRuntimeRef *runtime);
     void *maxim_get_node_ptr(MaximRuntimeRef *runtime, uint64_t surface, void *surface_ptr, size_t node);
     uint32_t *maxim_get_extracted_bitmask_ptr(MaximRuntimeRef *runtime, uint64_t surface, void *surface_ptr,
@@ -101,6 +163,22 @@ namespace MaximFrontend {
     void *maxim_get_surface_ptr(void *node_ptr);
     ControlPointers maxim_get_control_ptrs(MaximRuntimeRef *runtime, uint64_t block, void *node_ptr, size_t control);
 
+    MaximInterpreter *maxim_create_interpreter(bool includeUi, MathAccuracy mathAccuracy);
+    void maxim_destroy_interpreter(MaximInterpreter *interpreter);
+    uint64_t maxim_interpreter_allocate_id(MaximInterpreter *interpreter);
+    void maxim_interpreter_commit(MaximInterpreter *interpreter, MaximTransaction *transaction);
+    void maxim_interpreter_run_update(MaximInterpreter *interpreter);
+    void maxim_interpreter_set_bpm(MaximInterpreter *interpreter, double bpm);
+    double maxim_interpreter_get_bpm(MaximInterpreter *interpreter);
+    void maxim_interpreter_set_sample_rate(MaximInterpreter *interpreter, double sampleRate);
+    double maxim_interpreter_get_sample_rate(MaximInterpreter *interpreter);
+    void maxim_interpreter_set_control_rate_divisor(MaximInterpreter *interpreter, uint32_t divisor);
+    void maxim_interpreter_set_max_delay_seconds(MaximInterpreter *interpreter, double maxDelaySeconds);
+    bool maxim_interpreter_get_socket_num(MaximInterpreter *interpreter, size_t socket, double *leftOut,
+                                          double *rightOut);
+    bool maxim_interpreter_set_socket_num(MaximInterpreter *interpreter, size_t socket, double left, double right,
+                                          uint8_t form);
+
     void maxim_destroy_string(const char *);
 
     MaximTransaction *maxim_create_transaction();
@@ -132,6 +210,8 @@ namespace MaximFrontend {
     MaximValueGroupSource *maxim_valuegroupsource_clone(MaximValueGroupSource *base);
     void maxim_destroy_valuegroupsource(MaximValueGroupSource *);
     void maxim_build_value_group(MaximSurfaceRef *surface, MaximVarType *vartype, MaximValueGroupSource *source);
+    void maxim_build_param_value_group(MaximSurfaceRef *surface, MaximVarType *vartype, MaximValueGroupSource *source,
+                                       size_t param);
 
     MaximControlInitializer *maxim_control_initializer_none();
     MaximControlInitializer *maxim_control_initializer_graph(uint8_t curveCount, size_t startValuesCount,
@@ -144,6 +224,8 @@ namespace MaximFrontend {
     MaximNodeRef *maxim_build_custom_node(MaximSurfaceRef *surface, uint64_t block_id, size_t controlInitializersCount,
                                           MaximControlInitializer *const *controlInitializers);
     MaximNodeRef *maxim_build_group_node(MaximSurfaceRef *surface, uint64_t surface_id);
+    void maxim_set_node_update_rate(MaximNodeRef *node, UpdateRate rate);
+    void maxim_set_node_oversample_factor(MaximNodeRef *node, OversampleFactor factor);
     void maxim_build_value_socket(MaximNodeRef *node, size_t group_id, bool value_written, bool value_read,
                                   bool is_extractor);
 
@@ -165,6 +247,9 @@ namespace MaximFrontend {
     bool maxim_control_get_read(MaximBlockControlRef *control);
 
     void maxim_commit(MaximRuntimeRef *runtime, MaximTransaction *transaction);
+    bool maxim_prepare_commit(MaximRuntimeRef *runtime, MaximTransaction *transaction);
+    bool maxim_publish_commit(MaximAudioRuntimeRef *audio);
+    bool maxim_reclaim_commit(MaximRuntimeRef *runtime);
 
     MaximAudioConfig *maxim_create_audio_config(double sampleRate, double bpm);
     void maxim_destroy_audio_config(MaximAudioConfig *);
@@ -173,15 +258,31 @@ namespace MaximFrontend {
     void maxim_destroy_target_config(MaximTargetConfig *);
     MaximCodeConfig *maxim_create_code_config(OptimizationLevel optimizationLevel, const char *instrumentPrefix,
                                               bool includeInstrument, bool includeLibrary);
+    void maxim_code_config_set_control_rate_divisor(MaximCodeConfig *config, uint32_t divisor);
+    void maxim_code_config_set_storage_precision(MaximCodeConfig *config, StoragePrecision precision);
+    void maxim_code_config_set_math_accuracy(MaximCodeConfig *config, MathAccuracy mathAccuracy);
+    void maxim_code_config_set_max_delay_seconds(MaximCodeConfig *config, double maxDelaySeconds);
+    void maxim_code_config_set_denormal_mode(MaximCodeConfig *config, DenormalMode denormalMode);
+    void maxim_code_config_set_sanitize_outputs(MaximCodeConfig *config, bool sanitizeOutputs);
+    void maxim_code_config_set_instanced(MaximCodeConfig *config, bool instanced);
     void maxim_destroy_code_config(MaximCodeConfig *);
     MaximObjectOutputConfig *maxim_create_object_output_config(ObjectFormat format, const char *location);
     void maxim_destroy_object_output_config(MaximObjectOutputConfig *);
     MaximMetaOutputConfig *maxim_create_meta_output_config(MetaFormat format, const char *location,
                                                            const char *const *portalNames, size_t portalNameCount);
+    bool maxim_meta_output_config_add_param(MaximMetaOutputConfig *config, const char *name, double min, double max,
+                                            double defaultValue, uint8_t unit);
     void maxim_destroy_meta_output_config(MaximMetaOutputConfig *);
+    MaximSongConfig *maxim_create_song_config(uint32_t length);
+    MaximSongConfig *maxim_read_midi_file(const uint8_t *data, size_t len, double sampleRate, size_t portal);
+    void maxim_song_config_add_event(MaximSongConfig *config, uint32_t frame, size_t portal, uint8_t event,
+                                     uint8_t channel, uint8_t note, uint8_t param);
+    void maxim_song_config_set_loop(MaximSongConfig *config, uint32_t loopStart, uint32_t loopEnd);
+    void maxim_destroy_song_config(MaximSongConfig *);
     MaximExportConfig *maxim_create_export_config(MaximAudioConfig *audio, MaximTargetConfig *target,
                                                   MaximCodeConfig *code, MaximObjectOutputConfig *objectOrNull,
                                                   MaximMetaOutputConfig *metaOrNull);
+    void maxim_export_config_set_song(MaximExportConfig *config, MaximSongConfig *song);
     void maxim_destroy_export_config(MaximExportConfig *);
 
     void maxim_export(MaximExportConfigRef *config);
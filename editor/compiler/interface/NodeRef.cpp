@@ -6,6 +6,14 @@ using namespace MaximCompiler;
 
 NodeRef::NodeRef(void *handle) : handle(handle) {}
 
+void NodeRef::setUpdateRate(MaximFrontend::UpdateRate rate) {
+    MaximFrontend::maxim_set_node_update_rate(get(), rate);
+}
+
+void NodeRef::setOversampleFactor(MaximFrontend::OversampleFactor factor) {
+    MaximFrontend::maxim_set_node_oversample_factor(get(), factor);
+}
+
 void NodeRef::addValueSocket(size_t groupId, bool valueWritten, bool valueRead, bool isExtractor) {
     MaximFrontend::maxim_build_value_socket(get(), groupId, valueWritten, valueRead, isExtractor);
 }
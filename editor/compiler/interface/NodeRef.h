@@ -2,6 +2,8 @@
 
 #include <cstddef>
 
+#include "Frontend.h"
+
 namespace MaximCompiler {
 
     class NodeRef {
@@ -10,6 +12,10 @@ namespace MaximCompiler {
 
         void *get() const { return handle; }
 
+        void setUpdateRate(MaximFrontend::UpdateRate rate);
+
+        void setOversampleFactor(MaximFrontend::OversampleFactor factor);
+
         void addValueSocket(size_t groupId, bool valueWritten, bool valueRead, bool isExtractor);
 
     private:
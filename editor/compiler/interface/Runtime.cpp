@@ -4,27 +4,34 @@
 
 using namespace MaximCompiler;
 
-Runtime::Runtime(bool includeUi)
-    : OwnedObject(MaximFrontend::maxim_create_runtime(includeUi), &MaximFrontend::maxim_destroy_runtime) {}
+Runtime::Runtime(bool includeUi, MaximFrontend::MathAccuracy mathAccuracy, bool sanitizeOutputs,
+                 bool profileNodes, MaximFrontend::CodegenBackend backend)
+    : OwnedObject(
+          MaximFrontend::maxim_create_runtime(includeUi, mathAccuracy, sanitizeOutputs, profileNodes, backend),
+          &MaximFrontend::maxim_destroy_runtime) {}
 
 uint64_t Runtime::nextId() {
     return MaximFrontend::maxim_allocate_id(get());
 }
 
-void Runtime::runUpdate() {
-    MaximFrontend::maxim_run_update(get());
+bool Runtime::setDiskCache(const std::string &path, uint64_t maxSize) {
+    return MaximFrontend::maxim_set_disk_cache(get(), path.c_str(), maxSize);
 }
 
-void Runtime::setBpm(double bpm) {
-    MaximFrontend::maxim_set_bpm(get(), bpm);
+void Runtime::disableDiskCache() {
+    MaximFrontend::maxim_set_disk_cache(get(), nullptr, 0);
 }
 
-double Runtime::getBpm() {
-    return MaximFrontend::maxim_get_bpm(get());
+AudioRuntimeRef Runtime::audio() {
+    return AudioRuntimeRef(MaximFrontend::maxim_get_audio_runtime(get()));
+}
+
+uint32_t Runtime::maxBlockFrames() {
+    return MaximFrontend::maxim_get_max_block_frames();
 }
 
-void Runtime::setSampleRate(double sampleRate) {
-    MaximFrontend::maxim_set_sample_rate(get(), sampleRate);
+double Runtime::getBpm() {
+    return MaximFrontend::maxim_get_bpm(get());
 }
 
 double Runtime::getSampleRate() {
@@ -35,22 +42,71 @@ uint64_t *Runtime::getProfileTimesPtr() {
     return MaximFrontend::maxim_get_profile_times_ptr(get());
 }
 
+std::vector<MaximFrontend::Fault> Runtime::getFaults() {
+    std::vector<MaximFrontend::Fault> faults(64);
+    faults.resize(MaximFrontend::maxim_get_faults(get(), faults.data(), faults.size()));
+    return faults;
+}
+
+void Runtime::clearFaults() {
+    MaximFrontend::maxim_clear_faults(get());
+}
+
+std::vector<MaximFrontend::NodeProfile> Runtime::getProfileReport() {
+    auto report = MaximFrontend::maxim_create_profile_report(get());
+    std::vector<MaximFrontend::NodeProfile> profiles;
+    auto profileCount = MaximFrontend::maxim_profile_report_len(report);
+    for (size_t i = 0; i < profileCount; i++) {
+        profiles.push_back(MaximFrontend::maxim_profile_report_get(report, i));
+    }
+    MaximFrontend::maxim_destroy_profile_report(report);
+    return profiles;
+}
+
+std::vector<uint8_t> Runtime::snapshot() {
+    auto snapshot = MaximFrontend::maxim_create_snapshot(get());
+    auto data = MaximFrontend::maxim_snapshot_data(snapshot);
+    std::vector<uint8_t> bytes(data, data + MaximFrontend::maxim_snapshot_len(snapshot));
+    MaximFrontend::maxim_destroy_snapshot(snapshot);
+    return bytes;
+}
+
+bool Runtime::restore(const std::vector<uint8_t> &snapshot) {
+    return MaximFrontend::maxim_restore_snapshot(get(), snapshot.data(), snapshot.size());
+}
+
 void Runtime::commit(MaximCompiler::Transaction transaction) {
     MaximFrontend::maxim_commit(get(), transaction.release());
 }
 
+bool Runtime::prepare(MaximCompiler::Transaction transaction) {
+    return MaximFrontend::maxim_prepare_commit(get(), transaction.release());
+}
+
+bool Runtime::reclaim() {
+    return MaximFrontend::maxim_reclaim_commit(get());
+}
+
 bool Runtime::isNodeExtracted(uint64_t surface, size_t node) {
     return MaximFrontend::maxim_is_node_extracted(get(), surface, node);
 }
 
+bool Runtime::isSocketDelayed(uint64_t surface, size_t node, size_t socket) {
+    return MaximFrontend::maxim_is_socket_delayed(get(), surface, node, socket);
+}
+
 AxiomModel::NumValue Runtime::convertNum(AxiomModel::FormType targetForm, AxiomModel::NumValue value) {
     AxiomModel::NumValue result;
     MaximFrontend::maxim_convert_num(get(), &result, (uint8_t) targetForm, &value);
     return result;
 }
 
-void *Runtime::getPortalPtr(size_t portal) {
-    return MaximFrontend::maxim_get_portal_ptr(get(), portal);
+void *Runtime::getPendingPortalPtr(size_t portal) {
+    return MaximFrontend::maxim_get_pending_portal_ptr(get(), portal);
+}
+
+void *Runtime::getPendingPortalBufferPtr(size_t portal) {
+    return MaximFrontend::maxim_get_pending_portal_buffer_ptr(get(), portal);
 }
 
 void *Runtime::getRootPtr() {
@@ -1,5 +1,10 @@
 #pragma once
 
+#include <string>
+#include <vector>
+
+#include "AudioRuntimeRef.h"
+#include "Frontend.h"
 #include "OwnedObject.h"
 #include "Transaction.h"
 #include "editor/model/Value.h"
@@ -8,29 +13,54 @@ namespace MaximCompiler {
 
     class Runtime : public OwnedObject {
     public:
-        Runtime(bool includeUi);
+        Runtime(bool includeUi,
+                MaximFrontend::MathAccuracy mathAccuracy = MaximFrontend::MathAccuracy::BALANCED,
+                bool sanitizeOutputs = false, bool profileNodes = false,
+                MaximFrontend::CodegenBackend backend = MaximFrontend::CodegenBackend::LLVM);
 
         uint64_t nextId();
 
-        void runUpdate();
+        bool setDiskCache(const std::string &path, uint64_t maxSize);
 
-        void setBpm(double bpm);
+        void disableDiskCache();
 
-        double getBpm();
+        // The audio thread's side of the runtime, which lives as long as the runtime does.
+        AudioRuntimeRef audio();
 
-        void setSampleRate(double sampleRate);
+        static uint32_t maxBlockFrames();
+
+        double getBpm();
 
         double getSampleRate();
 
         uint64_t *getProfileTimesPtr();
 
+        std::vector<MaximFrontend::Fault> getFaults();
+
+        void clearFaults();
+
+        std::vector<MaximFrontend::NodeProfile> getProfileReport();
+
+        std::vector<uint8_t> snapshot();
+
+        bool restore(const std::vector<uint8_t> &snapshot);
+
         void commit(Transaction transaction);
 
+        bool prepare(Transaction transaction);
+
+        bool reclaim();
+
         bool isNodeExtracted(uint64_t surface, size_t node);
 
+        bool isSocketDelayed(uint64_t surface, size_t node, size_t socket);
+
         AxiomModel::NumValue convertNum(AxiomModel::FormType targetForm, AxiomModel::NumValue value);
 
-        void *getPortalPtr(size_t portal);
+        // Portals of the prepared commit, or the running code if it's been published.
+        void *getPendingPortalPtr(size_t portal);
+
+        void *getPendingPortalBufferPtr(size_t portal);
 
         void *getRootPtr();
 
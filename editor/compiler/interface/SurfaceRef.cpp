@@ -10,6 +10,11 @@ void SurfaceRef::addValueGroup(MaximCompiler::VarType vartype, MaximCompiler::Va
     MaximFrontend::maxim_build_value_group(get(), vartype.release(), source.release());
 }
 
+void SurfaceRef::addParamValueGroup(MaximCompiler::VarType vartype, MaximCompiler::ValueGroupSource source,
+                                    size_t param) {
+    MaximFrontend::maxim_build_param_value_group(get(), vartype.release(), source.release(), param);
+}
+
 NodeRef SurfaceRef::addCustomNode(uint64_t blockId, size_t controlInitializerCount, ControlInitializer *initializers) {
     std::vector<MaximFrontend::MaximControlInitializer *> initializerPtrs;
     initializerPtrs.reserve(controlInitializerCount);
@@ -15,6 +15,8 @@ namespace MaximCompiler {
 
         void addValueGroup(VarType vartype, ValueGroupSource source);
 
+        void addParamValueGroup(VarType vartype, ValueGroupSource source, size_t param);
+
         NodeRef addCustomNode(uint64_t blockId, size_t controlInitializerCount, ControlInitializer *initializers);
 
         NodeRef addGroupNode(uint64_t surfaceId);
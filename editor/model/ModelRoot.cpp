@@ -56,6 +56,10 @@ std::lock_guard<std::mutex> ModelRoot::lockRuntime() {
     return std::lock_guard(_runtimeLock);
 }
 
+std::unique_lock<std::mutex> ModelRoot::tryLockRuntime() {
+    return std::unique_lock(_runtimeLock, std::try_to_lock);
+}
+
 void ModelRoot::setHistory(AxiomModel::HistoryList history) {
     _history = std::move(history);
     _history.stackChanged.connectTo(this, &ModelRoot::compileDirtyItems);
@@ -99,7 +103,9 @@ void ModelRoot::applyTransaction(MaximCompiler::Transaction transaction) {
             obj->saveState();
         }
 
-        _runtime->commit(std::move(transaction));
+        // The audio thread keeps running the old code while this compiles, and publishes the new code once the
+        // runtime is unlocked. Until then the root pointer is the new code's, so restored state ends up there.
+        _runtime->prepare(std::move(transaction));
         rootSurface()->updateRuntimePointers(_runtime, _runtime->getRootPtr());
 
         for (const auto &obj : allObjects) {
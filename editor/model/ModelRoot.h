@@ -76,6 +76,9 @@ namespace AxiomModel {
 
         std::lock_guard<std::mutex> lockRuntime();
 
+        // Doesn't wait if the runtime is locked, for the audio thread to check for a prepared commit without blocking.
+        std::unique_lock<std::mutex> tryLockRuntime();
+
         void setHistory(HistoryList history);
 
         void applyDirtyItemsTo(MaximCompiler::Transaction *transaction);
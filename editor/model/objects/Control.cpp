@@ -179,6 +179,13 @@ void Control::setIsActive(bool isActive) {
     }
 }
 
+void Control::setIsDelayed(bool isDelayed) {
+    if (isDelayed != _isDelayed) {
+        _isDelayed = isDelayed;
+        isDelayedChanged(isDelayed);
+    }
+}
+
 QPointF Control::worldPos() const {
     auto worldPos = pos() + ControlSurface::nodeToControl(_surface->node()->pos()) +
                     QPointF(size().width() / 2.f, size().height() / 2.f);
@@ -48,6 +48,7 @@ namespace AxiomModel {
         AxiomCommon::Event<bool> showNameChanged;
         AxiomCommon::Event<QPointF> worldPosChanged;
         AxiomCommon::Event<bool> isActiveChanged;
+        AxiomCommon::Event<bool> isDelayedChanged;
         AxiomCommon::Event<QUuid> exposerUuidChanged;
         AxiomCommon::Event<bool> isEnabledChanged;
 
@@ -100,6 +101,11 @@ namespace AxiomModel {
 
         void setIsActive(bool isActive);
 
+        // Whether the control reads its connection one sample late, because it closes a feedback loop.
+        bool isDelayed() const { return _isDelayed; }
+
+        void setIsDelayed(bool isDelayed);
+
         AxiomCommon::BoxedWatchSequence<Connection *> &connections() { return _connections; }
 
         const AxiomCommon::BoxedWatchSequence<Connection *> &connections() const { return _connections; }
@@ -135,6 +141,7 @@ namespace AxiomModel {
         QUuid _exposerUuid;
         QUuid _exposingUuid;
         bool _isActive = false;
+        bool _isDelayed = false;
         std::optional<ControlCompileMeta> _compileMeta;
         std::optional<MaximFrontend::ControlPointers> _runtimePointers;
 
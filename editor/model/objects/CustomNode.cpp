@@ -98,12 +98,16 @@ void CustomNode::updateRuntimePointers(MaximCompiler::Runtime *runtime, void *su
 
     Node::updateRuntimePointers(runtime, surfacePtr);
 
-    auto nodePtr = runtime->getNodePtr(surface()->getRuntimeId(), surfacePtr, compileMeta()->mirIndex);
+    auto surfaceId = surface()->getRuntimeId();
+    auto mirIndex = compileMeta()->mirIndex;
+    auto nodePtr = runtime->getNodePtr(surfaceId, surfacePtr, mirIndex);
     auto runtimeId = getRuntimeId();
 
-    controls().then([nodePtr, runtime, runtimeId](ControlSurface *controlSurface) {
+    controls().then([nodePtr, runtime, runtimeId, surfaceId, mirIndex](ControlSurface *controlSurface) {
         for (const auto &control : controlSurface->controls().sequence()) {
-            control->setRuntimePointers(runtime->getControlPtrs(runtimeId, nodePtr, control->compileMeta()->index));
+            auto controlIndex = control->compileMeta()->index;
+            control->setIsDelayed(runtime->isSocketDelayed(surfaceId, mirIndex, controlIndex));
+            control->setRuntimePointers(runtime->getControlPtrs(runtimeId, nodePtr, controlIndex));
         }
     });
 }
@@ -38,6 +38,7 @@ ControlItem::ControlItem(Control *control, NodeSurfaceCanvas *canvas) : control(
     control->sizeChanged.connectTo(this, &ControlItem::setSize);
     control->selectedChanged.connectTo(this, &ControlItem::updateSelected);
     control->isActiveChanged.connectTo(this, &ControlItem::triggerUpdate);
+    control->isDelayedChanged.connectTo(this, &ControlItem::triggerUpdate);
     control->showNameChanged.connectTo(this, &ControlItem::triggerUpdate);
     control->exposerUuidChanged.connectTo(this, &ControlItem::triggerUpdate);
     control->isEnabledChanged.connectTo(this, &ControlItem::triggerUpdate);
@@ -150,7 +151,11 @@ void ControlItem::paint(QPainter *painter, const QStyleOptionGraphicsItem *optio
 
         auto bounds = controlPath();
         auto activeColor = AxiomUtil::mixColor(useNormalColor, useActiveColor, control->isActive());
-        painter->setPen(QPen(activeColor, 3));
+
+        // a dashed outline shows the connection is read a sample late to break a feedback loop
+        QPen outlinePen(activeColor, 3);
+        if (control->isDelayed()) outlinePen.setStyle(Qt::DashLine);
+        painter->setPen(outlinePen);
         painter->setBrush(QBrush(activeColor));
         painter->drawPath(bounds);
     }
@@ -17,7 +17,7 @@ MetaOutputConfigWidget::MetaOutputConfigWidget(const AxiomModel::Project &projec
     layout->addRow(portalEditor);
 
     outputBrowser =
-        new FileBrowserWidget("Meta Output Location", "Header File (*.h);;Rust Module (*.rs);;JSON File (*.json)");
+        new FileBrowserWidget("Meta Output Location", "Header File (*.h);;Rust Module (*.rs);;JSON File (*.json);;JavaScript Module (*.js)");
     layout->addRow("Location:", outputBrowser);
 }
 
@@ -32,6 +32,8 @@ MaximCompiler::MetaOutputConfig MetaOutputConfigWidget::buildConfig() {
         format = MaximFrontend::MetaFormat::RUST_MODULE;
     } else if (location.endsWith(".json")) {
         format = MaximFrontend::MetaFormat::JSON;
+    } else if (location.endsWith(".js")) {
+        format = MaximFrontend::MetaFormat::JAVASCRIPT;
     }
 
     auto portalNames = portalEditor->getNames();
@@ -14,7 +14,8 @@ ObjectOutputConfigWidget::ObjectOutputConfigWidget() {
 
     outputBrowser =
         new FileBrowserWidget("Object Output Location",
-                              "Object File (*.o);;LLVM Bitcode File (*.bc);;LLVM IR (*.ll);;Assembly Listing (*.s)");
+                              "Object File (*.o);;WebAssembly Module (*.wasm);;LLVM Bitcode File (*.bc);;LLVM IR (*.ll);;"
+                              "Assembly Listing (*.s);;C Source (*.c)");
     layout->addRow("Location:", outputBrowser);
 }
 
@@ -31,6 +32,8 @@ MaximCompiler::ObjectOutputConfig ObjectOutputConfigWidget::buildConfig() {
         format = MaximFrontend::ObjectFormat::IR;
     } else if (location.endsWith(".s")) {
         format = MaximFrontend::ObjectFormat::ASSEMBLY_LISTING;
+    } else if (location.endsWith(".c")) {
+        format = MaximFrontend::ObjectFormat::C_SOURCE;
     }
 
     return MaximCompiler::ObjectOutputConfig(format, location);
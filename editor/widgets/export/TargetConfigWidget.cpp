@@ -13,6 +13,39 @@
 
 using namespace AxiomGui;
 
+// Each instruction set has its own range of feature levels, which are consecutive in FeatureLevel.
+static MaximFrontend::FeatureLevel firstFeatureLevel(int instructionSetIndex) {
+    switch (instructionSetIndex) {
+    case 0:
+    case 1:
+        return MaximFrontend::FeatureLevel::SSE41;
+    case 2:
+        return MaximFrontend::FeatureLevel::VFP3;
+    case 3:
+        return MaximFrontend::FeatureLevel::ARMV8;
+    case 4:
+        return MaximFrontend::FeatureLevel::GENERIC;
+    default:
+        unreachable;
+    }
+}
+
+static QStringList featureLevelNames(int instructionSetIndex) {
+    switch (instructionSetIndex) {
+    case 0:
+    case 1:
+        return {"SSE4.1", "SSE4.2", "AVX", "AVX2"};
+    case 2:
+        return {"VFPv3", "NEON"};
+    case 3:
+        return {"ARMv8"};
+    case 4:
+        return {"Generic"};
+    default:
+        unreachable;
+    }
+}
+
 TargetConfigWidget::TargetConfigWidget() {
     auto layout = new QFormLayout();
     layout->setContentsMargins(0, 0, 0, 0);
@@ -27,6 +60,12 @@ TargetConfigWidget::TargetConfigWidget() {
     instructionSetSelect = new QComboBox();
     instructionSetSelect->addItem("i686 (32-bit)");
     instructionSetSelect->addItem("x86-64 (64-bit)");
+    instructionSetSelect->addItem("ARMv7 (32-bit)");
+    instructionSetSelect->addItem("AArch64 (64-bit)");
+    // the compiler might not have been built with the WebAssembly backend, this has to stay last if it's left out
+    if (MaximFrontend::maxim_supports_webassembly()) {
+        instructionSetSelect->addItem("WebAssembly (32-bit)");
+    }
 
     auto machineLayout = new QHBoxLayout();
     machineLayout->addWidget(machineSelect);
@@ -34,25 +73,22 @@ TargetConfigWidget::TargetConfigWidget() {
     layout->addRow("Machine:", machineLayout);
 
     featureSlider = new QSlider(Qt::Horizontal);
-    featureSlider->setRange(0, 3);
     featureSlider->setSingleStep(1);
     auto sliderLayout = new QVBoxLayout();
     sliderLayout->addWidget(featureSlider);
 
-    auto labelsLayout = new QHBoxLayout();
-    labelsLayout->addWidget(new QLabel("SSE4.1"), 1);
-    auto sse42Label = new QLabel("SSE4.2");
-    sse42Label->setAlignment(Qt::AlignHCenter);
-    labelsLayout->addWidget(sse42Label, 2);
-    auto avxLabel = new QLabel("AVX");
-    avxLabel->setAlignment(Qt::AlignHCenter);
-    labelsLayout->addWidget(avxLabel, 2);
-    auto avx2Label = new QLabel("AVX2");
-    avx2Label->setAlignment(Qt::AlignRight);
-    labelsLayout->addWidget(avx2Label, 1);
-    sliderLayout->addLayout(labelsLayout);
+    featureLabelsLayout = new QHBoxLayout();
+    sliderLayout->addLayout(featureLabelsLayout);
     layout->addRow("Features:", sliderLayout);
 
+    wasmLinkLabel = new QLabel("WebAssembly objects are linked into a module with wasm-ld, which has to be installed.");
+    wasmLinkLabel->setWordWrap(true);
+    layout->addRow(wasmLinkLabel);
+
+    updateFeatureLevels();
+    connect(instructionSetSelect, qOverload<int>(&QComboBox::currentIndexChanged), this,
+            &TargetConfigWidget::updateFeatureLevels);
+
     auto resetButton = new QPushButton("Reset to Current");
     resetButton->setFixedWidth(100);
     auto resetButtonLayout = new QHBoxLayout();
@@ -90,28 +126,22 @@ MaximCompiler::TargetConfig TargetConfigWidget::buildConfig() {
     case 1:
         instructionSet = MaximFrontend::TargetInstructionSet::X64;
         break;
-    default:
-        unreachable
-    }
-
-    MaximFrontend::FeatureLevel featureLevel;
-    switch (featureSlider->value()) {
-    case 0:
-        featureLevel = MaximFrontend::FeatureLevel::SSE41;
-        break;
-    case 1:
-        featureLevel = MaximFrontend::FeatureLevel::SSE42;
-        break;
     case 2:
-        featureLevel = MaximFrontend::FeatureLevel::AVX;
+        instructionSet = MaximFrontend::TargetInstructionSet::ARMV7;
         break;
     case 3:
-        featureLevel = MaximFrontend::FeatureLevel::AVX2;
+        instructionSet = MaximFrontend::TargetInstructionSet::AARCH64;
+        break;
+    case 4:
+        instructionSet = MaximFrontend::TargetInstructionSet::WASM32;
         break;
     default:
-        unreachable;
+        unreachable
     }
 
+    auto featureLevel = (MaximFrontend::FeatureLevel)(
+        (int) firstFeatureLevel(instructionSetSelect->currentIndex()) + featureSlider->value());
+
     return MaximCompiler::TargetConfig(platform, instructionSet, featureLevel);
 }
 
@@ -124,9 +154,51 @@ void TargetConfigWidget::setToCurrentMachine() {
     machineSelect->setCurrentIndex(3);
 #endif
 
+#if defined(Q_PROCESSOR_ARM_64)
+    instructionSetSelect->setCurrentIndex(3);
+#elif defined(Q_PROCESSOR_ARM)
+    instructionSetSelect->setCurrentIndex(2);
+#else
     // in general we use i686 export more often, so always select that by default
     instructionSetSelect->setCurrentIndex(0);
+#endif
 
+    // machines with the generic feature level get the lowest level the instruction set has
     auto featureLevel = MaximFrontend::maxim_get_feature_level();
-    featureSlider->setValue((int) featureLevel);
+    if (featureLevel == MaximFrontend::FeatureLevel::GENERIC) {
+        featureSlider->setValue(0);
+    } else {
+        featureSlider->setValue((int) featureLevel - (int) firstFeatureLevel(instructionSetSelect->currentIndex()));
+    }
+}
+
+void TargetConfigWidget::updateFeatureLevels() {
+    // WebAssembly modules don't depend on the platform they run on
+    machineSelect->setEnabled(instructionSetSelect->currentIndex() != 4);
+    wasmLinkLabel->setVisible(instructionSetSelect->currentIndex() == 4);
+
+    auto names = featureLevelNames(instructionSetSelect->currentIndex());
+    featureSlider->setRange(0, names.size() - 1);
+    featureSlider->setEnabled(names.size() > 1);
+
+    while (auto item = featureLabelsLayout->takeAt(0)) {
+        delete item->widget();
+        delete item;
+    }
+
+    // the first and last labels sit at the ends of the slider, with the others centered on their
+    // positions between them
+    for (auto i = 0; i < names.size(); i++) {
+        auto label = new QLabel(names[i]);
+        auto isFirst = i == 0;
+        auto isLast = i == names.size() - 1;
+        if (isFirst && isLast) {
+            label->setAlignment(Qt::AlignHCenter);
+        } else if (isLast) {
+            label->setAlignment(Qt::AlignRight);
+        } else if (!isFirst) {
+            label->setAlignment(Qt::AlignHCenter);
+        }
+        featureLabelsLayout->addWidget(label, isFirst || isLast ? 1 : 2);
+    }
 }
@@ -5,6 +5,8 @@
 #include "editor/compiler/interface/Exporter.h"
 
 class QComboBox;
+class QHBoxLayout;
+class QLabel;
 class QSlider;
 
 namespace AxiomGui {
@@ -20,9 +22,13 @@ namespace AxiomGui {
     private slots:
         void setToCurrentMachine();
 
+        void updateFeatureLevels();
+
     private:
         QComboBox *machineSelect;
         QComboBox *instructionSetSelect;
         QSlider *featureSlider;
+        QHBoxLayout *featureLabelsLayout;
+        QLabel *wasmLinkLabel;
     };
 }
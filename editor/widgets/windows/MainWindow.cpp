@@ -42,7 +42,9 @@ MainWindow::MainWindow(AxiomBackend::AudioBackend *backend)
       fileSaveAsAction("S&ave As..."), fileExportAction("&Export..."), fileQuitAction("&Quit"), editUndoAction("&Undo"),
       editRedoAction("&Redo"), editCutAction("C&ut"), editCopyAction("&Copy"), editPasteAction("&Paste"),
       editDeleteAction("&Delete"), editSelectAllAction("&Select All"), editPreferencesAction("Pr&eferences..."),
-      helpAboutAction("&About"), _backend(backend), _runtime(true), libraryLock(globalLibraryLockPath()),
+      helpAboutAction("&About"), _backend(backend),
+      _runtime(true, MaximFrontend::MathAccuracy::BALANCED, false, false, MaximFrontend::CodegenBackend::CRANELIFT),
+      libraryLock(globalLibraryLockPath()),
       rightResizer(this), bottomResizer(this), bottomRightResizer(this) {
     setStyleSheet(AxiomUtil::loadStylesheet(":/styles/MainStyles.qss"));
     setCentralWidget(nullptr);
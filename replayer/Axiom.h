@@ -5,6 +5,14 @@
 
 #define AXIOM_SAMPLERATE 44100
 #define AXIOM_BPM 60
+#define AXIOM_MAX_BLOCK_FRAMES 1024
+#define AXIOM_MIDI_QUEUE_CAPACITY 256
+
+// The embedded song, in frames. If it loops, it jumps back to the loop start at the loop end.
+#define AXIOM_SONG_LENGTH 0
+#define AXIOM_SONG_LOOPS 0
+#define AXIOM_SONG_LOOP_START 0
+#define AXIOM_SONG_LOOP_END 0
 
 #define AXIOM_INPUT_PORTAL 0
 #define AXIOM_OUTPUT_PORTAL 1
@@ -15,12 +23,19 @@ extern "C" {
 void __cdecl axiom_init();
 void __cdecl axiom_packup();
 void __cdecl axiom_generate();
+uint32_t __cdecl axiom_generate_block(uint32_t frames);
 
 void *__cdecl axiom_get_portal(uint32_t id);
+void *__cdecl axiom_get_portal_buffer(uint32_t id);
 
 void __cdecl axiom_midi_push(AxiomMidi *midi, AxiomMidiEvent event);
+void __cdecl axiom_queue_midi(uint32_t id, uint32_t offset, uint8_t event, uint8_t channel, uint8_t note, uint8_t param);
 #ifdef __cplusplus
 }
 #endif
 
+static inline void axiom_queue_midi_event(uint32_t id, AxiomTimedMidiEvent timed) {
+    axiom_queue_midi(id, timed.offset, timed.event.type, timed.event.channel, timed.event.note, timed.event.param);
+}
+
 #endif
@@ -30,4 +30,10 @@ typedef struct {
     AxiomMidiEvent events[16];
 } AxiomMidi;
 
+// An event to be delivered a number of frames into the next generated block.
+typedef struct {
+    uint32_t offset;
+    AxiomMidiEvent event;
+} AxiomTimedMidiEvent;
+
 #endif